@@ -1,6 +1,6 @@
 use log::{error, info};
 
-use vectorize_core::worker::base::{poll_job, Config};
+use vectorize_core::worker::base::{poll_job, warm_up_models, Config};
 use vectorize_core::worker::ops::init_extension;
 
 #[tokio::main]
@@ -22,6 +22,8 @@ async fn main() {
 
     let queue = pgmq::PGMQueueExt::new_with_pool(conn.clone()).await;
 
+    warm_up_models(&cfg).await;
+
     loop {
         match poll_job(&conn, &queue, &cfg).await {
             Ok(Some(_)) => {
@@ -0,0 +1,1351 @@
+use crate::transformers::types::Inputs;
+use crate::types::{ChunkFilter, ChunkParams, ChunkStrategy, Model, ModelSource};
+
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Mutex, OnceLock};
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::{get_bpe_from_tokenizer, CoreBPE};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// groups inputs into batches based on their total token count
+/// batch_size is the max token count per batch
+pub fn create_batches(data: Vec<Inputs>, batch_size: i32) -> Vec<Vec<Inputs>> {
+    let mut groups: Vec<Vec<Inputs>> = Vec::new();
+    let mut current_group: Vec<Inputs> = Vec::new();
+    let mut current_token_count = 0;
+
+    for input in data {
+        if current_token_count + input.token_estimate > batch_size {
+            // Create a new group
+            groups.push(current_group);
+            current_group = Vec::new();
+            current_token_count = 0;
+        }
+        current_token_count += input.token_estimate;
+        current_group.push(input);
+    }
+
+    // Add any remaining inputs to the groups
+    if !current_group.is_empty() {
+        groups.push(current_group);
+    }
+    groups
+}
+
+/// a piece of a text, along with where it sits in the text
+/// offsets are in characters, so that they can be used with Postgres' substr()
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    pub text: String,
+    pub char_start: usize,
+    pub char_end: usize,
+    pub token_count: usize,
+    // the headings of the markdown sections the chunk is in, from the outermost to the innermost
+    pub headings: Vec<String>,
+}
+
+/// measures texts in the tokens of a model, for the chunkers
+pub trait TokenCounter: Send + Sync {
+    /// the number of tokens of a text
+    fn count(&self, text: &str) -> usize;
+
+    /// the byte offset each of the tokens of a text starts at
+    fn token_offsets(&self, text: &str) -> Vec<usize>;
+}
+
+impl TokenCounter for CoreBPE {
+    fn count(&self, text: &str) -> usize {
+        self.encode_ordinary(text).len()
+    }
+
+    fn token_offsets(&self, text: &str) -> Vec<usize> {
+        let mut offset = 0;
+        self.encode_ordinary(text)
+            .into_iter()
+            .map(|token| {
+                let start = offset;
+                offset += self._decode_native(&[token]).len();
+                start
+            })
+            .collect()
+    }
+}
+
+impl<T: TokenCounter + ?Sized> TokenCounter for &T {
+    fn count(&self, text: &str) -> usize {
+        (**self).count(text)
+    }
+
+    fn token_offsets(&self, text: &str) -> Vec<usize> {
+        (**self).token_offsets(text)
+    }
+}
+
+// encodings are built once per process, building one takes longer than chunking most texts
+static ENCODINGS: OnceLock<Mutex<HashMap<Tokenizer, &'static CoreBPE>>> = OnceLock::new();
+
+/// the tiktoken encoding a model's inputs are measured with
+/// OpenAI models use their own encoding, other models are approximated with cl100k_base,
+/// which inputs are estimated with everywhere else
+pub fn tokenizer(model: Option<&Model>) -> Result<&'static CoreBPE> {
+    let encoding = match model {
+        Some(model) if model.source == ModelSource::OpenAI => {
+            get_tokenizer(&model.name).unwrap_or(Tokenizer::Cl100kBase)
+        }
+        _ => Tokenizer::Cl100kBase,
+    };
+    let mut encodings = ENCODINGS
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|_| anyhow!("tokenizer cache is poisoned"))?;
+    if let Some(bpe) = encodings.get(&encoding) {
+        return Ok(bpe);
+    }
+    let bpe: &'static CoreBPE = Box::leak(Box::new(get_bpe_from_tokenizer(encoding)?));
+    encodings.insert(encoding, bpe);
+    Ok(bpe)
+}
+
+// the similarity of adjacent sentences below which the semantic strategy starts a new chunk, when none is given
+pub const DEFAULT_SIMILARITY_THRESHOLD: f64 = 0.5;
+
+// the sentences on either side of a sentence that are embedded along with it by the semantic strategy,
+// as a single sentence is often too short to tell what it is about
+const SEMANTIC_WINDOW: usize = 1;
+
+// the separators of the recursive strategy when none are given: paragraphs, lines, sentences, then words
+pub const DEFAULT_SEPARATORS: [&str; 4] = ["\n\n", "\n", ". ", " "];
+
+/// splits a text into chunks with the strategy of the chunk params
+pub fn chunk(text: &str, params: &ChunkParams, bpe: &dyn TokenCounter) -> Result<Vec<Chunk>> {
+    let chunk_size = params.chunk_size as usize;
+    let chunk_overlap = params.chunk_overlap as usize;
+    match params.strategy {
+        ChunkStrategy::tokens => chunk_tokens(text, chunk_size, chunk_overlap, bpe),
+        ChunkStrategy::recursive => {
+            let separators: Vec<&str> = if params.separators.is_empty() {
+                DEFAULT_SEPARATORS.to_vec()
+            } else {
+                params.separators.iter().map(String::as_str).collect()
+            };
+            chunk_recursive(text, chunk_size, chunk_overlap, &separators, bpe)
+        }
+        ChunkStrategy::sentence => chunk_sentences(text, chunk_size, chunk_overlap, bpe),
+        ChunkStrategy::markdown => chunk_markdown(text, chunk_size, chunk_overlap, bpe),
+        ChunkStrategy::code => chunk_code(text, chunk_size, chunk_overlap, bpe),
+        ChunkStrategy::semantic => {
+            bail!("the semantic chunk_strategy needs the embeddings of the sentences, it is chunked by the worker")
+        }
+        ChunkStrategy::window => {
+            let stride = params
+                .chunk_stride
+                .map_or((chunk_size / 2).max(1), |s| s as usize);
+            chunk_windows(text, chunk_size, stride, bpe)
+        }
+        ChunkStrategy::separator => {
+            let Some(separator) = &params.separator else {
+                bail!("the separator chunk_strategy requires a separator");
+            };
+            chunk_separator(text, &separator_regex(separator)?, chunk_size, bpe)
+        }
+    }
+}
+
+/// splits the text of a column into chunks with the params of the column
+/// the whole text of a column that is not chunked is a single chunk
+pub fn chunk_column(
+    text: &str,
+    column: &str,
+    params: &ChunkParams,
+    bpe: &dyn TokenCounter,
+) -> Result<Vec<Chunk>> {
+    match params.of_column(column) {
+        Some(params) => chunk(text, params, bpe),
+        None => {
+            let mut chunks = Vec::new();
+            push_trimmed(text, 0..text.len(), false, bpe, &mut chunks);
+            Ok(chunks)
+        }
+    }
+}
+
+/// the min_tokens and exclude_patterns of a chunk filter, with the patterns compiled once for the chunks of many rows
+/// the filter's function is called by whatever writes the chunks, with filter_function_query
+pub struct CompiledFilter {
+    min_tokens: usize,
+    exclude_patterns: Vec<Regex>,
+}
+
+impl CompiledFilter {
+    pub fn new(filter: &ChunkFilter) -> Result<Self> {
+        let exclude_patterns = filter
+            .exclude_patterns
+            .iter()
+            .map(|p| Regex::new(p).map_err(|e| anyhow!("invalid exclude_patterns: {e}")))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(CompiledFilter {
+            min_tokens: filter.min_tokens.unwrap_or(0) as usize,
+            exclude_patterns,
+        })
+    }
+
+    /// true for the chunks that are discarded
+    pub fn discards(&self, chunk: &Chunk) -> bool {
+        chunk.token_count < self.min_tokens
+            || self
+                .exclude_patterns
+                .iter()
+                .any(|p| p.is_match(&chunk.text))
+    }
+}
+
+/// query calling a chunk filter's function on each of the chunk texts in $1, in their order
+pub fn filter_function_query(function: &str) -> String {
+    format!(
+        "SELECT {function}(u.chunk) AS keep
+        FROM unnest($1::text[]) WITH ORDINALITY AS u(chunk, i)
+        ORDER BY u.i"
+    )
+}
+
+/// the regular expression of the separator strategy, which must not match an empty string
+pub fn separator_regex(separator: &str) -> Result<Regex> {
+    let regex = Regex::new(separator).map_err(|e| anyhow!("invalid separator: {e}"))?;
+    if regex.is_match("") {
+        bail!("separator can not match an empty string: {separator}");
+    }
+    Ok(regex)
+}
+
+/// how the chunks of a row that was chunked again replace the chunks it had
+/// chunks are given by their ids, and new chunks by their position among the row's new chunks
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ChunkChanges {
+    // existing chunks whose column and text are those of a new chunk, and that new chunk, they keep their embeddings
+    pub kept: Vec<(i64, usize)>,
+    pub deleted: Vec<i64>,
+    pub inserted: Vec<usize>,
+}
+
+/// matches the existing chunks of a row, its chunk ids, columns and texts, with the columns and texts of its new chunks
+/// so that only the chunks whose text changed have to be embedded again
+pub fn chunk_changes(existing: &[(i64, String, String)], new: &[(&str, &str)]) -> ChunkChanges {
+    let mut unmatched: Vec<&(i64, String, String)> = existing.iter().collect();
+    let mut changes = ChunkChanges::default();
+    for (position, (column, text)) in new.iter().enumerate() {
+        match unmatched
+            .iter()
+            .position(|(_, c, t)| c == column && t == text)
+        {
+            Some(i) => changes.kept.push((unmatched.remove(i).0, position)),
+            None => changes.inserted.push(position),
+        }
+    }
+    changes.deleted = unmatched.into_iter().map(|(id, _, _)| *id).collect();
+    changes
+}
+
+/// splits a text into chunks of at most chunk_size tokens, each starting chunk_overlap tokens
+/// before the end of the previous chunk
+/// chunks end on character boundaries, so a character split over two tokens is kept whole
+pub fn chunk_tokens(
+    text: &str,
+    chunk_size: usize,
+    chunk_overlap: usize,
+    bpe: &dyn TokenCounter,
+) -> Result<Vec<Chunk>> {
+    validate_sizes(chunk_size, chunk_overlap)?;
+    Ok(
+        token_windows(text, chunk_size, chunk_size - chunk_overlap, false, bpe)
+            .into_iter()
+            .map(|range| chunk_at(text, range.start, range.end, bpe))
+            .collect(),
+    )
+}
+
+/// splits a text into sliding windows of chunk_size tokens, each starting chunk_stride tokens after the previous one
+/// the last window ends at the end of the text, so that every window of a text longer than chunk_size
+/// has chunk_size tokens
+pub fn chunk_windows(
+    text: &str,
+    chunk_size: usize,
+    chunk_stride: usize,
+    bpe: &dyn TokenCounter,
+) -> Result<Vec<Chunk>> {
+    if chunk_size == 0 {
+        bail!("chunk_size must be positive");
+    }
+    if chunk_stride == 0 || chunk_stride > chunk_size {
+        bail!("chunk_stride must be between 1 and chunk_size");
+    }
+    Ok(token_windows(text, chunk_size, chunk_stride, true, bpe)
+        .into_iter()
+        .map(|range| chunk_at(text, range.start, range.end, bpe))
+        .collect())
+}
+
+/// splits a text on the first separator it contains, keeping each separator at the end of its piece,
+/// and splits the pieces that are longer than chunk_size tokens again on the next separators,
+/// falling back to token windows once the separators run out
+/// the pieces are then merged into chunks of at most chunk_size tokens, each starting with
+/// the last pieces of the previous chunk, up to chunk_overlap tokens of them
+/// chunks are trimmed of leading and trailing whitespace, after they are measured
+pub fn chunk_recursive(
+    text: &str,
+    chunk_size: usize,
+    chunk_overlap: usize,
+    separators: &[&str],
+    bpe: &dyn TokenCounter,
+) -> Result<Vec<Chunk>> {
+    validate_sizes(chunk_size, chunk_overlap)?;
+    if separators.iter().any(|s| s.is_empty()) {
+        bail!("separators can not be empty strings");
+    }
+    let mut pieces = Vec::new();
+    split_pieces(
+        text,
+        0..text.len(),
+        separators,
+        chunk_size,
+        bpe,
+        &mut pieces,
+    );
+    Ok(merge_pieces(
+        text,
+        pieces,
+        chunk_size,
+        chunk_overlap,
+        false,
+        bpe,
+    ))
+}
+
+/// merges the sentences of a text into chunks of up to chunk_size tokens, without splitting any sentence
+/// a sentence longer than chunk_size is a chunk of its own
+/// each chunk starts with the last sentences of the previous chunk, up to chunk_overlap tokens of them
+/// sentences are found with the Unicode sentence boundaries of UAX #29
+pub fn chunk_sentences(
+    text: &str,
+    chunk_size: usize,
+    chunk_overlap: usize,
+    bpe: &dyn TokenCounter,
+) -> Result<Vec<Chunk>> {
+    validate_sizes(chunk_size, chunk_overlap)?;
+    let pieces = text
+        .split_sentence_bound_indices()
+        .map(|(start, sentence)| start..start + sentence.len())
+        .collect();
+    Ok(merge_pieces(
+        text,
+        pieces,
+        chunk_size,
+        chunk_overlap,
+        false,
+        bpe,
+    ))
+}
+
+/// splits markdown into the sections under its headings, each chunk is given the headings of its section
+/// sections longer than chunk_size are split further with the recursive strategy's default separators,
+/// and chunk_overlap only applies within a section
+/// sections without any text under their heading, e.g. a heading followed by a subheading, are skipped
+pub fn chunk_markdown(
+    text: &str,
+    chunk_size: usize,
+    chunk_overlap: usize,
+    bpe: &dyn TokenCounter,
+) -> Result<Vec<Chunk>> {
+    validate_sizes(chunk_size, chunk_overlap)?;
+    let mut chunks = Vec::new();
+    for section in markdown_sections(text) {
+        if text[section.body.clone()].trim().is_empty() {
+            continue;
+        }
+        let section_text = &text[section.range.clone()];
+        let char_offset = text[..section.range.start].chars().count();
+        let section_chunks = chunk_recursive(
+            section_text,
+            chunk_size,
+            chunk_overlap,
+            &DEFAULT_SEPARATORS,
+            bpe,
+        )?;
+        chunks.extend(section_chunks.into_iter().map(|chunk| Chunk {
+            char_start: chunk.char_start + char_offset,
+            char_end: chunk.char_end + char_offset,
+            headings: section.headings.clone(),
+            ..chunk
+        }));
+    }
+    Ok(chunks)
+}
+
+/// splits source code into its top level blocks, e.g. functions and classes, and merges them into
+/// chunks of up to chunk_size tokens, each starting with the last blocks of the previous chunk,
+/// up to chunk_overlap tokens of them
+/// blocks longer than chunk_size are split on the blocks indented within them, then on lines,
+/// and the comments, decorators and attributes right above a block are kept with it
+/// chunks keep the indentation of their first line, only blank lines are trimmed around them
+pub fn chunk_code(
+    text: &str,
+    chunk_size: usize,
+    chunk_overlap: usize,
+    bpe: &dyn TokenCounter,
+) -> Result<Vec<Chunk>> {
+    validate_sizes(chunk_size, chunk_overlap)?;
+    let mut pieces = Vec::new();
+    code_pieces(text, 0..text.len(), chunk_size, bpe, &mut pieces);
+    Ok(merge_pieces(
+        text,
+        pieces,
+        chunk_size,
+        chunk_overlap,
+        true,
+        bpe,
+    ))
+}
+
+/// splits a text on every match of a regular expression, each piece starting with the match,
+/// so that e.g. each entry of a log, or each turn of a transcript, is a chunk of its own
+/// pieces longer than chunk_size are split further with the recursive strategy's default separators
+pub fn chunk_separator(
+    text: &str,
+    separator: &Regex,
+    chunk_size: usize,
+    bpe: &dyn TokenCounter,
+) -> Result<Vec<Chunk>> {
+    validate_sizes(chunk_size, 0)?;
+    let mut starts: Vec<usize> = separator.find_iter(text).map(|m| m.start()).collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    let mut chunks = Vec::new();
+    for (i, start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(text.len());
+        let piece = &text[*start..end];
+        if bpe.count(piece) <= chunk_size {
+            push_trimmed(text, *start..end, false, bpe, &mut chunks);
+            continue;
+        }
+        let char_offset = text[..*start].chars().count();
+        let piece_chunks = chunk_recursive(piece, chunk_size, 0, &DEFAULT_SEPARATORS, bpe)?;
+        chunks.extend(piece_chunks.into_iter().map(|chunk| Chunk {
+            char_start: chunk.char_start + char_offset,
+            char_end: chunk.char_end + char_offset,
+            ..chunk
+        }));
+    }
+    Ok(chunks)
+}
+
+/// the sentences of a text, along with the window of sentences around each of them
+/// the semantic strategy compares the embeddings of the windows of adjacent sentences
+pub fn semantic_windows(text: &str) -> (Vec<Range<usize>>, Vec<String>) {
+    let sentences: Vec<Range<usize>> = text
+        .split_sentence_bound_indices()
+        .filter(|(_, sentence)| !sentence.trim().is_empty())
+        .map(|(start, sentence)| start..start + sentence.len())
+        .collect();
+    let windows = (0..sentences.len())
+        .map(|i| {
+            let first = i.saturating_sub(SEMANTIC_WINDOW);
+            let last = (i + SEMANTIC_WINDOW).min(sentences.len() - 1);
+            text[sentences[first].start..sentences[last].end]
+                .trim()
+                .to_string()
+        })
+        .collect();
+    (sentences, windows)
+}
+
+/// merges the sentences of a text into chunks, starting a new chunk where the cosine similarity of
+/// the embeddings of the windows of two adjacent sentences drops below similarity_threshold,
+/// or where the chunk would be longer than chunk_size tokens
+/// sentences and embeddings are those of semantic_windows, a sentence longer than chunk_size is a chunk of its own
+pub fn chunk_semantic(
+    text: &str,
+    sentences: &[Range<usize>],
+    embeddings: &[Vec<f64>],
+    similarity_threshold: f64,
+    chunk_size: usize,
+    bpe: &dyn TokenCounter,
+) -> Result<Vec<Chunk>> {
+    validate_sizes(chunk_size, 0)?;
+    if sentences.len() != embeddings.len() {
+        bail!(
+            "expected an embedding for each of the {} sentences, got {}",
+            sentences.len(),
+            embeddings.len()
+        );
+    }
+    let mut chunks = Vec::new();
+    let Some(first) = sentences.first() else {
+        return Ok(chunks);
+    };
+    let mut start = first.start;
+    for i in 1..sentences.len() {
+        let similarity = cosine_similarity(&embeddings[i - 1], &embeddings[i]);
+        if similarity < similarity_threshold
+            || bpe.count(&text[start..sentences[i].end]) > chunk_size
+        {
+            push_trimmed(text, start..sentences[i].start, false, bpe, &mut chunks);
+            start = sentences[i].start;
+        }
+    }
+    push_trimmed(text, start..text.len(), false, bpe, &mut chunks);
+    Ok(chunks)
+}
+
+fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms =
+        a.iter().map(|x| x * x).sum::<f64>().sqrt() * b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+// lines that close or continue the block above them rather than starting a new one
+const CONTINUATIONS: [&str; 7] = [
+    "end", "else", "elif", "except", "finally", "catch", "rescue",
+];
+// lines that belong to the block below them: comments, decorators and attributes
+const ATTACHED: [&str; 5] = ["#", "//", "/*", "*", "@"];
+
+// splits a byte range of source code into contiguous pieces of at most chunk_size tokens,
+// on the lines that start a block at the outermost indentation that splits it,
+// falling back to lines then token windows
+fn code_pieces(
+    text: &str,
+    range: Range<usize>,
+    chunk_size: usize,
+    bpe: &dyn TokenCounter,
+    pieces: &mut Vec<Range<usize>>,
+) {
+    let slice = &text[range.clone()];
+    if bpe.count(slice) <= chunk_size {
+        if !slice.is_empty() {
+            pieces.push(range);
+        }
+        return;
+    }
+    // the byte offset and indentation of each line, blank lines have no indentation
+    let mut lines: Vec<(usize, Option<usize>)> = Vec::new();
+    let mut offset = range.start;
+    for line in slice.split_inclusive('\n') {
+        let indent = (!line.trim().is_empty()).then(|| line.len() - line.trim_start().len());
+        lines.push((offset, indent));
+        offset += line.len();
+    }
+    let mut levels: Vec<usize> = lines.iter().filter_map(|(_, indent)| *indent).collect();
+    levels.sort_unstable();
+    levels.dedup();
+    for level in levels {
+        let starts = block_starts(text, &lines, level);
+        if starts.len() > 1 {
+            let ends = starts.iter().skip(1).copied().chain([range.end]);
+            for (start, end) in starts.iter().copied().zip(ends) {
+                code_pieces(text, start..end, chunk_size, bpe, pieces);
+            }
+            return;
+        }
+    }
+    split_pieces(text, range, &["\n", " "], chunk_size, bpe, pieces);
+}
+
+// the byte offsets of the blocks of lines at an indentation level, the first block starts with the first line
+// a block starts at a line of that indentation, or at the comments and attributes right above it
+fn block_starts(text: &str, lines: &[(usize, Option<usize>)], level: usize) -> Vec<usize> {
+    let mut starts = vec![lines[0].0];
+    // whether the line above, ignoring blank lines, is attached to the block below it
+    let mut attached = false;
+    for (i, (offset, indent)) in lines.iter().enumerate() {
+        let Some(indent) = indent else {
+            continue;
+        };
+        if *indent != level {
+            attached = false;
+            continue;
+        }
+        let end = lines.get(i + 1).map_or(text.len(), |(o, _)| *o);
+        let line = text[*offset..end].trim();
+        let word = line
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap_or_default();
+        let continues = line.starts_with(['}', ')', ']']) || CONTINUATIONS.contains(&word);
+        if i > 0 && !continues && !attached {
+            starts.push(*offset);
+        }
+        attached = ATTACHED.iter().any(|a| line.starts_with(a)) && !line.starts_with("#!");
+    }
+    starts
+}
+
+// a heading and the text under it, up to the next heading
+struct Section {
+    range: Range<usize>,
+    // the text after the heading
+    body: Range<usize>,
+    headings: Vec<String>,
+}
+
+// the sections of markdown, split on its ATX headings, e.g. "## Setup"
+// lines in fenced code blocks are never headings
+fn markdown_sections(text: &str) -> Vec<Section> {
+    let mut sections = vec![Section {
+        range: 0..0,
+        body: 0..0,
+        headings: Vec::new(),
+    }];
+    // the level and text of each heading the current section is under
+    let mut path: Vec<(usize, String)> = Vec::new();
+    let mut fence: Option<&str> = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let line_end = offset + line.len();
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            fence = match fence {
+                Some(open) if open == marker => None,
+                None => Some(marker),
+                open => open,
+            };
+        } else if let Some((level, heading)) = fence.is_none().then(|| atx_heading(line)).flatten()
+        {
+            path.retain(|(l, _)| *l < level);
+            path.push((level, heading));
+            sections.push(Section {
+                range: offset..line_end,
+                body: line_end..line_end,
+                headings: path.iter().map(|(_, h)| h.clone()).collect(),
+            });
+            offset = line_end;
+            continue;
+        }
+        if let Some(section) = sections.last_mut() {
+            section.range.end = line_end;
+            section.body.end = line_end;
+        }
+        offset = line_end;
+    }
+    sections
+}
+
+// the level and text of an ATX heading line, indented by at most 3 spaces
+fn atx_heading(line: &str) -> Option<(usize, String)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    let line = line[indent..].trim_end();
+    let level = line.len() - line.trim_start_matches('#').len();
+    let heading = &line[level..];
+    if indent > 3
+        || level == 0
+        || level > 6
+        || !(heading.is_empty() || heading.starts_with([' ', '\t']))
+    {
+        return None;
+    }
+    // a closing sequence of #s is not part of the heading
+    let heading = heading.trim();
+    let heading = match heading.trim_end_matches('#') {
+        h if h.is_empty() || h.ends_with([' ', '\t']) => h.trim_end(),
+        _ => heading,
+    };
+    Some((level, heading.to_string()))
+}
+
+// merges contiguous pieces of a text into trimmed chunks of up to chunk_size tokens,
+// each starting with the last pieces of the previous chunk, up to chunk_overlap tokens of them
+// a piece longer than chunk_size is a chunk of its own
+fn merge_pieces(
+    text: &str,
+    pieces: Vec<Range<usize>>,
+    chunk_size: usize,
+    chunk_overlap: usize,
+    keep_indentation: bool,
+    bpe: &dyn TokenCounter,
+) -> Vec<Chunk> {
+    // pieces are measured together, as tokens can merge across the boundary of two pieces
+    let count = |range: Range<usize>| bpe.count(&text[range]);
+    let mut chunks = Vec::new();
+    // the start offsets of the pieces of the chunk being merged
+    let mut current: Vec<usize> = Vec::new();
+    for range in pieces {
+        if !current.is_empty() && count(current[0]..range.end) > chunk_size {
+            push_trimmed(
+                text,
+                current[0]..range.start,
+                keep_indentation,
+                bpe,
+                &mut chunks,
+            );
+            // the last pieces are carried over into the next chunk, as long as the next piece still fits
+            while !current.is_empty()
+                && (count(current[0]..range.start) > chunk_overlap
+                    || count(current[0]..range.end) > chunk_size)
+            {
+                current.remove(0);
+            }
+        }
+        current.push(range.start);
+    }
+    if let Some(start) = current.first() {
+        push_trimmed(text, *start..text.len(), keep_indentation, bpe, &mut chunks);
+    }
+    chunks
+}
+
+fn validate_sizes(chunk_size: usize, chunk_overlap: usize) -> Result<()> {
+    if chunk_size == 0 {
+        bail!("chunk_size must be positive");
+    }
+    if chunk_overlap >= chunk_size {
+        bail!("chunk_overlap must be smaller than chunk_size");
+    }
+    Ok(())
+}
+
+// splits a byte range of a text into contiguous pieces of at most chunk_size tokens
+fn split_pieces(
+    text: &str,
+    range: Range<usize>,
+    separators: &[&str],
+    chunk_size: usize,
+    bpe: &dyn TokenCounter,
+    pieces: &mut Vec<Range<usize>>,
+) {
+    let slice = &text[range.clone()];
+    if bpe.count(slice) <= chunk_size {
+        if !slice.is_empty() {
+            pieces.push(range);
+        }
+        return;
+    }
+    let position = separators.iter().position(|s| slice.contains(s));
+    let Some(position) = position else {
+        for window in token_windows(slice, chunk_size, chunk_size, false, bpe) {
+            pieces.push(range.start + window.start..range.start + window.end);
+        }
+        return;
+    };
+    let separator = separators[position];
+    let mut start = range.start;
+    for (offset, _) in slice.match_indices(separator) {
+        let end = range.start + offset + separator.len();
+        split_pieces(
+            text,
+            start..end,
+            &separators[position + 1..],
+            chunk_size,
+            bpe,
+            pieces,
+        );
+        start = end;
+    }
+    split_pieces(
+        text,
+        start..range.end,
+        &separators[position + 1..],
+        chunk_size,
+        bpe,
+        pieces,
+    );
+}
+
+// adds the chunk of a byte range to the chunks, without its surrounding whitespace
+// or, keeping the indentation of its first line, its surrounding blank lines
+fn push_trimmed(
+    text: &str,
+    range: Range<usize>,
+    keep_indentation: bool,
+    bpe: &dyn TokenCounter,
+    chunks: &mut Vec<Chunk>,
+) {
+    let slice = &text[range.clone()];
+    let mut leading = slice.len() - slice.trim_start().len();
+    if keep_indentation {
+        leading = slice[..leading].rfind('\n').map_or(0, |i| i + 1);
+    }
+    let start = range.start + leading;
+    let end = range.end - (slice.len() - slice.trim_end().len());
+    if end > start {
+        chunks.push(chunk_at(text, start, end, bpe));
+    }
+}
+
+// the byte ranges of windows of at most chunk_size tokens over a text,
+// each starting stride tokens after the previous window
+// with end_aligned, the last window is moved back to end at the end of the text, rather than being cut short
+fn token_windows(
+    text: &str,
+    chunk_size: usize,
+    stride: usize,
+    end_aligned: bool,
+    bpe: &dyn TokenCounter,
+) -> Vec<Range<usize>> {
+    // the byte offset each token starts at, and the end of the text
+    let mut offsets = bpe.token_offsets(text);
+    let num_tokens = offsets.len();
+    offsets.push(text.len());
+
+    let mut windows = Vec::new();
+    let mut start = 0;
+    while start < num_tokens {
+        let end = (start + chunk_size).min(num_tokens);
+        if end_aligned && end == num_tokens {
+            start = start.min(end.saturating_sub(chunk_size));
+        }
+        let byte_start = floor_char_boundary(text, offsets[start]);
+        let byte_end = floor_char_boundary(text, offsets[end]);
+        if byte_end > byte_start {
+            windows.push(byte_start..byte_end);
+        }
+        if end == num_tokens {
+            break;
+        }
+        start += stride;
+    }
+    windows
+}
+
+// the chunk between two byte offsets of a text
+fn chunk_at(text: &str, byte_start: usize, byte_end: usize, bpe: &dyn TokenCounter) -> Chunk {
+    let chunk = &text[byte_start..byte_end];
+    let char_start = text[..byte_start].chars().count();
+    Chunk {
+        text: chunk.to_string(),
+        char_start,
+        char_end: char_start + chunk.chars().count(),
+        token_count: bpe.count(chunk),
+        headings: Vec::new(),
+    }
+}
+
+// the closest character boundary at or before a byte offset
+fn floor_char_boundary(text: &str, mut offset: usize) -> usize {
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_create_batches_normal() {
+        let data = vec![
+            Inputs {
+                record_id: "1".to_string(),
+                inputs: "Test 1.".to_string(),
+                token_estimate: 2,
+            },
+            Inputs {
+                record_id: "2".to_string(),
+                inputs: "Test 2.".to_string(),
+                token_estimate: 2,
+            },
+            Inputs {
+                record_id: "3".to_string(),
+                inputs: "Test 3.".to_string(),
+                token_estimate: 3,
+            },
+        ];
+
+        let batches = create_batches(data, 4);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].len(), 2);
+        assert_eq!(batches[1].len(), 1);
+    }
+
+    #[test]
+    fn test_create_batches_empty() {
+        let data: Vec<Inputs> = Vec::new();
+        let batches = create_batches(data, 4);
+        assert_eq!(batches.len(), 0);
+    }
+
+    #[test]
+    fn test_create_batches_large() {
+        let data = vec![
+            Inputs {
+                record_id: "1".to_string(),
+                inputs: "Test 1.".to_string(),
+                token_estimate: 2,
+            },
+            Inputs {
+                record_id: "2".to_string(),
+                inputs: "Test 2.".to_string(),
+                token_estimate: 2,
+            },
+            Inputs {
+                record_id: "3".to_string(),
+                inputs: "Test 3.".to_string(),
+                token_estimate: 100,
+            },
+        ];
+        let batches = create_batches(data, 5);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[1].len(), 1);
+        assert_eq!(batches[1][0].token_estimate, 100);
+    }
+
+    #[test]
+    fn test_chunk_tokens() {
+        let bpe = tokenizer(None).unwrap();
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(20);
+        let chunks = chunk_tokens(&text, 50, 10, &bpe).unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks[0].char_start, 0);
+        assert_eq!(chunks.last().unwrap().char_end, text.chars().count());
+        for chunk in &chunks {
+            assert!(chunk.token_count <= 50);
+            assert_eq!(
+                chunk.text,
+                text.chars()
+                    .skip(chunk.char_start)
+                    .take(chunk.char_end - chunk.char_start)
+                    .collect::<String>()
+            );
+        }
+        // consecutive chunks overlap
+        assert!(chunks[1].char_start < chunks[0].char_end);
+
+        assert!(chunk_tokens(&text, 50, 50, &bpe).is_err());
+        assert!(chunk_tokens("", 50, 0, &bpe).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tokenizer() {
+        // encodings are built once, and shared by the models that use them
+        let other = Model::new("sentence-transformers/all-MiniLM-L6-v2").unwrap();
+        assert!(std::ptr::eq(
+            tokenizer(None).unwrap(),
+            tokenizer(Some(&other)).unwrap()
+        ));
+        let bpe = tokenizer(None).unwrap();
+        let text = "The quick brown fox";
+        let offsets = bpe.token_offsets(text);
+        assert_eq!(offsets.len(), bpe.count(text));
+        assert_eq!(offsets[0], 0);
+        assert_eq!(&text[offsets[1]..offsets[2]], " quick");
+    }
+
+    #[test]
+    fn test_chunk_windows() {
+        let bpe = tokenizer(None).unwrap();
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(5);
+        let tokens = bpe.encode_ordinary(&text);
+        let num_tokens = tokens.len();
+        let chunks = chunk_windows(&text, 8, 3, &bpe).unwrap();
+        // every window has chunk_size tokens, the last one ends at the end of the text
+        assert!(chunks.iter().all(|c| c.token_count == 8));
+        assert_eq!(chunks.last().unwrap().char_end, text.chars().count());
+        assert_eq!(chunks.len(), (num_tokens - 8).div_ceil(3) + 1);
+        // windows start chunk_stride tokens apart
+        assert_eq!(chunks[1].text, bpe.decode(tokens[3..11].to_vec()).unwrap());
+        let short = chunk_windows("a short text", 8, 3, &bpe).unwrap();
+        assert_eq!(short.len(), 1);
+        assert_eq!(short[0].text, "a short text");
+
+        assert!(chunk_windows(&text, 8, 0, &bpe).is_err());
+        assert!(chunk_windows(&text, 8, 9, &bpe).is_err());
+        let params = ChunkParams {
+            chunk_size: 8,
+            strategy: ChunkStrategy::window,
+            ..Default::default()
+        };
+        // the stride is half of chunk_size when none is given
+        assert_eq!(
+            chunk(&text, &params, &bpe).unwrap(),
+            chunk_windows(&text, 8, 4, &bpe).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_chunk_tokens_cjk() {
+        // cl100k_base splits many CJK characters over more than one token
+        let bpe = tokenizer(None).unwrap();
+        let text = "向量搜索让数据库能够按含义查找相似的文本。".repeat(10);
+        let chunks = chunk_tokens(&text, 16, 0, &bpe).unwrap();
+        assert!(chunks.len() > 1);
+        // chunks tile the text without breaking up its characters
+        assert_eq!(
+            chunks.iter().map(|c| c.text.as_str()).collect::<String>(),
+            text
+        );
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].char_end, pair[1].char_start);
+        }
+        for chunk in &chunks {
+            assert!(chunk.token_count <= 17);
+        }
+    }
+
+    #[test]
+    fn test_chunk_recursive() {
+        let bpe = tokenizer(None).unwrap();
+        let paragraph = "Vector search finds similar texts. It compares their embeddings.";
+        let text = [paragraph; 6].join("\n\n");
+        let paragraph_tokens = bpe.encode_ordinary(paragraph).len();
+
+        // paragraphs that fit are kept whole, and merged while they fit
+        let chunks = chunk_recursive(
+            &text,
+            paragraph_tokens * 2 + 2,
+            0,
+            &DEFAULT_SEPARATORS,
+            &bpe,
+        )
+        .unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].text, format!("{paragraph}\n\n{paragraph}"));
+        for chunk in &chunks {
+            assert!(chunk.token_count <= paragraph_tokens * 2 + 2);
+        }
+
+        // paragraphs that do not fit are split into sentences
+        let chunks =
+            chunk_recursive(&text, paragraph_tokens - 2, 0, &DEFAULT_SEPARATORS, &bpe).unwrap();
+        assert_eq!(chunks.len(), 12);
+        assert_eq!(chunks[0].text, "Vector search finds similar texts.");
+        assert_eq!(chunks[1].text, "It compares their embeddings.");
+        assert_eq!(chunks[1].char_start, paragraph.find("It").unwrap());
+
+        // the last pieces of a chunk are repeated at the start of the next one
+        let chunks = chunk_recursive(
+            &text,
+            paragraph_tokens * 2 + 2,
+            paragraph_tokens + 1,
+            &DEFAULT_SEPARATORS,
+            &bpe,
+        )
+        .unwrap();
+        assert_eq!(chunks.len(), 5);
+        assert!(chunks[1].char_start < chunks[0].char_end);
+
+        // without a separator, long pieces fall back to token windows
+        let chunks = chunk_recursive(&text, 5, 0, &["|"], &bpe).unwrap();
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            // a word that loses its leading space when its chunk is trimmed can take one more token
+            assert!(chunk.token_count <= 6);
+        }
+        assert!(chunk_recursive(&text, 5, 0, &[""], &bpe).is_err());
+    }
+
+    #[test]
+    fn test_chunk_sentences() {
+        let bpe = tokenizer(None).unwrap();
+        let text = "Vector search finds similar texts. It compares their embeddings! \
+            Does it need an index? Large tables do.";
+        let sentence_tokens = bpe
+            .encode_ordinary("Vector search finds similar texts.")
+            .len();
+        let chunks = chunk_sentences(text, sentence_tokens + 4, 0, &bpe).unwrap();
+        assert_eq!(
+            chunks.iter().map(|c| c.text.as_str()).collect::<Vec<_>>(),
+            vec![
+                "Vector search finds similar texts.",
+                "It compares their embeddings!",
+                "Does it need an index? Large tables do.",
+            ]
+        );
+
+        // sentences are never split, even when they are longer than chunk_size
+        let chunks = chunk_sentences(text, 3, 0, &bpe).unwrap();
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[0].text, "Vector search finds similar texts.");
+
+        // the last sentences of a chunk are repeated at the start of the next one
+        let chunks = chunk_sentences(text, 20, 10, &bpe).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks[1].char_start < chunks[0].char_end);
+
+        // sentences of scripts without spaces after their punctuation
+        let text = "向量搜索很快。它比较嵌入。";
+        let chunks = chunk_sentences(text, 5, 0, &bpe).unwrap();
+        assert_eq!(chunks[0].text, "向量搜索很快。");
+    }
+
+    #[test]
+    fn test_chunk_markdown() {
+        let bpe = tokenizer(None).unwrap();
+        let text = "Intro text.\n\
+            # Guide\n\
+            ## Install\n\
+            Run the installer.\n\
+            ```sh\n\
+            # not a heading\n\
+            ```\n\
+            ### Linux ###\n\
+            Use the package.\n\
+            ## Usage\n\
+            Call the function.\n";
+        let chunks = chunk_markdown(text, 50, 0, &bpe).unwrap();
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.text.as_str(), c.headings.join(" > ")))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Intro text.", "".to_string()),
+                (
+                    "## Install\nRun the installer.\n```sh\n# not a heading\n```",
+                    "Guide > Install".to_string()
+                ),
+                (
+                    "### Linux ###\nUse the package.",
+                    "Guide > Install > Linux".to_string()
+                ),
+                ("## Usage\nCall the function.", "Guide > Usage".to_string()),
+            ]
+        );
+        assert_eq!(chunks[2].char_start, text.find("### Linux").unwrap());
+
+        // long sections are split further, keeping their headings
+        let text = format!("# Notes\n{}", "Vector search is fast. ".repeat(20));
+        let chunks = chunk_markdown(&text, 20, 0, &bpe).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks
+            .iter()
+            .all(|c| c.headings == vec!["Notes".to_string()]));
+
+        assert_eq!(atx_heading("#hashtag"), None);
+        assert_eq!(atx_heading("    # code"), None);
+        assert_eq!(atx_heading("####### seven"), None);
+        assert_eq!(atx_heading("# C# #"), Some((1, "C#".to_string())));
+    }
+
+    #[test]
+    fn test_chunk_separator() {
+        let bpe = tokenizer(None).unwrap();
+        let log = "2024-01-01 10:00 started\nloading config\n2024-01-01 10:01 ready\n2024-01-01 10:02 stopped";
+        let separator = separator_regex(r"(?m)^\d{4}-\d{2}-\d{2} ").unwrap();
+        let chunks = chunk_separator(log, &separator, 100, &bpe).unwrap();
+        // each entry is a chunk, starting with its timestamp, however short it is
+        let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "2024-01-01 10:00 started\nloading config",
+                "2024-01-01 10:01 ready",
+                "2024-01-01 10:02 stopped"
+            ]
+        );
+        for chunk in &chunks {
+            let text: String = log
+                .chars()
+                .skip(chunk.char_start)
+                .take(chunk.char_end - chunk.char_start)
+                .collect();
+            assert_eq!(text, chunk.text);
+        }
+        // text ahead of the first match is a chunk of its own, and long pieces are split further
+        let chunks = chunk_separator(
+            "intro;a b c d e f g h;end",
+            &separator_regex(";").unwrap(),
+            3,
+            &bpe,
+        )
+        .unwrap();
+        assert_eq!(chunks[0].text, "intro");
+        assert!(chunks.len() > 3);
+        assert!(chunks.iter().all(|c| c.token_count <= 3));
+        assert_eq!(chunks.last().unwrap().text, ";end");
+
+        assert!(separator_regex("(").is_err());
+        assert!(separator_regex("a*").is_err());
+        let params = ChunkParams {
+            chunk_size: 100,
+            strategy: ChunkStrategy::separator,
+            ..Default::default()
+        };
+        assert!(chunk(log, &params, &bpe).is_err());
+    }
+
+    #[test]
+    fn test_chunk_code() {
+        let bpe = tokenizer(None).unwrap();
+        let text = "use std::fmt;\n\n\
+            /// adds two numbers\n\
+            #[inline]\n\
+            fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n\n\
+            fn sub(a: i32, b: i32) -> i32 {\n    if a > b {\n        a - b\n    } else {\n        b - a\n    }\n}\n";
+        let chunks = chunk_code(text, 40, 0, &bpe).unwrap();
+        // blocks are merged up to chunk_size, comments and attributes are kept with the function below them,
+        // and if / else is a single block
+        assert_eq!(
+            chunks.iter().map(|c| c.text.as_str()).collect::<Vec<_>>(),
+            vec![
+                "use std::fmt;\n\n/// adds two numbers\n#[inline]\nfn add(a: i32, b: i32) -> i32 {\n    a + b\n}",
+                "fn sub(a: i32, b: i32) -> i32 {\n    if a > b {\n        a - b\n    } else {\n        b - a\n    }\n}",
+            ]
+        );
+
+        // a class longer than chunk_size is split on its methods, which keep their indentation
+        let text = "class Store:\n    \"\"\"a store of vectors\"\"\"\n\n    \
+            @property\n    def size(self):\n        return len(self.vectors)\n\n    \
+            def add(self, vector):\n        self.vectors.append(vector)\n";
+        let chunks = chunk_code(text, 20, 0, &bpe).unwrap();
+        assert_eq!(
+            chunks.iter().map(|c| c.text.as_str()).collect::<Vec<_>>(),
+            vec![
+                "class Store:\n    \"\"\"a store of vectors\"\"\"",
+                "    @property\n    def size(self):\n        return len(self.vectors)",
+                "    def add(self, vector):\n        self.vectors.append(vector)",
+            ]
+        );
+        assert!(chunks.iter().all(|c| c.token_count <= 20));
+
+        // code without any blocks falls back to lines
+        let text = "x = 1\n".repeat(20);
+        let chunks = chunk_code(&text, 10, 0, &bpe).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.text.starts_with("x = 1")));
+    }
+
+    #[test]
+    fn test_chunk_semantic() {
+        let bpe = tokenizer(None).unwrap();
+        let text = "Cats purr. Cats nap. Rust is fast. Rust is safe.";
+        let (sentences, windows) = semantic_windows(text);
+        assert_eq!(sentences.len(), 4);
+        assert_eq!(windows[0], "Cats purr. Cats nap.");
+        assert_eq!(windows[1], "Cats purr. Cats nap. Rust is fast.");
+        // the topic changes between the second and the third sentence
+        let embeddings = vec![
+            vec![1.0, 0.0],
+            vec![0.9, 0.1],
+            vec![0.1, 0.9],
+            vec![0.0, 1.0],
+        ];
+        let chunks = chunk_semantic(text, &sentences, &embeddings, 0.5, 100, &bpe).unwrap();
+        assert_eq!(
+            chunks.iter().map(|c| c.text.as_str()).collect::<Vec<_>>(),
+            vec!["Cats purr. Cats nap.", "Rust is fast. Rust is safe."]
+        );
+        // similar sentences are still split to fit chunk_size
+        let chunks = chunk_semantic(text, &sentences, &vec![vec![1.0]; 4], 0.5, 6, &bpe).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.token_count <= 6));
+
+        assert!(chunk_semantic(text, &sentences, &embeddings[1..], 0.5, 100, &bpe).is_err());
+        assert_eq!(semantic_windows("  ").0.len(), 0);
+    }
+
+    #[test]
+    fn test_chunk_changes() {
+        let existing = vec![
+            (1, "body".to_string(), "first".to_string()),
+            (2, "body".to_string(), "second".to_string()),
+            (3, "title".to_string(), "first".to_string()),
+        ];
+        let changes = chunk_changes(
+            &existing,
+            &[("body", "first"), ("body", "changed"), ("body", "first")],
+        );
+        // a chunk is matched once, and only within its column
+        assert_eq!(
+            changes,
+            ChunkChanges {
+                kept: vec![(1, 0)],
+                deleted: vec![2, 3],
+                inserted: vec![1, 2],
+            }
+        );
+        assert_eq!(chunk_changes(&[], &[("body", "first")]).inserted, vec![0]);
+    }
+
+    #[test]
+    fn test_chunk_strategy() {
+        let bpe = tokenizer(None).unwrap();
+        let text = "First line of the text\nSecond line of the text";
+        let mut params = ChunkParams {
+            chunk_size: 6,
+            ..Default::default()
+        };
+        let chunks = chunk(text, &params, &bpe).unwrap();
+        assert_eq!(chunks[0].text, "First line of the text\n");
+
+        params.strategy = ChunkStrategy::recursive;
+        let chunks = chunk(text, &params, &bpe).unwrap();
+        assert_eq!(chunks[0].text, "First line of the text");
+        assert_eq!(chunks[1].text, "Second line of the text");
+
+        params.separators = vec![" of ".to_string()];
+        let chunks = chunk(text, &params, &bpe).unwrap();
+        assert_eq!(chunks[0].text, "First line of");
+    }
+
+    #[test]
+    fn test_compiled_filter() {
+        let bpe = tokenizer(None).unwrap();
+        let filter = CompiledFilter::new(&ChunkFilter {
+            min_tokens: Some(4),
+            exclude_patterns: vec!["(?i)^all rights reserved".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let chunk = |text: &str| chunk_at(text, 0, text.len(), &bpe);
+        assert!(!filter.discards(&chunk("Vector search finds similar texts.")));
+        assert!(filter.discards(&chunk("Menu")));
+        assert!(filter.discards(&chunk("All rights reserved by the authors.")));
+        // without conditions, every chunk is kept
+        let filter = CompiledFilter::new(&ChunkFilter::default()).unwrap();
+        assert!(!filter.discards(&chunk("Menu")));
+        assert!(CompiledFilter::new(&ChunkFilter {
+            exclude_patterns: vec!["(".to_string()],
+            ..Default::default()
+        })
+        .is_err());
+        assert_eq!(
+            filter_function_query("public.is_useful"),
+            "SELECT public.is_useful(u.chunk) AS keep
+        FROM unnest($1::text[]) WITH ORDINALITY AS u(chunk, i)
+        ORDER BY u.i"
+        );
+    }
+
+    #[test]
+    fn test_chunk_column() {
+        let bpe = tokenizer(None).unwrap();
+        let text = "First line of the text\nSecond line of the text ";
+        let params = ChunkParams {
+            chunk_size: 6,
+            strategy: ChunkStrategy::recursive,
+            column_params: BTreeMap::from([
+                ("title".to_string(), None),
+                (
+                    "summary".to_string(),
+                    Some(ChunkParams {
+                        chunk_size: 3,
+                        ..Default::default()
+                    }),
+                ),
+            ]),
+            ..Default::default()
+        };
+        // columns without params of their own are chunked with the job's params
+        assert_eq!(chunk_column(text, "body", &params, &bpe).unwrap().len(), 2);
+        assert_eq!(
+            chunk_column(text, "summary", &params, &bpe).unwrap().len(),
+            4
+        );
+        // a column that is not chunked is a single chunk
+        let chunks = chunk_column(text, "title", &params, &bpe).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].text, text.trim_end());
+        assert_eq!(chunks[0].char_end, text.trim_end().chars().count());
+        assert!(chunk_column("  ", "title", &params, &bpe)
+            .unwrap()
+            .is_empty());
+    }
+}
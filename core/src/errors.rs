@@ -1,10 +1,13 @@
 use anyhow::Error as AnyhowError;
 use ollama_rs::error::OllamaError;
+#[cfg(feature = "worker")]
 use sqlx::error::Error as DbError;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum DatabaseError {
+    #[cfg(feature = "worker")]
     #[error("Database error: {0}")]
     Db(#[from] DbError),
     #[error("IO error: {0}")]
@@ -25,6 +28,32 @@ pub enum VectorizeError {
     InternalError(#[from] AnyhowError),
     #[error("model not found: {0}")]
     ModelNotFound(String),
+    #[error("embedding has {actual} dimensions, but the job's embeddings have {expected}")]
+    DimensionMismatch { expected: u32, actual: usize },
+    #[error("Failed to call method '{method}', received response with status code:{status} and body: {body}")]
+    ResponseError {
+        method: String,
+        status: reqwest::StatusCode,
+        body: String,
+        // how long the provider asked to wait before sending the request again
+        retry_after: Option<Duration>,
+    },
+    #[error("request is too large for the provider: {0}")]
+    PayloadTooLarge(String),
     #[error("ollama error: {0}")]
     OllamaError(#[from] OllamaError),
 }
+
+impl VectorizeError {
+    /// whether a request that failed with this error may succeed when it is sent again,
+    /// for rate limits, server errors and timeouts
+    pub fn is_transient(&self) -> bool {
+        match self {
+            VectorizeError::ResponseError { status, .. } => {
+                *status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            VectorizeError::Reqwest(e) => e.is_timeout() || e.is_connect(),
+            _ => false,
+        }
+    }
+}
@@ -1,4 +1,21 @@
+//! The parts of pg_vectorize that do not depend on Postgres: model parsing, provider requests and
+//! input batching, shared by the extension, the `vectorize-worker` binary and any sidecar service.
+//!
+//! The queue worker and its database access live in [`worker`], behind the default `worker` feature.
+//! Depend on this crate with `default-features = false` to leave out sqlx and pgmq.
+pub mod chunking;
 pub mod errors;
+pub mod preprocess;
 pub mod transformers;
 pub mod types;
+#[cfg(feature = "worker")]
 pub mod worker;
+
+pub use chunking::create_batches;
+pub use errors::VectorizeError;
+pub use transformers::providers::{
+    get_provider, prepare_generic_embedding_request, EmbeddingProvider, GenericEmbeddingRequest,
+    GenericEmbeddingResponse,
+};
+pub use transformers::types::Inputs;
+pub use types::{Model, ModelSource};
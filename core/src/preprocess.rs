@@ -0,0 +1,163 @@
+use crate::transformers::types::Inputs;
+use crate::types::{JobParams, Preprocess};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+// elements whose content is not part of a page's text: scripts, styles and navigation boilerplate
+const DROPPED_ELEMENTS: [&str; 11] = [
+    "head", "script", "style", "noscript", "template", "svg", "iframe", "nav", "header", "footer",
+    "aside",
+];
+
+lazy_static! {
+    static ref COMMENT: Regex = Regex::new(r"(?s)<!--.*?-->").expect("Invalid regex");
+    static ref DROPPED: Vec<Regex> = DROPPED_ELEMENTS
+        .iter()
+        .map(|e| Regex::new(&format!(r"(?is)<{e}\b[^>]*>.*?</{e}\s*>")).expect("Invalid regex"))
+        .collect();
+    // tags that start a new line of text
+    static ref BLOCK_TAG: Regex = Regex::new(
+        r"(?i)</?(p|div|br|hr|li|ul|ol|h[1-6]|tr|table|section|article|main|blockquote|pre|dd|dt|figcaption)\b[^>]*>"
+    )
+    .expect("Invalid regex");
+    static ref TAG: Regex = Regex::new(r"</?[A-Za-z!][^>]*>").expect("Invalid regex");
+    static ref ENTITY: Regex = Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[A-Za-z]+);").expect("Invalid regex");
+    static ref SPACES: Regex = Regex::new(r"[ \t\r\f\u{a0}]+").expect("Invalid regex");
+    static ref BLANK_LINES: Regex = Regex::new(r"\n{3,}").expect("Invalid regex");
+}
+
+/// the text of an html document, without its markup, scripts, styles and navigation boilerplate
+/// block elements such as paragraphs and list items are kept on lines of their own, and paragraphs
+/// are separated by a blank line, so that chunks can still be split on them
+pub fn strip_html(html: &str) -> String {
+    let mut text = COMMENT.replace_all(html, "").into_owned();
+    for dropped in DROPPED.iter() {
+        text = dropped.replace_all(&text, "").into_owned();
+    }
+    let text = BLOCK_TAG.replace_all(&text, "\n\n");
+    let text = TAG.replace_all(&text, "");
+    let text = ENTITY.replace_all(&text, |caps: &regex::Captures| {
+        decode_entity(&caps[1]).unwrap_or_else(|| caps[0].to_string())
+    });
+    let lines: Vec<String> = text
+        .split('\n')
+        .map(|line| SPACES.replace_all(line, " ").trim().to_string())
+        .collect();
+    BLANK_LINES
+        .replace_all(lines.join("\n").trim(), "\n\n")
+        .into_owned()
+}
+
+// the character of an html entity, given without its & and ;
+fn decode_entity(entity: &str) -> Option<String> {
+    let decoded = match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        _ => {
+            let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => entity.strip_prefix('#')?.parse().ok()?,
+            };
+            char::from_u32(code)?
+        }
+    };
+    Some(decoded.to_string())
+}
+
+/// a text, as preprocessed before it is chunked or embedded
+pub fn preprocess(text: &str, preprocess: Preprocess) -> String {
+    match preprocess {
+        Preprocess::html => strip_html(text),
+    }
+}
+
+/// the inputs of a job, as preprocessed before they are embedded
+/// the chunks of a chunked job were preprocessed before the job's rows were chunked
+pub fn preprocess_inputs(job_params: &JobParams, inputs: Vec<Inputs>) -> Vec<Inputs> {
+    let Some(pre) = job_params
+        .preprocess
+        .filter(|_| job_params.chunking.is_none())
+    else {
+        return inputs;
+    };
+    inputs
+        .into_iter()
+        .map(|input| {
+            let text = if job_params.is_weighted() {
+                // each column of a weighted job's json array is preprocessed on its own
+                match serde_json::from_str::<Vec<Option<String>>>(&input.inputs) {
+                    Ok(columns) => {
+                        let columns: Vec<Option<String>> = columns
+                            .into_iter()
+                            .map(|c| c.map(|c| preprocess(&c, pre)))
+                            .collect();
+                        serde_json::to_string(&columns).unwrap_or(input.inputs.clone())
+                    }
+                    Err(_) => input.inputs.clone(),
+                }
+            } else {
+                preprocess(&input.inputs, pre)
+            };
+            Inputs {
+                inputs: text,
+                ..input
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_strip_html() {
+        let html = r#"<html><head><title>Docs</title><style>p { color: red; }</style></head>
+            <body>
+                <nav><a href="/">Home</a> | <a href="/docs">Docs</a></nav>
+                <!-- main content -->
+                <h1>Vector   search</h1>
+                <p>Finds <b>similar</b> texts &amp; images.<br>Fast&nbsp;too.</p>
+                <script>track("page");</script>
+                <ul><li>a &lt; b</li><li>&#x1F600; &#65;</li></ul>
+                <footer>Copyright</footer>
+            </body></html>"#;
+        assert_eq!(
+            strip_html(html),
+            "Vector search\n\nFinds similar texts & images.\n\nFast too.\n\na < b\n\n😀 A"
+        );
+        // text that is not markup is left alone
+        assert_eq!(strip_html("1 < 2 and 3 > 2"), "1 < 2 and 3 > 2");
+        assert_eq!(strip_html("&unknown; &#xZZ;"), "&unknown; &#xZZ;");
+    }
+
+    #[test]
+    fn test_preprocess_inputs() {
+        let input = |text: &str| Inputs {
+            record_id: "1".to_string(),
+            inputs: text.to_string(),
+            token_estimate: 10,
+        };
+        let mut job_params = JobParams {
+            columns: vec!["title".to_string(), "body".to_string()],
+            ..Default::default()
+        };
+        // inputs are embedded as they are without a preprocessor
+        let inputs = preprocess_inputs(&job_params, vec![input("<p>text</p>")]);
+        assert_eq!(inputs[0].inputs, "<p>text</p>");
+
+        job_params.preprocess = Some(Preprocess::html);
+        let inputs = preprocess_inputs(&job_params, vec![input("<p>text</p>")]);
+        assert_eq!(inputs[0].inputs, "text");
+
+        job_params.column_weights = BTreeMap::from([("title".to_string(), 2.0)]);
+        let inputs = preprocess_inputs(&job_params, vec![input(r#"["<h1>Title</h1>", null]"#)]);
+        assert_eq!(inputs[0].inputs, r#"["Title",null]"#);
+    }
+}
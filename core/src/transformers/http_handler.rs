@@ -1,23 +1,243 @@
 use crate::errors::VectorizeError;
 use crate::transformers::types::{Inputs, PairedEmbeddings};
+use crate::types::{JobParams, Model};
+use anyhow::anyhow;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::future::Future;
+use std::time::Duration;
+
+/// how long the HTTP calls to providers may take
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeouts {
+    // to connect to the provider
+    pub connect: Duration,
+    // for an embedding request to be answered
+    pub embedding: Duration,
+    // for a chat request to be answered, or between the chunks of a streamed response
+    pub chat: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(10),
+            embedding: Duration::from_secs(120),
+            chat: Duration::from_secs(120),
+        }
+    }
+}
+
+impl Timeouts {
+    /// the timeouts with a job's own overrides
+    pub fn for_job(self, job_params: &JobParams) -> Self {
+        let secs = |s: u32| Duration::from_secs(s as u64);
+        Timeouts {
+            connect: job_params
+                .connect_timeout_sec
+                .map(secs)
+                .unwrap_or(self.connect),
+            embedding: job_params
+                .request_timeout_sec
+                .map(secs)
+                .unwrap_or(self.embedding),
+            chat: job_params
+                .request_timeout_sec
+                .map(secs)
+                .unwrap_or(self.chat),
+        }
+    }
+}
+
+tokio::task_local! {
+    static TIMEOUTS: Timeouts;
+}
+
+/// runs a future, with the provider calls it makes held to the given timeouts
+pub async fn with_timeouts<F: Future>(timeouts: Timeouts, f: F) -> F::Output {
+    TIMEOUTS.scope(timeouts, f).await
+}
+
+/// the timeouts of provider calls, the defaults outside of with_timeouts
+pub fn timeouts() -> Timeouts {
+    TIMEOUTS.try_with(|t| *t).unwrap_or_default()
+}
+
+/// the proxies HTTP calls to providers are sent through
+/// when none are given, the HTTP_PROXY, HTTPS_PROXY and NO_PROXY environment variables are followed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Proxies {
+    // for http:// urls
+    pub http: Option<String>,
+    // for https:// urls
+    pub https: Option<String>,
+    // comma separated hosts, domains and IP networks that are called directly, e.g. localhost,.internal,10.0.0.0/8
+    pub no_proxy: Option<String>,
+}
+
+impl Proxies {
+    /// the proxies of the given urls, of which empty ones are left out
+    pub fn new(
+        http: Option<String>,
+        https: Option<String>,
+        no_proxy: Option<String>,
+    ) -> Result<Self, VectorizeError> {
+        let given = |s: Option<String>| s.filter(|s| !s.trim().is_empty());
+        let proxies = Proxies {
+            http: given(http),
+            https: given(https),
+            no_proxy: given(no_proxy),
+        };
+        proxies.proxies()?;
+        Ok(proxies)
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.http.is_some() || self.https.is_some()
+    }
+
+    // the reqwest proxies, each skipping the no_proxy hosts
+    fn proxies(&self) -> Result<Vec<reqwest::Proxy>, VectorizeError> {
+        let no_proxy = self
+            .no_proxy
+            .as_deref()
+            .and_then(reqwest::NoProxy::from_string);
+        let mut proxies = Vec::new();
+        if let Some(url) = &self.http {
+            proxies.push(reqwest::Proxy::http(url)?.no_proxy(no_proxy.clone()));
+        }
+        if let Some(url) = &self.https {
+            proxies.push(reqwest::Proxy::https(url)?.no_proxy(no_proxy));
+        }
+        Ok(proxies)
+    }
+}
+
+tokio::task_local! {
+    static PROXIES: Proxies;
+}
+
+/// runs a future, with the provider calls it makes sent through the given proxies
+pub async fn with_proxies<F: Future>(proxies: Proxies, f: F) -> F::Output {
+    PROXIES.scope(proxies, f).await
+}
+
+/// the proxies of provider calls, none outside of with_proxies
+pub fn proxies() -> Proxies {
+    PROXIES.try_with(|p| p.clone()).unwrap_or_default()
+}
+
+/// extra headers sent with each HTTP call to a provider, e.g. the auth header of an API gateway
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Headers(pub HeaderMap);
+
+impl Headers {
+    /// the headers of a model, from a json object of header names and values per provider or model
+    /// e.g. {"openai": {"OpenAI-Organization": "org-1"}, "openai/text-embedding-3-small": {"x-team": "search"}}
+    /// a model's own headers, then those of its registration's "headers" option, take the place of its provider's
+    pub fn of(config: Option<&str>, model: &Model) -> Result<Self, VectorizeError> {
+        let config: serde_json::Value = match config.filter(|c| !c.trim().is_empty()) {
+            Some(c) => serde_json::from_str(c)?,
+            None => serde_json::json!({}),
+        };
+        if !config.is_object() {
+            return Err(
+                anyhow!("http headers must be a json object of providers and models").into(),
+            );
+        }
+        let registered = model
+            .registration
+            .as_ref()
+            .and_then(|r| r.options.get("headers"));
+        let mut headers = HeaderMap::new();
+        for entry in [
+            config.get(model.source.to_string()),
+            config.get(&model.fullname),
+            registered,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            let Some(entry) = entry.as_object() else {
+                return Err(anyhow!("the http headers of {model} must be a json object").into());
+            };
+            for (name, value) in entry {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| anyhow!("invalid http header name: {name}"))?;
+                let value = value
+                    .as_str()
+                    .and_then(|v| HeaderValue::from_str(v).ok())
+                    .ok_or_else(|| anyhow!("invalid value of http header {name}"))?;
+                headers.insert(name, value);
+            }
+        }
+        Ok(Headers(headers))
+    }
+}
+
+tokio::task_local! {
+    static HEADERS: Headers;
+}
+
+/// runs a future, with the provider calls it makes sent with the given headers
+pub async fn with_headers<F: Future>(headers: Headers, f: F) -> F::Output {
+    HEADERS.scope(headers, f).await
+}
+
+/// the extra headers of provider calls, none outside of with_headers
+pub fn headers() -> Headers {
+    HEADERS.try_with(|h| h.clone()).unwrap_or_default()
+}
+
+/// a client for requests to a provider, that gives up connecting after the connect timeout
+/// and is sent through the proxies of with_proxies, or otherwise those of the environment
+/// with the headers of with_headers, which the provider's own headers take the place of
+pub fn client() -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(timeouts().connect)
+        .default_headers(headers().0);
+    // Proxies::new has checked the urls, so these do not fail
+    for proxy in proxies().proxies().unwrap_or_default() {
+        builder = builder.proxy(proxy);
+    }
+    builder.build().unwrap_or_default()
+}
 
 pub async fn handle_response<T: for<'de> serde::Deserialize<'de>>(
     resp: reqwest::Response,
     method: &'static str,
 ) -> Result<T, VectorizeError> {
+    // rejected for its size, which callers can recover from by sending less at once
+    if resp.status() == reqwest::StatusCode::PAYLOAD_TOO_LARGE {
+        return Err(VectorizeError::PayloadTooLarge(resp.text().await?));
+    }
     if !resp.status().is_success() {
-        let errmsg = format!(
-            "Failed to call method '{}', received response with status code:{} and body: {}",
-            method,
-            resp.status(),
-            resp.text().await?
-        );
-        return Err(anyhow::anyhow!(errmsg)).map_err(VectorizeError::from);
+        return Err(response_error(resp, method).await);
     }
     let value = resp.json::<T>().await?;
     Ok(value)
 }
 
+/// the error of a response that was not successful
+pub async fn response_error(resp: reqwest::Response, method: &str) -> VectorizeError {
+    let status = resp.status();
+    // only the delay in seconds form of Retry-After is used
+    let retry_after = resp
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(std::time::Duration::from_secs);
+    match resp.text().await {
+        Ok(body) => VectorizeError::ResponseError {
+            method: method.to_string(),
+            status,
+            body,
+            retry_after,
+        },
+        Err(e) => e.into(),
+    }
+}
+
 // merges the vec of inputs with the embedding responses
 pub fn merge_input_output(inputs: Vec<Inputs>, values: Vec<Vec<f64>>) -> Vec<PairedEmbeddings> {
     inputs
@@ -29,3 +249,149 @@ pub fn merge_input_output(inputs: Vec<Inputs>, values: Vec<Vec<f64>>) -> Vec<Pai
         })
         .collect()
 }
+
+/// combines embeddings of the same record into one, as their weighted sum
+/// each embedding is normalized first, so a column's weight is not skewed by the length of its embedding
+pub fn fuse_embeddings(weighted: &[(f64, Vec<f64>)]) -> Vec<f64> {
+    let dim = weighted.first().map(|(_, e)| e.len()).unwrap_or(0);
+    let mut fused = vec![0.0; dim];
+    for (weight, embedding) in weighted {
+        for (f, x) in fused.iter_mut().zip(normalize(embedding)) {
+            *f += weight * x;
+        }
+    }
+    normalize(&fused)
+}
+
+/// keeps the leading dimensions of an embedding, scaled back to unit length
+pub fn truncate_embedding(embedding: &[f64], dimensions: usize) -> Vec<f64> {
+    normalize(&embedding[..dimensions.min(embedding.len())])
+}
+
+/// scales an embedding to unit length, leaving a zero embedding as it is
+pub fn normalize(embedding: &[f64]) -> Vec<f64> {
+    let norm = embedding.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm == 0.0 {
+        return embedding.to_vec();
+    }
+    embedding.iter().map(|x| x / norm).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeouts() {
+        let job_params = JobParams {
+            request_timeout_sec: Some(600),
+            ..Default::default()
+        };
+        let job_timeouts = Timeouts::default().for_job(&job_params);
+        assert_eq!(job_timeouts.connect, Duration::from_secs(10));
+        assert_eq!(job_timeouts.embedding, Duration::from_secs(600));
+        assert_eq!(job_timeouts.chat, Duration::from_secs(600));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(timeouts(), Timeouts::default());
+        let scoped = runtime.block_on(with_timeouts(job_timeouts, async { timeouts() }));
+        assert_eq!(scoped, job_timeouts);
+    }
+
+    #[test]
+    fn test_proxies() {
+        let corp = Proxies::new(
+            None,
+            Some("http://proxy.corp:3128".to_string()),
+            Some("".to_string()),
+        )
+        .unwrap();
+        assert!(corp.is_set());
+        assert_eq!(corp.no_proxy, None);
+        assert!(!Proxies::new(Some(" ".to_string()), None, None)
+            .unwrap()
+            .is_set());
+        assert!(Proxies::new(Some("http://[proxy".to_string()), None, None).is_err());
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(proxies(), Proxies::default());
+        let scoped = runtime.block_on(with_proxies(corp.clone(), async { proxies() }));
+        assert_eq!(scoped, corp);
+    }
+
+    #[test]
+    fn test_headers() {
+        let config = r#"{
+            "openai": {"OpenAI-Organization": "org-1", "x-team": "platform"},
+            "openai/text-embedding-3-small": {"x-team": "search"}
+        }"#;
+        let model = Model::new("openai/text-embedding-3-small").unwrap();
+        let headers = Headers::of(Some(config), &model).unwrap().0;
+        assert_eq!(headers["openai-organization"], "org-1");
+        assert_eq!(headers["x-team"], "search");
+        let other = Model::new("openai/text-embedding-3-large").unwrap();
+        assert_eq!(
+            Headers::of(Some(config), &other).unwrap().0["x-team"],
+            "platform"
+        );
+        assert!(Headers::of(
+            Some(config),
+            &Model::new("cohere/embed-english-v3.0").unwrap()
+        )
+        .unwrap()
+        .0
+        .is_empty());
+        assert_eq!(Headers::of(None, &model).unwrap(), Headers::default());
+
+        // a registered model's own headers come last
+        let registered = Model::registered(
+            "gateway-embed",
+            crate::types::ModelSource::OpenAI,
+            crate::types::Registration {
+                options: serde_json::json!({"headers": {"cf-aig-authorization": "Bearer abc"}}),
+                ..Default::default()
+            },
+        );
+        let headers = Headers::of(Some(config), &registered).unwrap().0;
+        assert_eq!(headers["cf-aig-authorization"], "Bearer abc");
+        assert_eq!(headers["x-team"], "platform");
+
+        assert!(Headers::of(Some("[]"), &model).is_err());
+        assert!(Headers::of(Some(r#"{"openai": "x"}"#), &model).is_err());
+        assert!(Headers::of(Some(r#"{"openai": {"bad header": "x"}}"#), &model).is_err());
+        assert!(Headers::of(Some(r#"{"openai": {"x-n": 1}}"#), &model).is_err());
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let set = Headers::of(Some(config), &model).unwrap();
+        let scoped = runtime.block_on(with_headers(set.clone(), async { super::headers() }));
+        assert_eq!(scoped, set);
+    }
+
+    #[test]
+    fn test_fuse_embeddings() {
+        let fused = fuse_embeddings(&[(1.0, vec![2.0, 0.0]), (1.0, vec![0.0, 5.0])]);
+        let expected = 1.0 / 2.0_f64.sqrt();
+        assert!((fused[0] - expected).abs() < 1e-9);
+        assert!((fused[1] - expected).abs() < 1e-9);
+
+        // the heavier column dominates
+        let fused = fuse_embeddings(&[(3.0, vec![1.0, 0.0]), (1.0, vec![0.0, 1.0])]);
+        assert!(fused[0] > fused[1]);
+        assert!((fused[0].powi(2) + fused[1].powi(2) - 1.0).abs() < 1e-9);
+
+        assert!(fuse_embeddings(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_truncate_embedding() {
+        let truncated = truncate_embedding(&[3.0, 4.0, 12.0], 2);
+        assert_eq!(truncated, vec![0.6, 0.8]);
+        assert_eq!(truncate_embedding(&[0.0, 0.0, 1.0], 2), vec![0.0, 0.0]);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod generic;
 pub mod http_handler;
 pub mod providers;
+pub mod retry;
 pub mod types;
@@ -0,0 +1,154 @@
+use serde::Deserialize;
+
+use super::ChatMessageRequest;
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{self, handle_response};
+use anyhow::anyhow;
+use log::warn;
+use std::env;
+
+pub const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+pub const ANTHROPIC_VERSION: &str = "2023-06-01";
+// the Messages API requires a bound on the tokens of each response
+pub const ANTHROPIC_MAX_TOKENS: u32 = 1024;
+
+pub struct AnthropicProvider {
+    pub url: String,
+    pub api_key: String,
+    pub max_tokens: u32,
+}
+
+impl AnthropicProvider {
+    pub fn new(url: Option<String>, api_key: Option<String>) -> Result<Self, VectorizeError> {
+        let api_key = api_key
+            .or_else(|| env::var("ANTHROPIC_API_KEY").ok())
+            .ok_or_else(|| anyhow!("Anthropic api key is required"))?;
+        Ok(AnthropicProvider {
+            url: url.unwrap_or_else(|| ANTHROPIC_BASE_URL.to_string()),
+            api_key,
+            max_tokens: ANTHROPIC_MAX_TOKENS,
+        })
+    }
+
+    pub async fn generate_response(
+        &self,
+        model_name: String,
+        messages: &[ChatMessageRequest],
+    ) -> Result<String, VectorizeError> {
+        let client = http_handler::client();
+        let mut body = messages_body(messages, self.max_tokens);
+        body["model"] = model_name.into();
+        let response = client
+            .post(format!("{}/messages", self.url))
+            .timeout(http_handler::timeouts().chat)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&body)
+            .send()
+            .await?;
+        let resp = handle_response::<MessagesResponse>(response, "messages").await?;
+        Ok(resp.text())
+    }
+}
+
+/// the body of a Messages API request, without its model
+/// system messages are given as the system prompt, as the API only takes user and assistant turns
+pub(crate) fn messages_body(messages: &[ChatMessageRequest], max_tokens: u32) -> serde_json::Value {
+    let system = messages
+        .iter()
+        .filter(|m| m.role == "system" && !m.content.is_empty())
+        .map(|m| m.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let messages: Vec<&ChatMessageRequest> =
+        messages.iter().filter(|m| m.role != "system").collect();
+    let mut body = serde_json::json!({
+        "max_tokens": max_tokens,
+        "messages": messages,
+    });
+    if !system.is_empty() {
+        body["system"] = system.into();
+    }
+    body
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    #[serde(default)]
+    stop_reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ContentBlock {
+    #[serde(default)]
+    text: String,
+}
+
+impl MessagesResponse {
+    /// the text of the response, warning when it was cut off by max_tokens
+    pub(crate) fn text(self) -> String {
+        if self.stop_reason.as_deref() == Some("max_tokens") {
+            warn!("chat response was truncated, it reached its max_tokens");
+        }
+        self.content
+            .into_iter()
+            .map(|c| c.text)
+            .collect::<Vec<_>>()
+            .join("")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_body() {
+        let body = messages_body(
+            &[
+                ChatMessageRequest {
+                    role: "system".to_string(),
+                    content: "answer from the context".to_string(),
+                },
+                ChatMessageRequest {
+                    role: "user".to_string(),
+                    content: "what is a pencil?".to_string(),
+                },
+            ],
+            256,
+        );
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "max_tokens": 256,
+                "system": "answer from the context",
+                "messages": [{"role": "user", "content": "what is a pencil?"}],
+            })
+        );
+        // generate() renders an empty system prompt, which is left out
+        let body = messages_body(
+            &[
+                ChatMessageRequest {
+                    role: "system".to_string(),
+                    content: "".to_string(),
+                },
+                ChatMessageRequest {
+                    role: "user".to_string(),
+                    content: "hello".to_string(),
+                },
+            ],
+            256,
+        );
+        assert!(body.get("system").is_none());
+
+        let resp: MessagesResponse = serde_json::from_value(serde_json::json!({
+            "content": [{"type": "text", "text": "a writing "}, {"type": "text", "text": "tool"}],
+            "stop_reason": "end_turn",
+        }))
+        .unwrap();
+        assert_eq!(resp.text(), "a writing tool");
+    }
+}
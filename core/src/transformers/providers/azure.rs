@@ -0,0 +1,164 @@
+use url::Url;
+
+use super::openai::{openai_embedding_dim, OpenAIEmbeddingResponse};
+use super::{
+    probe_model_dim, ChatMessageRequest, ChatResponse, EmbeddingProvider, GenericEmbeddingRequest,
+    GenericEmbeddingResponse,
+};
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{self, handle_response};
+use crate::transformers::providers;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::env;
+
+// used when the service url does not give an api-version of its own
+pub const AZURE_OPENAI_API_VERSION: &str = "2024-10-21";
+
+/// Azure OpenAI serves each model from a deployment on the account's resource,
+/// https://{resource}.openai.azure.com/openai/deployments/{deployment}/...?api-version=...
+pub struct AzureOpenAIProvider {
+    // the resource's endpoint, without the api-version
+    pub url: String,
+    pub api_key: String,
+    pub api_version: String,
+}
+
+impl AzureOpenAIProvider {
+    /// the url is the resource's endpoint, and can give the api-version in its query string
+    pub fn new(url: Option<String>, api_key: Option<String>) -> Result<Self, VectorizeError> {
+        let url = url.ok_or_else(|| anyhow!("Azure OpenAI service url is required"))?;
+        let api_key = api_key
+            .or_else(|| env::var("AZURE_OPENAI_API_KEY").ok())
+            .ok_or_else(|| anyhow!("Azure OpenAI api key is required"))?;
+        let (url, api_version) = azure_endpoint(&url)?;
+        Ok(AzureOpenAIProvider {
+            url,
+            api_key,
+            api_version,
+        })
+    }
+
+    // the url of a request to a deployment, e.g. its embeddings or chat/completions
+    fn deployment_url(&self, deployment: &str, method: &str) -> String {
+        format!(
+            "{}/openai/deployments/{deployment}/{method}?api-version={}",
+            self.url, self.api_version
+        )
+    }
+}
+
+// the endpoint of a resource, and the api-version given in its query string
+fn azure_endpoint(url: &str) -> Result<(String, String), VectorizeError> {
+    let mut parsed =
+        Url::parse(url).map_err(|e| anyhow!("invalid Azure OpenAI service url {url}: {e}"))?;
+    let api_version = parsed
+        .query_pairs()
+        .find(|(k, _)| k == "api-version")
+        .map(|(_, v)| v.into_owned())
+        .unwrap_or_else(|| AZURE_OPENAI_API_VERSION.to_string());
+    parsed.set_query(None);
+    let endpoint = parsed.as_str().trim_end_matches('/');
+    // the endpoint can be given as it is shown in the portal, or with its /openai path
+    let endpoint = endpoint.strip_suffix("/openai").unwrap_or(endpoint);
+    Ok((endpoint.to_string(), api_version))
+}
+
+#[async_trait]
+impl EmbeddingProvider for AzureOpenAIProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let client = http_handler::client();
+        let embeddings_url = self.deployment_url(&request.model, "embeddings");
+        let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(request.input.len());
+        // the deployment determines the model, so requests only carry their inputs
+        for input in providers::split_vector(request.input.clone(), 2048) {
+            let response = client
+                .post(&embeddings_url)
+                .timeout(http_handler::timeouts().embedding)
+                .header("Accept", "application/json")
+                .header("Content-Type", "application/json")
+                .header("api-key", &self.api_key)
+                .json(&serde_json::json!({ "input": input }))
+                .send()
+                .await?;
+            let embeddings =
+                handle_response::<OpenAIEmbeddingResponse>(response, "embeddings").await?;
+            all_embeddings.extend(embeddings.data.iter().map(|x| x.embedding.clone()));
+        }
+        Ok(GenericEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    fn endpoint(&self) -> String {
+        self.url.clone()
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        // deployments are often named after their model, any others are probed
+        match openai_embedding_dim(model_name) {
+            Some(dim) => Ok(dim),
+            None => probe_model_dim(self, model_name).await,
+        }
+    }
+}
+
+impl AzureOpenAIProvider {
+    pub async fn generate_response(
+        &self,
+        deployment: String,
+        messages: &[ChatMessageRequest],
+    ) -> Result<String, VectorizeError> {
+        let client = http_handler::client();
+        let chat_url = self.deployment_url(&deployment, "chat/completions");
+        let response = client
+            .post(&chat_url)
+            .timeout(http_handler::timeouts().chat)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .header("api-key", &self.api_key)
+            .json(&serde_json::json!({ "messages": messages }))
+            .send()
+            .await?;
+        let chat_response = handle_response::<ChatResponse>(response, "chat/completions").await?;
+        Ok(chat_response.choices[0].message.content.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_azure_endpoint() {
+        let (url, api_version) = azure_endpoint("https://acme.openai.azure.com/").unwrap();
+        assert_eq!(url, "https://acme.openai.azure.com");
+        assert_eq!(api_version, AZURE_OPENAI_API_VERSION);
+
+        let (url, api_version) =
+            azure_endpoint("https://acme.openai.azure.com/openai?api-version=2024-06-01").unwrap();
+        assert_eq!(url, "https://acme.openai.azure.com");
+        assert_eq!(api_version, "2024-06-01");
+        assert!(azure_endpoint("acme.openai.azure.com").is_err());
+    }
+
+    #[test]
+    fn test_deployment_url() {
+        let provider = AzureOpenAIProvider::new(
+            Some("https://acme.openai.azure.com?api-version=2024-06-01".to_string()),
+            Some("key".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            provider.deployment_url("embed-small", "embeddings"),
+            "https://acme.openai.azure.com/openai/deployments/embed-small/embeddings?api-version=2024-06-01"
+        );
+        assert_eq!(
+            provider.deployment_url("gpt-4o", "chat/completions"),
+            "https://acme.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-06-01"
+        );
+    }
+}
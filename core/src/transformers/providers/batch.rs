@@ -0,0 +1,259 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use std::collections::HashMap;
+
+use super::{embeds_content, EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse};
+use crate::errors::VectorizeError;
+use crate::transformers::retry::{with_retries, RetryPolicy};
+use crate::types::ModelSource;
+
+/// the most texts in an embedding request to a provider, unless configured otherwise
+pub fn default_batch_size(source: &ModelSource) -> usize {
+    match source {
+        ModelSource::OpenAI
+        | ModelSource::AzureOpenAI
+        | ModelSource::Portkey
+        | ModelSource::Cohere
+        | ModelSource::Bedrock => 96,
+        ModelSource::Voyage | ModelSource::Mistral | ModelSource::Jina => 128,
+        ModelSource::Gemini => 100,
+        ModelSource::HuggingFace | ModelSource::Ollama => 32,
+        _ => 64,
+    }
+}
+
+// the most text, in bytes, that fits in one request of providers that limit a request's tokens
+// OpenAI takes 300k tokens per request, these are about 4 bytes each
+fn max_batch_bytes(source: &ModelSource) -> Option<usize> {
+    match source {
+        ModelSource::OpenAI | ModelSource::AzureOpenAI | ModelSource::Portkey => Some(1_000_000),
+        ModelSource::Voyage => Some(400_000),
+        _ => None,
+    }
+}
+
+/// the batch size of a provider in a json object of sources to batch sizes, e.g. {"openai": 256, "ollama": 8}
+/// providers that are not in it keep their default
+pub fn batch_size(
+    source: &ModelSource,
+    batch_sizes: Option<&str>,
+) -> Result<usize, VectorizeError> {
+    let Some(batch_sizes) = batch_sizes.filter(|b| !b.trim().is_empty()) else {
+        return Ok(default_batch_size(source));
+    };
+    let batch_sizes: HashMap<String, usize> = serde_json::from_str(batch_sizes)
+        .map_err(|e| anyhow!("batch sizes must be a json object of positive integers: {e}"))?;
+    match batch_sizes.get(&source.to_string()) {
+        Some(0) => Err(anyhow!("batch size of {source} must be greater than 0"))?,
+        Some(size) => Ok(*size),
+        None => Ok(default_batch_size(source)),
+    }
+}
+
+/// sends the texts of an embedding request to a provider in batches of at most batch_size texts,
+/// with up to max_concurrent batches in flight at once
+/// batches are also kept under the provider's payload limit, and a batch the provider rejects
+/// as too large is split in half until it is taken
+/// batches that fail with a transient error are sent again as the retry policy allows
+pub struct BatchedProvider {
+    provider: Box<dyn EmbeddingProvider>,
+    source: ModelSource,
+    batch_size: usize,
+    max_bytes: Option<usize>,
+    max_concurrent: usize,
+    retry: RetryPolicy,
+}
+
+impl BatchedProvider {
+    pub fn new(
+        provider: Box<dyn EmbeddingProvider>,
+        source: &ModelSource,
+        batch_size: usize,
+        max_concurrent: usize,
+        retry: RetryPolicy,
+    ) -> Self {
+        BatchedProvider {
+            provider,
+            source: source.clone(),
+            batch_size: batch_size.max(1),
+            max_bytes: max_batch_bytes(source),
+            max_concurrent: max_concurrent.max(1),
+            retry,
+        }
+    }
+
+    // embeds a batch, splitting it in half for as long as the provider rejects it as too large
+    async fn embed_batch(
+        &self,
+        request: &GenericEmbeddingRequest,
+        batch: &[String],
+    ) -> Result<Vec<Vec<f64>>, VectorizeError> {
+        let mut embeddings = Vec::with_capacity(batch.len());
+        // halves are taken from the back, so that they keep their order
+        let mut pending = vec![batch];
+        while let Some(batch) = pending.pop() {
+            let batch_request = GenericEmbeddingRequest {
+                input: batch.to_vec(),
+                ..request.clone()
+            };
+            let response = with_retries(&self.retry, || {
+                self.provider.generate_embedding(&batch_request)
+            })
+            .await;
+            match response {
+                Ok(response) => embeddings.extend(response.embeddings),
+                Err(VectorizeError::PayloadTooLarge(_)) if batch.len() > 1 => {
+                    let (first, second) = batch.split_at(batch.len() / 2);
+                    pending.push(second);
+                    pending.push(first);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(embeddings)
+    }
+}
+
+// splits texts into batches of at most batch_size texts and max_bytes bytes
+// a text longer than max_bytes is a batch of its own, its provider truncates or rejects it
+fn split_batches(input: &[String], batch_size: usize, max_bytes: Option<usize>) -> Vec<&[String]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut bytes = 0;
+    for (i, text) in input.iter().enumerate() {
+        let full = i - start == batch_size;
+        let too_large = matches!(max_bytes, Some(max) if i > start && bytes + text.len() > max);
+        if full || too_large {
+            batches.push(&input[start..i]);
+            start = i;
+            bytes = 0;
+        }
+        bytes += text.len();
+    }
+    if start < input.len() {
+        batches.push(&input[start..]);
+    }
+    batches
+}
+
+#[async_trait]
+impl EmbeddingProvider for BatchedProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        if !embeds_content(&self.source, request.content_type) {
+            return Err(anyhow!(
+                "{} does not embed content of type {}",
+                self.source,
+                request.content_type
+            ))?;
+        }
+        let request = &request.clone().with_instructions();
+        let requests: Vec<_> = split_batches(&request.input, self.batch_size, self.max_bytes)
+            .into_iter()
+            .map(|batch| self.embed_batch(request, batch))
+            .collect();
+        // buffered keeps the embeddings of the batches in order, however they complete
+        let batches: Vec<Vec<Vec<f64>>> = stream::iter(requests)
+            .buffered(self.max_concurrent)
+            .try_collect()
+            .await?;
+        Ok(GenericEmbeddingResponse {
+            embeddings: batches.into_iter().flatten().collect(),
+        })
+    }
+
+    fn endpoint(&self) -> String {
+        self.provider.endpoint()
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        self.provider.model_dim(model_name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformers::providers::InputType;
+    use crate::types::ContentType;
+
+    // embeds each text as its length, and rejects batches of more than two texts as too large
+    struct LengthProvider;
+
+    #[async_trait]
+    impl EmbeddingProvider for LengthProvider {
+        async fn generate_embedding<'a>(
+            &self,
+            request: &'a GenericEmbeddingRequest,
+        ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+            if request.input.len() > 2 {
+                return Err(VectorizeError::PayloadTooLarge(
+                    "too many texts".to_string(),
+                ));
+            }
+            Ok(GenericEmbeddingResponse {
+                embeddings: request.input.iter().map(|t| vec![t.len() as f64]).collect(),
+            })
+        }
+
+        async fn model_dim(&self, _model_name: &str) -> Result<u32, VectorizeError> {
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_batched_provider() {
+        let provider = BatchedProvider::new(
+            Box::new(LengthProvider),
+            &ModelSource::OpenAI,
+            5,
+            3,
+            RetryPolicy::default(),
+        );
+        let request = GenericEmbeddingRequest {
+            input: (1..=12).map(|n| "x".repeat(n)).collect(),
+            model: "text-embedding-3-small".to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+            content_type: ContentType::text,
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let response = runtime
+            .block_on(provider.generate_embedding(&request))
+            .unwrap();
+        let expected: Vec<Vec<f64>> = (1..=12).map(|n| vec![n as f64]).collect();
+        assert_eq!(response.embeddings, expected);
+    }
+
+    #[test]
+    fn test_split_batches() {
+        let input: Vec<String> = ["a", "bb", "ccc", "dddd", "e"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let lens = |batches: Vec<&[String]>| batches.iter().map(|b| b.len()).collect::<Vec<_>>();
+        assert_eq!(lens(split_batches(&input, 2, None)), vec![2, 2, 1]);
+        assert_eq!(lens(split_batches(&input, 10, Some(6))), vec![3, 2]);
+        // texts longer than the limit are sent on their own
+        assert_eq!(
+            lens(split_batches(&input, 10, Some(2))),
+            vec![1, 1, 1, 1, 1]
+        );
+        assert!(split_batches(&[], 2, None).is_empty());
+    }
+
+    #[test]
+    fn test_batch_size() {
+        assert_eq!(batch_size(&ModelSource::OpenAI, None).unwrap(), 96);
+        let sizes = Some(r#"{"openai": 256, "ollama": 8}"#);
+        assert_eq!(batch_size(&ModelSource::OpenAI, sizes).unwrap(), 256);
+        assert_eq!(batch_size(&ModelSource::Cohere, sizes).unwrap(), 96);
+        assert!(batch_size(&ModelSource::OpenAI, Some(r#"{"openai": 0}"#)).is_err());
+        assert!(batch_size(&ModelSource::OpenAI, Some("[96]")).is_err());
+    }
+}
@@ -0,0 +1,482 @@
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use super::anthropic::{messages_body, MessagesResponse, ANTHROPIC_MAX_TOKENS};
+use super::{
+    probe_model_dim, ChatMessageRequest, EmbeddingProvider, GenericEmbeddingRequest,
+    GenericEmbeddingResponse, InputType,
+};
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{self, handle_response};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::env;
+use std::time::Duration;
+
+// the service that requests to the Bedrock runtime are signed for
+const SIGNING_SERVICE: &str = "bedrock";
+// the most texts Cohere models on Bedrock embed in one request
+const COHERE_BATCH_SIZE: usize = 96;
+const ANTHROPIC_VERSION: &str = "bedrock-2023-05-31";
+
+/// the endpoint of the Bedrock runtime in a region
+pub fn runtime_url(region: &str) -> String {
+    format!("https://bedrock-runtime.{region}.amazonaws.com")
+}
+
+/// the keys that requests to Bedrock are signed with
+/// they are given as the api key of the provider, as "access_key_id:secret_access_key",
+/// followed by ":session_token" for temporary credentials
+#[derive(Clone, Debug, PartialEq)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    pub fn from_key(key: &str) -> Result<Self, VectorizeError> {
+        let mut parts = key.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(access_key_id), Some(secret_access_key), session_token)
+                if !access_key_id.is_empty() && !secret_access_key.is_empty() =>
+            {
+                Ok(AwsCredentials {
+                    access_key_id: access_key_id.to_string(),
+                    secret_access_key: secret_access_key.to_string(),
+                    session_token: session_token.filter(|t| !t.is_empty()).map(str::to_string),
+                })
+            }
+            _ => Err(anyhow!(
+                "invalid AWS credentials, expected access_key_id:secret_access_key[:session_token]"
+            ))?,
+        }
+    }
+
+    pub fn to_key(&self) -> String {
+        match &self.session_token {
+            Some(token) => format!("{}:{}:{token}", self.access_key_id, self.secret_access_key),
+            None => format!("{}:{}", self.access_key_id, self.secret_access_key),
+        }
+    }
+
+    // the credentials of the standard AWS environment variables
+    fn from_env() -> Option<Self> {
+        Some(AwsCredentials {
+            access_key_id: env::var("AWS_ACCESS_KEY_ID").ok()?,
+            secret_access_key: env::var("AWS_SECRET_ACCESS_KEY").ok()?,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+pub struct BedrockProvider {
+    pub url: String,
+    pub region: String,
+    pub credentials: AwsCredentials,
+}
+
+impl BedrockProvider {
+    /// the url is the endpoint of the Bedrock runtime, e.g. https://bedrock-runtime.us-east-1.amazonaws.com
+    /// or a VPC endpoint of it, and requests are signed for the region in its host
+    pub fn new(url: Option<String>, api_key: Option<String>) -> Result<Self, VectorizeError> {
+        let url = match url {
+            Some(url) => url,
+            None => runtime_url(
+                &env::var("AWS_REGION").map_err(|_| anyhow!("Bedrock region is required"))?,
+            ),
+        };
+        let credentials = match api_key {
+            Some(key) => AwsCredentials::from_key(&key)?,
+            None => {
+                AwsCredentials::from_env().ok_or_else(|| anyhow!("AWS credentials are required"))?
+            }
+        };
+        let region = endpoint_region(&url)?;
+        Ok(BedrockProvider {
+            url: url.trim_end_matches('/').to_string(),
+            region,
+            credentials,
+        })
+    }
+
+    // invokes a model with a json body, signing the request with the provider's credentials
+    async fn invoke<T: for<'de> Deserialize<'de>>(
+        &self,
+        model_id: &str,
+        body: &serde_json::Value,
+        timeout: Duration,
+    ) -> Result<T, VectorizeError> {
+        let url = format!("{}/model/{}/invoke", self.url, uri_encode(model_id));
+        let parsed = Url::parse(&url).map_err(|e| anyhow!("invalid Bedrock url {url}: {e}"))?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| anyhow!("invalid Bedrock url {url}: missing host"))?;
+        // the path of the request is encoded once more in its signature
+        let canonical_uri = parsed
+            .path()
+            .split('/')
+            .map(uri_encode)
+            .collect::<Vec<_>>()
+            .join("/");
+        let payload = serde_json::to_vec(body)?;
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let mut headers = vec![("content-type", "application/json")];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.as_str()));
+        }
+        let authorization = sign(
+            &self.credentials,
+            &self.region,
+            SIGNING_SERVICE,
+            "POST",
+            host,
+            &canonical_uri,
+            &headers,
+            &payload,
+            &amz_date,
+        );
+        let mut req = http_handler::client()
+            .post(parsed.clone())
+            .timeout(timeout)
+            .header("Accept", "application/json")
+            .header("x-amz-date", &amz_date)
+            .header("Authorization", authorization);
+        for (name, value) in headers {
+            req = req.header(name, value);
+        }
+        let response = req.body(payload).send().await?;
+        handle_response::<T>(response, "invoke").await
+    }
+}
+
+// the region that requests to a Bedrock runtime endpoint are signed for, e.g. from
+// bedrock-runtime.us-east-1.amazonaws.com or vpce-0a1b.bedrock-runtime.us-east-1.vpce.amazonaws.com
+fn endpoint_region(url: &str) -> Result<String, VectorizeError> {
+    let parsed = Url::parse(url).map_err(|e| anyhow!("invalid Bedrock url {url}: {e}"))?;
+    let labels: Vec<&str> = parsed.host_str().unwrap_or_default().split('.').collect();
+    labels
+        .iter()
+        .position(|l| l.starts_with("bedrock-runtime"))
+        .and_then(|i| labels.get(i + 1))
+        .filter(|region| !region.is_empty())
+        .map(|region| region.to_string())
+        .ok_or_else(|| anyhow!("could not find the region of Bedrock endpoint {url}").into())
+}
+
+// percent-encodes everything but the unreserved characters, as AWS signatures expect
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn hmac_sha256(key: &[u8], msg: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts keys of any length");
+    mac.update(msg.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// the Authorization header of a request signed with AWS Signature Version 4
+// headers are the signed headers other than host and x-amz-date, by their lowercase names
+#[allow(clippy::too_many_arguments)]
+fn sign(
+    credentials: &AwsCredentials,
+    region: &str,
+    service: &str,
+    method: &str,
+    host: &str,
+    canonical_uri: &str,
+    headers: &[(&str, &str)],
+    payload: &[u8],
+    amz_date: &str,
+) -> String {
+    let mut signed: Vec<(&str, &str)> = headers.to_vec();
+    signed.push(("host", host));
+    signed.push(("x-amz-date", amz_date));
+    signed.sort();
+    let canonical_headers: String = signed
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = signed
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{method}\n{canonical_uri}\n\n{canonical_headers}\n{signed_headers}\n{}",
+        hex::encode(Sha256::digest(payload))
+    );
+    let date = &amz_date[..8];
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = hmac_sha256(
+        format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+        date,
+    );
+    let key = hmac_sha256(&key, region);
+    let key = hmac_sha256(&key, service);
+    let key = hmac_sha256(&key, "aws4_request");
+    let signature = hex::encode(hmac_sha256(&key, &string_to_sign));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id
+    )
+}
+
+// the families of models that Bedrock serves, each with its own request and response schema
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ModelFamily {
+    Titan,
+    Cohere,
+    Anthropic,
+}
+
+impl ModelFamily {
+    fn of(model_id: &str) -> Result<Self, VectorizeError> {
+        // cross-region inference profiles are prefixed by their geography, e.g. us.anthropic.claude-...
+        let id = match model_id.split_once('.') {
+            Some(("us" | "eu" | "apac", id)) => id,
+            _ => model_id,
+        };
+        if id.starts_with("amazon.titan-embed") {
+            Ok(ModelFamily::Titan)
+        } else if id.starts_with("cohere.embed") {
+            Ok(ModelFamily::Cohere)
+        } else if id.starts_with("anthropic.") {
+            Ok(ModelFamily::Anthropic)
+        } else {
+            Err(anyhow!(
+                "unsupported Bedrock model: {model_id}, expected an amazon.titan-embed, cohere.embed or anthropic model"
+            ))?
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct TitanEmbeddingResponse {
+    embedding: Vec<f64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CohereEmbeddingResponse {
+    embeddings: Vec<Vec<f64>>,
+}
+
+#[async_trait]
+impl EmbeddingProvider for BedrockProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let mut embeddings = Vec::with_capacity(request.input.len());
+        match ModelFamily::of(&request.model)? {
+            // titan models embed one text per request
+            ModelFamily::Titan => {
+                for text in &request.input {
+                    let body = serde_json::json!({ "inputText": text });
+                    let resp: TitanEmbeddingResponse = self
+                        .invoke(&request.model, &body, http_handler::timeouts().embedding)
+                        .await?;
+                    embeddings.push(resp.embedding);
+                }
+            }
+            ModelFamily::Cohere => {
+                let input_type = match request.input_type {
+                    InputType::Document => "search_document",
+                    InputType::Query => "search_query",
+                };
+                for texts in request.input.chunks(COHERE_BATCH_SIZE) {
+                    let body = serde_json::json!({
+                        "texts": texts,
+                        "input_type": input_type,
+                        "truncate": "END",
+                    });
+                    let resp: CohereEmbeddingResponse = self
+                        .invoke(&request.model, &body, http_handler::timeouts().embedding)
+                        .await?;
+                    embeddings.extend(resp.embeddings);
+                }
+            }
+            ModelFamily::Anthropic => Err(anyhow!(
+                "{} is a chat model, it can not embed texts",
+                request.model
+            ))?,
+        }
+        Ok(GenericEmbeddingResponse { embeddings })
+    }
+
+    fn endpoint(&self) -> String {
+        self.url.clone()
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        match bedrock_embedding_dim(model_name) {
+            Some(dim) => Ok(dim),
+            None => probe_model_dim(self, model_name).await,
+        }
+    }
+}
+
+pub fn bedrock_embedding_dim(model_name: &str) -> Option<u32> {
+    match model_name {
+        "amazon.titan-embed-text-v1" => Some(1536),
+        "amazon.titan-embed-text-v2:0" => Some(1024),
+        "cohere.embed-english-v3" => Some(1024),
+        "cohere.embed-multilingual-v3" => Some(1024),
+        _ => None,
+    }
+}
+
+impl BedrockProvider {
+    /// the response of an Anthropic model, the system messages are given to it as its system prompt
+    pub async fn generate_response(
+        &self,
+        model_name: String,
+        messages: &[ChatMessageRequest],
+    ) -> Result<String, VectorizeError> {
+        if ModelFamily::of(&model_name)? != ModelFamily::Anthropic {
+            Err(anyhow!(
+                "{model_name} is not a chat model, only anthropic models are supported for chat on Bedrock"
+            ))?;
+        }
+        let body = anthropic_body(messages);
+        let resp: MessagesResponse = self
+            .invoke(&model_name, &body, http_handler::timeouts().chat)
+            .await?;
+        Ok(resp.text())
+    }
+}
+
+// the body of a Messages API request, as Bedrock takes it
+fn anthropic_body(messages: &[ChatMessageRequest]) -> serde_json::Value {
+    let mut body = messages_body(messages, ANTHROPIC_MAX_TOKENS);
+    body["anthropic_version"] = ANTHROPIC_VERSION.into();
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_credentials() -> AwsCredentials {
+        AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        // get-vanilla of the AWS Signature Version 4 test suite
+        let authorization = sign(
+            &example_credentials(),
+            "us-east-1",
+            "service",
+            "GET",
+            "example.amazonaws.com",
+            "/",
+            &[],
+            b"",
+            "20150830T123600Z",
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+
+        let authorization = sign(
+            &example_credentials(),
+            "us-east-1",
+            "bedrock",
+            "POST",
+            "bedrock-runtime.us-east-1.amazonaws.com",
+            "/model/amazon.titan-embed-text-v2%253A0/invoke",
+            &[("content-type", "application/json")],
+            br#"{"inputText":"hello world"}"#,
+            "20150830T123600Z",
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/bedrock/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature=51f343491d7308dde0295713fefc4ab05567dca7cc8a183c3f603143677154f5"
+        );
+    }
+
+    #[test]
+    fn test_endpoint_region() {
+        assert_eq!(
+            endpoint_region("https://bedrock-runtime.eu-west-1.amazonaws.com").unwrap(),
+            "eu-west-1"
+        );
+        assert_eq!(
+            endpoint_region(
+                "https://vpce-0a1b2c3d-4e5f.bedrock-runtime.us-east-1.vpce.amazonaws.com/"
+            )
+            .unwrap(),
+            "us-east-1"
+        );
+        assert!(endpoint_region("https://bedrock.example.com").is_err());
+        assert_eq!(
+            uri_encode("amazon.titan-embed-text-v2:0"),
+            "amazon.titan-embed-text-v2%3A0"
+        );
+    }
+
+    #[test]
+    fn test_credentials() {
+        let credentials = AwsCredentials::from_key("AKID:secret/key+1").unwrap();
+        assert_eq!(credentials.access_key_id, "AKID");
+        assert_eq!(credentials.secret_access_key, "secret/key+1");
+        assert_eq!(credentials.session_token, None);
+        let credentials = AwsCredentials::from_key("AKID:secret:token").unwrap();
+        assert_eq!(credentials.session_token.as_deref(), Some("token"));
+        assert_eq!(
+            AwsCredentials::from_key(&credentials.to_key()).unwrap(),
+            credentials
+        );
+        assert!(AwsCredentials::from_key("AKID").is_err());
+    }
+
+    #[test]
+    fn test_model_family() {
+        assert_eq!(
+            ModelFamily::of("amazon.titan-embed-text-v2:0").unwrap(),
+            ModelFamily::Titan
+        );
+        assert_eq!(
+            ModelFamily::of("cohere.embed-english-v3").unwrap(),
+            ModelFamily::Cohere
+        );
+        assert_eq!(
+            ModelFamily::of("us.anthropic.claude-3-5-haiku-20241022-v1:0").unwrap(),
+            ModelFamily::Anthropic
+        );
+        assert!(ModelFamily::of("meta.llama3-8b-instruct-v1:0").is_err());
+
+        let body = anthropic_body(&[
+            ChatMessageRequest {
+                role: "system".to_string(),
+                content: "answer from the context".to_string(),
+            },
+            ChatMessageRequest {
+                role: "user".to_string(),
+                content: "what is a pencil?".to_string(),
+            },
+        ]);
+        assert_eq!(body["anthropic_version"], ANTHROPIC_VERSION);
+        assert_eq!(body["system"], "answer from the context");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+    }
+}
@@ -1,9 +1,9 @@
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use super::{EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse};
+use super::{EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse, InputType};
 use crate::errors::VectorizeError;
-use crate::transformers::http_handler::handle_response;
+use crate::transformers::http_handler::{self, handle_response};
+use crate::types::ContentType;
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
@@ -33,28 +33,79 @@ pub struct CohereProvider {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct CohereEmbeddingBody {
     model: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     texts: Vec<String>,
+    // data urls of images, embedded into the same space as texts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
     input_type: String,
     truncate: String,
 }
 
 impl From<GenericEmbeddingRequest> for CohereEmbeddingBody {
     fn from(request: GenericEmbeddingRequest) -> Self {
+        let (texts, images) = match request.content_type {
+            ContentType::image => (
+                vec![],
+                request.input.iter().map(|i| image_data_url(i)).collect(),
+            ),
+            _ => (request.input, vec![]),
+        };
         CohereEmbeddingBody {
             model: request.model,
-            texts: request.input,
-            input_type: "search_document".to_string(),
+            texts,
+            images,
+            // v3 models embed search queries apart from the documents they search
+            input_type: match (request.content_type, request.input_type) {
+                (ContentType::image, _) => "image",
+                (_, InputType::Document) => "search_document",
+                (_, InputType::Query) => "search_query",
+            }
+            .to_string(),
             truncate: "END".to_string(),
         }
     }
 }
 
+// the data url of a base64 encoded image, of the image type its first bytes are the signature of
+// images of other types are sent as application/octet-stream, for Cohere to reject
+fn image_data_url(image: &str) -> String {
+    let media_type = [
+        ("iVBORw0KGgo", "image/png"),
+        ("/9j/", "image/jpeg"),
+        ("R0lGOD", "image/gif"),
+        ("UklGR", "image/webp"),
+    ]
+    .iter()
+    .find(|(signature, _)| image.starts_with(signature))
+    .map(|(_, media_type)| *media_type)
+    .unwrap_or("application/octet-stream");
+    format!("data:{media_type};base64,{image}")
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct CohereEmbeddingResponse {
+    embeddings: Vec<Vec<f64>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CohereRerankBody {
     model: String,
-    texts: Vec<String>,
-    input_type: String,
-    truncate: String,
+    query: String,
+    documents: Vec<String>,
+    top_n: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CohereRerankResponse {
+    results: Vec<RerankResult>,
+}
+
+/// a document of a rerank request, by its position in the request, and how relevant it is to the query
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RerankResult {
+    pub index: usize,
+    pub relevance_score: f64,
 }
 
 impl CohereProvider {
@@ -80,24 +131,45 @@ impl EmbeddingProvider for CohereProvider {
         &self,
         request: &'a GenericEmbeddingRequest,
     ) -> Result<GenericEmbeddingResponse, VectorizeError> {
-        let client = Client::new();
+        let client = http_handler::client();
 
-        let payload = CohereEmbeddingBody::from(request.clone());
-        let payload_val = serde_json::to_value(payload)?;
+        let payloads = match request.content_type {
+            // v3 models take one image per request
+            ContentType::image => request
+                .input
+                .iter()
+                .map(|image| {
+                    CohereEmbeddingBody::from(GenericEmbeddingRequest {
+                        input: vec![image.clone()],
+                        ..request.clone()
+                    })
+                })
+                .collect(),
+            _ => vec![CohereEmbeddingBody::from(request.clone())],
+        };
         let embeddings_url = format!("{}/embed", self.url);
-        let response = client
-            .post(&embeddings_url)
-            .timeout(std::time::Duration::from_secs(120_u64))
-            .header("Accept", "application/json")
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&payload_val)
-            .send()
-            .await?;
+        let mut all_embeddings = Vec::with_capacity(request.input.len());
+        for payload in payloads {
+            let response = client
+                .post(&embeddings_url)
+                .timeout(http_handler::timeouts().embedding)
+                .header("Accept", "application/json")
+                .header("Content-Type", "application/json")
+                .header("Authorization", format!("Bearer {}", self.api_key))
+                .json(&payload)
+                .send()
+                .await?;
+            let embeddings =
+                handle_response::<CohereEmbeddingResponse>(response, "embeddings").await?;
+            all_embeddings.extend(embeddings.embeddings);
+        }
+        Ok(GenericEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
 
-        let embeddings =
-            handle_response::<GenericEmbeddingResponse>(response, "embeddings").await?;
-        Ok(embeddings)
+    fn endpoint(&self) -> String {
+        self.url.clone()
     }
 
     async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
@@ -108,6 +180,39 @@ impl EmbeddingProvider for CohereProvider {
     }
 }
 
+impl CohereProvider {
+    /// the top_n documents most relevant to the query, most relevant first
+    pub async fn rerank(
+        &self,
+        model_name: &str,
+        query: &str,
+        documents: Vec<String>,
+        top_n: usize,
+    ) -> Result<Vec<RerankResult>, VectorizeError> {
+        if documents.is_empty() {
+            return Ok(Vec::new());
+        }
+        let client = http_handler::client();
+        let payload = CohereRerankBody {
+            model: model_name.to_string(),
+            query: query.to_string(),
+            top_n: top_n.min(documents.len()),
+            documents,
+        };
+        let response = client
+            .post(format!("{}/rerank", self.url))
+            .timeout(http_handler::timeouts().embedding)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&payload)
+            .send()
+            .await?;
+        let reranked = handle_response::<CohereRerankResponse>(response, "rerank").await?;
+        Ok(reranked.results)
+    }
+}
+
 #[cfg(test)]
 mod integration_tests {
     use super::*;
@@ -119,6 +224,9 @@ mod integration_tests {
         let request = GenericEmbeddingRequest {
             model: "embed-english-light-v3.0".to_string(),
             input: vec!["hello world".to_string()],
+            input_type: InputType::Document,
+            dimensions: None,
+            content_type: ContentType::text,
         };
 
         let embeddings = provider.generate_embedding(&request).await.unwrap();
@@ -136,3 +244,54 @@ mod integration_tests {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cohere_embedding_body() {
+        let request = GenericEmbeddingRequest {
+            model: "embed-english-v3.0".to_string(),
+            input: vec!["what is a pencil?".to_string()],
+            input_type: InputType::Query,
+            dimensions: None,
+            content_type: ContentType::text,
+        };
+        let body = CohereEmbeddingBody::from(request.clone());
+        assert_eq!(body.input_type, "search_query");
+        let body = CohereEmbeddingBody::from(GenericEmbeddingRequest {
+            input_type: InputType::Document,
+            ..request.clone()
+        });
+        assert_eq!(body.input_type, "search_document");
+
+        let body = CohereEmbeddingBody::from(GenericEmbeddingRequest {
+            input: vec!["iVBORw0KGgoAAAANSUhEUg".to_string()],
+            content_type: ContentType::image,
+            ..request
+        });
+        assert!(body.texts.is_empty());
+        assert_eq!(
+            body.images,
+            vec!["data:image/png;base64,iVBORw0KGgoAAAANSUhEUg"]
+        );
+        assert_eq!(body.input_type, "image");
+        assert_eq!(
+            image_data_url("/9j/4AAQ"),
+            "data:image/jpeg;base64,/9j/4AAQ"
+        );
+        assert_eq!(
+            image_data_url("AAAA"),
+            "data:application/octet-stream;base64,AAAA"
+        );
+
+        let reranked: CohereRerankResponse = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "results": [{"index": 2, "relevance_score": 0.9}, {"index": 0, "relevance_score": 0.1}],
+        }))
+        .unwrap();
+        assert_eq!(reranked.results[0].index, 2);
+        assert_eq!(reranked.results[1].relevance_score, 0.1);
+    }
+}
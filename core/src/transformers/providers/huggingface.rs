@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    probe_model_dim, EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse,
+};
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{self, handle_response};
+use crate::transformers::providers;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::env;
+
+pub const HUGGINGFACE_BASE_URL: &str = "https://router.huggingface.co/hf-inference/models";
+const HUGGINGFACE_BATCH_SIZE: usize = 32;
+
+/// embeds texts with the feature-extraction task of the Hugging Face Inference API
+/// without a url, models are served by the serverless Inference API by their repo id,
+/// and with one, by the dedicated Inference Endpoint at that url
+pub struct HuggingFaceProvider {
+    pub url: Option<String>,
+    pub api_key: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct FeatureExtractionBody {
+    inputs: Vec<String>,
+}
+
+// models without a pooling layer return an embedding for each token of a text
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum FeatureExtractionResponse {
+    Pooled(Vec<Vec<f64>>),
+    Tokens(Vec<Vec<Vec<f64>>>),
+}
+
+impl FeatureExtractionResponse {
+    // an embedding for each text, the mean of its tokens' embeddings when they are not pooled
+    fn embeddings(self) -> Vec<Vec<f64>> {
+        match self {
+            FeatureExtractionResponse::Pooled(embeddings) => embeddings,
+            FeatureExtractionResponse::Tokens(texts) => texts
+                .into_iter()
+                .map(|tokens| {
+                    let dim = tokens.first().map(|t| t.len()).unwrap_or(0);
+                    let mut mean = vec![0.0; dim];
+                    for token in &tokens {
+                        for (m, v) in mean.iter_mut().zip(token) {
+                            *m += v / tokens.len() as f64;
+                        }
+                    }
+                    mean
+                })
+                .collect(),
+        }
+    }
+}
+
+impl HuggingFaceProvider {
+    pub fn new(url: Option<String>, api_key: Option<String>) -> Result<Self, VectorizeError> {
+        let api_key = api_key
+            .or_else(|| env::var("HF_TOKEN").ok())
+            .ok_or_else(|| anyhow!("Hugging Face access token is required"))?;
+        Ok(HuggingFaceProvider { url, api_key })
+    }
+
+    fn model_url(&self, model: &str) -> String {
+        match &self.url {
+            Some(url) => url.clone(),
+            None => format!("{HUGGINGFACE_BASE_URL}/{model}/pipeline/feature-extraction"),
+        }
+    }
+}
+
+/// the url of the dedicated endpoint that serves a model, from a json object of model names to urls
+pub fn endpoint_url(endpoints: &str, model: &str) -> Result<Option<String>, VectorizeError> {
+    let endpoints: HashMap<String, String> = serde_json::from_str(endpoints)
+        .map_err(|e| anyhow!("Hugging Face endpoints must be a json object of urls: {e}"))?;
+    Ok(endpoints.get(model).cloned())
+}
+
+#[async_trait]
+impl EmbeddingProvider for HuggingFaceProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let client = http_handler::client();
+        let url = self.model_url(&request.model);
+        let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(request.input.len());
+        for inputs in providers::split_vector(request.input.clone(), HUGGINGFACE_BATCH_SIZE) {
+            let response = client
+                .post(&url)
+                .timeout(http_handler::timeouts().embedding)
+                .header("Accept", "application/json")
+                .header("Content-Type", "application/json")
+                .header("Authorization", format!("Bearer {}", self.api_key))
+                .json(&FeatureExtractionBody { inputs })
+                .send()
+                .await?;
+            let embeddings =
+                handle_response::<FeatureExtractionResponse>(response, "feature-extraction")
+                    .await?;
+            all_embeddings.extend(embeddings.embeddings());
+        }
+        Ok(GenericEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    fn endpoint(&self) -> String {
+        self.url
+            .clone()
+            .unwrap_or_else(|| HUGGINGFACE_BASE_URL.to_string())
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        probe_model_dim(self, model_name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_extraction_response() {
+        let pooled: FeatureExtractionResponse =
+            serde_json::from_str("[[0.1, 0.2], [0.3, 0.4]]").unwrap();
+        assert_eq!(pooled.embeddings(), vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
+        let tokens: FeatureExtractionResponse =
+            serde_json::from_str("[[[1.0, 2.0], [3.0, 4.0]]]").unwrap();
+        assert_eq!(tokens.embeddings(), vec![vec![2.0, 3.0]]);
+    }
+
+    #[test]
+    fn test_model_url() {
+        let provider = HuggingFaceProvider::new(None, Some("hf_token".to_string())).unwrap();
+        assert_eq!(
+            provider.model_url("BAAI/bge-small-en-v1.5"),
+            "https://router.huggingface.co/hf-inference/models/BAAI/bge-small-en-v1.5/pipeline/feature-extraction"
+        );
+        let endpoints = r#"{"huggingface/acme/embedder": "https://abc.us-east-1.aws.endpoints.huggingface.cloud"}"#;
+        let url = endpoint_url(endpoints, "huggingface/acme/embedder").unwrap();
+        let provider = HuggingFaceProvider::new(url, Some("hf_token".to_string())).unwrap();
+        assert_eq!(
+            provider.model_url("acme/embedder"),
+            "https://abc.us-east-1.aws.endpoints.huggingface.cloud"
+        );
+        assert_eq!(endpoint_url(endpoints, "huggingface/other").unwrap(), None);
+        assert!(endpoint_url("https://abc.endpoints.huggingface.cloud", "x").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+use super::openai::OpenAIEmbeddingResponse;
+use super::{
+    probe_model_dim, EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse,
+    InputType,
+};
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{self, handle_response};
+use crate::transformers::providers;
+use crate::types::ContentType;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::env;
+
+pub const JINA_BASE_URL: &str = "https://api.jina.ai/v1";
+const JINA_BATCH_SIZE: usize = 512;
+
+pub struct JinaProvider {
+    pub url: String,
+    pub api_key: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct JinaEmbeddingBody {
+    model: String,
+    input: Vec<JinaInput>,
+    // only the v3 and later models are trained for a task, the v2 models reject it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task: Option<String>,
+}
+
+// CLIP models take images, base64 encoded or by their urls, along with texts
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum JinaInput {
+    Text(String),
+    Image { image: String },
+}
+
+impl JinaEmbeddingBody {
+    fn new(
+        model: &str,
+        input: Vec<String>,
+        input_type: InputType,
+        content_type: ContentType,
+    ) -> Self {
+        let task = if model.starts_with("jina-embeddings-v2") {
+            None
+        } else {
+            Some(
+                match input_type {
+                    InputType::Document => "retrieval.passage",
+                    InputType::Query => "retrieval.query",
+                }
+                .to_string(),
+            )
+        };
+        let input = input
+            .into_iter()
+            .map(|i| match content_type {
+                ContentType::text => JinaInput::Text(i),
+                ContentType::image | ContentType::image_url => JinaInput::Image { image: i },
+            })
+            .collect();
+        JinaEmbeddingBody {
+            model: model.to_string(),
+            input,
+            task,
+        }
+    }
+}
+
+impl JinaProvider {
+    pub fn new(url: Option<String>, api_key: Option<String>) -> Result<Self, VectorizeError> {
+        let api_key = api_key
+            .or_else(|| env::var("JINA_API_KEY").ok())
+            .ok_or_else(|| anyhow!("Jina api key is required"))?;
+        Ok(JinaProvider {
+            url: url.unwrap_or_else(|| JINA_BASE_URL.to_string()),
+            api_key,
+        })
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for JinaProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let client = http_handler::client();
+        let embeddings_url = format!("{}/embeddings", self.url);
+        let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(request.input.len());
+        for input in providers::split_vector(request.input.clone(), JINA_BATCH_SIZE) {
+            let body = JinaEmbeddingBody::new(
+                &request.model,
+                input,
+                request.input_type,
+                request.content_type,
+            );
+            let response = client
+                .post(&embeddings_url)
+                .timeout(http_handler::timeouts().embedding)
+                .header("Accept", "application/json")
+                .header("Content-Type", "application/json")
+                .header("Authorization", format!("Bearer {}", self.api_key))
+                .json(&body)
+                .send()
+                .await?;
+            let embeddings =
+                handle_response::<OpenAIEmbeddingResponse>(response, "embeddings").await?;
+            all_embeddings.extend(embeddings.data.iter().map(|x| x.embedding.clone()));
+        }
+        Ok(GenericEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    fn endpoint(&self) -> String {
+        self.url.clone()
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        match jina_embedding_dim(model_name) {
+            Some(dim) => Ok(dim),
+            None => probe_model_dim(self, model_name).await,
+        }
+    }
+}
+
+pub fn jina_embedding_dim(model_name: &str) -> Option<u32> {
+    match model_name {
+        "jina-embeddings-v3" | "jina-clip-v2" => Some(1024),
+        "jina-embeddings-v2-base-en"
+        | "jina-embeddings-v2-base-de"
+        | "jina-embeddings-v2-base-es"
+        | "jina-embeddings-v2-base-zh"
+        | "jina-embeddings-v2-base-code" => Some(768),
+        "jina-embeddings-v2-small-en" => Some(512),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jina_embedding_body() {
+        let body = JinaEmbeddingBody::new(
+            "jina-embeddings-v3",
+            vec!["what is a pencil?".to_string()],
+            InputType::Query,
+            ContentType::text,
+        );
+        assert_eq!(
+            serde_json::to_value(body).unwrap(),
+            serde_json::json!({
+                "model": "jina-embeddings-v3",
+                "input": ["what is a pencil?"],
+                "task": "retrieval.query",
+            })
+        );
+        let body = JinaEmbeddingBody::new(
+            "jina-embeddings-v2-base-en",
+            vec!["a pencil".to_string()],
+            InputType::Document,
+            ContentType::text,
+        );
+        assert_eq!(body.task, None);
+        let body = JinaEmbeddingBody::new(
+            "jina-clip-v2",
+            vec!["https://example.com/pencil.png".to_string()],
+            InputType::Document,
+            ContentType::image_url,
+        );
+        assert_eq!(
+            serde_json::to_value(body.input).unwrap(),
+            serde_json::json!([{"image": "https://example.com/pencil.png"}])
+        );
+        assert_eq!(jina_embedding_dim("jina-embeddings-v2-small-en"), Some(512));
+    }
+}
@@ -0,0 +1,124 @@
+use super::openai::{OpenAIEmbeddingBody, OpenAIEmbeddingResponse};
+use super::{
+    probe_model_dim, ChatMessageRequest, ChatResponse, EmbeddingProvider, GenericEmbeddingRequest,
+    GenericEmbeddingResponse,
+};
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{self, handle_response};
+use crate::transformers::providers;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::env;
+
+pub const MISTRAL_BASE_URL: &str = "https://api.mistral.ai/v1";
+// requests are limited by their total tokens rather than their number of inputs
+const MISTRAL_BATCH_SIZE: usize = 64;
+
+/// the Mistral API follows the request and response schemas of OpenAI's embeddings and chat completions
+pub struct MistralProvider {
+    pub url: String,
+    pub api_key: String,
+}
+
+impl MistralProvider {
+    pub fn new(url: Option<String>, api_key: Option<String>) -> Result<Self, VectorizeError> {
+        let api_key = api_key
+            .or_else(|| env::var("MISTRAL_API_KEY").ok())
+            .ok_or_else(|| anyhow!("Mistral api key is required"))?;
+        Ok(MistralProvider {
+            url: url.unwrap_or_else(|| MISTRAL_BASE_URL.to_string()),
+            api_key,
+        })
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for MistralProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let client = http_handler::client();
+        let embeddings_url = format!("{}/embeddings", self.url);
+        let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(request.input.len());
+        for input in providers::split_vector(request.input.clone(), MISTRAL_BATCH_SIZE) {
+            let body = OpenAIEmbeddingBody {
+                model: request.model.clone(),
+                input,
+                dimensions: request.dimensions,
+            };
+            let response = client
+                .post(&embeddings_url)
+                .timeout(http_handler::timeouts().embedding)
+                .header("Accept", "application/json")
+                .header("Content-Type", "application/json")
+                .header("Authorization", format!("Bearer {}", self.api_key))
+                .json(&body)
+                .send()
+                .await?;
+            let embeddings =
+                handle_response::<OpenAIEmbeddingResponse>(response, "embeddings").await?;
+            all_embeddings.extend(embeddings.data.iter().map(|x| x.embedding.clone()));
+        }
+        Ok(GenericEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    fn endpoint(&self) -> String {
+        self.url.clone()
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        match mistral_embedding_dim(model_name) {
+            Some(dim) => Ok(dim),
+            None => probe_model_dim(self, model_name).await,
+        }
+    }
+}
+
+pub fn mistral_embedding_dim(model_name: &str) -> Option<u32> {
+    match model_name {
+        "mistral-embed" => Some(1024),
+        "codestral-embed" => Some(1536),
+        _ => None,
+    }
+}
+
+impl MistralProvider {
+    pub async fn generate_response(
+        &self,
+        model_name: String,
+        messages: &[ChatMessageRequest],
+    ) -> Result<String, VectorizeError> {
+        let client = http_handler::client();
+        let chat_url = format!("{}/chat/completions", self.url);
+        let response = client
+            .post(&chat_url)
+            .timeout(http_handler::timeouts().chat)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&serde_json::json!({
+                "model": model_name,
+                "messages": messages,
+            }))
+            .send()
+            .await?;
+        let chat_response = handle_response::<ChatResponse>(response, "chat/completions").await?;
+        Ok(chat_response.choices[0].message.content.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mistral_provider() {
+        let provider = MistralProvider::new(None, Some("key".to_string())).unwrap();
+        assert_eq!(provider.endpoint(), MISTRAL_BASE_URL);
+        assert_eq!(mistral_embedding_dim("mistral-embed"), Some(1024));
+        assert_eq!(mistral_embedding_dim("mistral-small-latest"), None);
+    }
+}
@@ -1,6 +1,15 @@
+pub mod anthropic;
+pub mod azure;
+pub mod batch;
+pub mod bedrock;
 pub mod cohere;
+pub mod gemini;
+pub mod huggingface;
+pub mod jina;
+pub mod mistral;
 pub mod ollama;
 pub mod openai;
+pub mod openai_compatible;
 pub mod portkey;
 pub mod vector_serve;
 pub mod voyage;
@@ -8,15 +17,16 @@ pub mod voyage;
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 use super::types::Inputs;
 use crate::errors::VectorizeError;
-use crate::transformers::providers;
+use crate::transformers::{http_handler, providers};
 use crate::types::Model;
-use crate::types::ModelSource;
+use crate::types::{ContentType, JobParams, ModelSource};
 
 #[async_trait]
-pub trait EmbeddingProvider {
+pub trait EmbeddingProvider: Send + Sync {
     #[allow(async_fn_in_trait)]
     async fn generate_embedding<'a>(
         &self,
@@ -24,12 +34,106 @@ pub trait EmbeddingProvider {
     ) -> Result<GenericEmbeddingResponse, VectorizeError>;
     #[allow(async_fn_in_trait)]
     async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError>;
+    /// the base url that embedding requests are sent to, recorded with the provenance of its embeddings
+    fn endpoint(&self) -> String {
+        String::new()
+    }
 }
 
 #[derive(Clone, Deserialize, Debug, Serialize)]
 pub struct GenericEmbeddingRequest {
     pub input: Vec<String>,
     pub model: String,
+    #[serde(default)]
+    pub input_type: InputType,
+    // the length of the embeddings the model is asked for, by models that accept it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+    // images are sent base64 encoded, or as their urls
+    #[serde(default)]
+    pub content_type: ContentType,
+}
+
+impl GenericEmbeddingRequest {
+    /// asks for embeddings of the given dimensions, when the model can be asked for them
+    /// other models return embeddings of their own length, which are then truncated
+    pub fn with_dimensions(mut self, model: &Model, dimensions: Option<u32>) -> Self {
+        if model.source == ModelSource::OpenAI && openai::accepts_dimensions(&model.api_name()) {
+            self.dimensions = dimensions;
+        }
+        self
+    }
+
+    /// prefixes the texts with the instruction the model was trained to tell queries and documents apart by
+    /// texts that already start with it are left as they are
+    pub fn with_instructions(mut self) -> Self {
+        if let Some(instruction) = instruction(&self.model, self.input_type) {
+            for text in self
+                .input
+                .iter_mut()
+                .filter(|t| !t.starts_with(instruction))
+            {
+                text.insert_str(0, instruction);
+            }
+        }
+        self
+    }
+}
+
+/// the instruction a model's texts are prefixed with, for models that take the input type in the text itself
+/// providers with an input type parameter of their own, such as Cohere and Voyage, are given it there instead
+pub fn instruction(model_name: &str, input_type: InputType) -> Option<&'static str> {
+    let model_name = model_name.to_lowercase();
+    let is = |names: &[&str]| names.iter().any(|name| model_name.contains(name));
+    if is(&["nomic-embed-text"]) {
+        return Some(match input_type {
+            InputType::Query => "search_query: ",
+            InputType::Document => "search_document: ",
+        });
+    }
+    // e5-mistral is instructed with a task description instead
+    if is(&["e5-small", "e5-base", "e5-large"]) {
+        return Some(match input_type {
+            InputType::Query => "query: ",
+            InputType::Document => "passage: ",
+        });
+    }
+    // bge and mxbai only instruct their queries
+    let query_instructed = is(&[
+        "bge-small-en",
+        "bge-base-en",
+        "bge-large-en",
+        "mxbai-embed-large",
+    ]);
+    match input_type {
+        InputType::Query if query_instructed => {
+            Some("Represent this sentence for searching relevant passages: ")
+        }
+        _ => None,
+    }
+}
+
+/// what the texts of an embedding request are, for models that embed queries apart from the documents they search
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputType {
+    #[default]
+    Document,
+    Query,
+}
+
+impl FromStr for InputType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "document" => Ok(InputType::Document),
+            "query" => Ok(InputType::Query),
+            _ => Err(format!(
+                "Invalid input_type: {s}, expected one of: document, query"
+            )),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -46,6 +150,35 @@ pub fn prepare_generic_embedding_request(
     GenericEmbeddingRequest {
         input: text_inputs,
         model: model.api_name(),
+        input_type: InputType::Document,
+        dimensions: None,
+        content_type: ContentType::text,
+    }
+}
+
+/// whether a provider embeds values of a content type, every provider embeds text
+/// images are embedded by Cohere's v3 models from their bytes, and by Jina's CLIP models from their bytes or urls
+pub fn embeds_content(source: &ModelSource, content_type: ContentType) -> bool {
+    match content_type {
+        ContentType::text => true,
+        ContentType::image => matches!(source, ModelSource::Cohere | ModelSource::Jina),
+        ContentType::image_url => *source == ModelSource::Jina,
+    }
+}
+
+/// a small embedding model of a provider, that a one word request to is enough to check an api key with
+/// providers whose models are deployed or routed per account have none
+pub fn validation_model(source: &ModelSource) -> Option<&'static str> {
+    match source {
+        ModelSource::OpenAI => Some("text-embedding-3-small"),
+        ModelSource::Cohere => Some("embed-english-light-v3.0"),
+        ModelSource::Voyage => Some("voyage-3-lite"),
+        ModelSource::Jina => Some("jina-embeddings-v3"),
+        ModelSource::Mistral => Some("mistral-embed"),
+        ModelSource::Gemini => Some("text-embedding-004"),
+        ModelSource::Bedrock => Some("amazon.titan-embed-text-v2:0"),
+        ModelSource::HuggingFace => Some("sentence-transformers/all-MiniLM-L6-v2"),
+        _ => None,
     }
 }
 
@@ -73,13 +206,186 @@ pub fn get_provider(
         ModelSource::SentenceTransformers => Ok(Box::new(
             providers::vector_serve::VectorServeProvider::new(url, api_key),
         )),
-        ModelSource::Ollama => Ok(Box::new(providers::ollama::OllamaProvider::new(url))),
-        ModelSource::Tembo => Err(anyhow::anyhow!(
-            "Ollama/Tembo transformer not implemented yet"
+        ModelSource::Ollama => Ok(Box::new(providers::ollama::OllamaProvider::new(url)?)),
+        ModelSource::AzureOpenAI => Ok(Box::new(providers::azure::AzureOpenAIProvider::new(
+            url, api_key,
+        )?)),
+        ModelSource::Bedrock => Ok(Box::new(providers::bedrock::BedrockProvider::new(
+            url, api_key,
+        )?)),
+        ModelSource::Gemini => Ok(Box::new(providers::gemini::GeminiProvider::new(
+            url, api_key,
+        )?)),
+        ModelSource::Mistral => Ok(Box::new(providers::mistral::MistralProvider::new(
+            url, api_key,
+        )?)),
+        ModelSource::Jina => Ok(Box::new(providers::jina::JinaProvider::new(url, api_key)?)),
+        ModelSource::HuggingFace => Ok(Box::new(providers::huggingface::HuggingFaceProvider::new(
+            url, api_key,
+        )?)),
+        ModelSource::OpenAICompatible => Ok(Box::new(
+            providers::openai_compatible::OpenAICompatibleProvider::new(url, api_key)?,
+        )),
+        ModelSource::Vllm | ModelSource::LlamaCpp => Ok(Box::new(
+            providers::openai_compatible::OpenAICompatibleProvider::self_hosted(
+                model_source,
+                url,
+                api_key,
+            )?,
+        )),
+        ModelSource::Tembo => Err(anyhow::anyhow!("Tembo transformer not implemented yet"))?,
+        ModelSource::Anthropic => Err(anyhow::anyhow!(
+            "Anthropic does not provide embedding models"
         ))?,
     }
 }
 
+/// finds the embedding dimension of a model by embedding a short text with it
+pub async fn probe_model_dim<P: EmbeddingProvider + Sync + ?Sized>(
+    provider: &P,
+    model_name: &str,
+) -> Result<u32, VectorizeError> {
+    let req = GenericEmbeddingRequest {
+        input: vec!["hello world".to_string()],
+        model: model_name.to_string(),
+        input_type: InputType::Document,
+        dimensions: None,
+        content_type: ContentType::text,
+    };
+    let embedding = provider.generate_embedding(&req).await?;
+    match embedding.embeddings.first() {
+        Some(e) if !e.is_empty() => Ok(e.len() as u32),
+        _ => Err(anyhow::anyhow!(
+            "model {model_name} returned an empty embedding"
+        ))?,
+    }
+}
+
+/// the models of a comma separated list of models to warm up, e.g. ollama/nomic-embed-text,sentence-transformers/all-MiniLM-L6-v2
+pub fn warmup_models(list: Option<&str>) -> Vec<&str> {
+    list.unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .collect()
+}
+
+/// errors when an embedding does not have the dimension of the job's embeddings
+pub fn validate_dimensions(embeddings: &[Vec<f64>], expected: u32) -> Result<(), VectorizeError> {
+    match embeddings.iter().find(|e| e.len() != expected as usize) {
+        Some(e) => Err(VectorizeError::DimensionMismatch {
+            expected,
+            actual: e.len(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// brings embeddings to the dimensions of a job, and errors when they can not have them
+/// jobs that truncate their embeddings keep the leading dimensions of each one,
+/// and jobs that normalize their embeddings scale each to unit length
+pub fn fit_dimensions(
+    embeddings: Vec<Vec<f64>>,
+    job_params: &JobParams,
+) -> Result<Vec<Vec<f64>>, VectorizeError> {
+    let embeddings = match job_params.dimensions {
+        Some(dimensions) if job_params.truncate_dimensions => {
+            truncate_dimensions(embeddings, dimensions)?
+        }
+        Some(dimensions) => {
+            validate_dimensions(&embeddings, dimensions)?;
+            embeddings
+        }
+        None => embeddings,
+    };
+    if !job_params.normalize {
+        return Ok(embeddings);
+    }
+    Ok(embeddings
+        .iter()
+        .map(|e| http_handler::normalize(e))
+        .collect())
+}
+
+/// shortens embeddings to the given dimensions, scaling each back to unit length
+/// only models trained with matryoshka representation learning, such as OpenAI's text-embedding-3,
+/// keep their quality when truncated
+pub fn truncate_dimensions(
+    embeddings: Vec<Vec<f64>>,
+    dimensions: u32,
+) -> Result<Vec<Vec<f64>>, VectorizeError> {
+    embeddings
+        .into_iter()
+        .map(|e| {
+            if e.len() < dimensions as usize {
+                return Err(VectorizeError::DimensionMismatch {
+                    expected: dimensions,
+                    actual: e.len(),
+                });
+            }
+            Ok(http_handler::truncate_embedding(&e, dimensions as usize))
+        })
+        .collect()
+}
+
+/// embeds each input column of a weighted job on its own
+/// and combines them into one embedding per record
+/// records without any input text are dropped, the remaining inputs are returned with their embeddings
+pub async fn generate_weighted_embeddings(
+    provider: &dyn EmbeddingProvider,
+    model: &Model,
+    job_params: &JobParams,
+    inputs: Vec<Inputs>,
+) -> Result<(Vec<Inputs>, Vec<Vec<f64>>), VectorizeError> {
+    let mut records: Vec<(Inputs, Vec<f64>)> = Vec::new();
+    let mut column_inputs: Vec<Inputs> = Vec::new();
+    for input in inputs {
+        let columns = job_params.split_weighted_input(&input.inputs)?;
+        if columns.is_empty() {
+            continue;
+        }
+        let mut weights = Vec::new();
+        for (weight, text) in columns {
+            weights.push(weight);
+            column_inputs.push(Inputs {
+                record_id: input.record_id.clone(),
+                inputs: text,
+                // a column can not have more tokens than the whole record
+                token_estimate: input.token_estimate,
+            });
+        }
+        records.push((input, weights));
+    }
+    if records.is_empty() {
+        return Ok((vec![], vec![]));
+    }
+
+    let request = prepare_generic_embedding_request(model, &column_inputs)
+        .with_dimensions(model, job_params.truncated_dimensions());
+    let column_embeddings = provider.generate_embedding(&request).await?.embeddings;
+    // otherwise zipping the weights with the embeddings would silently drop columns
+    if column_embeddings.len() != column_inputs.len() {
+        return Err(anyhow::anyhow!(
+            "expected {} column embeddings, received {}",
+            column_inputs.len(),
+            column_embeddings.len()
+        )
+        .into());
+    }
+    let mut column_embeddings = column_embeddings.into_iter();
+    let mut kept = Vec::new();
+    let mut embeddings = Vec::new();
+    for (input, weights) in records {
+        let weighted: Vec<(f64, Vec<f64>)> = weights
+            .into_iter()
+            .zip(column_embeddings.by_ref())
+            .collect();
+        embeddings.push(http_handler::fuse_embeddings(&weighted));
+        kept.push(input);
+    }
+    Ok((kept, embeddings))
+}
+
 fn split_vector(vec: Vec<String>, chunk_size: usize) -> Vec<Vec<String>> {
     vec.chunks(chunk_size).map(|chunk| chunk.to_vec()).collect()
 }
@@ -104,3 +410,157 @@ struct Choice {
 struct ResponseMessage {
     content: String,
 }
+
+/// the tokens of a chat completion, as counted by the server that generated it
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> i64 {
+        self.prompt_tokens as i64 + self.completion_tokens as i64
+    }
+}
+
+/// a chat response, with its token usage when the server reports it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatCompletion {
+    pub content: String,
+    pub usage: Option<TokenUsage>,
+}
+
+impl From<String> for ChatCompletion {
+    fn from(content: String) -> Self {
+        ChatCompletion {
+            content,
+            usage: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    // returns one embedding fewer than it was sent texts
+    struct ShortProvider;
+
+    #[async_trait]
+    impl EmbeddingProvider for ShortProvider {
+        async fn generate_embedding<'a>(
+            &self,
+            request: &'a GenericEmbeddingRequest,
+        ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+            Ok(GenericEmbeddingResponse {
+                embeddings: vec![vec![1.0]; request.input.len() - 1],
+            })
+        }
+
+        async fn model_dim(&self, _model_name: &str) -> Result<u32, VectorizeError> {
+            Ok(1)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_weighted_embeddings_count() {
+        let job_params = JobParams {
+            columns: vec!["title".to_string(), "body".to_string()],
+            column_weights: BTreeMap::from([("title".to_string(), 2.0)]),
+            ..Default::default()
+        };
+        let model = Model::new("openai/text-embedding-3-small").unwrap();
+        let inputs = vec![Inputs {
+            record_id: "1".to_string(),
+            inputs: r#"["Wireless mouse", "a quiet mouse"]"#.to_string(),
+            token_estimate: 5,
+        }];
+        let result =
+            generate_weighted_embeddings(&ShortProvider, &model, &job_params, inputs).await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_warmup_models() {
+        assert!(warmup_models(None).is_empty());
+        assert!(warmup_models(Some(" , ")).is_empty());
+        assert_eq!(
+            warmup_models(Some(
+                "ollama/nomic-embed-text, sentence-transformers/all-MiniLM-L6-v2,"
+            )),
+            vec![
+                "ollama/nomic-embed-text",
+                "sentence-transformers/all-MiniLM-L6-v2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_with_instructions() {
+        let request = |model: &str, input_type: InputType| GenericEmbeddingRequest {
+            input: vec!["pencils".to_string(), "search_query: pens".to_string()],
+            model: model.to_string(),
+            input_type,
+            dimensions: None,
+            content_type: ContentType::text,
+        };
+        let instructed =
+            request("nomic-ai/nomic-embed-text-v1.5", InputType::Query).with_instructions();
+        assert_eq!(
+            instructed.input,
+            vec!["search_query: pencils", "search_query: pens"]
+        );
+        let instructed = request("nomic-embed-text", InputType::Document).with_instructions();
+        assert_eq!(instructed.input[0], "search_document: pencils");
+        let instructed =
+            request("intfloat/multilingual-e5-large", InputType::Document).with_instructions();
+        assert_eq!(instructed.input[0], "passage: pencils");
+        let instructed = request("BAAI/bge-small-en-v1.5", InputType::Document).with_instructions();
+        assert_eq!(instructed.input[0], "pencils");
+        let instructed = request("text-embedding-3-small", InputType::Query).with_instructions();
+        assert_eq!(instructed.input[0], "pencils");
+    }
+
+    #[test]
+    fn test_validate_dimensions() {
+        let embeddings = vec![vec![0.1, 0.2, 0.3], vec![0.4, 0.5, 0.6]];
+        assert!(validate_dimensions(&embeddings, 3).is_ok());
+        assert!(validate_dimensions(&[], 3).is_ok());
+        match validate_dimensions(&embeddings, 384) {
+            Err(VectorizeError::DimensionMismatch { expected, actual }) => {
+                assert_eq!((expected, actual), (384, 3));
+            }
+            other => panic!("expected a dimension mismatch, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_fit_dimensions() {
+        let embeddings = vec![vec![3.0, 4.0, 12.0]];
+        let mut job_params = JobParams {
+            dimensions: Some(2),
+            ..Default::default()
+        };
+        assert!(fit_dimensions(embeddings.clone(), &job_params).is_err());
+        job_params.truncate_dimensions = true;
+        assert_eq!(
+            fit_dimensions(embeddings.clone(), &job_params).unwrap(),
+            vec![vec![0.6, 0.8]]
+        );
+        // embeddings can not be made longer
+        job_params.dimensions = Some(4);
+        assert!(fit_dimensions(embeddings.clone(), &job_params).is_err());
+        job_params.dimensions = None;
+        assert_eq!(
+            fit_dimensions(embeddings.clone(), &job_params).unwrap(),
+            embeddings
+        );
+        job_params.normalize = true;
+        assert_eq!(
+            fit_dimensions(vec![vec![3.0, 4.0], vec![0.0, 0.0]], &job_params).unwrap(),
+            vec![vec![0.6, 0.8], vec![0.0, 0.0]]
+        );
+    }
+}
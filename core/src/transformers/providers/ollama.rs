@@ -1,7 +1,9 @@
 use super::{
-    ChatMessageRequest, EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse,
+    probe_model_dim, ChatMessageRequest, EmbeddingProvider, GenericEmbeddingRequest,
+    GenericEmbeddingResponse,
 };
 use crate::errors::VectorizeError;
+use anyhow::anyhow;
 use async_trait::async_trait;
 use ollama_rs::{
     generation::completion::request::GenerationRequest,
@@ -24,18 +26,12 @@ struct ModelInfo {
 }
 
 impl OllamaProvider {
-    pub fn new(url: Option<String>) -> Self {
+    pub fn new(url: Option<String>) -> Result<Self, VectorizeError> {
         let url_in = url.unwrap_or_else(|| OLLAMA_BASE_URL.to_string());
-        let parsed_url = Url::parse(&url_in).unwrap_or_else(|_| panic!("invalid url: {}", url_in));
-        let instance = Ollama::new(
-            format!(
-                "{}://{}",
-                parsed_url.scheme(),
-                parsed_url.host_str().expect("parsed url missing")
-            ),
-            parsed_url.port().expect("parsed port missing"),
-        );
-        OllamaProvider { instance }
+        let (host, port) = ollama_host(&url_in)?;
+        Ok(OllamaProvider {
+            instance: Ollama::new(host, port),
+        })
     }
 }
 
@@ -61,15 +57,12 @@ impl EmbeddingProvider for OllamaProvider {
         Ok(GenericEmbeddingResponse { embeddings: embed })
     }
 
+    fn endpoint(&self) -> String {
+        self.instance.uri()
+    }
+
     async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
-        // determine embedding dim by generating an embedding and getting length of array
-        let req = GenericEmbeddingRequest {
-            input: vec!["hello world".to_string()],
-            model: model_name.to_string(),
-        };
-        let embedding = self.generate_embedding(&req).await?;
-        let dim = embedding.embeddings[0].len();
-        Ok(dim as u32)
+        probe_model_dim(self, model_name).await
     }
 }
 
@@ -90,6 +83,19 @@ impl OllamaProvider {
     }
 }
 
+// the scheme and host, and the port, of an Ollama server's url
+// a url without a port is served on its scheme's default port
+fn ollama_host(url: &str) -> Result<(String, u16), VectorizeError> {
+    let parsed = Url::parse(url).map_err(|e| anyhow!("invalid Ollama url {url}: {e}"))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| anyhow!("invalid Ollama url {url}: missing host"))?;
+    let port = parsed
+        .port_or_known_default()
+        .ok_or_else(|| anyhow!("invalid Ollama url {url}: missing port"))?;
+    Ok((format!("{}://{host}", parsed.scheme()), port))
+}
+
 pub fn check_model_host(url: &str) -> Result<String, String> {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_io()
@@ -98,7 +104,7 @@ pub fn check_model_host(url: &str) -> Result<String, String> {
         .unwrap_or_else(|e| panic!("failed to initialize tokio runtime: {}", e));
 
     runtime.block_on(async {
-        let response = reqwest::get(url).await.unwrap();
+        let response = reqwest::get(url).await.map_err(|e| format!("Error! {e}"))?;
         match response.status() {
             reqwest::StatusCode::OK => Ok(format!("Success! {:?}", response)),
             _ => Err(format!("Error! {:?}", response)),
@@ -112,3 +118,21 @@ pub fn ollama_embedding_dim(model_name: &str) -> i32 {
         _ => 1536,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ollama_host() {
+        let (host, port) = ollama_host("http://localhost:11434").unwrap();
+        assert_eq!(host, "http://localhost");
+        assert_eq!(port, 11434);
+        // the port is the scheme's default when the url does not have one
+        let (host, port) = ollama_host("https://ollama.example.com/").unwrap();
+        assert_eq!(host, "https://ollama.example.com");
+        assert_eq!(port, 443);
+        assert!(ollama_host("localhost").is_err());
+        assert!(OllamaProvider::new(Some("not a url".to_string())).is_err());
+    }
+}
@@ -1,12 +1,11 @@
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
 use super::{
-    ChatMessageRequest, ChatResponse, EmbeddingProvider, GenericEmbeddingRequest,
+    probe_model_dim, ChatMessageRequest, ChatResponse, EmbeddingProvider, GenericEmbeddingRequest,
     GenericEmbeddingResponse,
 };
 use crate::errors::VectorizeError;
-use crate::transformers::http_handler::handle_response;
+use crate::transformers::http_handler::{self, handle_response};
 use crate::transformers::providers;
 use crate::transformers::types::Inputs;
 use async_trait::async_trait;
@@ -24,6 +23,8 @@ pub struct OpenAIProvider {
 pub struct OpenAIEmbeddingBody {
     pub model: String,
     pub input: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
 }
 
 impl From<GenericEmbeddingRequest> for OpenAIEmbeddingBody {
@@ -31,10 +32,16 @@ impl From<GenericEmbeddingRequest> for OpenAIEmbeddingBody {
         OpenAIEmbeddingBody {
             model: request.model,
             input: request.input,
+            dimensions: request.dimensions,
         }
     }
 }
 
+/// whether a model can be asked for shorter embeddings than its own, which text-embedding-3 models can
+pub fn accepts_dimensions(model_name: &str) -> bool {
+    model_name.starts_with("text-embedding-3")
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OpenAIEmbeddingResponse {
     pub model: String,
@@ -78,7 +85,7 @@ impl EmbeddingProvider for OpenAIProvider {
         &self,
         request: &'a GenericEmbeddingRequest,
     ) -> Result<GenericEmbeddingResponse, VectorizeError> {
-        let client = Client::new();
+        let client = http_handler::client();
         let req = OpenAIEmbeddingBody::from(request.clone());
         let num_inputs = request.input.len();
         let todo_requests: Vec<OpenAIEmbeddingBody> = if num_inputs > 2048 {
@@ -87,6 +94,7 @@ impl EmbeddingProvider for OpenAIProvider {
                 .map(|chunk| OpenAIEmbeddingBody {
                     input: chunk.clone(),
                     model: request.model.clone(),
+                    dimensions: request.dimensions,
                 })
                 .collect()
         } else {
@@ -100,7 +108,7 @@ impl EmbeddingProvider for OpenAIProvider {
             let embeddings_url = format!("{}/embeddings", self.url);
             let response = client
                 .post(&embeddings_url)
-                .timeout(std::time::Duration::from_secs(120_u64))
+                .timeout(http_handler::timeouts().embedding)
                 .header("Accept", "application/json")
                 .header("Content-Type", "application/json")
                 .header("Authorization", format!("Bearer {}", self.api_key))
@@ -117,17 +125,25 @@ impl EmbeddingProvider for OpenAIProvider {
         })
     }
 
+    fn endpoint(&self) -> String {
+        self.url.clone()
+    }
+
     async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
-        Ok(openai_embedding_dim(model_name) as u32)
+        // openai compatible services can serve any model, so unknown models are probed
+        match openai_embedding_dim(model_name) {
+            Some(dim) => Ok(dim),
+            None => probe_model_dim(self, model_name).await,
+        }
     }
 }
 
-pub fn openai_embedding_dim(model_name: &str) -> i32 {
+pub fn openai_embedding_dim(model_name: &str) -> Option<u32> {
     match model_name {
-        "text-embedding-3-large" => 3072,
-        "text-embedding-3-small" => 1536,
-        "text-embedding-ada-002" => 1536,
-        _ => 1536,
+        "text-embedding-3-large" => Some(3072),
+        "text-embedding-3-small" => Some(1536),
+        "text-embedding-ada-002" => Some(1536),
+        _ => None,
     }
 }
 
@@ -137,7 +153,7 @@ impl OpenAIProvider {
         model_name: String,
         messages: &[ChatMessageRequest],
     ) -> Result<String, VectorizeError> {
-        let client = Client::new();
+        let client = http_handler::client();
         let chat_url = format!("{}/chat/completions", self.url);
         let message = serde_json::json!({
             "model": model_name,
@@ -145,7 +161,7 @@ impl OpenAIProvider {
         });
         let response = client
             .post(&chat_url)
-            .timeout(std::time::Duration::from_secs(120_u64))
+            .timeout(http_handler::timeouts().chat)
             .header("Accept", "application/json")
             .header("Content-Type", "application/json")
             .header("Authorization", &format!("Bearer {}", self.api_key))
@@ -181,6 +197,8 @@ pub fn trim_inputs(inputs: &[Inputs]) -> Vec<String> {
 #[cfg(test)]
 mod integration_tests {
     use super::*;
+    use crate::transformers::providers::InputType;
+    use crate::types::ContentType;
     use tokio::test as async_test;
 
     #[async_test]
@@ -189,6 +207,9 @@ mod integration_tests {
         let request = GenericEmbeddingRequest {
             model: "text-embedding-ada-002".to_string(),
             input: vec!["hello world".to_string()],
+            input_type: InputType::Document,
+            dimensions: None,
+            content_type: ContentType::text,
         };
 
         let embeddings = provider.generate_embedding(&request).await.unwrap();
@@ -210,6 +231,7 @@ mod integration_tests {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::Model;
 
     #[test]
     fn test_trim_inputs_no_trimming_required() {
@@ -277,4 +299,25 @@ mod tests {
         assert_eq!(trimmed[0].split_whitespace().count(), 2);
         assert_eq!(trimmed[1].split_whitespace().count(), MAX_TOKEN_LEN);
     }
+
+    #[test]
+    fn test_embedding_body_dimensions() {
+        let inputs = vec![Inputs {
+            record_id: "1".to_string(),
+            inputs: "hello world".to_string(),
+            token_estimate: 2,
+        }];
+        let model = Model::new("openai/text-embedding-3-large").unwrap();
+        let request = providers::prepare_generic_embedding_request(&model, &inputs)
+            .with_dimensions(&model, Some(512));
+        let body = serde_json::to_value(OpenAIEmbeddingBody::from(request)).unwrap();
+        assert_eq!(body["dimensions"], 512);
+
+        // older models can not be asked for their dimensions
+        let model = Model::new("openai/text-embedding-ada-002").unwrap();
+        let request = providers::prepare_generic_embedding_request(&model, &inputs)
+            .with_dimensions(&model, Some(512));
+        let body = serde_json::to_value(OpenAIEmbeddingBody::from(request)).unwrap();
+        assert!(body.get("dimensions").is_none());
+    }
 }
@@ -0,0 +1,484 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::openai::{OpenAIEmbeddingBody, OpenAIEmbeddingResponse};
+use super::{
+    probe_model_dim, ChatCompletion, ChatMessageRequest, ChatResponse, EmbeddingProvider,
+    GenericEmbeddingRequest, GenericEmbeddingResponse, TokenUsage,
+};
+use crate::errors::VectorizeError;
+use crate::transformers::http_handler::{self, handle_response, response_error};
+use crate::transformers::providers;
+use crate::types::ModelSource;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const OPENAI_COMPATIBLE_BATCH_SIZE: usize = 64;
+// the default addresses of `vllm serve` and llama.cpp's `llama-server`
+pub const VLLM_BASE_URL: &str = "http://localhost:8000/v1";
+pub const LLAMACPP_BASE_URL: &str = "http://localhost:8080/v1";
+
+/// where a model is served by an API that follows OpenAI's embeddings and chat completions schemas,
+/// such as vLLM, LM Studio, Together, Groq or Fireworks
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OpenAICompatibleEndpoint {
+    pub base_url: String,
+    #[serde(default = "default_embeddings_path")]
+    pub embeddings_path: String,
+    #[serde(default = "default_chat_path")]
+    pub chat_path: String,
+    #[serde(default = "default_auth_header")]
+    pub auth_header: String,
+    // prefixed to the api key in the auth header, empty for servers that take the bare key
+    #[serde(default = "default_auth_scheme")]
+    pub auth_scheme: String,
+    // the name the server knows the model by, when it differs from the model's vectorize name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+}
+
+fn default_embeddings_path() -> String {
+    "/embeddings".to_string()
+}
+
+fn default_chat_path() -> String {
+    "/chat/completions".to_string()
+}
+
+fn default_auth_header() -> String {
+    "Authorization".to_string()
+}
+
+fn default_auth_scheme() -> String {
+    "Bearer".to_string()
+}
+
+// an endpoint is configured by its base url alone, or by an object that also sets its paths and auth
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum EndpointConfig {
+    BaseUrl(String),
+    Endpoint(OpenAICompatibleEndpoint),
+}
+
+impl From<EndpointConfig> for OpenAICompatibleEndpoint {
+    fn from(config: EndpointConfig) -> Self {
+        match config {
+            EndpointConfig::BaseUrl(base_url) => OpenAICompatibleEndpoint::new(base_url),
+            EndpointConfig::Endpoint(endpoint) => endpoint,
+        }
+    }
+}
+
+impl OpenAICompatibleEndpoint {
+    pub fn new(base_url: String) -> Self {
+        OpenAICompatibleEndpoint {
+            base_url,
+            embeddings_path: default_embeddings_path(),
+            chat_path: default_chat_path(),
+            auth_header: default_auth_header(),
+            auth_scheme: default_auth_scheme(),
+            model: None,
+            api_key: None,
+        }
+    }
+
+    /// an endpoint from its base url, or from its json object
+    pub fn parse(config: &str) -> Result<Self, VectorizeError> {
+        if !config.trim_start().starts_with('{') {
+            return Ok(OpenAICompatibleEndpoint::new(config.to_string()));
+        }
+        serde_json::from_str(config)
+            .map_err(|e| anyhow!("invalid OpenAI-compatible endpoint: {e}").into())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+
+    fn auth_value(&self, api_key: &str) -> String {
+        if self.auth_scheme.is_empty() {
+            api_key.to_string()
+        } else {
+            format!("{} {api_key}", self.auth_scheme)
+        }
+    }
+}
+
+/// the endpoint that serves a model, from a json object of model names to endpoints
+pub fn endpoint(
+    endpoints: &str,
+    model: &str,
+) -> Result<Option<OpenAICompatibleEndpoint>, VectorizeError> {
+    let mut endpoints: HashMap<String, EndpointConfig> = serde_json::from_str(endpoints)
+        .map_err(|e| anyhow!("OpenAI-compatible endpoints must be a json object: {e}"))?;
+    Ok(endpoints.remove(model).map(OpenAICompatibleEndpoint::from))
+}
+
+pub struct OpenAICompatibleProvider {
+    pub endpoint: OpenAICompatibleEndpoint,
+    // local servers often run without auth
+    pub api_key: Option<String>,
+}
+
+impl OpenAICompatibleProvider {
+    /// url is the endpoint of the model, its base url or its json object
+    pub fn new(url: Option<String>, api_key: Option<String>) -> Result<Self, VectorizeError> {
+        let url = url.ok_or_else(|| anyhow!("OpenAI-compatible endpoint is required"))?;
+        let endpoint = OpenAICompatibleEndpoint::parse(&url)?;
+        let api_key = api_key.or_else(|| endpoint.api_key.clone());
+        Ok(OpenAICompatibleProvider { endpoint, api_key })
+    }
+
+    /// a vLLM or llama.cpp server, at its default address unless given another
+    pub fn self_hosted(
+        source: &ModelSource,
+        url: Option<String>,
+        api_key: Option<String>,
+    ) -> Result<Self, VectorizeError> {
+        let default_url = match source {
+            ModelSource::Vllm => VLLM_BASE_URL,
+            ModelSource::LlamaCpp => LLAMACPP_BASE_URL,
+            _ => Err(anyhow!("{source} is not a self-hosted inference server"))?,
+        };
+        OpenAICompatibleProvider::new(url.or_else(|| Some(default_url.to_string())), api_key)
+    }
+
+    fn post(&self, client: &Client, path: &str, timeout: Duration) -> reqwest::RequestBuilder {
+        let request = client
+            .post(self.endpoint.url(path))
+            .timeout(timeout)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json");
+        match &self.api_key {
+            Some(key) => request.header(&self.endpoint.auth_header, self.endpoint.auth_value(key)),
+            None => request,
+        }
+    }
+
+    fn model_name(&self, model_name: &str) -> String {
+        self.endpoint
+            .model
+            .clone()
+            .unwrap_or_else(|| model_name.to_string())
+    }
+
+    pub async fn generate_response(
+        &self,
+        model_name: String,
+        messages: &[ChatMessageRequest],
+    ) -> Result<String, VectorizeError> {
+        let client = http_handler::client();
+        let response = self
+            .post(
+                &client,
+                &self.endpoint.chat_path,
+                http_handler::timeouts().chat,
+            )
+            .json(&serde_json::json!({
+                "model": self.model_name(&model_name),
+                "messages": messages,
+            }))
+            .send()
+            .await?;
+        let chat_response = handle_response::<ChatResponse>(response, "chat/completions").await?;
+        Ok(chat_response.choices[0].message.content.clone())
+    }
+
+    /// generates a response as a stream of server-sent events, along with the tokens it used
+    pub async fn stream_response(
+        &self,
+        model_name: String,
+        messages: &[ChatMessageRequest],
+    ) -> Result<ChatCompletion, VectorizeError> {
+        let client = http_handler::client();
+        let mut response = client
+            .post(self.endpoint.url(&self.endpoint.chat_path))
+            .header("Accept", "text/event-stream")
+            .header("Content-Type", "application/json");
+        if let Some(key) = &self.api_key {
+            response = response.header(&self.endpoint.auth_header, self.endpoint.auth_value(key));
+        }
+        let mut response = response
+            .json(&serde_json::json!({
+                "model": self.model_name(&model_name),
+                "messages": messages,
+                "stream": true,
+                "stream_options": {"include_usage": true},
+            }))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(response_error(response, "chat/completions").await);
+        }
+        let mut stream = ChatStream::default();
+        // a streamed response fails once the server has sent nothing for the chat timeout,
+        // however long the whole response takes to generate
+        loop {
+            let chunk = tokio::time::timeout(http_handler::timeouts().chat, response.chunk())
+                .await
+                .map_err(|_| anyhow!("chat/completions stream timed out"))??;
+            match chunk {
+                Some(bytes) if !stream.push(&bytes)? => continue,
+                _ => break,
+            }
+        }
+        stream.finish()
+    }
+}
+
+// a chunk of a streamed chat completion
+#[derive(Debug, Default, Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    // sent in a last chunk without choices, when requested with stream_options.include_usage
+    #[serde(default)]
+    usage: Option<TokenUsage>,
+    // llama.cpp counts tokens in its timings, which older servers send in place of usage
+    #[serde(default)]
+    timings: Option<Timings>,
+    #[serde(default)]
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: Delta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Delta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Timings {
+    prompt_n: u32,
+    predicted_n: u32,
+}
+
+/// collects the content and usage of server-sent chat completion events,
+/// which can be split across the chunks of a response
+#[derive(Debug, Default)]
+struct ChatStream {
+    pending: Vec<u8>,
+    content: String,
+    usage: Option<TokenUsage>,
+    done: bool,
+}
+
+impl ChatStream {
+    /// adds the bytes of a chunk, returning whether the stream is done
+    fn push(&mut self, bytes: &[u8]) -> Result<bool, VectorizeError> {
+        self.pending.extend_from_slice(bytes);
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            self.push_line(line.trim_end())?;
+        }
+        Ok(self.done)
+    }
+
+    fn push_line(&mut self, line: &str) -> Result<(), VectorizeError> {
+        // events are separated by blank lines, and lines starting with a colon are comments
+        if self.done || line.is_empty() || line.starts_with(':') {
+            return Ok(());
+        }
+        // llama.cpp reports errors that happen mid-stream in an error event
+        if let Some(error) = line.strip_prefix("error:") {
+            return Err(anyhow!("chat/completions stream failed: {}", error.trim()))?;
+        }
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            return Ok(());
+        };
+        if data == "[DONE]" {
+            self.done = true;
+            return Ok(());
+        }
+        let chunk: StreamChunk = serde_json::from_str(data)
+            .map_err(|e| anyhow!("invalid chat/completions stream event: {e}: {data}"))?;
+        if let Some(error) = chunk.error {
+            return Err(anyhow!("chat/completions stream failed: {error}"))?;
+        }
+        for choice in chunk.choices {
+            if let Some(content) = choice.delta.content {
+                self.content.push_str(&content);
+            }
+        }
+        if let Some(usage) = chunk.usage {
+            self.usage = Some(usage);
+        } else if let (None, Some(timings)) = (self.usage, chunk.timings) {
+            self.usage = Some(TokenUsage {
+                prompt_tokens: timings.prompt_n,
+                completion_tokens: timings.predicted_n,
+            });
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<ChatCompletion, VectorizeError> {
+        // the last event does not need to end in a newline
+        let rest = String::from_utf8_lossy(&std::mem::take(&mut self.pending)).to_string();
+        self.push_line(rest.trim_end())?;
+        Ok(ChatCompletion {
+            content: self.content,
+            usage: self.usage,
+        })
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OpenAICompatibleProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let client = http_handler::client();
+        let mut all_embeddings: Vec<Vec<f64>> = Vec::with_capacity(request.input.len());
+        for input in providers::split_vector(request.input.clone(), OPENAI_COMPATIBLE_BATCH_SIZE) {
+            let body = OpenAIEmbeddingBody {
+                model: self.model_name(&request.model),
+                input,
+                dimensions: request.dimensions,
+            };
+            let response = self
+                .post(
+                    &client,
+                    &self.endpoint.embeddings_path,
+                    http_handler::timeouts().embedding,
+                )
+                .json(&body)
+                .send()
+                .await?;
+            let embeddings =
+                handle_response::<OpenAIEmbeddingResponse>(response, "embeddings").await?;
+            all_embeddings.extend(embeddings.data.iter().map(|x| x.embedding.clone()));
+        }
+        Ok(GenericEmbeddingResponse {
+            embeddings: all_embeddings,
+        })
+    }
+
+    fn endpoint(&self) -> String {
+        self.endpoint.base_url.clone()
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        probe_model_dim(self, model_name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openai_compatible_endpoint() {
+        let endpoints = r#"{
+            "openai-compatible/bge-m3": "http://localhost:8000/v1/",
+            "openai-compatible/llama-3.1-8b": {
+                "base_url": "https://api.fireworks.ai/inference/v1",
+                "model": "accounts/fireworks/models/llama-v3p1-8b-instruct",
+                "auth_header": "x-api-key",
+                "auth_scheme": ""
+            }
+        }"#;
+        let local = endpoint(endpoints, "openai-compatible/bge-m3")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            local.url(&local.embeddings_path),
+            "http://localhost:8000/v1/embeddings"
+        );
+        assert_eq!(local.auth_value("key"), "Bearer key");
+
+        let hosted = endpoint(endpoints, "openai-compatible/llama-3.1-8b")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            hosted.url(&hosted.chat_path),
+            "https://api.fireworks.ai/inference/v1/chat/completions"
+        );
+        assert_eq!(hosted.auth_header, "x-api-key");
+        assert_eq!(hosted.auth_value("key"), "key");
+        assert_eq!(
+            endpoint(endpoints, "openai-compatible/other").unwrap(),
+            None
+        );
+        assert!(endpoint("http://localhost:8000/v1", "x").is_err());
+
+        // endpoints are passed to the provider as json, with their model and auth
+        let config = serde_json::to_string(&hosted).unwrap();
+        let provider = OpenAICompatibleProvider::new(Some(config), None).unwrap();
+        assert_eq!(provider.endpoint, hosted);
+        assert_eq!(
+            provider.model_name("llama-3.1-8b"),
+            "accounts/fireworks/models/llama-v3p1-8b-instruct"
+        );
+        let provider =
+            OpenAICompatibleProvider::new(Some("http://localhost:1234/v1".to_string()), None)
+                .unwrap();
+        assert_eq!(provider.model_name("bge-m3"), "bge-m3");
+        assert!(OpenAICompatibleProvider::new(None, None).is_err());
+        let provider =
+            OpenAICompatibleProvider::self_hosted(&ModelSource::LlamaCpp, None, None).unwrap();
+        assert_eq!(provider.endpoint(), LLAMACPP_BASE_URL);
+    }
+
+    #[test]
+    fn test_chat_stream() {
+        // a vLLM stream, split in the middle of an event
+        let mut stream = ChatStream::default();
+        let events = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"a writing\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\" tool\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":3,\"total_tokens\":15}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let (first, second) = events.split_at(70);
+        assert!(!stream.push(first.as_bytes()).unwrap());
+        assert!(stream.push(second.as_bytes()).unwrap());
+        let completion = stream.finish().unwrap();
+        assert_eq!(completion.content, "a writing tool");
+        assert_eq!(completion.usage.unwrap().total(), 15);
+
+        // an older llama.cpp server, which counts tokens in its timings and ends without [DONE]
+        let mut stream = ChatStream::default();
+        stream
+            .push(b"data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n\n")
+            .unwrap();
+        stream
+            .push(b"data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}],\"timings\":{\"prompt_n\":8,\"predicted_n\":1,\"predicted_ms\":20.5}}")
+            .unwrap();
+        let completion = stream.finish().unwrap();
+        assert_eq!(completion.content, "hi");
+        assert_eq!(
+            completion.usage,
+            Some(TokenUsage {
+                prompt_tokens: 8,
+                completion_tokens: 1,
+            })
+        );
+
+        let mut stream = ChatStream::default();
+        assert!(stream
+            .push(b"error: {\"code\":500,\"message\":\"context shift is disabled\"}\n\n")
+            .is_err());
+        let mut stream = ChatStream::default();
+        assert!(stream
+            .push(b"data: {\"error\":{\"message\":\"out of memory\"}}\n\n")
+            .is_err());
+    }
+}
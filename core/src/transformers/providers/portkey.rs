@@ -1,11 +1,9 @@
-use reqwest::Client;
-
 use super::{
-    ChatMessageRequest, ChatResponse, EmbeddingProvider, GenericEmbeddingRequest,
+    probe_model_dim, ChatMessageRequest, ChatResponse, EmbeddingProvider, GenericEmbeddingRequest,
     GenericEmbeddingResponse,
 };
 use crate::errors::VectorizeError;
-use crate::transformers::http_handler::handle_response;
+use crate::transformers::http_handler::{self, handle_response};
 use crate::transformers::providers;
 use crate::transformers::providers::openai;
 use async_trait::async_trait;
@@ -48,7 +46,7 @@ impl EmbeddingProvider for PortkeyProvider {
         &self,
         request: &'a GenericEmbeddingRequest,
     ) -> Result<GenericEmbeddingResponse, VectorizeError> {
-        let client = Client::new();
+        let client = http_handler::client();
 
         let req = openai::OpenAIEmbeddingBody::from(request.clone());
         let num_inputs = request.input.len();
@@ -58,6 +56,7 @@ impl EmbeddingProvider for PortkeyProvider {
                 .map(|chunk| openai::OpenAIEmbeddingBody {
                     input: chunk.clone(),
                     model: request.model.clone(),
+                    dimensions: request.dimensions,
                 })
                 .collect()
         } else {
@@ -70,7 +69,7 @@ impl EmbeddingProvider for PortkeyProvider {
             let payload_val = serde_json::to_value(request_payload)?;
             let response = client
                 .post(&embeddings_url)
-                .timeout(std::time::Duration::from_secs(120_u64))
+                .timeout(http_handler::timeouts().embedding)
                 .header("Accept", "application/json")
                 .header("Content-Type", "application/json")
                 .header("x-portkey-virtual-key", self.virtual_key.clone())
@@ -88,15 +87,12 @@ impl EmbeddingProvider for PortkeyProvider {
         })
     }
 
+    fn endpoint(&self) -> String {
+        self.url.clone()
+    }
+
     async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
-        // determine embedding dim by generating an embedding and getting length of array
-        let req = GenericEmbeddingRequest {
-            input: vec!["hello world".to_string()],
-            model: model_name.to_string(),
-        };
-        let embedding = self.generate_embedding(&req).await?;
-        let dim = embedding.embeddings[0].len();
-        Ok(dim as u32)
+        probe_model_dim(self, model_name).await
     }
 }
 
@@ -106,7 +102,7 @@ impl PortkeyProvider {
         model_name: String,
         messages: &[ChatMessageRequest],
     ) -> Result<String, VectorizeError> {
-        let client = Client::new();
+        let client = http_handler::client();
         let message = serde_json::json!({
             "model": model_name,
             "messages": messages,
@@ -114,7 +110,7 @@ impl PortkeyProvider {
         let chat_url = format!("{}/chat/completions", self.url);
         let response = client
             .post(&chat_url)
-            .timeout(std::time::Duration::from_secs(120_u64))
+            .timeout(http_handler::timeouts().chat)
             .header("Accept", "application/json")
             .header("Content-Type", "application/json")
             .header("x-portkey-virtual-key", self.virtual_key.clone())
@@ -130,6 +126,8 @@ impl PortkeyProvider {
 #[cfg(test)]
 mod portkey_integration_tests {
     use super::*;
+    use crate::transformers::providers::InputType;
+    use crate::types::ContentType;
     use tokio::test as async_test;
 
     #[async_test]
@@ -141,6 +139,9 @@ mod portkey_integration_tests {
         let request = GenericEmbeddingRequest {
             model: "text-embedding-ada-002".to_string(),
             input: vec!["hello world".to_string()],
+            input_type: InputType::Document,
+            dimensions: None,
+            content_type: ContentType::text,
         };
 
         let embeddings = provider.generate_embedding(&request).await.unwrap();
@@ -1,9 +1,8 @@
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
 use super::{EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse};
 use crate::errors::VectorizeError;
-use crate::transformers::http_handler::handle_response;
+use crate::transformers::http_handler::{self, handle_response};
 use crate::transformers::providers::openai;
 use async_trait::async_trait;
 use std::env;
@@ -46,7 +45,7 @@ impl EmbeddingProvider for VectorServeProvider {
         &self,
         request: &'a GenericEmbeddingRequest,
     ) -> Result<GenericEmbeddingResponse, VectorizeError> {
-        let client = Client::new();
+        let client = http_handler::client();
         let req = openai::OpenAIEmbeddingBody::from(request.clone());
         let num_inputs = request.input.len();
         let todo_requests: Vec<openai::OpenAIEmbeddingBody> = if num_inputs > 2048 {
@@ -55,6 +54,7 @@ impl EmbeddingProvider for VectorServeProvider {
                 .map(|chunk| openai::OpenAIEmbeddingBody {
                     input: chunk.clone(),
                     model: request.model.clone(),
+                    dimensions: request.dimensions,
                 })
                 .collect()
         } else {
@@ -68,7 +68,7 @@ impl EmbeddingProvider for VectorServeProvider {
             let embeddings_url = format!("{}/embeddings", self.url);
             let mut req = client
                 .post(&embeddings_url)
-                .timeout(std::time::Duration::from_secs(120_u64))
+                .timeout(http_handler::timeouts().embedding)
                 .header("Accept", "application/json")
                 .header("Content-Type", "application/json")
                 .json(&payload_val);
@@ -85,8 +85,12 @@ impl EmbeddingProvider for VectorServeProvider {
         })
     }
 
+    fn endpoint(&self) -> String {
+        self.url.clone()
+    }
+
     async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
-        let client = Client::new();
+        let client = http_handler::client();
         let mut req = client
             .get(format!("{}/info/?model_name={}", self.url, model_name))
             .header("Accept", "application/json")
@@ -107,6 +111,8 @@ fn split_vector(vec: Vec<String>, chunk_size: usize) -> Vec<Vec<String>> {
 #[cfg(test)]
 mod integration_tests {
     use super::*;
+    use crate::transformers::providers::InputType;
+    use crate::types::ContentType;
     use tokio::test as async_test;
 
     #[async_test]
@@ -115,6 +121,9 @@ mod integration_tests {
         let request = GenericEmbeddingRequest {
             model: "sentence-transformers/all-MiniLM-L6-v2".to_string(),
             input: vec!["hello world".to_string()],
+            input_type: InputType::Document,
+            dimensions: None,
+            content_type: ContentType::text,
         };
 
         let embeddings = provider.generate_embedding(&request).await.unwrap();
@@ -1,9 +1,11 @@
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use super::{EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse};
+use super::{
+    probe_model_dim, EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse,
+    InputType,
+};
 use crate::errors::VectorizeError;
-use crate::transformers::http_handler::handle_response;
+use crate::transformers::http_handler::{self, handle_response};
 use async_trait::async_trait;
 use std::env;
 
@@ -26,7 +28,11 @@ impl From<GenericEmbeddingRequest> for VoyageEmbeddingBody {
         VoyageEmbeddingBody {
             input: request.input,
             model: request.model,
-            input_type: "document".to_string(),
+            input_type: match request.input_type {
+                InputType::Document => "document",
+                InputType::Query => "query",
+            }
+            .to_string(),
         }
     }
 }
@@ -72,14 +78,14 @@ impl EmbeddingProvider for VoyageProvider {
         &self,
         request: &'a GenericEmbeddingRequest,
     ) -> Result<GenericEmbeddingResponse, VectorizeError> {
-        let client = Client::new();
+        let client = http_handler::client();
 
         let req_body = VoyageEmbeddingBody::from(request.clone());
         let embedding_url = format!("{}/embeddings", self.url);
 
         let response = client
             .post(&embedding_url)
-            .timeout(std::time::Duration::from_secs(120_u64))
+            .timeout(http_handler::timeouts().embedding)
             .header("Content-Type", "application/json")
             .header("Authorization", format!("Bearer {}", self.api_key))
             .json(&req_body)
@@ -96,21 +102,60 @@ impl EmbeddingProvider for VoyageProvider {
         })
     }
 
+    fn endpoint(&self) -> String {
+        self.url.clone()
+    }
+
     async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
-        // determine embedding dim by generating an embedding and getting length of array
-        let req = GenericEmbeddingRequest {
-            input: vec!["hello world".to_string()],
-            model: model_name.to_string(),
+        match voyage_embedding_dim(model_name) {
+            Some(dim) => Ok(dim),
+            None => probe_model_dim(self, model_name).await,
+        }
+    }
+}
+
+// the default dimensions of Voyage's models
+pub fn voyage_embedding_dim(model_name: &str) -> Option<u32> {
+    match model_name {
+        "voyage-3-large" | "voyage-3" | "voyage-code-3" => Some(1024),
+        "voyage-3-lite" => Some(512),
+        "voyage-finance-2" | "voyage-law-2" | "voyage-multilingual-2" => Some(1024),
+        "voyage-code-2" => Some(1536),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ContentType;
+
+    #[test]
+    fn test_voyage_embedding_body() {
+        let request = GenericEmbeddingRequest {
+            input: vec!["what is a pencil?".to_string()],
+            model: "voyage-3-lite".to_string(),
+            input_type: InputType::Query,
+            dimensions: None,
+            content_type: ContentType::text,
         };
-        let embedding = self.generate_embedding(&req).await?;
-        let dim = embedding.embeddings[0].len();
-        Ok(dim as u32)
+        let body = serde_json::to_value(VoyageEmbeddingBody::from(request)).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "input": ["what is a pencil?"],
+                "model": "voyage-3-lite",
+                "input_type": "query",
+            })
+        );
+        assert_eq!(voyage_embedding_dim("voyage-3-lite"), Some(512));
     }
 }
 
 #[cfg(test)]
 mod integration_tests {
     use super::*;
+    use crate::types::ContentType;
     use std::env;
 
     #[tokio::test]
@@ -121,6 +166,9 @@ mod integration_tests {
         let request = GenericEmbeddingRequest {
             input: vec!["hello world".to_string()],
             model: "voyage-3-lite".to_string(),
+            input_type: InputType::Document,
+            dimensions: None,
+            content_type: ContentType::text,
         };
 
         let embeddings = provider.generate_embedding(&request).await.unwrap();
@@ -0,0 +1,142 @@
+use std::future::Future;
+use std::time::Duration;
+
+use crate::errors::VectorizeError;
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// how many times a provider request is sent before its error is returned,
+/// and how long is waited between the attempts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            ..Default::default()
+        }
+    }
+
+    // a random delay of up to the exponential backoff of an attempt, so that the requests
+    // of concurrent batches that hit a rate limit together are not sent again together
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt - 1))
+            .min(self.max_delay);
+        backoff.mul_f64(fastrand::f64())
+    }
+}
+
+/// sends a request until it succeeds, fails with an error that is not transient,
+/// or has been sent max_attempts times
+/// the wait before an attempt is the Retry-After of the last response when it has one
+pub async fn with_retries<T, F, Fut>(
+    policy: &RetryPolicy,
+    mut request: F,
+) -> Result<T, VectorizeError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, VectorizeError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Err(e) if e.is_transient() && attempt < policy.max_attempts => {
+                let delay = match &e {
+                    VectorizeError::ResponseError {
+                        retry_after: Some(retry_after),
+                        ..
+                    } => (*retry_after).min(policy.max_delay),
+                    _ => policy.delay(attempt),
+                };
+                log::warn!(
+                    "attempt {attempt} of {} failed, retrying in {delay:?}: {e}",
+                    policy.max_attempts
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn error(status: u16) -> VectorizeError {
+        VectorizeError::ResponseError {
+            method: "embeddings".to_string(),
+            status: reqwest::StatusCode::from_u16(status).unwrap(),
+            body: String::new(),
+            retry_after: None,
+        }
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::default();
+        for attempt in 1..20 {
+            let cap = Duration::from_millis(500 * 2_u64.pow((attempt - 1).min(6)));
+            assert!(policy.delay(attempt) <= cap.min(policy.max_delay));
+        }
+    }
+
+    #[test]
+    fn test_with_retries() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let attempts = Cell::new(0);
+        // rate limited once, then succeeds
+        let result = runtime.block_on(with_retries(&policy, || async {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 => Err(error(429)),
+                _ => Ok(attempts.get()),
+            }
+        }));
+        assert_eq!(result.unwrap(), 2);
+
+        // gives up after max_attempts
+        attempts.set(0);
+        let result: Result<(), _> = runtime.block_on(with_retries(&policy, || async {
+            attempts.set(attempts.get() + 1);
+            Err(error(503))
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 3);
+
+        // client errors are not sent again
+        attempts.set(0);
+        let result: Result<(), _> = runtime.block_on(with_retries(&policy, || async {
+            attempts.set(attempts.get() + 1);
+            Err(error(401))
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
@@ -1,8 +1,10 @@
 use chrono::serde::ts_seconds_option::deserialize as from_tsopt;
 
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use sqlx::types::chrono::Utc;
+#[cfg(feature = "worker")]
 use sqlx::FromRow;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
@@ -51,7 +53,112 @@ pub enum IndexDist {
     pgv_hnsw_l2,
     pgv_hnsw_ip,
     pgv_hnsw_cosine,
+    pgv_ivfflat_l2,
+    pgv_ivfflat_ip,
+    pgv_ivfflat_cosine,
     vsc_diskann_cosine,
+    vsc_diskann_l2,
+    vsc_diskann_ip,
+}
+
+impl IndexDist {
+    pub fn is_hnsw(&self) -> bool {
+        matches!(
+            self,
+            IndexDist::pgv_hnsw_l2 | IndexDist::pgv_hnsw_ip | IndexDist::pgv_hnsw_cosine
+        )
+    }
+
+    pub fn is_ivfflat(&self) -> bool {
+        matches!(
+            self,
+            IndexDist::pgv_ivfflat_l2 | IndexDist::pgv_ivfflat_ip | IndexDist::pgv_ivfflat_cosine
+        )
+    }
+
+    /// pgvectorscale's StreamingDiskANN
+    pub fn is_diskann(&self) -> bool {
+        matches!(
+            self,
+            IndexDist::vsc_diskann_cosine | IndexDist::vsc_diskann_l2 | IndexDist::vsc_diskann_ip
+        )
+    }
+
+    /// the distance metric the index is built for
+    pub fn metric(&self) -> DistanceMetric {
+        match self {
+            IndexDist::pgv_hnsw_l2 | IndexDist::pgv_ivfflat_l2 | IndexDist::vsc_diskann_l2 => {
+                DistanceMetric::l2
+            }
+            IndexDist::pgv_hnsw_ip | IndexDist::pgv_ivfflat_ip | IndexDist::vsc_diskann_ip => {
+                DistanceMetric::ip
+            }
+            IndexDist::pgv_hnsw_cosine
+            | IndexDist::pgv_ivfflat_cosine
+            | IndexDist::vsc_diskann_cosine => DistanceMetric::cosine,
+        }
+    }
+
+    /// the pgvector operator the index orders by
+    pub fn distance_operator(&self) -> &'static str {
+        self.metric().operator()
+    }
+}
+
+/// a distance between two vectors, as computed by one of pgvector's operators
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DistanceMetric {
+    cosine,
+    l2,
+    ip,
+}
+
+impl DistanceMetric {
+    pub fn operator(&self) -> &'static str {
+        match self {
+            DistanceMetric::cosine => "<=>",
+            DistanceMetric::l2 => "<->",
+            DistanceMetric::ip => "<#>",
+        }
+    }
+
+    /// an expression turning a distance into a similarity, where higher is more similar
+    /// cosine similarity is 1 - cosine distance, l2 distances are mapped to 1 / (1 + distance) in (0, 1],
+    /// and pgvector's inner product distance is the negated inner product
+    pub fn similarity(&self, distance: &str) -> String {
+        match self {
+            DistanceMetric::cosine => format!("1 - ({distance})"),
+            DistanceMetric::l2 => format!("1 / (1 + ({distance}))"),
+            DistanceMetric::ip => format!("-({distance})"),
+        }
+    }
+}
+
+impl Display for DistanceMetric {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            DistanceMetric::cosine => write!(f, "cosine"),
+            DistanceMetric::l2 => write!(f, "l2"),
+            DistanceMetric::ip => write!(f, "ip"),
+        }
+    }
+}
+
+impl FromStr for DistanceMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cosine" => Ok(DistanceMetric::cosine),
+            "l2" => Ok(DistanceMetric::l2),
+            "ip" => Ok(DistanceMetric::ip),
+            _ => Err(format!(
+                "Invalid distance metric: {}, expected one of: cosine, l2, ip",
+                s
+            )),
+        }
+    }
 }
 
 impl Display for IndexDist {
@@ -60,7 +167,12 @@ impl Display for IndexDist {
             IndexDist::pgv_hnsw_l2 => write!(f, "pgv_hnsw_l2"),
             IndexDist::pgv_hnsw_ip => write!(f, "pgv_hnsw_ip"),
             IndexDist::pgv_hnsw_cosine => write!(f, "pgv_hnsw_cosine"),
+            IndexDist::pgv_ivfflat_l2 => write!(f, "pgv_ivfflat_l2"),
+            IndexDist::pgv_ivfflat_ip => write!(f, "pgv_ivfflat_ip"),
+            IndexDist::pgv_ivfflat_cosine => write!(f, "pgv_ivfflat_cosine"),
             IndexDist::vsc_diskann_cosine => write!(f, "vsc_diskann_cosine"),
+            IndexDist::vsc_diskann_l2 => write!(f, "vsc_diskann_l2"),
+            IndexDist::vsc_diskann_ip => write!(f, "vsc_diskann_ip"),
         }
     }
 }
@@ -73,7 +185,12 @@ impl FromStr for IndexDist {
             "pgv_hnsw_l2" => Ok(IndexDist::pgv_hnsw_l2),
             "pgv_hnsw_ip" => Ok(IndexDist::pgv_hnsw_ip),
             "pgv_hnsw_cosine" => Ok(IndexDist::pgv_hnsw_cosine),
+            "pgv_ivfflat_l2" => Ok(IndexDist::pgv_ivfflat_l2),
+            "pgv_ivfflat_ip" => Ok(IndexDist::pgv_ivfflat_ip),
+            "pgv_ivfflat_cosine" => Ok(IndexDist::pgv_ivfflat_cosine),
             "vsc_diskann_cosine" => Ok(IndexDist::vsc_diskann_cosine),
+            "vsc_diskann_l2" => Ok(IndexDist::vsc_diskann_l2),
+            "vsc_diskann_ip" => Ok(IndexDist::vsc_diskann_ip),
             _ => Err(format!("Invalid value for IndexDist: {}", s)),
         }
     }
@@ -85,12 +202,58 @@ impl From<String> for IndexDist {
             "pgv_hnsw_l2" => IndexDist::pgv_hnsw_l2,
             "pgv_hnsw_ip" => IndexDist::pgv_hnsw_ip,
             "pgv_hnsw_cosine" => IndexDist::pgv_hnsw_cosine,
+            "pgv_ivfflat_l2" => IndexDist::pgv_ivfflat_l2,
+            "pgv_ivfflat_ip" => IndexDist::pgv_ivfflat_ip,
+            "pgv_ivfflat_cosine" => IndexDist::pgv_ivfflat_cosine,
             "vsc_diskann_cosine" => IndexDist::vsc_diskann_cosine,
+            "vsc_diskann_l2" => IndexDist::vsc_diskann_l2,
+            "vsc_diskann_ip" => IndexDist::vsc_diskann_ip,
             _ => panic!("Invalid value for IndexDist: {}", s),
         }
     }
 }
 
+/// the kind of relation a job reads its rows from
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SourceKind {
+    // a table, or a partitioned table
+    #[default]
+    table,
+    // e.g. a table of postgres_fdw or parquet_fdw
+    foreign_table,
+    view,
+    materialized_view,
+}
+
+impl SourceKind {
+    /// the kind of a relation, from its relkind in pg_class
+    pub fn from_relkind(relkind: &str) -> Option<Self> {
+        match relkind {
+            "r" | "p" => Some(SourceKind::table),
+            "f" => Some(SourceKind::foreign_table),
+            "v" => Some(SourceKind::view),
+            "m" => Some(SourceKind::materialized_view),
+            _ => None,
+        }
+    }
+
+    pub fn is_table(&self) -> bool {
+        *self == SourceKind::table
+    }
+}
+
+impl Display for SourceKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SourceKind::table => write!(f, "table"),
+            SourceKind::foreign_table => write!(f, "foreign table"),
+            SourceKind::view => write!(f, "view"),
+            SourceKind::materialized_view => write!(f, "materialized view"),
+        }
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub enum TableMethod {
@@ -101,7 +264,541 @@ pub enum TableMethod {
     join,
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize, FromRow)]
+// build parameters for the vector index
+// unset parameters fall back to the index access method's defaults
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IndexParams {
+    // max number of connections per layer (hnsw)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub m: Option<i32>,
+    // size of the dynamic candidate list used while building the graph (hnsw)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ef_construction: Option<i32>,
+    // number of inverted lists (ivfflat)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lists: Option<i32>,
+    // max number of neighbors per node in the graph (diskann)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_neighbors: Option<i32>,
+    // size of the candidate list used while building the graph (diskann)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_list_size: Option<i32>,
+    // memory_optimized to compress the vectors stored in the index, or plain (diskann)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_layout: Option<String>,
+    // bits per dimension of the compressed vectors of a memory_optimized index (diskann)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_bits_per_dimension: Option<i32>,
+    // any other storage parameters of the index, e.g. those of diskann
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage_params: BTreeMap<String, serde_json::Value>,
+}
+
+impl IndexParams {
+    /// renders the WITH clause for CREATE INDEX, or an empty string when nothing is set
+    pub fn with_clause(&self) -> String {
+        let mut opts: Vec<String> = Vec::new();
+        if let Some(m) = self.m {
+            opts.push(format!("m = {m}"));
+        }
+        if let Some(ef_construction) = self.ef_construction {
+            opts.push(format!("ef_construction = {ef_construction}"));
+        }
+        if let Some(lists) = self.lists {
+            opts.push(format!("lists = {lists}"));
+        }
+        if let Some(num_neighbors) = self.num_neighbors {
+            opts.push(format!("num_neighbors = {num_neighbors}"));
+        }
+        if let Some(search_list_size) = self.search_list_size {
+            opts.push(format!("search_list_size = {search_list_size}"));
+        }
+        if let Some(storage_layout) = &self.storage_layout {
+            opts.push(format!("storage_layout = {storage_layout}"));
+        }
+        if let Some(num_bits_per_dimension) = self.num_bits_per_dimension {
+            opts.push(format!("num_bits_per_dimension = {num_bits_per_dimension}"));
+        }
+        opts.extend(
+            self.storage_params
+                .iter()
+                .map(|(k, v)| format!("{k} = {v}")),
+        );
+        if opts.is_empty() {
+            String::new()
+        } else {
+            format!(" WITH ({})", opts.join(", "))
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &IndexParams::default()
+    }
+}
+
+/// what a job's embeddings are stored as
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum VectorStorage {
+    // pgvector's vector, 4 bytes per dimension
+    #[default]
+    vector,
+    // pgvector's halfvec, 2 bytes per dimension
+    halfvec,
+    // int8 scalar quantization, a byte per dimension and a scale factor per embedding, stored as bytea
+    int8,
+}
+
+impl VectorStorage {
+    pub fn is_default(&self) -> bool {
+        *self == VectorStorage::vector
+    }
+
+    /// the column type of embeddings with the given dimensions
+    pub fn column_type(&self, dimensions: u32) -> String {
+        match self {
+            VectorStorage::vector => format!("vector({dimensions})"),
+            VectorStorage::halfvec => format!("halfvec({dimensions})"),
+            VectorStorage::int8 => "bytea".to_string(),
+        }
+    }
+
+    /// the expression storing an embedding, given as a vector or its text, e.g. $1
+    pub fn store(&self, embedding: &str) -> String {
+        match self {
+            VectorStorage::vector => format!("{embedding}::vector"),
+            VectorStorage::halfvec => format!("{embedding}::halfvec"),
+            VectorStorage::int8 => {
+                format!("vectorize._quantize_int8({embedding}::vector::real[])")
+            }
+        }
+    }
+
+    /// the pgvector type that stored embeddings are searched as, which queries are cast to
+    /// quantized embeddings are searched at half precision
+    pub fn vector_type(&self) -> &'static str {
+        match self {
+            VectorStorage::vector => "vector",
+            VectorStorage::halfvec | VectorStorage::int8 => "halfvec",
+        }
+    }
+
+    /// the searchable vector of a stored embeddings column
+    pub fn searchable(&self, column: &str, dimensions: u32) -> String {
+        match self {
+            VectorStorage::vector | VectorStorage::halfvec => column.to_string(),
+            VectorStorage::int8 => {
+                format!("vectorize._dequantize_int8({column})::halfvec({dimensions})")
+            }
+        }
+    }
+}
+
+impl FromStr for VectorStorage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vector" => Ok(VectorStorage::vector),
+            "halfvec" => Ok(VectorStorage::halfvec),
+            "int8" => Ok(VectorStorage::int8),
+            _ => Err(format!(
+                "Invalid storage: {}, expected one of: vector, halfvec, int8",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for VectorStorage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            VectorStorage::vector => write!(f, "vector"),
+            VectorStorage::halfvec => write!(f, "halfvec"),
+            VectorStorage::int8 => write!(f, "int8"),
+        }
+    }
+}
+
+// storage settings of a job's embeddings
+// unset settings fall back to the defaults of Postgres
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StorageParams {
+    // STORAGE of the embeddings column: plain, external, extended or main
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column_storage: Option<String>,
+    // COMPRESSION of the embeddings column: pglz or lz4
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    // tablespace of the embeddings table, and of the index unless it has a tablespace of its own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tablespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_tablespace: Option<String>,
+    // storage parameters of the embeddings table, e.g. fillfactor or toast_tuple_target
+    #[serde(flatten)]
+    pub table_params: BTreeMap<String, serde_json::Value>,
+}
+
+impl StorageParams {
+    /// renders the WITH clause for the embeddings table, or an empty string when nothing is set
+    pub fn with_clause(&self) -> String {
+        if self.table_params.is_empty() {
+            return String::new();
+        }
+        let opts: Vec<String> = self
+            .table_params
+            .iter()
+            .map(|(k, v)| format!("{k} = {v}"))
+            .collect();
+        format!(" WITH ({})", opts.join(", "))
+    }
+
+    /// renders the TABLESPACE clause for the embeddings table, or an empty string when it is not set
+    pub fn tablespace_clause(&self) -> String {
+        self.tablespace
+            .as_ref()
+            .map(|t| format!(" TABLESPACE {t}"))
+            .unwrap_or_default()
+    }
+
+    /// renders the TABLESPACE clause for the index, or an empty string when neither tablespace is set
+    pub fn index_tablespace_clause(&self) -> String {
+        self.index_tablespace
+            .as_ref()
+            .or(self.tablespace.as_ref())
+            .map(|t| format!(" TABLESPACE {t}"))
+            .unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &StorageParams::default()
+    }
+}
+
+/// picks the number of ivfflat lists for a table, following pgvector's guidance of
+/// rows / 1000 for up to 1M rows and sqrt(rows) beyond that
+pub fn ivfflat_lists(num_rows: i64) -> i32 {
+    if num_rows <= 1_000_000 {
+        (num_rows / 1000).max(1) as i32
+    } else {
+        (num_rows as f64).sqrt() as i32
+    }
+}
+
+// decrypts protected columns inside the worker, so that their plaintext is never written to the job queue
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ColumnDecryption {
+    // role the decryption expressions are evaluated as
+    pub role: String,
+    // role that created the job, which must still be a member of the decryption role for the worker to assume it
+    #[serde(default)]
+    pub owner: String,
+    // column name -> SQL expression returning the column's plaintext
+    // e.g. pgp_sym_decrypt(body, current_setting('app.key'))
+    pub expressions: BTreeMap<String, String>,
+}
+
+impl ColumnDecryption {
+    /// the expression that produces the embedding input for a column
+    /// columns without a decryption expression are read as-is
+    pub fn input_expression(&self, column: &str) -> String {
+        self.expressions
+            .get(column)
+            .cloned()
+            .unwrap_or_else(|| column.to_string())
+    }
+}
+
+// columns of a rag agent's table that locate each row, a chunk of a larger document, within that document
+// their values are returned with each piece of context, so that answers can link back to their sources
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkProvenance {
+    pub document_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<String>,
+    // character offsets of the chunk within the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_offset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_offset: Option<String>,
+}
+
+impl ChunkProvenance {
+    fn fields(&self) -> Vec<(&'static str, &str)> {
+        let mut fields = vec![("document_id", self.document_id.as_str())];
+        for (field, column) in [
+            ("chunk_index", &self.chunk_index),
+            ("start_offset", &self.start_offset),
+            ("end_offset", &self.end_offset),
+        ] {
+            if let Some(column) = column {
+                fields.push((field, column.as_str()));
+            }
+        }
+        fields
+    }
+
+    /// the columns that hold the provenance
+    pub fn columns(&self) -> Vec<String> {
+        self.fields()
+            .into_iter()
+            .map(|(_, column)| column.to_string())
+            .collect()
+    }
+
+    /// the provenance of a search result, keyed by field rather than by column
+    pub fn of(&self, row: &serde_json::Value) -> serde_json::Value {
+        serde_json::Value::Object(
+            self.fields()
+                .into_iter()
+                .map(|(field, column)| {
+                    let value = row.get(column).cloned().unwrap_or(serde_json::Value::Null);
+                    (field.to_string(), value)
+                })
+                .collect(),
+        )
+    }
+}
+
+/// how texts are split into chunks
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ChunkStrategy {
+    // fixed windows of chunk_size tokens
+    #[default]
+    tokens,
+    // splits on the first of the separators that keeps pieces within chunk_size, e.g. paragraphs,
+    // then lines, then sentences, then words, and merges the pieces back into chunks
+    recursive,
+    // merges whole sentences into chunks, a sentence longer than chunk_size is a chunk of its own
+    sentence,
+    // splits markdown into the sections under its headings, and sections longer than chunk_size recursively
+    markdown,
+    // splits source code on its top level blocks, e.g. functions and classes, then on the blocks indented within them
+    code,
+    // merges sentences into chunks until the embeddings of adjacent sentences are no longer similar,
+    // chunked by the worker as it needs the job's model
+    semantic,
+    // splits on every match of a regular expression, e.g. the timestamp starting each entry of a log
+    separator,
+    // sliding windows of chunk_size tokens, each starting chunk_stride tokens after the previous one
+    window,
+}
+
+impl FromStr for ChunkStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(ChunkStrategy::tokens),
+            "recursive" => Ok(ChunkStrategy::recursive),
+            "sentence" => Ok(ChunkStrategy::sentence),
+            "markdown" => Ok(ChunkStrategy::markdown),
+            "code" => Ok(ChunkStrategy::code),
+            "semantic" => Ok(ChunkStrategy::semantic),
+            "separator" => Ok(ChunkStrategy::separator),
+            "window" => Ok(ChunkStrategy::window),
+            _ => Err(format!(
+                "Invalid chunk_strategy: {}, expected one of: tokens, recursive, sentence, markdown, code, semantic, separator, window",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for ChunkStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ChunkStrategy::tokens => write!(f, "tokens"),
+            ChunkStrategy::recursive => write!(f, "recursive"),
+            ChunkStrategy::sentence => write!(f, "sentence"),
+            ChunkStrategy::markdown => write!(f, "markdown"),
+            ChunkStrategy::code => write!(f, "code"),
+            ChunkStrategy::semantic => write!(f, "semantic"),
+            ChunkStrategy::separator => write!(f, "separator"),
+            ChunkStrategy::window => write!(f, "window"),
+        }
+    }
+}
+
+/// how a job's texts are cleaned up before they are chunked and embedded
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Preprocess {
+    // strips markup, scripts, styles and navigation from html pages
+    html,
+}
+
+impl FromStr for Preprocess {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(Preprocess::html),
+            _ => Err(format!("Invalid preprocess: {}, expected one of: html", s)),
+        }
+    }
+}
+
+impl Display for Preprocess {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Preprocess::html => write!(f, "html"),
+        }
+    }
+}
+
+/// what the column of a job holds, which is embedded as text or as an image
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ContentType {
+    #[default]
+    text,
+    // bytea of a png, jpeg, gif or webp image
+    image,
+    // text of the url of an image
+    image_url,
+}
+
+impl ContentType {
+    pub fn is_text(&self) -> bool {
+        *self == ContentType::text
+    }
+
+    /// the expression producing the input of a column's value, images are sent to providers base64 encoded
+    pub fn input_text(&self, column: &str) -> String {
+        match self {
+            // encode() breaks its output into lines
+            ContentType::image => format!("translate(encode({column}, 'base64'), E'\\n', '')"),
+            ContentType::text | ContentType::image_url => format!("{column}::text"),
+        }
+    }
+}
+
+impl FromStr for ContentType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ContentType::text),
+            "image" => Ok(ContentType::image),
+            "image_url" => Ok(ContentType::image_url),
+            _ => Err(format!(
+                "Invalid content_type: {}, expected one of: text, image, image_url",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for ContentType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ContentType::text => write!(f, "text"),
+            ContentType::image => write!(f, "image"),
+            ContentType::image_url => write!(f, "image_url"),
+        }
+    }
+}
+
+// how a text is split into chunks, sizes are in tokens
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkParams {
+    pub chunk_size: u32,
+    #[serde(default)]
+    pub chunk_overlap: u32,
+    #[serde(default)]
+    pub strategy: ChunkStrategy,
+    // separators of the recursive strategy, from the coarsest to the finest
+    // the defaults are used when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub separators: Vec<String>,
+    // the similarity of adjacent sentences below which the semantic strategy starts a new chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub similarity_threshold: Option<f64>,
+    // the regular expression the separator strategy splits on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub separator: Option<String>,
+    // the tokens each window of the window strategy starts after the previous one, half of chunk_size when none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_stride: Option<u32>,
+    // discards chunks before they are written and embedded, e.g. boilerplate or very short chunks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<ChunkFilter>,
+    // the params of the columns that are chunked apart from the others, by column name
+    // a column given no params is not chunked, its whole text is a single chunk
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub column_params: BTreeMap<String, Option<ChunkParams>>,
+}
+
+// the chunks a job discards before they are embedded, a chunk is discarded by any of the conditions
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkFilter {
+    // chunks of fewer tokens are discarded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_tokens: Option<u32>,
+    // chunks matching any of these regular expressions are discarded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_patterns: Vec<String>,
+    // a SQL function taking a chunk's text and returning a boolean, chunks it does not return true for are discarded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+    // the role that gave the filter, which the worker calls the function as
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub owner: String,
+}
+
+impl ChunkParams {
+    /// the chunk params of a column, none when the column is not chunked
+    pub fn of_column(&self, column: &str) -> Option<&ChunkParams> {
+        match self.column_params.get(column) {
+            Some(params) => params.as_ref(),
+            None => Some(self),
+        }
+    }
+}
+
+// a chunked job embeds the chunks of its source table's rows, which are written to a table of chunks
+// the job's schema, table, columns and primary key are those of the chunks table
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Chunking {
+    pub schema: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub primary_key: String,
+    pub pkey_type: String,
+    pub params: ChunkParams,
+    // the templated text of each row is chunked in place of its columns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_template: Option<String>,
+}
+
+// the source_column of the chunks of a row's templated text
+pub const TEMPLATE_SOURCE_COLUMN: &str = "input_template";
+
+impl Chunking {
+    /// the names of the texts of each row that are chunked, which their chunks record as their source_column
+    /// the job's columns, or the templated text of the row for a job with an input template
+    pub fn sources(&self) -> Vec<&str> {
+        match self.input_template {
+            Some(_) => vec![TEMPLATE_SOURCE_COLUMN],
+            None => self.columns.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+// token estimate given to rows whose input text is only resolved by the worker
+pub const DECRYPTED_INPUT_TOKEN_ESTIMATE: i32 = 256;
+
+// token estimate given to images, whose base64 encoding says nothing of what embedding them costs
+pub const IMAGE_INPUT_TOKEN_ESTIMATE: i32 = 1000;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "worker", derive(FromRow))]
 pub struct JobParams {
     pub schema: String,
     pub table: String,
@@ -115,23 +812,348 @@ pub struct JobParams {
     #[serde(default = "default_schedule")]
     pub schedule: String,
     pub args: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "IndexParams::is_empty")]
+    pub index_params: IndexParams,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decryption: Option<ColumnDecryption>,
+    // column name -> weight, columns without one weigh 1
+    // when set, each column is embedded on its own and the embeddings are combined by weight
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub column_weights: BTreeMap<String, f64>,
+    // partition the embeddings table the same way as the partitioned source table
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partition_embeddings: bool,
+    // schema of the embeddings table of a join job, vectorize when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dest_schema: Option<String>,
+    // length of the job's embeddings, every embedding written is checked against it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+    // embeddings are truncated to dimensions, which is shorter than the model's output
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncate_dimensions: bool,
+    // embeddings and search queries are scaled to unit length, so that inner product ranks as cosine does
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub normalize: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<ChunkProvenance>,
+    // the job's table is a collection of documents, created and managed by vectorize
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub collection: bool,
+    // SQL expression over a row's columns that produces its input text, in place of joining the columns
+    // e.g. format('Q: %s A: %s', question, answer)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<Ttl>,
+    // max tokens per queued batch of the job's rows, vectorize.batch_size when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_size: Option<i32>,
+    // times each embedding request of the job is sent before its batch fails, vectorize.max_attempts when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    // seconds to wait for a connection to the job's provider, vectorize.connect_timeout_sec when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_sec: Option<u32>,
+    // seconds to wait for the job's embedding and chat requests to be answered,
+    // vectorize.embedding_req_timeout_sec and vectorize.chat_req_timeout_sec when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_timeout_sec: Option<u32>,
+    // name of the job's credential in vectorize.credentials, used in place of the provider's GUCs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+    // views and foreign tables can not have triggers with transition tables or be referenced by foreign keys
+    // their changes are found by diffing them on the job's schedule
+    #[serde(default, skip_serializing_if = "SourceKind::is_table")]
+    pub source_kind: SourceKind,
+    #[serde(default, skip_serializing_if = "VectorStorage::is_default")]
+    pub vector_storage: VectorStorage,
+    #[serde(default, skip_serializing_if = "StorageParams::is_empty")]
+    pub storage_params: StorageParams,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunking: Option<Chunking>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preprocess: Option<Preprocess>,
+    // the job's one column holds images, which are embedded into the same space as text queries
+    #[serde(default, skip_serializing_if = "ContentType::is_text")]
+    pub content_type: ContentType,
+}
+
+/// the keys that a job's requests to a provider are sent with, in place of the provider's GUCs
+/// requests to other providers, e.g. the chat model of an agent, keep their GUCs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Credential {
+    pub source: ModelSource,
+    pub api_key: Option<String>,
+    // the Portkey virtual key of the job's requests
+    pub virtual_key: Option<String>,
+}
+
+// how long a job keeps its embeddings, expired embeddings are purged on a schedule
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Ttl {
+    // a Postgres interval, e.g. 30 days
+    pub interval: String,
+    // also delete the expired documents of a collection
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub delete_source: bool,
 }
 
 fn default_schedule() -> String {
     "realtime".to_string()
 }
 
+impl JobParams {
+    pub fn pkey(&self) -> PrimaryKey {
+        PrimaryKey::new(&self.primary_key, &self.pkey_type)
+    }
+
+    /// the schema of the table holding the job's embeddings
+    pub fn embeddings_schema(&self) -> &str {
+        match self.table_method {
+            TableMethod::append => &self.schema,
+            TableMethod::join => self.dest_schema.as_deref().unwrap_or(VECTORIZE_SCHEMA),
+        }
+    }
+
+    /// the schema, table and column of the job's embeddings
+    pub fn embeddings_location(&self, job_name: &str) -> (String, String, String) {
+        match self.table_method {
+            TableMethod::append => (
+                self.schema.clone(),
+                self.table.clone(),
+                format!("{job_name}_embeddings"),
+            ),
+            TableMethod::join => (
+                self.embeddings_schema().to_string(),
+                format!("_embeddings_{job_name}"),
+                "embeddings".to_string(),
+            ),
+        }
+    }
+
+    /// the searchable vector of the job's embeddings column, e.g. t1.embeddings
+    pub fn searchable_embeddings(&self, column: &str) -> String {
+        self.vector_storage
+            .searchable(column, self.dimensions.unwrap_or_default())
+    }
+
+    /// the input text of a row, for jobs with an input template or of images, given a reference to the row, e.g. t0
+    /// the template is evaluated over the row's columns alone, so its column names are never ambiguous
+    /// the template of a job of image urls produces the url of each row's image
+    pub fn templated_input_text(&self, row: &str) -> Option<String> {
+        let templated = self
+            .input_template
+            .as_ref()
+            .map(|template| format!("(SELECT ({template})::text FROM (SELECT {row}.*) input_row)"));
+        if let Some(column) = self
+            .columns
+            .first()
+            .filter(|_| !self.content_type.is_text())
+        {
+            let input = templated.unwrap_or_else(|| format!("{row}.{column}"));
+            return Some(self.content_type.input_text(&input));
+        }
+        templated
+    }
+
+    /// the dimensions the job's embeddings are truncated to, when they are shorter than the model's
+    pub fn truncated_dimensions(&self) -> Option<u32> {
+        self.dimensions.filter(|_| self.truncate_dimensions)
+    }
+
+    pub fn is_weighted(&self) -> bool {
+        !self.column_weights.is_empty()
+    }
+
+    /// the input text of a weighted job, which keeps its columns apart as a json array
+    /// takes one expression per input column, in the order of the job's columns
+    pub fn weighted_input_text(&self, column_expressions: &[String]) -> String {
+        format!("jsonb_build_array({})::text", column_expressions.join(", "))
+    }
+
+    /// splits the input text of a weighted job into each column's weight and text
+    /// columns without any text are left out
+    /// errors when the input does not have a text for each of the job's columns
+    pub fn split_weighted_input(
+        &self,
+        input: &str,
+    ) -> Result<Vec<(f64, String)>, serde_json::Error> {
+        let texts: Vec<Option<String>> = serde_json::from_str(input)?;
+        if texts.len() != self.columns.len() {
+            return Err(serde::de::Error::invalid_length(
+                texts.len(),
+                &format!("a text for each of the {} columns", self.columns.len()).as_str(),
+            ));
+        }
+        Ok(self
+            .columns
+            .iter()
+            .zip(texts)
+            .filter_map(|(col, text)| {
+                let text = text?.trim().to_owned();
+                if text.is_empty() {
+                    return None;
+                }
+                let weight = self.column_weights.get(col).copied().unwrap_or(1.0);
+                Some((weight, text))
+            })
+            .collect())
+    }
+}
+
+// splits a comma separated list, ignoring commas inside parentheses, e.g. in numeric(10, 2)
+fn split_list(list: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in list.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(current.trim().to_string());
+                current = String::new();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    items.push(current.trim().to_string());
+    items
+}
+
+// a job's primary key, made up of one or more columns
+// composite keys are stored in JobParams as comma separated lists of columns and their types
+// the record id of a row is the key's text representation, or a json array of the values when composite
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrimaryKey {
+    columns: Vec<(String, String)>,
+}
+
+impl PrimaryKey {
+    pub fn new(primary_key: &str, pkey_type: &str) -> PrimaryKey {
+        PrimaryKey {
+            columns: split_list(primary_key)
+                .into_iter()
+                .zip(split_list(pkey_type))
+                .collect(),
+        }
+    }
+
+    pub fn is_composite(&self) -> bool {
+        self.columns.len() > 1
+    }
+
+    pub fn column_names(&self) -> Vec<String> {
+        self.columns.iter().map(|(c, _)| c.clone()).collect()
+    }
+
+    /// column definitions, e.g. for an embeddings table keyed by the same columns
+    pub fn column_defs(&self) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|(c, t)| format!("{c} {t}"))
+            .collect()
+    }
+
+    fn qualified(alias: Option<&str>, column: &str) -> String {
+        match alias {
+            Some(a) => format!("{a}.{column}"),
+            None => column.to_string(),
+        }
+    }
+
+    /// expression producing a row's record id
+    pub fn record_id(&self, alias: Option<&str>) -> String {
+        let cols: Vec<String> = self
+            .columns
+            .iter()
+            .map(|(c, _)| Self::qualified(alias, c))
+            .collect();
+        if self.is_composite() {
+            format!("jsonb_build_array({})::text", cols.join(", "))
+        } else {
+            format!("{}::text", cols[0])
+        }
+    }
+
+    /// join condition between two tables sharing the key
+    pub fn join_on(&self, left: &str, right: &str) -> String {
+        self.columns
+            .iter()
+            .map(|(c, _)| format!("{left}.{c} = {right}.{c}"))
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    }
+
+    /// the key's values, as typed expressions over a parameter holding a record id
+    pub fn values_from_record_id(&self, param: &str) -> Vec<String> {
+        if self.is_composite() {
+            self.columns
+                .iter()
+                .enumerate()
+                .map(|(i, (_, t))| format!("({param}::jsonb->>{i})::{t}"))
+                .collect()
+        } else {
+            vec![format!("{param}::{}", self.columns[0].1)]
+        }
+    }
+
+    /// condition matching the row identified by the record id in a parameter
+    pub fn matches_record_id(&self, alias: Option<&str>, param: &str) -> String {
+        self.columns
+            .iter()
+            .zip(self.values_from_record_id(param))
+            .map(|((c, _), v)| format!("{} = {v}", Self::qualified(alias, c)))
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    }
+
+    /// condition matching the rows identified by the text array of record ids in a parameter
+    pub fn matches_record_ids(&self, alias: Option<&str>, param: &str) -> String {
+        if self.is_composite() {
+            let cols: Vec<String> = self
+                .columns
+                .iter()
+                .map(|(c, _)| Self::qualified(alias, c))
+                .collect();
+            let values: Vec<String> = self
+                .columns
+                .iter()
+                .enumerate()
+                .map(|(i, (_, t))| format!("(r->>{i})::{t}"))
+                .collect();
+            format!(
+                "({}) IN (SELECT {} FROM unnest({param}::jsonb[]) AS r)",
+                cols.join(", "),
+                values.join(", ")
+            )
+        } else {
+            let (c, t) = &self.columns[0];
+            format!("{} = ANY({param}::{t}[])", Self::qualified(alias, c))
+        }
+    }
+}
+
 // schema for all messages that hit pgmq
 #[derive(Clone, Deserialize, Debug, Serialize)]
 pub struct JobMessage {
     pub job_name: String,
     pub job_meta: VectorizeMeta,
     pub inputs: Vec<crate::transformers::types::Inputs>,
+    // the inputs are rows of a chunked job's source table, for the worker to split into chunks with
+    // the semantic strategy, rather than chunks to embed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub semantic_chunking: bool,
 }
 
 // schema for every job
 // also schema for the vectorize.vectorize_meta table
-#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "worker", derive(FromRow))]
 pub struct VectorizeMeta {
     pub job_id: i64,
     pub name: String,
@@ -148,11 +1170,52 @@ pub struct Model {
     // the model's namespace + model name
     pub fullname: String,
     pub name: String,
+    // set for models registered in vectorize.models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration: Option<Registration>,
+}
+
+/// what vectorize.models knows of a model, beyond its source
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Registration {
+    pub dimensions: Option<u32>,
+    // the url the model is served at, in place of its source's
+    pub endpoint: Option<String>,
+    // settings of the model's source, e.g. the name its API knows it by in "model"
+    #[serde(default)]
+    pub options: serde_json::Value,
 }
 
 impl Model {
+    /// a model registered under a name of its own, which may have no source prefix
+    pub fn registered(name: &str, source: ModelSource, registration: Registration) -> Model {
+        Model {
+            source,
+            fullname: name.to_string(),
+            name: name.rsplit('/').next().unwrap_or(name).to_string(),
+            registration: Some(registration),
+        }
+    }
+
+    /// the dimensions of a registered model's embeddings, which then need not be probed
+    pub fn registered_dimensions(&self) -> Option<u32> {
+        self.registration.as_ref().and_then(|r| r.dimensions)
+    }
+
+    pub fn registered_endpoint(&self) -> Option<String> {
+        self.registration.as_ref().and_then(|r| r.endpoint.clone())
+    }
+
     // the name to use when calling an API
     pub fn api_name(&self) -> String {
+        let registered_name = self
+            .registration
+            .as_ref()
+            .and_then(|r| r.options.get("model"))
+            .and_then(|m| m.as_str());
+        if let Some(name) = registered_name {
+            return name.to_string();
+        }
         match self.source {
             ModelSource::OpenAI => self.name.clone(),
             ModelSource::SentenceTransformers => self.fullname.clone(),
@@ -161,10 +1224,86 @@ impl Model {
             ModelSource::Cohere => self.name.clone(),
             ModelSource::Portkey => self.name.clone(),
             ModelSource::Voyage => self.name.clone(),
+            ModelSource::AzureOpenAI => self.name.clone(),
+            ModelSource::Bedrock => self.name.clone(),
+            ModelSource::Gemini => self.name.clone(),
+            ModelSource::Mistral => self.name.clone(),
+            ModelSource::Jina => self.name.clone(),
+            ModelSource::Anthropic => self.name.clone(),
+            // the repo id of the model on the Hugging Face Hub
+            ModelSource::HuggingFace => self
+                .fullname
+                .strip_prefix("huggingface/")
+                .unwrap_or(&self.fullname)
+                .to_string(),
+            ModelSource::OpenAICompatible => self
+                .fullname
+                .strip_prefix("openai-compatible/")
+                .unwrap_or(&self.fullname)
+                .to_string(),
+            // the name the server was started with, e.g. meta-llama/Llama-3.1-8B-Instruct
+            ModelSource::Vllm | ModelSource::LlamaCpp => self
+                .fullname
+                .split_once('/')
+                .map(|(_, name)| name.to_string())
+                .unwrap_or_else(|| self.name.clone()),
         }
     }
+
+    // the recommended replacement, when the provider has deprecated this model
+    pub fn deprecated_replacement(&self) -> Option<Model> {
+        DEPRECATED_MODELS
+            .iter()
+            .find(|(deprecated, _)| *deprecated == self.fullname)
+            .map(|(_, replacement)| Model::new(replacement).expect("invalid replacement model"))
+    }
 }
 
+// models that providers have deprecated, and what to move to
+// keyed by the model's fullname
+pub const DEPRECATED_MODELS: &[(&str, &str)] = &[
+    (
+        "openai/text-embedding-ada-002",
+        "openai/text-embedding-3-small",
+    ),
+    (
+        "openai/text-similarity-ada-001",
+        "openai/text-embedding-3-small",
+    ),
+    (
+        "openai/text-search-ada-doc-001",
+        "openai/text-embedding-3-small",
+    ),
+    (
+        "openai/text-search-ada-query-001",
+        "openai/text-embedding-3-small",
+    ),
+    (
+        "openai/text-similarity-babbage-001",
+        "openai/text-embedding-3-small",
+    ),
+    (
+        "openai/text-similarity-curie-001",
+        "openai/text-embedding-3-large",
+    ),
+    (
+        "openai/text-similarity-davinci-001",
+        "openai/text-embedding-3-large",
+    ),
+    ("cohere/embed-english-v2.0", "cohere/embed-english-v3.0"),
+    (
+        "cohere/embed-english-light-v2.0",
+        "cohere/embed-english-light-v3.0",
+    ),
+    (
+        "cohere/embed-multilingual-v2.0",
+        "cohere/embed-multilingual-v3.0",
+    ),
+    ("voyage/voyage-01", "voyage/voyage-3"),
+    ("voyage/voyage-lite-01", "voyage/voyage-3-lite"),
+    ("voyage/voyage-lite-02-instruct", "voyage/voyage-3-lite"),
+];
+
 impl From<String> for Model {
     fn from(input: String) -> Self {
         let errmsg = format!("Invalid input string for Model: {}", input);
@@ -218,6 +1357,7 @@ impl Model {
             source,
             fullname: parts.join("/"),
             name,
+            registration: None,
         })
     }
 }
@@ -239,6 +1379,23 @@ pub enum ModelSource {
     Cohere,
     Portkey,
     Voyage,
+    // models are addressed by the name of their Azure OpenAI deployment
+    AzureOpenAI,
+    // models are addressed by their Bedrock model id, e.g. amazon.titan-embed-text-v2:0
+    Bedrock,
+    // Gemini models, served by the Gemini API or Vertex AI
+    Gemini,
+    Mistral,
+    Jina,
+    // chat models only
+    Anthropic,
+    // models of the Hugging Face Hub, by their repo id, e.g. huggingface/BAAI/bge-small-en-v1.5
+    HuggingFace,
+    // models served by any API that follows OpenAI's schemas, configured per model
+    OpenAICompatible,
+    // self-hosted inference servers
+    Vllm,
+    LlamaCpp,
 }
 
 impl FromStr for ModelSource {
@@ -253,6 +1410,16 @@ impl FromStr for ModelSource {
             "cohere" => Ok(ModelSource::Cohere),
             "portkey" => Ok(ModelSource::Portkey),
             "voyage" => Ok(ModelSource::Voyage),
+            "azure" => Ok(ModelSource::AzureOpenAI),
+            "bedrock" => Ok(ModelSource::Bedrock),
+            "gemini" => Ok(ModelSource::Gemini),
+            "mistral" => Ok(ModelSource::Mistral),
+            "jina" => Ok(ModelSource::Jina),
+            "anthropic" => Ok(ModelSource::Anthropic),
+            "huggingface" => Ok(ModelSource::HuggingFace),
+            "openai-compatible" => Ok(ModelSource::OpenAICompatible),
+            "vllm" => Ok(ModelSource::Vllm),
+            "llamacpp" => Ok(ModelSource::LlamaCpp),
             _ => Ok(ModelSource::SentenceTransformers),
         }
     }
@@ -268,6 +1435,16 @@ impl Display for ModelSource {
             ModelSource::Cohere => write!(f, "cohere"),
             ModelSource::Portkey => write!(f, "portkey"),
             ModelSource::Voyage => write!(f, "voyage"),
+            ModelSource::AzureOpenAI => write!(f, "azure"),
+            ModelSource::Bedrock => write!(f, "bedrock"),
+            ModelSource::Gemini => write!(f, "gemini"),
+            ModelSource::Mistral => write!(f, "mistral"),
+            ModelSource::Jina => write!(f, "jina"),
+            ModelSource::Anthropic => write!(f, "anthropic"),
+            ModelSource::HuggingFace => write!(f, "huggingface"),
+            ModelSource::OpenAICompatible => write!(f, "openai-compatible"),
+            ModelSource::Vllm => write!(f, "vllm"),
+            ModelSource::LlamaCpp => write!(f, "llamacpp"),
         }
     }
 }
@@ -282,6 +1459,16 @@ impl From<String> for ModelSource {
             "cohere" => ModelSource::Cohere,
             "portkey" => ModelSource::Portkey,
             "voyage" => ModelSource::Voyage,
+            "azure" => ModelSource::AzureOpenAI,
+            "bedrock" => ModelSource::Bedrock,
+            "gemini" => ModelSource::Gemini,
+            "mistral" => ModelSource::Mistral,
+            "jina" => ModelSource::Jina,
+            "anthropic" => ModelSource::Anthropic,
+            "huggingface" => ModelSource::HuggingFace,
+            "openai-compatible" => ModelSource::OpenAICompatible,
+            "vllm" => ModelSource::Vllm,
+            "llamacpp" => ModelSource::LlamaCpp,
             // other cases are assumed to be private sentence-transformer compatible model
             // and can be hot-loaded
             _ => ModelSource::SentenceTransformers,
@@ -312,6 +1499,117 @@ mod model_tests {
         assert_eq!(model.api_name(), "voyage-3-lite");
     }
 
+    #[test]
+    fn test_azure_parsing() {
+        let model = Model::new("azure/my-embedding-deployment").unwrap();
+        assert_eq!(model.source, ModelSource::AzureOpenAI);
+        assert_eq!(model.fullname, "azure/my-embedding-deployment");
+        assert_eq!(model.api_name(), "my-embedding-deployment");
+        assert_eq!(model.to_string(), "azure/my-embedding-deployment");
+    }
+
+    #[test]
+    fn test_bedrock_parsing() {
+        let model = Model::new("bedrock/amazon.titan-embed-text-v2:0").unwrap();
+        assert_eq!(model.source, ModelSource::Bedrock);
+        assert_eq!(model.fullname, "bedrock/amazon.titan-embed-text-v2:0");
+        assert_eq!(model.api_name(), "amazon.titan-embed-text-v2:0");
+        assert_eq!(model.to_string(), "bedrock/amazon.titan-embed-text-v2:0");
+    }
+
+    #[test]
+    fn test_gemini_parsing() {
+        let model = Model::new("gemini/text-embedding-004").unwrap();
+        assert_eq!(model.source, ModelSource::Gemini);
+        assert_eq!(model.fullname, "gemini/text-embedding-004");
+        assert_eq!(model.api_name(), "text-embedding-004");
+    }
+
+    #[test]
+    fn test_mistral_parsing() {
+        let model = Model::new("mistral/mistral-embed").unwrap();
+        assert_eq!(model.source, ModelSource::Mistral);
+        assert_eq!(model.fullname, "mistral/mistral-embed");
+        assert_eq!(model.api_name(), "mistral-embed");
+    }
+
+    #[test]
+    fn test_jina_parsing() {
+        let model = Model::new("jina/jina-embeddings-v3").unwrap();
+        assert_eq!(model.source, ModelSource::Jina);
+        assert_eq!(model.fullname, "jina/jina-embeddings-v3");
+        assert_eq!(model.api_name(), "jina-embeddings-v3");
+    }
+
+    #[test]
+    fn test_anthropic_parsing() {
+        let model = Model::new("anthropic/claude-3-5-haiku-latest").unwrap();
+        assert_eq!(model.source, ModelSource::Anthropic);
+        assert_eq!(model.fullname, "anthropic/claude-3-5-haiku-latest");
+        assert_eq!(model.api_name(), "claude-3-5-haiku-latest");
+    }
+
+    #[test]
+    fn test_huggingface_parsing() {
+        let model = Model::new("huggingface/BAAI/bge-small-en-v1.5").unwrap();
+        assert_eq!(model.source, ModelSource::HuggingFace);
+        assert_eq!(model.fullname, "huggingface/BAAI/bge-small-en-v1.5");
+        assert_eq!(model.api_name(), "BAAI/bge-small-en-v1.5");
+        let model = Model::new("huggingface/acme-embedder").unwrap();
+        assert_eq!(model.api_name(), "acme-embedder");
+    }
+
+    #[test]
+    fn test_openai_compatible_parsing() {
+        let model = Model::new("openai-compatible/nomic-ai/nomic-embed-text-v1.5").unwrap();
+        assert_eq!(model.source, ModelSource::OpenAICompatible);
+        assert_eq!(
+            model.fullname,
+            "openai-compatible/nomic-ai/nomic-embed-text-v1.5"
+        );
+        assert_eq!(model.api_name(), "nomic-ai/nomic-embed-text-v1.5");
+        assert_eq!(model.source.to_string(), "openai-compatible");
+    }
+
+    #[test]
+    fn test_registered_model() {
+        let model = Model::registered(
+            "acme-embed",
+            ModelSource::OpenAI,
+            Registration {
+                dimensions: Some(768),
+                endpoint: Some("http://localhost:8000/v1".to_string()),
+                options: serde_json::json!({"model": "acme/embed-v2"}),
+            },
+        );
+        assert_eq!(model.fullname, "acme-embed");
+        assert_eq!(model.api_name(), "acme/embed-v2");
+        assert_eq!(model.registered_dimensions(), Some(768));
+        // the registration is sent to the worker along with the job
+        let model: Model = serde_json::from_value(serde_json::to_value(&model).unwrap()).unwrap();
+        assert_eq!(
+            model.registered_endpoint().as_deref(),
+            Some("http://localhost:8000/v1")
+        );
+        let model = Model::registered(
+            "ft/minilm",
+            ModelSource::SentenceTransformers,
+            Registration::default(),
+        );
+        assert_eq!(model.api_name(), "ft/minilm");
+        assert_eq!(model.registered_dimensions(), None);
+    }
+
+    #[test]
+    fn test_self_hosted_parsing() {
+        let model = Model::new("vllm/meta-llama/Llama-3.1-8B-Instruct").unwrap();
+        assert_eq!(model.source, ModelSource::Vllm);
+        assert_eq!(model.api_name(), "meta-llama/Llama-3.1-8B-Instruct");
+        let model = Model::new("llamacpp/qwen2.5-7b-instruct").unwrap();
+        assert_eq!(model.source, ModelSource::LlamaCpp);
+        assert_eq!(model.api_name(), "qwen2.5-7b-instruct");
+    }
+
     #[test]
     fn test_tembo_parsing() {
         let model = Model::new("tembo/meta-llama/Meta-Llama-3-8B-Instruct").unwrap();
@@ -381,6 +1679,22 @@ mod model_tests {
         assert_eq!(model.name, "text-embedding-ada-002");
     }
 
+    #[test]
+    fn test_deprecated_replacement() {
+        let model = Model::new("text-embedding-ada-002").unwrap();
+        let replacement = model.deprecated_replacement().unwrap();
+        assert_eq!(replacement.fullname, "openai/text-embedding-3-small");
+
+        let model = Model::new("openai/text-embedding-3-small").unwrap();
+        assert!(model.deprecated_replacement().is_none());
+
+        // every replacement must itself be a current model
+        for (_, replacement) in DEPRECATED_MODELS {
+            let model = Model::new(replacement).unwrap();
+            assert!(model.deprecated_replacement().is_none());
+        }
+    }
+
     #[test]
     fn test_private_hf_sentence_transformer() {
         let model = Model::new("chuckhend/private-model").unwrap();
@@ -391,3 +1705,336 @@ mod model_tests {
         assert_eq!(model_string, "chuckhend/private-model");
     }
 }
+
+#[cfg(test)]
+mod index_params_tests {
+    use super::*;
+
+    #[test]
+    fn test_with_clause() {
+        assert_eq!(IndexParams::default().with_clause(), "");
+
+        let params = IndexParams {
+            m: Some(32),
+            ..Default::default()
+        };
+        assert_eq!(params.with_clause(), " WITH (m = 32)");
+
+        let params = IndexParams {
+            m: Some(32),
+            ef_construction: Some(128),
+            ..Default::default()
+        };
+        assert_eq!(
+            params.with_clause(),
+            " WITH (m = 32, ef_construction = 128)"
+        );
+
+        let params = IndexParams {
+            storage_params: BTreeMap::from([("num_neighbors".to_string(), serde_json::json!(50))]),
+            ..Default::default()
+        };
+        assert_eq!(params.with_clause(), " WITH (num_neighbors = 50)");
+
+        let params = IndexParams {
+            num_neighbors: Some(50),
+            storage_layout: Some("memory_optimized".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            params.with_clause(),
+            " WITH (num_neighbors = 50, storage_layout = memory_optimized)"
+        );
+        let params = serde_json::to_value(IndexParams::default()).unwrap();
+        assert!(params.get("storage_params").is_none());
+    }
+
+    #[test]
+    fn test_distance_operator() {
+        assert_eq!(IndexDist::pgv_hnsw_l2.distance_operator(), "<->");
+        assert_eq!(IndexDist::pgv_ivfflat_ip.distance_operator(), "<#>");
+        assert_eq!(IndexDist::vsc_diskann_cosine.distance_operator(), "<=>");
+        assert_eq!(IndexDist::vsc_diskann_ip.distance_operator(), "<#>");
+        assert!(IndexDist::vsc_diskann_l2.is_diskann());
+        assert!(!IndexDist::pgv_hnsw_l2.is_diskann());
+    }
+
+    #[test]
+    fn test_distance_metric() {
+        assert_eq!(IndexDist::pgv_ivfflat_l2.metric(), DistanceMetric::l2);
+        assert_eq!("ip".parse::<DistanceMetric>(), Ok(DistanceMetric::ip));
+        assert!("euclidean".parse::<DistanceMetric>().is_err());
+        let distance = "e <=> q";
+        assert_eq!(DistanceMetric::cosine.similarity(distance), "1 - (e <=> q)");
+        assert_eq!(
+            DistanceMetric::l2.similarity("e <-> q"),
+            "1 / (1 + (e <-> q))"
+        );
+        assert_eq!(DistanceMetric::ip.similarity("e <#> q"), "-(e <#> q)");
+    }
+
+    #[test]
+    fn test_ivfflat_lists() {
+        assert_eq!(ivfflat_lists(0), 1);
+        assert_eq!(ivfflat_lists(500), 1);
+        assert_eq!(ivfflat_lists(250_000), 250);
+        assert_eq!(ivfflat_lists(1_000_000), 1000);
+        assert_eq!(ivfflat_lists(50_000_000), 7071);
+
+        let params = IndexParams {
+            lists: Some(250),
+            ..Default::default()
+        };
+        assert_eq!(params.with_clause(), " WITH (lists = 250)");
+    }
+
+    #[test]
+    fn test_split_weighted_input() {
+        let params = JobParams {
+            columns: vec!["title".to_string(), "body".to_string(), "tags".to_string()],
+            column_weights: BTreeMap::from([("title".to_string(), 2.0)]),
+            ..Default::default()
+        };
+        assert_eq!(
+            params.weighted_input_text(&["title".to_string(), "body".to_string()]),
+            "jsonb_build_array(title, body)::text"
+        );
+        let columns = params
+            .split_weighted_input(r#"["Wireless mouse", " a quiet mouse ", null]"#)
+            .unwrap();
+        assert_eq!(
+            columns,
+            vec![
+                (2.0, "Wireless mouse".to_string()),
+                (1.0, "a quiet mouse".to_string())
+            ]
+        );
+        assert!(params.split_weighted_input("not json").is_err());
+        assert!(params
+            .split_weighted_input(r#"["Wireless mouse", "a quiet mouse"]"#)
+            .is_err());
+    }
+
+    #[test]
+    fn test_job_params_without_index_params() {
+        // jobs created before index parameters existed must still deserialize
+        let params: JobParams = serde_json::from_value(serde_json::json!({
+            "schema": "public",
+            "table": "products",
+            "columns": ["description"],
+            "update_time_col": null,
+            "table_method": "join",
+            "primary_key": "product_id",
+            "pkey_type": "integer",
+            "args": null
+        }))
+        .unwrap();
+        assert!(params.index_params.is_empty());
+        assert!(params.decryption.is_none());
+        assert!(!params.is_weighted());
+        assert_eq!(params.embeddings_schema(), "vectorize");
+    }
+
+    #[test]
+    fn test_templated_input_text() {
+        let mut params = JobParams::default();
+        assert_eq!(params.templated_input_text("t0"), None);
+        params.input_template = Some("format('Q: %s A: %s', question, answer)".to_string());
+        assert_eq!(
+            params.templated_input_text("t0").unwrap(),
+            "(SELECT (format('Q: %s A: %s', question, answer))::text FROM (SELECT t0.*) input_row)"
+        );
+    }
+
+    #[test]
+    fn test_embeddings_schema() {
+        let params = JobParams {
+            schema: "public".to_string(),
+            table_method: TableMethod::join,
+            dest_schema: Some("vectorize_data".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(params.embeddings_schema(), "vectorize_data");
+        let params = JobParams {
+            table_method: TableMethod::append,
+            ..params
+        };
+        assert_eq!(params.embeddings_schema(), "public");
+    }
+
+    #[test]
+    fn test_chunk_provenance() {
+        let provenance: ChunkProvenance = serde_json::from_value(serde_json::json!({
+            "document_id": "doc_id",
+            "chunk_index": "chunk_ix",
+            "start_offset": "char_start"
+        }))
+        .unwrap();
+        assert_eq!(
+            provenance.columns(),
+            vec!["doc_id", "chunk_ix", "char_start"]
+        );
+        let row =
+            serde_json::json!({"doc_id": 7, "chunk_ix": 2, "char_start": 1024, "body": "..."});
+        assert_eq!(
+            provenance.of(&row),
+            serde_json::json!({"document_id": 7, "chunk_index": 2, "start_offset": 1024})
+        );
+        // misspelled fields must not be silently ignored
+        let res: Result<ChunkProvenance, _> =
+            serde_json::from_value(serde_json::json!({"document_id": "doc_id", "offset": "start"}));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_primary_key() {
+        let pkey = PrimaryKey::new("product_id", "integer");
+        assert!(!pkey.is_composite());
+        assert_eq!(pkey.record_id(Some("t0")), "t0.product_id::text");
+        assert_eq!(pkey.join_on("t0", "t1"), "t0.product_id = t1.product_id");
+        assert_eq!(
+            pkey.matches_record_id(None, "$2"),
+            "product_id = $2::integer"
+        );
+        assert_eq!(
+            pkey.matches_record_ids(None, "$1"),
+            "product_id = ANY($1::integer[])"
+        );
+
+        let pkey = PrimaryKey::new("tenant_id, price", "uuid, numeric(10, 2)");
+        assert!(pkey.is_composite());
+        assert_eq!(
+            pkey.column_defs(),
+            vec!["tenant_id uuid", "price numeric(10, 2)"]
+        );
+        assert_eq!(
+            pkey.record_id(None),
+            "jsonb_build_array(tenant_id, price)::text"
+        );
+        assert_eq!(
+            pkey.join_on("t0", "t1"),
+            "t0.tenant_id = t1.tenant_id AND t0.price = t1.price"
+        );
+        assert_eq!(
+            pkey.matches_record_id(Some("t"), "$2"),
+            "t.tenant_id = ($2::jsonb->>0)::uuid AND t.price = ($2::jsonb->>1)::numeric(10, 2)"
+        );
+        assert_eq!(
+            pkey.matches_record_ids(None, "$1"),
+            "(tenant_id, price) IN (SELECT (r->>0)::uuid, (r->>1)::numeric(10, 2) FROM unnest($1::jsonb[]) AS r)"
+        );
+    }
+
+    #[test]
+    fn test_image_input_text() {
+        let mut params = JobParams {
+            columns: vec!["photo".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(params.templated_input_text("t0"), None);
+        params.content_type = ContentType::image;
+        assert_eq!(
+            params.templated_input_text("t0").unwrap(),
+            "translate(encode(t0.photo, 'base64'), E'\\n', '')"
+        );
+        params.content_type = ContentType::image_url;
+        assert_eq!(params.templated_input_text("n").unwrap(), "n.photo::text");
+        // a template produces the url of the image
+        params.input_template = Some("format('https://cdn.example.com/%s', photo)".to_string());
+        assert_eq!(
+            params.templated_input_text("n").unwrap(),
+            "(SELECT (format('https://cdn.example.com/%s', photo))::text FROM (SELECT n.*) input_row)::text"
+        );
+        assert_eq!(
+            "image_url".parse::<ContentType>(),
+            Ok(ContentType::image_url)
+        );
+        assert!("video".parse::<ContentType>().is_err());
+    }
+
+    #[test]
+    fn test_decryption_input_expression() {
+        let decryption = ColumnDecryption {
+            role: "vectorize_decrypt".to_string(),
+            owner: "app".to_string(),
+            expressions: BTreeMap::from([(
+                "body".to_string(),
+                "pgp_sym_decrypt(body, 'key')".to_string(),
+            )]),
+        };
+        assert_eq!(
+            decryption.input_expression("body"),
+            "pgp_sym_decrypt(body, 'key')"
+        );
+        assert_eq!(decryption.input_expression("title"), "title");
+    }
+
+    #[test]
+    fn test_source_kind() {
+        assert_eq!(SourceKind::from_relkind("p"), Some(SourceKind::table));
+        assert_eq!(
+            SourceKind::from_relkind("m"),
+            Some(SourceKind::materialized_view)
+        );
+        assert_eq!(SourceKind::from_relkind("S"), None);
+        // jobs on tables are stored without a source kind
+        let params = serde_json::to_value(JobParams::default()).unwrap();
+        assert!(params.get("source_kind").is_none());
+        let params = JobParams {
+            source_kind: SourceKind::view,
+            ..Default::default()
+        };
+        let params = serde_json::to_value(params).unwrap();
+        assert_eq!(params["source_kind"], "view");
+        let params: JobParams = serde_json::from_value(params).unwrap();
+        assert_eq!(params.source_kind, SourceKind::view);
+    }
+
+    #[test]
+    fn test_vector_storage() {
+        assert_eq!(VectorStorage::from_str("int8"), Ok(VectorStorage::int8));
+        assert!(VectorStorage::from_str("binary").is_err());
+        assert_eq!(VectorStorage::halfvec.column_type(384), "halfvec(384)");
+        assert_eq!(VectorStorage::vector.store("$1"), "$1::vector");
+        assert_eq!(
+            VectorStorage::int8.store("$1"),
+            "vectorize._quantize_int8($1::vector::real[])"
+        );
+        assert_eq!(
+            VectorStorage::halfvec.searchable("embeddings", 384),
+            "embeddings"
+        );
+        assert_eq!(
+            VectorStorage::int8.searchable("embeddings", 384),
+            "vectorize._dequantize_int8(embeddings)::halfvec(384)"
+        );
+
+        // table parameters are stored alongside the column settings
+        let params: StorageParams = serde_json::from_value(serde_json::json!({
+            "compression": "lz4",
+            "fillfactor": 90,
+            "autovacuum_enabled": false
+        }))
+        .unwrap();
+        assert_eq!(params.compression, Some("lz4".to_string()));
+        assert_eq!(
+            params.with_clause(),
+            " WITH (autovacuum_enabled = false, fillfactor = 90)"
+        );
+        assert_eq!(StorageParams::default().with_clause(), "");
+        assert_eq!(params.tablespace_clause(), "");
+
+        // the index is in the table's tablespace unless it has one of its own
+        let mut params = StorageParams {
+            tablespace: Some("bulk".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(params.tablespace_clause(), " TABLESPACE bulk");
+        assert_eq!(params.index_tablespace_clause(), " TABLESPACE bulk");
+        params.index_tablespace = Some("fast".to_string());
+        assert_eq!(params.index_tablespace_clause(), " TABLESPACE fast");
+        let job_params = serde_json::to_value(JobParams::default()).unwrap();
+        assert!(job_params.get("vector_storage").is_none());
+        assert!(job_params.get("storage_params").is_none());
+    }
+}
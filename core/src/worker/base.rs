@@ -1,11 +1,17 @@
-use crate::transformers::{http_handler, providers};
-use crate::types::{JobMessage, JobParams};
+use crate::preprocess;
+use crate::transformers::http_handler::{with_headers, with_timeouts, Headers, Timeouts};
+use crate::transformers::providers::openai_compatible::{LLAMACPP_BASE_URL, VLLM_BASE_URL};
+use crate::transformers::providers::{self, batch, EmbeddingProvider};
+use crate::transformers::retry::RetryPolicy;
+use crate::types::{JobMessage, JobParams, Model, ModelSource};
 use crate::worker::ops;
+use crate::worker::rate_limit::{self, RateLimits};
 use anyhow::Result;
-use log::error;
+use log::{error, info};
 use pgmq::{Message, PGMQueueExt};
 use sqlx::{Pool, Postgres};
 use std::env;
+use std::time::Duration;
 
 use crate::types::VectorizeMeta;
 
@@ -26,8 +32,53 @@ pub async fn poll_job(
 
     let read_ct: i32 = msg.read_ct;
     let msg_id: i64 = msg.msg_id;
+    let job_name = &msg.message.job_name;
+    let source = &msg.message.job_meta.transformer.source;
+    if ops::is_job_paused(conn, job_name).await?
+        || ops::is_over_budget(conn, job_name, source).await?
+        || ops::is_provider_down(conn, source).await?
+    {
+        // re-send a fresh copy so that time spent paused does not count against the retries
+        // over budget jobs are held back until the budget window resets or the cap is raised
+        // and jobs of a provider that failed its health check until it is checked again, or a minute has passed
+        queue
+            .send_delay(&config.queue_name, &msg.message, ops::PAUSED_JOB_DELAY)
+            .await?;
+        queue.delete(&config.queue_name, msg_id).await?;
+        return Ok(Some(()));
+    }
     if read_ct <= config.max_retries {
-        execute_job(conn, msg, config).await?;
+        let limits = RateLimits::of(
+            source,
+            config.requests_per_minute.as_deref(),
+            config.tokens_per_minute.as_deref(),
+        )?;
+        let batch_size = batch::batch_size(source, config.embedding_batch_sizes.as_deref())?;
+        let inputs = &msg.message.inputs;
+        if let Some(delay) = rate_limit::claim(conn, source, &limits, inputs, batch_size).await? {
+            // held back until the provider's minute is over, without counting against the retries
+            queue
+                .send_delay(&config.queue_name, &msg.message, delay)
+                .await?;
+            queue.delete(&config.queue_name, msg_id).await?;
+            return Ok(Some(()));
+        }
+        let job_params: JobParams = serde_json::from_value(msg.message.job_meta.params.clone())?;
+        let timeouts = Timeouts {
+            connect: Duration::from_secs(config.connect_timeout),
+            embedding: Duration::from_secs(config.embedding_request_timeout),
+            ..Default::default()
+        }
+        .for_job(&job_params);
+        let headers = Headers::of(
+            config.http_headers.as_deref(),
+            &msg.message.job_meta.transformer,
+        )?;
+        with_headers(
+            headers,
+            with_timeouts(timeouts, execute_job(conn, msg, config)),
+        )
+        .await?;
     } else {
         error!(
             "message exceeds max retry of {}, archiving msg_id: {}",
@@ -46,10 +97,33 @@ pub struct Config {
     pub embedding_svc_url: String,
     pub openai_api_key: Option<String>,
     pub ollama_svc_url: String,
-    pub embedding_request_timeout: i32,
+    pub azure_openai_svc_url: Option<String>,
+    pub bedrock_svc_url: Option<String>,
+    pub gemini_svc_url: Option<String>,
+    pub vllm_svc_url: String,
+    pub llamacpp_svc_url: String,
+    // json object of model sources to the most texts in one embedding request
+    pub embedding_batch_sizes: Option<String>,
+    // embedding requests of a message that are sent at once
+    pub max_concurrent_requests: usize,
+    // attempts at each embedding request of jobs that do not set their own max_attempts
+    pub max_attempts: u32,
+    // json objects of model sources to the requests and tokens they may be sent per minute
+    pub requests_per_minute: Option<String>,
+    pub tokens_per_minute: Option<String>,
+    // reuse the embeddings of texts the model embedded before, for any job
+    pub embedding_cache: bool,
+    // seconds to wait for embedding requests of jobs without their own request_timeout_sec
+    pub embedding_request_timeout: u64,
+    // seconds to wait for a connection to a provider
+    pub connect_timeout: u64,
     pub poll_interval: u64,
     pub poll_interval_error: u64,
     pub max_retries: i32,
+    // comma separated models that are sent an embedding request when the worker starts
+    pub warmup_models: Option<String>,
+    // json object of the extra headers sent to each provider or model
+    pub http_headers: Option<String>,
 }
 
 impl Config {
@@ -66,9 +140,27 @@ impl Config {
             ),
             openai_api_key: env::var("OPENAI_API_KEY").ok(),
             ollama_svc_url: from_env_default("OLLAMA_SVC_URL", "http://localhost:3001"),
-            embedding_request_timeout: from_env_default("EMBEDDING_REQUEST_TIMEOUT", "6")
+            azure_openai_svc_url: env::var("AZURE_OPENAI_SVC_URL").ok(),
+            // defaults to the Bedrock runtime of AWS_REGION
+            bedrock_svc_url: env::var("BEDROCK_SVC_URL").ok(),
+            // defaults to the Gemini API, or to Vertex AI in the project of a service account
+            gemini_svc_url: env::var("GEMINI_SVC_URL").ok(),
+            vllm_svc_url: from_env_default("VLLM_SVC_URL", VLLM_BASE_URL),
+            llamacpp_svc_url: from_env_default("LLAMACPP_SVC_URL", LLAMACPP_BASE_URL),
+            embedding_batch_sizes: env::var("EMBEDDING_BATCH_SIZES").ok(),
+            max_concurrent_requests: from_env_default("MAX_CONCURRENT_REQUESTS", "4")
+                .parse()
+                .unwrap(),
+            max_attempts: from_env_default("MAX_ATTEMPTS", "3").parse().unwrap(),
+            requests_per_minute: env::var("REQUESTS_PER_MINUTE").ok(),
+            tokens_per_minute: env::var("TOKENS_PER_MINUTE").ok(),
+            embedding_cache: from_env_default("EMBEDDING_CACHE", "false")
+                .parse()
+                .unwrap(),
+            embedding_request_timeout: from_env_default("EMBEDDING_REQUEST_TIMEOUT", "120")
                 .parse()
                 .unwrap(),
+            connect_timeout: from_env_default("CONNECT_TIMEOUT", "10").parse().unwrap(),
             // time to wait between polling for job when there are no messages in queue
             poll_interval: from_env_default("POLL_INTERVAL", "2").parse().unwrap(),
             // time to wait between polling for job when there has been an error in processing
@@ -76,6 +168,8 @@ impl Config {
                 .parse()
                 .unwrap(),
             max_retries: from_env_default("MAX_RETRIES", "2").parse().unwrap(),
+            warmup_models: env::var("WARMUP_MODELS").ok(),
+            http_headers: env::var("HTTP_HEADERS").ok(),
         }
     }
 }
@@ -85,12 +179,63 @@ pub fn from_env_default(key: &str, default: &str) -> String {
     env::var(key).unwrap_or_else(|_| default.to_owned())
 }
 
+// the worker is pointed at its Ollama server and cloud endpoints through its environment
+fn env_service_url(cfg: &Config, source: &ModelSource) -> Option<String> {
+    match source {
+        ModelSource::Ollama => Some(cfg.ollama_svc_url.clone()),
+        ModelSource::AzureOpenAI => cfg.azure_openai_svc_url.clone(),
+        ModelSource::Bedrock => cfg.bedrock_svc_url.clone(),
+        ModelSource::Gemini => cfg.gemini_svc_url.clone(),
+        ModelSource::Vllm => Some(cfg.vllm_svc_url.clone()),
+        ModelSource::LlamaCpp => Some(cfg.llamacpp_svc_url.clone()),
+        _ => None,
+    }
+}
+
+/// sends an embedding request to each model of WARMUP_MODELS, so that locally served models are loaded
+/// before the first message of their jobs arrives
+/// models that fail to warm up are logged, and left to be loaded by their jobs
+pub async fn warm_up_models(cfg: &Config) {
+    for name in providers::warmup_models(cfg.warmup_models.as_deref()) {
+        match warm_up_model(cfg, name).await {
+            Ok(dim) => info!("warmed up {name}, which returns {dim} dimensions"),
+            Err(e) => error!("failed to warm up {name}: {e}"),
+        }
+    }
+}
+
+async fn warm_up_model(cfg: &Config, name: &str) -> Result<u32> {
+    let model = Model::new(name)?;
+    let provider = providers::get_provider(
+        &model.source,
+        None,
+        env_service_url(cfg, &model.source),
+        None,
+    )?;
+    let timeouts = Timeouts {
+        connect: Duration::from_secs(cfg.connect_timeout),
+        embedding: Duration::from_secs(cfg.embedding_request_timeout),
+        ..Default::default()
+    };
+    let headers = Headers::of(cfg.http_headers.as_deref(), &model)?;
+    let dim = with_headers(
+        headers,
+        with_timeouts(
+            timeouts,
+            providers::probe_model_dim(provider.as_ref(), &model.api_name()),
+        ),
+    )
+    .await?;
+    Ok(dim)
+}
+
 /// processes a single job from the queue
 async fn execute_job(
     dbclient: &Pool<Postgres>,
     msg: Message<JobMessage>,
-    _cfg: &Config,
+    cfg: &Config,
 ) -> Result<()> {
+    let trace_id = ops::trace_id(&cfg.queue_name, msg.msg_id);
     let job_meta: VectorizeMeta = msg.message.job_meta;
     let job_params: JobParams = serde_json::from_value(job_meta.params.clone())?;
 
@@ -99,38 +244,132 @@ async fn execute_job(
     } else {
         None
     };
+    // the dedicated Hugging Face endpoint of the job's model, saved when the job was created
+    let endpoint_url = job_params
+        .args
+        .as_ref()
+        .and_then(|args| args.get("endpoint_url"))
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+    // the OpenAI-compatible endpoint of the job's model, saved when the job was created
+    let endpoint = job_params
+        .args
+        .as_ref()
+        .and_then(|args| args.get("endpoint"))
+        .map(|v| v.to_string());
 
+    let service_url = match job_meta.transformer.source {
+        // the endpoint saved with the job already starts from the model's registered one
+        ModelSource::OpenAICompatible => endpoint,
+        _ if job_meta.transformer.registered_endpoint().is_some() => {
+            job_meta.transformer.registered_endpoint()
+        }
+        ModelSource::HuggingFace => endpoint_url,
+        _ => env_service_url(cfg, &job_meta.transformer.source),
+    };
+    // a job's own credential takes the place of the keys saved with it
+    let credential = match &job_params.credential {
+        Some(name) => Some(ops::get_credential(dbclient, name).await?),
+        None => None,
+    };
+    let (api_key, virtual_key) = match credential {
+        Some(credential) if credential.source == job_meta.transformer.source => (
+            credential.api_key.or_else(|| job_params.api_key.clone()),
+            credential.virtual_key.or(virtual_key),
+        ),
+        _ => (job_params.api_key.clone(), virtual_key),
+    };
     let provider = providers::get_provider(
         &job_meta.transformer.source,
-        job_params.api_key.clone(),
-        None,
+        api_key,
+        service_url,
         virtual_key,
     )?;
+    let batch_size = batch::batch_size(
+        &job_meta.transformer.source,
+        cfg.embedding_batch_sizes.as_deref(),
+    )?;
+    let provider = batch::BatchedProvider::new(
+        provider,
+        &job_meta.transformer.source,
+        batch_size,
+        cfg.max_concurrent_requests,
+        RetryPolicy::new(job_params.max_attempts.unwrap_or(cfg.max_attempts)),
+    );
 
-    let embedding_request =
-        providers::prepare_generic_embedding_request(&job_meta.transformer, &msg.message.inputs);
-
-    let embeddings = provider.generate_embedding(&embedding_request).await?;
-
-    let paired_embeddings =
-        http_handler::merge_input_output(msg.message.inputs, embeddings.embeddings);
-    match job_params.clone().table_method {
-        crate::types::TableMethod::append => {
-            ops::update_embeddings(
-                dbclient,
-                &job_params.schema,
-                &job_params.table,
-                &job_meta.clone().name,
-                &job_params.primary_key,
-                &job_params.pkey_type,
-                paired_embeddings,
-            )
-            .await?;
-        }
-        crate::types::TableMethod::join => {
-            ops::upsert_embedding_table(dbclient, &job_meta.name, &job_params, paired_embeddings)
-                .await?
-        }
+    if msg.message.semantic_chunking {
+        return ops::write_semantic_chunks(
+            dbclient,
+            &provider,
+            &job_meta.transformer,
+            &job_meta.name,
+            &job_params,
+            msg.message.inputs,
+        )
+        .await;
+    }
+
+    // jobs with encrypted columns are queued without their input text
+    let inputs = ops::decrypt_inputs(dbclient, &job_params, msg.message.inputs).await?;
+    let inputs = preprocess::preprocess_inputs(&job_params, inputs);
+    if inputs.is_empty() {
+        return Ok(());
     }
+
+    // the chunks of a chunked job whose text is already embedded are not embedded again,
+    // nor with the embedding cache are texts the model embedded for any job
+    let deduped = ops::dedup_chunks(
+        dbclient,
+        &job_meta.name,
+        &job_meta.transformer,
+        &job_params,
+        inputs,
+        cfg.embedding_cache,
+    )
+    .await?;
+    let (inputs, embeddings) = if job_params.is_weighted() {
+        providers::generate_weighted_embeddings(
+            &provider,
+            &job_meta.transformer,
+            &job_params,
+            deduped.to_embed.clone(),
+        )
+        .await?
+    } else if deduped.to_embed.is_empty() {
+        (vec![], vec![])
+    } else {
+        let mut embedding_request =
+            providers::prepare_generic_embedding_request(&job_meta.transformer, &deduped.to_embed)
+                .with_dimensions(&job_meta.transformer, job_params.truncated_dimensions());
+        embedding_request.content_type = job_params.content_type;
+        let embeddings = provider.generate_embedding(&embedding_request).await?;
+        (deduped.to_embed.clone(), embeddings.embeddings)
+    };
+    if cfg.embedding_cache && !job_params.is_weighted() {
+        ops::cache_embeddings(dbclient, &job_meta.transformer, &inputs, &embeddings).await?;
+    }
+    let embeddings = providers::fit_dimensions(embeddings, &job_params)?;
+
+    ops::record_token_usage(dbclient, &job_meta.name, &job_meta.transformer, &inputs).await?;
+    ops::record_chunk_stats(
+        dbclient,
+        &job_meta.name,
+        &job_params,
+        &inputs,
+        deduped.savings(),
+    )
+    .await?;
+    let (inputs, paired_embeddings) = deduped.merge(inputs, embeddings);
+    ops::write_embeddings(
+        dbclient,
+        &job_meta.name,
+        &job_params,
+        &job_meta.transformer,
+        &provider.endpoint(),
+        &trace_id,
+        &inputs,
+        paired_embeddings,
+    )
+    .await?;
     Ok(())
 }
@@ -1,2 +1,3 @@
 pub mod base;
 pub mod ops;
+pub mod rate_limit;
@@ -1,12 +1,44 @@
-use crate::transformers::types::PairedEmbeddings;
+use crate::chunking::{self, Chunk};
+use crate::preprocess;
+use crate::transformers::http_handler;
+use crate::transformers::providers::{self, EmbeddingProvider};
+use crate::transformers::types::{Inputs, PairedEmbeddings};
 use crate::types;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde_json::to_string;
-use sqlx::{Pool, Postgres};
+use sqlx::{PgConnection, Pool, Postgres};
+use std::collections::HashMap;
 use std::fmt::Write;
 
+/// writes a batch of embeddings to the job's table, and records their provenance in the same transaction
+/// so that provenance only ever describes embeddings that were written
+#[allow(clippy::too_many_arguments)]
+pub async fn write_embeddings(
+    pool: &Pool<Postgres>,
+    job_name: &str,
+    job_params: &types::JobParams,
+    model: &types::Model,
+    endpoint: &str,
+    trace_id: &str,
+    inputs: &[Inputs],
+    embeddings: Vec<PairedEmbeddings>,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    match job_params.table_method {
+        types::TableMethod::append => {
+            update_embeddings(&mut tx, job_name, job_params, embeddings).await?
+        }
+        types::TableMethod::join => {
+            upsert_embedding_table(&mut tx, job_name, job_params, embeddings).await?
+        }
+    }
+    record_provenance(&mut tx, job_name, model, endpoint, trace_id, inputs).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
 pub async fn upsert_embedding_table(
-    conn: &Pool<Postgres>,
+    conn: &mut PgConnection,
     project: &str,
     job_params: &types::JobParams,
     embeddings: Vec<PairedEmbeddings>,
@@ -24,19 +56,19 @@ pub async fn upsert_embedding_table(
 
 // returns query and bindings
 // only compatible with pg-vector data types
+// records deleted from the source table while their embeddings were being generated are skipped
 fn build_upsert_query(
     project: &str,
     job_params: &types::JobParams,
     embeddings: Vec<PairedEmbeddings>,
 ) -> (String, Vec<(String, String)>) {
-    let join_key = &job_params.primary_key;
-    let schema = match &job_params.table_method {
-        types::TableMethod::append => job_params.schema.clone(),
-        types::TableMethod::join => "vectorize".to_string(),
-    };
+    let pkey = job_params.pkey();
+    let join_key = pkey.column_names().join(", ");
+    let schema = job_params.embeddings_schema();
     let mut query = format!(
         "
-        INSERT INTO {schema}._embeddings_{project} ({join_key}, embeddings) VALUES",
+        INSERT INTO {schema}._embeddings_{project} ({join_key}, embeddings)
+        SELECT v.* FROM (VALUES",
         schema = schema,
         join_key = join_key,
     );
@@ -47,10 +79,12 @@ fn build_upsert_query(
             query.push(',');
         }
         query.push_str(&format!(
-            " (${}::{}, ${}::vector)",
-            2 * index + 1,
-            job_params.pkey_type,
-            2 * index + 2
+            " ({}, {})",
+            pkey.values_from_record_id(&format!("${}", 2 * index + 1))
+                .join(", "),
+            job_params
+                .vector_storage
+                .store(&format!("${}", 2 * index + 2))
         ));
 
         let embedding =
@@ -58,52 +92,54 @@ fn build_upsert_query(
         bindings.push((pair.primary_key, embedding));
     }
     let upsert = format!(
-        " ON CONFLICT ({join_key})
+        ") AS v ({join_key}, embeddings)
+        WHERE EXISTS (SELECT 1 FROM {src_schema}.{src_table} s WHERE {join_on})
+        ON CONFLICT ({join_key})
         DO UPDATE SET embeddings = EXCLUDED.embeddings, updated_at = NOW();",
-        join_key = join_key
+        join_key = join_key,
+        src_schema = job_params.schema,
+        src_table = job_params.table,
+        join_on = pkey.join_on("s", "v"),
     );
     query.push_str(&upsert);
     (query, bindings)
 }
 
 pub async fn update_embeddings(
-    pool: &Pool<Postgres>,
-    schema: &str,
-    table: &str,
+    conn: &mut PgConnection,
     project: &str,
-    pkey: &str,
-    pkey_type: &str,
+    job_params: &types::JobParams,
     embeddings: Vec<PairedEmbeddings>,
 ) -> anyhow::Result<()> {
     if embeddings.len() > 10 {
-        bulk_update_embeddings(pool, schema, table, project, pkey, pkey_type, embeddings).await
+        bulk_update_embeddings(conn, project, job_params, embeddings).await
     } else {
-        update_append_table(pool, embeddings, schema, table, project, pkey, pkey_type).await
+        update_append_table(conn, embeddings, project, job_params).await
     }
 }
 
 // creates a temporary table, inserts all new values into the temporary table, and then performs an update by join
+// runs in the caller's transaction
 async fn bulk_update_embeddings(
-    pool: &Pool<Postgres>,
-    schema: &str,
-    table: &str,
+    conn: &mut PgConnection,
     project: &str,
-    pkey: &str,
-    pkey_type: &str,
+    job_params: &types::JobParams,
     embeddings: Vec<PairedEmbeddings>,
 ) -> anyhow::Result<()> {
-    let mut tx = pool.begin().await?;
+    let schema = &job_params.schema;
+    let table = &job_params.table;
 
     let tmp_table = format!("temp_embeddings_{project}");
 
+    // rows are matched by record id, which also covers composite keys
     let temp_table_query = format!(
         "CREATE TEMP TABLE IF NOT EXISTS {tmp_table} (
-            pkey {pkey_type} PRIMARY KEY,
+            pkey TEXT PRIMARY KEY,
             embeddings vector
         ) ON COMMIT DROP;", // note, dropping on commit
     );
 
-    sqlx::query(&temp_table_query).execute(&mut *tx).await?;
+    sqlx::query(&temp_table_query).execute(&mut *conn).await?;
 
     // insert all new values into the temporary table
     let mut insert_query = format!("INSERT INTO {tmp_table} (pkey, embeddings) VALUES ");
@@ -121,9 +157,8 @@ async fn bulk_update_embeddings(
         }
         write!(
             &mut insert_query,
-            "(${}::{}, ${}::vector)",
+            "(${}, ${}::vector)",
             i * 2 + 1,
-            pkey_type,
             i * 2 + 2
         )
         .expect("Failed to write to query string");
@@ -135,31 +170,34 @@ async fn bulk_update_embeddings(
         insert_statement = insert_statement.bind(pkey).bind(embedding);
     }
     // insert to the temp table
-    insert_statement.execute(&mut *tx).await?;
+    insert_statement.execute(&mut *conn).await?;
 
     let update_query = format!(
         "UPDATE {schema}.{table} SET
-            {project}_embeddings = temp.embeddings,
+            {project}_embeddings = {embeddings},
             {project}_updated_at = (NOW())
         FROM {tmp_table} temp
-        WHERE {schema}.{table}.{pkey}::{pkey_type} = temp.pkey::{pkey_type};"
+        WHERE {matches};",
+        embeddings = job_params.vector_storage.store("temp.embeddings"),
+        matches = job_params
+            .pkey()
+            .matches_record_id(Some(&format!("{schema}.{table}")), "temp.pkey"),
     );
 
-    sqlx::query(&update_query).execute(&mut *tx).await?;
-    tx.commit().await?;
-
+    sqlx::query(&update_query).execute(&mut *conn).await?;
     Ok(())
 }
 
 async fn update_append_table(
-    pool: &Pool<Postgres>,
+    conn: &mut PgConnection,
     embeddings: Vec<PairedEmbeddings>,
-    schema: &str,
-    table: &str,
     project: &str,
-    pkey: &str,
-    pkey_type: &str,
+    job_params: &types::JobParams,
 ) -> anyhow::Result<()> {
+    let schema = &job_params.schema;
+    let table = &job_params.table;
+    let matches = job_params.pkey().matches_record_id(None, "$2");
+    let stored = job_params.vector_storage.store("$1");
     for embed in embeddings {
         // Serialize the Vec<f64> to a JSON string
         let embedding = to_string(&embed.embeddings).expect("failed to serialize embedding");
@@ -168,16 +206,16 @@ async fn update_append_table(
             "
             UPDATE {schema}.{table}
             SET 
-                {project}_embeddings = $1::vector,
+                {project}_embeddings = {stored},
                 {project}_updated_at = (NOW())
-            WHERE {pkey} = $2::{pkey_type}
+            WHERE {matches}
         "
         );
         // Prepare and execute the update statement for this pair within the transaction
         sqlx::query(&update_query)
             .bind(embedding)
             .bind(embed.primary_key)
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
     Ok(())
@@ -188,3 +226,825 @@ pub async fn init_extension(pool: &Pool<Postgres>) -> anyhow::Result<()> {
     sqlx::query(query).execute(pool).await?;
     Ok(())
 }
+
+// seconds to hold back a message for a paused or over budget job, or a down provider, before checking again
+pub const PAUSED_JOB_DELAY: u32 = 60;
+
+/// true when the job has been paused with vectorize.pause()
+/// jobs that no longer exist are reported as not paused
+pub async fn is_job_paused(pool: &Pool<Postgres>, job_name: &str) -> anyhow::Result<bool> {
+    let paused: Option<bool> =
+        sqlx::query_scalar("SELECT paused FROM vectorize.job WHERE name = $1")
+            .bind(job_name)
+            .fetch_optional(pool)
+            .await?;
+    Ok(paused.unwrap_or(false))
+}
+
+// the source, api key and virtual key of a credential ($1)
+pub const CREDENTIAL_QUERY: &str =
+    "SELECT source, api_key, virtual_key FROM vectorize.credentials WHERE name = $1";
+
+/// the credential of a name in vectorize.credentials
+pub async fn get_credential(
+    pool: &Pool<Postgres>,
+    name: &str,
+) -> anyhow::Result<types::Credential> {
+    let credential: Option<(String, Option<String>, Option<String>)> =
+        sqlx::query_as(CREDENTIAL_QUERY)
+            .bind(name)
+            .fetch_optional(pool)
+            .await?;
+    match credential {
+        Some((source, api_key, virtual_key)) => Ok(types::Credential {
+            source: source.parse().map_err(|e: String| anyhow::anyhow!(e))?,
+            api_key,
+            virtual_key,
+        }),
+        None => bail!("credential {name} does not exist"),
+    }
+}
+
+// true when the job ($1), or the provider it uses ($2), has used up its token budget for the current window
+pub const OVER_BUDGET_QUERY: &str = "
+    SELECT EXISTS (
+        SELECT 1 FROM vectorize.budget
+        WHERE ((scope = 'job' AND name = $1) OR (scope = 'provider' AND name = $2))
+        AND now() < window_start + period
+        AND tokens_used >= token_limit
+    )";
+
+// adds $3 tokens to the budgets of the job ($1) and the provider it uses ($2)
+// a budget whose window has expired starts a new window
+pub const RECORD_TOKEN_USAGE_QUERY: &str = "
+    UPDATE vectorize.budget SET
+        tokens_used = CASE WHEN now() >= window_start + period THEN $3 ELSE tokens_used + $3 END,
+        window_start = CASE WHEN now() >= window_start + period THEN now() ELSE window_start END
+    WHERE (scope = 'job' AND name = $1) OR (scope = 'provider' AND name = $2)";
+
+pub async fn is_over_budget(
+    pool: &Pool<Postgres>,
+    job_name: &str,
+    source: &types::ModelSource,
+) -> anyhow::Result<bool> {
+    let over_budget: bool = sqlx::query_scalar(OVER_BUDGET_QUERY)
+        .bind(job_name)
+        .bind(source.to_string())
+        .fetch_one(pool)
+        .await?;
+    Ok(over_budget)
+}
+
+// true when the last check of the provider ($1) with vectorize.check_provider() failed, less than a minute ago
+pub const PROVIDER_DOWN_QUERY: &str = "
+    SELECT EXISTS (
+        SELECT 1 FROM vectorize.provider_health
+        WHERE provider = $1 AND NOT reachable AND checked_at > now() - interval '1 minute'
+    )";
+
+/// true when the provider was found unreachable by its last health check, within the last minute
+/// providers that have not been checked are reported as up
+pub async fn is_provider_down(
+    pool: &Pool<Postgres>,
+    source: &types::ModelSource,
+) -> anyhow::Result<bool> {
+    let down: bool = sqlx::query_scalar(PROVIDER_DOWN_QUERY)
+        .bind(source.to_string())
+        .fetch_one(pool)
+        .await?;
+    Ok(down)
+}
+
+// adds a call of $4 tokens in and $5 tokens out to the day's usage of the job ($1) with the model ($3) of
+// the provider ($2), at the model's price in vectorize.model_prices
+// the cost of models without a price is left NULL
+pub const RECORD_USAGE_QUERY: &str = "
+    INSERT INTO vectorize.usage AS u (job_name, day, provider, model, calls, tokens_in, tokens_out, cost)
+    VALUES ($1, current_date, $2, $3, 1, $4::bigint, $5::bigint, (
+        SELECT ($4::bigint * input_price + $5::bigint * output_price) / 1000000
+        FROM vectorize.model_prices WHERE model = $3
+    ))
+    ON CONFLICT (job_name, day, model) DO UPDATE SET
+        calls = u.calls + 1,
+        tokens_in = u.tokens_in + EXCLUDED.tokens_in,
+        tokens_out = u.tokens_out + EXCLUDED.tokens_out,
+        cost = coalesce(u.cost + EXCLUDED.cost, u.cost, EXCLUDED.cost)";
+
+/// charges the texts embedded for a job against its and its provider's budgets, and records them in the job's usage
+pub async fn record_token_usage(
+    pool: &Pool<Postgres>,
+    job_name: &str,
+    model: &types::Model,
+    inputs: &[Inputs],
+) -> anyhow::Result<()> {
+    let tokens: i64 = inputs.iter().map(|i| i.token_estimate as i64).sum();
+    sqlx::query(RECORD_TOKEN_USAGE_QUERY)
+        .bind(job_name)
+        .bind(model.source.to_string())
+        .bind(tokens)
+        .execute(pool)
+        .await?;
+    sqlx::query(RECORD_USAGE_QUERY)
+        .bind(job_name)
+        .bind(model.source.to_string())
+        .bind(model.to_string())
+        .bind(tokens)
+        .bind(0_i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// records where the embeddings of a batch came from, replacing what was recorded for earlier embeddings
+// $1 job name, $2 model, $3 provider, $4 endpoint, $5 trace id, $6 and $7 the record ids and their input text
+// the input text is kept only as its sha256 hash
+pub const RECORD_PROVENANCE_QUERY: &str = "
+    INSERT INTO vectorize.embedding_provenance
+        (job_name, record_id, model, provider, endpoint, content_hash, trace_id, vectorize_version)
+    SELECT $1, r.record_id, $2, $3, $4, encode(sha256(convert_to(r.input, 'UTF8')), 'hex'), $5,
+        (SELECT extversion FROM pg_extension WHERE extname = 'vectorize')
+    FROM unnest($6::text[], $7::text[]) AS r (record_id, input)
+    ON CONFLICT (job_name, record_id) DO UPDATE SET
+        model = EXCLUDED.model,
+        provider = EXCLUDED.provider,
+        endpoint = EXCLUDED.endpoint,
+        content_hash = EXCLUDED.content_hash,
+        trace_id = EXCLUDED.trace_id,
+        vectorize_version = EXCLUDED.vectorize_version,
+        embedded_at = NOW()";
+
+/// records the model, provider endpoint, input hash, and trace id of a batch of embeddings
+/// the trace id identifies the queue message that the batch was embedded from
+pub async fn record_provenance(
+    conn: &mut PgConnection,
+    job_name: &str,
+    model: &types::Model,
+    endpoint: &str,
+    trace_id: &str,
+    inputs: &[Inputs],
+) -> anyhow::Result<()> {
+    let (record_ids, texts): (Vec<&str>, Vec<&str>) = inputs
+        .iter()
+        .map(|i| (i.record_id.as_str(), i.inputs.as_str()))
+        .unzip();
+    sqlx::query(RECORD_PROVENANCE_QUERY)
+        .bind(job_name)
+        .bind(model.to_string())
+        .bind(model.source.to_string())
+        .bind(endpoint)
+        .bind(trace_id)
+        .bind(record_ids)
+        .bind(texts)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// the chunks of a batch that are left to embed, along with the chunks whose text is already embedded
+/// only the first chunk of each text is embedded, the others are given its embeddings
+#[derive(Debug, Default)]
+pub struct DedupedChunks {
+    pub to_embed: Vec<Inputs>,
+    // chunks whose text the job's model embedded before, and those embeddings
+    reused: Vec<(Inputs, Vec<f64>)>,
+    // chunks whose text is that of a chunk to embed, and its position in to_embed
+    duplicates: Vec<(Inputs, usize)>,
+}
+
+impl DedupedChunks {
+    /// the chunks that are given the embeddings of another chunk, and the tokens that are not embedded for them
+    pub fn savings(&self) -> (i64, i64) {
+        let saved = self
+            .reused
+            .iter()
+            .map(|(input, _)| input)
+            .chain(self.duplicates.iter().map(|(input, _)| input));
+        let (count, tokens) = saved.fold((0, 0), |(count, tokens), input| {
+            (count + 1, tokens + input.token_estimate as i64)
+        });
+        (count, tokens)
+    }
+
+    /// every chunk of the batch and its embeddings, given the chunks that were embedded and their embeddings
+    pub fn merge(
+        self,
+        embedded: Vec<Inputs>,
+        embeddings: Vec<Vec<f64>>,
+    ) -> (Vec<Inputs>, Vec<PairedEmbeddings>) {
+        let mut paired = http_handler::merge_input_output(embedded.clone(), embeddings);
+        let by_record: HashMap<String, Vec<f64>> = paired
+            .iter()
+            .map(|p| (p.primary_key.clone(), p.embeddings.clone()))
+            .collect();
+        let mut inputs = embedded;
+        for (input, embeddings) in self.reused {
+            paired.push(PairedEmbeddings {
+                primary_key: input.record_id.clone(),
+                embeddings,
+            });
+            inputs.push(input);
+        }
+        for (input, position) in self.duplicates {
+            // a chunk that could not be embedded leaves its duplicates without embeddings
+            let Some(embeddings) = by_record.get(&self.to_embed[position].record_id) else {
+                continue;
+            };
+            paired.push(PairedEmbeddings {
+                primary_key: input.record_id.clone(),
+                embeddings: embeddings.clone(),
+            });
+            inputs.push(input);
+        }
+        (inputs, paired)
+    }
+}
+
+/// splits the chunks of a chunked job into those to embed, and those whose text is already embedded,
+/// by another of the job's chunks with the same content hash that was embedded by the same model,
+/// or by another chunk of the batch
+/// with the embedding cache, inputs whose text the model embedded before, for any job, are given the
+/// cached embeddings, and inputs repeating the text of another input of the batch are embedded once
+/// the inputs of other jobs are all embedded
+pub async fn dedup_chunks(
+    pool: &Pool<Postgres>,
+    job_name: &str,
+    model: &types::Model,
+    job_params: &types::JobParams,
+    inputs: Vec<Inputs>,
+    cache: bool,
+) -> Result<DedupedChunks> {
+    // weighted jobs embed each column on its own, which the cache does not hold
+    let cache = cache && !job_params.is_weighted();
+    if job_params.chunking.is_none() && !cache {
+        return Ok(DedupedChunks {
+            to_embed: inputs,
+            ..Default::default()
+        });
+    }
+    let mut embedded = if job_params.chunking.is_some() {
+        embedded_chunks(pool, job_name, model, job_params, &inputs).await?
+    } else {
+        HashMap::new()
+    };
+    let cached = if cache {
+        cached_embeddings(pool, model, job_params, &inputs).await?
+    } else {
+        HashMap::new()
+    };
+
+    let mut deduped = DedupedChunks::default();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for input in inputs {
+        if let Some(embeddings) = embedded.remove(&input.record_id) {
+            deduped.reused.push((input, embeddings));
+        } else if let Some(embeddings) = cached.get(&input.inputs) {
+            deduped.reused.push((input, embeddings.clone()));
+        } else if let Some(position) = positions.get(&input.inputs) {
+            deduped.duplicates.push((input, *position));
+        } else {
+            positions.insert(input.inputs.clone(), deduped.to_embed.len());
+            deduped.to_embed.push(input);
+        }
+    }
+    Ok(deduped)
+}
+
+// the embeddings of the chunks whose text another of the job's chunks was embedded from by the same model
+async fn embedded_chunks(
+    pool: &Pool<Postgres>,
+    job_name: &str,
+    model: &types::Model,
+    job_params: &types::JobParams,
+    inputs: &[Inputs],
+) -> Result<HashMap<String, Vec<f64>>> {
+    let (emb_schema, emb_table, emb_col) = job_params.embeddings_location(job_name);
+    let record_ids: Vec<&str> = inputs.iter().map(|i| i.record_id.as_str()).collect();
+    let embedded: Vec<(String, Vec<f64>)> = sqlx::query_as(&format!(
+        "SELECT c.chunk_id::text, r.embeddings FROM {schema}.{table} c
+        CROSS JOIN LATERAL (
+            SELECT {searchable}::real[]::float8[] AS embeddings
+            FROM {schema}.{table} d
+            INNER JOIN {emb_schema}.{emb_table} e ON e.chunk_id = d.chunk_id
+            INNER JOIN vectorize.embedding_provenance p
+                ON p.job_name = $2 AND p.record_id = d.chunk_id::text AND p.model = $3
+            WHERE d.content_hash = c.content_hash AND d.chunk_id <> c.chunk_id AND e.{emb_col} IS NOT NULL
+            LIMIT 1
+        ) r
+        WHERE c.chunk_id::text = ANY($1)",
+        schema = job_params.schema,
+        table = job_params.table,
+        searchable = job_params.searchable_embeddings(&format!("e.{emb_col}")),
+    ))
+    .bind(&record_ids)
+    .bind(job_name)
+    .bind(model.to_string())
+    .fetch_all(pool)
+    .await?;
+    Ok(embedded.into_iter().collect())
+}
+
+// the cached embeddings of the model ($1) for texts ($2), looked up by the sha256 hash of the text
+pub const CACHED_EMBEDDINGS_QUERY: &str = "
+    SELECT t.input, c.embeddings
+    FROM unnest($2::text[]) AS t (input)
+    INNER JOIN vectorize.embedding_cache c
+        ON c.model = $1 AND c.content_hash = encode(sha256(convert_to(t.input, 'UTF8')), 'hex')";
+
+// the cached embeddings of the inputs' texts, fitted to the job's dimensions
+// embeddings cached for a job that asked the model for fewer dimensions are too short to be fitted, and are left out
+async fn cached_embeddings(
+    pool: &Pool<Postgres>,
+    model: &types::Model,
+    job_params: &types::JobParams,
+    inputs: &[Inputs],
+) -> Result<HashMap<String, Vec<f64>>> {
+    let texts: Vec<&str> = inputs.iter().map(|i| i.inputs.as_str()).collect();
+    let cached: Vec<(String, Vec<f64>)> = sqlx::query_as(CACHED_EMBEDDINGS_QUERY)
+        .bind(model.to_string())
+        .bind(&texts)
+        .fetch_all(pool)
+        .await?;
+    let (texts, embeddings): (Vec<String>, Vec<Vec<f64>>) = cached
+        .into_iter()
+        .filter(|(_, e)| e.len() >= job_params.dimensions.unwrap_or_default() as usize)
+        .unzip();
+    let embeddings = providers::fit_dimensions(embeddings, job_params)?;
+    Ok(texts.into_iter().zip(embeddings).collect())
+}
+
+// caches the embeddings of the model ($1) for texts ($2), given as float8[] literals ($3)
+// texts that are already cached keep their embeddings
+pub const CACHE_EMBEDDINGS_QUERY: &str = "
+    INSERT INTO vectorize.embedding_cache (model, content_hash, embeddings)
+    SELECT $1, encode(sha256(convert_to(e.input, 'UTF8')), 'hex'), e.embeddings::float8[]
+    FROM unnest($2::text[], $3::text[]) AS e (input, embeddings)
+    ON CONFLICT (model, content_hash) DO NOTHING";
+
+/// adds embeddings of the model to the embedding cache, as the provider returned them
+pub async fn cache_embeddings(
+    pool: &Pool<Postgres>,
+    model: &types::Model,
+    inputs: &[Inputs],
+    embeddings: &[Vec<f64>],
+) -> anyhow::Result<()> {
+    if inputs.is_empty() {
+        return Ok(());
+    }
+    let texts: Vec<&str> = inputs.iter().map(|i| i.inputs.as_str()).collect();
+    let embeddings: Vec<String> = embeddings.iter().map(|e| array_literal(e)).collect();
+    sqlx::query(CACHE_EMBEDDINGS_QUERY)
+        .bind(model.to_string())
+        .bind(texts)
+        .bind(embeddings)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// an embedding as a Postgres array literal, e.g. {0.5,0.25}
+fn array_literal(embedding: &[f64]) -> String {
+    let values: Vec<String> = embedding.iter().map(|v| v.to_string()).collect();
+    format!("{{{}}}", values.join(","))
+}
+
+// adds to a job's counts of embedded and deduplicated chunks, and of the tokens deduplication saved
+pub const RECORD_CHUNK_STATS_QUERY: &str = "
+    INSERT INTO vectorize.job_stats (job_name, embedded_chunks, deduplicated_chunks, tokens_saved)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (job_name) DO UPDATE SET
+        embedded_chunks = job_stats.embedded_chunks + EXCLUDED.embedded_chunks,
+        deduplicated_chunks = job_stats.deduplicated_chunks + EXCLUDED.deduplicated_chunks,
+        tokens_saved = job_stats.tokens_saved + EXCLUDED.tokens_saved,
+        updated_at = NOW()";
+
+/// records how many of a chunked job's chunks were embedded, and how many were given the embeddings of another chunk
+pub async fn record_chunk_stats(
+    pool: &Pool<Postgres>,
+    job_name: &str,
+    job_params: &types::JobParams,
+    embedded: &[Inputs],
+    (deduplicated, tokens_saved): (i64, i64),
+) -> anyhow::Result<()> {
+    if job_params.chunking.is_none() {
+        return Ok(());
+    }
+    sqlx::query(RECORD_CHUNK_STATS_QUERY)
+        .bind(job_name)
+        .bind(embedded.len() as i64)
+        .bind(deduplicated)
+        .bind(tokens_saved)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// the trace id of the embeddings generated from a queue message
+pub fn trace_id(queue_name: &str, msg_id: i64) -> String {
+    format!("{queue_name}:{msg_id}")
+}
+
+// query returning the decrypted input text for a set of primary keys, which are bound as a text array in $1
+fn decrypted_inputs_query(
+    job_params: &types::JobParams,
+    decryption: &types::ColumnDecryption,
+) -> String {
+    let column_expressions = job_params
+        .columns
+        .iter()
+        .map(|c| decryption.input_expression(c))
+        .collect::<Vec<_>>();
+    let input_text = if job_params.is_weighted() {
+        job_params.weighted_input_text(&column_expressions)
+    } else {
+        column_expressions.join(" || ', ' || ")
+    };
+    let pkey = job_params.pkey();
+    format!(
+        "SELECT {record_id} AS record_id, {input_text} AS input_text
+        FROM {schema}.{table}
+        WHERE {matches}",
+        record_id = pkey.record_id(None),
+        schema = job_params.schema,
+        table = job_params.table,
+        matches = pkey.matches_record_ids(None, "$1"),
+    )
+}
+
+/// fills in the input text for jobs with encrypted columns
+/// the decryption expressions run as the job's decryption role, inside a transaction that is never committed,
+/// and only while the job's owner is still a member of that role
+/// records that no longer exist are dropped
+pub async fn decrypt_inputs(
+    pool: &Pool<Postgres>,
+    job_params: &types::JobParams,
+    inputs: Vec<Inputs>,
+) -> Result<Vec<Inputs>> {
+    let decryption = match &job_params.decryption {
+        Some(d) => d,
+        None => return Ok(inputs),
+    };
+    let record_ids: Vec<String> = inputs.iter().map(|i| i.record_id.clone()).collect();
+    if decryption.owner.is_empty() {
+        bail!("job has no owner recorded for its decryption role, it must be created again");
+    }
+    let mut tx = pool.begin().await?;
+    let owner_is_member: bool = sqlx::query_scalar(
+        "SELECT CASE WHEN EXISTS (SELECT 1 FROM pg_roles WHERE rolname = $1)
+        THEN pg_has_role($1, $2, 'MEMBER') ELSE false END",
+    )
+    .bind(&decryption.owner)
+    .bind(&decryption.role)
+    .fetch_one(&mut *tx)
+    .await?;
+    if !owner_is_member {
+        tx.rollback().await?;
+        bail!(
+            "job owner {} is no longer a member of decryption role {}",
+            decryption.owner,
+            decryption.role
+        );
+    }
+    sqlx::query(&format!(
+        "SET LOCAL ROLE \"{}\"",
+        decryption.role.replace('"', "\"\"")
+    ))
+    .execute(&mut *tx)
+    .await?;
+    let rows: Vec<(String, Option<String>)> =
+        sqlx::query_as(&decrypted_inputs_query(job_params, decryption))
+            .bind(&record_ids)
+            .fetch_all(&mut *tx)
+            .await?;
+    tx.rollback().await?;
+
+    let bpe = tiktoken_rs::cl100k_base()?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(record_id, input_text)| {
+            let text = input_text?.trim().to_owned();
+            Some(Inputs {
+                record_id,
+                token_estimate: bpe.encode_with_special_tokens(&text).len() as i32,
+                inputs: text,
+            })
+        })
+        .collect())
+}
+
+/// splits the source rows of a chunked job with the semantic strategy, and writes their chunks to the job's table of chunks
+/// inputs are the json arrays of the text of each row's columns, and the windows of sentences of every row
+/// are embedded in a single request
+/// each row's chunks replace those it had, and are then embedded like any other change to the table of chunks
+pub async fn write_semantic_chunks(
+    pool: &Pool<Postgres>,
+    provider: &dyn EmbeddingProvider,
+    model: &types::Model,
+    job_name: &str,
+    job_params: &types::JobParams,
+    inputs: Vec<Inputs>,
+) -> Result<()> {
+    let Some(chunking) = &job_params.chunking else {
+        bail!("job {job_name} does not chunk its rows");
+    };
+    let bpe = chunking::tokenizer(Some(model))?;
+    // the record id, column, text and sentences of each column of each row, in the order of their windows
+    let mut texts = Vec::new();
+    let mut windows: Vec<Inputs> = Vec::new();
+    for input in &inputs {
+        let columns: Vec<Option<String>> = serde_json::from_str(&input.inputs)?;
+        for (column, text) in chunking.sources().into_iter().zip(columns) {
+            let Some(text) = text else {
+                continue;
+            };
+            let text = match job_params.preprocess {
+                Some(pre) => preprocess::preprocess(&text, pre),
+                None => text,
+            };
+            let (sentences, sentence_windows) = chunking::semantic_windows(&text);
+            windows.extend(sentence_windows.into_iter().map(|window| Inputs {
+                record_id: input.record_id.clone(),
+                token_estimate: bpe.encode_ordinary(&window).len() as i32,
+                inputs: window,
+            }));
+            texts.push((input.record_id.as_str(), column, text, sentences));
+        }
+    }
+    let embeddings = if windows.is_empty() {
+        vec![]
+    } else {
+        let request = providers::prepare_generic_embedding_request(model, &windows);
+        provider.generate_embedding(&request).await?.embeddings
+    };
+    record_token_usage(pool, job_name, model, &windows).await?;
+
+    let threshold = chunking
+        .params
+        .similarity_threshold
+        .unwrap_or(chunking::DEFAULT_SIMILARITY_THRESHOLD);
+    let mut embeddings = embeddings.into_iter();
+    // the chunks of a row are numbered across its columns
+    let mut row_chunks: Vec<(&str, Vec<(&str, Chunk)>)> = inputs
+        .iter()
+        .map(|i| (i.record_id.as_str(), Vec::new()))
+        .collect();
+    for (record_id, column, text, sentences) in &texts {
+        let text_embeddings: Vec<Vec<f64>> = embeddings.by_ref().take(sentences.len()).collect();
+        let chunks = chunking::chunk_semantic(
+            text,
+            sentences,
+            &text_embeddings,
+            threshold,
+            chunking.params.chunk_size as usize,
+            &bpe,
+        )?;
+        if let Some((_, row)) = row_chunks.iter_mut().find(|(id, _)| id == record_id) {
+            row.extend(chunks.into_iter().map(|chunk| (*column, chunk)));
+        }
+    }
+    if let Some(filter) = &chunking.params.filter {
+        filter_chunks(pool, filter, &mut row_chunks).await?;
+    }
+
+    let record_ids: Vec<&str> = inputs.iter().map(|i| i.record_id.as_str()).collect();
+    let mut tx = pool.begin().await?;
+    let rows: Vec<(i64, String, Option<String>, String)> = sqlx::query_as(&format!(
+        "SELECT chunk_id, original_id::text, source_column, chunk FROM {schema}.{table}
+        WHERE original_id::text = ANY($1)",
+        schema = job_params.schema,
+        table = job_params.table,
+    ))
+    .bind(&record_ids)
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut existing: HashMap<String, Vec<(i64, String, String)>> = HashMap::new();
+    for (chunk_id, record_id, column, chunk) in rows {
+        existing
+            .entry(record_id)
+            .or_default()
+            .push((chunk_id, column.unwrap_or_default(), chunk));
+    }
+    // the chunks of every row are written together, with one statement each for the deleted, kept and new chunks
+    let mut deleted: Vec<i64> = Vec::new();
+    let mut kept = ChunkColumns::default();
+    let mut inserted = ChunkColumns::default();
+    for (record_id, chunks) in &row_chunks {
+        let new: Vec<(&str, &str)> = chunks
+            .iter()
+            .map(|(column, chunk)| (*column, chunk.text.as_str()))
+            .collect();
+        let changes =
+            chunking::chunk_changes(existing.get(*record_id).map_or(&[], |e| e.as_slice()), &new);
+        deleted.extend(changes.deleted);
+        for (chunk_id, position) in changes.kept {
+            kept.chunk_ids.push(chunk_id);
+            kept.push(record_id, position, &chunks[position]);
+        }
+        for position in changes.inserted {
+            inserted.push(record_id, position, &chunks[position]);
+        }
+    }
+    if !deleted.is_empty() {
+        sqlx::query(&format!(
+            "DELETE FROM {schema}.{table} WHERE chunk_id = ANY($1)",
+            schema = job_params.schema,
+            table = job_params.table,
+        ))
+        .bind(&deleted)
+        .execute(&mut *tx)
+        .await?;
+    }
+    if !kept.chunk_ids.is_empty() {
+        // a kept chunk keeps its updated_at, so that it is not embedded again
+        sqlx::query(&format!(
+            "UPDATE {schema}.{table} t
+            SET chunk_index = c.chunk_index, char_start = c.char_start, char_end = c.char_end, token_count = c.token_count
+            FROM unnest($1::bigint[], $2::int[], $3::int[], $4::int[], $5::int[])
+                AS c(chunk_id, chunk_index, char_start, char_end, token_count)
+            WHERE t.chunk_id = c.chunk_id",
+            schema = job_params.schema,
+            table = job_params.table,
+        ))
+        .bind(&kept.chunk_ids)
+        .bind(&kept.chunk_indexes)
+        .bind(&kept.char_starts)
+        .bind(&kept.char_ends)
+        .bind(&kept.token_counts)
+        .execute(&mut *tx)
+        .await?;
+    }
+    if !inserted.record_ids.is_empty() {
+        // rows deleted since they were queued have no chunks to write
+        sqlx::query(&format!(
+            "INSERT INTO {schema}.{table} (original_id, chunk_index, source_column, char_start, char_end, token_count, chunk)
+            SELECT c.original_id::{pkey_type}, c.chunk_index, c.source_column, c.char_start, c.char_end, c.token_count, c.chunk
+            FROM unnest($1::text[], $2::int[], $3::text[], $4::int[], $5::int[], $6::int[], $7::text[])
+                AS c(original_id, chunk_index, source_column, char_start, char_end, token_count, chunk)
+            WHERE EXISTS (SELECT 1 FROM {src_schema}.{src_table} s WHERE s.{pkey} = c.original_id::{pkey_type})",
+            schema = job_params.schema,
+            table = job_params.table,
+            pkey_type = chunking.pkey_type,
+            src_schema = chunking.schema,
+            src_table = chunking.table,
+            pkey = chunking.primary_key,
+        ))
+        .bind(&inserted.record_ids)
+        .bind(&inserted.chunk_indexes)
+        .bind(&inserted.columns)
+        .bind(&inserted.char_starts)
+        .bind(&inserted.char_ends)
+        .bind(&inserted.token_counts)
+        .bind(&inserted.texts)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// discards the chunks of each row that the job's chunk filter does not keep, before they are numbered
+// the filter's function is called once for the chunks of every row, as the filter's owner,
+// inside a transaction that is never committed, and only while the owner can still execute it
+async fn filter_chunks(
+    pool: &Pool<Postgres>,
+    filter: &types::ChunkFilter,
+    row_chunks: &mut [(&str, Vec<(&str, Chunk)>)],
+) -> Result<()> {
+    let compiled = chunking::CompiledFilter::new(filter)?;
+    for (_, chunks) in row_chunks.iter_mut() {
+        chunks.retain(|(_, chunk)| !compiled.discards(chunk));
+    }
+    let Some(function) = &filter.function else {
+        return Ok(());
+    };
+    let texts: Vec<&str> = row_chunks
+        .iter()
+        .flat_map(|(_, chunks)| chunks.iter().map(|(_, chunk)| chunk.text.as_str()))
+        .collect();
+    if texts.is_empty() {
+        return Ok(());
+    }
+    if filter.owner.is_empty() {
+        bail!("chunk_filter has no owner recorded for its function, it must be given again with vectorize.rechunk");
+    }
+    let mut tx = pool.begin().await?;
+    let owner_can_execute: bool = sqlx::query_scalar(
+        "SELECT CASE WHEN EXISTS (SELECT 1 FROM pg_roles WHERE rolname = $1)
+        THEN coalesce(has_function_privilege($1, to_regprocedure($2 || '(text)'), 'EXECUTE'), false)
+        ELSE false END",
+    )
+    .bind(&filter.owner)
+    .bind(function)
+    .fetch_one(&mut *tx)
+    .await?;
+    if !owner_can_execute {
+        tx.rollback().await?;
+        bail!(
+            "chunk_filter owner {} can no longer execute function {function}",
+            filter.owner
+        );
+    }
+    sqlx::query(&format!(
+        "SET LOCAL ROLE \"{}\"",
+        filter.owner.replace('"', "\"\"")
+    ))
+    .execute(&mut *tx)
+    .await?;
+    let keep: Vec<Option<bool>> = sqlx::query_scalar(&chunking::filter_function_query(function))
+        .bind(&texts)
+        .fetch_all(&mut *tx)
+        .await?;
+    tx.rollback().await?;
+    let mut keep = keep.into_iter();
+    for (_, chunks) in row_chunks.iter_mut() {
+        chunks.retain(|_| keep.next().flatten().unwrap_or(false));
+    }
+    Ok(())
+}
+
+// the values of a set of chunks, one array per column, to be written with a single statement
+#[derive(Default)]
+struct ChunkColumns {
+    chunk_ids: Vec<i64>,
+    record_ids: Vec<String>,
+    chunk_indexes: Vec<i32>,
+    columns: Vec<String>,
+    char_starts: Vec<i32>,
+    char_ends: Vec<i32>,
+    token_counts: Vec<i32>,
+    texts: Vec<String>,
+}
+
+impl ChunkColumns {
+    fn push(&mut self, record_id: &str, chunk_index: usize, (column, chunk): &(&str, Chunk)) {
+        self.record_ids.push(record_id.to_string());
+        self.chunk_indexes.push(chunk_index as i32);
+        self.columns.push(column.to_string());
+        self.char_starts.push(chunk.char_start as i32);
+        self.char_ends.push(chunk.char_end as i32);
+        self.token_counts.push(chunk.token_count as i32);
+        self.texts.push(chunk.text.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_upsert_query() {
+        let job_params = types::JobParams {
+            schema: "public".to_string(),
+            table: "products".to_string(),
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            ..Default::default()
+        };
+        let embeddings = vec![
+            PairedEmbeddings {
+                primary_key: "1".to_string(),
+                embeddings: vec![0.5, 0.5],
+            },
+            PairedEmbeddings {
+                primary_key: "2".to_string(),
+                embeddings: vec![1.0, 0.0],
+            },
+        ];
+        let (query, bindings) = build_upsert_query("my_job", &job_params, embeddings);
+        assert!(query.contains("INSERT INTO vectorize._embeddings_my_job (product_id, embeddings)"));
+        assert!(query.contains("SELECT v.* FROM (VALUES ($1::integer, $2::vector), ($3::integer, $4::vector)) AS v (product_id, embeddings)"));
+        // rows that were deleted from the source table are not given embeddings
+        assert!(query.contains(
+            "WHERE EXISTS (SELECT 1 FROM public.products s WHERE s.product_id = v.product_id)"
+        ));
+        assert_eq!(bindings[1], ("2".to_string(), "[1.0,0.0]".to_string()));
+    }
+
+    #[test]
+    fn test_array_literal() {
+        assert_eq!(array_literal(&[0.5, -0.25, 1.0]), "{0.5,-0.25,1}");
+        assert_eq!(array_literal(&[]), "{}");
+    }
+
+    #[test]
+    fn test_deduped_chunks() {
+        let input = |record_id: &str, text: &str| Inputs {
+            record_id: record_id.to_string(),
+            inputs: text.to_string(),
+            token_estimate: 5,
+        };
+        let deduped = DedupedChunks {
+            to_embed: vec![input("1", "a"), input("2", "b")],
+            reused: vec![(input("3", "c"), vec![0.0, 1.0])],
+            duplicates: vec![(input("4", "b"), 1)],
+        };
+        assert_eq!(deduped.savings(), (2, 10));
+        let embedded = deduped.to_embed.clone();
+        let (inputs, paired) = deduped.merge(embedded, vec![vec![1.0, 0.0], vec![0.5, 0.5]]);
+        let record_ids: Vec<&str> = inputs.iter().map(|i| i.record_id.as_str()).collect();
+        assert_eq!(record_ids, vec!["1", "2", "3", "4"]);
+        // a duplicate is given the embeddings of the chunk with its text
+        assert_eq!(paired[2].embeddings, vec![0.0, 1.0]);
+        assert_eq!(paired[3].primary_key, "4");
+        assert_eq!(paired[3].embeddings, vec![0.5, 0.5]);
+    }
+}
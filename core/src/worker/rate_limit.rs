@@ -0,0 +1,141 @@
+use anyhow::anyhow;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+
+use crate::errors::VectorizeError;
+use crate::transformers::types::Inputs;
+use crate::types::ModelSource;
+
+/// the limits of a json object of sources to limits, e.g. {"openai": 3000, "cohere": 100}
+pub fn parse_limits(limits: Option<&str>) -> Result<HashMap<String, i64>, VectorizeError> {
+    let Some(limits) = limits.filter(|l| !l.trim().is_empty()) else {
+        return Ok(HashMap::new());
+    };
+    let limits: HashMap<String, i64> = serde_json::from_str(limits)
+        .map_err(|e| anyhow!("rate limits must be a json object of positive integers: {e}"))?;
+    if let Some((source, _)) = limits.iter().find(|(_, limit)| **limit < 1) {
+        Err(anyhow!("rate limit of {source} must be greater than 0"))?;
+    }
+    Ok(limits)
+}
+
+/// the requests and tokens a provider may be sent per minute, by every job using it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateLimits {
+    pub requests_per_minute: Option<i64>,
+    pub tokens_per_minute: Option<i64>,
+}
+
+impl RateLimits {
+    /// the limits of a provider in json objects of sources to requests and to tokens per minute
+    /// providers that are not in them are not limited
+    pub fn of(
+        source: &ModelSource,
+        requests_per_minute: Option<&str>,
+        tokens_per_minute: Option<&str>,
+    ) -> Result<Self, VectorizeError> {
+        let source = source.to_string();
+        Ok(RateLimits {
+            requests_per_minute: parse_limits(requests_per_minute)?.get(&source).copied(),
+            tokens_per_minute: parse_limits(tokens_per_minute)?.get(&source).copied(),
+        })
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.requests_per_minute.is_some() || self.tokens_per_minute.is_some()
+    }
+}
+
+// the requests a message is sent to its provider in, and the tokens of its texts
+// requests that are sent again after failing are not counted
+fn message_usage(inputs: &[Inputs], batch_size: usize) -> (i64, i64) {
+    let requests = inputs.len().div_ceil(batch_size.max(1)) as i64;
+    let tokens = inputs.iter().map(|i| i.token_estimate as i64).sum();
+    (requests, tokens)
+}
+
+// charges $2 requests and $3 tokens to the current minute of the provider ($1), when they fit
+// within its limits of $4 requests and $5 tokens, or when its last minute is over
+// returns NULL when they are charged, and otherwise the seconds until the provider's minute is over
+// a message larger than the limits is charged to a minute of its own
+pub const CLAIM_RATE_LIMIT_QUERY: &str = "
+    WITH claimed AS (
+        INSERT INTO vectorize.rate_limit AS r (provider, window_start, requests, tokens)
+        VALUES ($1, now(), $2, $3)
+        ON CONFLICT (provider) DO UPDATE SET
+            requests = CASE WHEN now() >= r.window_start + interval '1 minute'
+                THEN EXCLUDED.requests ELSE r.requests + EXCLUDED.requests END,
+            tokens = CASE WHEN now() >= r.window_start + interval '1 minute'
+                THEN EXCLUDED.tokens ELSE r.tokens + EXCLUDED.tokens END,
+            window_start = CASE WHEN now() >= r.window_start + interval '1 minute'
+                THEN now() ELSE r.window_start END
+        WHERE now() >= r.window_start + interval '1 minute'
+            OR (($4::bigint IS NULL OR r.requests + EXCLUDED.requests <= $4)
+                AND ($5::bigint IS NULL OR r.tokens + EXCLUDED.tokens <= $5))
+        RETURNING 1
+    )
+    SELECT CASE WHEN EXISTS (SELECT 1 FROM claimed) THEN NULL ELSE (
+        SELECT GREATEST(ceil(extract(epoch FROM window_start + interval '1 minute' - now())), 1)::int
+        FROM vectorize.rate_limit WHERE provider = $1
+    ) END";
+
+/// charges the requests and tokens of a message's inputs to its provider's current minute
+/// returns the seconds to hold the message back for when they would go over the provider's limits
+/// every worker charges the same minute, so the limits hold across workers and databases sharing vectorize.rate_limit
+pub async fn claim(
+    pool: &Pool<Postgres>,
+    source: &ModelSource,
+    limits: &RateLimits,
+    inputs: &[Inputs],
+    batch_size: usize,
+) -> anyhow::Result<Option<u32>> {
+    if !limits.is_limited() {
+        return Ok(None);
+    }
+    let (requests, tokens) = message_usage(inputs, batch_size);
+    let delay: Option<i32> = sqlx::query_scalar(CLAIM_RATE_LIMIT_QUERY)
+        .bind(source.to_string())
+        .bind(requests)
+        .bind(tokens)
+        .bind(limits.requests_per_minute)
+        .bind(limits.tokens_per_minute)
+        .fetch_one(pool)
+        .await?;
+    Ok(delay.map(|d| d as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limits() {
+        let requests = Some(r#"{"openai": 3000, "cohere": 100}"#);
+        let tokens = Some(r#"{"openai": 1000000}"#);
+        let limits = RateLimits::of(&ModelSource::OpenAI, requests, tokens).unwrap();
+        assert_eq!(limits.requests_per_minute, Some(3000));
+        assert_eq!(limits.tokens_per_minute, Some(1000000));
+        let limits = RateLimits::of(&ModelSource::Cohere, requests, tokens).unwrap();
+        assert_eq!(limits.tokens_per_minute, None);
+        assert!(limits.is_limited());
+        assert!(!RateLimits::of(&ModelSource::Voyage, requests, None)
+            .unwrap()
+            .is_limited());
+        assert!(RateLimits::of(&ModelSource::OpenAI, Some(r#"{"openai": 0}"#), None).is_err());
+        assert!(RateLimits::of(&ModelSource::OpenAI, Some("3000"), None).is_err());
+    }
+
+    #[test]
+    fn test_message_usage() {
+        let inputs: Vec<Inputs> = (0..5)
+            .map(|i| Inputs {
+                record_id: i.to_string(),
+                inputs: "text".to_string(),
+                token_estimate: 10,
+            })
+            .collect();
+        assert_eq!(message_usage(&inputs, 2), (3, 50));
+        assert_eq!(message_usage(&inputs, 96), (1, 50));
+        assert_eq!(message_usage(&[], 96), (0, 0));
+    }
+}
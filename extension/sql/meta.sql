@@ -4,7 +4,9 @@ CREATE TABLE vectorize.job (
     index_dist_type TEXT NOT NULL DEFAULT 'pgv_hsnw_cosine',
     transformer TEXT NOT NULL,
     params jsonb NOT NULL,
-    last_completion TIMESTAMP WITH TIME ZONE
+    last_completion TIMESTAMP WITH TIME ZONE,
+    paused BOOLEAN NOT NULL DEFAULT false,
+    refreshed_at TIMESTAMP WITH TIME ZONE
 );
 
 CREATE TABLE vectorize.prompts (
@@ -13,12 +15,171 @@ CREATE TABLE vectorize.prompts (
     user_prompt TEXT NOT NULL
 );
 
+CREATE TABLE vectorize.rag_batch (
+    batch_id bigserial,
+    name TEXT NOT NULL UNIQUE,
+    params jsonb NOT NULL,
+    status TEXT NOT NULL DEFAULT 'running',
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE TABLE vectorize.rag_batch_failures (
+    batch_name TEXT NOT NULL,
+    question_id TEXT NOT NULL,
+    attempts INT NOT NULL DEFAULT 1,
+    error TEXT NOT NULL,
+    failed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (batch_name, question_id)
+);
+
+CREATE TABLE vectorize.budget (
+    scope TEXT NOT NULL CHECK (scope IN ('job', 'provider')),
+    name TEXT NOT NULL,
+    token_limit BIGINT NOT NULL,
+    period INTERVAL NOT NULL DEFAULT '1 day',
+    window_start TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    tokens_used BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (scope, name)
+);
+
+CREATE TABLE vectorize.benchmark (
+    benchmark_id bigserial PRIMARY KEY,
+    job_name TEXT NOT NULL,
+    mode TEXT NOT NULL,
+    queries INT NOT NULL,
+    concurrency INT NOT NULL,
+    num_results INT NOT NULL,
+    errors INT NOT NULL,
+    p50_ms FLOAT8 NOT NULL,
+    p95_ms FLOAT8 NOT NULL,
+    p99_ms FLOAT8 NOT NULL,
+    qps FLOAT8 NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+CREATE TABLE vectorize.embedding_provenance (
+    job_name TEXT NOT NULL,
+    record_id TEXT NOT NULL,
+    model TEXT NOT NULL,
+    provider TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    trace_id TEXT NOT NULL,
+    vectorize_version TEXT,
+    embedded_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (job_name, record_id)
+);
+
+CREATE TABLE vectorize.job_stats (
+    job_name TEXT PRIMARY KEY,
+    embedded_chunks BIGINT NOT NULL DEFAULT 0,
+    deduplicated_chunks BIGINT NOT NULL DEFAULT 0,
+    tokens_saved BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+CREATE TABLE vectorize.models (
+    name TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    dimensions INT,
+    endpoint TEXT,
+    options jsonb NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+CREATE TABLE vectorize.rate_limit (
+    provider TEXT PRIMARY KEY,
+    window_start TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+    tokens BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE vectorize.embedding_cache (
+    model TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    embeddings float8[] NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (model, content_hash)
+);
+
+CREATE TABLE vectorize.usage (
+    job_name TEXT NOT NULL,
+    day DATE NOT NULL,
+    provider TEXT NOT NULL,
+    model TEXT NOT NULL,
+    calls BIGINT NOT NULL DEFAULT 0,
+    tokens_in BIGINT NOT NULL DEFAULT 0,
+    tokens_out BIGINT NOT NULL DEFAULT 0,
+    cost FLOAT8,
+    PRIMARY KEY (job_name, day, model)
+);
+
+CREATE TABLE vectorize.model_prices (
+    model TEXT PRIMARY KEY,
+    input_price FLOAT8 NOT NULL,
+    output_price FLOAT8 NOT NULL DEFAULT 0
+);
+
+INSERT INTO vectorize.model_prices (model, input_price, output_price) VALUES
+    ('openai/text-embedding-3-small', 0.02, 0),
+    ('openai/text-embedding-3-large', 0.13, 0),
+    ('openai/text-embedding-ada-002', 0.10, 0),
+    ('openai/gpt-4o-mini', 0.15, 0.60),
+    ('openai/gpt-4o', 2.50, 10.00);
+
+CREATE TABLE vectorize.credentials (
+    name TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    api_key TEXT,
+    virtual_key TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+CREATE TABLE vectorize.provider_health (
+    provider TEXT PRIMARY KEY,
+    reachable BOOLEAN NOT NULL,
+    latency_ms DOUBLE PRECISION NOT NULL,
+    dimensions INT,
+    error TEXT,
+    checked_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+CREATE TABLE vectorize.chat_history (
+    id BIGSERIAL PRIMARY KEY,
+    session_id TEXT NOT NULL,
+    agent_name TEXT NOT NULL,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    owner TEXT NOT NULL DEFAULT current_user,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX chat_history_session_idx ON vectorize.chat_history (session_id, id);
+
+CREATE TABLE vectorize.migrations (
+    version INT PRIMARY KEY,
+    description TEXT NOT NULL,
+    applied_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
 -- allow pg_monitor to read from vectorize schema
 GRANT USAGE ON SCHEMA vectorize TO pg_monitor;
 GRANT SELECT ON ALL TABLES IN SCHEMA vectorize TO pg_monitor;
 GRANT SELECT ON ALL SEQUENCES IN SCHEMA vectorize TO pg_monitor;
 ALTER DEFAULT PRIVILEGES IN SCHEMA vectorize GRANT SELECT ON TABLES TO pg_monitor;
 ALTER DEFAULT PRIVILEGES IN SCHEMA vectorize GRANT SELECT ON SEQUENCES TO pg_monitor;
+-- credentials are only read by the roles that run the jobs using them
+REVOKE ALL ON vectorize.credentials FROM PUBLIC, pg_monitor;
+-- hashes of the embedded text, which may have been decrypted by the worker
+REVOKE ALL ON vectorize.embedding_provenance FROM PUBLIC, pg_monitor;
+-- embeddings of the embedded text, from which the text can be approximately recovered
+REVOKE ALL ON vectorize.embedding_cache FROM PUBLIC, pg_monitor;
+-- the questions and answers of rag sessions
+REVOKE ALL ON vectorize.chat_history FROM PUBLIC, pg_monitor;
+-- the options of registered models, whose headers may hold tokens
+REVOKE SELECT ON vectorize.models FROM pg_monitor;
+GRANT SELECT (name, source, dimensions, endpoint, created_at) ON vectorize.models TO pg_monitor;
 
 CREATE OR REPLACE FUNCTION handle_table_drop()
 RETURNS event_trigger AS $$
@@ -0,0 +1,724 @@
+ALTER TYPE vectorize.indexdist ADD VALUE 'pgv_ivfflat_l2';
+ALTER TYPE vectorize.indexdist ADD VALUE 'pgv_ivfflat_ip';
+ALTER TYPE vectorize.indexdist ADD VALUE 'pgv_ivfflat_cosine';
+ALTER TYPE vectorize.indexdist ADD VALUE 'vsc_diskann_l2';
+ALTER TYPE vectorize.indexdist ADD VALUE 'vsc_diskann_ip';
+
+CREATE TABLE vectorize.migrations (
+    version INT PRIMARY KEY,
+    description TEXT NOT NULL,
+    applied_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+DROP FUNCTION IF EXISTS vectorize."search";
+CREATE  FUNCTION vectorize."search"(
+	"job_name" TEXT, /* alloc::string::String */
+	"query" TEXT, /* alloc::string::String */
+	"api_key" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"return_columns" TEXT[] DEFAULT ARRAY['*']::text[], /* alloc::vec::Vec<alloc::string::String> */
+	"num_results" INT DEFAULT 10, /* i32 */
+	"where_sql" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"lexical_fallback" bool DEFAULT false, /* bool */
+	"include_unembedded" bool DEFAULT false, /* bool */
+	"metric" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"parent_document" bool DEFAULT false, /* bool */
+	"chunk_window" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"rerank_model" TEXT DEFAULT NULL /* core::option::Option<alloc::string::String> */
+) RETURNS TABLE (
+	"search_results" jsonb  /* pgrx::datum::json::JsonB */
+)
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'search_wrapper';
+
+DROP FUNCTION IF EXISTS vectorize."rag";
+CREATE  FUNCTION vectorize."rag"(
+	"agent_name" TEXT, /* &str */
+	"query" TEXT, /* &str */
+	"chat_model" TEXT DEFAULT 'tembo/meta-llama/Meta-Llama-3-8B-Instruct', /* alloc::string::String */
+	"task" TEXT DEFAULT 'question_answer', /* alloc::string::String */
+	"api_key" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"num_context" INT DEFAULT 2, /* i32 */
+	"force_trim" bool DEFAULT false, /* bool */
+	"rerank_model" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"session_id" TEXT DEFAULT NULL /* core::option::Option<&str> */
+) RETURNS TABLE (
+	"chat_results" jsonb  /* pgrx::datum::json::JsonB */
+)
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'rag_wrapper';
+
+CREATE  FUNCTION vectorize."rag_batch"(
+	"agent_name" TEXT, /* &str */
+	"questions_table" TEXT, /* &str */
+	"output_table" TEXT, /* &str */
+	"schema" TEXT DEFAULT 'public', /* &str */
+	"question_column" TEXT DEFAULT 'question', /* &str */
+	"id_column" TEXT DEFAULT 'id', /* &str */
+	"chat_model" TEXT DEFAULT 'tembo/meta-llama/Meta-Llama-3-8B-Instruct', /* alloc::string::String */
+	"task" TEXT DEFAULT 'question_answer', /* alloc::string::String */
+	"num_context" INT DEFAULT 2, /* i32 */
+	"force_trim" bool DEFAULT false, /* bool */
+	"questions_per_minute" INT DEFAULT 60 /* i32 */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'rag_batch_wrapper';
+
+CREATE  FUNCTION vectorize."_rag_batch_execute"(
+	"batch_name" TEXT /* &str */
+) RETURNS bigint /* core::result::Result<i64, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', '_rag_batch_execute_wrapper';
+
+DROP FUNCTION IF EXISTS vectorize."table";
+CREATE  FUNCTION vectorize."table"(
+	"table" TEXT, /* &str */
+	"columns" TEXT[], /* alloc::vec::Vec<alloc::string::String> */
+	"job_name" TEXT, /* &str */
+	"primary_key" TEXT, /* &str */
+	"schema" TEXT DEFAULT 'public', /* &str */
+	"update_col" TEXT DEFAULT 'last_updated_at', /* alloc::string::String */
+	"index_dist_type" vectorize.IndexDist DEFAULT 'pgv_hnsw_cosine', /* vectorize::types::IndexDist */
+	"transformer" TEXT DEFAULT 'sentence-transformers/all-MiniLM-L6-v2', /* &str */
+	"table_method" vectorize.TableMethod DEFAULT 'join', /* vectorize::types::TableMethod */
+	"schedule" TEXT DEFAULT '* * * * *', /* &str */
+	"m" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"ef_construction" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"lists" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"num_neighbors" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"search_list_size" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"storage_layout" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"num_bits_per_dimension" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"decrypt_expressions" jsonb DEFAULT NULL, /* core::option::Option<pgrx::datum::json::JsonB> */
+	"decrypt_role" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"column_weights" jsonb DEFAULT NULL, /* core::option::Option<pgrx::datum::json::JsonB> */
+	"partition_embeddings" bool DEFAULT false, /* bool */
+	"dest_schema" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"dimensions" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"input_template" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"storage" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"storage_params" jsonb DEFAULT NULL, /* core::option::Option<pgrx::datum::json::JsonB> */
+	"tablespace" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"index_tablespace" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"index_storage_params" jsonb DEFAULT NULL, /* core::option::Option<pgrx::datum::json::JsonB> */
+	"chunk_size" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"chunk_overlap" INT DEFAULT 0, /* i32 */
+	"chunk_strategy" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"chunk_separators" TEXT[] DEFAULT NULL, /* core::option::Option<alloc::vec::Vec<alloc::string::String>> */
+	"chunk_similarity_threshold" double precision DEFAULT NULL, /* core::option::Option<f64> */
+	"chunk_separator" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"chunk_stride" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"chunk_column_params" jsonb DEFAULT NULL, /* core::option::Option<pgrx::datum::json::JsonB> */
+	"chunk_filter" jsonb DEFAULT NULL, /* core::option::Option<pgrx::datum::json::JsonB> */
+	"preprocess" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"credential" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"normalize" bool DEFAULT false, /* bool */
+	"content_type" TEXT DEFAULT 'text' /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'table_wrapper';
+
+CREATE  FUNCTION vectorize."vec_add"(
+	"a" double precision[], /* alloc::vec::Vec<f64> */
+	"b" double precision[] /* alloc::vec::Vec<f64> */
+) RETURNS double precision[] /* core::result::Result<alloc::vec::Vec<f64>, anyhow::Error> */
+IMMUTABLE STRICT PARALLEL SAFE
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'vec_add_wrapper';
+
+CREATE  FUNCTION vectorize."vec_sub"(
+	"a" double precision[], /* alloc::vec::Vec<f64> */
+	"b" double precision[] /* alloc::vec::Vec<f64> */
+) RETURNS double precision[] /* core::result::Result<alloc::vec::Vec<f64>, anyhow::Error> */
+IMMUTABLE STRICT PARALLEL SAFE
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'vec_sub_wrapper';
+
+CREATE  FUNCTION vectorize."vec_normalize"(
+	"v" double precision[] /* alloc::vec::Vec<f64> */
+) RETURNS double precision[] /* core::result::Result<alloc::vec::Vec<f64>, anyhow::Error> */
+IMMUTABLE STRICT PARALLEL SAFE
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'vec_normalize_wrapper';
+
+CREATE  FUNCTION vectorize."embedding"(
+	"job_name" TEXT, /* &str */
+	"record_id" TEXT /* &str */
+) RETURNS double precision[] /* core::result::Result<alloc::vec::Vec<f64>, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'embedding_wrapper';
+
+CREATE  FUNCTION vectorize."vec_avg"(
+	"job_name" TEXT, /* &str */
+	"record_ids" TEXT[] /* alloc::vec::Vec<alloc::string::String> */
+) RETURNS double precision[] /* core::result::Result<alloc::vec::Vec<f64>, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'vec_avg_wrapper';
+
+CREATE  FUNCTION vectorize."cosine_similarity"(
+	"job_name" TEXT, /* &str */
+	"record_a" TEXT, /* &str */
+	"record_b" TEXT /* &str */
+) RETURNS double precision /* core::result::Result<f64, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'cosine_similarity_wrapper';
+
+CREATE  FUNCTION vectorize."dot_product"(
+	"job_name" TEXT, /* &str */
+	"record_a" TEXT, /* &str */
+	"record_b" TEXT /* &str */
+) RETURNS double precision /* core::result::Result<f64, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'dot_product_wrapper';
+
+CREATE  FUNCTION vectorize."drop"(
+	"job_name" TEXT /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'drop_job_wrapper';
+
+CREATE  FUNCTION vectorize."migrate_deprecated_models"(
+	"dry_run" bool DEFAULT true /* bool */
+) RETURNS TABLE (
+	"job_name" TEXT,  /* alloc::string::String */
+	"transformer" TEXT,  /* alloc::string::String */
+	"replacement" TEXT,  /* alloc::string::String */
+	"status" TEXT  /* alloc::string::String */
+)
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'migrate_deprecated_models_wrapper';
+
+CREATE  FUNCTION vectorize."pause"(
+	"job_name" TEXT /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'pause_wrapper';
+
+CREATE  FUNCTION vectorize."resume"(
+	"job_name" TEXT /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'resume_wrapper';
+
+CREATE  FUNCTION vectorize."reindex"(
+	"job_name" TEXT, /* &str */
+	"index_dist_type" vectorize.IndexDist, /* vectorize::types::IndexDist */
+	"m" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"ef_construction" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"lists" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"num_neighbors" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"search_list_size" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"storage_layout" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"num_bits_per_dimension" INT DEFAULT NULL /* core::option::Option<i32> */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'reindex_wrapper';
+
+CREATE  FUNCTION vectorize."_reindex_finalize"(
+	"job_name" TEXT, /* &str */
+	"index_dist_type" TEXT, /* &str */
+	"index_params" jsonb /* pgrx::datum::json::JsonB */
+) RETURNS void /* core::result::Result<(), anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', '_reindex_finalize_wrapper';
+
+CREATE  FUNCTION vectorize."search_export"(
+	"job_name" TEXT, /* &str */
+	"query" TEXT, /* &str */
+	"destination" TEXT, /* &str */
+	"format" TEXT DEFAULT 'ndjson', /* &str */
+	"num_results" INT DEFAULT 1000, /* i32 */
+	"where_sql" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"api_key" TEXT DEFAULT NULL /* core::option::Option<alloc::string::String> */
+) RETURNS bigint /* core::result::Result<i64, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'search_export_wrapper';
+
+CREATE  FUNCTION vectorize."refresh"(
+	"job_name" TEXT, /* &str */
+	"force" bool DEFAULT false /* bool */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'refresh_wrapper';
+
+CREATE  FUNCTION vectorize."refresh_progress"(
+	"job_name" TEXT /* &str */
+) RETURNS TABLE (
+	"total_rows" bigint,  /* i64 */
+	"completed_rows" bigint,  /* i64 */
+	"percent_complete" double precision  /* f64 */
+)
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'refresh_progress_wrapper';
+
+CREATE  FUNCTION vectorize."set_budget"(
+	"name" TEXT, /* &str */
+	"token_limit" bigint, /* i64 */
+	"period" TEXT DEFAULT '1 day', /* &str */
+	"scope" TEXT DEFAULT 'job' /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'set_budget_wrapper';
+
+CREATE  FUNCTION vectorize."remove_budget"(
+	"name" TEXT, /* &str */
+	"scope" TEXT DEFAULT 'job' /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'remove_budget_wrapper';
+
+CREATE  FUNCTION vectorize."search_and_rag"(
+	"agent_name" TEXT, /* &str */
+	"query" TEXT, /* &str */
+	"chat_model" TEXT DEFAULT 'tembo/meta-llama/Meta-Llama-3-8B-Instruct', /* alloc::string::String */
+	"task" TEXT DEFAULT 'question_answer', /* alloc::string::String */
+	"api_key" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"return_columns" TEXT[] DEFAULT ARRAY['*']::text[], /* alloc::vec::Vec<alloc::string::String> */
+	"num_results" INT DEFAULT 10, /* i32 */
+	"num_context" INT DEFAULT 2, /* i32 */
+	"where_sql" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"force_trim" bool DEFAULT false, /* bool */
+	"rerank_model" TEXT DEFAULT NULL /* core::option::Option<alloc::string::String> */
+) RETURNS TABLE (
+	"search_results" jsonb,  /* pgrx::datum::json::JsonB */
+	"chat_results" jsonb  /* pgrx::datum::json::JsonB */
+)
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'search_and_rag_wrapper';
+
+CREATE  FUNCTION vectorize."prewarm_index"(
+	"job_name" TEXT, /* &str */
+	"num_queries" INT DEFAULT 10 /* i32 */
+) RETURNS TABLE (
+	"relation" TEXT,  /* alloc::string::String */
+	"blocks" bigint,  /* core::option::Option<i64> */
+	"searches" bigint  /* i64 */
+)
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'prewarm_index_wrapper';
+
+CREATE  FUNCTION vectorize."warmup"(
+	"model" TEXT /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'warmup_wrapper';
+
+DROP FUNCTION IF EXISTS vectorize."init_rag";
+CREATE  FUNCTION vectorize."init_rag"(
+	"agent_name" TEXT, /* &str */
+	"table_name" TEXT, /* &str */
+	"unique_record_id" TEXT, /* &str */
+	"column" TEXT, /* &str */
+	"schema" TEXT DEFAULT 'public', /* &str */
+	"index_dist_type" vectorize.IndexDist DEFAULT 'pgv_hnsw_cosine', /* vectorize::types::IndexDist */
+	"transformer" TEXT DEFAULT 'sentence-transformers/all-MiniLM-L6-v2', /* &str */
+	"table_method" vectorize.TableMethod DEFAULT 'join', /* vectorize::types::TableMethod */
+	"schedule" TEXT DEFAULT '* * * * *', /* &str */
+	"provenance" jsonb DEFAULT NULL /* core::option::Option<pgrx::datum::json::JsonB> */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'init_rag_wrapper';
+
+DROP FUNCTION IF EXISTS vectorize."transform_embeddings";
+CREATE  FUNCTION vectorize."transform_embeddings"(
+	"input" TEXT, /* &str */
+	"model_name" TEXT DEFAULT 'sentence-transformers/all-MiniLM-L6-v2', /* alloc::string::String */
+	"api_key" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"dimensions" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"input_type" TEXT DEFAULT 'document' /* &str */
+) RETURNS double precision[] /* core::result::Result<alloc::vec::Vec<f64>, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'transform_embeddings_wrapper';
+
+CREATE  FUNCTION vectorize."create_collection"(
+	"name" TEXT, /* &str */
+	"transformer" TEXT DEFAULT 'sentence-transformers/all-MiniLM-L6-v2', /* &str */
+	"schema" TEXT DEFAULT 'public', /* &str */
+	"index_dist_type" vectorize.IndexDist DEFAULT 'pgv_hnsw_cosine', /* vectorize::types::IndexDist */
+	"schedule" TEXT DEFAULT 'realtime' /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'create_collection_wrapper';
+
+CREATE  FUNCTION vectorize."benchmark"(
+	"job_name" TEXT, /* &str */
+	"queries" INT DEFAULT 100, /* i32 */
+	"concurrency" INT DEFAULT 4, /* i32 */
+	"num_results" INT DEFAULT 10, /* i32 */
+	"recorded_queries" TEXT[] DEFAULT NULL /* core::option::Option<alloc::vec::Vec<alloc::string::String>> */
+) RETURNS TABLE (
+	"benchmark_id" bigint,  /* i64 */
+	"p50_ms" double precision,  /* f64 */
+	"p95_ms" double precision,  /* f64 */
+	"p99_ms" double precision,  /* f64 */
+	"qps" double precision,  /* f64 */
+	"errors" INT  /* i32 */
+)
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'benchmark_wrapper';
+
+CREATE  FUNCTION vectorize."upsert"(
+	"collection" TEXT, /* &str */
+	"id" TEXT, /* core::option::Option<alloc::string::String> */
+	"content" TEXT, /* &str */
+	"metadata" jsonb DEFAULT '{}' /* pgrx::datum::json::JsonB */
+) RETURNS TEXT /* alloc::string::String */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'upsert_wrapper';
+
+CREATE  FUNCTION vectorize."upsert_batch"(
+	"collection" TEXT, /* &str */
+	"documents" jsonb /* pgrx::datum::json::JsonB */
+) RETURNS TABLE (
+	"id" TEXT  /* alloc::string::String */
+)
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'upsert_batch_wrapper';
+
+CREATE  FUNCTION vectorize."delete"(
+	"job_name" TEXT, /* &str */
+	"ids" TEXT[] /* alloc::vec::Vec<alloc::string::String> */
+) RETURNS bigint /* i64 */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'delete_records_wrapper';
+
+CREATE  FUNCTION vectorize."set_ttl"(
+	"job_name" TEXT, /* &str */
+	"ttl" TEXT, /* core::option::Option<alloc::string::String> */
+	"delete_source" bool DEFAULT false /* bool */
+) RETURNS TEXT /* alloc::string::String */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'set_ttl_wrapper';
+
+CREATE  FUNCTION vectorize."_purge_expired"(
+	"job_name" TEXT /* &str */
+) RETURNS bigint /* i64 */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', '_purge_expired_wrapper';
+
+CREATE  FUNCTION vectorize."provenance"(
+	"job_name" TEXT, /* &str */
+	"record_id" TEXT /* &str */
+) RETURNS TABLE (
+	"model" TEXT,  /* alloc::string::String */
+	"provider" TEXT,  /* alloc::string::String */
+	"endpoint" TEXT,  /* alloc::string::String */
+	"content_hash" TEXT,  /* alloc::string::String */
+	"trace_id" TEXT,  /* alloc::string::String */
+	"vectorize_version" TEXT,  /* core::option::Option<alloc::string::String> */
+	"embedded_at" timestamp with time zone  /* pgrx::datum::time_stamp_with_timezone::TimestampWithTimeZone */
+)
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'provenance_wrapper';
+
+CREATE  FUNCTION vectorize."alter_job"(
+	"job_name" TEXT, /* &str */
+	"params" jsonb /* pgrx::datum::json::JsonB */
+) RETURNS TEXT /* alloc::string::String */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'alter_job_wrapper';
+
+CREATE  FUNCTION vectorize."clone_job"(
+	"source_job" TEXT, /* &str */
+	"new_job" TEXT, /* &str */
+	"new_table" TEXT /* &str */
+) RETURNS TEXT /* alloc::string::String */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'clone_job_wrapper';
+
+CREATE  FUNCTION vectorize."rename_job"(
+	"job_name" TEXT, /* &str */
+	"new_name" TEXT /* &str */
+) RETURNS TEXT /* alloc::string::String */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'rename_job_wrapper';
+
+CREATE  FUNCTION vectorize."migrate_model"(
+	"job_name" TEXT, /* &str */
+	"new_model" TEXT /* &str */
+) RETURNS TEXT /* alloc::string::String */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'migrate_model_wrapper';
+
+CREATE  FUNCTION vectorize."_migrate_model_finalize"(
+	"job_name" TEXT /* &str */
+) RETURNS void /* core::result::Result<(), anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', '_migrate_model_finalize_wrapper';
+
+CREATE  FUNCTION vectorize."_quantize_int8"(
+	"embedding" real[] /* alloc::vec::Vec<f32> */
+) RETURNS bytea /* alloc::vec::Vec<u8> */
+IMMUTABLE STRICT PARALLEL SAFE
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', '_quantize_int8_wrapper';
+
+CREATE  FUNCTION vectorize."_dequantize_int8"(
+	"quantized" bytea /* &[u8] */
+) RETURNS real[] /* core::result::Result<alloc::vec::Vec<f32>, anyhow::Error> */
+IMMUTABLE STRICT PARALLEL SAFE
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', '_dequantize_int8_wrapper';
+
+CREATE  FUNCTION vectorize."chunk_text"(
+	"text" TEXT, /* &str */
+	"chunk_size" INT, /* i32 */
+	"chunk_overlap" INT DEFAULT 0, /* i32 */
+	"transformer" TEXT DEFAULT NULL /* core::option::Option<&str> */
+) RETURNS TEXT[] /* core::result::Result<alloc::vec::Vec<alloc::string::String>, anyhow::Error> */
+STABLE PARALLEL SAFE
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'chunk_text_wrapper';
+
+CREATE  FUNCTION vectorize."chunk_table"(
+	"input_table" TEXT, /* &str */
+	"column_name" TEXT, /* &str */
+	"primary_key" TEXT, /* &str */
+	"chunk_size" INT, /* i32 */
+	"chunk_overlap" INT DEFAULT 0, /* i32 */
+	"output_table" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"schema" TEXT DEFAULT 'public', /* &str */
+	"chunk_strategy" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"separators" TEXT[] DEFAULT NULL, /* core::option::Option<alloc::vec::Vec<alloc::string::String>> */
+	"separator" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"chunk_stride" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"chunk_filter" jsonb DEFAULT NULL, /* core::option::Option<pgrx::datum::json::JsonB> */
+	"transformer" TEXT DEFAULT NULL, /* core::option::Option<&str> */
+	"preprocess" TEXT DEFAULT NULL /* core::option::Option<alloc::string::String> */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'chunk_table_wrapper';
+
+CREATE  FUNCTION vectorize."_rechunk_rows"(
+	"job_name" TEXT, /* &str */
+	"record_ids" TEXT[] /* alloc::vec::Vec<alloc::string::String> */
+) RETURNS void /* core::result::Result<(), anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', '_rechunk_rows_wrapper';
+
+CREATE  FUNCTION vectorize."rechunk"(
+	"job_name" TEXT, /* &str */
+	"chunk_size" INT, /* i32 */
+	"chunk_overlap" INT DEFAULT 0, /* i32 */
+	"chunk_strategy" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"chunk_separators" TEXT[] DEFAULT NULL, /* core::option::Option<alloc::vec::Vec<alloc::string::String>> */
+	"chunk_similarity_threshold" double precision DEFAULT NULL, /* core::option::Option<f64> */
+	"chunk_separator" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"chunk_stride" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"chunk_column_params" jsonb DEFAULT NULL, /* core::option::Option<pgrx::datum::json::JsonB> */
+	"chunk_filter" jsonb DEFAULT NULL /* core::option::Option<pgrx::datum::json::JsonB> */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'rechunk_wrapper';
+
+CREATE  FUNCTION vectorize."register_model"(
+	"name" TEXT, /* &str */
+	"source" TEXT, /* &str */
+	"dimensions" INT DEFAULT NULL, /* core::option::Option<i32> */
+	"endpoint" TEXT DEFAULT NULL, /* core::option::Option<&str> */
+	"options" jsonb DEFAULT NULL /* core::option::Option<pgrx::datum::json::JsonB> */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'register_model_wrapper';
+
+CREATE  FUNCTION vectorize."unregister_model"(
+	"name" TEXT /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'unregister_model_wrapper';
+
+CREATE  FUNCTION vectorize."create_credential"(
+	"name" TEXT, /* &str */
+	"source" TEXT, /* &str */
+	"api_key" TEXT DEFAULT NULL, /* core::option::Option<&str> */
+	"virtual_key" TEXT DEFAULT NULL /* core::option::Option<&str> */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'create_credential_wrapper';
+
+CREATE  FUNCTION vectorize."drop_credential"(
+	"name" TEXT /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'drop_credential_wrapper';
+
+CREATE  FUNCTION vectorize."validate_api_key"(
+	"provider" TEXT, /* &str */
+	"api_key" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"model" TEXT DEFAULT NULL /* core::option::Option<&str> */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'validate_api_key_wrapper';
+
+CREATE  FUNCTION vectorize."rotate_api_key"(
+	"provider" TEXT, /* &str */
+	"new_key" TEXT, /* &str */
+	"credential" TEXT DEFAULT NULL, /* core::option::Option<&str> */
+	"model" TEXT DEFAULT NULL /* core::option::Option<&str> */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'rotate_api_key_wrapper';
+
+CREATE  FUNCTION vectorize."usage_report"(
+	"job_name" TEXT DEFAULT NULL, /* core::option::Option<&str> */
+	"days" INT DEFAULT 30 /* i32 */
+) RETURNS TABLE (
+	"day" date,  /* pgrx::datum::date::Date */
+	"job_name" TEXT,  /* alloc::string::String */
+	"provider" TEXT,  /* alloc::string::String */
+	"model" TEXT,  /* alloc::string::String */
+	"calls" bigint,  /* i64 */
+	"tokens_in" bigint,  /* i64 */
+	"tokens_out" bigint,  /* i64 */
+	"cost" double precision  /* core::option::Option<f64> */
+)
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'usage_report_wrapper';
+
+CREATE  FUNCTION vectorize."set_model_price"(
+	"model" TEXT, /* &str */
+	"input_price" double precision, /* f64 */
+	"output_price" double precision DEFAULT 0.0 /* f64 */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'set_model_price_wrapper';
+
+DROP FUNCTION IF EXISTS vectorize."encode"(TEXT, TEXT, TEXT);
+CREATE  FUNCTION vectorize."encode"(
+	"input" TEXT, /* &str */
+	"model" TEXT DEFAULT 'sentence-transformers/all-MiniLM-L6-v2', /* alloc::string::String */
+	"api_key" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"dimensions" INT DEFAULT NULL /* core::option::Option<i32> */
+) RETURNS double precision[] /* core::result::Result<alloc::vec::Vec<f64>, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'encode_wrapper';
+
+CREATE  FUNCTION vectorize."encode"(
+	"inputs" TEXT[], /* alloc::vec::Vec<core::option::Option<alloc::string::String>> */
+	"model" TEXT DEFAULT 'sentence-transformers/all-MiniLM-L6-v2', /* alloc::string::String */
+	"api_key" TEXT DEFAULT NULL, /* core::option::Option<alloc::string::String> */
+	"dimensions" INT DEFAULT NULL /* core::option::Option<i32> */
+) RETURNS TABLE (
+	"idx" INT,  /* i32 */
+	"embedding" double precision[]  /* alloc::vec::Vec<f64> */
+)
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'encode_batch_wrapper';
+
+CREATE FUNCTION vectorize."encode_vector"(
+    "input" TEXT,
+    "model" TEXT DEFAULT 'sentence-transformers/all-MiniLM-L6-v2',
+    "api_key" TEXT DEFAULT NULL,
+    "dimensions" INT DEFAULT NULL,
+    "input_type" TEXT DEFAULT 'document'
+) RETURNS vector
+LANGUAGE sql
+AS $$
+    SELECT vectorize.transform_embeddings("input", "model", "api_key", "dimensions", "input_type")::vector
+$$;
+
+CREATE FUNCTION vectorize."encode_real"(
+    "input" TEXT,
+    "model" TEXT DEFAULT 'sentence-transformers/all-MiniLM-L6-v2',
+    "api_key" TEXT DEFAULT NULL,
+    "dimensions" INT DEFAULT NULL,
+    "input_type" TEXT DEFAULT 'document'
+) RETURNS real[]
+LANGUAGE sql
+AS $$
+    SELECT vectorize.transform_embeddings("input", "model", "api_key", "dimensions", "input_type")::real[]
+$$;
+
+CREATE  FUNCTION vectorize."rate_limits"() RETURNS TABLE (
+	"provider" TEXT,  /* alloc::string::String */
+	"requests_per_minute" bigint,  /* core::option::Option<i64> */
+	"requests_used" bigint,  /* i64 */
+	"tokens_per_minute" bigint,  /* core::option::Option<i64> */
+	"tokens_used" bigint,  /* i64 */
+	"resets_at" timestamp with time zone  /* core::option::Option<pgrx::datum::time_stamp_with_timezone::TimestampWithTimeZone> */
+)
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'rate_limits_wrapper';
+
+CREATE  FUNCTION vectorize."check_provider"(
+	"provider_or_model" TEXT /* &str */
+) RETURNS TABLE (
+	"provider" TEXT,  /* alloc::string::String */
+	"model" TEXT,  /* alloc::string::String */
+	"reachable" bool,  /* bool */
+	"latency_ms" double precision,  /* f64 */
+	"dimensions" INT,  /* core::option::Option<i32> */
+	"error" TEXT  /* core::option::Option<alloc::string::String> */
+)
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'check_provider_wrapper';
+
+CREATE  FUNCTION vectorize."create_prompt"(
+	"task" TEXT, /* &str */
+	"sys_prompt" TEXT, /* &str */
+	"user_prompt" TEXT /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'create_prompt_wrapper';
+
+CREATE  FUNCTION vectorize."update_prompt"(
+	"task" TEXT, /* &str */
+	"sys_prompt" TEXT DEFAULT NULL, /* core::option::Option<&str> */
+	"user_prompt" TEXT DEFAULT NULL /* core::option::Option<&str> */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'update_prompt_wrapper';
+
+CREATE  FUNCTION vectorize."delete_prompt"(
+	"task" TEXT /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'delete_prompt_wrapper';
+
+CREATE  FUNCTION vectorize."delete_session"(
+	"session_id" TEXT /* &str */
+) RETURNS TEXT /* core::result::Result<alloc::string::String, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', 'delete_session_wrapper';
+
+CREATE  FUNCTION vectorize."_migrate"() RETURNS bigint /* core::result::Result<i64, anyhow::Error> */
+STRICT
+LANGUAGE c /* Rust */
+AS 'MODULE_PATHNAME', '_migrate_wrapper';
+
+SELECT vectorize._migrate();
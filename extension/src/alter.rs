@@ -0,0 +1,270 @@
+use crate::chunking;
+use crate::compat::{self, arg};
+use crate::credential;
+use crate::init;
+use crate::job::realtime_trigger_queries;
+use crate::model_migration;
+use crate::registry;
+use crate::util;
+
+use anyhow::{bail, Result};
+use pgrx::prelude::*;
+use serde::{Deserialize, Deserializer};
+use vectorize_core::types::JobParams;
+
+/// the settings that vectorize.alter_job changes, settings that are not given are left as they are
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobChanges {
+    // cron-like, or realtime
+    pub schedule: Option<String>,
+    // null stops looking for updated rows by their update time
+    #[serde(default, deserialize_with = "present")]
+    pub update_time_col: Option<Option<String>>,
+    // null goes back to vectorize.batch_size
+    #[serde(default, deserialize_with = "present")]
+    pub batch_size: Option<Option<i32>>,
+    // null goes back to vectorize.max_attempts
+    #[serde(default, deserialize_with = "present")]
+    pub max_attempts: Option<Option<u32>>,
+    // null goes back to vectorize.connect_timeout_sec
+    #[serde(default, deserialize_with = "present")]
+    pub connect_timeout_sec: Option<Option<u32>>,
+    // null goes back to vectorize.embedding_req_timeout_sec and vectorize.chat_req_timeout_sec
+    #[serde(default, deserialize_with = "present")]
+    pub request_timeout_sec: Option<Option<u32>>,
+    // null goes back to the provider's GUCs
+    #[serde(default, deserialize_with = "present")]
+    pub credential: Option<Option<String>>,
+    // migrated to in the background, see model_migration::migrate_model
+    pub transformer: Option<String>,
+    // of a chunked job, its rows are chunked again
+    pub chunk_size: Option<i32>,
+}
+
+// tells a setting given as null, which clears it, apart from a setting that was not given
+fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// changes a job's settings without dropping its embeddings
+/// a new transformer is migrated to in the background, searches use the old model until every row is embedded with it
+pub fn alter_job(job_name: &str, changes: JobChanges) -> Result<String> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let mut job_params: JobParams = serde_json::from_value(meta.params)?;
+    // checked before any change is made
+    let transformer = changes
+        .transformer
+        .as_deref()
+        .map(registry::resolve)
+        .transpose()?;
+    if let Some(Some(name)) = &changes.credential {
+        credential::validate(name, transformer.as_ref().unwrap_or(&meta.transformer))?;
+    }
+
+    let old_schedule = job_params.schedule.clone();
+    apply_changes(&mut job_params, &changes)?;
+    if let Some(Some(col)) = &changes.update_time_col {
+        init::get_column_datatype(&job_params.schema, &job_params.table, col)?;
+    }
+    util::update_job_params(job_name, &job_params)?;
+    if job_params.schedule != old_schedule {
+        reschedule(job_name, &job_params)?;
+    }
+    let mut altered = format!("Altered job: {job_name}");
+    if let Some(chunking) = job_params
+        .chunking
+        .as_ref()
+        .filter(|_| changes.chunk_size.is_some())
+    {
+        altered = chunking::rechunk(job_name, chunking.params.clone(), None)?;
+    }
+    // the migration copies the job's settings, so it is started once they are all changed
+    if let Some(new_model) = &changes.transformer {
+        altered = model_migration::migrate_model(job_name, new_model)?;
+    }
+    Ok(altered)
+}
+
+// validates the changes and applies them to the job's params
+fn apply_changes(job_params: &mut JobParams, changes: &JobChanges) -> Result<()> {
+    if let Some(schedule) = &changes.schedule {
+        job_params.schedule = schedule.clone();
+    }
+    if let Some(update_time_col) = &changes.update_time_col {
+        job_params.update_time_col = update_time_col.clone();
+    }
+    if changes.schedule.is_some() || changes.update_time_col.is_some() {
+        init::validate_source_kind(
+            job_params.source_kind,
+            &job_params.schedule,
+            &job_params.table_method,
+            job_params.update_time_col.as_deref(),
+        )?;
+    }
+    if let Some(batch_size) = changes.batch_size {
+        if batch_size.is_some_and(|b| b < 1) {
+            bail!("batch_size must be positive");
+        }
+        job_params.batch_size = batch_size;
+    }
+    if let Some(max_attempts) = changes.max_attempts {
+        if max_attempts == Some(0) {
+            bail!("max_attempts must be positive");
+        }
+        job_params.max_attempts = max_attempts;
+    }
+    for (name, timeout) in [
+        ("connect_timeout_sec", changes.connect_timeout_sec),
+        ("request_timeout_sec", changes.request_timeout_sec),
+    ] {
+        if timeout == Some(Some(0)) {
+            bail!("{name} must be positive");
+        }
+    }
+    if let Some(connect_timeout_sec) = changes.connect_timeout_sec {
+        job_params.connect_timeout_sec = connect_timeout_sec;
+    }
+    if let Some(request_timeout_sec) = changes.request_timeout_sec {
+        job_params.request_timeout_sec = request_timeout_sec;
+    }
+    if let Some(credential) = &changes.credential {
+        job_params.credential = credential.clone();
+    }
+    if let Some(chunk_size) = changes.chunk_size {
+        let Some(chunking) = job_params.chunking.as_mut() else {
+            bail!("chunk_size can only be changed on a job that chunks its rows");
+        };
+        if chunk_size < 1 {
+            bail!("chunk_size must be positive, got {chunk_size}");
+        }
+        let params = &mut chunking.params;
+        if params.chunk_overlap >= chunk_size as u32 {
+            bail!(
+                "chunk_size must be larger than the job's chunk_overlap, {}",
+                params.chunk_overlap
+            );
+        }
+        if params.chunk_stride.is_some_and(|s| s > chunk_size as u32) {
+            bail!("chunk_size can not be smaller than the job's chunk_stride");
+        }
+        params.chunk_size = chunk_size as u32;
+    }
+    Ok(())
+}
+
+// swaps the realtime triggers or the pg_cron job of a job for those of its new schedule
+// a paused job's new pg_cron job is created inactive
+fn reschedule(job_name: &str, job_params: &JobParams) -> Result<()> {
+    let mut queries = init::drop_trigger_queries(job_name, &job_params.schema, &job_params.table);
+    queries.push(format!(
+        "SELECT cron.unschedule(jobid) FROM cron.job WHERE jobname = '{job_name}';"
+    ));
+    if job_params.schedule == "realtime" {
+        let partitioning = init::get_partitioning(&job_params.schema, &job_params.table)?;
+        queries.extend(realtime_trigger_queries(
+            job_name,
+            job_params,
+            partitioning.as_ref(),
+        ));
+    }
+    compat::connect(|mut c| {
+        for q in &queries {
+            compat::update(&mut c, q, vec![])?;
+        }
+        if job_params.schedule != "realtime" {
+            compat::update(
+                &mut c,
+                "SELECT cron.schedule($1, $2, $3)",
+                vec![
+                    arg(job_name),
+                    arg(job_params.schedule.as_str()),
+                    arg(init::job_execute_command(job_name)),
+                ],
+            )?;
+            compat::update(
+                &mut c,
+                "SELECT cron.alter_job(c.jobid, active := false)
+                FROM cron.job c, vectorize.job j
+                WHERE c.jobname = $1 AND j.name = $1 AND j.paused",
+                vec![arg(job_name)],
+            )?;
+        }
+        Ok::<_, spi::Error>(())
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_changes() {
+        let changes: JobChanges =
+            serde_json::from_value(serde_json::json!({"update_time_col": null})).unwrap();
+        assert_eq!(changes.update_time_col, Some(None));
+        assert_eq!(changes.batch_size, None);
+        let changes: JobChanges =
+            serde_json::from_value(serde_json::json!({"batch_size": 500, "max_attempts": 5}))
+                .unwrap();
+        assert_eq!(changes.batch_size, Some(Some(500)));
+        assert_eq!(changes.max_attempts, Some(Some(5)));
+        let changes: JobChanges =
+            serde_json::from_value(serde_json::json!({"credential": "tenant_a"})).unwrap();
+        assert_eq!(changes.credential, Some(Some("tenant_a".to_string())));
+        // settings that can not be altered are rejected rather than ignored
+        assert!(serde_json::from_value::<JobChanges>(serde_json::json!({"columns": []})).is_err());
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let mut job_params = JobParams {
+            schedule: "realtime".to_string(),
+            update_time_col: Some("updated_at".to_string()),
+            ..Default::default()
+        };
+        let changes = JobChanges {
+            schedule: Some("*/5 * * * *".to_string()),
+            update_time_col: Some(None),
+            batch_size: Some(Some(1000)),
+            ..Default::default()
+        };
+        apply_changes(&mut job_params, &changes).unwrap();
+        assert_eq!(job_params.schedule, "*/5 * * * *");
+        assert_eq!(job_params.update_time_col, None);
+        assert_eq!(job_params.batch_size, Some(1000));
+
+        let changes = JobChanges {
+            batch_size: Some(Some(0)),
+            ..Default::default()
+        };
+        assert!(apply_changes(&mut job_params, &changes).is_err());
+        let changes = JobChanges {
+            max_attempts: Some(Some(0)),
+            ..Default::default()
+        };
+        assert!(apply_changes(&mut job_params, &changes).is_err());
+        let changes = JobChanges {
+            request_timeout_sec: Some(Some(600)),
+            ..Default::default()
+        };
+        apply_changes(&mut job_params, &changes).unwrap();
+        assert_eq!(job_params.request_timeout_sec, Some(600));
+        let changes = JobChanges {
+            connect_timeout_sec: Some(Some(0)),
+            ..Default::default()
+        };
+        assert!(apply_changes(&mut job_params, &changes).is_err());
+        // chunk_size is only a setting of chunked jobs
+        let changes = JobChanges {
+            chunk_size: Some(200),
+            ..Default::default()
+        };
+        assert!(apply_changes(&mut job_params, &changes).is_err());
+    }
+}
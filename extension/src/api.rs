@@ -1,14 +1,41 @@
-use crate::chat::ops::{call_chat, call_chat_completions};
-use crate::chat::types::RenderedPrompt;
-use crate::guc::get_guc_configs;
+use crate::alter;
+use crate::api_key;
+use crate::arithmetic;
+use crate::benchmark;
+use crate::budget;
+use crate::chat::batch::init_rag_batch;
+use crate::chat::history;
+use crate::chat::ops::{call_chat, call_chat_completions, search_and_chat};
+use crate::chat::prompts;
+use crate::chat::types::{RagBatchParams, RenderedPrompt};
+use crate::chunking;
+use crate::collection;
+use crate::compat::{self, arg};
+use crate::credential;
+use crate::export;
+use crate::guc::{self, get_model_guc_configs};
+use crate::health;
+use crate::model_migration;
+use crate::provenance;
+use crate::rate_limit;
+use crate::registry;
+use crate::reindex;
 use crate::search::{self, init_table};
 use crate::transformers::generic::env_interpolate_string;
-use crate::transformers::transform;
+use crate::transformers::transform_batch;
+use crate::ttl;
 use crate::types;
+use crate::usage;
+use crate::warm;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use pgrx::prelude::*;
-use vectorize_core::types::Model;
+use std::collections::BTreeMap;
+use vectorize_core::transformers::providers::{truncate_dimensions, InputType};
+use vectorize_core::types::{
+    ColumnDecryption, ContentType, DistanceMetric, IndexParams, Model, Preprocess, StorageParams,
+    VectorStorage,
+};
 
 #[allow(clippy::too_many_arguments)]
 #[pg_extern]
@@ -24,8 +51,136 @@ fn table(
     table_method: default!(types::TableMethod, "'join'"),
     // cron-like for a cron based update model, or 'realtime' for a trigger-based
     schedule: default!(&str, "'* * * * *'"),
+    // hnsw build parameters, pgvector defaults are used when NULL
+    m: default!(Option<i32>, "NULL"),
+    ef_construction: default!(Option<i32>, "NULL"),
+    // ivfflat lists, picked from the table's row count when NULL
+    lists: default!(Option<i32>, "NULL"),
+    // diskann build parameters, pgvectorscale defaults are used when NULL
+    num_neighbors: default!(Option<i32>, "NULL"),
+    search_list_size: default!(Option<i32>, "NULL"),
+    storage_layout: default!(Option<String>, "NULL"),
+    num_bits_per_dimension: default!(Option<i32>, "NULL"),
+    // column -> SQL expression returning the column's plaintext, evaluated by the worker as decrypt_role
+    decrypt_expressions: default!(Option<pgrx::JsonB>, "NULL"),
+    decrypt_role: default!(Option<String>, "NULL"),
+    // column -> weight, each column is then embedded on its own and the embeddings are combined by weight
+    column_weights: default!(Option<pgrx::JsonB>, "NULL"),
+    // partition the embeddings table the same way as a partitioned source table
+    partition_embeddings: default!(bool, false),
+    // schema for the embeddings table of a join job, vectorize when NULL
+    dest_schema: default!(Option<String>, "NULL"),
+    // truncates the model's embeddings to fewer dimensions, for matryoshka models such as text-embedding-3
+    dimensions: default!(Option<i32>, "NULL"),
+    // SQL expression over the row's columns producing the text to embed, in place of joining the columns
+    input_template: default!(Option<String>, "NULL"),
+    // what the embeddings are stored as: vector, halfvec or int8. defaults to vector
+    storage: default!(Option<String>, "NULL"),
+    // STORAGE and COMPRESSION of the embeddings column, and storage parameters of the embeddings table
+    storage_params: default!(Option<pgrx::JsonB>, "NULL"),
+    // tablespace of the embeddings table and its index, and of the index alone, e.g. for faster disks
+    tablespace: default!(Option<String>, "NULL"),
+    index_tablespace: default!(Option<String>, "NULL"),
+    // storage parameters of the index other than m, ef_construction and lists
+    index_storage_params: default!(Option<pgrx::JsonB>, "NULL"),
+    // splits the columns of each row into chunks of at most chunk_size tokens, which are embedded in place of the rows
+    chunk_size: default!(Option<i32>, "NULL"),
+    chunk_overlap: default!(i32, 0),
+    // tokens, recursive, sentence, markdown, code, semantic, separator or window, defaults to tokens
+    chunk_strategy: default!(Option<String>, "NULL"),
+    // separators of the recursive strategy, from the coarsest to the finest
+    chunk_separators: default!(Option<Vec<String>>, "NULL"),
+    // the similarity of adjacent sentences below which the semantic strategy starts a new chunk, 0.5 when NULL
+    chunk_similarity_threshold: default!(Option<f64>, "NULL"),
+    // the regular expression the separator strategy splits on
+    chunk_separator: default!(Option<String>, "NULL"),
+    // the tokens each window of the window strategy starts after the previous one, half of chunk_size when NULL
+    chunk_stride: default!(Option<i32>, "NULL"),
+    // the chunk params of columns chunked apart from the others, by column name, null for a column that is not chunked
+    chunk_column_params: default!(Option<pgrx::JsonB>, "NULL"),
+    // discards chunks before they are embedded: min_tokens, exclude_patterns or a boolean function of the chunk's text
+    chunk_filter: default!(Option<pgrx::JsonB>, "NULL"),
+    // html strips the markup and boilerplate of each row before it is chunked and embedded
+    preprocess: default!(Option<String>, "NULL"),
+    // name of a credential created with vectorize.create_credential(), used in place of the provider's GUCs
+    credential: default!(Option<String>, "NULL"),
+    // scales embeddings and search queries to unit length, for inner product indexes
+    normalize: default!(bool, false),
+    // text, or image for a bytea column of images, or image_url for a column of their urls
+    content_type: default!(&str, "'text'"),
 ) -> Result<String> {
-    let model = Model::new(transformer)?;
+    let model = registry::resolve(transformer)?;
+    let preprocess = parse_preprocess(preprocess.as_deref())?;
+    let content_type = content_type
+        .parse::<ContentType>()
+        .map_err(|e| anyhow!(e))?;
+    let chunk_params = match chunk_size {
+        Some(chunk_size) => {
+            let mut params = chunking::chunk_params(
+                chunk_size,
+                chunk_overlap,
+                chunk_strategy.as_deref(),
+                chunk_separators,
+                chunk_similarity_threshold,
+                chunk_separator,
+                chunk_stride,
+            )?;
+            params.filter = chunk_filter
+                .map(|f| chunking::chunk_filter(f.0))
+                .transpose()?;
+            Some(match chunk_column_params {
+                Some(column_params) => {
+                    chunking::with_column_params(params, &columns, column_params.0)?
+                }
+                None => params,
+            })
+        }
+        None if chunk_strategy.is_some()
+            || chunk_separators.is_some()
+            || chunk_similarity_threshold.is_some()
+            || chunk_separator.is_some()
+            || chunk_stride.is_some()
+            || chunk_column_params.is_some()
+            || chunk_filter.is_some() =>
+        {
+            bail!("chunk_strategy, chunk_separators, chunk_similarity_threshold, chunk_separator, chunk_stride, chunk_column_params and chunk_filter require a chunk_size")
+        }
+        None => None,
+    };
+    let vector_storage = storage
+        .map(|s| s.parse::<VectorStorage>().map_err(|e| anyhow!(e)))
+        .transpose()?
+        .unwrap_or_default();
+    let mut storage_params: StorageParams = match storage_params {
+        Some(params) => serde_json::from_value(params.0).context(
+            "storage_params must be an object of column_storage, compression or table storage parameters",
+        )?,
+        None => StorageParams::default(),
+    };
+    storage_params.tablespace = tablespace.or(storage_params.tablespace);
+    storage_params.index_tablespace = index_tablespace.or(storage_params.index_tablespace);
+    let index_storage_params = match index_storage_params {
+        Some(params) => serde_json::from_value(params.0)
+            .context("index_storage_params must be an object of storage parameter to value")?,
+        None => BTreeMap::new(),
+    };
+    let decryption = match (decrypt_expressions, decrypt_role) {
+        (Some(expressions), Some(role)) => Some(ColumnDecryption {
+            role,
+            // recorded when the job is created
+            owner: String::new(),
+            expressions: serde_json::from_value(expressions.0).context(
+                "decrypt_expressions must be an object of column name to SQL expression",
+            )?,
+        }),
+        (None, None) => None,
+        _ => bail!("decrypt_expressions and decrypt_role must be provided together"),
+    };
+    let column_weights = match column_weights {
+        Some(weights) => serde_json::from_value(weights.0)
+            .context("column_weights must be an object of column name to weight")?,
+        None => BTreeMap::new(),
+    };
     init_table(
         job_name,
         schema,
@@ -34,12 +189,510 @@ fn table(
         primary_key,
         Some(update_col),
         index_dist_type.into(),
+        IndexParams {
+            m,
+            ef_construction,
+            lists,
+            num_neighbors,
+            search_list_size,
+            storage_layout,
+            num_bits_per_dimension,
+            storage_params: index_storage_params,
+        },
+        decryption,
+        column_weights,
+        partition_embeddings,
+        dest_schema,
+        positive_dimensions(dimensions)?,
+        None,
+        false,
+        input_template,
+        vector_storage,
+        storage_params,
+        chunk_params,
+        preprocess,
+        credential,
+        normalize,
+        content_type,
         &model,
         table_method.into(),
         schedule,
     )
 }
 
+/// creates a table of documents, with id, content and metadata columns, along with a job that embeds their content
+/// the collection is searched like any other job, with its name as the job_name
+#[pg_extern]
+fn create_collection(
+    name: &str,
+    transformer: default!(&str, "'sentence-transformers/all-MiniLM-L6-v2'"),
+    schema: default!(&str, "'public'"),
+    index_dist_type: default!(types::IndexDist, "'pgv_hnsw_cosine'"),
+    schedule: default!(&str, "'realtime'"),
+) -> Result<String> {
+    let model = registry::resolve(transformer)?;
+    collection::create_collection(name, schema, &model, index_dist_type.into(), schedule)
+}
+
+/// writes a document to a collection, replacing the content and metadata of an existing document with the same id
+/// a NULL id generates one, returns the id of the document
+#[pg_extern]
+fn upsert(
+    collection: &str,
+    id: Option<String>,
+    content: &str,
+    metadata: default!(pgrx::JsonB, "'{}'"),
+) -> Result<String> {
+    let document = collection::Document {
+        id,
+        content: content.to_string(),
+        metadata: Some(metadata.0),
+    };
+    Ok(collection::upsert(collection, &[document])?.remove(0))
+}
+
+/// writes a jsonb array of documents, each with content and optionally an id and metadata, to a collection
+#[pg_extern]
+fn upsert_batch(
+    collection: &str,
+    documents: pgrx::JsonB,
+) -> Result<TableIterator<'static, (name!(id, String),)>> {
+    let documents: Vec<collection::Document> =
+        serde_json::from_value(documents.0).map_err(|e| {
+            anyhow!("documents must be an array of objects with content, id and metadata: {e}")
+        })?;
+    let ids = collection::upsert(collection, &documents)?;
+    Ok(compat::table(ids.into_iter().map(|id| (id,))))
+}
+
+/// deletes documents from a collection, or records from a job's table, by id
+/// along with their embeddings and any of their work still waiting in the job queue
+#[pg_extern(name = "delete")]
+fn delete_records(job_name: &str, ids: Vec<String>) -> Result<i64> {
+    collection::delete(job_name, ids)
+}
+
+/// sets how long a job keeps its embeddings, as a Postgres interval, or keeps them indefinitely when ttl is NULL
+/// expired embeddings are purged every few minutes, and with delete_source so are the documents of a collection
+#[pg_extern]
+fn set_ttl(
+    job_name: &str,
+    ttl: Option<String>,
+    delete_source: default!(bool, false),
+) -> Result<String> {
+    ttl::set_ttl(job_name, ttl.as_deref(), delete_source)
+}
+
+/// removes a job along with its triggers, embeddings, schedule and pending queue messages
+#[pg_extern(name = "drop")]
+fn drop_job(job_name: &str) -> Result<String> {
+    search::drop_job(job_name)
+}
+
+/// changes a job's schedule, update column, batch size, chunk size or transformer, without dropping and re-creating it
+/// a new transformer re-embeds the job's rows in the background, as vectorize.migrate_model does
+#[pg_extern]
+fn alter_job(job_name: &str, params: pgrx::JsonB) -> Result<String> {
+    let changes: alter::JobChanges =
+        serde_json::from_value(params.0).context("invalid job settings")?;
+    alter::alter_job(job_name, changes)
+}
+
+/// moves a job to a new model without downtime
+/// the rows are embedded with the new model in the background, and the job switches over once they all are
+#[pg_extern]
+fn migrate_model(job_name: &str, new_model: &str) -> Result<String> {
+    model_migration::migrate_model(job_name, new_model)
+}
+
+/// creates a job over another table with the same model, columns, index and schedule as an existing job
+/// new_table may be schema qualified, e.g. 'tenant_b.products', otherwise it is in the schema of the existing job's table
+#[pg_extern]
+fn clone_job(source_job: &str, new_job: &str, new_table: &str) -> Result<String> {
+    search::clone_job(source_job, new_job, new_table)
+}
+
+/// renames a job, keeping its embeddings, so that it does not have to be dropped and re-embedded
+#[pg_extern]
+fn rename_job(job_name: &str, new_name: &str) -> Result<String> {
+    search::rename_job(job_name, new_name)
+}
+
+/// rebuilds a job's vector index with a different index type or build parameters
+/// the existing embeddings are kept, and the new index is built concurrently in the background
+#[pg_extern]
+fn reindex(
+    job_name: &str,
+    index_dist_type: types::IndexDist,
+    m: default!(Option<i32>, "NULL"),
+    ef_construction: default!(Option<i32>, "NULL"),
+    lists: default!(Option<i32>, "NULL"),
+    num_neighbors: default!(Option<i32>, "NULL"),
+    search_list_size: default!(Option<i32>, "NULL"),
+    storage_layout: default!(Option<String>, "NULL"),
+    num_bits_per_dimension: default!(Option<i32>, "NULL"),
+) -> Result<String> {
+    reindex::reindex(
+        job_name,
+        index_dist_type.into(),
+        IndexParams {
+            m,
+            ef_construction,
+            lists,
+            num_neighbors,
+            search_list_size,
+            storage_layout,
+            num_bits_per_dimension,
+            ..Default::default()
+        },
+    )
+}
+
+/// re-embeds a job's rows in the background
+/// force re-embeds every row, otherwise only rows that are new or have changed are picked up
+#[pg_extern]
+fn refresh(job_name: &str, force: default!(bool, false)) -> Result<String> {
+    search::refresh_job(job_name, force)
+}
+
+/// reports how many of a job's rows have been embedded
+#[pg_extern]
+fn refresh_progress(
+    job_name: &str,
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(total_rows, i64),
+            name!(completed_rows, i64),
+            name!(percent_complete, f64),
+        ),
+    >,
+> {
+    let (total_rows, completed_rows) = search::refresh_progress(job_name)?;
+    let percent_complete = if total_rows > 0 {
+        100.0 * completed_rows as f64 / total_rows as f64
+    } else {
+        100.0
+    };
+    Ok(compat::one_row((
+        total_rows,
+        completed_rows,
+        percent_complete,
+    )))
+}
+
+/// caps the tokens a job, or every job using a provider, may send to providers within each period
+/// once the cap is reached, the worker holds back the job's messages and search() and rag() raise an error
+#[pg_extern]
+fn set_budget(
+    name: &str,
+    token_limit: i64,
+    period: default!(&str, "'1 day'"),
+    // 'job' or 'provider'
+    scope: default!(&str, "'job'"),
+) -> Result<String> {
+    budget::set_budget(scope, name, token_limit, period)
+}
+
+#[pg_extern]
+fn remove_budget(name: &str, scope: default!(&str, "'job'")) -> Result<String> {
+    budget::remove_budget(scope, name)
+}
+
+/// stores the keys a job's requests to a provider are sent with, so that jobs can bill their own accounts
+/// jobs use a credential once it is given to vectorize.table() or vectorize.alter_job() as their credential
+#[pg_extern]
+fn create_credential(
+    name: &str,
+    source: &str,
+    api_key: default!(Option<&str>, "NULL"),
+    virtual_key: default!(Option<&str>, "NULL"),
+) -> Result<String> {
+    credential::create_credential(name, source, api_key, virtual_key)
+}
+
+#[pg_extern]
+fn drop_credential(name: &str) -> Result<String> {
+    credential::drop_credential(name)
+}
+
+/// checks an API key with a one word embedding request, the provider's GUC key when none is given
+/// the request is sent to model, or to a small model of the provider when NULL
+#[pg_extern]
+fn validate_api_key(
+    provider: &str,
+    api_key: default!(Option<String>, "NULL"),
+    model: default!(Option<&str>, "NULL"),
+) -> Result<String> {
+    api_key::validate_api_key(provider, api_key, model)
+}
+
+/// checks a new API key, then swaps it in for the key of credential, or of the provider's GUC when NULL
+#[pg_extern]
+fn rotate_api_key(
+    provider: &str,
+    new_key: &str,
+    credential: default!(Option<&str>, "NULL"),
+    model: default!(Option<&str>, "NULL"),
+) -> Result<String> {
+    api_key::rotate_api_key(provider, new_key, credential, model)
+}
+
+/// the calls each job made to each model per day, their tokens in and out, and their estimated cost
+/// covers the last days, of every job or only of job_name
+#[pg_extern]
+fn usage_report(
+    job_name: default!(Option<&str>, "NULL"),
+    days: default!(i32, 30),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(day, Date),
+            name!(job_name, String),
+            name!(provider, String),
+            name!(model, String),
+            name!(calls, i64),
+            name!(tokens_in, i64),
+            name!(tokens_out, i64),
+            name!(cost, Option<f64>),
+        ),
+    >,
+> {
+    let rows = usage::usage_report(job_name, days)?.into_iter().map(|u| {
+        (
+            u.day,
+            u.job_name,
+            u.provider,
+            u.model,
+            u.calls,
+            u.tokens_in,
+            u.tokens_out,
+            u.cost,
+        )
+    });
+    Ok(compat::table(rows))
+}
+
+/// sets the price a model's usage is costed at, in USD per million tokens
+#[pg_extern]
+fn set_model_price(
+    model: &str,
+    input_price: f64,
+    output_price: default!(f64, 0.0),
+) -> Result<String> {
+    usage::set_model_price(model, input_price, output_price)
+}
+
+/// the embedding requests and tokens sent to each rate limited provider in its current minute
+#[pg_extern]
+fn rate_limits() -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(provider, String),
+            name!(requests_per_minute, Option<i64>),
+            name!(requests_used, i64),
+            name!(tokens_per_minute, Option<i64>),
+            name!(tokens_used, i64),
+            name!(resets_at, Option<TimestampWithTimeZone>),
+        ),
+    >,
+> {
+    let rows = rate_limit::rate_limits()?.into_iter().map(|r| {
+        (
+            r.provider,
+            r.requests_per_minute,
+            r.requests_used,
+            r.tokens_per_minute,
+            r.tokens_used,
+            r.resets_at,
+        )
+    });
+    Ok(compat::table(rows))
+}
+
+/// sends a one word embedding request to a provider, or to a model, and reports its latency and embedding dimensions
+/// jobs of a provider found unreachable are held back by the workers for the next minute
+#[pg_extern]
+fn check_provider(
+    provider_or_model: &str,
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(provider, String),
+            name!(model, String),
+            name!(reachable, bool),
+            name!(latency_ms, f64),
+            name!(dimensions, Option<i32>),
+            name!(error, Option<String>),
+        ),
+    >,
+> {
+    let h = health::check_provider(provider_or_model)?;
+    Ok(compat::one_row((
+        h.provider,
+        h.model,
+        h.reachable,
+        h.latency_ms,
+        h.dimensions,
+        h.error,
+    )))
+}
+
+/// stops a job from generating embeddings until it is resumed
+#[pg_extern]
+fn pause(job_name: &str) -> Result<String> {
+    search::set_job_paused(job_name, true)
+}
+
+#[pg_extern]
+fn resume(job_name: &str) -> Result<String> {
+    search::set_job_paused(job_name, false)
+}
+
+/// loads a job's index and embeddings into memory, so that first searches after a restart are not slowed down by disk reads
+/// uses pg_prewarm when it is installed, then runs num_queries searches against the index
+#[pg_extern]
+fn prewarm_index(
+    job_name: &str,
+    num_queries: default!(i32, 10),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(relation, String),
+            name!(blocks, Option<i64>),
+            name!(searches, i64),
+        ),
+    >,
+> {
+    Ok(compat::table(warm::prewarm_index(job_name, num_queries)?))
+}
+
+/// sends an embedding request to a model, so that the first texts of its jobs are not held up while it is loaded
+#[pg_extern]
+fn warmup(model: &str) -> Result<String> {
+    let model = registry::resolve(model)?;
+    let (dim, took) = warm::warm_up_model(&model)?;
+    Ok(format!(
+        "Warmed up {model} in {} ms, {dim} dimensions",
+        took.as_millis()
+    ))
+}
+
+/// measures search latency and throughput for a job, and records the results in vectorize.benchmark
+/// searches for stored embeddings by default, or for recorded_queries through vectorize.search
+#[pg_extern]
+fn benchmark(
+    job_name: &str,
+    queries: default!(i32, 100),
+    concurrency: default!(i32, 4),
+    num_results: default!(i32, 10),
+    recorded_queries: default!(Option<Vec<String>>, "NULL"),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(benchmark_id, i64),
+            name!(p50_ms, f64),
+            name!(p95_ms, f64),
+            name!(p99_ms, f64),
+            name!(qps, f64),
+            name!(errors, i32),
+        ),
+    >,
+> {
+    let r = benchmark::benchmark(
+        job_name,
+        queries,
+        concurrency,
+        num_results,
+        recorded_queries,
+    )?;
+    Ok(compat::one_row((
+        r.benchmark_id,
+        r.p50_ms,
+        r.p95_ms,
+        r.p99_ms,
+        r.qps,
+        r.errors,
+    )))
+}
+
+/// which model embedded a record, when, from what input, through which provider endpoint, and under which trace id
+/// returns no rows when the record's embeddings were written before provenance was recorded
+#[pg_extern]
+fn provenance(
+    job_name: &str,
+    record_id: &str,
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(model, String),
+            name!(provider, String),
+            name!(endpoint, String),
+            name!(content_hash, String),
+            name!(trace_id, String),
+            name!(vectorize_version, Option<String>),
+            name!(embedded_at, TimestampWithTimeZone),
+        ),
+    >,
+> {
+    let rows = provenance::provenance(job_name, record_id)?.map(|p| {
+        (
+            p.model,
+            p.provider,
+            p.endpoint,
+            p.content_hash,
+            p.trace_id,
+            p.vectorize_version,
+            p.embedded_at,
+        )
+    });
+    Ok(compat::table(rows))
+}
+
+/// lists jobs using a deprecated transformer
+/// when dry_run is false, each of those jobs is migrated to the replacement model in the background
+#[pg_extern]
+fn migrate_deprecated_models(
+    dry_run: default!(bool, true),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(job_name, String),
+            name!(transformer, String),
+            name!(replacement, String),
+            name!(status, String),
+        ),
+    >,
+> {
+    let mut results = Vec::new();
+    for (job_name, transformer, replacement) in search::deprecated_jobs()? {
+        let status = if dry_run {
+            "deprecated".to_string()
+        } else {
+            // searches keep using the deprecated model until the replacement has embedded every row
+            if !model_migration::is_migrating(&job_name)? {
+                model_migration::migrate_model(&job_name, &replacement.to_string())?;
+            }
+            "migrating".to_string()
+        };
+        results.push((
+            job_name,
+            transformer.to_string(),
+            replacement.to_string(),
+            status,
+        ));
+    }
+    Ok(compat::table(results))
+}
+
 #[pg_extern]
 fn search(
     job_name: String,
@@ -48,7 +701,24 @@ fn search(
     return_columns: default!(Vec<String>, "ARRAY['*']::text[]"),
     num_results: default!(i32, 10),
     where_sql: default!(Option<String>, "NULL"),
+    // fall back to full-text search when the query cannot be embedded
+    lexical_fallback: default!(bool, false),
+    // also run a full-text search over rows that do not have embeddings yet, and merge the results
+    include_unembedded: default!(bool, false),
+    // the metric to score results with: cosine, l2 or ip. defaults to the metric of the job's index
+    metric: default!(Option<String>, "NULL"),
+    // for chunked jobs, returns the rows the chunks were taken from in place of the chunks
+    parent_document: default!(bool, false),
+    // for chunked jobs, returns each chunk along with the text of this many chunks on either side of it
+    chunk_window: default!(Option<i32>, "NULL"),
+    // reorders the results with a reranking model, e.g. cohere/rerank-english-v3.0
+    rerank_model: default!(Option<String>, "NULL"),
 ) -> Result<TableIterator<'static, (name!(search_results, pgrx::JsonB),)>> {
+    let metric = metric
+        .map(|m| m.parse::<DistanceMetric>().map_err(|e| anyhow!(e)))
+        .transpose()?;
+    let retrieval = search::ChunkRetrieval::new(parent_document, chunk_window)?;
+    let rerank_model = rerank_model.as_deref().map(registry::resolve).transpose()?;
     let search_results = search::search(
         &job_name,
         &query,
@@ -56,8 +726,37 @@ fn search(
         return_columns,
         num_results,
         where_sql,
-    )?;
-    Ok(TableIterator::new(search_results.into_iter().map(|r| (r,))))
+        lexical_fallback,
+        include_unembedded,
+        metric,
+        retrieval,
+        rerank_model.as_ref(),
+    )
+    .map_err(budget::report_exceeded)?;
+    Ok(compat::table(search_results.into_iter().map(|r| (r,))))
+}
+
+/// writes search results as newline delimited json to a server-side file,
+/// or queues them for the background worker to POST to an http(s) callback
+#[pg_extern]
+fn search_export(
+    job_name: &str,
+    query: &str,
+    destination: &str,
+    format: default!(&str, "'ndjson'"),
+    num_results: default!(i32, 1000),
+    where_sql: default!(Option<String>, "NULL"),
+    api_key: default!(Option<String>, "NULL"),
+) -> Result<i64> {
+    export::search_export(
+        job_name,
+        query,
+        destination,
+        format,
+        num_results,
+        where_sql,
+        api_key,
+    )
 }
 
 #[pg_extern]
@@ -65,9 +764,38 @@ fn transform_embeddings(
     input: &str,
     model_name: default!(String, "'sentence-transformers/all-MiniLM-L6-v2'"),
     api_key: default!(Option<String>, "NULL"),
+    // asked of models that accept it, and otherwise kept as the leading dimensions, scaled back to unit length
+    dimensions: default!(Option<i32>, "NULL"),
+    // document or query, for models that embed search queries apart from the documents they search
+    input_type: default!(&str, "'document'"),
 ) -> Result<Vec<f64>> {
-    let model = Model::new(&model_name)?;
-    Ok(transform(input, &model, api_key).remove(0))
+    let input_type = input_type.parse::<InputType>().map_err(|e| anyhow!(e))?;
+    Ok(embed(&[input], &model_name, api_key, dimensions, input_type)?.remove(0))
+}
+
+// embeds texts with a model, asking it for the dimensions when it accepts them and otherwise truncating to them
+fn embed(
+    inputs: &[&str],
+    model: &str,
+    api_key: Option<String>,
+    dimensions: Option<i32>,
+    input_type: InputType,
+) -> Result<Vec<Vec<f64>>> {
+    let model = registry::resolve(model)?;
+    let dimensions = positive_dimensions(dimensions)?;
+    let embeddings = transform_batch(inputs, &model, api_key, None, input_type, dimensions)?;
+    match dimensions {
+        Some(dimensions) => Ok(truncate_dimensions(embeddings, dimensions)?),
+        None => Ok(embeddings),
+    }
+}
+
+fn positive_dimensions(dimensions: Option<i32>) -> Result<Option<u32>> {
+    match dimensions {
+        Some(d) if d <= 0 => bail!("dimensions must be positive, got {d}"),
+        Some(d) => Ok(Some(d as u32)),
+        None => Ok(None),
+    }
 }
 
 #[pg_extern]
@@ -75,9 +803,245 @@ fn encode(
     input: &str,
     model: default!(String, "'sentence-transformers/all-MiniLM-L6-v2'"),
     api_key: default!(Option<String>, "NULL"),
+    // as for transform_embeddings
+    dimensions: default!(Option<i32>, "NULL"),
 ) -> Result<Vec<f64>> {
-    let model = Model::new(&model)?;
-    Ok(transform(input, &model, api_key).remove(0))
+    Ok(embed(&[input], &model, api_key, dimensions, InputType::Document)?.remove(0))
+}
+
+/// embeds an array of texts in one batched request, returning the embedding of each text by its index in the array
+/// NULL texts are skipped
+#[pg_extern(name = "encode")]
+fn encode_batch(
+    inputs: Vec<Option<String>>,
+    model: default!(String, "'sentence-transformers/all-MiniLM-L6-v2'"),
+    api_key: default!(Option<String>, "NULL"),
+    dimensions: default!(Option<i32>, "NULL"),
+) -> Result<TableIterator<'static, (name!(idx, i32), name!(embedding, Vec<f64>))>> {
+    // array indexes start at 1
+    let (idx, texts): (Vec<i32>, Vec<&str>) = inputs
+        .iter()
+        .zip(1..)
+        .filter_map(|(input, i)| input.as_deref().map(|text| (i, text)))
+        .unzip();
+    let embeddings = if texts.is_empty() {
+        Vec::new()
+    } else {
+        embed(&texts, &model, api_key, dimensions, InputType::Document)?
+    };
+    Ok(compat::table(idx.into_iter().zip(embeddings)))
+}
+
+// encode() as pgvector's vector and as real[], which Postgres can not overload by return type alone
+extension_sql!(
+    r#"
+CREATE FUNCTION vectorize."encode_vector"(
+    "input" TEXT,
+    "model" TEXT DEFAULT 'sentence-transformers/all-MiniLM-L6-v2',
+    "api_key" TEXT DEFAULT NULL,
+    "dimensions" INT DEFAULT NULL,
+    "input_type" TEXT DEFAULT 'document'
+) RETURNS vector
+LANGUAGE sql
+AS $$
+    SELECT vectorize.transform_embeddings("input", "model", "api_key", "dimensions", "input_type")::vector
+$$;
+
+CREATE FUNCTION vectorize."encode_real"(
+    "input" TEXT,
+    "model" TEXT DEFAULT 'sentence-transformers/all-MiniLM-L6-v2',
+    "api_key" TEXT DEFAULT NULL,
+    "dimensions" INT DEFAULT NULL,
+    "input_type" TEXT DEFAULT 'document'
+) RETURNS real[]
+LANGUAGE sql
+AS $$
+    SELECT vectorize.transform_embeddings("input", "model", "api_key", "dimensions", "input_type")::real[]
+$$;
+"#,
+    name = "encode_vector",
+    requires = [transform_embeddings]
+);
+
+/// splits a text into chunks of at most chunk_size tokens, as counted by the transformer's tokenizer
+/// each chunk starts chunk_overlap tokens before the end of the previous one
+/// stable rather than immutable, as the tokenizer can be read from vectorize.onnx_model_dir
+#[pg_extern(stable, parallel_safe)]
+fn chunk_text(
+    text: &str,
+    chunk_size: i32,
+    chunk_overlap: default!(i32, 0),
+    transformer: default!(Option<&str>, "NULL"),
+) -> Result<Vec<String>> {
+    if chunk_overlap < 0 {
+        bail!("chunk_overlap can not be negative, got {chunk_overlap}");
+    }
+    let model = transformer.map(Model::new).transpose()?;
+    let bpe = chunking::tokenizer(model.as_ref())?;
+    let chunks = vectorize_core::chunking::chunk_tokens(
+        text,
+        chunk_size.max(0) as usize,
+        chunk_overlap as usize,
+        bpe,
+    )?;
+    Ok(chunks.into_iter().map(|chunk| chunk.text).collect())
+}
+
+/// splits a column of each row of a table into chunks, written to a new table along with the row's primary key
+/// the chunks are a snapshot, they are not updated when the table's rows change
+#[allow(clippy::too_many_arguments)]
+#[pg_extern]
+fn chunk_table(
+    input_table: &str,
+    column_name: &str,
+    primary_key: &str,
+    chunk_size: i32,
+    chunk_overlap: default!(i32, 0),
+    // <input_table>_chunks when NULL, in the same schema as the input table
+    output_table: default!(Option<String>, "NULL"),
+    schema: default!(&str, "'public'"),
+    // tokens, recursive, sentence, markdown, code, separator or window, defaults to tokens
+    chunk_strategy: default!(Option<String>, "NULL"),
+    separators: default!(Option<Vec<String>>, "NULL"),
+    // the regular expression the separator strategy splits on
+    separator: default!(Option<String>, "NULL"),
+    // the tokens each window of the window strategy starts after the previous one
+    chunk_stride: default!(Option<i32>, "NULL"),
+    // discards chunks before they are written: min_tokens, exclude_patterns or a boolean function of the chunk's text
+    chunk_filter: default!(Option<pgrx::JsonB>, "NULL"),
+    transformer: default!(Option<&str>, "NULL"),
+    // html strips the markup and boilerplate of each row before it is chunked
+    preprocess: default!(Option<String>, "NULL"),
+) -> Result<String> {
+    let mut params = chunking::chunk_params(
+        chunk_size,
+        chunk_overlap,
+        chunk_strategy.as_deref(),
+        separators,
+        None,
+        separator,
+        chunk_stride,
+    )?;
+    params.filter = chunk_filter
+        .map(|f| chunking::chunk_filter(f.0))
+        .transpose()?;
+    let model = transformer.map(registry::resolve).transpose()?;
+    let output_table = output_table.unwrap_or_else(|| format!("{input_table}_chunks"));
+    chunking::chunk_table(
+        schema,
+        input_table,
+        vec![column_name.to_string()],
+        primary_key,
+        params,
+        &output_table,
+        parse_preprocess(preprocess.as_deref())?,
+        model.as_ref(),
+    )
+}
+
+/// chunks the rows of a chunked job again with new chunk params
+/// chunks whose text did not change keep their embeddings, only new chunks are embedded
+#[pg_extern]
+fn rechunk(
+    job_name: &str,
+    chunk_size: i32,
+    chunk_overlap: default!(i32, 0),
+    // tokens, recursive, sentence, markdown, code, semantic, separator or window, defaults to tokens
+    chunk_strategy: default!(Option<String>, "NULL"),
+    chunk_separators: default!(Option<Vec<String>>, "NULL"),
+    chunk_similarity_threshold: default!(Option<f64>, "NULL"),
+    chunk_separator: default!(Option<String>, "NULL"),
+    chunk_stride: default!(Option<i32>, "NULL"),
+    chunk_column_params: default!(Option<pgrx::JsonB>, "NULL"),
+    chunk_filter: default!(Option<pgrx::JsonB>, "NULL"),
+) -> Result<String> {
+    let mut params = chunking::chunk_params(
+        chunk_size,
+        chunk_overlap,
+        chunk_strategy.as_deref(),
+        chunk_separators,
+        chunk_similarity_threshold,
+        chunk_separator,
+        chunk_stride,
+    )?;
+    params.filter = chunk_filter
+        .map(|f| chunking::chunk_filter(f.0))
+        .transpose()?;
+    chunking::rechunk(job_name, params, chunk_column_params.map(|p| p.0))
+}
+
+/// registers a model under its own name, so jobs and rag() can use it without a source prefix
+/// options are sent with every request to the model's provider, e.g. {"model": "my-deployment"}
+#[pg_extern]
+fn register_model(
+    name: &str,
+    source: &str,
+    dimensions: default!(Option<i32>, "NULL"),
+    endpoint: default!(Option<&str>, "NULL"),
+    options: default!(Option<pgrx::JsonB>, "NULL"),
+) -> Result<String> {
+    registry::register_model(
+        name,
+        source,
+        dimensions,
+        endpoint,
+        options
+            .map(|o| o.0)
+            .unwrap_or_else(|| serde_json::json!({})),
+    )
+}
+
+#[pg_extern]
+fn unregister_model(name: &str) -> Result<String> {
+    registry::unregister_model(name)
+}
+
+fn parse_preprocess(preprocess: Option<&str>) -> Result<Option<Preprocess>> {
+    preprocess
+        .map(|p| p.parse::<Preprocess>().map_err(|e| anyhow!(e)))
+        .transpose()
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn vec_add(a: Vec<f64>, b: Vec<f64>) -> Result<Vec<f64>> {
+    arithmetic::add(&a, &b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn vec_sub(a: Vec<f64>, b: Vec<f64>) -> Result<Vec<f64>> {
+    arithmetic::sub(&a, &b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn vec_normalize(v: Vec<f64>) -> Result<Vec<f64>> {
+    arithmetic::normalize(&v)
+}
+
+/// the stored embedding of a single record in a job
+#[pg_extern]
+fn embedding(job_name: &str, record_id: &str) -> Result<Vec<f64>> {
+    arithmetic::get_embedding(job_name, record_id)
+}
+
+/// element-wise mean of the stored embeddings of a set of records, e.g. to build a profile
+#[pg_extern]
+fn vec_avg(job_name: &str, record_ids: Vec<String>) -> Result<Vec<f64>> {
+    let embeddings = arithmetic::get_embeddings(job_name, &record_ids)?;
+    arithmetic::average(&embeddings)
+}
+
+#[pg_extern]
+fn cosine_similarity(job_name: &str, record_a: &str, record_b: &str) -> Result<f64> {
+    let embeddings =
+        arithmetic::get_embeddings(job_name, &[record_a.to_string(), record_b.to_string()])?;
+    arithmetic::cosine_similarity(&embeddings[0], &embeddings[1])
+}
+
+#[pg_extern]
+fn dot_product(job_name: &str, record_a: &str, record_b: &str) -> Result<f64> {
+    let embeddings =
+        arithmetic::get_embeddings(job_name, &[record_a.to_string(), record_b.to_string()])?;
+    arithmetic::dot(&embeddings[0], &embeddings[1])
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -94,10 +1058,19 @@ fn init_rag(
     transformer: default!(&str, "'sentence-transformers/all-MiniLM-L6-v2'"),
     table_method: default!(types::TableMethod, "'join'"),
     schedule: default!(&str, "'* * * * *'"),
+    // field -> column locating each row within its source document, returned with the context
+    // document_id is required, chunk_index, start_offset and end_offset are optional
+    provenance: default!(Option<pgrx::JsonB>, "NULL"),
 ) -> Result<String> {
     // chat only supports single columns transform
     let columns = vec![column.to_string()];
-    let transformer_model = Model::new(transformer)?;
+    let transformer_model = registry::resolve(transformer)?;
+    let provenance = match provenance {
+        Some(provenance) => Some(serde_json::from_value(provenance.0).context(
+            "provenance must be an object of document_id, chunk_index, start_offset or end_offset to column name",
+        )?),
+        None => None,
+    };
     init_table(
         agent_name,
         schema,
@@ -106,12 +1079,56 @@ fn init_rag(
         unique_record_id,
         None,
         index_dist_type.into(),
+        IndexParams::default(),
+        None,
+        BTreeMap::new(),
+        false,
+        None,
+        None,
+        provenance,
+        false,
+        None,
+        VectorStorage::default(),
+        StorageParams::default(),
+        None,
+        None,
+        None,
+        false,
+        ContentType::text,
         &transformer_model,
         table_method.into(),
         schedule,
     )
 }
 
+/// adds the system and user prompt templates of a task, which rag() is then given as its task
+/// the user prompt is rendered with {{ context_str }} and {{ query_str }}
+#[pg_extern]
+fn create_prompt(task: &str, sys_prompt: &str, user_prompt: &str) -> Result<String> {
+    prompts::create_prompt(task, sys_prompt, user_prompt)
+}
+
+/// replaces the templates of a task that are given, keeping the others
+#[pg_extern]
+fn update_prompt(
+    task: &str,
+    sys_prompt: default!(Option<&str>, "NULL"),
+    user_prompt: default!(Option<&str>, "NULL"),
+) -> Result<String> {
+    prompts::update_prompt(task, sys_prompt, user_prompt)
+}
+
+#[pg_extern]
+fn delete_prompt(task: &str) -> Result<String> {
+    prompts::delete_prompt(task)
+}
+
+/// forgets the prior turns of a rag() session
+#[pg_extern]
+fn delete_session(session_id: &str) -> Result<String> {
+    history::delete_session(session_id)
+}
+
 /// creates a table indexed with embeddings for chat completion workloads
 #[pg_extern]
 fn rag(
@@ -125,8 +1142,13 @@ fn rag(
     num_context: default!(i32, 2),
     // truncates context to fit the model's context window
     force_trim: default!(bool, false),
+    // picks the context from the search results reranked with this model
+    rerank_model: default!(Option<String>, "NULL"),
+    // includes the prior turns of this session in the prompt, and adds this one to them
+    session_id: default!(Option<&str>, "NULL"),
 ) -> Result<TableIterator<'static, (name!(chat_results, pgrx::JsonB),)>> {
-    let model = Model::new(&chat_model)?;
+    let model = registry::resolve(&chat_model)?;
+    let rerank_model = rerank_model.as_deref().map(registry::resolve).transpose()?;
     let resp = call_chat(
         agent_name,
         query,
@@ -135,9 +1157,100 @@ fn rag(
         api_key,
         num_context,
         force_trim,
-    )?;
+        rerank_model.as_ref(),
+        session_id,
+    )
+    .map_err(budget::report_exceeded)?;
     let iter = vec![(pgrx::JsonB(serde_json::to_value(resp)?),)];
-    Ok(TableIterator::new(iter))
+    Ok(compat::table(iter))
+}
+
+/// runs a search and answers the query from its top results, embedding the query once
+/// returns the search results, as a json array, along with the chat response
+#[allow(clippy::too_many_arguments)]
+#[pg_extern]
+fn search_and_rag(
+    agent_name: &str,
+    query: &str,
+    chat_model: default!(String, "'tembo/meta-llama/Meta-Llama-3-8B-Instruct'"),
+    task: default!(String, "'question_answer'"),
+    api_key: default!(Option<String>, "NULL"),
+    return_columns: default!(Vec<String>, "ARRAY['*']::text[]"),
+    num_results: default!(i32, 10),
+    // number of the top search results to include in the context
+    num_context: default!(i32, 2),
+    where_sql: default!(Option<String>, "NULL"),
+    force_trim: default!(bool, false),
+    rerank_model: default!(Option<String>, "NULL"),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(search_results, pgrx::JsonB),
+            name!(chat_results, pgrx::JsonB),
+        ),
+    >,
+> {
+    let model = registry::resolve(&chat_model)?;
+    let rerank_model = rerank_model.as_deref().map(registry::resolve).transpose()?;
+    let (search_results, resp) = search_and_chat(
+        agent_name,
+        query,
+        &model,
+        &task,
+        api_key,
+        return_columns,
+        num_results,
+        num_context,
+        where_sql,
+        force_trim,
+        rerank_model.as_ref(),
+    )
+    .map_err(budget::report_exceeded)?;
+    let search_results: Vec<serde_json::Value> = search_results.into_iter().map(|r| r.0).collect();
+    Ok(compat::one_row((
+        pgrx::JsonB(serde_json::to_value(search_results)?),
+        pgrx::JsonB(serde_json::to_value(resp)?),
+    )))
+}
+
+/// answers every question in a table with a rag agent, writing the responses to `output_table`
+/// processed on a schedule and resumable, questions already in `output_table` are skipped
+#[allow(clippy::too_many_arguments)]
+#[pg_extern]
+fn rag_batch(
+    agent_name: &str,
+    questions_table: &str,
+    output_table: &str,
+    schema: default!(&str, "'public'"),
+    question_column: default!(&str, "'question'"),
+    id_column: default!(&str, "'id'"),
+    chat_model: default!(String, "'tembo/meta-llama/Meta-Llama-3-8B-Instruct'"),
+    task: default!(String, "'question_answer'"),
+    num_context: default!(i32, 2),
+    force_trim: default!(bool, false),
+    // upper bound on the number of questions answered per minute
+    questions_per_minute: default!(i32, 60),
+) -> Result<String> {
+    if questions_per_minute < 1 {
+        return Err(anyhow::anyhow!(
+            "questions_per_minute must be greater than 0"
+        ));
+    }
+    let params = RagBatchParams {
+        agent_name: agent_name.to_string(),
+        schema: schema.to_string(),
+        questions_table: questions_table.to_string(),
+        question_column: question_column.to_string(),
+        id_column: id_column.to_string(),
+        output_table: output_table.to_string(),
+        chat_model,
+        task,
+        num_context,
+        force_trim,
+        questions_per_minute,
+    };
+    init_rag_batch(&params)
 }
 
 #[pg_extern]
@@ -146,24 +1259,22 @@ fn generate(
     model: default!(String, "'tembo/meta-llama/Meta-Llama-3-8B-Instruct'"),
     api_key: default!(Option<String>, "NULL"),
 ) -> Result<String> {
-    let model = Model::new(&model)?;
+    let model = registry::resolve(&model)?;
     let prompt = RenderedPrompt {
         sys_rendered: "".to_string(),
         user_rendered: input.to_string(),
+        history: Vec::new(),
     };
-    let mut guc_configs = get_guc_configs(&model.source);
+    let mut guc_configs = get_model_guc_configs(&model)?;
     if let Some(api_key) = api_key {
         guc_configs.api_key = Some(api_key);
     }
-    call_chat_completions(prompt, &model, &guc_configs)
+    Ok(call_chat_completions(prompt, &model, &guc_configs, guc::timeouts())?.content)
 }
 
 #[pg_extern]
 fn env_interpolate_guc(guc_name: &str) -> Result<String> {
-    let g: String = Spi::get_one_with_args(
-        "SELECT current_setting($1)",
-        vec![(PgBuiltInOids::TEXTOID.oid(), guc_name.into_datum())],
-    )?
-    .unwrap_or_else(|| panic!("no value set for guc: {guc_name}"));
+    let g: String = compat::get_one("SELECT current_setting($1)", vec![arg(guc_name)])?
+        .unwrap_or_else(|| panic!("no value set for guc: {guc_name}"));
     env_interpolate_string(&g)
 }
@@ -0,0 +1,140 @@
+use crate::compat::{self, arg};
+use crate::credential;
+use crate::guc;
+use crate::registry::parse_source;
+use crate::transformers::get_provider;
+use crate::util::get_pg_conn;
+
+use anyhow::{anyhow, bail, Context, Result};
+use pgrx::prelude::*;
+use sqlx::Executor;
+use vectorize_core::transformers::http_handler::{with_headers, with_proxies, with_timeouts};
+use vectorize_core::transformers::providers::{
+    prepare_generic_embedding_request, validation_model,
+};
+use vectorize_core::transformers::types::Inputs;
+use vectorize_core::types::{Model, ModelSource};
+
+/// the model a provider's key is checked with, the given one or a small one of the provider's
+pub fn model_to_validate(source: &ModelSource, model: Option<&str>) -> Result<Model> {
+    let name = model.or(validation_model(source)).with_context(|| {
+        format!("{source} models are deployed per account, give the model to validate the key with")
+    })?;
+    Ok(Model::new(&format!("{source}/{name}"))?)
+}
+
+// sends a one word embedding request with the key, once
+fn check_key(model: &Model, api_key: String, virtual_key: Option<String>) -> Result<()> {
+    let guc_configs = guc::get_model_guc_configs(model)?;
+    let provider = get_provider(
+        model,
+        Some(api_key),
+        guc_configs.service_url,
+        virtual_key.or(guc_configs.virtual_key),
+        Some(1),
+    )?;
+    let input = Inputs {
+        record_id: "".to_string(),
+        inputs: "ping".to_string(),
+        token_estimate: 1,
+    };
+    let request = prepare_generic_embedding_request(model, &[input]);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
+    runtime
+        .block_on(with_headers(
+            guc::headers(model)?,
+            with_proxies(
+                guc::proxies()?,
+                with_timeouts(guc::timeouts(), provider.generate_embedding(&request)),
+            ),
+        ))
+        .map_err(|e| anyhow!("{} rejected the API key: {e}", model.source))?;
+    Ok(())
+}
+
+/// checks an API key with a one word embedding request to the provider, the key in its GUC when none is given
+pub fn validate_api_key(
+    provider: &str,
+    api_key: Option<String>,
+    model: Option<&str>,
+) -> Result<String> {
+    let source = parse_source(provider)?;
+    let model = model_to_validate(&source, model)?;
+    let api_key = match api_key.or(guc::get_model_guc_configs(&model)?.api_key) {
+        Some(key) => key,
+        None => match guc::api_key_guc(&source) {
+            Some(name) => bail!("no API key is set for {source}, set {name}"),
+            None => bail!("no API key is set for {source}"),
+        },
+    };
+    check_key(&model, api_key, None)?;
+    Ok(format!("API key for {source} is valid"))
+}
+
+/// checks a new API key, then puts it in place of the key of a credential, or of the provider's GUC
+/// the GUC is set with ALTER SYSTEM over a connection of its own, so it is not undone by rolling back
+pub fn rotate_api_key(
+    provider: &str,
+    new_key: &str,
+    credential_name: Option<&str>,
+    model: Option<&str>,
+) -> Result<String> {
+    let source = parse_source(provider)?;
+    let model = model_to_validate(&source, model)?;
+    match credential_name {
+        Some(name) => {
+            let credential = credential::get(name)?;
+            if credential.source != source {
+                bail!(
+                    "credential {name} is for {}, not for {source}",
+                    credential.source
+                );
+            }
+            check_key(&model, new_key.to_string(), credential.virtual_key)?;
+            compat::run(
+                "UPDATE vectorize.credentials SET api_key = $2 WHERE name = $1",
+                vec![arg(name), arg(new_key)],
+            )?;
+            Ok(format!("Rotated API key of credential {name}"))
+        }
+        None => {
+            let Some(guc_name) = guc::api_key_guc(&source) else {
+                bail!("the key of {source} is not a single GUC, set its GUCs with ALTER SYSTEM");
+            };
+            // the worker's connection may set GUCs that the caller could not
+            let superuser: Option<String> =
+                compat::get_one("SELECT current_setting('is_superuser')", vec![])?;
+            if superuser.as_deref() != Some("on") {
+                bail!("only superusers can rotate the API key of {guc_name}");
+            }
+            check_key(&model, new_key.to_string(), None)?;
+            set_system_guc(guc_name, new_key)?;
+            Ok(format!("Rotated API key of {source} in {guc_name}"))
+        }
+    }
+}
+
+// ALTER SYSTEM can not run in a transaction, so it is sent over a connection of the worker's
+fn set_system_guc(name: &str, value: &str) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
+    runtime.block_on(async {
+        let conn = get_pg_conn().await?;
+        let statement: String =
+            sqlx::query_scalar("SELECT format('ALTER SYSTEM SET %s TO %L', $1::text, $2::text)")
+                .bind(name)
+                .bind(value)
+                .fetch_one(&conn)
+                .await?;
+        conn.execute(statement.as_str()).await?;
+        conn.execute("SELECT pg_reload_conf()").await?;
+        Ok::<(), anyhow::Error>(())
+    })
+}
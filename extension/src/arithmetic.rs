@@ -0,0 +1,153 @@
+use crate::compat::{self, arg};
+use crate::{init, util};
+
+use anyhow::{anyhow, bail, Result};
+use pgrx::prelude::*;
+use vectorize_core::types::JobParams;
+
+fn check_dims(a: &[f64], b: &[f64]) -> Result<()> {
+    if a.len() != b.len() {
+        bail!("vector dimensions do not match: {} != {}", a.len(), b.len());
+    }
+    Ok(())
+}
+
+pub fn add(a: &[f64], b: &[f64]) -> Result<Vec<f64>> {
+    check_dims(a, b)?;
+    Ok(a.iter().zip(b).map(|(x, y)| x + y).collect())
+}
+
+pub fn sub(a: &[f64], b: &[f64]) -> Result<Vec<f64>> {
+    check_dims(a, b)?;
+    Ok(a.iter().zip(b).map(|(x, y)| x - y).collect())
+}
+
+pub fn dot(a: &[f64], b: &[f64]) -> Result<f64> {
+    check_dims(a, b)?;
+    Ok(a.iter().zip(b).map(|(x, y)| x * y).sum())
+}
+
+fn norm(a: &[f64]) -> f64 {
+    a.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+/// scales a vector to unit length
+pub fn normalize(a: &[f64]) -> Result<Vec<f64>> {
+    let n = norm(a);
+    if n == 0.0 {
+        bail!("cannot normalize a zero vector");
+    }
+    Ok(a.iter().map(|x| x / n).collect())
+}
+
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> Result<f64> {
+    let d = dot(a, b)?;
+    let n = norm(a) * norm(b);
+    if n == 0.0 {
+        bail!("cosine similarity is undefined for a zero vector");
+    }
+    Ok(d / n)
+}
+
+/// element-wise mean of a set of vectors
+pub fn average(vectors: &[Vec<f64>]) -> Result<Vec<f64>> {
+    let first = vectors
+        .first()
+        .ok_or_else(|| anyhow!("cannot average an empty set of vectors"))?;
+    let mut sum = vec![0.0; first.len()];
+    for v in vectors {
+        sum = add(&sum, v)?;
+    }
+    let count = vectors.len() as f64;
+    Ok(sum.into_iter().map(|x| x / count).collect())
+}
+
+/// query returning the stored embeddings for a set of primary keys
+/// takes the keys as a text array in $1
+fn embeddings_by_pk_query(job_name: &str, job_params: &JobParams) -> String {
+    let (schema, table, embeddings_col) = init::embeddings_location(job_name, job_params);
+    let pkey = job_params.pkey();
+    format!(
+        "SELECT {record_id} AS record_id, {searchable}::real[]::float8[] AS embeddings
+        FROM {schema}.{table}
+        WHERE {matches}
+        AND {embeddings_col} IS NOT NULL",
+        record_id = pkey.record_id(None),
+        matches = pkey.matches_record_ids(None, "$1"),
+        searchable = job_params.searchable_embeddings(&embeddings_col),
+    )
+}
+
+/// fetches the stored embeddings for the given records of a job, in the order they were requested
+pub fn get_embeddings(job_name: &str, record_ids: &[String]) -> Result<Vec<Vec<f64>>> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: JobParams = serde_json::from_value(meta.params)?;
+    let query = embeddings_by_pk_query(job_name, &job_params);
+
+    let rows: Vec<(String, Vec<f64>)> = compat::connect(|client| {
+        let mut rows = Vec::new();
+        let tup_table = compat::select(&client, &query, vec![arg(record_ids.to_vec())])?;
+        for row in tup_table {
+            let record_id: String = row["record_id"]
+                .value()?
+                .ok_or_else(|| anyhow!("record_id was null"))?;
+            let embeddings: Vec<f64> = row["embeddings"]
+                .value()?
+                .ok_or_else(|| anyhow!("embeddings were null"))?;
+            rows.push((record_id, embeddings));
+        }
+        Ok::<_, anyhow::Error>(rows)
+    })?;
+
+    record_ids
+        .iter()
+        .map(|id| {
+            rows.iter()
+                .find(|(record_id, _)| record_id == id)
+                .map(|(_, embeddings)| embeddings.clone())
+                .ok_or_else(|| anyhow!("no embeddings found for record '{id}' in job {job_name}"))
+        })
+        .collect()
+}
+
+pub fn get_embedding(job_name: &str, record_id: &str) -> Result<Vec<f64>> {
+    Ok(get_embeddings(job_name, &[record_id.to_string()])?.remove(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_sub() {
+        let a = vec![1.0, 2.0, 3.0];
+        let b = vec![0.5, 0.5, 0.5];
+        assert_eq!(add(&a, &b).unwrap(), vec![1.5, 2.5, 3.5]);
+        assert_eq!(sub(&a, &b).unwrap(), vec![0.5, 1.5, 2.5]);
+        assert!(add(&a, &[1.0]).is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let v = normalize(&[3.0, 4.0]).unwrap();
+        assert_eq!(v, vec![0.6, 0.8]);
+        assert!(normalize(&[0.0, 0.0]).is_err());
+    }
+
+    #[test]
+    fn test_similarity() {
+        let a = vec![1.0, 0.0];
+        let b = vec![0.0, 2.0];
+        assert_eq!(dot(&a, &b).unwrap(), 0.0);
+        assert_eq!(cosine_similarity(&a, &b).unwrap(), 0.0);
+        assert_eq!(cosine_similarity(&a, &[2.0, 0.0]).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_average() {
+        let vectors = vec![vec![1.0, 2.0], vec![3.0, 4.0]];
+        assert_eq!(average(&vectors).unwrap(), vec![2.0, 3.0]);
+        assert!(average(&[]).is_err());
+        assert!(average(&[vec![1.0], vec![1.0, 2.0]]).is_err());
+    }
+}
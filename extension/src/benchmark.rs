@@ -0,0 +1,247 @@
+use crate::compat::{self, arg};
+use crate::init;
+use crate::util;
+
+use anyhow::{bail, Result};
+use pgrx::prelude::*;
+use sqlx::{Pool, Postgres};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use vectorize_core::types::JobParams;
+
+pub struct BenchmarkResult {
+    pub benchmark_id: i64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub qps: f64,
+    pub errors: i32,
+}
+
+// the latencies of the queries that succeeded, the number that failed and the first error
+#[derive(Default)]
+struct Load {
+    latencies_ms: Vec<f64>,
+    errors: i32,
+    first_error: Option<String>,
+    elapsed_secs: f64,
+}
+
+/// runs num_queries searches against a job from concurrency connections,
+/// and records their latency percentiles and throughput in vectorize.benchmark
+/// by default the queries are stored embeddings sampled from the job, so only the index is measured
+/// recorded_queries are run through vectorize.search instead, so their time includes embedding the query
+pub fn benchmark(
+    job_name: &str,
+    num_queries: i32,
+    concurrency: i32,
+    num_results: i32,
+    recorded_queries: Option<Vec<String>>,
+) -> Result<BenchmarkResult> {
+    if num_queries < 1 || concurrency < 1 || num_results < 1 {
+        bail!("queries, concurrency and num_results must be positive");
+    }
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: JobParams = serde_json::from_value(meta.params)?;
+
+    let (mode, query, inputs) = match recorded_queries {
+        Some(queries) if queries.is_empty() => bail!("recorded_queries must not be empty"),
+        Some(queries) => (
+            "recorded",
+            format!(
+                "SELECT 1 FROM vectorize.search(job_name => '{job_name}', query => $1, num_results => {num_results})"
+            ),
+            queries,
+        ),
+        None => {
+            let (schema, table, embeddings_col) = init::embeddings_location(job_name, &job_params);
+            let embeddings_col = job_params.searchable_embeddings(&embeddings_col);
+            let embeddings = sample_embeddings(&schema, &table, &embeddings_col, num_queries)?;
+            if embeddings.is_empty() {
+                bail!("job {job_name} has no embeddings to benchmark with");
+            }
+            (
+                "embeddings",
+                format!(
+                    "SELECT 1 FROM {schema}.{table}
+                    ORDER BY {embeddings_col} {op} $1::{vector_type}
+                    LIMIT {num_results}",
+                    op = meta.index_dist_type.distance_operator(),
+                    vector_type = job_params.vector_storage.vector_type(),
+                ),
+                embeddings,
+            )
+        }
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()?;
+    let load = runtime.block_on(async {
+        let pool = util::get_pg_pool(concurrency as u32).await?;
+        // open every connection up front, so that connecting is not timed as part of the first queries
+        let mut conns = Vec::new();
+        for _ in 0..concurrency {
+            conns.push(pool.acquire().await?);
+        }
+        drop(conns);
+        Ok::<_, anyhow::Error>(
+            run_load(
+                &pool,
+                Arc::new(query),
+                Arc::new(inputs),
+                num_queries as usize,
+                concurrency as usize,
+            )
+            .await,
+        )
+    })?;
+
+    let mut latencies = load.latencies_ms;
+    if latencies.is_empty() {
+        bail!(
+            "every benchmark query failed: {}",
+            load.first_error.unwrap_or_default()
+        );
+    }
+    if let Some(e) = load.first_error {
+        warning!(
+            "pg-vectorize: {} benchmark queries failed: {e}",
+            load.errors
+        );
+    }
+    latencies.sort_by(|a, b| a.total_cmp(b));
+    let mut result = BenchmarkResult {
+        benchmark_id: 0,
+        p50_ms: percentile(&latencies, 50.0),
+        p95_ms: percentile(&latencies, 95.0),
+        p99_ms: percentile(&latencies, 99.0),
+        qps: latencies.len() as f64 / load.elapsed_secs,
+        errors: load.errors,
+    };
+    result.benchmark_id = compat::get_one(
+        "INSERT INTO vectorize.benchmark
+            (job_name, mode, queries, concurrency, num_results, errors, p50_ms, p95_ms, p99_ms, qps)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING benchmark_id",
+        vec![
+            arg(job_name),
+            arg(mode),
+            arg(num_queries),
+            arg(concurrency),
+            arg(num_results),
+            arg(result.errors),
+            arg(result.p50_ms),
+            arg(result.p95_ms),
+            arg(result.p99_ms),
+            arg(result.qps),
+        ],
+    )?
+    .unwrap_or_default();
+    Ok(result)
+}
+
+// random stored embeddings, as vector literals
+fn sample_embeddings(
+    schema: &str,
+    table: &str,
+    embeddings_col: &str,
+    limit: i32,
+) -> Result<Vec<String>> {
+    let query = format!(
+        "SELECT {embeddings_col}::text AS q FROM {schema}.{table}
+        WHERE {embeddings_col} IS NOT NULL
+        ORDER BY random()
+        LIMIT $1"
+    );
+    compat::connect(|client| {
+        let mut embeddings = Vec::new();
+        for row in compat::select(&client, &query, vec![arg(limit)])? {
+            embeddings.extend(row["q"].value::<String>()?);
+        }
+        Ok(embeddings)
+    })
+}
+
+// each worker takes the next query until num_queries have been run
+// inputs are reused in turn when there are fewer of them than queries
+async fn run_load(
+    pool: &Pool<Postgres>,
+    query: Arc<String>,
+    inputs: Arc<Vec<String>>,
+    num_queries: usize,
+    concurrency: usize,
+) -> Load {
+    let next = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+    let mut workers = tokio::task::JoinSet::new();
+    for _ in 0..concurrency {
+        let (pool, query, inputs, next) =
+            (pool.clone(), query.clone(), inputs.clone(), next.clone());
+        workers.spawn(async move {
+            let mut load = Load::default();
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= num_queries {
+                    break;
+                }
+                let started = Instant::now();
+                match sqlx::query(&query)
+                    .bind(&inputs[i % inputs.len()])
+                    .execute(&pool)
+                    .await
+                {
+                    Ok(_) => load
+                        .latencies_ms
+                        .push(started.elapsed().as_secs_f64() * 1000.0),
+                    Err(e) => {
+                        load.errors += 1;
+                        load.first_error.get_or_insert(e.to_string());
+                    }
+                }
+            }
+            load
+        });
+    }
+
+    let mut total = Load::default();
+    while let Some(joined) = workers.join_next().await {
+        match joined {
+            Ok(load) => {
+                total.latencies_ms.extend(load.latencies_ms);
+                total.errors += load.errors;
+                total.first_error = total.first_error.or(load.first_error);
+            }
+            Err(e) => {
+                total.first_error.get_or_insert(e.to_string());
+            }
+        }
+    }
+    total.elapsed_secs = start.elapsed().as_secs_f64();
+    total
+}
+
+// nearest-rank percentile of latencies sorted in ascending order
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let latencies: Vec<f64> = (1..=100).map(|x| x as f64).collect();
+        assert_eq!(percentile(&latencies, 50.0), 50.0);
+        assert_eq!(percentile(&latencies, 95.0), 95.0);
+        assert_eq!(percentile(&latencies, 99.0), 99.0);
+        // a single query is every percentile
+        assert_eq!(percentile(&[3.5], 50.0), 3.5);
+        assert_eq!(percentile(&[3.5], 99.0), 3.5);
+        assert_eq!(percentile(&[1.0, 2.0, 3.0], 0.0), 1.0);
+    }
+}
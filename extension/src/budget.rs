@@ -0,0 +1,134 @@
+use crate::compat::{self, arg, SpiArg};
+
+use anyhow::{bail, Result};
+use pgrx::prelude::*;
+use thiserror::Error;
+use tiktoken_rs::cl100k_base;
+use vectorize_core::types::{Model, ModelSource};
+use vectorize_core::worker::ops::{
+    OVER_BUDGET_QUERY, RECORD_TOKEN_USAGE_QUERY, RECORD_USAGE_QUERY,
+};
+
+fn budget_args(job_name: &str, source: &ModelSource) -> Vec<SpiArg> {
+    vec![arg(job_name), arg(source.to_string())]
+}
+
+pub fn is_over_budget(job_name: &str, source: &ModelSource) -> Result<bool> {
+    Ok(compat::get_one(OVER_BUDGET_QUERY, budget_args(job_name, source))?.unwrap_or(false))
+}
+
+#[derive(Debug, Error)]
+#[error("token budget exceeded for job {job_name} or provider {provider}")]
+pub struct BudgetExceeded {
+    pub job_name: String,
+    pub provider: ModelSource,
+}
+
+/// errors with BudgetExceeded when the job, or the provider, has used up its token budget
+pub fn check_budget(job_name: &str, source: &ModelSource) -> Result<()> {
+    if is_over_budget(job_name, source)? {
+        return Err(BudgetExceeded {
+            job_name: job_name.to_string(),
+            provider: source.clone(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// raises BudgetExceeded errors with their own SQLSTATE (53400, configuration_limit_exceeded)
+/// so that callers can tell them apart from other failures, any other error is passed through
+pub fn report_exceeded(e: anyhow::Error) -> anyhow::Error {
+    if let Some(exceeded) = e.downcast_ref::<BudgetExceeded>() {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_CONFIGURATION_LIMIT_EXCEEDED,
+            exceeded.to_string(),
+            "requests resume when the budget window resets, or once the cap is raised with vectorize.set_budget()"
+        );
+    }
+    e
+}
+
+/// charges the texts sent to a provider, and those it sent back, against the job's and the provider's budgets
+/// and records them in the job's usage
+pub fn record_token_usage(
+    job_name: &str,
+    model: &Model,
+    sent: &[&str],
+    received: &[&str],
+) -> Result<()> {
+    let bpe = cl100k_base()?;
+    let count = |texts: &[&str]| -> i64 {
+        texts
+            .iter()
+            .map(|t| bpe.encode_with_special_tokens(t).len() as i64)
+            .sum()
+    };
+    record_tokens(job_name, model, count(sent), count(received))
+}
+
+// true when the job or its provider has a budget to charge
+fn has_budget(job_name: &str, source: &ModelSource) -> Result<bool> {
+    Ok(compat::get_one(
+        "SELECT EXISTS (
+            SELECT 1 FROM vectorize.budget
+            WHERE (scope = 'job' AND name = $1) OR (scope = 'provider' AND name = $2)
+        )",
+        budget_args(job_name, source),
+    )?
+    .unwrap_or(false))
+}
+
+/// charges tokens counted by the provider against the job's and the provider's budgets
+/// and records them in the job's usage
+/// nothing is written in read only transactions, e.g. searches on a hot standby
+pub fn record_tokens(job_name: &str, model: &Model, tokens_in: i64, tokens_out: i64) -> Result<()> {
+    let read_only: Option<String> =
+        compat::get_one("SELECT current_setting('transaction_read_only')", vec![])?;
+    if read_only.as_deref() == Some("on") {
+        return Ok(());
+    }
+    if has_budget(job_name, &model.source)? {
+        let mut args = budget_args(job_name, &model.source);
+        args.push(arg(tokens_in + tokens_out));
+        compat::run(RECORD_TOKEN_USAGE_QUERY, args)?;
+    }
+    compat::run(
+        RECORD_USAGE_QUERY,
+        vec![
+            arg(job_name),
+            arg(model.source.to_string()),
+            arg(model.to_string()),
+            arg(tokens_in),
+            arg(tokens_out),
+        ],
+    )?;
+    Ok(())
+}
+
+/// creates or updates a token budget
+/// changing the limit or period of an existing budget keeps the usage in its current window
+pub fn set_budget(scope: &str, name: &str, token_limit: i64, period: &str) -> Result<String> {
+    if !matches!(scope, "job" | "provider") {
+        bail!("invalid budget scope: {scope}, expected one of: job, provider");
+    }
+    compat::run(
+        "INSERT INTO vectorize.budget (scope, name, token_limit, period)
+        VALUES ($1, $2, $3, $4::interval)
+        ON CONFLICT (scope, name)
+        DO UPDATE SET token_limit = EXCLUDED.token_limit, period = EXCLUDED.period",
+        vec![arg(scope), arg(name), arg(token_limit), arg(period)],
+    )?;
+    Ok(format!(
+        "Set {scope} budget for {name}: {token_limit} tokens per {period}"
+    ))
+}
+
+pub fn remove_budget(scope: &str, name: &str) -> Result<String> {
+    compat::run(
+        "DELETE FROM vectorize.budget WHERE scope = $1 AND name = $2",
+        vec![arg(scope), arg(name)],
+    )?;
+    Ok(format!("Removed {scope} budget for {name}"))
+}
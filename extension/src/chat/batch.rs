@@ -0,0 +1,252 @@
+use crate::budget;
+use crate::chat::ops::call_chat;
+use crate::chat::types::RagBatchParams;
+use crate::compat::{self, arg};
+use crate::query::check_input;
+use crate::registry;
+use crate::types;
+use crate::util::get_vectorize_meta_spi;
+
+use anyhow::{Context, Result};
+use pgrx::prelude::*;
+use vectorize_core::types::Model;
+
+// questions that failed this many times are left unanswered, so they do not hold back the rest of the batch
+const MAX_QUESTION_ATTEMPTS: i32 = 3;
+
+/// registers a batch of questions to be answered by a rag agent
+/// questions are answered on a pg_cron schedule, at most `questions_per_minute` per run
+pub fn init_rag_batch(params: &RagBatchParams) -> Result<String> {
+    for ident in [
+        &params.schema,
+        &params.questions_table,
+        &params.question_column,
+        &params.id_column,
+        &params.output_table,
+    ] {
+        check_input(ident)?;
+    }
+    // fail early on an invalid model or a missing agent
+    registry::resolve(&params.chat_model)?;
+    get_vectorize_meta_spi(&params.agent_name)?;
+
+    let batch_name = rag_batch_name(params);
+    let create_output = format!(
+        "CREATE TABLE IF NOT EXISTS {schema}.{output_table} (
+            question_id TEXT PRIMARY KEY,
+            question TEXT NOT NULL,
+            chat_results jsonb NOT NULL,
+            answered_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+        );",
+        schema = params.schema,
+        output_table = params.output_table,
+    );
+    let batch_params = pgrx::JsonB(serde_json::to_value(params)?);
+    let ran: Result<_, spi::Error> = compat::connect(|mut c| {
+        compat::update(&mut c, &create_output, vec![])?;
+        compat::update(
+            &mut c,
+            &format!(
+                "INSERT INTO {schema}.rag_batch (name, params)
+                VALUES ($1, $2)
+                ON CONFLICT (name) DO UPDATE SET
+                    params = EXCLUDED.params,
+                    status = 'running',
+                    completed_at = NULL;",
+                schema = types::VECTORIZE_SCHEMA
+            ),
+            vec![arg(batch_name.clone()), arg(batch_params)],
+        )?;
+        // a batch that is created again gives its failed questions another try
+        compat::update(
+            &mut c,
+            "DELETE FROM vectorize.rag_batch_failures WHERE batch_name = $1",
+            vec![arg(batch_name.clone())],
+        )?;
+        Ok(())
+    });
+    ran?;
+
+    let cronjob = format!(
+        "SELECT cron.schedule(
+            '{cron_name}',
+            '* * * * *',
+            $$select vectorize._rag_batch_execute('{batch_name}')$$
+        );",
+        cron_name = rag_batch_cron_name(&batch_name),
+    );
+    let _: Option<i64> = compat::get_one(&cronjob, vec![])?;
+    Ok(format!("Successfully created rag batch: {batch_name}"))
+}
+
+/// called by pg_cron on schedule
+/// answers the next set of unanswered questions. resumes from whatever is already in the output table
+/// each question is answered in a subtransaction, so a failing question is rolled back on its own
+/// and recorded in vectorize.rag_batch_failures, where it is given up on after MAX_QUESTION_ATTEMPTS
+#[pg_extern]
+fn _rag_batch_execute(batch_name: &str) -> Result<i64> {
+    let params = get_rag_batch_params(batch_name)?;
+    let pending = pending_questions(batch_name, &params)?;
+    if pending.is_empty() {
+        complete_rag_batch(batch_name)?;
+        return Ok(0);
+    }
+
+    let chat_model = registry::resolve(&params.chat_model)?;
+    // batches are not interactive, so wait for the budget window to reset instead of failing each question
+    let agent_meta = get_vectorize_meta_spi(&params.agent_name)?;
+    if budget::is_over_budget(&params.agent_name, &agent_meta.transformer.source)?
+        || budget::is_over_budget(&params.agent_name, &chat_model.source)?
+    {
+        log!(
+            "pg-vectorize: rag batch {} is over its token budget, skipping",
+            batch_name
+        );
+        return Ok(0);
+    }
+    let insert_q = format!(
+        "INSERT INTO {schema}.{output_table} (question_id, question, chat_results)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (question_id) DO NOTHING;",
+        schema = params.schema,
+        output_table = params.output_table,
+    );
+    let mut num_answered: i64 = 0;
+    for (question_id, question) in pending {
+        let answered = compat::subtransaction(|| {
+            let resp = call_chat(
+                &params.agent_name,
+                &question,
+                &chat_model,
+                &params.task,
+                None,
+                params.num_context,
+                params.force_trim,
+                None,
+                None,
+            )?;
+            let chat_results = pgrx::JsonB(serde_json::to_value(resp)?);
+            compat::run(
+                &insert_q,
+                vec![
+                    arg(question_id.as_str()),
+                    arg(question.as_str()),
+                    arg(chat_results),
+                ],
+            )?;
+            Ok(())
+        });
+        match answered {
+            Ok(()) => num_answered += 1,
+            Err(e) => {
+                // left unanswered, so it is retried on the next run until it runs out of attempts
+                warning!(
+                    "pg-vectorize: rag batch {} failed on question {}: {}",
+                    batch_name,
+                    question_id,
+                    e
+                );
+                record_failure(batch_name, &question_id, &e.to_string())?;
+            }
+        }
+    }
+    log!(
+        "pg-vectorize: rag batch {}, answered {} questions",
+        batch_name,
+        num_answered
+    );
+    Ok(num_answered)
+}
+
+fn rag_batch_name(params: &RagBatchParams) -> String {
+    format!("{}.{}", params.schema, params.output_table)
+}
+
+fn rag_batch_cron_name(batch_name: &str) -> String {
+    format!("vectorize_rag_batch_{batch_name}")
+}
+
+fn get_rag_batch_params(batch_name: &str) -> Result<RagBatchParams> {
+    let params: pgrx::JsonB = compat::get_one(
+        "SELECT params FROM vectorize.rag_batch WHERE name = $1",
+        vec![arg(batch_name)],
+    )?
+    .context(format!("rag batch '{batch_name}' does not exist"))?;
+    Ok(serde_json::from_value(params.0)?)
+}
+
+// questions in the source table that do not yet have a row in the output table
+// and have attempts left, those that have not failed before first
+fn pending_questions(batch_name: &str, params: &RagBatchParams) -> Result<Vec<(String, String)>> {
+    let query = format!(
+        "SELECT q.{id_column}::text AS question_id, q.{question_column}::text AS question
+        FROM {schema}.{questions_table} q
+        LEFT JOIN {schema}.{output_table} o ON o.question_id = q.{id_column}::text
+        LEFT JOIN vectorize.rag_batch_failures f
+            ON f.batch_name = $1 AND f.question_id = q.{id_column}::text
+        WHERE o.question_id IS NULL
+            AND q.{question_column} IS NOT NULL
+            AND COALESCE(f.attempts, 0) < {max_attempts}
+        ORDER BY COALESCE(f.attempts, 0), q.{id_column}
+        LIMIT {limit};",
+        id_column = params.id_column,
+        question_column = params.question_column,
+        schema = params.schema,
+        questions_table = params.questions_table,
+        output_table = params.output_table,
+        max_attempts = MAX_QUESTION_ATTEMPTS,
+        limit = params.questions_per_minute,
+    );
+    let pending: Result<Vec<(String, String)>, spi::Error> = compat::connect(|c| {
+        let mut pending: Vec<(String, String)> = Vec::new();
+        let tup_table = compat::select(&c, &query, vec![arg(batch_name)])?;
+        for row in tup_table {
+            let question_id = row["question_id"]
+                .value::<String>()?
+                .expect("question_id is null");
+            let question = row["question"]
+                .value::<String>()?
+                .expect("question is null");
+            pending.push((question_id, question));
+        }
+        Ok(pending)
+    });
+    Ok(pending?)
+}
+
+// counts a failed attempt at a question, along with its latest error
+fn record_failure(batch_name: &str, question_id: &str, error: &str) -> Result<()> {
+    compat::run(
+        "INSERT INTO vectorize.rag_batch_failures (batch_name, question_id, error)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (batch_name, question_id)
+        DO UPDATE SET attempts = vectorize.rag_batch_failures.attempts + 1,
+            error = EXCLUDED.error, failed_at = now()",
+        vec![arg(batch_name), arg(question_id), arg(error)],
+    )?;
+    Ok(())
+}
+
+fn complete_rag_batch(batch_name: &str) -> Result<()> {
+    compat::run(
+        "UPDATE vectorize.rag_batch
+        SET status = 'completed', completed_at = NOW()
+        WHERE name = $1 AND status != 'completed';",
+        vec![arg(batch_name)],
+    )?;
+    let unschedule = format!(
+        "SELECT cron.unschedule(jobname) FROM cron.job WHERE jobname = '{}';",
+        rag_batch_cron_name(batch_name)
+    );
+    compat::run(&unschedule, vec![])?;
+    let failed: Option<i64> = compat::get_one(
+        "SELECT count(*) FROM vectorize.rag_batch_failures WHERE batch_name = $1 AND attempts >= $2",
+        vec![arg(batch_name), arg(MAX_QUESTION_ATTEMPTS)],
+    )?;
+    log!(
+        "pg-vectorize: rag batch {} completed, {} questions failed",
+        batch_name,
+        failed.unwrap_or(0)
+    );
+    Ok(())
+}
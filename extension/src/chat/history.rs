@@ -0,0 +1,63 @@
+use crate::compat::{self, arg};
+
+use anyhow::{bail, Result};
+use pgrx::prelude::*;
+use vectorize_core::transformers::providers::ChatMessageRequest;
+
+/// the most recent turns the current user had with an agent in a session, oldest first
+/// a turn is a question and its answer
+pub fn get(session_id: &str, agent_name: &str, turns: i32) -> Result<Vec<ChatMessageRequest>> {
+    if turns <= 0 {
+        return Ok(Vec::new());
+    }
+    compat::connect(|client| {
+        let tup_table = compat::select(
+            &client,
+            "SELECT role, content FROM (
+                SELECT id, role, content FROM vectorize.chat_history
+                WHERE session_id = $1 AND agent_name = $2 AND owner = current_user
+                ORDER BY id DESC
+                LIMIT $3
+            ) recent
+            ORDER BY id",
+            vec![arg(session_id), arg(agent_name), arg(turns as i64 * 2)],
+        )?;
+        let mut messages = Vec::new();
+        for row in tup_table {
+            messages.push(ChatMessageRequest {
+                role: row["role"].value()?.unwrap_or_default(),
+                content: row["content"].value()?.unwrap_or_default(),
+            });
+        }
+        Ok(messages)
+    })
+}
+
+/// adds a question and its answer to a session
+/// the question is stored without its context, which is searched for again on each turn
+/// the turns are owned by the current user, who is the only one to see them again
+pub fn record(session_id: &str, agent_name: &str, query: &str, response: &str) -> Result<()> {
+    compat::run(
+        "INSERT INTO vectorize.chat_history (session_id, agent_name, role, content)
+        VALUES ($1, $2, 'user', $3), ($1, $2, 'assistant', $4)",
+        vec![arg(session_id), arg(agent_name), arg(query), arg(response)],
+    )?;
+    Ok(())
+}
+
+/// removes the current user's turns of a session from vectorize.chat_history
+pub fn delete_session(session_id: &str) -> Result<String> {
+    let deleted: Option<i64> = compat::get_one(
+        "WITH deleted AS (
+            DELETE FROM vectorize.chat_history
+            WHERE session_id = $1 AND owner = current_user
+            RETURNING 1
+        )
+        SELECT count(*) FROM deleted",
+        vec![arg(session_id)],
+    )?;
+    match deleted {
+        Some(n) if n > 0 => Ok(format!("Deleted session {session_id}")),
+        _ => bail!("session {session_id} does not exist"),
+    }
+}
@@ -1,2 +1,5 @@
+pub mod batch;
+pub mod history;
 pub mod ops;
+pub mod prompts;
 pub mod types;
@@ -1,3 +1,4 @@
+use crate::budget;
 use crate::guc;
 use crate::search;
 use crate::util::get_vectorize_meta_spi;
@@ -5,17 +6,31 @@ use crate::util::get_vectorize_meta_spi;
 use anyhow::{anyhow, Result};
 use handlebars::Handlebars;
 use pgrx::prelude::*;
+use vectorize_core::errors::VectorizeError;
+use vectorize_core::transformers::http_handler::{
+    with_headers, with_proxies, with_timeouts, Timeouts,
+};
+use vectorize_core::transformers::providers::anthropic::AnthropicProvider;
+use vectorize_core::transformers::providers::azure::AzureOpenAIProvider;
+use vectorize_core::transformers::providers::bedrock::BedrockProvider;
+use vectorize_core::transformers::providers::gemini::GeminiProvider;
+use vectorize_core::transformers::providers::mistral::MistralProvider;
 use vectorize_core::transformers::providers::ollama::OllamaProvider;
 use vectorize_core::transformers::providers::openai::OpenAIProvider;
+use vectorize_core::transformers::providers::openai_compatible::OpenAICompatibleProvider;
 use vectorize_core::transformers::providers::portkey::PortkeyProvider;
-use vectorize_core::transformers::providers::ChatMessageRequest;
+use vectorize_core::transformers::providers::{ChatCompletion, ChatMessageRequest};
+use vectorize_core::transformers::retry::{with_retries, RetryPolicy};
 use vectorize_core::types::Model;
 use vectorize_core::types::ModelSource;
 
-use crate::chat::types::{ChatResponse, ContextualSearch, PromptTemplate, RenderedPrompt};
+use crate::chat::history;
+use crate::chat::prompts;
+use crate::chat::types::{ChatResponse, ContextualSearch, RenderedPrompt};
 use tiktoken_rs::{get_bpe_from_model, model::get_context_size, CoreBPE};
 use vectorize_core::types::{JobParams, VectorizeMeta};
 
+#[allow(clippy::too_many_arguments)]
 pub fn call_chat(
     agent_name: &str,
     query: &str,
@@ -24,13 +39,153 @@ pub fn call_chat(
     api_key: Option<String>,
     num_context: i32,
     force_trim: bool,
+    rerank_model: Option<&Model>,
+    session_id: Option<&str>,
 ) -> Result<ChatResponse> {
-    // get job metadata
+    let job_params = agent_job_params(agent_name)?;
+    let raw_search = search::search(
+        agent_name,
+        query,
+        api_key,
+        context_columns(&job_params),
+        num_context,
+        None,
+        false,
+        false,
+        None,
+        search::ChunkRetrieval::Chunks,
+        rerank_model,
+    )?;
+    let history = match session_id {
+        Some(id) => history::get(id, agent_name, guc::CHAT_HISTORY_TURNS.get())?,
+        None => Vec::new(),
+    };
+    let chat_response = chat_with_context(
+        agent_name,
+        &job_params,
+        query,
+        chat_model,
+        task,
+        &raw_search,
+        &history,
+        force_trim,
+    )?;
+    if let Some(id) = session_id {
+        history::record(id, agent_name, query, &chat_response.chat_response)?;
+    }
+    Ok(chat_response)
+}
+
+/// searches a rag agent's table and answers the query from the top results, embedding the query only once
+/// returns the first num_results search results, with the requested columns, along with the answer
+#[allow(clippy::too_many_arguments)]
+pub fn search_and_chat(
+    agent_name: &str,
+    query: &str,
+    chat_model: &Model,
+    task: &str,
+    api_key: Option<String>,
+    return_columns: Vec<String>,
+    num_results: i32,
+    num_context: i32,
+    where_clause: Option<String>,
+    force_trim: bool,
+    rerank_model: Option<&Model>,
+) -> Result<(Vec<pgrx::JsonB>, ChatResponse)> {
+    let job_params = agent_job_params(agent_name)?;
+    // the context needs the key and content columns, even when they were not asked for
+    let mut columns = return_columns.clone();
+    if !return_columns.iter().any(|c| c == "*") {
+        for col in context_columns(&job_params) {
+            if !columns.contains(&col) {
+                columns.push(col);
+            }
+        }
+    }
+    let raw_search = search::search(
+        agent_name,
+        query,
+        api_key,
+        columns,
+        num_results.max(num_context),
+        where_clause,
+        false,
+        false,
+        None,
+        search::ChunkRetrieval::Chunks,
+        rerank_model,
+    )?;
+    let num_context = (num_context.max(0) as usize).min(raw_search.len());
+    let chat_response = chat_with_context(
+        agent_name,
+        &job_params,
+        query,
+        chat_model,
+        task,
+        &raw_search[..num_context],
+        &[],
+        force_trim,
+    )?;
+    let search_results = raw_search
+        .into_iter()
+        .take(num_results.max(0) as usize)
+        .map(|r| pgrx::JsonB(select_columns(r.0, &return_columns)))
+        .collect();
+    Ok((search_results, chat_response))
+}
+
+fn agent_job_params(agent_name: &str) -> Result<JobParams> {
     let project_meta: VectorizeMeta = get_vectorize_meta_spi(agent_name)?;
+    Ok(serde_json::from_value::<JobParams>(project_meta.params)
+        .unwrap_or_else(|e| error!("failed to deserialize job params: {}", e)))
+}
 
-    let job_params = serde_json::from_value::<JobParams>(project_meta.params.clone())
-        .unwrap_or_else(|e| error!("failed to deserialize job params: {}", e));
+// the primary key columns, followed by the content column and any provenance columns
+fn context_columns(job_params: &JobParams) -> Vec<String> {
+    // can only be 1 column in a chat job, for now, so safe to grab first element
+    let mut columns = job_params.pkey().column_names();
+    columns.push(job_params.columns[0].clone());
+    if let Some(provenance) = &job_params.provenance {
+        for col in provenance.columns() {
+            if !columns.contains(&col) {
+                columns.push(col);
+            }
+        }
+    }
+    columns
+}
 
+// keeps the requested columns of a search result, along with its score
+fn select_columns(row: serde_json::Value, columns: &[String]) -> serde_json::Value {
+    if columns.iter().any(|c| c == "*") {
+        return row;
+    }
+    match row {
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.into_iter()
+                .filter(|(k, _)| {
+                    matches!(k.as_str(), "similarity_score" | "distance") || columns.contains(k)
+                })
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// answers a query from search results that have already been retrieved
+/// each result must include the job's context columns
+/// the prior turns of a conversation are sent between the system prompt and the query
+#[allow(clippy::too_many_arguments)]
+fn chat_with_context(
+    agent_name: &str,
+    job_params: &JobParams,
+    query: &str,
+    chat_model: &Model,
+    task: &str,
+    raw_search: &[pgrx::JsonB],
+    history: &[ChatMessageRequest],
+    force_trim: bool,
+) -> Result<ChatResponse> {
     // for various token count estimations
     let bpe = match chat_model.source {
         ModelSource::Ollama => {
@@ -44,71 +199,70 @@ pub fn call_chat(
             // Using gpt-3.5-turbo tokenizer as placeholder for Llama3-8B-Instruct
             get_bpe_from_model("gpt-3.5-turbo").expect("failed to get BPE from model")
         }
-        ModelSource::SentenceTransformers | ModelSource::Cohere => {
-            error!("SentenceTransformers and Cohere not yet supported for chat completions")
+        ModelSource::SentenceTransformers
+        | ModelSource::Cohere
+        | ModelSource::Voyage
+        | ModelSource::Jina
+        | ModelSource::HuggingFace => {
+            error!(
+                "{} models are not supported for chat completions",
+                chat_model.source
+            )
         }
         ModelSource::Portkey => {
             get_bpe_from_model(&chat_model.name).expect("failed to get BPE from model")
         }
-        ModelSource::Voyage => {
-            get_bpe_from_model(&chat_model.name).expect("failed to get BPE from model")
+        ModelSource::AzureOpenAI => {
+            // deployments are named by their owner, so the model behind them is not known
+            get_bpe_from_model(&chat_model.name)
+                .or_else(|_| get_bpe_from_model("gpt-3.5-turbo"))
+                .expect("failed to get BPE from model")
+        }
+        ModelSource::Bedrock
+        | ModelSource::Gemini
+        | ModelSource::Mistral
+        | ModelSource::Anthropic
+        | ModelSource::OpenAICompatible
+        | ModelSource::Vllm
+        | ModelSource::LlamaCpp => {
+            // Using gpt-3.5-turbo tokenizer as an estimate for Anthropic, Gemini, Mistral and self-hosted models
+            get_bpe_from_model("gpt-3.5-turbo").expect("failed to get BPE from model")
         }
     };
 
-    // can only be 1 column in a chat job, for now, so safe to grab first element
-    let content_column = job_params.columns[0].clone();
-    let pk = job_params.primary_key;
-    let columns = vec![pk.clone(), content_column.clone()];
-
-    let raw_search = search::search(
-        agent_name,
-        query,
-        api_key.clone(),
-        columns,
-        num_context,
-        None,
-    )?;
+    let content_column = &job_params.columns[0];
+    let pkey_columns = job_params.pkey().column_names();
 
     let mut search_results: Vec<ContextualSearch> = Vec::new();
     for s in raw_search {
-        let row_js: serde_json::Value = s.0;
-        let record_id = row_js
-            .get(&pk)
-            .unwrap_or_else(|| error!("`{pk}` not found"));
+        let row_js = &s.0;
+        // composite keys are identified by an array of their values
+        let key_values: Vec<&serde_json::Value> = pkey_columns
+            .iter()
+            .map(|pk| row_js.get(pk).unwrap_or_else(|| error!("`{pk}` not found")))
+            .collect();
+        let record_id = match key_values.as_slice() {
+            [value] => serde_json::to_value(value),
+            values => serde_json::to_value(values),
+        }
+        .expect("failed to serialize record_id");
         let content = row_js
-            .get(&content_column)
+            .get(content_column)
             .unwrap_or_else(|| error!("`{content_column}` not found"));
         let text_content =
             serde_json::to_string(content).expect("failed to serialize content to string");
         let token_ct = bpe.encode_ordinary(&text_content).len() as i32;
         search_results.push(ContextualSearch {
-            record_id: serde_json::to_string(record_id)
+            record_id: serde_json::to_string(&record_id)
                 .expect("failed to serialize record_id to string"),
             content: text_content,
             token_ct,
+            provenance: job_params.provenance.as_ref().map(|p| p.of(row_js)),
         });
     }
 
     // read prompt template
-    let res_prompts: Result<PromptTemplate, spi::Error> = Spi::connect(|c| {
-        let q = format!("select * from vectorize.prompts where prompt_type = '{task}'");
-        let tup_table = c.select(&q, None, None)?;
-        let mut sys_prompt = String::new();
-        let mut user_prompt = String::new();
-        for row in tup_table {
-            sys_prompt = row["sys_prompt"]
-                .value::<String>()?
-                .expect("sys_prompt is null");
-            user_prompt = row["user_prompt"]
-                .value::<String>()?
-                .expect("user_prompt is null");
-        }
-        Ok(PromptTemplate {
-            sys_prompt,
-            user_prompt,
-        })
-    });
-    let p_ok = res_prompts?;
+    let p_ok = prompts::get(task)?;
 
     let sys_prompt_template = p_ok.sys_prompt;
     let user_prompt_template = p_ok.user_prompt;
@@ -120,18 +274,39 @@ pub fn call_chat(
         &sys_prompt_template,
         &user_prompt_template,
         query,
+        history,
         force_trim,
         &bpe,
         max_context_length,
     )?;
 
     // http request to chat completions
-    let guc_configs = guc::get_guc_configs(&chat_model.source);
-    let chat_response = call_chat_completions(rendered_prompt, chat_model, &guc_configs)?;
+    budget::check_budget(agent_name, &chat_model.source)?;
+    let mut prompt_texts = vec![rendered_prompt.sys_rendered.clone()];
+    prompt_texts.extend(rendered_prompt.history.iter().map(|m| m.content.clone()));
+    prompt_texts.push(rendered_prompt.user_rendered.clone());
+    let guc_configs = guc::get_job_guc_configs(chat_model, job_params.credential.as_deref())?;
+    let timeouts = guc::timeouts().for_job(&job_params);
+    let completion = call_chat_completions(rendered_prompt, chat_model, &guc_configs, timeouts)?;
+    // servers that count the tokens of their responses are charged their own count
+    match completion.usage {
+        Some(usage) => budget::record_tokens(
+            agent_name,
+            chat_model,
+            usage.prompt_tokens as i64,
+            usage.completion_tokens as i64,
+        )?,
+        None => budget::record_token_usage(
+            agent_name,
+            chat_model,
+            &prompt_texts.iter().map(String::as_str).collect::<Vec<_>>(),
+            &[&completion.content],
+        )?,
+    }
 
     Ok(ChatResponse {
         context: search_results,
-        chat_response,
+        chat_response: completion.content,
     })
 }
 
@@ -149,33 +324,34 @@ pub fn call_chat_completions(
     prompts: RenderedPrompt,
     model: &Model,
     guc_configs: &guc::ModelGucConfig,
-) -> Result<String> {
-    let messages = vec![
-        ChatMessageRequest {
-            role: "system".to_owned(),
-            content: prompts.sys_rendered.clone(),
-        },
-        ChatMessageRequest {
-            role: "user".to_owned(),
-            content: prompts.user_rendered.clone(),
-        },
-    ];
+    timeouts: Timeouts,
+) -> Result<ChatCompletion> {
+    let mut messages = vec![ChatMessageRequest {
+        role: "system".to_owned(),
+        content: prompts.sys_rendered.clone(),
+    }];
+    messages.extend(prompts.history.iter().cloned());
+    messages.push(ChatMessageRequest {
+        role: "user".to_owned(),
+        content: prompts.user_rendered.clone(),
+    });
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_io()
         .enable_time()
         .build()
         .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
 
-    let chat_response: String = runtime.block_on(async {
-        match model.source {
+    // rate limited, timed out and failed requests are sent again
+    let retry = RetryPolicy::new(guc::MAX_ATTEMPTS.get() as u32);
+    let messages = &messages;
+    let chat = with_retries(&retry, || async move {
+        let content = match model.source {
             ModelSource::OpenAI | ModelSource::Tembo => {
                 let provider = OpenAIProvider::new(
                     guc_configs.service_url.clone(),
                     guc_configs.api_key.clone(),
                 );
-                provider
-                    .generate_response(model.api_name(), &messages)
-                    .await
+                provider.generate_response(model.api_name(), messages).await
             }
             ModelSource::Portkey => {
                 let provider = PortkeyProvider::new(
@@ -183,21 +359,81 @@ pub fn call_chat_completions(
                     guc_configs.api_key.clone(),
                     guc_configs.virtual_key.clone(),
                 );
-                provider
-                    .generate_response(model.api_name(), &messages)
-                    .await
+                provider.generate_response(model.api_name(), messages).await
+            }
+            ModelSource::AzureOpenAI => {
+                let provider = AzureOpenAIProvider::new(
+                    guc_configs.service_url.clone(),
+                    guc_configs.api_key.clone(),
+                )?;
+                provider.generate_response(model.api_name(), messages).await
+            }
+            ModelSource::Bedrock => {
+                let provider = BedrockProvider::new(
+                    guc_configs.service_url.clone(),
+                    guc_configs.api_key.clone(),
+                )?;
+                provider.generate_response(model.api_name(), messages).await
+            }
+            ModelSource::Gemini => {
+                let provider = GeminiProvider::new(
+                    guc_configs.service_url.clone(),
+                    guc_configs.api_key.clone(),
+                )?;
+                provider.generate_response(model.api_name(), messages).await
+            }
+            ModelSource::Anthropic => {
+                let mut provider = AnthropicProvider::new(
+                    guc_configs.service_url.clone(),
+                    guc_configs.api_key.clone(),
+                )?;
+                provider.max_tokens = guc::ANTHROPIC_MAX_TOKENS.get() as u32;
+                provider.generate_response(model.api_name(), messages).await
+            }
+            ModelSource::Mistral => {
+                let provider = MistralProvider::new(
+                    guc_configs.service_url.clone(),
+                    guc_configs.api_key.clone(),
+                )?;
+                provider.generate_response(model.api_name(), messages).await
+            }
+            ModelSource::OpenAICompatible => {
+                let provider = OpenAICompatibleProvider::new(
+                    guc_configs.service_url.clone(),
+                    guc_configs.api_key.clone(),
+                )?;
+                provider.generate_response(model.api_name(), messages).await
+            }
+            // self-hosted servers stream their responses, which keeps long generations from timing out
+            ModelSource::Vllm | ModelSource::LlamaCpp => {
+                let provider = OpenAICompatibleProvider::self_hosted(
+                    &model.source,
+                    guc_configs.service_url.clone(),
+                    guc_configs.api_key.clone(),
+                )?;
+                return provider.stream_response(model.api_name(), messages).await;
             }
             ModelSource::Ollama => {
-                let provider = OllamaProvider::new(guc_configs.service_url.clone());
-                provider
-                    .generate_response(model.api_name(), &messages)
-                    .await
+                let provider = OllamaProvider::new(guc_configs.service_url.clone())?;
+                provider.generate_response(model.api_name(), messages).await
             }
-            ModelSource::SentenceTransformers | ModelSource::Cohere | ModelSource::Voyage => {
-                error!("SentenceTransformers and Cohere not yet supported for chat completions")
+            ModelSource::SentenceTransformers
+            | ModelSource::Cohere
+            | ModelSource::Voyage
+            | ModelSource::Jina
+            | ModelSource::HuggingFace => {
+                error!(
+                    "{} models are not supported for chat completions",
+                    model.source
+                )
             }
-        }
-    })?;
+        }?;
+        Ok::<ChatCompletion, VectorizeError>(content.into())
+    });
+    let chat_response: ChatCompletion = runtime.block_on(with_headers(
+        guc::headers(model)?,
+        with_proxies(guc::proxies()?, with_timeouts(timeouts, chat)),
+    ))?;
     Ok(chat_response)
 }
 
@@ -229,13 +465,37 @@ fn trim_context(context: &str, overage: i32, bpe: &CoreBPE) -> Result<String> {
     Ok(trimmed_context)
 }
 
+// leaves out the oldest turns of a conversation until the rest fit within max_tokens
+// turns are left out whole, so that the history never starts with an answer
+fn trim_history<'a>(
+    history: &'a [ChatMessageRequest],
+    bpe: &CoreBPE,
+    max_tokens: i32,
+) -> &'a [ChatMessageRequest] {
+    let token_cts: Vec<i32> = history
+        .iter()
+        .map(|m| bpe.encode_ordinary(&m.content).len() as i32)
+        .collect();
+    let mut total: i32 = token_cts.iter().sum();
+    let mut start = 0;
+    while total > max_tokens && start < history.len() {
+        let end = (start + 2).min(history.len());
+        total -= token_cts[start..end].iter().sum::<i32>();
+        start = end;
+    }
+    &history[start..]
+}
+
 // handles all preparation of prompt with context
 // optionally rims the context to fit within the token limit
+// prior turns take up to half of the token limit, and the context what is left
+#[allow(clippy::too_many_arguments)]
 fn prepared_prompt(
     searches: &[ContextualSearch],
     sys_prompt_template: &str,
     user_prompt_template: &str,
     query: &str,
+    history: &[ChatMessageRequest],
     force_trim: bool,
     bpe: &CoreBPE,
     max_context_length: i32,
@@ -254,7 +514,14 @@ fn prepared_prompt(
     let sys_prompt_token_ct = bpe.encode_ordinary(sys_prompt_template).len() as i32;
     let user_prompt_token_ct = bpe.encode_ordinary(user_prompt_template).len() as i32;
 
-    let remaining_tokens = max_context_length - sys_prompt_token_ct - user_prompt_token_ct;
+    let history = trim_history(history, bpe, max_context_length / 2);
+    let history_token_ct: i32 = history
+        .iter()
+        .map(|m| bpe.encode_ordinary(&m.content).len() as i32)
+        .sum();
+
+    let remaining_tokens =
+        max_context_length - sys_prompt_token_ct - user_prompt_token_ct - history_token_ct;
 
     // overage
     let overage = user_message_ct >= remaining_tokens;
@@ -269,6 +536,7 @@ fn prepared_prompt(
         return Ok(RenderedPrompt {
             sys_rendered: sys_prompt_template.to_string(),
             user_rendered: user_message,
+            history: history.to_vec(),
         });
     }
 
@@ -282,12 +550,14 @@ fn prepared_prompt(
     Ok(RenderedPrompt {
         sys_rendered: sys_prompt_template.to_string(),
         user_rendered: user_message,
+        history: history.to_vec(),
     })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use vectorize_core::types::ChunkProvenance;
 
     #[test]
     fn test_prepared_prompt() {
@@ -299,12 +569,14 @@ mod tests {
             record_id: "1".to_string(),
             content: "The sky is the color blue.".to_string(),
             token_ct: 7,
+            provenance: None,
         }];
         let rendered = prepared_prompt(
             &searches,
             sys_prompt_template,
             user_prompt_template,
             query,
+            &[],
             true,
             &bpe,
             36,
@@ -322,6 +594,7 @@ mod tests {
             sys_prompt_template,
             user_prompt_template,
             query,
+            &[],
             false,
             &bpe,
             36,
@@ -333,6 +606,7 @@ mod tests {
             sys_prompt_template,
             user_prompt_template,
             query,
+            &[],
             false,
             &bpe,
             1000,
@@ -349,6 +623,7 @@ mod tests {
             sys_prompt_template,
             user_prompt_template,
             query,
+            &[],
             true,
             &bpe,
             1000,
@@ -378,6 +653,27 @@ mod tests {
         assert_eq!("The sky", trimmed);
     }
 
+    #[test]
+    fn test_trim_history() {
+        let bpe = get_bpe_from_model("gpt-3.5-turbo").unwrap();
+        let message = |role: &str, content: &str| ChatMessageRequest {
+            role: role.to_string(),
+            content: content.to_string(),
+        };
+        let history = vec![
+            message("user", "What color is the sky?"),
+            message("assistant", "The sky is blue."),
+            message("user", "Why?"),
+            message("assistant", "Because of Rayleigh scattering."),
+        ];
+        assert_eq!(trim_history(&history, &bpe, 1000).len(), 4);
+        // the oldest turn is left out whole
+        let trimmed = trim_history(&history, &bpe, 12);
+        assert_eq!(trimmed.len(), 2);
+        assert_eq!(trimmed[0].content, "Why?");
+        assert!(trim_history(&history, &bpe, 0).is_empty());
+    }
+
     #[test]
     fn test_render_user_message() {
         let prompt_template =
@@ -387,4 +683,39 @@ mod tests {
         let rendered = render_user_message(prompt_template, context, query).unwrap();
         assert_eq!("You are a sky expert, and here is context: The sky is the color blue. Question: What color is the sky?", rendered);
     }
+
+    #[test]
+    fn test_context_columns() {
+        let mut job_params = JobParams {
+            columns: vec!["body".to_string()],
+            primary_key: "chunk_id".to_string(),
+            pkey_type: "integer".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(context_columns(&job_params), vec!["chunk_id", "body"]);
+        job_params.provenance = Some(ChunkProvenance {
+            document_id: "doc_id".to_string(),
+            chunk_index: Some("chunk_id".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            context_columns(&job_params),
+            vec!["chunk_id", "body", "doc_id"]
+        );
+    }
+
+    #[test]
+    fn test_select_columns() {
+        let row = serde_json::json!({
+            "product_id": 1,
+            "description": "a quiet mouse",
+            "distance": 0.1,
+            "similarity_score": 0.9
+        });
+        assert_eq!(
+            select_columns(row.clone(), &["product_id".to_string()]),
+            serde_json::json!({"product_id": 1, "distance": 0.1, "similarity_score": 0.9})
+        );
+        assert_eq!(select_columns(row.clone(), &["*".to_string()]), row);
+    }
 }
@@ -0,0 +1,113 @@
+use crate::chat::types::PromptTemplate;
+use crate::compat::{self, arg};
+
+use anyhow::{bail, Context, Result};
+use handlebars::Handlebars;
+use pgrx::prelude::*;
+
+// the task of vectorize.rag() when none is given
+const DEFAULT_TASK: &str = "question_answer";
+
+/// the prompt templates of a task in vectorize.prompts
+pub fn get(task: &str) -> Result<PromptTemplate> {
+    compat::connect(|client| {
+        let tup_table = compat::select(
+            &client,
+            "SELECT sys_prompt, user_prompt FROM vectorize.prompts WHERE prompt_type = $1",
+            vec![arg(task)],
+        )?;
+        let Some(row) = tup_table.into_iter().next() else {
+            bail!("prompt {task} does not exist, create it with vectorize.create_prompt()");
+        };
+        Ok(PromptTemplate {
+            sys_prompt: row["sys_prompt"].value()?.unwrap_or_default(),
+            user_prompt: row["user_prompt"].value()?.unwrap_or_default(),
+        })
+    })
+}
+
+// templates are handlebars templates, which are rendered with context_str and query_str
+fn validate_template(name: &str, template: &str) -> Result<()> {
+    Handlebars::new()
+        .register_template_string(name, template)
+        .with_context(|| format!("{name} is not a valid template"))?;
+    Ok(())
+}
+
+/// adds the prompt templates of a task to vectorize.prompts, which vectorize.rag() is then given as its task
+pub fn create_prompt(task: &str, sys_prompt: &str, user_prompt: &str) -> Result<String> {
+    validate_template("sys_prompt", sys_prompt)?;
+    validate_template("user_prompt", user_prompt)?;
+    let created: Option<String> = compat::get_one(
+        "INSERT INTO vectorize.prompts (prompt_type, sys_prompt, user_prompt)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (prompt_type) DO NOTHING
+        RETURNING prompt_type",
+        vec![arg(task), arg(sys_prompt), arg(user_prompt)],
+    )?;
+    match created {
+        Some(_) => Ok(format!("Created prompt {task}")),
+        None => bail!("prompt {task} already exists, change it with vectorize.update_prompt()"),
+    }
+}
+
+/// replaces the system prompt, the user prompt, or both, of a task
+pub fn update_prompt(
+    task: &str,
+    sys_prompt: Option<&str>,
+    user_prompt: Option<&str>,
+) -> Result<String> {
+    if sys_prompt.is_none() && user_prompt.is_none() {
+        bail!("give a sys_prompt, a user_prompt or both");
+    }
+    if let Some(template) = sys_prompt {
+        validate_template("sys_prompt", template)?;
+    }
+    if let Some(template) = user_prompt {
+        validate_template("user_prompt", template)?;
+    }
+    let updated: Option<String> = compat::get_one(
+        "UPDATE vectorize.prompts
+        SET sys_prompt = COALESCE($2, sys_prompt), user_prompt = COALESCE($3, user_prompt)
+        WHERE prompt_type = $1
+        RETURNING prompt_type",
+        vec![arg(task), arg(sys_prompt), arg(user_prompt)],
+    )?;
+    match updated {
+        Some(_) => Ok(format!("Updated prompt {task}")),
+        None => bail!("prompt {task} does not exist"),
+    }
+}
+
+/// removes the prompt templates of a task
+/// the default task of vectorize.rag() is kept, its wording can be changed with vectorize.update_prompt()
+pub fn delete_prompt(task: &str) -> Result<String> {
+    if task == DEFAULT_TASK {
+        bail!("{DEFAULT_TASK} is the default task of vectorize.rag(), update it in place of deleting it");
+    }
+    let deleted: Option<String> = compat::get_one(
+        "DELETE FROM vectorize.prompts WHERE prompt_type = $1 RETURNING prompt_type",
+        vec![arg(task)],
+    )?;
+    match deleted {
+        Some(_) => Ok(format!("Deleted prompt {task}")),
+        None => bail!("prompt {task} does not exist"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_template() {
+        assert!(validate_template(
+            "user_prompt",
+            "Context: {{ context_str }}\nQ: {{query_str}}"
+        )
+        .is_ok());
+        assert!(validate_template("sys_prompt", "You answer questions about pencils.").is_ok());
+        assert!(validate_template("user_prompt", "Q: {{ query_str }").is_err());
+        assert!(validate_template("user_prompt", "{{#if context_str}}{{ context_str }}").is_err());
+    }
+}
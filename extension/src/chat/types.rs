@@ -1,4 +1,5 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use vectorize_core::transformers::providers::ChatMessageRequest;
 
 pub struct PromptTemplate {
     pub sys_prompt: String,
@@ -8,6 +9,8 @@ pub struct PromptTemplate {
 pub struct RenderedPrompt {
     pub sys_rendered: String,
     pub user_rendered: String,
+    // prior turns of the conversation, sent between the system prompt and the user prompt
+    pub history: Vec<ChatMessageRequest>,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -15,6 +18,9 @@ pub struct ContextualSearch {
     pub record_id: String,
     pub content: String,
     pub token_ct: i32,
+    // where the content sits in its source document, for agents that declare their chunk provenance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize)]
@@ -22,3 +28,20 @@ pub struct ChatResponse {
     pub context: Vec<ContextualSearch>,
     pub chat_response: String,
 }
+
+// schema for the params column of the vectorize.rag_batch table
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RagBatchParams {
+    pub agent_name: String,
+    pub schema: String,
+    pub questions_table: String,
+    pub question_column: String,
+    pub id_column: String,
+    pub output_table: String,
+    pub chat_model: String,
+    pub task: String,
+    pub num_context: i32,
+    pub force_trim: bool,
+    // maximum number of questions answered per scheduled run
+    pub questions_per_minute: i32,
+}
@@ -0,0 +1,1098 @@
+use crate::compat::{self, arg};
+use crate::guc::BATCH_SIZE;
+use crate::init::{self, Partitioning, VECTORIZE_QUEUE};
+use crate::query::check_input;
+use crate::util;
+
+use anyhow::{anyhow, bail, Context, Result};
+use pgrx::prelude::*;
+use pgrx::spi::SpiClient;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use vectorize_core::chunking::{self, Chunk, TokenCounter};
+use vectorize_core::preprocess;
+use vectorize_core::transformers::types::Inputs;
+use vectorize_core::types::{
+    ChunkFilter, ChunkParams, ChunkStrategy, Chunking, JobMessage, JobParams, Model, Preprocess,
+};
+
+/// name of the table of a chunked job's chunks, in the job's embeddings schema
+pub fn chunks_table_name(job_name: &str) -> String {
+    format!("_chunks_{job_name}")
+}
+
+/// the params of a job once it is renamed, the table of chunks of a chunked job is renamed along with it
+pub fn renamed_job_params(new_name: &str, job_params: &JobParams) -> JobParams {
+    let mut renamed = job_params.clone();
+    if renamed.chunking.is_some() {
+        renamed.table = chunks_table_name(new_name);
+    }
+    renamed
+}
+
+/// the tokenizer the chunks of a model's inputs are measured with
+/// with the onnx feature, a model whose tokenizer.json is in vectorize.onnx_model_dir is measured with its own tokenizer,
+/// otherwise with the tiktoken encoding of chunking::tokenizer
+pub fn tokenizer(model: Option<&Model>) -> Result<&'static dyn TokenCounter> {
+    #[cfg(feature = "onnx")]
+    if let Some(tokenizer) = model
+        .map(crate::transformers::onnx::HfTokenizer::find)
+        .transpose()?
+        .flatten()
+    {
+        return Ok(tokenizer);
+    }
+    Ok(chunking::tokenizer(model)?)
+}
+
+/// the chunk params given to vectorize.table or vectorize.chunk_table
+pub fn chunk_params(
+    chunk_size: i32,
+    chunk_overlap: i32,
+    strategy: Option<&str>,
+    separators: Option<Vec<String>>,
+    similarity_threshold: Option<f64>,
+    separator: Option<String>,
+    chunk_stride: Option<i32>,
+) -> Result<ChunkParams> {
+    if chunk_size < 1 {
+        bail!("chunk_size must be positive, got {chunk_size}");
+    }
+    if chunk_overlap < 0 || chunk_overlap >= chunk_size {
+        bail!("chunk_overlap must be at least 0 and smaller than chunk_size, got {chunk_overlap}");
+    }
+    let strategy = strategy
+        .map(|s| s.parse::<ChunkStrategy>().map_err(|e| anyhow!(e)))
+        .transpose()?
+        .unwrap_or_default();
+    let separators = separators.unwrap_or_default();
+    if !separators.is_empty() && strategy != ChunkStrategy::recursive {
+        bail!("separators are only used by the recursive chunk_strategy");
+    }
+    if separators.iter().any(|s| s.is_empty()) {
+        bail!("separators can not be empty strings");
+    }
+    if strategy == ChunkStrategy::semantic && chunk_overlap > 0 {
+        bail!("chunk_overlap is not used by the semantic chunk_strategy, chunks end where the topic changes");
+    }
+    if let Some(threshold) = similarity_threshold {
+        if strategy != ChunkStrategy::semantic {
+            bail!("similarity_threshold is only used by the semantic chunk_strategy");
+        }
+        if !(-1.0..=1.0).contains(&threshold) {
+            bail!("similarity_threshold must be between -1 and 1, got {threshold}");
+        }
+    }
+    match &separator {
+        Some(separator) if strategy == ChunkStrategy::separator => {
+            chunking::separator_regex(separator)?;
+        }
+        Some(_) => bail!("separator is only used by the separator chunk_strategy"),
+        None if strategy == ChunkStrategy::separator => {
+            bail!("the separator chunk_strategy requires a separator")
+        }
+        None => {}
+    }
+    if strategy == ChunkStrategy::separator && chunk_overlap > 0 {
+        bail!("chunk_overlap is not used by the separator chunk_strategy, chunks end where the separator matches");
+    }
+    if strategy == ChunkStrategy::window && chunk_overlap > 0 {
+        bail!("chunk_overlap is not used by the window chunk_strategy, windows overlap by chunk_size - chunk_stride tokens");
+    }
+    if let Some(stride) = chunk_stride {
+        if strategy != ChunkStrategy::window {
+            bail!("chunk_stride is only used by the window chunk_strategy");
+        }
+        if stride < 1 || stride > chunk_size {
+            bail!("chunk_stride must be between 1 and chunk_size, got {stride}");
+        }
+    }
+    Ok(ChunkParams {
+        chunk_size: chunk_size as u32,
+        chunk_overlap: chunk_overlap as u32,
+        strategy,
+        separators,
+        similarity_threshold,
+        separator,
+        chunk_stride: chunk_stride.map(|s| s as u32),
+        filter: None,
+        column_params: BTreeMap::new(),
+    })
+}
+
+/// the chunk filter given to vectorize.table, vectorize.chunk_table or vectorize.rechunk
+/// the current user is recorded as the owner of a filter with a function, which the worker calls the function as
+pub fn chunk_filter(filter: serde_json::Value) -> Result<ChunkFilter> {
+    let mut filter: ChunkFilter = serde_json::from_value(filter)
+        .context("chunk_filter must be an object of min_tokens, exclude_patterns or function")?;
+    chunking::CompiledFilter::new(&filter)?;
+    filter.owner = String::new();
+    if let Some(function) = &filter.function {
+        validate_filter_function(function)?;
+        filter.owner = compat::get_one::<String>("SELECT current_user::text", vec![])?
+            .context("current_user was null")?;
+    }
+    Ok(filter)
+}
+
+// a chunk filter's function, which may be schema qualified, must take the text of a chunk and return a boolean,
+// and be one the current user can execute
+fn validate_filter_function(function: &str) -> Result<()> {
+    let parts: Vec<&str> = function.split('.').collect();
+    if parts.len() > 2 {
+        bail!("invalid chunk_filter function: {function}");
+    }
+    for part in parts {
+        check_input(part)?;
+    }
+    let returns_boolean: Option<bool> = compat::get_one(
+        "SELECT prorettype = 'boolean'::regtype FROM pg_proc WHERE oid = to_regprocedure($1 || '(text)')",
+        vec![arg(function)],
+    )?;
+    match returns_boolean {
+        Some(true) => {}
+        Some(false) => bail!("chunk_filter function {function} must return boolean"),
+        None => bail!("chunk_filter function {function}(text) does not exist"),
+    }
+    let executable: Option<bool> = compat::get_one(
+        "SELECT has_function_privilege(to_regprocedure($1 || '(text)'), 'EXECUTE')",
+        vec![arg(function)],
+    )?;
+    if executable != Some(true) {
+        bail!("permission denied for chunk_filter function {function}");
+    }
+    Ok(())
+}
+
+// the chunk params of a column given in chunk_column_params
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ColumnChunkParams {
+    chunk_size: Option<i32>,
+    #[serde(default)]
+    chunk_overlap: i32,
+    chunk_strategy: Option<String>,
+    chunk_separators: Option<Vec<String>>,
+    chunk_similarity_threshold: Option<f64>,
+    chunk_separator: Option<String>,
+    chunk_stride: Option<i32>,
+}
+
+/// the chunk params of a job along with the params of the columns given in chunk_column_params,
+/// an object of column name to the chunk params of the column, or to null for a column that is not chunked
+/// a column's chunk_size defaults to the job's, and its other params to those of vectorize.table
+pub fn with_column_params(
+    mut params: ChunkParams,
+    columns: &[String],
+    column_params: serde_json::Value,
+) -> Result<ChunkParams> {
+    let column_params: BTreeMap<String, Option<ColumnChunkParams>> =
+        serde_json::from_value(column_params).context(
+            "chunk_column_params must be an object of column name to the column's chunk params, or to null",
+        )?;
+    if params.strategy == ChunkStrategy::semantic && !column_params.is_empty() {
+        bail!("chunk_column_params can not be used along with the semantic chunk_strategy");
+    }
+    for (column, given) in column_params {
+        if !columns.contains(&column) {
+            bail!("chunk_column_params are given for {column}, which is not one of the columns");
+        }
+        let column_chunk_params = given
+            .map(|p| {
+                chunk_params(
+                    p.chunk_size.unwrap_or(params.chunk_size as i32),
+                    p.chunk_overlap,
+                    p.chunk_strategy.as_deref(),
+                    p.chunk_separators,
+                    p.chunk_similarity_threshold,
+                    p.chunk_separator,
+                    p.chunk_stride,
+                )
+            })
+            .transpose()
+            .with_context(|| format!("invalid chunk_column_params of column {column}"))?;
+        if column_chunk_params
+            .as_ref()
+            .is_some_and(|p| p.strategy == ChunkStrategy::semantic)
+        {
+            bail!(
+                "the semantic chunk_strategy can not be given to a single column, column: {column}"
+            );
+        }
+        params.column_params.insert(column, column_chunk_params);
+    }
+    Ok(params)
+}
+
+/// the source table and chunk params of a table whose rows are to be chunked
+/// with an input template, the templated text of each row is chunked as a whole in place of its columns
+pub fn chunking_of(
+    schema: &str,
+    table: &str,
+    columns: Vec<String>,
+    primary_key: &str,
+    params: ChunkParams,
+    input_template: Option<&str>,
+) -> Result<Chunking> {
+    check_input(schema)?;
+    check_input(table)?;
+    for column in &columns {
+        check_input(column)?;
+    }
+    if primary_key.contains(',') {
+        bail!("chunking requires a single column primary_key, got: {primary_key}");
+    }
+    let pkey_type = init::get_column_datatype(schema, table, primary_key)?;
+    let input_template = input_template
+        .map(|template| init::normalize_input_template(template, &columns))
+        .transpose()?;
+    if input_template.is_some() && !params.column_params.is_empty() {
+        bail!("chunk_column_params can not be used along with input_template, the templated text is chunked as a whole");
+    }
+    let chunking = Chunking {
+        schema: schema.to_string(),
+        table: table.to_string(),
+        columns,
+        primary_key: primary_key.to_string(),
+        pkey_type,
+        params,
+        input_template,
+    };
+    if chunking.input_template.is_some() {
+        compat::run(
+            &format!("{} LIMIT 0", select_rows_query(&chunking, false)),
+            vec![],
+        )
+        .context("input_template is not a valid expression over the table's columns")?;
+    }
+    Ok(chunking)
+}
+
+/// creates a table of the chunks of a table's rows, and writes the chunks of its current rows
+/// the rows are preprocessed before they are chunked, returns the number of chunks written
+/// the semantic strategy needs the job's model, its table is left empty for the worker to write its chunks
+/// with synced, the chunks reference their rows, and are deleted along with them
+pub fn create_chunks(
+    chunking: &Chunking,
+    chunks_schema: &str,
+    chunks_table: &str,
+    synced: bool,
+    preprocess: Option<Preprocess>,
+    transformer: Option<&Model>,
+) -> Result<i64> {
+    check_input(chunks_schema)?;
+    check_input(chunks_table)?;
+    compat::run(
+        &create_chunks_table_query(chunks_schema, chunks_table, chunking, synced),
+        vec![],
+    )?;
+    if synced {
+        compat::run(
+            &format!("CREATE INDEX ON {chunks_schema}.{chunks_table} (content_hash)"),
+            vec![],
+        )?;
+    }
+    if chunking.params.strategy == ChunkStrategy::semantic {
+        return Ok(0);
+    }
+    let counts = write_chunks(
+        chunking,
+        chunks_schema,
+        chunks_table,
+        preprocess,
+        transformer,
+        None,
+    )?;
+    Ok(counts.written)
+}
+
+/// writes the chunks of a table's rows to a new table in the same schema
+#[allow(clippy::too_many_arguments)]
+pub fn chunk_table(
+    schema: &str,
+    table: &str,
+    columns: Vec<String>,
+    primary_key: &str,
+    params: ChunkParams,
+    output_table: &str,
+    preprocess: Option<Preprocess>,
+    transformer: Option<&Model>,
+) -> Result<String> {
+    if params.strategy == ChunkStrategy::semantic {
+        bail!("the semantic chunk_strategy is chunked by the worker with a job's model, use vectorize.table() with chunk_size");
+    }
+    let chunking = chunking_of(schema, table, columns, primary_key, params, None)?;
+    let num_chunks = create_chunks(
+        &chunking,
+        schema,
+        output_table,
+        false,
+        preprocess,
+        transformer,
+    )?;
+    Ok(format!(
+        "Wrote {num_chunks} chunks of {schema}.{table} to {schema}.{output_table}"
+    ))
+}
+
+/// queues the rows of a semantic chunked job's source table for the worker, which splits them into chunks
+/// with the job's model and writes them to the job's table of chunks, where they are embedded like new rows
+/// returns the number of rows queued
+/// only the rows with the given record ids are queued, when they are given
+pub fn enqueue_semantic_chunking(job_name: &str, record_ids: Option<Vec<String>>) -> Result<i64> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: JobParams = serde_json::from_value(meta.params.clone())?;
+    let chunking = job_params
+        .chunking
+        .as_ref()
+        .context("job does not chunk its rows")?;
+    let bpe = tokenizer(Some(&meta.transformer))?;
+    // each row is queued as the json array of its columns' text
+    let select_q = select_rows_query(chunking, record_ids.is_some());
+    let inputs = compat::connect(|c| {
+        let mut inputs = Vec::new();
+        for row in compat::select(&c, &select_q, record_ids.map(arg).into_iter().collect())? {
+            let id: String = row["id"].value()?.context("primary key was null")?;
+            let texts = (1..=chunking.sources().len())
+                .map(|i| row.get::<String>(i + 1))
+                .collect::<Result<Vec<_>, _>>()?;
+            let token_estimate = texts.iter().flatten().map(|t| bpe.count(t) as i32).sum();
+            inputs.push(Inputs {
+                record_id: id,
+                inputs: serde_json::to_string(&texts)?,
+                token_estimate,
+            });
+        }
+        Ok::<_, anyhow::Error>(inputs)
+    })?;
+    let num_rows = inputs.len() as i64;
+    let batch_size = job_params.batch_size.unwrap_or_else(|| BATCH_SIZE.get());
+    for batch in chunking::create_batches(inputs, batch_size) {
+        let message = JobMessage {
+            job_name: job_name.to_string(),
+            job_meta: meta.clone(),
+            inputs: batch,
+            semantic_chunking: true,
+        };
+        compat::run(
+            "SELECT pgmq.send($1, $2::jsonb)",
+            vec![
+                arg(VECTORIZE_QUEUE),
+                arg(pgrx::JsonB(serde_json::to_value(message)?)),
+            ],
+        )?;
+    }
+    Ok(num_rows)
+}
+
+// original_id is the primary key of the row a chunk comes from, and chunk_index its position among the row's chunks
+// source_column is the column the chunk was taken from, and char_start and char_end its character offsets in that column,
+// as preprocessed, so that substr(column, char_start + 1, char_end - char_start) is the chunk
+// headings are the headings of the markdown sections a chunk is in, for the markdown strategy
+// content_hash finds the chunks with the same text, which a job only embeds once
+// with synced, original_id references the row, so that its chunks are deleted along with it
+fn create_chunks_table_query(
+    schema: &str,
+    table: &str,
+    chunking: &Chunking,
+    synced: bool,
+) -> String {
+    let foreign_key = match synced {
+        true => format!(
+            ",\n            FOREIGN KEY (original_id) REFERENCES {src_schema}.{src_table} ({pkey})
+                ON DELETE CASCADE ON UPDATE CASCADE",
+            src_schema = chunking.schema,
+            src_table = chunking.table,
+            pkey = chunking.primary_key,
+        ),
+        false => String::new(),
+    };
+    format!(
+        "CREATE TABLE {schema}.{table} (
+            chunk_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+            original_id {pkey_type} NOT NULL,
+            chunk_index INTEGER NOT NULL,
+            source_column TEXT NOT NULL,
+            char_start INTEGER NOT NULL,
+            char_end INTEGER NOT NULL,
+            token_count INTEGER NOT NULL,
+            chunk TEXT NOT NULL,
+            content_hash TEXT GENERATED ALWAYS AS (md5(chunk)) STORED,
+            headings TEXT[],
+            updated_at TIMESTAMPTZ DEFAULT NOW() NOT NULL{foreign_key}
+        );",
+        pkey_type = chunking.pkey_type,
+    )
+}
+
+// the number of chunks written, kept as they were, deleted, and discarded by the chunk filter when rows are chunked
+#[derive(Debug, Default)]
+struct ChunkCounts {
+    written: i64,
+    kept: i64,
+    deleted: i64,
+    discarded: i64,
+}
+
+// the rows of a table are read and chunked this many at a time, and the chunks of each batch written together
+const ROWS_PER_BATCH: i64 = 1000;
+
+// the columns of a row are chunked one after another, their chunks are numbered across the columns
+// the chunks a row already had are kept where their text did not change, so that they keep their embeddings,
+// and only the chunks with new text are written, to be embedded
+// only the rows with the given record ids are chunked, when they are given
+fn write_chunks(
+    chunking: &Chunking,
+    chunks_schema: &str,
+    chunks_table: &str,
+    pre: Option<Preprocess>,
+    transformer: Option<&Model>,
+    record_ids: Option<Vec<String>>,
+) -> Result<ChunkCounts> {
+    let bpe = tokenizer(transformer)?;
+    let select_q = select_rows_query(chunking, record_ids.is_some());
+    let queries = ChunkQueries::new(chunking, chunks_schema, chunks_table);
+    compat::connect(|mut c| {
+        let mut rows_cursor =
+            compat::open_cursor(&c, &select_q, record_ids.map(arg).into_iter().collect())?;
+        let mut counts = ChunkCounts::default();
+        loop {
+            let mut rows: Vec<(String, Vec<Option<String>>)> = Vec::new();
+            for row in rows_cursor.fetch(ROWS_PER_BATCH)? {
+                let id: String = row["id"].value()?.context("primary key was null")?;
+                let texts = (1..=chunking.sources().len())
+                    .map(|i| row.get::<String>(i + 1))
+                    .collect::<Result<Vec<_>, _>>()?;
+                rows.push((id, texts));
+            }
+            if rows.is_empty() {
+                break;
+            }
+            let ids: Vec<String> = rows.iter().map(|(id, _)| id.clone()).collect();
+            let mut existing: HashMap<String, Vec<(i64, String, String)>> = HashMap::new();
+            for row in compat::select(&c, &queries.existing, vec![arg(ids)])? {
+                let chunk_id: i64 = row["chunk_id"].value()?.context("chunk_id was null")?;
+                let id: String = row["id"].value()?.context("original_id was null")?;
+                let column: String = row["source_column"].value()?.unwrap_or_default();
+                let chunk: String = row["chunk"].value()?.unwrap_or_default();
+                existing
+                    .entry(id)
+                    .or_default()
+                    .push((chunk_id, column, chunk));
+            }
+
+            let mut row_chunks: Vec<(&str, Vec<(&str, Chunk)>)> = Vec::new();
+            for (id, texts) in &rows {
+                let mut chunks: Vec<(&str, Chunk)> = Vec::new();
+                for (column, text) in chunking.sources().into_iter().zip(texts) {
+                    let Some(text) = text else {
+                        continue;
+                    };
+                    let text = match pre {
+                        Some(pre) => preprocess::preprocess(text, pre),
+                        None => text.clone(),
+                    };
+                    for chunk in chunking::chunk_column(&text, column, &chunking.params, bpe)? {
+                        chunks.push((column, chunk));
+                    }
+                }
+                row_chunks.push((id.as_str(), chunks));
+            }
+            if let Some(filter) = &chunking.params.filter {
+                counts.discarded += filter_chunks(&c, filter, &mut row_chunks)?;
+            }
+
+            let mut batch = ChunkBatch::default();
+            for (id, chunks) in &row_chunks {
+                let new: Vec<(&str, &str)> = chunks
+                    .iter()
+                    .map(|(column, chunk)| (*column, chunk.text.as_str()))
+                    .collect();
+                let changes =
+                    chunking::chunk_changes(existing.get(*id).map_or(&[], |e| e.as_slice()), &new);
+                batch.add(id, chunking, chunks, changes);
+            }
+            counts.written += batch.inserted.len() as i64;
+            counts.kept += batch.kept.len() as i64;
+            counts.deleted += batch.deleted.len() as i64;
+            batch.write(&mut c, &queries)?;
+        }
+        Ok::<_, anyhow::Error>(counts)
+    })
+}
+
+// discards the chunks of each row that the chunk filter does not keep, before they are numbered
+// the filter's function is called once for the chunks of every row, returns the number of chunks discarded
+fn filter_chunks(
+    c: &SpiClient,
+    filter: &ChunkFilter,
+    row_chunks: &mut [(&str, Vec<(&str, Chunk)>)],
+) -> Result<i64> {
+    let num_chunks = |row_chunks: &[(&str, Vec<(&str, Chunk)>)]| {
+        row_chunks.iter().map(|(_, c)| c.len() as i64).sum::<i64>()
+    };
+    let before = num_chunks(row_chunks);
+    let compiled = chunking::CompiledFilter::new(filter)?;
+    for (_, chunks) in row_chunks.iter_mut() {
+        chunks.retain(|(_, chunk)| !compiled.discards(chunk));
+    }
+    if let Some(function) = &filter.function {
+        let texts: Vec<String> = row_chunks
+            .iter()
+            .flat_map(|(_, chunks)| chunks.iter().map(|(_, chunk)| chunk.text.clone()))
+            .collect();
+        if !texts.is_empty() {
+            let mut keep = Vec::with_capacity(texts.len());
+            let query = chunking::filter_function_query(function);
+            for row in compat::select(c, &query, vec![arg(texts)])? {
+                keep.push(row["keep"].value::<bool>()?.unwrap_or(false));
+            }
+            let mut keep = keep.into_iter();
+            for (_, chunks) in row_chunks.iter_mut() {
+                chunks.retain(|_| keep.next().unwrap_or(false));
+            }
+        }
+    }
+    Ok(before - num_chunks(row_chunks))
+}
+
+// the statements writing the chunks of a batch of rows, each given arrays of the values of every chunk
+struct ChunkQueries {
+    existing: String,
+    delete: String,
+    update: String,
+    insert: String,
+}
+
+impl ChunkQueries {
+    fn new(chunking: &Chunking, schema: &str, table: &str) -> Self {
+        // the headings of each chunk are given as a json array, as arrays of arrays can not be unnested by row
+        let headings = "CASE WHEN c.headings IS NOT NULL
+                THEN ARRAY(SELECT jsonb_array_elements_text(c.headings::jsonb)) END";
+        ChunkQueries {
+            existing: format!(
+                "SELECT chunk_id, original_id::text AS id, source_column, chunk
+                FROM {schema}.{table} WHERE original_id::text = ANY($1)"
+            ),
+            delete: format!("DELETE FROM {schema}.{table} WHERE chunk_id = ANY($1)"),
+            // a kept chunk keeps its updated_at, so that it is not embedded again
+            update: format!(
+                "UPDATE {schema}.{table} t
+                SET chunk_index = c.chunk_index, char_start = c.char_start, char_end = c.char_end,
+                    token_count = c.token_count, headings = {headings}
+                FROM unnest($1::bigint[], $2::int[], $3::int[], $4::int[], $5::int[], $6::text[])
+                    AS c(chunk_id, chunk_index, char_start, char_end, token_count, headings)
+                WHERE t.chunk_id = c.chunk_id"
+            ),
+            insert: format!(
+                "INSERT INTO {schema}.{table}
+                    (original_id, chunk_index, source_column, char_start, char_end, token_count, chunk, headings)
+                SELECT c.original_id::{pkey_type}, c.chunk_index, c.source_column, c.char_start, c.char_end,
+                    c.token_count, c.chunk, {headings}
+                FROM unnest($1::text[], $2::int[], $3::text[], $4::int[], $5::int[], $6::int[], $7::text[], $8::text[])
+                    AS c(original_id, chunk_index, source_column, char_start, char_end, token_count, chunk, headings)",
+                pkey_type = chunking.pkey_type,
+            ),
+        }
+    }
+}
+
+// a chunk's position among its row's chunks, offsets, token count and headings
+type ChunkPosition = (i32, i32, i32, i32, Option<String>);
+
+// the chunks of a batch of rows that are deleted, kept with their new positions, and inserted
+#[derive(Default)]
+struct ChunkBatch {
+    deleted: Vec<i64>,
+    kept: Vec<(i64, ChunkPosition)>,
+    // the record id, column and text of each new chunk
+    inserted: Vec<(String, String, String, ChunkPosition)>,
+}
+
+impl ChunkBatch {
+    fn add(
+        &mut self,
+        id: &str,
+        chunking: &Chunking,
+        chunks: &[(&str, Chunk)],
+        changes: chunking::ChunkChanges,
+    ) {
+        let position = |i: usize| {
+            let chunk = &chunks[i].1;
+            (
+                i as i32,
+                chunk.char_start as i32,
+                chunk.char_end as i32,
+                chunk.token_count as i32,
+                headings(chunking, chunks[i].0, chunk)
+                    .map(|h| serde_json::Value::from(h).to_string()),
+            )
+        };
+        self.deleted.extend(changes.deleted);
+        for (chunk_id, i) in changes.kept {
+            self.kept.push((chunk_id, position(i)));
+        }
+        for i in changes.inserted {
+            let (column, chunk) = &chunks[i];
+            self.inserted.push((
+                id.to_string(),
+                column.to_string(),
+                chunk.text.clone(),
+                position(i),
+            ));
+        }
+    }
+
+    // one statement each for the deleted, kept and inserted chunks
+    fn write(self, c: &mut SpiClient, queries: &ChunkQueries) -> Result<()> {
+        if !self.deleted.is_empty() {
+            compat::update(c, &queries.delete, vec![arg(self.deleted)])?;
+        }
+        if !self.kept.is_empty() {
+            let (chunk_ids, positions): (Vec<i64>, Vec<ChunkPosition>) =
+                self.kept.into_iter().unzip();
+            let (indexes, starts, ends, tokens, headings) = unzip_positions(positions);
+            compat::update(
+                c,
+                &queries.update,
+                vec![
+                    arg(chunk_ids),
+                    arg(indexes),
+                    arg(starts),
+                    arg(ends),
+                    arg(tokens),
+                    arg(headings),
+                ],
+            )?;
+        }
+        if !self.inserted.is_empty() {
+            let mut ids = Vec::new();
+            let mut columns = Vec::new();
+            let mut texts = Vec::new();
+            let mut positions = Vec::new();
+            for (id, column, text, position) in self.inserted {
+                ids.push(id);
+                columns.push(column);
+                texts.push(text);
+                positions.push(position);
+            }
+            let (indexes, starts, ends, tokens, headings) = unzip_positions(positions);
+            compat::update(
+                c,
+                &queries.insert,
+                vec![
+                    arg(ids),
+                    arg(indexes),
+                    arg(columns),
+                    arg(starts),
+                    arg(ends),
+                    arg(tokens),
+                    arg(texts),
+                    arg(headings),
+                ],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn unzip_positions(
+    positions: Vec<ChunkPosition>,
+) -> (Vec<i32>, Vec<i32>, Vec<i32>, Vec<i32>, Vec<Option<String>>) {
+    let mut unzipped = (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (index, start, end, tokens, headings) in positions {
+        unzipped.0.push(index);
+        unzipped.1.push(start);
+        unzipped.2.push(end);
+        unzipped.3.push(tokens);
+        unzipped.4.push(headings);
+    }
+    unzipped
+}
+
+// the headings of a chunk are only recorded by the markdown strategy
+fn headings(chunking: &Chunking, column: &str, chunk: &Chunk) -> Option<Vec<String>> {
+    match chunking.params.of_column(column).map(|p| p.strategy) {
+        Some(ChunkStrategy::markdown) => Some(chunk.headings.clone()),
+        _ => None,
+    }
+}
+
+// the rows whose record ids are in $1, when filtered
+// followed by the text of each of the chunked sources of a row
+fn select_rows_query(chunking: &Chunking, filtered: bool) -> String {
+    let columns = match &chunking.input_template {
+        // evaluated over the row's columns alone, as the templates of jobs that are not chunked are
+        Some(template) => format!(
+            "(SELECT ({template})::text FROM (SELECT {schema}.{table}.*) input_row)",
+            schema = chunking.schema,
+            table = chunking.table,
+        ),
+        None => chunking
+            .columns
+            .iter()
+            .map(|c| format!("{c}::text"))
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+    let filter = match filtered {
+        true => format!(" WHERE {pkey}::text = ANY($1)", pkey = chunking.primary_key),
+        false => String::new(),
+    };
+    format!(
+        "SELECT {pkey}::text AS id, {columns} FROM {schema}.{table}{filter}",
+        pkey = chunking.primary_key,
+        schema = chunking.schema,
+        table = chunking.table,
+    )
+}
+
+/// statements creating the triggers that chunk the new rows of a chunked job's source table, and chunk its rows again once they change
+/// updated rows whose chunked columns did not change are skipped, and deleted rows take their chunks with them
+/// statement triggers on a partitioned table only fire for statements naming it, so the partitions
+/// are given triggers of their own
+pub fn rechunk_trigger_queries(
+    job_name: &str,
+    chunking: &Chunking,
+    partitioning: Option<&Partitioning>,
+) -> Vec<String> {
+    let mut trigger_tables = vec![(chunking.schema.clone(), chunking.table.clone())];
+    if let Some(p) = partitioning {
+        trigger_tables.extend(p.leaves.iter().cloned());
+    }
+    let changed = chunking
+        .columns
+        .iter()
+        .map(|c| format!("n.{c} IS DISTINCT FROM o.{c}"))
+        .collect::<Vec<String>>()
+        .join(" OR ");
+    let mut queries = vec![format!(
+        "
+CREATE OR REPLACE FUNCTION vectorize.handle_rechunk_{job_name}()
+RETURNS TRIGGER AS $$
+DECLARE
+    record_ids TEXT[];
+BEGIN
+    IF TG_OP = 'UPDATE' THEN
+        SELECT array_agg(n.{pkey}::text) INTO record_ids
+        FROM new_table n
+        INNER JOIN old_table o ON n.{pkey} = o.{pkey}
+        WHERE {changed};
+    ELSE
+        SELECT array_agg(n.{pkey}::text) INTO record_ids FROM new_table n;
+    END IF;
+    IF record_ids IS NULL THEN
+        RETURN NULL;
+    END IF;
+    PERFORM vectorize._rechunk_rows('{job_name}', record_ids);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+",
+        pkey = chunking.primary_key,
+    )];
+    // transition tables can not be given to a trigger of more than one event
+    for (schema, table) in &trigger_tables {
+        for (event, transition_tables) in [
+            ("INSERT", "NEW TABLE AS new_table"),
+            ("UPDATE", "NEW TABLE AS new_table OLD TABLE AS old_table"),
+        ] {
+            queries.push(format!(
+                "
+CREATE OR REPLACE TRIGGER vectorize_rechunk_{event_name}_{job_name}
+AFTER {event} ON {schema}.{table}
+REFERENCING {transition_tables}
+FOR EACH STATEMENT
+EXECUTE FUNCTION vectorize.handle_rechunk_{job_name}();",
+                event_name = event.to_lowercase(),
+            ));
+        }
+    }
+    queries
+}
+
+/// statements removing the triggers that chunk the rows of a chunked job's source table
+pub fn drop_rechunk_trigger_queries(job_name: &str, chunking: &Chunking) -> Vec<String> {
+    let (schema, table) = (&chunking.schema, &chunking.table);
+    vec![
+        format!("DROP TRIGGER IF EXISTS vectorize_rechunk_insert_{job_name} ON {schema}.{table};"),
+        format!("DROP TRIGGER IF EXISTS vectorize_rechunk_update_{job_name} ON {schema}.{table};"),
+        // cascades to the triggers on the partitions of a partitioned table
+        format!("DROP FUNCTION IF EXISTS vectorize.handle_rechunk_{job_name}() CASCADE;"),
+    ]
+}
+
+/// called by the triggers on a chunked job's source table, with the record ids of the rows that were inserted or changed
+/// their chunks are replaced with those of their new text, which the job then embeds like any new rows
+#[pg_extern]
+fn _rechunk_rows(job_name: &str, record_ids: Vec<String>) -> Result<()> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: JobParams = serde_json::from_value(meta.params)?;
+    let chunking = job_params
+        .chunking
+        .as_ref()
+        .context("job does not chunk its rows")?;
+    if chunking.params.strategy == ChunkStrategy::semantic {
+        // the worker replaces the rows' chunks once it has chunked them
+        enqueue_semantic_chunking(job_name, Some(record_ids))?;
+        return Ok(());
+    }
+    write_chunks(
+        chunking,
+        &job_params.schema,
+        &job_params.table,
+        job_params.preprocess,
+        Some(&meta.transformer),
+        Some(record_ids),
+    )?;
+    Ok(())
+}
+
+/// chunks every row of a chunked job again, with new chunk params
+/// chunks whose text is the same as before keep their embeddings, only the new chunks are embedded by the job
+/// the semantic strategy is chunked again by the worker
+pub fn rechunk(
+    job_name: &str,
+    params: ChunkParams,
+    column_params: Option<serde_json::Value>,
+) -> Result<String> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let mut job_params: JobParams = serde_json::from_value(meta.params)?;
+    let Some(chunking) = job_params.chunking.as_mut() else {
+        bail!("job {job_name} does not chunk its rows");
+    };
+    chunking.params = match column_params {
+        Some(_) if chunking.input_template.is_some() => {
+            bail!("chunk_column_params can not be used along with input_template, the templated text is chunked as a whole")
+        }
+        Some(column_params) => with_column_params(params, &chunking.columns, column_params)?,
+        None => params,
+    };
+    util::update_job_params(job_name, &job_params)?;
+    let chunking = job_params.chunking.as_ref().expect("chunking was set");
+    if chunking.params.strategy == ChunkStrategy::semantic {
+        let queued = enqueue_semantic_chunking(job_name, None)?;
+        return Ok(format!(
+            "Rechunking job {job_name}, {queued} rows queued for the worker"
+        ));
+    }
+    let counts = write_chunks(
+        chunking,
+        &job_params.schema,
+        &job_params.table,
+        job_params.preprocess,
+        Some(&meta.transformer),
+        None,
+    )?;
+    Ok(format!(
+        "Rechunked job {job_name}: {written} new chunks to embed, {kept} chunks kept, {deleted} chunks deleted, {discarded} chunks filtered out",
+        written = counts.written,
+        kept = counts.kept,
+        deleted = counts.deleted,
+        discarded = counts.discarded,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_params() {
+        let params = chunk_params(
+            200,
+            20,
+            Some("recursive"),
+            Some(vec!["\n".to_string()]),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(params.strategy, ChunkStrategy::recursive);
+        assert_eq!(params.separators, vec!["\n".to_string()]);
+        assert_eq!(
+            chunk_params(200, 0, None, None, None, None, None)
+                .unwrap()
+                .strategy,
+            ChunkStrategy::tokens
+        );
+        assert!(chunk_params(0, 0, None, None, None, None, None).is_err());
+        assert!(chunk_params(200, 200, None, None, None, None, None).is_err());
+        assert!(chunk_params(200, 0, Some("paragraphs"), None, None, None, None).is_err());
+        // separators only apply to the recursive strategy
+        assert!(
+            chunk_params(200, 0, None, Some(vec!["\n".to_string()]), None, None, None).is_err()
+        );
+        assert!(chunk_params(
+            200,
+            0,
+            Some("recursive"),
+            Some(vec!["".to_string()]),
+            None,
+            None,
+            None
+        )
+        .is_err());
+        // the similarity threshold only applies to the semantic strategy, which has no overlap
+        let params = chunk_params(200, 0, Some("semantic"), None, Some(0.6), None, None).unwrap();
+        assert_eq!(params.similarity_threshold, Some(0.6));
+        assert!(chunk_params(200, 20, Some("semantic"), None, None, None, None).is_err());
+        assert!(chunk_params(200, 0, Some("semantic"), None, Some(1.5), None, None).is_err());
+        assert!(chunk_params(200, 0, None, None, Some(0.6), None, None).is_err());
+        // the separator strategy splits on its regular expression, without overlap
+        let params = chunk_params(
+            200,
+            0,
+            Some("separator"),
+            None,
+            None,
+            Some("\n---\n".to_string()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(params.separator.as_deref(), Some("\n---\n"));
+        assert!(chunk_params(200, 0, Some("separator"), None, None, None, None).is_err());
+        assert!(
+            chunk_params(200, 0, Some("separator"), None, None, Some("(".to_string())).is_err(),
+            None
+        );
+        assert!(chunk_params(
+            200,
+            20,
+            Some("separator"),
+            None,
+            None,
+            Some(";".to_string()),
+            None
+        )
+        .is_err());
+        assert!(chunk_params(200, 0, None, None, None, Some(";".to_string()), None).is_err());
+        // windows overlap by chunk_size - chunk_stride rather than chunk_overlap
+        let params = chunk_params(200, 0, Some("window"), None, None, None, Some(50)).unwrap();
+        assert_eq!(params.chunk_stride, Some(50));
+        assert!(chunk_params(200, 20, Some("window"), None, None, None, None).is_err());
+        assert!(chunk_params(200, 0, Some("window"), None, None, None, Some(201)).is_err());
+        assert!(chunk_params(200, 0, None, None, None, None, Some(50)).is_err());
+    }
+
+    #[test]
+    fn test_chunk_filter() {
+        let filter = chunk_filter(serde_json::json!({
+            "min_tokens": 20,
+            "exclude_patterns": ["(?i)cookie policy"]
+        }))
+        .unwrap();
+        assert_eq!(filter.min_tokens, Some(20));
+        assert_eq!(filter.function, None);
+        assert!(chunk_filter(serde_json::json!({"min_length": 20})).is_err());
+        assert!(chunk_filter(serde_json::json!({"exclude_patterns": ["("]})).is_err());
+        assert!(chunk_filter(serde_json::json!({"min_tokens": -1})).is_err());
+    }
+
+    #[test]
+    fn test_with_column_params() {
+        let columns = vec!["title".to_string(), "body".to_string()];
+        let params = chunk_params(512, 0, Some("recursive"), None, None, None, None).unwrap();
+        let params = with_column_params(
+            params,
+            &columns,
+            serde_json::json!({"title": null, "body": {"chunk_strategy": "sentence"}}),
+        )
+        .unwrap();
+        assert_eq!(params.of_column("title"), None);
+        // a column's chunk_size defaults to the job's
+        let body = params.of_column("body").unwrap();
+        assert_eq!(body.strategy, ChunkStrategy::sentence);
+        assert_eq!(body.chunk_size, 512);
+
+        let params = chunk_params(512, 0, None, None, None, None, None).unwrap();
+        let invalid = [
+            serde_json::json!({"summary": null}),
+            serde_json::json!({"body": {"chunk_size": 0}}),
+            serde_json::json!({"body": {"chunk_strategy": "semantic"}}),
+            serde_json::json!({"body": {"size": 100}}),
+            serde_json::json!(["body"]),
+        ];
+        for column_params in invalid {
+            assert!(with_column_params(params.clone(), &columns, column_params).is_err());
+        }
+        let semantic = chunk_params(512, 0, Some("semantic"), None, None, None, None).unwrap();
+        assert!(
+            with_column_params(semantic, &columns, serde_json::json!({"title": null})).is_err()
+        );
+    }
+
+    #[test]
+    fn test_chunks_queries() {
+        let chunking = Chunking {
+            schema: "public".to_string(),
+            table: "articles".to_string(),
+            columns: vec!["title".to_string(), "body".to_string()],
+            primary_key: "article_id".to_string(),
+            pkey_type: "integer".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            select_rows_query(&chunking, false),
+            "SELECT article_id::text AS id, title::text, body::text FROM public.articles"
+        );
+        assert!(select_rows_query(&chunking, true).ends_with(" WHERE article_id::text = ANY($1)"));
+        // the templated text of a row is chunked in place of its columns
+        let templated = Chunking {
+            input_template: Some("pg_catalog.format('%s: %s', title, body)".to_string()),
+            ..chunking.clone()
+        };
+        assert_eq!(
+            select_rows_query(&templated, false),
+            "SELECT article_id::text AS id, (SELECT (pg_catalog.format('%s: %s', title, body))::text FROM (SELECT public.articles.*) input_row) FROM public.articles"
+        );
+        assert_eq!(templated.sources(), vec!["input_template"]);
+        let query = create_chunks_table_query("vectorize", "_chunks_articles", &chunking, false);
+        assert!(query.starts_with("CREATE TABLE vectorize._chunks_articles ("));
+        assert!(query.contains("original_id integer NOT NULL"));
+        assert!(query.contains("char_start INTEGER NOT NULL"));
+        assert!(query.contains("content_hash TEXT GENERATED ALWAYS AS (md5(chunk)) STORED"));
+        assert!(!query.contains("FOREIGN KEY"));
+        // the chunks of a job reference their rows
+        let query = create_chunks_table_query("vectorize", "_chunks_articles", &chunking, true);
+        assert!(query.contains(
+            "FOREIGN KEY (original_id) REFERENCES public.articles (article_id)\n                ON DELETE CASCADE ON UPDATE CASCADE"
+        ));
+        // the chunks of a batch of rows are written with one statement each
+        let queries = ChunkQueries::new(&chunking, "vectorize", "_chunks_articles");
+        assert!(queries
+            .insert
+            .contains("SELECT c.original_id::integer, c.chunk_index"));
+        assert!(queries.insert.contains("FROM unnest($1::text[], $2::int[]"));
+        assert!(queries.update.contains("WHERE t.chunk_id = c.chunk_id"));
+    }
+
+    #[test]
+    fn test_rechunk_trigger_queries() {
+        let chunking = Chunking {
+            schema: "public".to_string(),
+            table: "articles".to_string(),
+            columns: vec!["title".to_string(), "body".to_string()],
+            primary_key: "article_id".to_string(),
+            pkey_type: "integer".to_string(),
+            ..Default::default()
+        };
+        let queries = rechunk_trigger_queries("my_job", &chunking, None);
+        assert_eq!(queries.len(), 3);
+        assert!(queries[0].contains("CREATE OR REPLACE FUNCTION vectorize.handle_rechunk_my_job()"));
+        assert!(queries[0]
+            .contains("WHERE n.title IS DISTINCT FROM o.title OR n.body IS DISTINCT FROM o.body;"));
+        assert!(queries[0].contains("PERFORM vectorize._rechunk_rows('my_job', record_ids);"));
+        // new rows are chunked as they are inserted
+        assert!(queries[1].contains("CREATE OR REPLACE TRIGGER vectorize_rechunk_insert_my_job\nAFTER INSERT ON public.articles"));
+        assert!(queries[2].contains("AFTER UPDATE ON public.articles"));
+        let drop_queries = drop_rechunk_trigger_queries("my_job", &chunking);
+        assert_eq!(
+            drop_queries[0],
+            "DROP TRIGGER IF EXISTS vectorize_rechunk_insert_my_job ON public.articles;"
+        );
+        assert_eq!(drop_queries.len(), 3);
+    }
+}
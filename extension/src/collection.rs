@@ -0,0 +1,287 @@
+use crate::compat::{self, arg};
+use crate::init::{self, VECTORIZE_QUEUE};
+use crate::query::check_input;
+use crate::search::init_table;
+use crate::util;
+
+use anyhow::{bail, Result};
+use pgrx::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use vectorize_core::types::{
+    ContentType, IndexDist, IndexParams, JobParams, Model, PrimaryKey, StorageParams, TableMethod,
+    VectorStorage,
+};
+
+// columns of a collection's documents table
+pub const ID_COLUMN: &str = "id";
+pub const CONTENT_COLUMN: &str = "content";
+pub const METADATA_COLUMN: &str = "metadata";
+const UPDATED_AT_COLUMN: &str = "last_updated_at";
+
+/// creates a collection's documents table, and the job that embeds their content
+/// the job is named after the collection
+pub fn create_collection(
+    name: &str,
+    schema: &str,
+    transformer: &Model,
+    index_dist_type: IndexDist,
+    schedule: &str,
+) -> Result<String> {
+    init::validate_job_name(name)?;
+    check_input(schema)?;
+    compat::run(&create_collection_table_query(schema, name), vec![])?;
+    init_table(
+        name,
+        schema,
+        name,
+        vec![CONTENT_COLUMN.to_string()],
+        ID_COLUMN,
+        Some(UPDATED_AT_COLUMN.to_string()),
+        index_dist_type,
+        IndexParams::default(),
+        None,
+        BTreeMap::new(),
+        false,
+        None,
+        None,
+        None,
+        true,
+        None,
+        VectorStorage::default(),
+        StorageParams::default(),
+        None,
+        None,
+        None,
+        false,
+        ContentType::text,
+        transformer,
+        TableMethod::join,
+        schedule,
+    )?;
+    Ok(format!("Successfully created collection: {schema}.{name}"))
+}
+
+/// a document written to a collection
+/// documents without an id are given a random uuid
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Document {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// the params of a collection's job, erroring when the job is not a collection
+pub fn get_collection(name: &str) -> Result<JobParams> {
+    let meta = util::get_vectorize_meta_spi(name)?;
+    let job_params: JobParams = serde_json::from_value(meta.params)?;
+    if !job_params.collection {
+        bail!("job {name} is not a collection");
+    }
+    Ok(job_params)
+}
+
+/// inserts documents into a collection, replacing the content and metadata of those whose id already exists
+/// the collection's job then embeds them, as it does for any other write to the table
+/// returns the id of each document, in the order they were given
+pub fn upsert(collection: &str, documents: &[Document]) -> Result<Vec<String>> {
+    let job_params = get_collection(collection)?;
+    let query = upsert_query(&job_params.schema, &job_params.table);
+    let documents = pgrx::JsonB(serde_json::to_value(documents)?);
+    compat::connect(|mut client| {
+        let mut ids = Vec::new();
+        for row in compat::update(&mut client, &query, vec![arg(documents)])? {
+            ids.extend(row[ID_COLUMN].value::<String>()?);
+        }
+        Ok(ids)
+    })
+}
+
+// takes the documents as a jsonb array in $1
+fn upsert_query(schema: &str, table: &str) -> String {
+    format!(
+        "INSERT INTO {schema}.{table} ({ID_COLUMN}, {CONTENT_COLUMN}, {METADATA_COLUMN})
+        SELECT COALESCE(d.id, gen_random_uuid()::text), d.content, COALESCE(d.metadata, '{{}}'::jsonb)
+        FROM jsonb_to_recordset($1) AS d(id TEXT, content TEXT, metadata JSONB)
+        ON CONFLICT ({ID_COLUMN}) DO UPDATE SET
+            {CONTENT_COLUMN} = EXCLUDED.{CONTENT_COLUMN},
+            {METADATA_COLUMN} = EXCLUDED.{METADATA_COLUMN},
+            {UPDATED_AT_COLUMN} = NOW()
+        RETURNING {ID_COLUMN}"
+    )
+}
+
+/// removes documents from a collection by their id
+/// their embeddings are removed along with them, and they are dropped from messages still waiting in the job queue
+/// returns the number of documents deleted
+pub fn delete(job_name: &str, ids: Vec<String>) -> Result<i64> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: JobParams = serde_json::from_value(meta.params)?;
+    // the table of any other job belongs to the application, which deletes its rows itself
+    if !job_params.collection {
+        bail!(
+            "job {job_name} is not a collection, delete its rows from {}.{} instead",
+            job_params.schema,
+            job_params.table
+        );
+    }
+    let queries = delete_queries(job_name, &job_params);
+    compat::connect(|mut client| {
+        let mut deleted = 0;
+        for (i, query) in queries.iter().enumerate() {
+            let tup_table = compat::update(&mut client, query, vec![arg(ids.clone())])?;
+            if i == queries.len() - 1 {
+                if let Some(row) = tup_table.into_iter().next() {
+                    deleted = row["deleted"].value::<i64>()?.unwrap_or_default();
+                }
+            }
+        }
+        Ok(deleted)
+    })
+}
+
+// each query takes the record ids as a text array in $1, the last one counts the deleted rows
+// queued work goes first, so that the worker does not embed deleted records after them
+// messages holding deleted records are sent again without them, then deleted, through pgmq's functions
+// along with the provenance of the embeddings that are deleted
+// embeddings tables reference the source table with ON DELETE CASCADE
+fn delete_queries(job_name: &str, job_params: &JobParams) -> Vec<String> {
+    let pkey = job_params.pkey();
+    let queued = queued_record_matches(&pkey, "i->>'record_id'", "$1");
+    let holds_deleted = format!(
+        "message->>'job_name' = '{job_name}'
+        AND EXISTS (SELECT 1 FROM jsonb_array_elements(message->'inputs') i WHERE {queued})"
+    );
+    let mut queries = vec![
+        format!(
+            "SELECT pgmq.send('{VECTORIZE_QUEUE}', jsonb_set(message, '{{inputs}}', remaining))
+            FROM (
+                SELECT message, (
+                    SELECT jsonb_agg(i) FROM jsonb_array_elements(message->'inputs') i WHERE NOT ({queued})
+                ) AS remaining
+                FROM pgmq.q_{VECTORIZE_QUEUE}
+                WHERE {holds_deleted}
+            ) held
+            WHERE remaining IS NOT NULL"
+        ),
+        format!(
+            "SELECT pgmq.delete('{VECTORIZE_QUEUE}', msg_id)
+            FROM pgmq.q_{VECTORIZE_QUEUE}
+            WHERE {holds_deleted}"
+        ),
+        format!(
+            "DELETE FROM vectorize.embedding_provenance WHERE job_name = '{job_name}' AND {recorded}",
+            recorded = queued_record_matches(&pkey, "record_id", "$1"),
+        ),
+    ];
+    queries.push(format!(
+        "WITH deleted AS (
+            DELETE FROM {schema}.{table} WHERE {matches} RETURNING 1
+        )
+        SELECT count(*) AS deleted FROM deleted",
+        schema = job_params.schema,
+        table = job_params.table,
+        matches = pkey.matches_record_ids(None, "$1"),
+    ));
+    queries
+}
+
+// condition matching a record id, of a queued input or of recorded provenance, against the record ids in a text array
+// composite keys are compared as jsonb, so that the formatting of the ids does not matter
+fn queued_record_matches(pkey: &PrimaryKey, record_id: &str, param: &str) -> String {
+    if pkey.is_composite() {
+        format!("({record_id})::jsonb = ANY({param}::jsonb[])")
+    } else {
+        format!("{record_id} = ANY({param})")
+    }
+}
+
+fn create_collection_table_query(schema: &str, name: &str) -> String {
+    format!(
+        "CREATE TABLE {schema}.{name} (
+            {ID_COLUMN} TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+            {CONTENT_COLUMN} TEXT NOT NULL,
+            {METADATA_COLUMN} JSONB NOT NULL DEFAULT '{{}}'::jsonb,
+            {UPDATED_AT_COLUMN} TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        );"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_collection_table_query() {
+        let query = create_collection_table_query("public", "docs");
+        assert!(query.starts_with("CREATE TABLE public.docs ("));
+        assert!(query.contains("id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,"));
+        assert!(query.contains("metadata JSONB NOT NULL DEFAULT '{}'::jsonb,"));
+    }
+
+    #[test]
+    fn test_upsert_query() {
+        let query = upsert_query("public", "docs");
+        assert!(query.starts_with("INSERT INTO public.docs (id, content, metadata)"));
+        assert!(query.contains("ON CONFLICT (id) DO UPDATE SET"));
+        // cron based collections find updated documents by last_updated_at
+        assert!(query.contains("last_updated_at = NOW()"));
+    }
+
+    #[test]
+    fn test_delete_queries() {
+        let job_params = JobParams {
+            schema: "public".to_string(),
+            table: "docs".to_string(),
+            primary_key: ID_COLUMN.to_string(),
+            pkey_type: "text".to_string(),
+            collection: true,
+            ..Default::default()
+        };
+        let queries = delete_queries("docs", &job_params);
+        assert_eq!(queries.len(), 4);
+        assert!(queries[0].starts_with("SELECT pgmq.send('vectorize_jobs', jsonb_set("));
+        assert!(queries[0].contains("WHERE NOT (i->>'record_id' = ANY($1))"));
+        assert!(queries[1].starts_with("SELECT pgmq.delete('vectorize_jobs', msg_id)"));
+        assert!(queries[2].starts_with(
+            "DELETE FROM vectorize.embedding_provenance WHERE job_name = 'docs' AND record_id = ANY($1)"
+        ));
+        assert!(queries[3].contains("DELETE FROM public.docs WHERE"));
+    }
+
+    #[test]
+    fn test_queued_record_matches() {
+        let pkey = PrimaryKey::new("id", "text");
+        assert_eq!(
+            queued_record_matches(&pkey, "i->>'record_id'", "$1"),
+            "i->>'record_id' = ANY($1)"
+        );
+        let pkey = PrimaryKey::new("tenant_id, id", "integer, text");
+        assert_eq!(
+            queued_record_matches(&pkey, "i->>'record_id'", "$1"),
+            "(i->>'record_id')::jsonb = ANY($1::jsonb[])"
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        let docs: Vec<Document> = serde_json::from_value(serde_json::json!([
+            {"content": "a"},
+            {"id": "2", "content": "b", "metadata": {"product": "billing"}}
+        ]))
+        .unwrap();
+        assert_eq!(docs[0].id, None);
+        assert_eq!(docs[1].id.as_deref(), Some("2"));
+        // documents are passed to the upsert query as they were given
+        let value = serde_json::to_value(&docs[0]).unwrap();
+        assert_eq!(value, serde_json::json!({"content": "a"}));
+        assert!(serde_json::from_value::<Document>(serde_json::json!({"id": "3"})).is_err());
+        assert!(serde_json::from_value::<Document>(
+            serde_json::json!({"content": "c", "body": "d"})
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,178 @@
+// the SPI and set-returning function APIs are the parts of pgrx that change most between releases
+// queries are run through these functions so that a pgrx upgrade only has to touch this module
+use anyhow::anyhow;
+use pgrx::pg_sys::panic::CaughtError;
+use pgrx::prelude::*;
+use pgrx::spi::{SpiClient, SpiCursor, SpiResult, SpiTupleTable};
+use std::panic::AssertUnwindSafe;
+
+/// a query argument, typed by its value
+pub type SpiArg = (PgOid, Option<pg_sys::Datum>);
+
+/// binds a value as a query argument, using the Postgres type of the value
+pub fn arg<T: IntoDatum>(value: T) -> SpiArg {
+    (PgOid::from(T::type_oid()), value.into_datum())
+}
+
+/// connects to SPI for the duration of f, whose queries are run with select and update
+pub fn connect<R, F: FnOnce(SpiClient<'_>) -> R>(f: F) -> R {
+    Spi::connect(f)
+}
+
+/// runs a query and returns the first column of its first row
+pub fn get_one<A: FromDatum + IntoDatum>(query: &str, args: Vec<SpiArg>) -> SpiResult<Option<A>> {
+    Spi::get_one_with_args(query, args)
+}
+
+/// runs a query for its side effects
+pub fn run(query: &str, args: Vec<SpiArg>) -> SpiResult<()> {
+    Spi::run_with_args(query, Some(args))
+}
+
+/// runs a read only query on a connected client
+pub fn select<'conn>(
+    client: &SpiClient<'conn>,
+    query: &str,
+    args: Vec<SpiArg>,
+) -> SpiResult<SpiTupleTable<'conn>> {
+    client.select(query, None, none_if_empty(args))
+}
+
+/// runs a query that may write on a connected client
+pub fn update<'conn>(
+    client: &mut SpiClient<'conn>,
+    query: &str,
+    args: Vec<SpiArg>,
+) -> SpiResult<SpiTupleTable<'conn>> {
+    client.update(query, None, none_if_empty(args))
+}
+
+/// opens a cursor over the rows of a read only query, so that they can be fetched a batch at a time
+pub fn open_cursor<'conn>(
+    client: &SpiClient<'conn>,
+    query: &str,
+    args: Vec<SpiArg>,
+) -> SpiResult<SpiCursor<'conn>> {
+    client.try_open_cursor(query, none_if_empty(args))
+}
+
+/// the rows returned by a set-returning function
+pub fn table<'a, Row: 'a>(rows: impl IntoIterator<Item = Row> + 'a) -> TableIterator<'a, Row> {
+    TableIterator::new(rows)
+}
+
+/// the single row returned by a set-returning function
+pub fn one_row<'a, Row: 'a>(row: Row) -> TableIterator<'a, Row> {
+    TableIterator::once(row)
+}
+
+/// runs f in a subtransaction, which is rolled back when f fails or raises an ERROR, and kept otherwise
+/// so that one failing step of a long running call does not roll back the steps before it
+pub fn subtransaction<T>(f: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    // the subtransaction is entered in a memory context and resource owner of its own
+    let (context, owner) = unsafe { (pg_sys::CurrentMemoryContext, pg_sys::CurrentResourceOwner) };
+    unsafe { pg_sys::BeginInternalSubTransaction(std::ptr::null()) };
+    let result = PgTryBuilder::new(AssertUnwindSafe(f))
+        .catch_others(|e| {
+            let message = match &e {
+                CaughtError::PostgresError(report) | CaughtError::ErrorReport(report) => {
+                    report.message().to_string()
+                }
+                CaughtError::RustPanic { ereport, .. } => ereport.message().to_string(),
+            };
+            Err(anyhow!(message))
+        })
+        .execute();
+    unsafe {
+        if result.is_ok() {
+            pg_sys::ReleaseCurrentSubTransaction();
+        } else {
+            pg_sys::RollbackAndReleaseCurrentSubTransaction();
+        }
+        pg_sys::CurrentMemoryContext = context;
+        pg_sys::CurrentResourceOwner = owner;
+    }
+    result
+}
+
+fn none_if_empty(args: Vec<SpiArg>) -> Option<Vec<SpiArg>> {
+    if args.is_empty() {
+        None
+    } else {
+        Some(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arg_types() {
+        assert_eq!(arg(1_i64).0, PgBuiltInOids::INT8OID.oid());
+        assert_eq!(arg(1_i32).0, PgBuiltInOids::INT4OID.oid());
+        assert_eq!(arg(true).0, PgBuiltInOids::BOOLOID.oid());
+        // a null keeps the type of the value it stands in for
+        let null = arg(None::<i64>);
+        assert_eq!(null.0, PgBuiltInOids::INT8OID.oid());
+        assert!(null.1.is_none());
+    }
+}
+
+// run by `make test-unit` against the Postgres version being built for, 17 by default
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod pg_tests {
+    use super::*;
+
+    #[pg_test]
+    fn test_server_version() {
+        let version: Option<i32> =
+            get_one("SELECT current_setting('server_version_num')::int", vec![]).unwrap();
+        assert_eq!(version.unwrap() / 10000, pg_sys::PG_MAJORVERSION_NUM as i32);
+    }
+
+    #[pg_test]
+    fn test_queries() {
+        run("CREATE TEMP TABLE compat_test (id INT, name TEXT)", vec![]).unwrap();
+        connect(|mut c| {
+            update(
+                &mut c,
+                "INSERT INTO compat_test VALUES ($1, $2), ($3, NULL)",
+                vec![arg(1), arg("a"), arg(2)],
+            )?;
+            let rows = select(
+                &c,
+                "SELECT name FROM compat_test WHERE id = $1",
+                vec![arg(1)],
+            )?;
+            let names: Vec<Option<String>> = rows
+                .map(|row| row["name"].value())
+                .collect::<SpiResult<_>>()?;
+            assert_eq!(names, vec![Some("a".to_string())]);
+            Ok::<_, spi::Error>(())
+        })
+        .unwrap();
+        let count: Option<i64> = get_one(
+            "SELECT count(*) FROM compat_test WHERE name IS NULL",
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(count, Some(1));
+    }
+
+    #[pg_test]
+    fn test_subtransaction() {
+        run("CREATE TEMP TABLE compat_subxact (id INT)", vec![]).unwrap();
+        // an error rolls back only the subtransaction it was raised in
+        let failed = subtransaction(|| {
+            run("INSERT INTO compat_subxact VALUES (1)", vec![])?;
+            run("SELECT 1 / 0", vec![])?;
+            Ok(())
+        });
+        assert!(failed.unwrap_err().to_string().contains("division by zero"));
+        subtransaction(|| Ok(run("INSERT INTO compat_subxact VALUES (2)", vec![])?)).unwrap();
+        let ids: Option<i64> = get_one("SELECT sum(id) FROM compat_subxact", vec![]).unwrap();
+        assert_eq!(ids, Some(2));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::compat::{self, arg};
+use crate::registry::parse_source;
+
+use anyhow::{bail, Result};
+use pgrx::prelude::*;
+use vectorize_core::types::{Credential, Model};
+use vectorize_core::worker::ops::CREDENTIAL_QUERY;
+
+/// the credential of a name in vectorize.credentials
+pub fn get(name: &str) -> Result<Credential> {
+    compat::connect(|client| {
+        let tup_table = compat::select(&client, CREDENTIAL_QUERY, vec![arg(name)])?;
+        let Some(row) = tup_table.into_iter().next() else {
+            bail!("credential {name} does not exist");
+        };
+        let source: String = row["source"].value()?.unwrap_or_default();
+        Ok(Credential {
+            source: parse_source(&source)?,
+            api_key: row["api_key"].value()?,
+            virtual_key: row["virtual_key"].value()?,
+        })
+    })
+}
+
+/// errors unless the credential exists and is for the model's provider
+pub fn validate(name: &str, model: &Model) -> Result<()> {
+    let credential = get(name)?;
+    if credential.source != model.source {
+        bail!(
+            "credential {name} is for {}, not for {}, the provider of {model}",
+            credential.source,
+            model.source
+        );
+    }
+    Ok(())
+}
+
+/// adds a credential to vectorize.credentials, or replaces its keys
+pub fn create_credential(
+    name: &str,
+    source: &str,
+    api_key: Option<&str>,
+    virtual_key: Option<&str>,
+) -> Result<String> {
+    parse_source(source)?;
+    if api_key.is_none() && virtual_key.is_none() {
+        bail!("a credential needs an api_key or a virtual_key");
+    }
+    compat::run(
+        "INSERT INTO vectorize.credentials (name, source, api_key, virtual_key)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (name)
+        DO UPDATE SET source = EXCLUDED.source, api_key = EXCLUDED.api_key,
+            virtual_key = EXCLUDED.virtual_key",
+        vec![arg(name), arg(source), arg(api_key), arg(virtual_key)],
+    )?;
+    Ok(format!("Created credential {name}"))
+}
+
+/// removes a credential from vectorize.credentials, as long as no job uses it
+pub fn drop_credential(name: &str) -> Result<String> {
+    let jobs: Option<String> = compat::get_one(
+        "SELECT string_agg(name, ', ' ORDER BY name) FROM vectorize.job WHERE params->>'credential' = $1",
+        vec![arg(name)],
+    )?;
+    if let Some(jobs) = jobs {
+        bail!("credential {name} is used by jobs: {jobs}");
+    }
+    let removed: Option<String> = compat::get_one(
+        "DELETE FROM vectorize.credentials WHERE name = $1 RETURNING name",
+        vec![arg(name)],
+    )?;
+    match removed {
+        Some(_) => Ok(format!("Dropped credential {name}")),
+        None => bail!("credential {name} does not exist"),
+    }
+}
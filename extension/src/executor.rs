@@ -9,35 +9,15 @@ use sqlx::error::Error;
 use sqlx::postgres::PgRow;
 use sqlx::types::chrono::Utc;
 use sqlx::{Pool, Postgres, Row};
-use tiktoken_rs::cl100k_base;
+use tiktoken_rs::{cl100k_base, CoreBPE};
+use vectorize_core::chunking::create_batches;
 use vectorize_core::errors::DatabaseError;
 use vectorize_core::transformers::types::Inputs;
-use vectorize_core::types::{JobMessage, JobParams, TableMethod, VectorizeMeta};
-
-// creates batches based on total token count
-// batch_size is the max token count per batch
-pub fn create_batches(data: Vec<Inputs>, batch_size: i32) -> Vec<Vec<Inputs>> {
-    let mut groups: Vec<Vec<Inputs>> = Vec::new();
-    let mut current_group: Vec<Inputs> = Vec::new();
-    let mut current_token_count = 0;
-
-    for input in data {
-        if current_token_count + input.token_estimate > batch_size {
-            // Create a new group
-            groups.push(current_group);
-            current_group = Vec::new();
-            current_token_count = 0;
-        }
-        current_token_count += input.token_estimate;
-        current_group.push(input);
-    }
-
-    // Add any remaining inputs to the groups
-    if !current_group.is_empty() {
-        groups.push(current_group);
-    }
-    groups
-}
+use vectorize_core::types::{
+    JobMessage, JobParams, TableMethod, VectorizeMeta, DECRYPTED_INPUT_TOKEN_ESTIMATE,
+    IMAGE_INPUT_TOKEN_ESTIMATE,
+};
+use vectorize_core::worker::ops;
 
 // called by pg_cron on schedule
 // identifiers new inputs and enqueues them
@@ -51,23 +31,43 @@ fn job_execute(job_name: String) {
         .build()
         .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
 
-    let max_batch_size = BATCH_SIZE.get();
-
     runtime.block_on(async {
         let conn = get_pg_conn()
             .await
             .unwrap_or_else(|e| error!("pg-vectorize: failed to establish db connection: {}", e));
+        let paused = ops::is_job_paused(&conn, &job_name)
+            .await
+            .unwrap_or_else(|e| error!("failed to get job status: {}", e));
+        if paused {
+            log!("pg-vectorize: job: {} is paused, skipping", job_name);
+            return;
+        }
         let queue = pgmq::PGMQueueExt::new_with_pool(conn.clone()).await;
         let meta = get_vectorize_meta(&job_name, &conn)
             .await
             .unwrap_or_else(|e| error!("failed to get job metadata: {}", e));
         let job_params = serde_json::from_value::<JobParams>(meta.params.clone())
             .unwrap_or_else(|e| error!("failed to deserialize job params: {}", e));
+        let max_batch_size = job_params.batch_size.unwrap_or_else(|| BATCH_SIZE.get());
         let _last_completion = match meta.last_completion {
             Some(t) => t,
             None => Utc.with_ymd_and_hms(970, 1, 1, 0, 0, 0).unwrap(),
         };
 
+        if !job_params.source_kind.is_table() {
+            let deleted: i64 = sqlx::query_scalar(&deleted_rows_query(&job_name, &job_params))
+                .fetch_one(&conn)
+                .await
+                .unwrap_or_else(|e| error!("failed to delete embeddings of deleted rows: {}", e));
+            if deleted > 0 {
+                log!(
+                    "pg-vectorize: job: {}, deleted embeddings of {} rows",
+                    job_name,
+                    deleted
+                );
+            }
+        }
+
         let new_or_updated_rows = get_new_updates(&conn, &job_name, job_params)
             .await
             .unwrap_or_else(|e| error!("failed to get new updates: {}", e));
@@ -86,6 +86,7 @@ fn job_execute(job_name: String) {
                         job_name: job_name.clone(),
                         job_meta: meta.clone(),
                         inputs: b,
+                        semantic_chunking: false,
                     };
                     let msg_id = queue
                         .send(VECTORIZE_QUEUE, &msg)
@@ -101,6 +102,21 @@ fn job_execute(job_name: String) {
     })
 }
 
+// jobs with encrypted columns enqueue their rows without input text
+// the worker decrypts the inputs itself, so that plaintext is never written to the queue
+pub static ENCRYPTED_INPUT_PLACEHOLDER: &str = "''::text";
+
+/// token estimate for a new input, used to size the batches sent to the worker
+pub fn estimate_tokens(bpe: &CoreBPE, job_params: &JobParams, input: &str) -> i32 {
+    if job_params.decryption.is_some() {
+        DECRYPTED_INPUT_TOKEN_ESTIMATE
+    } else if !job_params.content_type.is_text() {
+        IMAGE_INPUT_TOKEN_ESTIMATE
+    } else {
+        bpe.encode_with_special_tokens(input).len() as i32
+    }
+}
+
 // get job meta
 pub async fn get_vectorize_meta(
     job_name: &str,
@@ -122,64 +138,92 @@ pub async fn get_vectorize_meta(
 }
 
 pub fn new_rows_query_join(job_name: &str, job_params: &JobParams) -> String {
-    let cols = &job_params
-        .columns
-        .iter()
-        .map(|s| format!("t0.{}", s))
-        .collect::<Vec<_>>()
-        .join(",");
+    let cols = input_text(job_params, Some("t0"));
     let schema = job_params.schema.clone();
     let table = job_params.table.clone();
+    let pkey = job_params.pkey();
+    let join_key = &pkey.column_names()[0];
 
-    let base_query = format!(
-        "
-    SELECT t0.{join_key}::text as record_id, {cols} as input_text
-    FROM {schema}.{table} t0
-    LEFT JOIN vectorize._embeddings_{job_name} t1 ON t0.{join_key} = t1.{join_key}
-    WHERE t1.{join_key} IS NULL",
-        join_key = job_params.primary_key,
-        cols = cols,
-        schema = schema,
-        table = table,
-        job_name = job_name
-    );
-    if let Some(updated_at_col) = &job_params.update_time_col {
-        // updated_at_column is not required when `schedule` is realtime
-        let where_clause = format!(
-            "
+    // updated_at_column is not required when `schedule` is realtime
+    let needs_embeddings = match &job_params.update_time_col {
+        Some(updated_at_col) => format!(
+            "(t1.{join_key} IS NULL
             OR t0.{updated_at_col} > COALESCE
             (
                 t1.updated_at::timestamp,
                 '0001-01-01 00:00:00'::timestamp
-            )",
-        );
-        format!(
-            "
-            {base_query}
-            {where_clause}
+            ))
+            {ttl_clause}",
+            ttl_clause = ttl_clause(job_params, &format!("t0.{updated_at_col}")),
+        ),
+        None => format!("t1.{join_key} IS NULL"),
+    };
+    format!(
         "
+    SELECT {record_id} as record_id, {cols} as input_text
+    FROM {schema}.{table} t0
+    LEFT JOIN {embeddings_schema}._embeddings_{job_name} t1 ON {join_on}
+    WHERE {needs_embeddings}",
+        record_id = pkey.record_id(Some("t0")),
+        join_on = pkey.join_on("t0", "t1"),
+        embeddings_schema = job_params.embeddings_schema(),
+    )
+}
+
+/// deletes the embeddings, and their provenance, of rows that are no longer in a view or foreign table
+/// the embeddings table can only reference tables, so deletes from these do not cascade
+pub fn deleted_rows_query(job_name: &str, job_params: &JobParams) -> String {
+    let pkey = job_params.pkey();
+    format!(
+        "WITH deleted AS (
+            DELETE FROM {embeddings_schema}._embeddings_{job_name} t1
+            WHERE NOT EXISTS (SELECT 1 FROM {schema}.{table} t0 WHERE {join_on})
+            RETURNING {record_id} AS record_id
+        ), forgotten AS (
+            DELETE FROM vectorize.embedding_provenance p USING deleted
+            WHERE p.job_name = '{job_name}' AND p.record_id = deleted.record_id
         )
-    } else {
-        base_query
-    }
+        SELECT count(*) FROM deleted",
+        embeddings_schema = job_params.embeddings_schema(),
+        schema = job_params.schema,
+        table = job_params.table,
+        join_on = pkey.join_on("t0", "t1"),
+        record_id = pkey.record_id(Some("t1")),
+    )
 }
 
-pub fn new_rows_query(job_name: &str, job_params: &JobParams) -> String {
-    let cols = collapse_to_csv(&job_params.columns);
+// rows last updated longer than the job's ttl ago would only be purged again, so they are not embedded
+fn ttl_clause(job_params: &JobParams, updated_at_col: &str) -> String {
+    match &job_params.ttl {
+        Some(ttl) => format!(
+            "AND {updated_at_col} >= NOW() - '{interval}'::interval",
+            interval = ttl.interval
+        ),
+        None => "".to_string(),
+    }
+}
 
-    // query source and return any new rows that need transformation
-    // return any row where last updated embedding is also null (never populated)
-    let base_query = format!(
+/// query returning every row of the source table, whether or not it already has embeddings
+pub fn all_rows_query(job_params: &JobParams) -> String {
+    let cols = input_text(job_params, None);
+    format!(
         "
         SELECT 
-        {record_id}::text as record_id,
+        {record_id} as record_id,
         {cols} as input_text
         FROM {schema}.{table}
         ",
-        record_id = job_params.primary_key,
+        record_id = job_params.pkey().record_id(None),
         schema = job_params.schema,
         table = job_params.table,
-    );
+    )
+}
+
+pub fn new_rows_query(job_name: &str, job_params: &JobParams) -> String {
+    // query source and return any new rows that need transformation
+    // return any row where last updated embedding is also null (never populated)
+    // without an update time column, e.g. for realtime jobs, only the rows that were never embedded are new
+    let base_query = all_rows_query(job_params);
     if let Some(updated_at_col) = &job_params.update_time_col {
         // updated_at_column is not required when `schedule` is realtime
         let where_clause = format!(
@@ -188,7 +232,9 @@ pub fn new_rows_query(job_name: &str, job_params: &JobParams) -> String {
             (
                 {job_name}_updated_at::timestamp,
                 '0001-01-01 00:00:00'::timestamp
-            )",
+            )
+            {ttl_clause}",
+            ttl_clause = ttl_clause(job_params, updated_at_col),
         );
         format!(
             "
@@ -197,7 +243,12 @@ pub fn new_rows_query(job_name: &str, job_params: &JobParams) -> String {
         "
         )
     } else {
-        base_query
+        format!(
+            "
+            {base_query}
+            WHERE {job_name}_updated_at IS NULL
+        "
+        )
     }
 }
 
@@ -220,7 +271,7 @@ pub async fn get_new_updates(
                 let mut new_inputs: Vec<Inputs> = Vec::new();
                 for r in rows {
                     let ipt: String = r.get("input_text");
-                    let token_estimate = bpe.encode_with_special_tokens(&ipt).len() as i32;
+                    let token_estimate = estimate_tokens(&bpe, &job_params, &ipt);
                     new_inputs.push(Inputs {
                         record_id: r.get("record_id"),
                         inputs: ipt.trim().to_owned(),
@@ -238,76 +289,73 @@ pub async fn get_new_updates(
     }
 }
 
-fn collapse_to_csv(strings: &[String]) -> String {
-    strings
+/// the expression producing a row's input text from the job's columns, or from its input template
+/// weighted jobs keep the columns apart, so that the worker can embed each of them on its own
+fn input_text(job_params: &JobParams, alias: Option<&str>) -> String {
+    if job_params.decryption.is_some() {
+        return ENCRYPTED_INPUT_PLACEHOLDER.to_string();
+    }
+    if let Some(text) = job_params.templated_input_text(alias.unwrap_or(&job_params.table)) {
+        return text;
+    }
+    let columns: Vec<String> = job_params
+        .columns
         .iter()
-        .map(|s| {
-            check_input(s).expect("Failed to validate input");
-            s.as_str()
+        .map(|c| {
+            check_input(c).expect("Failed to validate input");
+            match alias {
+                Some(alias) => format!("{alias}.{c}"),
+                None => c.clone(),
+            }
         })
-        .collect::<Vec<_>>()
-        .join("|| ', ' ||")
+        .collect();
+    if job_params.is_weighted() {
+        job_params.weighted_input_text(&columns)
+    } else {
+        columns.join("|| ', ' ||")
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use vectorize_core::types::SourceKind;
 
     #[test]
-    fn test_create_batches_normal() {
-        let data = vec![
-            Inputs {
-                record_id: "1".to_string(),
-                inputs: "Test 1.".to_string(),
-                token_estimate: 2,
-            },
-            Inputs {
-                record_id: "2".to_string(),
-                inputs: "Test 2.".to_string(),
-                token_estimate: 2,
-            },
-            Inputs {
-                record_id: "3".to_string(),
-                inputs: "Test 3.".to_string(),
-                token_estimate: 3,
-            },
-        ];
-
-        let batches = create_batches(data, 4);
-        assert_eq!(batches.len(), 2);
-        assert_eq!(batches[0].len(), 2);
-        assert_eq!(batches[1].len(), 1);
-    }
-
-    #[test]
-    fn test_create_batches_empty() {
-        let data: Vec<Inputs> = Vec::new();
-        let batches = create_batches(data, 4);
-        assert_eq!(batches.len(), 0);
+    fn test_new_rows_query() {
+        let mut job_params = JobParams {
+            schema: "public".to_string(),
+            table: "products".to_string(),
+            columns: vec!["description".to_string()],
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            table_method: TableMethod::append,
+            update_time_col: Some("last_updated_at".to_string()),
+            ..Default::default()
+        };
+        let query = new_rows_query("search", &job_params);
+        assert!(query.contains("WHERE last_updated_at > COALESCE"));
+        // realtime jobs have no update time column, their rows are new until they are first embedded
+        job_params.update_time_col = None;
+        let query = new_rows_query("search", &job_params);
+        assert!(query.contains("WHERE search_updated_at IS NULL"));
     }
 
     #[test]
-    fn test_create_batches_large() {
-        let data = vec![
-            Inputs {
-                record_id: "1".to_string(),
-                inputs: "Test 1.".to_string(),
-                token_estimate: 2,
-            },
-            Inputs {
-                record_id: "2".to_string(),
-                inputs: "Test 2.".to_string(),
-                token_estimate: 2,
-            },
-            Inputs {
-                record_id: "3".to_string(),
-                inputs: "Test 3.".to_string(),
-                token_estimate: 100,
-            },
-        ];
-        let batches = create_batches(data, 5);
-        assert_eq!(batches.len(), 2);
-        assert_eq!(batches[1].len(), 1);
-        assert_eq!(batches[1][0].token_estimate, 100);
+    fn test_deleted_rows_query() {
+        let job_params = JobParams {
+            schema: "remote".to_string(),
+            table: "products".to_string(),
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            source_kind: SourceKind::foreign_table,
+            ..Default::default()
+        };
+        let query = deleted_rows_query("search", &job_params);
+        assert!(query.contains("DELETE FROM vectorize._embeddings_search t1"));
+        assert!(query.contains(
+            "WHERE NOT EXISTS (SELECT 1 FROM remote.products t0 WHERE t0.product_id = t1.product_id)"
+        ));
+        assert!(query.contains("RETURNING t1.product_id::text AS record_id"));
     }
 }
@@ -0,0 +1,179 @@
+use crate::compat::{self, arg};
+use crate::init::{init_queue, VECTORIZE_EXPORT_QUEUE};
+use crate::search;
+
+use anyhow::{anyhow, bail, Context, Result};
+use pgmq::{Message, PGMQueueExt};
+use pgrx::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+// a set of search results waiting to be delivered to a callback url by the background worker
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExportMessage {
+    pub job_name: String,
+    pub destination: String,
+    pub results: Vec<serde_json::Value>,
+}
+
+fn is_http_destination(destination: &str) -> bool {
+    destination.starts_with("http://") || destination.starts_with("https://")
+}
+
+/// renders results as newline delimited json
+pub fn to_ndjson(results: &[serde_json::Value]) -> String {
+    results.iter().map(|r| format!("{r}\n")).collect()
+}
+
+/// runs a search and exports the results as newline delimited json
+/// http(s) destinations are POSTed to by the background worker, anything else is a server-side file
+/// returns the number of results exported
+pub fn search_export(
+    job_name: &str,
+    query: &str,
+    destination: &str,
+    format: &str,
+    num_results: i32,
+    where_clause: Option<String>,
+    api_key: Option<String>,
+) -> Result<i64> {
+    if !matches!(format, "ndjson" | "jsonl") {
+        bail!("unsupported export format: {format}, expected one of: ndjson, jsonl");
+    }
+    let results: Vec<serde_json::Value> = search::search(
+        job_name,
+        query,
+        api_key,
+        vec!["*".to_string()],
+        num_results,
+        where_clause,
+        false,
+        false,
+        None,
+        search::ChunkRetrieval::Chunks,
+        None,
+    )?
+    .into_iter()
+    .map(|r| r.0)
+    .collect();
+    let num_exported = results.len() as i64;
+
+    if is_http_destination(destination) {
+        init_queue(VECTORIZE_EXPORT_QUEUE)?;
+        let message = ExportMessage {
+            job_name: job_name.to_string(),
+            destination: destination.to_string(),
+            results,
+        };
+        compat::run(
+            "SELECT pgmq.send($1, $2::jsonb)",
+            vec![
+                arg(VECTORIZE_EXPORT_QUEUE),
+                arg(pgrx::JsonB(serde_json::to_value(message)?)),
+            ],
+        )?;
+    } else {
+        write_server_file(destination, &results)?;
+    }
+    Ok(num_exported)
+}
+
+// files are written by the postgres server process, so the same privileges as COPY TO are required
+fn write_server_file(path: &str, results: &[serde_json::Value]) -> Result<()> {
+    if !std::path::Path::new(path).is_absolute() {
+        bail!("export destination must be an absolute path or an http(s) url: {path}");
+    }
+    let allowed: bool = compat::get_one(
+        "SELECT pg_has_role(current_user, 'pg_write_server_files', 'MEMBER')",
+        vec![],
+    )?
+    .unwrap_or(false);
+    if !allowed {
+        bail!("must be superuser or a member of pg_write_server_files to export to a file");
+    }
+    let mut file = std::fs::File::create(path)
+        .with_context(|| format!("failed to create export file: {path}"))?;
+    file.write_all(to_ndjson(results).as_bytes())
+        .with_context(|| format!("failed to write export file: {path}"))?;
+    Ok(())
+}
+
+/// delivers a single export from the export queue
+pub async fn run_export_worker(queue: PGMQueueExt) -> Result<Option<()>> {
+    let msg: Message<ExportMessage> = match queue
+        .read::<ExportMessage>(VECTORIZE_EXPORT_QUEUE, 180_i32)
+        .await
+    {
+        Ok(Some(msg)) => msg,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            warning!("pg-vectorize: Error reading export message: {e}");
+            return Err(anyhow!("failed to read export message"));
+        }
+    };
+
+    let msg_id = msg.msg_id;
+    let delivered = post_export(&msg.message).await;
+    let delete_it = match delivered {
+        Ok(_) => {
+            info!(
+                "pg-vectorize: exported {} results for job: {}",
+                msg.message.results.len(),
+                msg.message.job_name
+            );
+            true
+        }
+        Err(e) => {
+            warning!("pg-vectorize: export failed: {:?}", e);
+            msg.read_ct > 2
+        }
+    };
+    if delete_it {
+        queue.delete(VECTORIZE_EXPORT_QUEUE, msg_id).await?;
+    }
+    Ok(Some(()))
+}
+
+async fn post_export(export: &ExportMessage) -> Result<()> {
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(&export.destination)
+        .header("Content-Type", "application/x-ndjson")
+        .body(to_ndjson(&export.results))
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        bail!(
+            "callback {} responded with status: {}",
+            export.destination,
+            resp.status()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_ndjson() {
+        let results = vec![
+            serde_json::json!({"product_id": 1, "description": "line one\nline two"}),
+            serde_json::json!({"product_id": 2}),
+        ];
+        let ndjson = to_ndjson(&results);
+        let lines: Vec<&str> = ndjson.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first, results[0]);
+        assert!(ndjson.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_is_http_destination() {
+        assert!(is_http_destination("https://example.com/hook"));
+        assert!(is_http_destination("http://localhost:8080"));
+        assert!(!is_http_destination("/tmp/results.ndjson"));
+    }
+}
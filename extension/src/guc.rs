@@ -1,9 +1,17 @@
 use core::ffi::CStr;
 use pgrx::*;
 
-use anyhow::Result;
-use vectorize_core::types::ModelSource;
+use anyhow::{Context, Result};
+use std::time::Duration;
+use vectorize_core::transformers::http_handler::{Headers, Proxies, Timeouts};
+use vectorize_core::transformers::providers::bedrock::{runtime_url, AwsCredentials};
+use vectorize_core::transformers::providers::gemini::{
+    vertex_url, ServiceAccountKey, VERTEX_DEFAULT_LOCATION,
+};
+use vectorize_core::transformers::providers::{huggingface, openai_compatible};
+use vectorize_core::types::{Credential, Model, ModelSource};
 
+use crate::credential;
 use crate::transformers::generic::env_interpolate_string;
 
 pub static VECTORIZE_HOST: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
@@ -16,11 +24,17 @@ pub static OPENAI_BASE_URL: GucSetting<Option<&CStr>> =
 pub static OPENAI_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
 pub static BATCH_SIZE: GucSetting<i32> = GucSetting::<i32>::new(10000);
 pub static NUM_BGW_PROC: GucSetting<i32> = GucSetting::<i32>::new(1);
+pub static WARM_ON_STARTUP: GucSetting<bool> = GucSetting::<bool>::new(false);
+pub static EMBEDDING_CACHE: GucSetting<bool> = GucSetting::<bool>::new(false);
 pub static EMBEDDING_SERVICE_API_KEY: GucSetting<Option<&CStr>> =
     GucSetting::<Option<&CStr>>::new(None);
 pub static EMBEDDING_SERVICE_HOST: GucSetting<Option<&CStr>> =
     GucSetting::<Option<&CStr>>::new(None);
 pub static EMBEDDING_REQ_TIMEOUT_SEC: GucSetting<i32> = GucSetting::<i32>::new(120);
+pub static MAX_CONCURRENT_REQUESTS: GucSetting<i32> = GucSetting::<i32>::new(4);
+pub static MAX_ATTEMPTS: GucSetting<i32> = GucSetting::<i32>::new(3);
+pub static CHAT_REQ_TIMEOUT_SEC: GucSetting<i32> = GucSetting::<i32>::new(120);
+pub static CONNECT_TIMEOUT_SEC: GucSetting<i32> = GucSetting::<i32>::new(10);
 pub static OLLAMA_SERVICE_HOST: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
 pub static TEMBO_SERVICE_HOST: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
 pub static TEMBO_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
@@ -30,6 +44,60 @@ pub static PORTKEY_VIRTUAL_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<
 pub static PORTKEY_SERVICE_URL: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
 pub static VOYAGE_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
 pub static VOYAGE_SERVICE_URL: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static AZURE_OPENAI_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static AZURE_OPENAI_SERVICE_URL: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static BEDROCK_REGION: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static BEDROCK_SERVICE_URL: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static BEDROCK_ACCESS_KEY_ID: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static BEDROCK_SECRET_ACCESS_KEY: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static BEDROCK_SESSION_TOKEN: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static GEMINI_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static GEMINI_SERVICE_URL: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static VERTEX_SERVICE_ACCOUNT_KEY: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static VERTEX_PROJECT: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static VERTEX_LOCATION: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static MISTRAL_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static MISTRAL_SERVICE_URL: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static JINA_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static JINA_SERVICE_URL: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static ANTHROPIC_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static ANTHROPIC_SERVICE_URL: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static ANTHROPIC_MAX_TOKENS: GucSetting<i32> = GucSetting::<i32>::new(1024);
+pub static HUGGINGFACE_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static HUGGINGFACE_ENDPOINTS: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static OPENAI_COMPATIBLE_API_KEY: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static OPENAI_COMPATIBLE_ENDPOINTS: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static VLLM_SERVICE_URL: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&'static CStr>>::new(Some(unsafe {
+        CStr::from_bytes_with_nul_unchecked(b"http://localhost:8000/v1\0")
+    }));
+pub static VLLM_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static LLAMACPP_SERVICE_URL: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&'static CStr>>::new(Some(unsafe {
+        CStr::from_bytes_with_nul_unchecked(b"http://localhost:8080/v1\0")
+    }));
+pub static LLAMACPP_API_KEY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static EMBEDDING_BATCH_SIZES: GucSetting<Option<&CStr>> =
+    GucSetting::<Option<&CStr>>::new(None);
+pub static REQUESTS_PER_MINUTE: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static TOKENS_PER_MINUTE: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static HTTP_PROXY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static HTTPS_PROXY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static NO_PROXY: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static HTTP_HEADERS: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static WARMUP_MODELS: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
+pub static CHAT_HISTORY_TURNS: GucSetting<i32> = GucSetting::<i32>::new(5);
+#[cfg(feature = "onnx")]
+pub static ONNX_MODEL_DIR: GucSetting<Option<&CStr>> = GucSetting::<Option<&CStr>>::new(None);
 
 // initialize GUCs
 pub fn init_guc() {
@@ -113,6 +181,24 @@ pub fn init_guc() {
         GucFlags::default(),
     );
 
+    GucRegistry::define_bool_guc(
+        "vectorize.embedding_cache",
+        "Reuse embeddings of the same text and model",
+        "Keep the embeddings the background worker receives in vectorize.embedding_cache, and give texts that a model embedded before, for any job, those embeddings instead of sending them to the provider again. Default is off.",
+        &EMBEDDING_CACHE,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        "vectorize.warm_on_startup",
+        "Warm the indexes of all jobs on startup",
+        "Load the index and embeddings of every job into memory when the background worker starts. Default is off.",
+        &WARM_ON_STARTUP,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
     GucRegistry::define_int_guc(
         "vectorize.embedding_req_timeout_sec",
         "Timeout, in seconds, for embedding transform requests",
@@ -124,6 +210,50 @@ pub fn init_guc() {
         GucFlags::default(),
     );
 
+    GucRegistry::define_int_guc(
+        "vectorize.chat_req_timeout_sec",
+        "Timeout, in seconds, for chat completion requests",
+        "Number of seconds to wait for a chat completion http request to complete, or between the chunks of a streamed response. Default is 120 seconds.",
+        &CHAT_REQ_TIMEOUT_SEC,
+        1,
+        3600,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "vectorize.connect_timeout_sec",
+        "Timeout, in seconds, for connecting to providers",
+        "Number of seconds to wait for a connection to an embedding or chat provider. Default is 10 seconds.",
+        &CONNECT_TIMEOUT_SEC,
+        1,
+        300,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "vectorize.max_concurrent_requests",
+        "Embedding requests sent at once",
+        "Number of embedding requests of a batch that a background worker sends at once. Default is 4.",
+        &MAX_CONCURRENT_REQUESTS,
+        1,
+        64,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "vectorize.max_attempts",
+        "Attempts at each provider request",
+        "Number of times an embedding or chat request is sent when it is rate limited, times out or fails with a server error. Jobs can set their own max_attempts. Default is 3.",
+        &MAX_ATTEMPTS,
+        1,
+        10,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
     GucRegistry::define_string_guc(
         "vectorize.tembo_service_url",
         "Url for an Tembo AI service",
@@ -195,6 +325,372 @@ pub fn init_guc() {
         GucContext::Suset,
         GucFlags::default(),
     );
+
+    GucRegistry::define_string_guc(
+        "vectorize.azure_openai_service_url",
+        "Endpoint of the Azure OpenAI resource",
+        "Endpoint of the Azure OpenAI resource, e.g. https://{resource}.openai.azure.com. An api-version can be given in its query string.",
+        &AZURE_OPENAI_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.azure_openai_key",
+        "API key for the Azure OpenAI resource",
+        "API key for the Azure OpenAI resource, sent in the api-key header.",
+        &AZURE_OPENAI_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.bedrock_region",
+        "AWS region of the Bedrock runtime",
+        "AWS region of the Bedrock runtime, e.g. us-east-1. Requests are sent to https://bedrock-runtime.{region}.amazonaws.com unless vectorize.bedrock_service_url is set.",
+        &BEDROCK_REGION,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.bedrock_service_url",
+        "Endpoint of the Bedrock runtime",
+        "Endpoint of the Bedrock runtime, such as a VPC endpoint. Requests are signed for the region in its bedrock-runtime.{region} host.",
+        &BEDROCK_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.bedrock_access_key_id",
+        "AWS access key id for Bedrock",
+        "AWS access key id that requests to Bedrock are signed with.",
+        &BEDROCK_ACCESS_KEY_ID,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.bedrock_secret_access_key",
+        "AWS secret access key for Bedrock",
+        "AWS secret access key that requests to Bedrock are signed with.",
+        &BEDROCK_SECRET_ACCESS_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.bedrock_session_token",
+        "AWS session token for Bedrock",
+        "AWS session token of temporary credentials, sent with requests to Bedrock.",
+        &BEDROCK_SESSION_TOKEN,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.gemini_api_key",
+        "API key for the Gemini API",
+        "API key for the Gemini API, sent in the x-goog-api-key header.",
+        &GEMINI_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.gemini_service_url",
+        "Base url of the Gemini API or Vertex AI",
+        "Base url of the Gemini API, or of Vertex AI when a service account key is set. Defaults to https://generativelanguage.googleapis.com/v1beta, or to Vertex AI in vectorize.vertex_project and vectorize.vertex_location.",
+        &GEMINI_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.vertex_service_account_key",
+        "Google service account key for Vertex AI",
+        "JSON key of the Google service account that requests to Vertex AI are authorized with. Takes precedence over vectorize.gemini_api_key.",
+        &VERTEX_SERVICE_ACCOUNT_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.vertex_project",
+        "Google Cloud project of Vertex AI",
+        "Google Cloud project that Vertex AI is called in. Defaults to the project of the service account.",
+        &VERTEX_PROJECT,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.vertex_location",
+        "Google Cloud location of Vertex AI",
+        "Google Cloud location that Vertex AI is called in, e.g. us-central1.",
+        &VERTEX_LOCATION,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.mistral_api_key",
+        "API key for the Mistral API",
+        "API key for the Mistral platform, sent as a bearer token.",
+        &MISTRAL_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.mistral_service_url",
+        "Base url of the Mistral API",
+        "Base url of the Mistral API. Defaults to https://api.mistral.ai/v1.",
+        &MISTRAL_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.jina_api_key",
+        "API key for the Jina AI platform",
+        "API key for the Jina AI platform, sent as a bearer token.",
+        &JINA_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.jina_service_url",
+        "Base url of the Jina AI platform",
+        "Base url of the Jina AI platform. Defaults to https://api.jina.ai/v1.",
+        &JINA_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.anthropic_api_key",
+        "API key for the Anthropic API",
+        "API key for the Anthropic API, sent in the x-api-key header.",
+        &ANTHROPIC_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.anthropic_service_url",
+        "Base url of the Anthropic API",
+        "Base url of the Anthropic API. Defaults to https://api.anthropic.com/v1.",
+        &ANTHROPIC_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "vectorize.anthropic_max_tokens",
+        "Maximum number of tokens in a response from an Anthropic model",
+        "Maximum number of tokens that an Anthropic model generates in a response, which the Messages API requires. Default is 1024.",
+        &ANTHROPIC_MAX_TOKENS,
+        1,
+        128000,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.huggingface_api_key",
+        "Access token for Hugging Face",
+        "Hugging Face access token, sent as a bearer token to the Inference API and to Inference Endpoints.",
+        &HUGGINGFACE_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.huggingface_endpoints",
+        "Hugging Face Inference Endpoints of models",
+        "JSON object of model names to the urls of the dedicated Inference Endpoints that serve them, e.g. {\"huggingface/acme/embedder\": \"https://xyz.us-east-1.aws.endpoints.huggingface.cloud\"}. Other models are served by the serverless Inference API.",
+        &HUGGINGFACE_ENDPOINTS,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.openai_compatible_api_key",
+        "API key for OpenAI-compatible endpoints",
+        "API key sent to the OpenAI-compatible endpoints that do not set their own api_key.",
+        &OPENAI_COMPATIBLE_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.openai_compatible_endpoints",
+        "OpenAI-compatible endpoints of models",
+        "JSON object of model names to the base urls of the OpenAI-compatible APIs that serve them, or to objects with a base_url and optional embeddings_path, chat_path, auth_header, auth_scheme, model and api_key, e.g. {\"openai-compatible/bge-m3\": \"http://localhost:8000/v1\"}.",
+        &OPENAI_COMPATIBLE_ENDPOINTS,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.vllm_service_url",
+        "Base url of a vLLM server",
+        "Base url of the OpenAI-compatible API of a vLLM server. Default is http://localhost:8000/v1.",
+        &VLLM_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.vllm_api_key",
+        "API key for a vLLM server",
+        "API key of a vLLM server started with --api-key. Optional.",
+        &VLLM_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.llamacpp_service_url",
+        "Base url of a llama.cpp server",
+        "Base url of the OpenAI-compatible API of a llama.cpp server. Default is http://localhost:8080/v1.",
+        &LLAMACPP_SERVICE_URL,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.llamacpp_api_key",
+        "API key for a llama.cpp server",
+        "API key of a llama.cpp server started with --api-key. Optional.",
+        &LLAMACPP_API_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.embedding_batch_sizes",
+        "Texts per embedding request of each provider",
+        "JSON object of model sources to the most texts sent in one embedding request, e.g. {\"openai\": 256, \"ollama\": 8}. Providers that are not in it keep their default.",
+        &EMBEDDING_BATCH_SIZES,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.requests_per_minute",
+        "Embedding requests per minute of each provider",
+        "JSON object of model sources to the most embedding requests the worker sends them per minute, e.g. {\"openai\": 3000}. Providers that are not in it are not limited.",
+        &REQUESTS_PER_MINUTE,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.tokens_per_minute",
+        "Embedded tokens per minute of each provider",
+        "JSON object of model sources to the most tokens the worker sends them to embed per minute, e.g. {\"openai\": 1000000}. Providers that are not in it are not limited.",
+        &TOKENS_PER_MINUTE,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.http_proxy",
+        "Proxy for http:// calls to providers",
+        "URL of the proxy that calls to http:// provider urls are sent through, e.g. http://proxy.corp:3128. When neither this nor vectorize.https_proxy is set, the HTTP_PROXY, HTTPS_PROXY and NO_PROXY environment variables of the server are followed.",
+        &HTTP_PROXY,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.https_proxy",
+        "Proxy for https:// calls to providers",
+        "URL of the proxy that calls to https:// provider urls are sent through, e.g. http://proxy.corp:3128.",
+        &HTTPS_PROXY,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.no_proxy",
+        "Hosts called without the proxies",
+        "Comma separated hosts, domains and IP networks that are called directly rather than through vectorize.http_proxy or vectorize.https_proxy, e.g. localhost,.internal,10.0.0.0/8.",
+        &NO_PROXY,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.http_headers",
+        "Extra headers of calls to providers",
+        "Json object of the headers sent with each call to a provider or model, keyed by provider or model, e.g. {\"openai\": {\"OpenAI-Organization\": \"org-1\"}}. A model's headers take the place of those of its provider with the same name.",
+        &HTTP_HEADERS,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        "vectorize.warmup_models",
+        "Models to warm up on startup",
+        "Comma separated models that each background worker sends an embedding request to when it starts, e.g. ollama/nomic-embed-text, so that locally served models are loaded before the first messages of their jobs.",
+        &WARMUP_MODELS,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "vectorize.chat_history_turns",
+        "Number of prior turns of a session included in a rag prompt",
+        "Number of the most recent questions and answers of a session that vectorize.rag() includes in the prompt when it is given a session_id. Default is 5, 0 stores the turns without including them.",
+        &CHAT_HISTORY_TURNS,
+        0,
+        100,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    #[cfg(feature = "onnx")]
+    GucRegistry::define_string_guc(
+        "vectorize.onnx_model_dir",
+        "Directory of ONNX models",
+        "Directory of sentence-transformer models exported to ONNX, which are run in-process. A model is read from model.onnx and tokenizer.json in the directory of its name, e.g. sentence-transformers/all-MiniLM-L6-v2.",
+        &ONNX_MODEL_DIR,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+}
+
+/// the timeouts of provider calls, from vectorize.connect_timeout_sec,
+/// vectorize.embedding_req_timeout_sec and vectorize.chat_req_timeout_sec
+pub fn timeouts() -> Timeouts {
+    let secs = |s: i32| Duration::from_secs(s as u64);
+    Timeouts {
+        connect: secs(CONNECT_TIMEOUT_SEC.get()),
+        embedding: secs(EMBEDDING_REQ_TIMEOUT_SEC.get()),
+        chat: secs(CHAT_REQ_TIMEOUT_SEC.get()),
+    }
+}
+
+/// the proxies of provider calls, from vectorize.http_proxy, vectorize.https_proxy and vectorize.no_proxy
+pub fn proxies() -> Result<Proxies> {
+    Ok(Proxies::new(
+        get_guc(VectorizeGuc::HttpProxy),
+        get_guc(VectorizeGuc::HttpsProxy),
+        get_guc(VectorizeGuc::NoProxy),
+    )?)
+}
+
+/// the extra headers of calls to a model, from vectorize.http_headers and the model's registration
+pub fn headers(model: &Model) -> Result<Headers> {
+    Ok(Headers::of(
+        get_guc(VectorizeGuc::HttpHeaders).as_deref(),
+        model,
+    )?)
 }
 
 // for handling of GUCs that can be error prone
@@ -215,6 +711,42 @@ pub enum VectorizeGuc {
     PortkeyServiceUrl,
     VoyageApiKey,
     VoyageServiceUrl,
+    AzureOpenAIKey,
+    AzureOpenAIServiceUrl,
+    BedrockRegion,
+    BedrockServiceUrl,
+    BedrockAccessKeyId,
+    BedrockSecretAccessKey,
+    BedrockSessionToken,
+    GeminiApiKey,
+    GeminiServiceUrl,
+    VertexServiceAccountKey,
+    VertexProject,
+    VertexLocation,
+    MistralApiKey,
+    MistralServiceUrl,
+    JinaApiKey,
+    JinaServiceUrl,
+    AnthropicApiKey,
+    AnthropicServiceUrl,
+    HuggingFaceApiKey,
+    HuggingFaceEndpoints,
+    OpenAICompatibleApiKey,
+    OpenAICompatibleEndpoints,
+    VllmServiceUrl,
+    VllmApiKey,
+    LlamaCppServiceUrl,
+    LlamaCppApiKey,
+    EmbeddingBatchSizes,
+    RequestsPerMinute,
+    TokensPerMinute,
+    HttpProxy,
+    HttpsProxy,
+    NoProxy,
+    HttpHeaders,
+    WarmupModels,
+    #[cfg(feature = "onnx")]
+    OnnxModelDir,
 }
 
 /// a convenience function to get this project's GUCs
@@ -235,6 +767,42 @@ pub fn get_guc(guc: VectorizeGuc) -> Option<String> {
         VectorizeGuc::PortkeyServiceUrl => PORTKEY_SERVICE_URL.get(),
         VectorizeGuc::VoyageApiKey => VOYAGE_API_KEY.get(),
         VectorizeGuc::VoyageServiceUrl => VOYAGE_SERVICE_URL.get(),
+        VectorizeGuc::AzureOpenAIKey => AZURE_OPENAI_API_KEY.get(),
+        VectorizeGuc::AzureOpenAIServiceUrl => AZURE_OPENAI_SERVICE_URL.get(),
+        VectorizeGuc::BedrockRegion => BEDROCK_REGION.get(),
+        VectorizeGuc::BedrockServiceUrl => BEDROCK_SERVICE_URL.get(),
+        VectorizeGuc::BedrockAccessKeyId => BEDROCK_ACCESS_KEY_ID.get(),
+        VectorizeGuc::BedrockSecretAccessKey => BEDROCK_SECRET_ACCESS_KEY.get(),
+        VectorizeGuc::BedrockSessionToken => BEDROCK_SESSION_TOKEN.get(),
+        VectorizeGuc::GeminiApiKey => GEMINI_API_KEY.get(),
+        VectorizeGuc::GeminiServiceUrl => GEMINI_SERVICE_URL.get(),
+        VectorizeGuc::VertexServiceAccountKey => VERTEX_SERVICE_ACCOUNT_KEY.get(),
+        VectorizeGuc::VertexProject => VERTEX_PROJECT.get(),
+        VectorizeGuc::VertexLocation => VERTEX_LOCATION.get(),
+        VectorizeGuc::MistralApiKey => MISTRAL_API_KEY.get(),
+        VectorizeGuc::MistralServiceUrl => MISTRAL_SERVICE_URL.get(),
+        VectorizeGuc::JinaApiKey => JINA_API_KEY.get(),
+        VectorizeGuc::JinaServiceUrl => JINA_SERVICE_URL.get(),
+        VectorizeGuc::AnthropicApiKey => ANTHROPIC_API_KEY.get(),
+        VectorizeGuc::AnthropicServiceUrl => ANTHROPIC_SERVICE_URL.get(),
+        VectorizeGuc::HuggingFaceApiKey => HUGGINGFACE_API_KEY.get(),
+        VectorizeGuc::HuggingFaceEndpoints => HUGGINGFACE_ENDPOINTS.get(),
+        VectorizeGuc::OpenAICompatibleApiKey => OPENAI_COMPATIBLE_API_KEY.get(),
+        VectorizeGuc::OpenAICompatibleEndpoints => OPENAI_COMPATIBLE_ENDPOINTS.get(),
+        VectorizeGuc::VllmServiceUrl => VLLM_SERVICE_URL.get(),
+        VectorizeGuc::VllmApiKey => VLLM_API_KEY.get(),
+        VectorizeGuc::LlamaCppServiceUrl => LLAMACPP_SERVICE_URL.get(),
+        VectorizeGuc::LlamaCppApiKey => LLAMACPP_API_KEY.get(),
+        VectorizeGuc::EmbeddingBatchSizes => EMBEDDING_BATCH_SIZES.get(),
+        VectorizeGuc::RequestsPerMinute => REQUESTS_PER_MINUTE.get(),
+        VectorizeGuc::TokensPerMinute => TOKENS_PER_MINUTE.get(),
+        VectorizeGuc::HttpProxy => HTTP_PROXY.get(),
+        VectorizeGuc::HttpsProxy => HTTPS_PROXY.get(),
+        VectorizeGuc::NoProxy => NO_PROXY.get(),
+        VectorizeGuc::HttpHeaders => HTTP_HEADERS.get(),
+        VectorizeGuc::WarmupModels => WARMUP_MODELS.get(),
+        #[cfg(feature = "onnx")]
+        VectorizeGuc::OnnxModelDir => ONNX_MODEL_DIR.get(),
     };
     if let Some(cstr) = val {
         if let Ok(s) = handle_cstr(cstr) {
@@ -265,6 +833,38 @@ pub struct ModelGucConfig {
     pub virtual_key: Option<String>,
 }
 
+impl ModelGucConfig {
+    /// the configs with the keys of a credential in place of the GUCs, when it is for the model's provider
+    pub fn with_credential(mut self, model: &Model, credential: Credential) -> Self {
+        if credential.source == model.source {
+            self.api_key = credential.api_key.or(self.api_key);
+            self.virtual_key = credential.virtual_key.or(self.virtual_key);
+        }
+        self
+    }
+}
+
+/// the GUC a provider's API key is set in, for providers whose key is a single GUC
+pub fn api_key_guc(model_source: &ModelSource) -> Option<&'static str> {
+    match model_source {
+        ModelSource::OpenAI => Some("vectorize.openai_key"),
+        ModelSource::SentenceTransformers => Some("vectorize.embedding_service_api_key"),
+        ModelSource::Cohere => Some("vectorize.cohere_api_key"),
+        ModelSource::Portkey => Some("vectorize.portkey_api_key"),
+        ModelSource::Voyage => Some("vectorize.voyage_api_key"),
+        ModelSource::AzureOpenAI => Some("vectorize.azure_openai_key"),
+        ModelSource::Mistral => Some("vectorize.mistral_api_key"),
+        ModelSource::Jina => Some("vectorize.jina_api_key"),
+        ModelSource::Anthropic => Some("vectorize.anthropic_api_key"),
+        ModelSource::HuggingFace => Some("vectorize.huggingface_api_key"),
+        ModelSource::OpenAICompatible => Some("vectorize.openai_compatible_api_key"),
+        ModelSource::Vllm => Some("vectorize.vllm_api_key"),
+        ModelSource::LlamaCpp => Some("vectorize.llamacpp_api_key"),
+        // Gemini's key is in one of two GUCs, and Bedrock's in several
+        _ => None,
+    }
+}
+
 pub fn get_guc_configs(model_source: &ModelSource) -> ModelGucConfig {
     match model_source {
         ModelSource::OpenAI => ModelGucConfig {
@@ -302,5 +902,127 @@ pub fn get_guc_configs(model_source: &ModelSource) -> ModelGucConfig {
             service_url: get_guc(VectorizeGuc::VoyageServiceUrl),
             virtual_key: None,
         },
+        ModelSource::AzureOpenAI => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::AzureOpenAIKey),
+            service_url: get_guc(VectorizeGuc::AzureOpenAIServiceUrl),
+            virtual_key: None,
+        },
+        // the provider's api key carries the credentials that its requests are signed with
+        ModelSource::Bedrock => ModelGucConfig {
+            api_key: match (
+                get_guc(VectorizeGuc::BedrockAccessKeyId),
+                get_guc(VectorizeGuc::BedrockSecretAccessKey),
+            ) {
+                (Some(access_key_id), Some(secret_access_key)) => Some(
+                    AwsCredentials {
+                        access_key_id,
+                        secret_access_key,
+                        session_token: get_guc(VectorizeGuc::BedrockSessionToken),
+                    }
+                    .to_key(),
+                ),
+                _ => None,
+            },
+            service_url: get_guc(VectorizeGuc::BedrockServiceUrl)
+                .or_else(|| get_guc(VectorizeGuc::BedrockRegion).map(|r| runtime_url(&r))),
+            virtual_key: None,
+        },
+        // a service account key calls Vertex AI, and an API key calls the Gemini API
+        ModelSource::Gemini => match get_guc(VectorizeGuc::VertexServiceAccountKey) {
+            Some(key) => ModelGucConfig {
+                service_url: get_guc(VectorizeGuc::GeminiServiceUrl).or_else(|| {
+                    let project = get_guc(VectorizeGuc::VertexProject).or_else(|| {
+                        ServiceAccountKey::from_json(&key)
+                            .ok()
+                            .map(|sa| sa.project_id)
+                    })?;
+                    let location = get_guc(VectorizeGuc::VertexLocation)
+                        .unwrap_or_else(|| VERTEX_DEFAULT_LOCATION.to_string());
+                    Some(vertex_url(&project, &location))
+                }),
+                api_key: Some(key),
+                virtual_key: None,
+            },
+            None => ModelGucConfig {
+                api_key: get_guc(VectorizeGuc::GeminiApiKey),
+                service_url: get_guc(VectorizeGuc::GeminiServiceUrl),
+                virtual_key: None,
+            },
+        },
+        ModelSource::Mistral => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::MistralApiKey),
+            service_url: get_guc(VectorizeGuc::MistralServiceUrl),
+            virtual_key: None,
+        },
+        ModelSource::Jina => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::JinaApiKey),
+            service_url: get_guc(VectorizeGuc::JinaServiceUrl),
+            virtual_key: None,
+        },
+        ModelSource::Anthropic => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::AnthropicApiKey),
+            service_url: get_guc(VectorizeGuc::AnthropicServiceUrl),
+            virtual_key: None,
+        },
+        // the endpoint depends on the model, see get_model_guc_configs
+        ModelSource::HuggingFace => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::HuggingFaceApiKey),
+            service_url: None,
+            virtual_key: None,
+        },
+        // the endpoint depends on the model, see get_model_guc_configs
+        ModelSource::OpenAICompatible => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::OpenAICompatibleApiKey),
+            service_url: None,
+            virtual_key: None,
+        },
+        ModelSource::Vllm => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::VllmApiKey),
+            service_url: get_guc(VectorizeGuc::VllmServiceUrl),
+            virtual_key: None,
+        },
+        ModelSource::LlamaCpp => ModelGucConfig {
+            api_key: get_guc(VectorizeGuc::LlamaCppApiKey),
+            service_url: get_guc(VectorizeGuc::LlamaCppServiceUrl),
+            virtual_key: None,
+        },
+    }
+}
+
+/// the configs of a model for a job, from the job's credential in vectorize.credentials when it has one,
+/// falling back to the GUCs of the model's source
+pub fn get_job_guc_configs(model: &Model, credential: Option<&str>) -> Result<ModelGucConfig> {
+    let configs = get_model_guc_configs(model)?;
+    match credential {
+        Some(name) => Ok(configs.with_credential(model, credential::get(name)?)),
+        None => Ok(configs),
+    }
+}
+
+/// the configs of a model's source, along with the endpoint of a model served on its own
+pub fn get_model_guc_configs(model: &Model) -> Result<ModelGucConfig> {
+    let mut configs = get_guc_configs(&model.source);
+    // a registered model's own endpoint takes the place of its source's
+    if let Some(endpoint) = model.registered_endpoint() {
+        configs.service_url = Some(endpoint);
+        return Ok(configs);
+    }
+    if model.source == ModelSource::HuggingFace {
+        if let Some(endpoints) = get_guc(VectorizeGuc::HuggingFaceEndpoints) {
+            configs.service_url = huggingface::endpoint_url(&endpoints, &model.fullname)?;
+        }
+    }
+    if model.source == ModelSource::OpenAICompatible {
+        let mut endpoint = match get_guc(VectorizeGuc::OpenAICompatibleEndpoints) {
+            Some(endpoints) => openai_compatible::endpoint(&endpoints, &model.fullname)?,
+            None => None,
+        }
+        .with_context(|| format!("{model} is not in vectorize.openai_compatible_endpoints"))?;
+        // an endpoint's own key takes the place of the shared one, and is saved with jobs like it
+        if let Some(key) = endpoint.api_key.take() {
+            configs.api_key = Some(key);
+        }
+        configs.service_url = Some(serde_json::to_string(&endpoint)?);
     }
+    Ok(configs)
 }
@@ -0,0 +1,89 @@
+use crate::api_key::model_to_validate;
+use crate::compat::{self, arg};
+use crate::guc;
+use crate::registry::{parse_source, resolve};
+use crate::transformers::get_provider;
+
+use anyhow::Result;
+use pgrx::prelude::*;
+use std::time::Instant;
+use vectorize_core::transformers::http_handler::{with_headers, with_proxies, with_timeouts};
+use vectorize_core::transformers::providers::prepare_generic_embedding_request;
+use vectorize_core::transformers::types::Inputs;
+
+/// the outcome of a one word embedding request to a provider
+pub struct ProviderHealth {
+    pub provider: String,
+    pub model: String,
+    pub reachable: bool,
+    pub latency_ms: f64,
+    // None when the provider did not return an embedding
+    pub dimensions: Option<i32>,
+    pub error: Option<String>,
+}
+
+/// sends a one word embedding request to a provider, with a small model of it, or to a model
+/// and records the outcome in vectorize.provider_health, where the workers hold back the jobs of providers found down
+pub fn check_provider(provider_or_model: &str) -> Result<ProviderHealth> {
+    let model = match parse_source(provider_or_model) {
+        Ok(source) => model_to_validate(&source, None)?,
+        Err(_) => resolve(provider_or_model)?,
+    };
+    let guc_configs = guc::get_job_guc_configs(&model, None)?;
+    let provider = get_provider(
+        &model,
+        guc_configs.api_key,
+        guc_configs.service_url,
+        guc_configs.virtual_key,
+        Some(1),
+    )?;
+    let input = Inputs {
+        record_id: "".to_string(),
+        inputs: "ping".to_string(),
+        token_estimate: 1,
+    };
+    let request = prepare_generic_embedding_request(&model, &[input]);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
+    let proxies = guc::proxies()?;
+    let headers = guc::headers(&model)?;
+    let started = Instant::now();
+    let response = runtime.block_on(with_headers(
+        headers,
+        with_proxies(
+            proxies,
+            with_timeouts(guc::timeouts(), provider.generate_embedding(&request)),
+        ),
+    ));
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let (dimensions, error) = match response {
+        Ok(response) => (response.embeddings.first().map(|e| e.len() as i32), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let health = ProviderHealth {
+        provider: model.source.to_string(),
+        model: model.to_string(),
+        reachable: error.is_none(),
+        latency_ms,
+        dimensions,
+        error,
+    };
+    compat::run(
+        "INSERT INTO vectorize.provider_health (provider, reachable, latency_ms, dimensions, error)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (provider)
+        DO UPDATE SET reachable = EXCLUDED.reachable, latency_ms = EXCLUDED.latency_ms,
+            dimensions = EXCLUDED.dimensions, error = EXCLUDED.error, checked_at = now()",
+        vec![
+            arg(health.provider.as_str()),
+            arg(health.reachable),
+            arg(health.latency_ms),
+            arg(health.dimensions),
+            arg(health.error.as_deref()),
+        ],
+    )?;
+    Ok(health)
+}
@@ -1,28 +1,60 @@
+use crate::chunking::{drop_rechunk_trigger_queries, renamed_job_params};
+use crate::compat::{self, arg};
+use crate::model_migration::migration_cron_name;
+use crate::reindex::reindex_cron_names;
+use crate::ttl::ttl_cron_name;
 use crate::{query::check_input, types};
 use pgrx::prelude::*;
+use std::collections::BTreeMap;
 
-use anyhow::{anyhow, Context, Result};
-use vectorize_core::types::IndexDist;
-use vectorize_core::types::{JobParams, TableMethod, VECTORIZE_SCHEMA};
+use anyhow::{anyhow, bail, Context, Result};
+use vectorize_core::transformers::providers::embeds_content;
+use vectorize_core::types::{
+    ivfflat_lists, ColumnDecryption, ContentType, DistanceMetric, IndexDist, IndexParams, Model,
+};
+use vectorize_core::types::{
+    ChunkProvenance, JobParams, PrimaryKey, SourceKind, StorageParams, TableMethod, VectorStorage,
+};
 
 pub static VECTORIZE_QUEUE: &str = "vectorize_jobs";
 
+pub static VECTORIZE_EXPORT_QUEUE: &str = "vectorize_exports";
+
+// postgres truncates identifiers to 63 bytes
+// the longest name derived from a job's name is its realtime trigger, vectorize_insert_trigger_<job_name>
+// so longer job names could make two jobs' triggers, tables or columns collide
+const MAX_JOB_NAME_LENGTH: usize = 63 - "vectorize_insert_trigger_".len();
+
+/// errors if a job name can not be used to name the job's objects
+pub fn validate_job_name(job_name: &str) -> Result<()> {
+    check_input(job_name)?;
+    if job_name.len() > MAX_JOB_NAME_LENGTH {
+        bail!("job name must be at most {MAX_JOB_NAME_LENGTH} characters: {job_name}");
+    }
+    Ok(())
+}
+
 pub fn init_pgmq() -> Result<()> {
+    init_queue(VECTORIZE_QUEUE)
+}
+
+pub fn init_queue(queue_name: &str) -> Result<()> {
     // check if queue already created:
-    let queue_exists: bool = Spi::get_one(&format!(
-        "SELECT EXISTS (SELECT 1 FROM pgmq.meta WHERE queue_name = '{VECTORIZE_QUEUE}');",
-    ))?
+    let queue_exists: bool = compat::get_one(
+        "SELECT EXISTS (SELECT 1 FROM pgmq.meta WHERE queue_name = $1);",
+        vec![arg(queue_name)],
+    )?
     .context("error checking if queue exists")?;
     if queue_exists {
         debug1!("queue already exists");
         return Ok(());
     } else {
         debug1!("creating queue;");
-        let ran: Result<_, spi::Error> = Spi::connect(|mut c| {
-            let _r = c.update(
-                &format!("SELECT pgmq.create('{VECTORIZE_QUEUE}');"),
-                None,
-                None,
+        let ran: Result<_, spi::Error> = compat::connect(|mut c| {
+            let _r = compat::update(
+                &mut c,
+                &format!("SELECT pgmq.create('{queue_name}');"),
+                vec![],
             )?;
             Ok(())
         });
@@ -33,17 +65,23 @@ pub fn init_pgmq() -> Result<()> {
     Ok(())
 }
 
+/// the command pg_cron runs for a job with a cron-like schedule
+pub fn job_execute_command(job_name: &str) -> String {
+    format!("select vectorize.job_execute('{job_name}')")
+}
+
 pub fn init_cron(cron: &str, job_name: &str) -> Result<Option<i64>, spi::Error> {
     let cronjob = format!(
         "
         SELECT cron.schedule(
             '{job_name}',
             '{cron}',
-            $$select vectorize.job_execute('{job_name}')$$
+            $${command}$$
         )
-        ;"
+        ;",
+        command = job_execute_command(job_name),
     );
-    Spi::get_one(&cronjob)
+    compat::get_one(&cronjob, vec![])
 }
 
 pub fn init_job_query() -> String {
@@ -72,145 +110,513 @@ fn create_project_view(job_name: &str, job_params: &JobParams) -> String {
         "CREATE VIEW vectorize.{job_name}_view as 
         SELECT t0.*, t1.embeddings, t1.updated_at as embeddings_updated_at
         FROM {schema}.{table} t0
-        INNER JOIN vectorize._embeddings_{job_name} t1
-            ON t0.{primary_key} = t1.{primary_key};
+        INNER JOIN {embeddings_schema}._embeddings_{job_name} t1
+            ON {join_on};
         ",
         job_name = job_name,
+        embeddings_schema = job_params.embeddings_schema(),
         schema = job_params.schema,
         table = job_params.table,
-        primary_key = job_params.primary_key,
+        join_on = job_params.pkey().join_on("t0", "t1"),
     )
 }
 
+/// statements creating a job's embeddings, and its index
+/// embeddings_partitioning is the source table's partitioning, when the embeddings table is partitioned the same way
 pub fn init_embedding_table_query(
     job_name: &str,
     job_params: &JobParams,
     index_type: &IndexDist,
     model_dim: u32,
+    embeddings_partitioning: Option<&Partitioning>,
 ) -> Vec<String> {
     check_input(job_name).expect("invalid job name");
     let src_schema = job_params.schema.clone();
     let src_table = job_params.table.clone();
 
-    let col_type = format!("vector({model_dim})");
-
-    let (index_schema, table_name, embeddings_col) = match job_params.table_method {
-        TableMethod::append => {
-            let embeddings_col = format!("{job_name}_embeddings");
-            (src_schema.clone(), src_table.clone(), embeddings_col)
-        }
-        TableMethod::join => {
-            let table_name = format!("_embeddings_{}", job_name);
+    let col_type = job_params.vector_storage.column_type(model_dim);
 
-            (
-                VECTORIZE_SCHEMA.to_string(),
-                table_name.to_string(),
-                "embeddings".to_string(),
-            )
-        }
-    };
+    let index_stmt = create_index_query(
+        job_name,
+        job_params,
+        index_type,
+        &index_name(job_name, index_type),
+        false,
+    );
 
-    let index_stmt = match index_type {
-        IndexDist::pgv_hnsw_cosine => {
-            create_hnsw_cosine_index(job_name, &index_schema, &table_name, &embeddings_col)
-        }
-        IndexDist::vsc_diskann_cosine => {
-            create_diskann_index(job_name, &index_schema, &table_name, &embeddings_col)
-        }
-        IndexDist::pgv_hnsw_ip => {
-            create_hnsw_ip_index(job_name, &index_schema, &table_name, &embeddings_col)
-        }
-        IndexDist::pgv_hnsw_l2 => {
-            create_hnsw_l2_index(job_name, &index_schema, &table_name, &embeddings_col)
-        }
-    };
+    let (schema, table, embeddings_col) = embeddings_location(job_name, job_params);
+    let column_storage =
+        embeddings_column_storage(&schema, &table, &embeddings_col, &job_params.storage_params);
 
     match job_params.table_method {
         TableMethod::append => {
-            vec![
-                append_embedding_column(job_name, &src_schema, &src_table, &col_type),
-                index_stmt,
-            ]
+            let mut queries = vec![append_embedding_column(
+                job_name,
+                &src_schema,
+                &src_table,
+                &col_type,
+            )];
+            queries.extend(column_storage);
+            queries.push(index_stmt);
+            queries
         }
         TableMethod::join => {
-            vec![
+            let embeddings_schema = job_params.embeddings_schema();
+            let mut queries = vec![
+                format!("CREATE SCHEMA IF NOT EXISTS {embeddings_schema};"),
                 create_embedding_table(
                     job_name,
-                    &job_params.primary_key,
-                    &job_params.pkey_type,
+                    embeddings_schema,
+                    &job_params.pkey(),
                     &col_type,
-                    &src_schema,
-                    &src_table,
+                    &format!(
+                        "{}{}",
+                        job_params.storage_params.with_clause(),
+                        job_params.storage_params.tablespace_clause()
+                    ),
+                    // the embeddings of rows deleted from a view or foreign table are removed by the job instead
+                    job_params
+                        .source_kind
+                        .is_table()
+                        .then_some((src_schema.as_str(), src_table.as_str())),
+                    embeddings_partitioning.map(|p| p.key_def.as_str()),
                 ),
+            ];
+            if let Some(partitioning) = embeddings_partitioning {
+                queries.extend(create_embedding_partitions(
+                    job_name,
+                    embeddings_schema,
+                    partitioning,
+                ));
+            }
+            queries.extend(column_storage);
+            queries.extend([
                 index_stmt,
                 // also create a view over the source table and the embedding table, for this project
                 drop_project_view(job_name),
                 create_project_view(job_name, job_params),
-            ]
+            ]);
+            queries
         }
     }
 }
 
+/// statements that remove everything a job created
+/// the job's row in vectorize.job is removed separately
+pub fn drop_job_queries(job_name: &str, job_params: &JobParams) -> Vec<String> {
+    check_input(job_name).expect("invalid job name");
+    let schema = &job_params.schema;
+    let table = &job_params.table;
+    // triggers and their handler, in case the job was ever realtime
+    let mut queries = drop_trigger_queries(job_name, schema, table);
+    queries.extend([
+        format!(
+            "SELECT cron.unschedule(jobid) FROM cron.job WHERE jobname IN ('{job_name}', {crons});",
+            crons = reindex_cron_names(job_name)
+                .into_iter()
+                .chain([ttl_cron_name(job_name), migration_cron_name(job_name)])
+                .map(|n| format!("'{n}'"))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        format!("DELETE FROM pgmq.q_{VECTORIZE_QUEUE} WHERE message->>'job_name' = '{job_name}';"),
+        format!("DELETE FROM vectorize.embedding_provenance WHERE job_name = '{job_name}';"),
+        format!("DELETE FROM vectorize.job_stats WHERE job_name = '{job_name}';"),
+    ]);
+    match job_params.table_method {
+        TableMethod::append => queries.push(format!(
+            "ALTER TABLE IF EXISTS {schema}.{table}
+            DROP COLUMN IF EXISTS {job_name}_embeddings,
+            DROP COLUMN IF EXISTS {job_name}_updated_at;"
+        )),
+        TableMethod::join => {
+            queries.push(drop_project_view(job_name));
+            queries.push(format!(
+                "DROP TABLE IF EXISTS {embeddings_schema}._embeddings_{job_name};",
+                embeddings_schema = job_params.embeddings_schema(),
+            ));
+        }
+    }
+    // the source table of a chunked job is its table of chunks
+    if let Some(chunking) = &job_params.chunking {
+        queries.extend(drop_rechunk_trigger_queries(job_name, chunking));
+        queries.push(format!("DROP TABLE IF EXISTS {schema}.{table};"));
+    }
+    queries
+}
+
+/// statements removing a job's realtime triggers and their handler
+pub fn drop_trigger_queries(job_name: &str, schema: &str, table: &str) -> Vec<String> {
+    vec![
+        format!("DROP TRIGGER IF EXISTS vectorize_insert_trigger_{job_name} ON {schema}.{table};"),
+        format!("DROP TRIGGER IF EXISTS vectorize_update_trigger_{job_name} ON {schema}.{table};"),
+        // cascades to the triggers on the partitions of a partitioned table
+        format!("DROP FUNCTION IF EXISTS vectorize.handle_update_{job_name}() CASCADE;"),
+    ]
+}
+
+/// statements moving a job's tables, columns, indexes and metadata over to a new name
+/// embedding_partitions are the names of the partitions of a partitioned embeddings table
+/// the realtime triggers and pg_cron jobs run commands naming the job, so they are re-created separately
+pub fn rename_job_queries(
+    job_name: &str,
+    new_name: &str,
+    job_params: &JobParams,
+    index_dist_type: &IndexDist,
+    embedding_partitions: &[String],
+) -> Vec<String> {
+    check_input(job_name).expect("invalid job name");
+    check_input(new_name).expect("invalid job name");
+    let schema = &job_params.schema;
+    let table = &job_params.table;
+    let embeddings_schema = job_params.embeddings_schema();
+    let mut queries = vec![format!(
+        "UPDATE vectorize.job SET name = '{new_name}' WHERE name = '{job_name}';"
+    )];
+    match job_params.table_method {
+        TableMethod::append => queries.extend([
+            format!("ALTER TABLE {schema}.{table} RENAME COLUMN {job_name}_embeddings TO {new_name}_embeddings;"),
+            format!("ALTER TABLE {schema}.{table} RENAME COLUMN {job_name}_updated_at TO {new_name}_updated_at;"),
+        ]),
+        TableMethod::join => {
+            queries.push(drop_project_view(job_name));
+            queries.push(format!(
+                "ALTER TABLE {embeddings_schema}._embeddings_{job_name} RENAME TO _embeddings_{new_name};"
+            ));
+            let prefix = format!("_embeddings_{job_name}_");
+            for partition in embedding_partitions {
+                if let Some(suffix) = partition.strip_prefix(&prefix) {
+                    queries.push(format!(
+                        "ALTER TABLE {embeddings_schema}.{partition} RENAME TO _embeddings_{new_name}_{suffix};"
+                    ));
+                }
+            }
+        }
+    }
+    queries.push(format!(
+        "ALTER INDEX IF EXISTS {embeddings_schema}.{} RENAME TO {};",
+        index_name(job_name, index_dist_type),
+        index_name(new_name, index_dist_type),
+    ));
+    let renamed_params = renamed_job_params(new_name, job_params);
+    if job_params.chunking.is_some() {
+        queries.extend([
+            format!(
+                "ALTER TABLE {schema}.{table} RENAME TO {new_table};",
+                new_table = renamed_params.table,
+            ),
+            format!(
+                "UPDATE vectorize.job SET params = jsonb_set(params, '{{table}}', to_jsonb('{new_table}'::text))
+                WHERE name = '{new_name}';",
+                new_table = renamed_params.table,
+            ),
+        ]);
+    }
+    if job_params.table_method == TableMethod::join {
+        queries.push(create_project_view(new_name, &renamed_params));
+    }
+    // work queued under the old name
+    queries.extend([
+        format!(
+            "UPDATE pgmq.q_{VECTORIZE_QUEUE}
+            SET message = jsonb_set(
+                jsonb_set(message, '{{job_name}}', to_jsonb('{new_name}'::text)),
+                '{{job_meta,name}}', to_jsonb('{new_name}'::text)
+            )
+            WHERE message->>'job_name' = '{job_name}';"
+        ),
+        format!(
+            "UPDATE vectorize.budget SET name = '{new_name}' WHERE scope = 'job' AND name = '{job_name}';"
+        ),
+        format!(
+            "UPDATE vectorize.embedding_provenance SET job_name = '{new_name}' WHERE job_name = '{job_name}';"
+        ),
+        format!("UPDATE vectorize.job_stats SET job_name = '{new_name}' WHERE job_name = '{job_name}';"),
+        format!(
+            "UPDATE vectorize.benchmark SET job_name = '{new_name}' WHERE job_name = '{job_name}';"
+        ),
+        format!(
+            "UPDATE vectorize.rag_batch SET params = jsonb_set(params, '{{agent_name}}', to_jsonb('{new_name}'::text))
+            WHERE params->>'agent_name' = '{job_name}';"
+        ),
+    ]);
+    queries
+}
+
+/// statements moving a pg_cron job to a new name and command, keeping its schedule and whether it is active
+/// nothing is scheduled when there is no pg_cron job with the old name
+pub fn rename_cron_queries(cron_name: &str, new_cron_name: &str, command: &str) -> Vec<String> {
+    vec![
+        format!(
+            "SELECT cron.schedule('{new_cron_name}', schedule, $${command}$$)
+            FROM cron.job WHERE jobname = '{cron_name}';"
+        ),
+        format!(
+            "SELECT cron.alter_job(n.jobid, active := o.active)
+            FROM cron.job o, cron.job n
+            WHERE o.jobname = '{cron_name}' AND n.jobname = '{new_cron_name}';"
+        ),
+        format!("SELECT cron.unschedule(jobid) FROM cron.job WHERE jobname = '{cron_name}';"),
+    ]
+}
+
 fn create_embedding_table(
     job_name: &str,
-    join_key: &str,
-    join_key_type: &str,
+    schema: &str,
+    pkey: &PrimaryKey,
     col_type: &str,
-    src_schema: &str,
-    src_table: &str,
+    // storage parameters and tablespace of the table
+    table_options: &str,
+    // the source table, whose deleted rows' embeddings are deleted along with them
+    source: Option<(&str, &str)>,
+    partition_by: Option<&str>,
 ) -> String {
+    let partition_by = partition_by
+        .map(|key_def| format!(" PARTITION BY {key_def}"))
+        .unwrap_or_default();
+    let join_key = pkey.column_names().join(", ");
+    let foreign_key = source
+        .map(|(src_schema, src_table)| {
+            format!(
+                ",\n            FOREIGN KEY ({join_key}) REFERENCES {src_schema}.{src_table} ({join_key}) ON DELETE CASCADE"
+            )
+        })
+        .unwrap_or_default();
+    let key_defs = pkey
+        .column_defs()
+        .iter()
+        .map(|def| format!("{def} NOT NULL,"))
+        .collect::<Vec<_>>()
+        .join("\n            ");
     format!(
-        "CREATE TABLE IF NOT EXISTS vectorize._embeddings_{job_name} (
-            {join_key} {join_key_type} UNIQUE NOT NULL,
+        "CREATE TABLE IF NOT EXISTS {schema}._embeddings_{job_name} (
+            {key_defs}
             embeddings {col_type} NOT NULL,
             updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
-            FOREIGN KEY ({join_key}) REFERENCES {src_schema}.{src_table} ({join_key}) ON DELETE CASCADE
-        );
+            UNIQUE ({join_key}){foreign_key}
+        ){partition_by}{table_options};
         ",
-        job_name = job_name,
-        join_key = join_key,
-        join_key_type = join_key_type,
-        col_type = col_type,
-        src_schema = src_schema,
-        src_table = src_table,
     )
 }
 
-fn create_hnsw_l2_index(job_name: &str, schema: &str, table: &str, embedding_col: &str) -> String {
-    format!(
-        "CREATE INDEX IF NOT EXISTS {job_name}_hnsw_l2_idx ON {schema}.{table}
-        USING hnsw ({embedding_col} vector_l2_ops);
-        ",
-    )
+// one embeddings partition for each partition of the source table
+fn create_embedding_partitions(
+    job_name: &str,
+    schema: &str,
+    partitioning: &Partitioning,
+) -> Vec<String> {
+    let mut queries: Vec<String> = partitioning
+        .bounds
+        .iter()
+        .enumerate()
+        .map(|(i, bound)| {
+            format!(
+                "CREATE TABLE IF NOT EXISTS {schema}._embeddings_{job_name}_{i}
+                PARTITION OF {schema}._embeddings_{job_name} {bound};"
+            )
+        })
+        .collect();
+    // embeddings of partitions that are attached to the source table later on
+    // hash partitioned tables can not have a default partition
+    let has_default = partitioning.bounds.iter().any(|b| b == "DEFAULT");
+    if !has_default && !partitioning.key_def.starts_with("HASH") {
+        queries.push(format!(
+            "CREATE TABLE IF NOT EXISTS {schema}._embeddings_{job_name}_default
+            PARTITION OF {schema}._embeddings_{job_name} DEFAULT;"
+        ));
+    }
+    queries
+}
+
+/// a declaratively partitioned table
+pub struct Partitioning {
+    // the partition key, e.g. RANGE (created_at)
+    pub key_def: String,
+    pub key_columns: Vec<String>,
+    // the bound of each partition, e.g. FOR VALUES FROM ('2024-01-01') TO ('2025-01-01'), or DEFAULT
+    pub bounds: Vec<String>,
+    // schema and name of each leaf partition
+    pub leaves: Vec<(String, String)>,
+}
+
+/// whether a job's table is a table, a view or a foreign table
+pub fn get_source_kind(schema: &str, table: &str) -> Result<SourceKind> {
+    let relkind: String = compat::get_one(
+        "SELECT relkind::text FROM pg_class WHERE oid = format('%I.%I', $1, $2)::regclass",
+        vec![arg(schema), arg(table)],
+    )?
+    .ok_or_else(|| anyhow!("table {schema}.{table} does not exist"))?;
+    SourceKind::from_relkind(&relkind)
+        .ok_or_else(|| anyhow!("{schema}.{table} is not a table, view or foreign table"))
+}
+
+/// how a table is partitioned, or None when it is not a partitioned table
+pub fn get_partitioning(schema: &str, table: &str) -> Result<Option<Partitioning>> {
+    let args = || vec![arg(schema), arg(table)];
+    let key_def: Option<String> = compat::get_one(
+        "SELECT pg_get_partkeydef(format('%I.%I', $1, $2)::regclass)",
+        args(),
+    )?;
+    let key_def = match key_def {
+        Some(k) => k,
+        None => return Ok(None),
+    };
+    compat::connect(|client| {
+        let mut key_columns = Vec::new();
+        let tup_table = compat::select(
+            &client,
+            "SELECT a.attname::text AS attname
+            FROM pg_partitioned_table pt
+            CROSS JOIN LATERAL unnest(pt.partattrs::int2[]) WITH ORDINALITY AS k(attnum, ord)
+            LEFT JOIN pg_attribute a ON a.attrelid = pt.partrelid AND a.attnum = k.attnum
+            WHERE pt.partrelid = format('%I.%I', $1, $2)::regclass
+            ORDER BY k.ord",
+            args(),
+        )?;
+        for row in tup_table {
+            // expressions in the partition key have no column
+            let col: Option<String> = row["attname"].value()?;
+            key_columns.push(col.ok_or_else(|| {
+                anyhow!("tables partitioned by an expression are not supported: {schema}.{table}")
+            })?);
+        }
+
+        let mut bounds = Vec::new();
+        let tup_table = compat::select(
+            &client,
+            "SELECT pg_get_expr(c.relpartbound, c.oid) AS bound
+            FROM pg_inherits i
+            JOIN pg_class c ON c.oid = i.inhrelid
+            WHERE i.inhparent = format('%I.%I', $1, $2)::regclass
+            ORDER BY c.relname",
+            args(),
+        )?;
+        for row in tup_table {
+            let bound: Option<String> = row["bound"].value()?;
+            bounds.extend(bound);
+        }
+
+        let mut leaves = Vec::new();
+        let tup_table = compat::select(
+            &client,
+            "SELECT n.nspname::text AS schema, c.relname::text AS name
+            FROM pg_partition_tree(format('%I.%I', $1, $2)::regclass) t
+            JOIN pg_class c ON c.oid = t.relid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE t.isleaf",
+            args(),
+        )?;
+        for row in tup_table {
+            if let (Some(schema), Some(name)) = (row["schema"].value()?, row["name"].value()?) {
+                leaves.push((schema, name));
+            }
+        }
+        Ok::<_, anyhow::Error>(Some(Partitioning {
+            key_def,
+            key_columns,
+            bounds,
+            leaves,
+        }))
+    })
+}
+
+/// the schema, table and column holding a job's embeddings
+pub fn embeddings_location(job_name: &str, job_params: &JobParams) -> (String, String, String) {
+    job_params.embeddings_location(job_name)
 }
 
-fn create_hnsw_ip_index(job_name: &str, schema: &str, table: &str, embedding_col: &str) -> String {
+/// counts a job's rows that have embeddings, written since the job's last forced refresh when there was one
+/// takes the job name in $1
+pub fn embedded_rows_query(job_name: &str, job_params: &JobParams) -> String {
+    let (schema, table, embeddings_col) = embeddings_location(job_name, job_params);
+    let updated_at_col = match job_params.table_method {
+        TableMethod::append => format!("{job_name}_updated_at"),
+        TableMethod::join => "updated_at".to_string(),
+    };
     format!(
-        "CREATE INDEX IF NOT EXISTS {job_name}_hnsw_ip_idx ON {schema}.{table}
-        USING hnsw ({embedding_col} vector_ip_ops);
-        ",
+        "SELECT count(*) FROM {schema}.{table}
+        WHERE {embeddings_col} IS NOT NULL
+        AND {updated_at_col} >= COALESCE(
+            (SELECT refreshed_at FROM vectorize.job WHERE name = $1),
+            '-infinity'::timestamptz
+        )"
     )
 }
 
-fn create_hnsw_cosine_index(
+/// the name of the vector index built for a job
+pub fn index_name(job_name: &str, index_type: &IndexDist) -> String {
+    let suffix = match index_type {
+        IndexDist::pgv_hnsw_l2 => "hnsw_l2",
+        IndexDist::pgv_hnsw_ip => "hnsw_ip",
+        IndexDist::pgv_hnsw_cosine => "hnsw_cos",
+        IndexDist::pgv_ivfflat_l2 => "ivfflat_l2",
+        IndexDist::pgv_ivfflat_ip => "ivfflat_ip",
+        IndexDist::pgv_ivfflat_cosine => "ivfflat_cos",
+        IndexDist::vsc_diskann_cosine => "diskann",
+        IndexDist::vsc_diskann_l2 => "diskann_l2",
+        IndexDist::vsc_diskann_ip => "diskann_ip",
+    };
+    format!("{job_name}_{suffix}_idx")
+}
+
+pub fn create_index_query(
     job_name: &str,
-    schema: &str,
-    table: &str,
-    embedding_col: &str,
+    job_params: &JobParams,
+    index_type: &IndexDist,
+    index_name: &str,
+    concurrently: bool,
 ) -> String {
+    let (schema, table, embeddings_col) = embeddings_location(job_name, job_params);
+    let (method, ops) = match index_type {
+        IndexDist::pgv_hnsw_l2 => ("hnsw", "_l2_ops"),
+        IndexDist::pgv_hnsw_ip => ("hnsw", "_ip_ops"),
+        IndexDist::pgv_hnsw_cosine => ("hnsw", "_cosine_ops"),
+        IndexDist::pgv_ivfflat_l2 => ("ivfflat", "_l2_ops"),
+        IndexDist::pgv_ivfflat_ip => ("ivfflat", "_ip_ops"),
+        IndexDist::pgv_ivfflat_cosine => ("ivfflat", "_cosine_ops"),
+        // cosine is the default operator class of diskann indexes
+        IndexDist::vsc_diskann_cosine => ("diskann", ""),
+        IndexDist::vsc_diskann_l2 => ("diskann", "_l2_ops"),
+        IndexDist::vsc_diskann_ip => ("diskann", "_ip_ops"),
+    };
+    let ops = match ops {
+        "" => String::new(),
+        ops => format!(" {}{ops}", job_params.vector_storage.vector_type()),
+    };
+    // quantized embeddings are indexed by an expression over the stored column
+    let indexed = match job_params.searchable_embeddings(&embeddings_col) {
+        searchable if searchable == embeddings_col => searchable,
+        searchable => format!("({searchable})"),
+    };
+    let with_clause = job_params.index_params.with_clause();
+    let tablespace = job_params.storage_params.index_tablespace_clause();
+    let concurrently = if concurrently { " CONCURRENTLY" } else { "" };
     format!(
-        "CREATE INDEX IF NOT EXISTS {job_name}_hnsw_cos_idx ON {schema}.{table}
-        USING hnsw ({embedding_col} vector_cosine_ops);
+        "CREATE INDEX{concurrently} IF NOT EXISTS {index_name} ON {schema}.{table}
+        USING {method} ({indexed}{ops}){with_clause}{tablespace};
         ",
     )
 }
 
-fn create_diskann_index(job_name: &str, schema: &str, table: &str, embedding_col: &str) -> String {
-    format!(
-        "CREATE INDEX IF NOT EXISTS {job_name}_diskann_idx ON {schema}.{table}
-        USING diskann ({embedding_col});
-        ",
-    )
+// sets the STORAGE and COMPRESSION of a job's embeddings column
+fn embeddings_column_storage(
+    schema: &str,
+    table: &str,
+    embeddings_col: &str,
+    storage_params: &StorageParams,
+) -> Vec<String> {
+    let mut queries = Vec::new();
+    if let Some(storage) = &storage_params.column_storage {
+        queries.push(format!(
+            "ALTER TABLE {schema}.{table} ALTER COLUMN {embeddings_col} SET STORAGE {storage};"
+        ));
+    }
+    if let Some(compression) = &storage_params.compression {
+        queries.push(format!(
+            "ALTER TABLE {schema}.{table} ALTER COLUMN {embeddings_col} SET COMPRESSION {compression};"
+        ));
+    }
+    queries
 }
 
 fn append_embedding_column(job_name: &str, schema: &str, table: &str, col_type: &str) -> String {
@@ -236,21 +642,412 @@ fn append_embedding_column(job_name: &str, schema: &str, table: &str, col_type:
     )
 }
 
+// diskann indexes are provided by pgvectorscale, which vectorize does not depend on
+fn vectorscale_installed() -> Result<bool> {
+    Ok(compat::get_one(
+        "SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'vectorscale')",
+        vec![],
+    )?
+    .unwrap_or(false))
+}
+
+/// validates index build parameters against the index type
+/// and fills in the ones that are picked automatically
+pub fn resolve_index_params(
+    index_type: &IndexDist,
+    index_params: IndexParams,
+    schema: &str,
+    table: &str,
+) -> Result<IndexParams> {
+    if (index_params.m.is_some() || index_params.ef_construction.is_some()) && !index_type.is_hnsw()
+    {
+        return Err(anyhow!(
+            "m and ef_construction are only supported for hnsw indexes"
+        ));
+    }
+    if index_params.lists.is_some() && !index_type.is_ivfflat() {
+        return Err(anyhow!("lists is only supported for ivfflat indexes"));
+    }
+    let diskann_params = [
+        index_params.num_neighbors,
+        index_params.search_list_size,
+        index_params.num_bits_per_dimension,
+    ];
+    if (diskann_params.iter().any(Option::is_some) || index_params.storage_layout.is_some())
+        && !index_type.is_diskann()
+    {
+        bail!(
+            "num_neighbors, search_list_size, storage_layout and num_bits_per_dimension are only supported for diskann indexes"
+        );
+    }
+    if let Some(layout) = &index_params.storage_layout {
+        if !["memory_optimized", "plain"].contains(&layout.as_str()) {
+            bail!("storage_layout must be memory_optimized or plain: {layout}");
+        }
+    }
+    if index_type.is_diskann() && !vectorscale_installed()? {
+        bail!(
+            "diskann indexes require the pgvectorscale extension, install it with: CREATE EXTENSION vectorscale"
+        );
+    }
+    if let Some(param) = [
+        "m",
+        "ef_construction",
+        "lists",
+        "num_neighbors",
+        "search_list_size",
+        "storage_layout",
+        "num_bits_per_dimension",
+    ]
+    .iter()
+    .find(|p| index_params.storage_params.contains_key(**p))
+    {
+        bail!("{param} must be given as an argument, not as an index storage parameter");
+    }
+    validate_storage_parameters(&index_params.storage_params)?;
+    let mut index_params = index_params;
+    if index_type.is_ivfflat() && index_params.lists.is_none() {
+        // auto mode, size the lists from the current row count
+        let num_rows = estimate_row_count(schema, table)?;
+        index_params.lists = Some(ivfflat_lists(num_rows));
+    }
+    Ok(index_params)
+}
+
+/// checks that decryption expressions only reference the job's input columns
+/// and that the role they are evaluated as exists and is one the current user is a member of
+/// the current user is recorded as the owner, whose membership the worker checks again before each decryption
+pub fn validate_decryption(
+    mut decryption: ColumnDecryption,
+    columns: &[String],
+) -> Result<ColumnDecryption> {
+    if let Some(col) = decryption.expressions.keys().find(|c| !columns.contains(c)) {
+        return Err(anyhow!(
+            "decryption expression given for {col}, which is not one of the job's columns"
+        ));
+    }
+    let role_exists: bool = compat::get_one(
+        "SELECT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = $1)",
+        vec![arg(decryption.role.clone())],
+    )?
+    .unwrap_or(false);
+    if !role_exists {
+        return Err(anyhow!(
+            "decryption role does not exist: {}",
+            decryption.role
+        ));
+    }
+    // otherwise any user could read columns through a role they can not assume themselves
+    let is_member: bool = compat::get_one(
+        "SELECT pg_has_role(current_user, $1, 'MEMBER')",
+        vec![arg(decryption.role.clone())],
+    )?
+    .unwrap_or(false);
+    if !is_member {
+        return Err(anyhow!(
+            "current user must be a member of decryption role: {}",
+            decryption.role
+        ));
+    }
+    decryption.owner = compat::get_one::<String>("SELECT current_user::text", vec![])?
+        .context("error getting current user")?;
+    Ok(decryption)
+}
+
+/// checks that column weights are positive and only given for the job's input columns
+pub fn validate_column_weights(weights: &BTreeMap<String, f64>, columns: &[String]) -> Result<()> {
+    for (col, weight) in weights {
+        if !columns.contains(col) {
+            bail!("weight given for {col}, which is not one of the job's columns");
+        }
+        if !weight.is_finite() || *weight <= 0.0 {
+            bail!("weight of {col} must be a positive number, got {weight}");
+        }
+    }
+    Ok(())
+}
+
+/// checks that a job's embeddings can be kept in the given schema, which is created if it does not exist
+pub fn validate_dest_schema(dest_schema: &str, table_method: &TableMethod) -> Result<()> {
+    check_input(dest_schema)?;
+    if *table_method != TableMethod::join {
+        bail!("dest_schema is only compatible with the join table method");
+    }
+    Ok(())
+}
+
+/// checks that a job's embeddings can be stored as asked
+/// table storage parameters are only set on embeddings tables, as the source table is not vectorize's to change
+pub fn validate_storage(
+    vector_storage: VectorStorage,
+    storage_params: &StorageParams,
+    table_method: &TableMethod,
+    index_type: &IndexDist,
+    partition_embeddings: bool,
+) -> Result<()> {
+    if !vector_storage.is_default() && index_type.is_diskann() {
+        bail!("diskann indexes are only supported for vector storage, not {vector_storage}");
+    }
+    if let Some(storage) = &storage_params.column_storage {
+        if !["plain", "external", "extended", "main"].contains(&storage.as_str()) {
+            bail!("column_storage must be one of plain, external, extended or main: {storage}");
+        }
+    }
+    if let Some(compression) = &storage_params.compression {
+        if !["pglz", "lz4"].contains(&compression.as_str()) {
+            bail!("compression must be pglz or lz4: {compression}");
+        }
+    }
+    for tablespace in [&storage_params.tablespace, &storage_params.index_tablespace]
+        .into_iter()
+        .flatten()
+    {
+        check_input(tablespace)?;
+    }
+    if storage_params.table_params.is_empty() {
+        return Ok(());
+    }
+    if *table_method != TableMethod::join {
+        bail!("table storage parameters are only compatible with the join table method");
+    }
+    if partition_embeddings {
+        bail!("table storage parameters can not be set on partitioned embeddings tables");
+    }
+    validate_storage_parameters(&storage_params.table_params)
+}
+
+/// checks that normalizing a job's embeddings makes a difference to its index
+/// cosine distance already ignores the length of embeddings, so normalized jobs use inner product or l2 indexes
+pub fn validate_normalize(normalize: bool, index_type: &IndexDist) -> Result<()> {
+    if normalize && index_type.metric() == DistanceMetric::cosine {
+        bail!(
+            "normalize is for inner product and l2 indexes, which rank normalized embeddings as cosine does: use an _ip index in place of {index_type}"
+        );
+    }
+    Ok(())
+}
+
+/// checks that a job of images embeds a single column, of bytea for images or of text for their urls,
+/// with a model that embeds them
+pub fn validate_content_type(
+    content_type: ContentType,
+    model: &Model,
+    column_types: &[String],
+) -> Result<()> {
+    if content_type.is_text() {
+        return Ok(());
+    }
+    if !embeds_content(&model.source, content_type) {
+        bail!("{model} does not embed content of type {content_type}");
+    }
+    let [column_type] = column_types else {
+        bail!("a job of content type {content_type} embeds a single column");
+    };
+    let expected = match content_type {
+        ContentType::image => column_type == "bytea",
+        _ => column_type == "text" || column_type.starts_with("character varying"),
+    };
+    if !expected {
+        bail!("a column of content type {content_type} can not be of type {column_type}");
+    }
+    Ok(())
+}
+
+// storage parameters are interpolated into WITH clauses
+fn validate_storage_parameters(params: &BTreeMap<String, serde_json::Value>) -> Result<()> {
+    for (param, value) in params {
+        check_input(param)?;
+        if !(value.is_number() || value.is_boolean()) {
+            bail!("storage parameter {param} must be a number or a boolean: {value}");
+        }
+    }
+    Ok(())
+}
+
+/// checks that a job over a view or foreign table can be kept up to date
+/// these can not have triggers with transition tables, or columns added for the job, so they are diffed
+/// on a cron-like schedule into an embeddings table of their own
+/// a view's rows are only found to have changed by their update_col, as the diff otherwise finds only new rows
+pub fn validate_source_kind(
+    source_kind: SourceKind,
+    schedule: &str,
+    table_method: &TableMethod,
+    update_col: Option<&str>,
+) -> Result<()> {
+    if source_kind.is_table() {
+        return Ok(());
+    }
+    if schedule == "realtime" {
+        bail!("a {source_kind} can not have a realtime schedule, use a cron-like schedule");
+    }
+    if *table_method != TableMethod::join {
+        bail!("a {source_kind} is only compatible with the join table method");
+    }
+    let is_view = matches!(
+        source_kind,
+        SourceKind::view | SourceKind::materialized_view
+    );
+    if is_view && update_col.is_none() {
+        bail!("a {source_kind} requires an update_col, the time each of its rows last changed");
+    }
+    Ok(())
+}
+
+/// checks that the columns holding a rag agent's chunk provenance exist in its table
+pub fn validate_provenance(provenance: &ChunkProvenance, schema: &str, table: &str) -> Result<()> {
+    for column in provenance.columns() {
+        get_column_datatype(schema, table, &column)?;
+    }
+    Ok(())
+}
+
+// the functions an input template can call
+const TEMPLATE_FUNCTIONS: [&str; 3] = ["format", "concat", "concat_ws"];
+
+/// restricts an input template to the job's columns, string literals, || and calls to format, concat and concat_ws,
+/// since templates are evaluated by the background worker and by the job's triggers
+/// functions are called from pg_catalog, so that a function of the same name elsewhere on the search_path is never run
+/// returns the template as it is evaluated
+pub fn normalize_input_template(template: &str, columns: &[String]) -> Result<String> {
+    let invalid = || {
+        anyhow!(
+            "input_template can only combine the job's columns and string literals with ||, format, concat and concat_ws"
+        )
+    };
+    let chars: Vec<char> = template.chars().collect();
+    let mut out = String::with_capacity(template.len());
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        match c {
+            '\'' | '"' => {
+                // a doubled quote is an escaped quote, not the end of the literal
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(invalid()),
+                        Some(q) if *q == c && chars.get(i + 1) == Some(&c) => i += 2,
+                        Some(q) if *q == c => break,
+                        Some(_) => i += 1,
+                    }
+                }
+                i += 1;
+                let token: String = chars[start..i].iter().collect();
+                if c == '"' && !columns.contains(&token[1..token.len() - 1].replace("\"\"", "\"")) {
+                    return Err(invalid());
+                }
+                out.push_str(&token);
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let name = chars[start..i].iter().collect::<String>().to_lowercase();
+                let is_call = chars[i..].iter().find(|c| !c.is_whitespace()) == Some(&'(');
+                if is_call && TEMPLATE_FUNCTIONS.contains(&name.as_str()) {
+                    out.push_str(&format!("pg_catalog.{name}"));
+                } else if !is_call && columns.contains(&name) {
+                    out.push_str(&name);
+                } else {
+                    return Err(invalid());
+                }
+            }
+            '(' | ')' | ',' => {
+                depth += if c == '(' {
+                    1
+                } else if c == ')' {
+                    -1
+                } else {
+                    0
+                };
+                if depth < 0 {
+                    return Err(invalid());
+                }
+                out.push(c);
+                i += 1;
+            }
+            '|' if chars.get(i + 1) == Some(&'|') => {
+                out.push_str("||");
+                i += 2;
+            }
+            c if c.is_whitespace() => {
+                out.push(c);
+                i += 1;
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    if depth != 0 {
+        return Err(invalid());
+    }
+    Ok(out)
+}
+
+/// checks that a job's input template can be evaluated over the rows of its table
+/// a template produces the whole input text of a row, so it can not be combined with column weights or decryption
+pub fn validate_input_template(job_params: &mut JobParams) -> Result<()> {
+    if let Some(template) = &job_params.input_template {
+        job_params.input_template = Some(normalize_input_template(template, &job_params.columns)?);
+    }
+    let text = match job_params.templated_input_text("t0") {
+        Some(text) => text,
+        None => return Ok(()),
+    };
+    if job_params.is_weighted() {
+        bail!("input_template can not be combined with column_weights");
+    }
+    if job_params.decryption.is_some() {
+        bail!("input_template can not be combined with decrypt_expressions");
+    }
+    compat::run(
+        &format!(
+            "SELECT {text} FROM {schema}.{table} t0 LIMIT 0",
+            schema = job_params.schema,
+            table = job_params.table,
+        ),
+        vec![],
+    )
+    .context("input_template is not a valid expression over the table's columns")
+}
+
+/// estimated number of rows in a table, from planner statistics when they are available
+pub fn estimate_row_count(schema: &str, table: &str) -> Result<i64> {
+    // partitioned tables have no statistics of their own, so their partitions are summed
+    let estimate: i64 = compat::get_one(
+        "SELECT COALESCE(CASE WHEN bool_or(c.reltuples < 0) THEN -1 ELSE sum(c.reltuples)::bigint END, -1)
+        FROM pg_partition_tree(format('%I.%I', $1, $2)::regclass) t
+        JOIN pg_class c ON c.oid = t.relid
+        WHERE t.isleaf",
+        vec![
+            arg(schema),
+            arg(table),
+        ],
+    )?
+    .context("error estimating row count")?;
+    if estimate >= 0 {
+        return Ok(estimate);
+    }
+    // table has never been vacuumed or analyzed, or is a view or foreign table without a partition tree
+    compat::get_one(&format!("SELECT count(*) FROM {schema}.{table}"), vec![])?
+        .context("error counting rows")
+}
+
 pub fn get_column_datatype(schema: &str, table: &str, column: &str) -> Result<String> {
-    Spi::get_one_with_args(
+    compat::get_one(
         "
-        SELECT data_type
-        FROM information_schema.columns
+        SELECT format_type(a.atttypid, a.atttypmod)
+        FROM pg_attribute a
+        JOIN pg_class c ON c.oid = a.attrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
         WHERE
-            table_schema = $1
-            AND table_name = $2
-            AND column_name = $3    
+            n.nspname = $1
+            AND c.relname = $2
+            AND a.attname = $3
+            AND NOT a.attisdropped
         ",
-        vec![
-            (PgBuiltInOids::TEXTOID.oid(), schema.into_datum()),
-            (PgBuiltInOids::TEXTOID.oid(), table.into_datum()),
-            (PgBuiltInOids::TEXTOID.oid(), column.into_datum()),
-        ],
+        vec![arg(schema), arg(table), arg(column)],
     )
     .map_err(|_| {
         anyhow!(
@@ -269,3 +1066,345 @@ pub fn get_column_datatype(schema: &str, table: &str, column: &str) -> Result<St
         )
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_input_template() {
+        let columns = vec!["question".to_string(), "answer".to_string()];
+        assert_eq!(
+            normalize_input_template("format('Q: %s A: %s', question, Answer)", &columns).unwrap(),
+            "pg_catalog.format('Q: %s A: %s', question, answer)"
+        );
+        assert_eq!(
+            normalize_input_template(
+                "'Q: ' || \"question\" || concat(' it''s ', answer)",
+                &columns
+            )
+            .unwrap(),
+            "'Q: ' || \"question\" || pg_catalog.concat(' it''s ', answer)"
+        );
+        // other columns, functions, subqueries and statements are rejected
+        for template in [
+            "format('%s', secret)",
+            "upper(question)",
+            "(SELECT answer FROM faqs LIMIT 1)",
+            "question; DROP TABLE faqs",
+            "question::text",
+            "format('%s', question",
+            "'unterminated",
+        ] {
+            assert!(
+                normalize_input_template(template, &columns).is_err(),
+                "{template}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_rename_job_queries() {
+        let mut job_params = JobParams {
+            schema: "public".to_string(),
+            table: "products".to_string(),
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            ..Default::default()
+        };
+        let partitions = vec![
+            "_embeddings_old_0".to_string(),
+            "_embeddings_old_default".to_string(),
+        ];
+        let queries = rename_job_queries(
+            "old",
+            "new",
+            &job_params,
+            &IndexDist::pgv_hnsw_cosine,
+            &partitions,
+        );
+        assert_eq!(
+            queries[0],
+            "UPDATE vectorize.job SET name = 'new' WHERE name = 'old';"
+        );
+        assert!(queries.contains(
+            &"ALTER TABLE vectorize._embeddings_old RENAME TO _embeddings_new;".to_string()
+        ));
+        assert!(queries.contains(
+            &"ALTER TABLE vectorize._embeddings_old_default RENAME TO _embeddings_new_default;"
+                .to_string()
+        ));
+        assert!(queries.contains(
+            &"ALTER INDEX IF EXISTS vectorize.old_hnsw_cos_idx RENAME TO new_hnsw_cos_idx;"
+                .to_string()
+        ));
+        assert!(queries
+            .iter()
+            .any(|q| q.starts_with("CREATE VIEW vectorize.new_view")));
+
+        job_params.table_method = TableMethod::append;
+        let queries =
+            rename_job_queries("old", "new", &job_params, &IndexDist::pgv_hnsw_cosine, &[]);
+        assert!(queries.contains(
+            &"ALTER TABLE public.products RENAME COLUMN old_embeddings TO new_embeddings;"
+                .to_string()
+        ));
+        assert!(queries.contains(
+            &"ALTER INDEX IF EXISTS public.old_hnsw_cos_idx RENAME TO new_hnsw_cos_idx;"
+                .to_string()
+        ));
+        assert!(!queries.iter().any(|q| q.contains("_view")));
+
+        // a chunked job's table of chunks is renamed along with it
+        let job_params = JobParams {
+            schema: "vectorize".to_string(),
+            table: "_chunks_old".to_string(),
+            primary_key: "chunk_id".to_string(),
+            pkey_type: "bigint".to_string(),
+            chunking: Some(Default::default()),
+            ..Default::default()
+        };
+        let queries =
+            rename_job_queries("old", "new", &job_params, &IndexDist::pgv_hnsw_cosine, &[]);
+        assert!(queries
+            .contains(&"ALTER TABLE vectorize._chunks_old RENAME TO _chunks_new;".to_string()));
+        let view = queries
+            .iter()
+            .find(|q| q.starts_with("CREATE VIEW vectorize.new_view"))
+            .unwrap();
+        assert!(view.contains("vectorize._chunks_new"));
+        assert!(drop_job_queries("old", &job_params)
+            .last()
+            .is_some_and(|q| q == "DROP TABLE IF EXISTS vectorize._chunks_old;"));
+    }
+
+    #[test]
+    fn test_rename_cron_queries() {
+        let queries = rename_cron_queries("old", "new", &job_execute_command("new"));
+        assert_eq!(queries.len(), 3);
+        assert!(queries[0].starts_with(
+            "SELECT cron.schedule('new', schedule, $$select vectorize.job_execute('new')$$)"
+        ));
+        // a paused job's schedule stays inactive
+        assert!(queries[1].contains("cron.alter_job(n.jobid, active := o.active)"));
+        assert!(queries[2].contains("WHERE jobname = 'old'"));
+    }
+
+    #[test]
+    fn test_source_kind() {
+        let foreign = SourceKind::foreign_table;
+        assert!(validate_source_kind(foreign, "*/5 * * * *", &TableMethod::join, None).is_ok());
+        assert!(validate_source_kind(foreign, "realtime", &TableMethod::join, None).is_err());
+        assert!(validate_source_kind(foreign, "*/5 * * * *", &TableMethod::append, None).is_err());
+        assert!(
+            validate_source_kind(SourceKind::table, "realtime", &TableMethod::append, None).is_ok()
+        );
+        let err = validate_source_kind(
+            SourceKind::materialized_view,
+            "realtime",
+            &TableMethod::join,
+            Some("updated_at"),
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("a materialized view can not have a realtime schedule"));
+        // the rows of a view that changed are only found by their update_col
+        let view = SourceKind::view;
+        assert!(validate_source_kind(view, "*/5 * * * *", &TableMethod::join, None).is_err());
+        assert!(
+            validate_source_kind(view, "*/5 * * * *", &TableMethod::join, Some("updated_at"))
+                .is_ok()
+        );
+
+        let job_params = JobParams {
+            schema: "remote".to_string(),
+            table: "products".to_string(),
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            source_kind: SourceKind::foreign_table,
+            ..Default::default()
+        };
+        let queries = init_embedding_table_query(
+            "search",
+            &job_params,
+            &IndexDist::pgv_hnsw_cosine,
+            384,
+            None,
+        );
+        assert!(queries[1].contains("CREATE TABLE IF NOT EXISTS vectorize._embeddings_search"));
+        // foreign tables can not be referenced by a foreign key
+        assert!(!queries[1].contains("FOREIGN KEY"));
+    }
+
+    #[test]
+    fn test_storage() {
+        let storage_params: StorageParams = serde_json::from_value(serde_json::json!({
+            "compression": "lz4",
+            "fillfactor": 90
+        }))
+        .unwrap();
+        let join = TableMethod::join;
+        let hnsw = IndexDist::pgv_hnsw_cosine;
+        assert!(
+            validate_storage(VectorStorage::int8, &storage_params, &join, &hnsw, false).is_ok()
+        );
+        assert!(validate_storage(
+            VectorStorage::int8,
+            &storage_params,
+            &TableMethod::append,
+            &hnsw,
+            false
+        )
+        .is_err());
+        assert!(
+            validate_storage(VectorStorage::int8, &storage_params, &join, &hnsw, true).is_err()
+        );
+        let diskann = IndexDist::vsc_diskann_cosine;
+        assert!(validate_storage(
+            VectorStorage::halfvec,
+            &StorageParams::default(),
+            &join,
+            &diskann,
+            false
+        )
+        .is_err());
+        let invalid: StorageParams =
+            serde_json::from_value(serde_json::json!({"fillfactor": "90; DROP TABLE t"})).unwrap();
+        assert!(validate_storage(VectorStorage::vector, &invalid, &join, &hnsw, false).is_err());
+
+        let mut job_params = JobParams {
+            schema: "public".to_string(),
+            table: "products".to_string(),
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            dimensions: Some(384),
+            vector_storage: VectorStorage::halfvec,
+            storage_params,
+            ..Default::default()
+        };
+        let queries = init_embedding_table_query("search", &job_params, &hnsw, 384, None);
+        assert!(queries[1].contains("embeddings halfvec(384) NOT NULL"));
+        assert!(queries[1].contains(") WITH (fillfactor = 90);"));
+        assert_eq!(
+            queries[2],
+            "ALTER TABLE vectorize._embeddings_search ALTER COLUMN embeddings SET COMPRESSION lz4;"
+        );
+        assert!(queries[3].contains("USING hnsw (embeddings halfvec_cosine_ops)"));
+
+        // quantized embeddings are indexed as the halfvec they are searched as
+        job_params.vector_storage = VectorStorage::int8;
+        let query = create_index_query("search", &job_params, &hnsw, "search_idx", false);
+        assert!(query.contains(
+            "USING hnsw ((vectorize._dequantize_int8(embeddings)::halfvec(384)) halfvec_cosine_ops)"
+        ));
+    }
+
+    #[test]
+    fn test_validate_content_type() {
+        let cohere = Model::new("cohere/embed-english-v3.0").unwrap();
+        let jina = Model::new("jina/jina-clip-v2").unwrap();
+        let bytea = vec!["bytea".to_string()];
+        let text = vec!["text".to_string()];
+        assert!(validate_content_type(ContentType::image, &cohere, &bytea).is_ok());
+        assert!(validate_content_type(ContentType::image_url, &jina, &text).is_ok());
+        assert!(validate_content_type(ContentType::image_url, &cohere, &text).is_err());
+        assert!(validate_content_type(ContentType::image, &cohere, &text).is_err());
+        assert!(validate_content_type(
+            ContentType::image,
+            &jina,
+            &[bytea[0].clone(), text[0].clone()]
+        )
+        .is_err());
+        let openai = Model::new("openai/text-embedding-3-small").unwrap();
+        assert!(validate_content_type(ContentType::image, &openai, &bytea).is_err());
+        assert!(validate_content_type(ContentType::text, &openai, &text).is_ok());
+    }
+
+    #[test]
+    fn test_validate_normalize() {
+        assert!(validate_normalize(true, &IndexDist::pgv_hnsw_ip).is_ok());
+        assert!(validate_normalize(true, &IndexDist::vsc_diskann_l2).is_ok());
+        assert!(validate_normalize(true, &IndexDist::pgv_hnsw_cosine).is_err());
+        assert!(validate_normalize(false, &IndexDist::pgv_ivfflat_cosine).is_ok());
+    }
+
+    #[test]
+    fn test_diskann_index() {
+        let job_params = JobParams {
+            index_params: IndexParams {
+                num_neighbors: Some(50),
+                storage_layout: Some("plain".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let l2 = IndexDist::vsc_diskann_l2;
+        let query = create_index_query(
+            "search",
+            &job_params,
+            &l2,
+            &index_name("search", &l2),
+            false,
+        );
+        assert!(query.contains("IF NOT EXISTS search_diskann_l2_idx"));
+        assert!(query.contains(
+            "USING diskann (embeddings vector_l2_ops) WITH (num_neighbors = 50, storage_layout = plain);"
+        ));
+        let cosine = IndexDist::vsc_diskann_cosine;
+        let query = create_index_query("search", &job_params, &cosine, "search_idx", false);
+        assert!(query.contains("USING diskann (embeddings) WITH"));
+
+        // diskann parameters are rejected for other index types
+        let index_params = IndexParams {
+            num_neighbors: Some(50),
+            ..Default::default()
+        };
+        let err = resolve_index_params(&IndexDist::pgv_hnsw_l2, index_params, "public", "products")
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("only supported for diskann indexes"));
+    }
+
+    #[test]
+    fn test_tablespace() {
+        let mut job_params = JobParams {
+            schema: "public".to_string(),
+            table: "products".to_string(),
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            storage_params: StorageParams {
+                tablespace: Some("bulk".to_string()),
+                ..Default::default()
+            },
+            index_params: IndexParams {
+                m: Some(32),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let hnsw = IndexDist::pgv_hnsw_cosine;
+        let queries = init_embedding_table_query("search", &job_params, &hnsw, 384, None);
+        assert!(queries[1].ends_with(") TABLESPACE bulk;\n        "));
+        assert!(queries[2].contains("embeddings vector_cosine_ops) WITH (m = 32) TABLESPACE bulk;"));
+
+        job_params.storage_params.index_tablespace = Some("fast".to_string());
+        job_params.table_method = TableMethod::append;
+        let queries = init_embedding_table_query("search", &job_params, &hnsw, 384, None);
+        assert!(queries[1].contains("ON public.products"));
+        assert!(queries[1].contains("WITH (m = 32) TABLESPACE fast;"));
+
+        // m, ef_construction and lists have arguments of their own
+        let index_params = IndexParams {
+            storage_params: BTreeMap::from([("m".to_string(), serde_json::json!(32))]),
+            ..Default::default()
+        };
+        assert!(resolve_index_params(&hnsw, index_params, "public", "products").is_err());
+        let index_params = IndexParams {
+            storage_params: BTreeMap::from([("deferred".to_string(), serde_json::json!("x"))]),
+            ..Default::default()
+        };
+        assert!(resolve_index_params(&hnsw, index_params, "public", "products").is_err());
+    }
+}
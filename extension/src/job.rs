@@ -1,12 +1,16 @@
 use anyhow::Result;
 
-use crate::executor::{create_batches, new_rows_query, new_rows_query_join};
+use crate::compat::{self, arg};
+use crate::executor::{
+    estimate_tokens, new_rows_query, new_rows_query_join, ENCRYPTED_INPUT_PLACEHOLDER,
+};
 use crate::guc::BATCH_SIZE;
-use crate::init::VECTORIZE_QUEUE;
+use crate::init::{Partitioning, VECTORIZE_QUEUE};
 use crate::util;
 
 use pgrx::prelude::*;
 use tiktoken_rs::cl100k_base;
+use vectorize_core::chunking::create_batches;
 use vectorize_core::transformers::types::Inputs;
 use vectorize_core::types::{IndexDist, JobMessage, JobParams, Model, TableMethod, VectorizeMeta};
 
@@ -24,11 +28,14 @@ fn _handle_table_update(job_name: &str, record_ids: Vec<String>, inputs: Vec<Str
         error!("failed to get project metadata");
     };
 
+    let job_params: JobParams = serde_json::from_value(project_meta.params.clone())
+        .unwrap_or_else(|e| error!("failed to parse job params: {}", e));
+
     // create Input objects
     let bpe = cl100k_base().unwrap();
     let mut new_inputs: Vec<Inputs> = Vec::new();
     for (record_id, input) in record_ids.into_iter().zip(inputs.into_iter()) {
-        let token_estimate = bpe.encode_with_special_tokens(&input).len() as i32;
+        let token_estimate = estimate_tokens(&bpe, &job_params, &input);
         new_inputs.push(Inputs {
             record_id,
             inputs: input.trim().to_owned(),
@@ -41,24 +48,21 @@ fn _handle_table_update(job_name: &str, record_ids: Vec<String>, inputs: Vec<Str
         job_name: job_name.to_string(),
         job_meta: project_meta,
         inputs: new_inputs,
+        semantic_chunking: false,
     };
 
     // send the job message to the queue
     let query = "select pgmq.send($1, $2::jsonb);";
-    let _ran: Result<_, spi::Error> = Spi::connect(|mut c| {
-        let _r = c.update(
+    let _ran: Result<_, spi::Error> = compat::connect(|mut c| {
+        let _r = compat::update(
+            &mut c,
             query,
-            None,
-            Some(vec![
-                (PgBuiltInOids::TEXTOID.oid(), VECTORIZE_QUEUE.into_datum()),
-                (
-                    PgBuiltInOids::JSONBOID.oid(),
-                    pgrx::JsonB(
-                        serde_json::to_value(job_message).expect("failed parsing job message"),
-                    )
-                    .into_datum(),
-                ),
-            ]),
+            vec![
+                arg(VECTORIZE_QUEUE),
+                arg(pgrx::JsonB(
+                    serde_json::to_value(job_message).expect("failed parsing job message"),
+                )),
+            ],
         )?;
         Ok(())
     });
@@ -67,9 +71,32 @@ fn _handle_table_update(job_name: &str, record_ids: Vec<String>, inputs: Vec<Str
 static TRIGGER_FN_PREFIX: &str = "vectorize.handle_update_";
 
 /// creates a function that can be called by trigger
-pub fn create_trigger_handler(job_name: &str, input_columns: &[String], pkey: &str) -> String {
-    let input_cols = input_columns.join(", ");
-    let select_cols = generate_select_cols(input_columns);
+/// encrypted inputs are enqueued without input text, for the worker to decrypt
+/// updates only enqueue rows whose input columns changed, so that one job writing to a table
+/// does not re-trigger the other jobs on the same table
+pub fn create_trigger_handler(job_name: &str, job_params: &JobParams) -> String {
+    let pkey = job_params.pkey();
+    let input_columns: &[String] = match job_params.decryption {
+        Some(_) => &[],
+        None => &job_params.columns,
+    };
+    let record_id = pkey.record_id(Some("n"));
+    let (input_cols, select_cols) = match job_params.templated_input_text("n") {
+        Some(text) => (
+            format!(", {text} AS input_text"),
+            "r.input_text".to_string(),
+        ),
+        None => (
+            input_columns.iter().map(|c| format!(", n.{c}")).collect(),
+            generate_select_cols(job_params, input_columns),
+        ),
+    };
+    let changed = job_params
+        .columns
+        .iter()
+        .map(|c| format!("n.{c} IS DISTINCT FROM o.{c}"))
+        .collect::<Vec<String>>()
+        .join(" OR ");
     format!(
         "
 CREATE OR REPLACE FUNCTION {TRIGGER_FN_PREFIX}{job_name}()
@@ -79,10 +106,23 @@ DECLARE
     inputs_array TEXT[] := ARRAY[]::TEXT[];
     r RECORD;
 BEGIN
-    FOR r IN SELECT {pkey} as pkey, {input_cols} FROM new_table LOOP
-    record_id_array := array_append(record_id_array, r.pkey::text);
-        inputs_array := array_append(inputs_array, {select_cols} );
-    END LOOP;
+    IF TG_OP = 'UPDATE' THEN
+        FOR r IN SELECT {record_id} as pkey{input_cols}
+        FROM new_table n
+        INNER JOIN old_table o ON {join_on}
+        WHERE {changed} LOOP
+        record_id_array := array_append(record_id_array, r.pkey::text);
+            inputs_array := array_append(inputs_array, {select_cols} );
+        END LOOP;
+    ELSE
+        FOR r IN SELECT {record_id} as pkey{input_cols} FROM new_table n LOOP
+        record_id_array := array_append(record_id_array, r.pkey::text);
+            inputs_array := array_append(inputs_array, {select_cols} );
+        END LOOP;
+    END IF;
+    IF array_length(record_id_array, 1) IS NULL THEN
+        RETURN NULL;
+    END IF;
     PERFORM vectorize._handle_table_update(
         '{job_name}',
         record_id_array::TEXT[],
@@ -91,7 +131,8 @@ BEGIN
     RETURN NULL;
 END;
 $$ LANGUAGE plpgsql;    
-"
+",
+        join_on = pkey.join_on("n", "o"),
     )
 }
 
@@ -99,24 +140,58 @@ $$ LANGUAGE plpgsql;
 // these triggers use transition tables
 // transition tables cannot be specified for triggers with more than one event
 // so we create two triggers instead
+// update triggers also capture the old rows, to skip rows whose inputs did not change
 pub fn create_event_trigger(job_name: &str, schema: &str, table_name: &str, event: &str) -> String {
+    let transition_tables = match event {
+        "UPDATE" => "NEW TABLE AS new_table OLD TABLE AS old_table",
+        _ => "NEW TABLE AS new_table",
+    };
     format!(
         "
 CREATE OR REPLACE TRIGGER vectorize_{event_name}_trigger_{job_name}
 AFTER {event} ON {schema}.{table_name}
-REFERENCING NEW TABLE AS new_table
+REFERENCING {transition_tables}
 FOR EACH STATEMENT
 EXECUTE FUNCTION vectorize.handle_update_{job_name}();",
         event_name = event.to_lowercase()
     )
 }
 
-fn generate_select_cols(inputs: &[String]) -> String {
-    inputs
-        .iter()
-        .map(|item| format!("r.{item}"))
-        .collect::<Vec<String>>()
-        .join("|| ' ' ||")
+/// statements creating a realtime job's trigger handler, and its insert and update triggers
+/// statement triggers on a partitioned table only fire for statements naming it
+/// so writes made directly to a partition are captured by triggers on each partition
+pub fn realtime_trigger_queries(
+    job_name: &str,
+    job_params: &JobParams,
+    partitioning: Option<&Partitioning>,
+) -> Vec<String> {
+    let mut trigger_tables = vec![(job_params.schema.clone(), job_params.table.clone())];
+    if let Some(p) = partitioning {
+        trigger_tables.extend(p.leaves.iter().cloned());
+    }
+    let mut queries = vec![create_trigger_handler(job_name, job_params)];
+    for (trigger_schema, trigger_table) in &trigger_tables {
+        for event in ["INSERT", "UPDATE"] {
+            queries.push(create_event_trigger(
+                job_name,
+                trigger_schema,
+                trigger_table,
+                event,
+            ));
+        }
+    }
+    queries
+}
+
+fn generate_select_cols(job_params: &JobParams, inputs: &[String]) -> String {
+    if inputs.is_empty() {
+        return ENCRYPTED_INPUT_PLACEHOLDER.to_string();
+    }
+    let cols: Vec<String> = inputs.iter().map(|item| format!("r.{item}")).collect();
+    if job_params.is_weighted() {
+        return job_params.weighted_input_text(&cols);
+    }
+    cols.join("|| ' ' ||")
 }
 
 // creates batches of embedding jobs
@@ -132,15 +207,37 @@ pub fn initalize_table_job(
         TableMethod::append => new_rows_query(job_name, job_params),
         TableMethod::join => new_rows_query_join(job_name, job_params),
     };
+    let vectorize_meta = VectorizeMeta {
+        name: job_name.to_string(),
+        // TODO: in future, lookup job id once this gets put into use
+        // job_id is currently not used, job_name is unique
+        job_id: 0,
+        params: serde_json::to_value(job_params.clone()).unwrap(),
+        index_dist_type: index_dist_type.clone(),
+        transformer: transformer.clone(),
+        last_completion: None,
+    };
+    enqueue_rows(&vectorize_meta, job_params, &rows_need_update_query)?;
+    Ok(())
+}
+
+/// queues embedding jobs for the rows returned by a query, in batches sized by vectorize.batch_size
+/// returns the number of rows queued
+pub fn enqueue_rows(
+    vectorize_meta: &VectorizeMeta,
+    job_params: &JobParams,
+    rows_need_update_query: &str,
+) -> Result<i64> {
+    let job_name = vectorize_meta.name.as_str();
     let mut inputs: Vec<Inputs> = Vec::new();
     let bpe = cl100k_base().unwrap();
-    let _: Result<_, spi::Error> = Spi::connect(|c| {
-        let rows = c.select(&rows_need_update_query, None, None)?;
+    let _: Result<_, spi::Error> = compat::connect(|c| {
+        let rows = compat::select(&c, rows_need_update_query, vec![])?;
         for row in rows {
             let ipt = row["input_text"]
                 .value::<String>()?
                 .expect("input_text is null");
-            let token_estimate = bpe.encode_with_special_tokens(&ipt).len() as i32;
+            let token_estimate = estimate_tokens(&bpe, job_params, &ipt);
             inputs.push(Inputs {
                 record_id: row["record_id"]
                     .value::<String>()?
@@ -152,44 +249,32 @@ pub fn initalize_table_job(
         Ok(())
     });
 
-    let max_batch_size = BATCH_SIZE.get();
+    let max_batch_size = job_params.batch_size.unwrap_or_else(|| BATCH_SIZE.get());
+    let num_rows = inputs.len() as i64;
     let batches = create_batches(inputs, max_batch_size);
-    let vectorize_meta = VectorizeMeta {
-        name: job_name.to_string(),
-        // TODO: in future, lookup job id once this gets put into use
-        // job_id is currently not used, job_name is unique
-        job_id: 0,
-        params: serde_json::to_value(job_params.clone()).unwrap(),
-        index_dist_type: index_dist_type.clone(),
-        transformer: transformer.clone(),
-        last_completion: None,
-    };
     for b in batches {
         let job_message = JobMessage {
             job_name: job_name.to_string(),
             job_meta: vectorize_meta.clone(),
             inputs: b,
+            semantic_chunking: false,
         };
         let query = "select pgmq.send($1, $2::jsonb);";
-        let _ran: Result<_, spi::Error> = Spi::connect(|mut c| {
-            let _r = c.update(
+        let _ran: Result<_, spi::Error> = compat::connect(|mut c| {
+            let _r = compat::update(
+                &mut c,
                 query,
-                None,
-                Some(vec![
-                    (PgBuiltInOids::TEXTOID.oid(), VECTORIZE_QUEUE.into_datum()),
-                    (
-                        PgBuiltInOids::JSONBOID.oid(),
-                        pgrx::JsonB(
-                            serde_json::to_value(job_message).expect("failed parsing job message"),
-                        )
-                        .into_datum(),
-                    ),
-                ]),
+                vec![
+                    arg(VECTORIZE_QUEUE),
+                    arg(pgrx::JsonB(
+                        serde_json::to_value(job_message).expect("failed parsing job message"),
+                    )),
+                ],
             )?;
             Ok(())
         });
     }
-    Ok(())
+    Ok(num_rows)
 }
 
 #[cfg(test)]
@@ -205,7 +290,7 @@ mod tests {
             "
 CREATE OR REPLACE TRIGGER vectorize_update_trigger_another_job
 AFTER UPDATE ON myschema.another_table
-REFERENCING NEW TABLE AS new_table
+REFERENCING NEW TABLE AS new_table OLD TABLE AS old_table
 FOR EACH STATEMENT
 EXECUTE FUNCTION vectorize.handle_update_another_job();"
         );
@@ -229,4 +314,36 @@ EXECUTE FUNCTION vectorize.handle_update_another_job();"
         let result = create_event_trigger(job_name, "myschema", table_name, "INSERT");
         assert_eq!(expected, result);
     }
+
+    #[test]
+    fn test_trigger_handler_skips_unchanged_inputs() {
+        let job_params = JobParams {
+            columns: vec!["product_name".to_string(), "description".to_string()],
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            ..Default::default()
+        };
+        let handler = create_trigger_handler("my_job", &job_params);
+        assert!(handler.contains("INNER JOIN old_table o ON n.product_id = o.product_id"));
+        assert!(handler.contains(
+            "WHERE n.product_name IS DISTINCT FROM o.product_name OR n.description IS DISTINCT FROM o.description"
+        ));
+        assert!(handler.contains("PERFORM vectorize._handle_table_update(\n        'my_job'"));
+    }
+
+    #[test]
+    fn test_trigger_handler_input_template() {
+        let job_params = JobParams {
+            columns: vec!["question".to_string(), "answer".to_string()],
+            primary_key: "faq_id".to_string(),
+            pkey_type: "integer".to_string(),
+            input_template: Some("format('Q: %s A: %s', question, answer)".to_string()),
+            ..Default::default()
+        };
+        let handler = create_trigger_handler("my_job", &job_params);
+        assert!(handler.contains(
+            "SELECT n.faq_id::text as pkey, (SELECT (format('Q: %s A: %s', question, answer))::text FROM (SELECT n.*) input_row) AS input_text FROM new_table n"
+        ));
+        assert!(handler.contains("inputs_array := array_append(inputs_array, r.input_text );"));
+    }
 }
@@ -1,16 +1,38 @@
 use pgrx::prelude::*;
 
+mod alter;
 mod api;
+mod api_key;
+mod arithmetic;
+mod benchmark;
+mod budget;
 mod chat;
+mod chunking;
+mod collection;
+mod compat;
+mod credential;
 mod executor;
+mod export;
 mod guc;
+mod health;
 mod init;
 mod job;
+mod migrations;
+mod model_migration;
+mod provenance;
+mod quantize;
 mod query;
+mod rate_limit;
+mod registry;
+mod reindex;
+mod rerank;
 mod search;
 mod transformers;
+mod ttl;
 mod types;
+mod usage;
 mod util;
+mod warm;
 pub mod workers;
 
 pgrx::pg_module_magic!();
@@ -0,0 +1,413 @@
+use crate::compat::{self, arg};
+use crate::init;
+use crate::util;
+
+use anyhow::Result;
+use pgrx::prelude::*;
+use vectorize_core::types::JobParams;
+
+// changes to vectorize's own tables are made here rather than in the upgrade scripts
+// each migration runs once, in order, when the extension is created or updated
+// and is recorded in vectorize.migrations, so an upgrade that skips versions still applies all of them
+// sql/meta.sql always has the latest schema, so statements must be safe to run against it again
+enum Step {
+    Sql(&'static str),
+    Rust(fn() -> Result<()>),
+}
+
+struct Migration {
+    version: i32,
+    description: &'static str,
+    steps: &'static [Step],
+}
+
+static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "pause jobs",
+        steps: &[Step::Sql(
+            "ALTER TABLE vectorize.job ADD COLUMN IF NOT EXISTS paused BOOLEAN NOT NULL DEFAULT false",
+        )],
+    },
+    Migration {
+        version: 2,
+        description: "track refresh progress",
+        steps: &[Step::Sql(
+            "ALTER TABLE vectorize.job ADD COLUMN IF NOT EXISTS refreshed_at TIMESTAMP WITH TIME ZONE",
+        )],
+    },
+    Migration {
+        version: 3,
+        description: "rag batches",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vectorize.rag_batch (
+                batch_id bigserial,
+                name TEXT NOT NULL UNIQUE,
+                params jsonb NOT NULL,
+                status TEXT NOT NULL DEFAULT 'running',
+                created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+                completed_at TIMESTAMP WITH TIME ZONE
+            )",
+        )],
+    },
+    Migration {
+        version: 4,
+        description: "token budgets",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vectorize.budget (
+                scope TEXT NOT NULL CHECK (scope IN ('job', 'provider')),
+                name TEXT NOT NULL,
+                token_limit BIGINT NOT NULL,
+                period INTERVAL NOT NULL DEFAULT '1 day',
+                window_start TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+                tokens_used BIGINT NOT NULL DEFAULT 0,
+                PRIMARY KEY (scope, name)
+            )",
+        )],
+    },
+    Migration {
+        version: 5,
+        description: "fill in defaults for job params",
+        steps: &[Step::Rust(rewrite_job_params)],
+    },
+    Migration {
+        version: 6,
+        description: "record embedding dimensions",
+        steps: &[Step::Rust(record_dimensions)],
+    },
+    Migration {
+        version: 7,
+        description: "benchmark results",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vectorize.benchmark (
+                benchmark_id bigserial PRIMARY KEY,
+                job_name TEXT NOT NULL,
+                mode TEXT NOT NULL,
+                queries INT NOT NULL,
+                concurrency INT NOT NULL,
+                num_results INT NOT NULL,
+                errors INT NOT NULL,
+                p50_ms FLOAT8 NOT NULL,
+                p95_ms FLOAT8 NOT NULL,
+                p99_ms FLOAT8 NOT NULL,
+                qps FLOAT8 NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+            )",
+        )],
+    },
+    Migration {
+        version: 8,
+        description: "embedding provenance",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vectorize.embedding_provenance (
+                job_name TEXT NOT NULL,
+                record_id TEXT NOT NULL,
+                model TEXT NOT NULL,
+                provider TEXT NOT NULL,
+                endpoint TEXT NOT NULL,
+                content_hash TEXT NOT NULL,
+                trace_id TEXT NOT NULL,
+                vectorize_version TEXT,
+                embedded_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+                PRIMARY KEY (job_name, record_id)
+            )",
+        )],
+    },
+    Migration {
+        version: 9,
+        description: "chunk deduplication stats",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vectorize.job_stats (
+                job_name TEXT PRIMARY KEY,
+                embedded_chunks BIGINT NOT NULL DEFAULT 0,
+                deduplicated_chunks BIGINT NOT NULL DEFAULT 0,
+                tokens_saved BIGINT NOT NULL DEFAULT 0,
+                updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+            )",
+        )],
+    },
+    Migration {
+        version: 10,
+        description: "model registry",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vectorize.models (
+                name TEXT PRIMARY KEY,
+                source TEXT NOT NULL,
+                dimensions INT,
+                endpoint TEXT,
+                options jsonb NOT NULL DEFAULT '{}',
+                created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+            )",
+        )],
+    },
+    Migration {
+        version: 11,
+        description: "provider rate limits",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vectorize.rate_limit (
+                provider TEXT PRIMARY KEY,
+                window_start TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+                requests BIGINT NOT NULL DEFAULT 0,
+                tokens BIGINT NOT NULL DEFAULT 0
+            )",
+        )],
+    },
+    Migration {
+        version: 12,
+        description: "embedding cache",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vectorize.embedding_cache (
+                model TEXT NOT NULL,
+                content_hash TEXT NOT NULL,
+                embeddings float8[] NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+                PRIMARY KEY (model, content_hash)
+            )",
+        )],
+    },
+    Migration {
+        version: 13,
+        description: "usage accounting",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS vectorize.usage (
+                    job_name TEXT NOT NULL,
+                    day DATE NOT NULL,
+                    provider TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    calls BIGINT NOT NULL DEFAULT 0,
+                    tokens_in BIGINT NOT NULL DEFAULT 0,
+                    tokens_out BIGINT NOT NULL DEFAULT 0,
+                    cost FLOAT8,
+                    PRIMARY KEY (job_name, day, model)
+                )",
+            ),
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS vectorize.model_prices (
+                    model TEXT PRIMARY KEY,
+                    input_price FLOAT8 NOT NULL,
+                    output_price FLOAT8 NOT NULL DEFAULT 0
+                )",
+            ),
+            Step::Sql(
+                "INSERT INTO vectorize.model_prices (model, input_price, output_price) VALUES
+                    ('openai/text-embedding-3-small', 0.02, 0),
+                    ('openai/text-embedding-3-large', 0.13, 0),
+                    ('openai/text-embedding-ada-002', 0.10, 0),
+                    ('openai/gpt-4o-mini', 0.15, 0.60),
+                    ('openai/gpt-4o', 2.50, 10.00)
+                ON CONFLICT (model) DO NOTHING",
+            ),
+        ],
+    },
+    Migration {
+        version: 14,
+        description: "job credentials",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS vectorize.credentials (
+                    name TEXT PRIMARY KEY,
+                    source TEXT NOT NULL,
+                    api_key TEXT,
+                    virtual_key TEXT,
+                    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+                )",
+            ),
+            // new tables of the schema are readable by pg_monitor by default
+            Step::Sql("REVOKE ALL ON vectorize.credentials FROM PUBLIC, pg_monitor"),
+        ],
+    },
+    Migration {
+        version: 15,
+        description: "provider health",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vectorize.provider_health (
+                provider TEXT PRIMARY KEY,
+                reachable BOOLEAN NOT NULL,
+                latency_ms DOUBLE PRECISION NOT NULL,
+                dimensions INT,
+                error TEXT,
+                checked_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+            )",
+        )],
+    },
+    Migration {
+        version: 16,
+        description: "chat history",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS vectorize.chat_history (
+                    id BIGSERIAL PRIMARY KEY,
+                    session_id TEXT NOT NULL,
+                    agent_name TEXT NOT NULL,
+                    role TEXT NOT NULL,
+                    content TEXT NOT NULL,
+                    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+                )",
+            ),
+            Step::Sql(
+                "CREATE INDEX IF NOT EXISTS chat_history_session_idx
+                ON vectorize.chat_history (session_id, id)",
+            ),
+        ],
+    },
+    Migration {
+        version: 17,
+        description: "rag batch failures",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vectorize.rag_batch_failures (
+                batch_name TEXT NOT NULL,
+                question_id TEXT NOT NULL,
+                attempts INT NOT NULL DEFAULT 1,
+                error TEXT NOT NULL,
+                failed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+                PRIMARY KEY (batch_name, question_id)
+            )",
+        )],
+    },
+    Migration {
+        version: 18,
+        description: "embedding provenance privileges",
+        steps: &[Step::Sql(
+            "REVOKE ALL ON vectorize.embedding_provenance FROM PUBLIC, pg_monitor",
+        )],
+    },
+    Migration {
+        version: 19,
+        description: "embedding cache privileges",
+        steps: &[Step::Sql(
+            "REVOKE ALL ON vectorize.embedding_cache FROM PUBLIC, pg_monitor",
+        )],
+    },
+    Migration {
+        version: 20,
+        description: "chat history owners",
+        steps: &[
+            Step::Sql(
+                "ALTER TABLE vectorize.chat_history
+                ADD COLUMN IF NOT EXISTS owner TEXT NOT NULL DEFAULT current_user",
+            ),
+            Step::Sql("REVOKE ALL ON vectorize.chat_history FROM PUBLIC, pg_monitor"),
+        ],
+    },
+    Migration {
+        version: 21,
+        description: "model options privileges",
+        steps: &[
+            Step::Sql("REVOKE SELECT ON vectorize.models FROM pg_monitor"),
+            Step::Sql(
+                "GRANT SELECT (name, source, dimensions, endpoint, created_at)
+                ON vectorize.models TO pg_monitor",
+            ),
+        ],
+    },
+];
+
+fn all_job_params() -> Result<Vec<(String, pgrx::JsonB)>> {
+    Ok(compat::connect(|client| {
+        let mut jobs = Vec::new();
+        let tup_table = compat::select(&client, "SELECT name, params FROM vectorize.job", vec![])?;
+        for row in tup_table {
+            if let (Some(name), Some(params)) = (row["name"].value()?, row["params"].value()?) {
+                jobs.push((name, params));
+            }
+        }
+        Ok::<_, spi::Error>(jobs)
+    })?)
+}
+
+// re-serializes every job's params, so that they carry the current defaults
+// params that no longer deserialize are left as they are
+fn rewrite_job_params() -> Result<()> {
+    for (name, params) in all_job_params()? {
+        let job_params: JobParams = match serde_json::from_value(params.0) {
+            Ok(p) => p,
+            Err(e) => {
+                warning!("pg-vectorize: could not migrate params of job {name}: {e}");
+                continue;
+            }
+        };
+        util::update_job_params(&name, &job_params)?;
+    }
+    Ok(())
+}
+
+// jobs created before dimensions were recorded get them from the type of their embeddings column
+// pgvector keeps the dimensions of a vector column as its type modifier
+fn record_dimensions() -> Result<()> {
+    for (name, params) in all_job_params()? {
+        let mut job_params: JobParams = match serde_json::from_value(params.0) {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        if job_params.dimensions.is_some() {
+            continue;
+        }
+        let (schema, table, column) = init::embeddings_location(&name, &job_params);
+        let dimensions: Option<i32> = compat::get_one(
+            "SELECT (
+                SELECT atttypmod FROM pg_attribute
+                WHERE attrelid = to_regclass(format('%I.%I', $1, $2))
+                AND attname = $3
+                AND atttypmod > 0
+            )",
+            vec![arg(schema), arg(table), arg(column)],
+        )?;
+        if let Some(dimensions) = dimensions {
+            job_params.dimensions = Some(dimensions as u32);
+            util::update_job_params(&name, &job_params)?;
+        }
+    }
+    Ok(())
+}
+
+/// applies the migrations that have not been applied yet
+/// called at the end of the install script and of every upgrade script
+/// returns the number of migrations applied
+#[pg_extern]
+fn _migrate() -> Result<i64> {
+    let applied: i32 = compat::get_one(
+        "SELECT COALESCE(max(version), 0) FROM vectorize.migrations",
+        vec![],
+    )?
+    .unwrap_or(0);
+    let mut num_applied = 0;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > applied) {
+        for step in migration.steps {
+            match step {
+                Step::Sql(query) => compat::run(query, vec![])?,
+                Step::Rust(f) => f()?,
+            }
+        }
+        compat::run(
+            "INSERT INTO vectorize.migrations (version, description) VALUES ($1, $2)",
+            vec![arg(migration.version), arg(migration.description)],
+        )?;
+        log!(
+            "pg-vectorize: applied migration {}: {}",
+            migration.version,
+            migration.description
+        );
+        num_applied += 1;
+    }
+    Ok(num_applied)
+}
+
+extension_sql!(
+    "SELECT vectorize._migrate();",
+    name = "run_migrations",
+    finalize
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migration_versions() {
+        // versions are applied in order and must never be reused
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as i32 + 1);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::compat::{self, arg};
+use crate::init::{self, VECTORIZE_QUEUE};
+use crate::registry;
+use crate::reindex;
+use crate::search;
+use crate::util;
+
+use anyhow::{anyhow, bail, Result};
+use pgrx::prelude::*;
+use vectorize_core::types::Model;
+
+/// name of the job embedding a job's rows with its new model while the model is migrated
+/// it is searchable under this name until it replaces the job
+pub fn migration_job_name(job_name: &str) -> String {
+    format!("{job_name}_next")
+}
+
+/// name of the pg_cron job that swaps in the new model once its embeddings are complete
+pub fn migration_cron_name(job_name: &str) -> String {
+    format!("vectorize_migrate_{job_name}")
+}
+
+/// true while a job's model is being migrated
+pub fn is_migrating(job_name: &str) -> Result<bool> {
+    Ok(compat::get_one(
+        "SELECT EXISTS (SELECT 1 FROM cron.job WHERE jobname = $1)",
+        vec![arg(migration_cron_name(job_name))],
+    )?
+    .unwrap_or(false))
+}
+
+/// moves a job to a new model without downtime
+/// a parallel job embeds the rows with the new model in the background while searches keep using the old one,
+/// and _migrate_model_finalize replaces the job with it, under the job's name, once every row is embedded
+pub fn migrate_model(job_name: &str, new_model: &str) -> Result<String> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let transformer = registry::resolve(new_model)?;
+    if transformer.to_string() == meta.transformer.to_string() {
+        bail!("job {job_name} already uses {transformer}");
+    }
+    if is_migrating(job_name)? {
+        bail!("a model migration is already in progress for job: {job_name}");
+    }
+    let next = migration_job_name(job_name);
+    init::validate_job_name(&next)
+        .map_err(|_| anyhow!("job {job_name} can not be migrated, job name is too long: {next}"))?;
+
+    // realtime triggers or the schedule of the new job write its embeddings alongside the old ones
+    search::init_job_like(&next, meta, &transformer)?;
+    compat::run(
+        "SELECT cron.schedule($1, '* * * * *', $2)",
+        vec![
+            arg(migration_cron_name(job_name)),
+            arg(format!(
+                "SELECT vectorize._migrate_model_finalize('{job_name}')"
+            )),
+        ],
+    )?;
+    Ok(format!(
+        "Migrating job {job_name} to {transformer}, its new embeddings can be searched as job {next}"
+    ))
+}
+
+// true once every row of a job has been embedded, and none of its rows are waiting in the queue
+fn is_backfilled(job_name: &str) -> Result<bool> {
+    let (total_rows, completed_rows) = search::refresh_progress(job_name)?;
+    let queued: bool = compat::get_one(
+        &format!(
+            "SELECT EXISTS (SELECT 1 FROM pgmq.q_{VECTORIZE_QUEUE} WHERE message->>'job_name' = $1)"
+        ),
+        vec![arg(job_name)],
+    )?
+    .unwrap_or(false);
+    Ok(completed_rows >= total_rows && !queued)
+}
+
+/// called by pg_cron while a job's model is being migrated
+/// once the new model has embedded every row, drops the job and renames the new job in its place,
+/// in the same transaction so that searches move from one model to the other at once
+#[pg_extern]
+fn _migrate_model_finalize(job_name: &str) -> Result<()> {
+    let next = migration_job_name(job_name);
+    let jobs: i64 = compat::get_one(
+        "SELECT count(*) FROM vectorize.job WHERE name IN ($1, $2)",
+        vec![arg(job_name), arg(next.as_str())],
+    )?
+    .unwrap_or(0);
+    if jobs == 2 {
+        if !is_backfilled(&next)? || reindex::is_reindexing(&next)? {
+            return Ok(());
+        }
+        search::drop_job(job_name)?;
+        search::rename_job(&next, job_name)?;
+        log!("pg-vectorize: job {job_name} migrated to its new model");
+    } else {
+        // the migration was cancelled by dropping or renaming the new job
+        warning!("pg-vectorize: model migration of job {job_name} was cancelled");
+    }
+    compat::run(
+        "SELECT cron.unschedule(jobid) FROM cron.job WHERE jobname = $1",
+        vec![arg(migration_cron_name(job_name))],
+    )?;
+    Ok(())
+}
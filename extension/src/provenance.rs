@@ -0,0 +1,71 @@
+use crate::compat::{self, arg};
+use crate::util;
+
+use anyhow::{anyhow, Result};
+use pgrx::prelude::*;
+use vectorize_core::types::{JobParams, PrimaryKey};
+
+/// where a record's current embeddings came from, as recorded by the worker that wrote them
+pub struct Provenance {
+    pub model: String,
+    pub provider: String,
+    pub endpoint: String,
+    pub content_hash: String,
+    pub trace_id: String,
+    pub vectorize_version: Option<String>,
+    pub embedded_at: TimestampWithTimeZone,
+}
+
+/// the provenance of a record's embeddings, or None when the record has not been embedded since it was recorded
+pub fn provenance(job_name: &str, record_id: &str) -> Result<Option<Provenance>> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: JobParams = serde_json::from_value(meta.params)?;
+    let query = provenance_query(&job_params.pkey());
+    compat::connect(|client| {
+        let tup_table = compat::select(&client, &query, vec![arg(job_name), arg(record_id)])?;
+        let row = match tup_table.into_iter().next() {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        Ok(Some(Provenance {
+            model: row["model"].value()?.unwrap_or_default(),
+            provider: row["provider"].value()?.unwrap_or_default(),
+            endpoint: row["endpoint"].value()?.unwrap_or_default(),
+            content_hash: row["content_hash"].value()?.unwrap_or_default(),
+            trace_id: row["trace_id"].value()?.unwrap_or_default(),
+            vectorize_version: row["vectorize_version"].value()?,
+            embedded_at: row["embedded_at"]
+                .value()?
+                .ok_or_else(|| anyhow!("provenance of {record_id} has no embedded_at"))?,
+        }))
+    })
+}
+
+// the job name is bound to $1 and the record id to $2
+// record ids of composite keys are json arrays, which are matched in their canonical form
+fn provenance_query(pkey: &PrimaryKey) -> String {
+    let record_id = if pkey.is_composite() {
+        "$2::jsonb::text"
+    } else {
+        "$2"
+    };
+    format!(
+        "SELECT model, provider, endpoint, content_hash, trace_id, vectorize_version, embedded_at
+        FROM vectorize.embedding_provenance
+        WHERE job_name = $1 AND record_id = {record_id}"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provenance_query() {
+        let query = provenance_query(&PrimaryKey::new("id", "text"));
+        assert!(query.contains("WHERE job_name = $1 AND record_id = $2"));
+        // a composite record id may be written with different spacing than the stored one
+        let query = provenance_query(&PrimaryKey::new("tenant_id,id", "integer,integer"));
+        assert!(query.contains("AND record_id = $2::jsonb::text"));
+    }
+}
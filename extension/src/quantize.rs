@@ -0,0 +1,67 @@
+use anyhow::{bail, Result};
+use pgrx::prelude::*;
+
+// the scale factor, a little endian float4, is stored ahead of the quantized dimensions
+const SCALE_BYTES: usize = 4;
+
+/// int8 scalar quantization of an embedding, for jobs with int8 storage
+/// each dimension is stored as a signed byte, scaled so that the largest magnitude maps to 127
+#[pg_extern(immutable, parallel_safe)]
+fn _quantize_int8(embedding: Vec<f32>) -> Vec<u8> {
+    quantize_int8(&embedding)
+}
+
+/// the embedding an int8 quantized embedding approximates
+#[pg_extern(immutable, parallel_safe)]
+fn _dequantize_int8(quantized: &[u8]) -> Result<Vec<f32>> {
+    dequantize_int8(quantized)
+}
+
+fn quantize_int8(embedding: &[f32]) -> Vec<u8> {
+    let max = embedding.iter().fold(0.0_f32, |m, x| m.max(x.abs()));
+    let scale = max / i8::MAX as f32;
+    let mut quantized = Vec::with_capacity(SCALE_BYTES + embedding.len());
+    quantized.extend_from_slice(&scale.to_le_bytes());
+    quantized.extend(embedding.iter().map(|x| {
+        let q = if scale > 0.0 {
+            (x / scale).round()
+        } else {
+            0.0
+        };
+        q.clamp(-(i8::MAX as f32), i8::MAX as f32) as i8 as u8
+    }));
+    quantized
+}
+
+fn dequantize_int8(quantized: &[u8]) -> Result<Vec<f32>> {
+    if quantized.len() < SCALE_BYTES {
+        bail!("invalid int8 embedding, it is missing its scale factor");
+    }
+    let (scale, dims) = quantized.split_at(SCALE_BYTES);
+    let scale = f32::from_le_bytes(scale.try_into()?);
+    Ok(dims.iter().map(|q| *q as i8 as f32 * scale).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantize_int8() {
+        let embedding = vec![0.5, -0.25, 0.1, -0.5, 0.0];
+        let quantized = quantize_int8(&embedding);
+        assert_eq!(quantized.len(), 4 + embedding.len());
+        // the largest magnitude is kept exactly, the rest to within half a step
+        let restored = dequantize_int8(&quantized).unwrap();
+        assert_eq!(restored[0], 0.5);
+        assert_eq!(restored[3], -0.5);
+        let step = 0.5 / 127.0;
+        for (x, r) in embedding.iter().zip(&restored) {
+            assert!((x - r).abs() <= step / 2.0 + f32::EPSILON);
+        }
+
+        let zeros = dequantize_int8(&quantize_int8(&[0.0, 0.0])).unwrap();
+        assert_eq!(zeros, vec![0.0, 0.0]);
+        assert!(dequantize_int8(&[1, 2]).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use crate::compat;
+use crate::guc;
+
+use anyhow::Result;
+use pgrx::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use vectorize_core::worker::rate_limit::parse_limits;
+
+/// the requests and tokens a provider has been sent in its current minute, and its limits
+pub struct RateLimitUsage {
+    pub provider: String,
+    pub requests_per_minute: Option<i64>,
+    pub requests_used: i64,
+    pub tokens_per_minute: Option<i64>,
+    pub tokens_used: i64,
+    // None when nothing has been sent to the provider in the last minute
+    pub resets_at: Option<TimestampWithTimeZone>,
+}
+
+// the requests, tokens and end of the current minute of providers that have been sent messages in the last minute
+fn current_usage() -> Result<HashMap<String, (i64, i64, Option<TimestampWithTimeZone>)>> {
+    Ok(compat::connect(|client| {
+        let tup_table = compat::select(
+            &client,
+            "SELECT provider, requests, tokens, window_start + interval '1 minute' AS resets_at
+            FROM vectorize.rate_limit
+            WHERE now() < window_start + interval '1 minute'",
+            vec![],
+        )?;
+        let mut used = HashMap::new();
+        for row in tup_table {
+            let provider: String = row["provider"].value()?.unwrap_or_default();
+            let requests: i64 = row["requests"].value()?.unwrap_or_default();
+            let tokens: i64 = row["tokens"].value()?.unwrap_or_default();
+            used.insert(provider, (requests, tokens, row["resets_at"].value()?));
+        }
+        Ok::<_, spi::Error>(used)
+    })?)
+}
+
+/// the usage of every provider limited by vectorize.requests_per_minute or vectorize.tokens_per_minute
+pub fn rate_limits() -> Result<Vec<RateLimitUsage>> {
+    let requests_per_minute =
+        parse_limits(guc::get_guc(guc::VectorizeGuc::RequestsPerMinute).as_deref())?;
+    let tokens_per_minute =
+        parse_limits(guc::get_guc(guc::VectorizeGuc::TokensPerMinute).as_deref())?;
+    let mut used = current_usage()?;
+    let providers: BTreeSet<&String> = requests_per_minute
+        .keys()
+        .chain(tokens_per_minute.keys())
+        .collect();
+    Ok(providers
+        .into_iter()
+        .map(|provider| {
+            let (requests_used, tokens_used, resets_at) = used.remove(provider).unwrap_or_default();
+            RateLimitUsage {
+                provider: provider.clone(),
+                requests_per_minute: requests_per_minute.get(provider).copied(),
+                requests_used,
+                tokens_per_minute: tokens_per_minute.get(provider).copied(),
+                tokens_used,
+                resets_at,
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,108 @@
+use crate::compat::{self, arg};
+
+use anyhow::{bail, Result};
+use pgrx::prelude::*;
+use vectorize_core::types::{Model, ModelSource, Registration};
+
+/// the model of a name, from vectorize.models when it is registered there
+/// and otherwise from its source prefix, e.g. openai/text-embedding-3-small
+pub fn resolve(name: &str) -> Result<Model> {
+    compat::connect(|client| {
+        let tup_table = compat::select(
+            &client,
+            "SELECT source, dimensions, endpoint, options FROM vectorize.models WHERE name = $1",
+            vec![arg(name)],
+        )?;
+        let Some(row) = tup_table.into_iter().next() else {
+            return Ok(Model::new(name)?);
+        };
+        let source: String = row["source"].value()?.unwrap_or_default();
+        let dimensions: Option<i32> = row["dimensions"].value()?;
+        let options: Option<pgrx::JsonB> = row["options"].value()?;
+        Ok(Model::registered(
+            name,
+            parse_source(&source)?,
+            Registration {
+                dimensions: dimensions.map(|d| d as u32),
+                endpoint: row["endpoint"].value()?,
+                options: options.map(|o| o.0).unwrap_or_default(),
+            },
+        ))
+    })
+}
+
+// sources parse leniently, falling back to sentence-transformers, so registrations are held to their exact names
+pub fn parse_source(source: &str) -> Result<ModelSource> {
+    let parsed: ModelSource = source.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+    if parsed.to_string() != source {
+        bail!("invalid model source: {source}");
+    }
+    Ok(parsed)
+}
+
+/// adds a model to vectorize.models, or replaces its registration
+pub fn register_model(
+    name: &str,
+    source: &str,
+    dimensions: Option<i32>,
+    endpoint: Option<&str>,
+    options: serde_json::Value,
+) -> Result<String> {
+    parse_source(source)?;
+    if matches!(dimensions, Some(d) if d < 1) {
+        bail!("dimensions must be greater than 0");
+    }
+    if !options.is_object() {
+        bail!("options must be a json object");
+    }
+    compat::run(
+        "INSERT INTO vectorize.models (name, source, dimensions, endpoint, options)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (name)
+        DO UPDATE SET source = EXCLUDED.source, dimensions = EXCLUDED.dimensions,
+            endpoint = EXCLUDED.endpoint, options = EXCLUDED.options",
+        vec![
+            arg(name),
+            arg(source),
+            arg(dimensions),
+            arg(endpoint),
+            arg(pgrx::JsonB(options)),
+        ],
+    )?;
+    Ok(format!("Registered model {name}"))
+}
+
+/// removes a model from vectorize.models, as long as no job embeds with it
+pub fn unregister_model(name: &str) -> Result<String> {
+    let jobs: Option<String> = compat::get_one(
+        "SELECT string_agg(name, ', ' ORDER BY name) FROM vectorize.job WHERE transformer = $1",
+        vec![arg(name)],
+    )?;
+    if let Some(jobs) = jobs {
+        bail!("model {name} is used by jobs: {jobs}");
+    }
+    let removed: Option<String> = compat::get_one(
+        "DELETE FROM vectorize.models WHERE name = $1 RETURNING name",
+        vec![arg(name)],
+    )?;
+    match removed {
+        Some(_) => Ok(format!("Unregistered model {name}")),
+        None => bail!("model {name} is not registered"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!(
+            parse_source("openai-compatible").unwrap(),
+            ModelSource::OpenAICompatible
+        );
+        assert_eq!(parse_source("azure").unwrap(), ModelSource::AzureOpenAI);
+        assert!(parse_source("acme").is_err());
+        assert!(parse_source("OpenAI").is_err());
+    }
+}
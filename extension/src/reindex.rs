@@ -0,0 +1,279 @@
+use crate::compat::{self, arg};
+use crate::init;
+use crate::util;
+
+use anyhow::{anyhow, bail, Result};
+use pgrx::prelude::*;
+use vectorize_core::types::{IndexDist, IndexParams, JobParams};
+
+// the methods an index type is built with, whose storage parameters can be carried over to one another
+fn same_method(a: &IndexDist, b: &IndexDist) -> bool {
+    (a.is_hnsw() && b.is_hnsw())
+        || (a.is_ivfflat() && b.is_ivfflat())
+        || (a.is_diskann() && b.is_diskann())
+}
+
+// the storage parameters of the current index, from its reloptions, that were not given to the rebuilt index
+// e.g. m = 32 of an hnsw index is kept when it is rebuilt with only a new ef_construction
+fn carry_over_reloptions(mut index_params: IndexParams, reloptions: &[String]) -> IndexParams {
+    for option in reloptions {
+        let Some((name, value)) = option.split_once('=') else {
+            continue;
+        };
+        let number = value.parse::<i32>().ok();
+        match name {
+            "m" => index_params.m = index_params.m.or(number),
+            "ef_construction" => {
+                index_params.ef_construction = index_params.ef_construction.or(number)
+            }
+            "lists" => index_params.lists = index_params.lists.or(number),
+            "num_neighbors" => index_params.num_neighbors = index_params.num_neighbors.or(number),
+            "search_list_size" => {
+                index_params.search_list_size = index_params.search_list_size.or(number)
+            }
+            "num_bits_per_dimension" => {
+                index_params.num_bits_per_dimension = index_params.num_bits_per_dimension.or(number)
+            }
+            "storage_layout" => {
+                index_params.storage_layout = index_params
+                    .storage_layout
+                    .or_else(|| Some(value.to_string()))
+            }
+            _ => {
+                let value = match value {
+                    "true" | "on" => serde_json::json!(true),
+                    "false" | "off" => serde_json::json!(false),
+                    _ => match value.parse::<f64>() {
+                        Ok(n) => serde_json::json!(n),
+                        Err(_) => continue,
+                    },
+                };
+                index_params
+                    .storage_params
+                    .entry(name.to_string())
+                    .or_insert(value);
+            }
+        }
+    }
+    index_params
+}
+
+// the new index is built under a temporary name, and renamed once it replaces the old one
+fn reindex_index_name(job_name: &str) -> String {
+    format!("{job_name}_reindex_idx")
+}
+
+fn build_cron_name(job_name: &str) -> String {
+    format!("vectorize_reindex_{job_name}")
+}
+
+fn finalize_cron_name(job_name: &str) -> String {
+    format!("vectorize_reindex_finalize_{job_name}")
+}
+
+/// names of the pg_cron jobs scheduled while a job's index is being rebuilt
+pub fn reindex_cron_names(job_name: &str) -> Vec<String> {
+    vec![build_cron_name(job_name), finalize_cron_name(job_name)]
+}
+
+/// true while a job's index is being rebuilt
+pub fn is_reindexing(job_name: &str) -> Result<bool> {
+    Ok(compat::get_one(
+        "SELECT EXISTS (SELECT 1 FROM cron.job WHERE jobname = $1)",
+        vec![arg(build_cron_name(job_name))],
+    )?
+    .unwrap_or(false))
+}
+
+/// rebuilds a job's vector index with a new index type and build parameters, keeping its embeddings
+/// CREATE INDEX CONCURRENTLY cannot run inside a function, so the build is handed to pg_cron
+/// and _reindex_finalize swaps the new index in once the build has completed
+pub fn reindex(
+    job_name: &str,
+    index_dist_type: IndexDist,
+    index_params: IndexParams,
+) -> Result<String> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let mut job_params: JobParams = serde_json::from_value(meta.params)?;
+    let (schema, table, _) = init::embeddings_location(job_name, &job_params);
+
+    // the rebuilt index keeps the current index's storage parameters and tablespace, unless they are given
+    let current_index = format!(
+        "{schema}.{}",
+        init::index_name(job_name, &meta.index_dist_type)
+    );
+    let (reloptions, tablespace) = compat::connect(|client| {
+        let mut current = (Vec::new(), None);
+        for row in compat::select(
+            &client,
+            "SELECT c.reloptions::text[] AS reloptions, t.spcname::text AS tablespace
+            FROM pg_class c LEFT JOIN pg_tablespace t ON t.oid = c.reltablespace
+            WHERE c.oid = to_regclass($1)",
+            vec![arg(current_index)],
+        )? {
+            current = (
+                row["reloptions"]
+                    .value::<Vec<String>>()?
+                    .unwrap_or_default(),
+                row["tablespace"].value::<String>()?,
+            );
+        }
+        Ok::<_, spi::Error>(current)
+    })?;
+    let index_params = if same_method(&meta.index_dist_type, &index_dist_type) {
+        carry_over_reloptions(index_params, &reloptions)
+    } else {
+        index_params
+    };
+    if tablespace.is_some() {
+        job_params.storage_params.index_tablespace = tablespace;
+    }
+    job_params.index_params = init::resolve_index_params(
+        &index_dist_type,
+        index_params,
+        &job_params.schema,
+        &job_params.table,
+    )?;
+
+    // indexes on partitioned tables can not be built concurrently
+    if init::get_partitioning(&schema, &table)?.is_some() {
+        bail!("reindex is not supported for partitioned tables: {schema}.{table}");
+    }
+
+    if is_reindexing(job_name)? {
+        bail!("a reindex is already in progress for job: {job_name}");
+    }
+
+    let create_index = init::create_index_query(
+        job_name,
+        &job_params,
+        &index_dist_type,
+        &reindex_index_name(job_name),
+        true,
+    );
+    let finalize = format!(
+        "SELECT vectorize._reindex_finalize('{job_name}', '{index_dist_type}', '{index_params}'::jsonb)",
+        index_params = serde_json::to_string(&job_params.index_params)?,
+    );
+    let schedule = "SELECT cron.schedule($1, '* * * * *', $2)";
+    compat::connect(|mut c| {
+        for (cron_name, command) in [
+            (build_cron_name(job_name), create_index),
+            (finalize_cron_name(job_name), finalize),
+        ] {
+            compat::update(&mut c, schedule, vec![arg(cron_name), arg(command)])?;
+        }
+        Ok::<_, spi::Error>(())
+    })?;
+    Ok(format!(
+        "Rebuilding index for job: {job_name} as {index_dist_type}"
+    ))
+}
+
+/// called by pg_cron while a reindex is in progress
+/// once the concurrent build has produced a valid index, replaces the job's index with it
+#[pg_extern]
+fn _reindex_finalize(
+    job_name: &str,
+    index_dist_type: &str,
+    index_params: pgrx::JsonB,
+) -> Result<()> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let mut job_params: JobParams = serde_json::from_value(meta.params)?;
+    let new_index_dist: IndexDist = index_dist_type.parse().map_err(|e: String| anyhow!(e))?;
+    let (schema, _, _) = init::embeddings_location(job_name, &job_params);
+    let new_index = format!("{schema}.{}", reindex_index_name(job_name));
+
+    // NULL until the build has created the index
+    let valid: Option<bool> = compat::get_one(
+        "SELECT (SELECT indisvalid FROM pg_index WHERE indexrelid = to_regclass($1))",
+        vec![arg(new_index.clone())],
+    )?;
+    match valid {
+        None => return Ok(()),
+        Some(false) => {
+            let building: bool = compat::get_one(
+                "SELECT EXISTS (
+                    SELECT 1 FROM pg_stat_progress_create_index WHERE index_relid = to_regclass($1)
+                )",
+                vec![arg(new_index.clone())],
+            )?
+            .unwrap_or(false);
+            if !building {
+                // a failed concurrent build leaves an invalid index behind
+                // drop it so that the next scheduled build starts over
+                warning!("pg-vectorize: reindex of job {job_name} failed, retrying");
+                compat::run(&format!("DROP INDEX IF EXISTS {new_index}"), vec![])?;
+            }
+            return Ok(());
+        }
+        Some(true) => {}
+    }
+
+    job_params.index_params = serde_json::from_value(index_params.0)?;
+    let old_index = init::index_name(job_name, &meta.index_dist_type);
+    let new_index_name = init::index_name(job_name, &new_index_dist);
+    let params = pgrx::JsonB(serde_json::to_value(&job_params)?);
+    compat::connect(|mut c| {
+        compat::update(
+            &mut c,
+            &format!("DROP INDEX IF EXISTS {schema}.{old_index}"),
+            vec![],
+        )?;
+        compat::update(
+            &mut c,
+            &format!("ALTER INDEX {new_index} RENAME TO {new_index_name}"),
+            vec![],
+        )?;
+        compat::update(
+            &mut c,
+            "UPDATE vectorize.job SET index_dist_type = $2, params = $3 WHERE name = $1",
+            vec![arg(job_name), arg(new_index_dist.to_string()), arg(params)],
+        )?;
+        compat::update(
+            &mut c,
+            "SELECT cron.unschedule(jobid) FROM cron.job WHERE jobname = ANY($1)",
+            vec![arg(reindex_cron_names(job_name))],
+        )?;
+        Ok::<_, spi::Error>(())
+    })?;
+    log!("pg-vectorize: job {job_name} reindexed as {new_index_dist}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_carry_over_reloptions() {
+        let reloptions = vec!["m=32".to_string(), "ef_construction=64".to_string()];
+        let index_params = IndexParams {
+            ef_construction: Some(128),
+            ..Default::default()
+        };
+        let index_params = carry_over_reloptions(index_params, &reloptions);
+        assert_eq!(index_params.m, Some(32));
+        // a parameter given to the rebuilt index replaces the current one
+        assert_eq!(index_params.ef_construction, Some(128));
+
+        let reloptions = vec![
+            "storage_layout=plain".to_string(),
+            "num_dimensions=384".to_string(),
+        ];
+        let index_params = carry_over_reloptions(IndexParams::default(), &reloptions);
+        assert_eq!(index_params.storage_layout.as_deref(), Some("plain"));
+        assert_eq!(
+            index_params.storage_params.get("num_dimensions"),
+            Some(&serde_json::json!(384.0))
+        );
+        assert!(same_method(
+            &IndexDist::pgv_hnsw_l2,
+            &IndexDist::pgv_hnsw_cosine
+        ));
+        assert!(!same_method(
+            &IndexDist::pgv_hnsw_l2,
+            &IndexDist::pgv_ivfflat_l2
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::guc;
+
+use anyhow::{bail, Context, Result};
+use pgrx::prelude::*;
+use vectorize_core::transformers::http_handler::with_headers;
+use vectorize_core::transformers::providers::cohere::{CohereProvider, RerankResult};
+use vectorize_core::types::{JobParams, Model, ModelSource};
+
+// the candidates searched for each result of a reranked search, as the reranker picks the results from them
+const RERANK_CANDIDATES: i32 = 4;
+
+/// reorders the results of a search by how relevant a reranking model finds their text to the query
+pub struct Rerank {
+    model: Model,
+    // the number of results that are kept once the candidates are reranked
+    num_results: usize,
+    // the columns the text of a result is read from
+    text_columns: Vec<String>,
+    // the text columns that were not asked for, left out of the results once they are reranked
+    added_columns: Vec<String>,
+}
+
+impl Rerank {
+    /// widens a search for num_results results of the columns into a search for the reranker's candidates
+    pub fn new(
+        model: &Model,
+        job_params: &JobParams,
+        columns: &mut Vec<String>,
+        num_results: &mut i32,
+    ) -> Result<Self> {
+        if model.source != ModelSource::Cohere {
+            bail!("reranking is only supported by cohere models, got {model}");
+        }
+        let mut added_columns = Vec::new();
+        if !columns.iter().any(|c| c == "*") {
+            for col in &job_params.columns {
+                if !columns.contains(col) {
+                    columns.push(col.clone());
+                    added_columns.push(col.clone());
+                }
+            }
+        }
+        let rerank = Rerank {
+            model: model.clone(),
+            num_results: (*num_results).max(0) as usize,
+            text_columns: job_params.columns.clone(),
+            added_columns,
+        };
+        *num_results = num_results.saturating_mul(RERANK_CANDIDATES);
+        Ok(rerank)
+    }
+
+    /// the best num_results of the candidates, each with its rerank_score, best first
+    pub fn apply(&self, query: &str, candidates: Vec<pgrx::JsonB>) -> Result<Vec<pgrx::JsonB>> {
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+        let documents = candidates
+            .iter()
+            .map(|c| document_text(&c.0, &self.text_columns))
+            .collect();
+        let guc_configs = guc::get_guc_configs(&self.model.source);
+        let provider = CohereProvider::new(
+            guc_configs.service_url,
+            Some(
+                guc_configs
+                    .api_key
+                    .context("vectorize.cohere_api_key is required to rerank")?,
+            ),
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
+        let reranked = runtime
+            .block_on(with_headers(guc::headers(&self.model)?, async {
+                provider
+                    .rerank(&self.model.api_name(), query, documents, self.num_results)
+                    .await
+            }))
+            .map_err(|e| anyhow::anyhow!("error reranking search results: {}", e))?;
+        Ok(self.reorder(candidates, reranked))
+    }
+
+    // the candidates in the order of the reranker's results
+    fn reorder(
+        &self,
+        candidates: Vec<pgrx::JsonB>,
+        reranked: Vec<RerankResult>,
+    ) -> Vec<pgrx::JsonB> {
+        let mut candidates: Vec<Option<pgrx::JsonB>> = candidates.into_iter().map(Some).collect();
+        reranked
+            .into_iter()
+            .filter_map(|r| {
+                let mut result = candidates.get_mut(r.index)?.take()?;
+                if let Some(fields) = result.0.as_object_mut() {
+                    for col in &self.added_columns {
+                        fields.remove(col);
+                    }
+                    fields.insert("rerank_score".to_string(), r.relevance_score.into());
+                }
+                Some(result)
+            })
+            .take(self.num_results)
+            .collect()
+    }
+}
+
+// the text of a result that the reranker reads, the values of its text columns one per line
+fn document_text(result: &serde_json::Value, text_columns: &[String]) -> String {
+    text_columns
+        .iter()
+        .filter_map(|col| match &result[col] {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(text) => Some(text.clone()),
+            value => Some(value.to_string()),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rerank() {
+        let job_params = JobParams {
+            columns: vec!["description".to_string()],
+            ..Default::default()
+        };
+        let model = Model::new("cohere/rerank-english-v3.0").unwrap();
+        let mut columns = vec!["product_id".to_string()];
+        let mut num_results = 2;
+        let rerank = Rerank::new(&model, &job_params, &mut columns, &mut num_results).unwrap();
+        // the candidates are searched along with the text they are reranked by
+        assert_eq!(columns, vec!["product_id", "description"]);
+        assert_eq!(num_results, 8);
+
+        let candidates = (0..3)
+            .map(|i| {
+                pgrx::JsonB(
+                    serde_json::json!({"product_id": i, "description": format!("text {i}")}),
+                )
+            })
+            .collect();
+        let reranked = vec![
+            RerankResult {
+                index: 2,
+                relevance_score: 0.9,
+            },
+            RerankResult {
+                index: 0,
+                relevance_score: 0.5,
+            },
+        ];
+        let results: Vec<serde_json::Value> = rerank
+            .reorder(candidates, reranked)
+            .into_iter()
+            .map(|r| r.0)
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                serde_json::json!({"product_id": 2, "rerank_score": 0.9}),
+                serde_json::json!({"product_id": 0, "rerank_score": 0.5}),
+            ]
+        );
+
+        let model = Model::new("openai/text-embedding-3-small").unwrap();
+        assert!(Rerank::new(&model, &job_params, &mut columns, &mut num_results).is_err());
+    }
+
+    #[test]
+    fn test_document_text() {
+        let columns = vec!["title".to_string(), "body".to_string(), "year".to_string()];
+        let result = serde_json::json!({"title": "Pencil", "body": null, "year": 2024});
+        assert_eq!(document_text(&result, &columns), "Pencil\n2024");
+    }
+}
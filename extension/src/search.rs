@@ -1,16 +1,30 @@
+use crate::budget;
+use crate::chunking;
+use crate::compat::{self, arg};
+use crate::credential;
+use crate::executor::{all_rows_query, new_rows_query, new_rows_query_join};
 use crate::guc;
-use crate::guc::get_guc_configs;
 use crate::init;
-use crate::job::{create_event_trigger, create_trigger_handler, initalize_table_job};
+use crate::job::{enqueue_rows, initalize_table_job, realtime_trigger_queries};
+use crate::model_migration;
+use crate::query::check_input;
+use crate::registry;
+use crate::reindex;
+use crate::rerank::Rerank;
 use crate::transformers::openai;
-use crate::transformers::transform;
+use crate::transformers::{get_provider, transform};
+use crate::ttl;
 use crate::util;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use pgrx::prelude::*;
-use vectorize_core::transformers::providers::get_provider;
-use vectorize_core::transformers::providers::ollama::check_model_host;
-use vectorize_core::types::{self, Model, ModelSource, TableMethod, VectorizeMeta};
+use std::collections::BTreeMap;
+use vectorize_core::transformers::http_handler::{with_headers, with_proxies, with_timeouts};
+use vectorize_core::transformers::providers::ollama::{check_model_host, OLLAMA_BASE_URL};
+use vectorize_core::transformers::providers::{fit_dimensions, InputType};
+use vectorize_core::types::{
+    self, DistanceMetric, Model, ModelSource, TableMethod, VectorStorage, VectorizeMeta,
+};
 
 #[allow(clippy::too_many_arguments)]
 pub fn init_table(
@@ -21,22 +35,182 @@ pub fn init_table(
     primary_key: &str,
     update_col: Option<String>,
     index_dist_type: types::IndexDist,
+    index_params: types::IndexParams,
+    decryption: Option<types::ColumnDecryption>,
+    column_weights: BTreeMap<String, f64>,
+    partition_embeddings: bool,
+    dest_schema: Option<String>,
+    // shorter than the model's dimensions, for jobs that truncate their embeddings
+    dimensions: Option<u32>,
+    provenance: Option<types::ChunkProvenance>,
+    collection: bool,
+    input_template: Option<String>,
+    vector_storage: types::VectorStorage,
+    storage_params: types::StorageParams,
+    // splits the columns of each row into chunks, which are embedded in place of the rows
+    chunk_params: Option<types::ChunkParams>,
+    // cleans up the text of each row before it is chunked and embedded
+    preprocess: Option<types::Preprocess>,
+    // the job's credential in vectorize.credentials, in place of the provider's GUCs
+    credential: Option<String>,
+    // scales embeddings and search queries to unit length
+    normalize: bool,
+    // the job's one column holds images, or their urls, in place of text
+    content_type: types::ContentType,
     transformer: &Model,
     table_method: types::TableMethod,
     // cron-like for a cron based update model, or 'realtime' for a trigger-based
     schedule: &str,
 ) -> Result<String> {
-    // validate table method
-    // realtime is only compatible with the join method
-    if schedule == "realtime" && table_method != TableMethod::join {
-        error!("realtime schedule is only compatible with the join table method");
+    init::validate_job_name(job_name)?;
+    let mut input_template = input_template;
+
+    let decryption = decryption
+        .map(|decryption| init::validate_decryption(decryption, &columns))
+        .transpose()?;
+    init::validate_column_weights(&column_weights, &columns)?;
+    if let Some(dest_schema) = &dest_schema {
+        init::validate_dest_schema(dest_schema, &table_method)?;
+    }
+    if let Some(provenance) = &provenance {
+        init::validate_provenance(provenance, schema, table)?;
+    }
+    if !content_type.is_text() {
+        let unsupported = [
+            ("decrypt_expressions", decryption.is_some()),
+            ("column_weights", !column_weights.is_empty()),
+            // the template of a job of image urls produces the urls
+            (
+                "input_template",
+                input_template.is_some() && content_type == types::ContentType::image,
+            ),
+            ("chunk_size", chunk_params.is_some()),
+            ("preprocess", preprocess.is_some()),
+        ];
+        if let Some((name, _)) = unsupported.iter().find(|(_, given)| *given) {
+            bail!("{name} can not be used along with content_type {content_type}");
+        }
+        let column_types = columns
+            .iter()
+            .map(|c| init::get_column_datatype(schema, table, c))
+            .collect::<Result<Vec<String>>>()?;
+        init::validate_content_type(content_type, transformer, &column_types)?;
     }
+    // a chunked job is a job over its table of chunks, which is kept next to its embeddings
+    let chunking = match chunk_params {
+        Some(params) => {
+            let unsupported = [
+                ("decrypt_expressions", decryption.is_some()),
+                ("column_weights", !column_weights.is_empty()),
+                ("partition_embeddings", partition_embeddings),
+                ("provenance", provenance.is_some()),
+                ("collection", collection),
+            ];
+            if let Some((name, _)) = unsupported.iter().find(|(_, given)| *given) {
+                bail!("{name} can not be used along with chunk_size");
+            }
+            // the templated text of the rows is chunked, the job embeds the chunks as they are
+            Some(chunking::chunking_of(
+                schema,
+                table,
+                columns.clone(),
+                primary_key,
+                params,
+                input_template.take().as_deref(),
+            )?)
+        }
+        None => None,
+    };
+    let chunks_schema = dest_schema
+        .clone()
+        .unwrap_or_else(|| types::VECTORIZE_SCHEMA.to_string());
+    let chunks_table = chunking::chunks_table_name(job_name);
+    let (schema, table, columns, primary_key, update_col) = match &chunking {
+        Some(chunking) => {
+            let num_chunks = chunking::create_chunks(
+                chunking,
+                &chunks_schema,
+                &chunks_table,
+                true,
+                preprocess,
+                Some(transformer),
+            )?;
+            if chunking.params.strategy != types::ChunkStrategy::semantic {
+                log!(
+                    "pg-vectorize: split {schema}.{table} into {num_chunks} chunks for job {job_name}"
+                );
+            }
+            (
+                chunks_schema.as_str(),
+                chunks_table.as_str(),
+                vec!["chunk".to_string()],
+                "chunk_id",
+                Some("updated_at".to_string()),
+            )
+        }
+        None => (schema, table, columns, primary_key, update_col),
+    };
+    let index_params = init::resolve_index_params(&index_dist_type, index_params, schema, table)?;
 
-    // get prim key type
-    let pkey_type = init::get_column_datatype(schema, table, primary_key)?;
+    if let Some(replacement) = transformer.deprecated_replacement() {
+        warning!(
+            "model {} is deprecated by its provider, consider using {} instead",
+            transformer,
+            replacement
+        );
+    }
+
+    // get prim key type, composite keys are given as a comma separated list of columns
+    let pkey_columns: Vec<String> = primary_key
+        .split(',')
+        .map(|c| c.trim().to_string())
+        .collect();
+    let pkey_type = pkey_columns
+        .iter()
+        .map(|c| init::get_column_datatype(schema, table, c))
+        .collect::<Result<Vec<String>>>()?
+        .join(", ");
+    let primary_key = pkey_columns.join(", ");
+
+    // unique constraints and foreign keys on a partitioned table must include its partition key
+    let partitioning = init::get_partitioning(schema, table)?;
+    match &partitioning {
+        Some(p) => {
+            if let Some(col) = p.key_columns.iter().find(|c| !pkey_columns.contains(c)) {
+                bail!(
+                    "primary_key of a partitioned table must include its partition key, missing: {col}"
+                );
+            }
+        }
+        None if partition_embeddings => {
+            bail!("partition_embeddings requires a partitioned table: {schema}.{table}")
+        }
+        None => {}
+    }
+    if partition_embeddings && table_method != TableMethod::join {
+        bail!("partition_embeddings is only compatible with the join table method");
+    }
+    init::validate_storage(
+        vector_storage,
+        &storage_params,
+        &table_method,
+        &index_dist_type,
+        partition_embeddings,
+    )?;
+    init::validate_normalize(normalize, &index_dist_type)?;
+    let source_kind = init::get_source_kind(schema, table)?;
+    init::validate_source_kind(source_kind, schedule, &table_method, update_col.as_deref())?;
+    // the job's runs find the rows that changed by their update_col
+    if let (false, Some(col)) = (source_kind.is_table(), &update_col) {
+        init::get_column_datatype(schema, table, col)
+            .with_context(|| format!("update_col {col} is not a column of {schema}.{table}"))?;
+    }
     init::init_pgmq()?;
 
-    let guc_configs = get_guc_configs(&transformer.source);
+    if let Some(name) = &credential {
+        credential::validate(name, transformer)?;
+    }
+    let guc_configs = guc::get_job_guc_configs(transformer, credential.as_deref())?;
     // validate API key where necessary and collect any optional arguments
     // certain embedding services require an API key, e.g. openAI
     // key can be set in a GUC, so if its required but not provided in args, and not in GUC, error
@@ -54,12 +228,10 @@ pub fn init_table(
             error!("Tembo not implemented for search yet");
         }
         ModelSource::Ollama => {
-            let url = match guc::get_guc(guc::VectorizeGuc::OllamaServiceUrl) {
-                Some(k) => k,
-                None => {
-                    error!("failed to get Ollama url from GUC");
-                }
-            };
+            let url = guc_configs
+                .service_url
+                .clone()
+                .unwrap_or_else(|| OLLAMA_BASE_URL.to_string());
             let res = check_model_host(&url);
             match res {
                 Ok(_) => {
@@ -71,6 +243,77 @@ pub fn init_table(
                 }
             }
         }
+        ModelSource::AzureOpenAI => {
+            guc_configs
+                .service_url
+                .as_ref()
+                .context("vectorize.azure_openai_service_url is required")?;
+            guc_configs
+                .api_key
+                .as_ref()
+                .context("Azure OpenAI key is required")?;
+            None
+        }
+        ModelSource::Bedrock => {
+            guc_configs
+                .service_url
+                .as_ref()
+                .context("vectorize.bedrock_region or vectorize.bedrock_service_url is required")?;
+            guc_configs.api_key.as_ref().context(
+                "vectorize.bedrock_access_key_id and vectorize.bedrock_secret_access_key are required",
+            )?;
+            None
+        }
+        ModelSource::Gemini => {
+            guc_configs.api_key.as_ref().context(
+                "vectorize.gemini_api_key or vectorize.vertex_service_account_key is required",
+            )?;
+            None
+        }
+        ModelSource::Mistral => {
+            guc_configs
+                .api_key
+                .as_ref()
+                .context("vectorize.mistral_api_key is required")?;
+            None
+        }
+        ModelSource::Voyage => {
+            guc_configs
+                .api_key
+                .as_ref()
+                .context("vectorize.voyage_api_key is required")?;
+            None
+        }
+        ModelSource::Jina => {
+            guc_configs
+                .api_key
+                .as_ref()
+                .context("vectorize.jina_api_key is required")?;
+            None
+        }
+        ModelSource::HuggingFace => {
+            guc_configs
+                .api_key
+                .as_ref()
+                .context("vectorize.huggingface_api_key is required")?;
+            // saved with the job, for workers that do not read the GUCs
+            guc_configs
+                .service_url
+                .as_ref()
+                .map(|url| serde_json::json!({ "endpoint_url": url }))
+        }
+        // saved with the job, for workers that do not read the GUCs
+        ModelSource::OpenAICompatible => {
+            let endpoint: serde_json::Value = serde_json::from_str(
+                guc_configs
+                    .service_url
+                    .as_deref()
+                    .context("OpenAI-compatible endpoint is required")?,
+            )?;
+            Some(serde_json::json!({ "endpoint": endpoint }))
+        }
+        // the virtual key of a credential is read along with its api key, rather than saved with the job
+        ModelSource::Portkey if credential.is_some() => None,
         ModelSource::Portkey => Some(serde_json::json!({
             "virtual_key": guc_configs.virtual_key.clone().expect("Portkey virtual key is required")
         })),
@@ -78,10 +321,11 @@ pub fn init_table(
     };
 
     let provider = get_provider(
-        &transformer.source,
+        transformer,
         guc_configs.api_key.clone(),
         guc_configs.service_url.clone(),
         guc_configs.virtual_key.clone(),
+        None,
     )?;
 
     // synchronous
@@ -90,26 +334,70 @@ pub fn init_table(
         .enable_time()
         .build()
         .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
-    let model_dim =
-        match runtime.block_on(async { provider.model_dim(&transformer.api_name()).await }) {
+    // registered models that state their dimensions are not probed for them
+    let model_dim = match transformer.registered_dimensions() {
+        Some(d) => d,
+        None => match runtime.block_on(with_headers(
+            guc::headers(transformer)?,
+            with_proxies(
+                guc::proxies()?,
+                with_timeouts(guc::timeouts(), async {
+                    provider.model_dim(&transformer.api_name()).await
+                }),
+            ),
+        )) {
             Ok(e) => e,
             Err(e) => {
                 error!("error getting model dim: {}", e);
             }
-        };
+        },
+    };
+    let truncate_dimensions = match dimensions {
+        Some(d) if d > model_dim => {
+            bail!("dimensions can be at most {model_dim}, the dimensions of {transformer}")
+        }
+        Some(d) => d < model_dim,
+        None => false,
+    };
+    let dimensions = dimensions.unwrap_or(model_dim);
 
-    let valid_params = types::JobParams {
+    let mut valid_params = types::JobParams {
         schema: schema.to_string(),
         table: table.to_string(),
         columns: columns.clone(),
         update_time_col: update_col,
         table_method: table_method.clone(),
-        primary_key: primary_key.to_string(),
+        primary_key,
         pkey_type,
-        api_key: guc_configs.api_key.clone(),
+        // the keys of a credential are not saved with the job
+        api_key: guc_configs.api_key.clone().filter(|_| credential.is_none()),
+        credential,
         schedule: schedule.to_string(),
         args: optional_args,
+        index_params,
+        decryption,
+        column_weights,
+        partition_embeddings,
+        dest_schema,
+        dimensions: Some(dimensions),
+        truncate_dimensions,
+        normalize,
+        provenance,
+        collection,
+        input_template,
+        ttl: None,
+        batch_size: None,
+        max_attempts: None,
+        connect_timeout_sec: None,
+        request_timeout_sec: None,
+        source_kind,
+        vector_storage,
+        storage_params,
+        chunking,
+        preprocess,
+        content_type,
     };
+    init::validate_input_template(&mut valid_params)?;
     let params =
         pgrx::JsonB(serde_json::to_value(valid_params.clone()).expect("error serializing params"));
 
@@ -117,22 +405,16 @@ pub fn init_table(
     let init_job_q = init::init_job_query();
     // using SPI here because it is unlikely that this code will be run anywhere but inside the extension.
     // background worker will likely be moved to an external container or service in near future
-    let ran: Result<_, spi::Error> = Spi::connect(|mut c| {
-        match c.update(
+    let ran: Result<_, spi::Error> = compat::connect(|mut c| {
+        match compat::update(
+            &mut c,
             &init_job_q,
-            None,
-            Some(vec![
-                (PgBuiltInOids::TEXTOID.oid(), job_name.into_datum()),
-                (
-                    PgBuiltInOids::TEXTOID.oid(),
-                    index_dist_type.to_string().into_datum(),
-                ),
-                (
-                    PgBuiltInOids::TEXTOID.oid(),
-                    transformer.to_string().into_datum(),
-                ),
-                (PgBuiltInOids::JSONBOID.oid(), params.into_datum()),
-            ]),
+            vec![
+                arg(job_name),
+                arg(index_dist_type.to_string()),
+                arg(transformer.to_string()),
+                arg(params),
+            ],
         ) {
             Ok(_) => (),
             Err(e) => {
@@ -143,12 +425,18 @@ pub fn init_table(
     });
     ran?;
 
-    let init_embed_q =
-        init::init_embedding_table_query(job_name, &valid_params, &index_dist_type, model_dim);
+    let embeddings_partitioning = partitioning.as_ref().filter(|_| partition_embeddings);
+    let init_embed_q = init::init_embedding_table_query(
+        job_name,
+        &valid_params,
+        &index_dist_type,
+        dimensions,
+        embeddings_partitioning,
+    );
 
-    let ran: Result<_, spi::Error> = Spi::connect(|mut c| {
+    let ran: Result<_, spi::Error> = compat::connect(|mut c| {
         for q in init_embed_q {
-            let _r = c.update(&q, None, None)?;
+            let _r = compat::update(&mut c, &q, vec![])?;
         }
         Ok(())
     });
@@ -159,13 +447,12 @@ pub fn init_table(
         "realtime" => {
             // setup triggers
             // create the trigger if not exists
-            let trigger_handler = create_trigger_handler(job_name, &columns, primary_key);
-            let insert_trigger = create_event_trigger(job_name, schema, table, "INSERT");
-            let update_trigger = create_event_trigger(job_name, schema, table, "UPDATE");
-            let _: Result<_, spi::Error> = Spi::connect(|mut c| {
-                let _r = c.update(&trigger_handler, None, None)?;
-                let _r = c.update(&insert_trigger, None, None)?;
-                let _r = c.update(&update_trigger, None, None)?;
+            let trigger_queries =
+                realtime_trigger_queries(job_name, &valid_params, partitioning.as_ref());
+            let _: Result<_, spi::Error> = compat::connect(|mut c| {
+                for q in &trigger_queries {
+                    let _r = compat::update(&mut c, q, vec![])?;
+                }
                 Ok(())
             });
         }
@@ -175,11 +462,335 @@ pub fn init_table(
             log!("Initialized cron job");
         }
     }
+    if let Some(chunking) = &valid_params.chunking {
+        // the chunks follow the changes to their rows, whatever the job's schedule
+        let partitioning = init::get_partitioning(&chunking.schema, &chunking.table)?;
+        for q in chunking::rechunk_trigger_queries(job_name, chunking, partitioning.as_ref()) {
+            compat::run(&q, vec![])?;
+        }
+        if chunking.params.strategy == types::ChunkStrategy::semantic {
+            let num_rows = chunking::enqueue_semantic_chunking(job_name, None)?;
+            log!("pg-vectorize: queued {num_rows} rows to be chunked for job {job_name}");
+        }
+    }
     // start with initial batch load
     initalize_table_job(job_name, &valid_params, index_dist_type, transformer)?;
     Ok(format!("Successfully created job: {job_name}"))
 }
 
+/// removes a job and everything it created
+pub fn drop_job(job_name: &str) -> Result<String> {
+    let project_meta: VectorizeMeta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: types::JobParams = serde_json::from_value(project_meta.params)?;
+
+    let drop_queries = init::drop_job_queries(job_name, &job_params);
+    compat::connect(|mut c| {
+        for q in drop_queries {
+            compat::update(&mut c, &q, vec![])?;
+        }
+        compat::update(
+            &mut c,
+            "DELETE FROM vectorize.job WHERE name = $1",
+            vec![arg(job_name)],
+        )?;
+        Ok::<_, spi::Error>(())
+    })?;
+    Ok(format!("Successfully dropped job: {job_name}"))
+}
+
+/// renames a job, along with the triggers, pg_cron jobs, tables, columns and indexes named after it
+/// the embeddings are kept, and everything is renamed in the calling transaction
+pub fn rename_job(job_name: &str, new_name: &str) -> Result<String> {
+    let project_meta: VectorizeMeta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: types::JobParams = serde_json::from_value(project_meta.params)?;
+    init::validate_job_name(new_name)?;
+    if job_exists(new_name)? {
+        bail!("a job named {new_name} already exists");
+    }
+    // the index build and swap are scheduled under the job's name
+    if reindex::is_reindexing(job_name)? {
+        bail!("job {job_name} can not be renamed while its index is being rebuilt");
+    }
+    if model_migration::is_migrating(job_name)? {
+        bail!("job {job_name} can not be renamed while its model is being migrated");
+    }
+
+    let (embeddings_schema, embeddings_table, _) = init::embeddings_location(job_name, &job_params);
+    let embedding_partitions = match job_params.table_method {
+        TableMethod::join => init::get_partitioning(&embeddings_schema, &embeddings_table)?
+            .map(|p| p.leaves.into_iter().map(|(_, name)| name).collect())
+            .unwrap_or_default(),
+        TableMethod::append => vec![],
+    };
+    let mut queries = init::drop_trigger_queries(job_name, &job_params.schema, &job_params.table);
+    if let Some(chunking) = &job_params.chunking {
+        queries.extend(chunking::drop_rechunk_trigger_queries(job_name, chunking));
+    }
+    queries.extend(init::rename_job_queries(
+        job_name,
+        new_name,
+        &job_params,
+        &project_meta.index_dist_type,
+        &embedding_partitions,
+    ));
+    queries.extend(init::rename_cron_queries(
+        job_name,
+        new_name,
+        &init::job_execute_command(new_name),
+    ));
+    queries.extend(init::rename_cron_queries(
+        &ttl::ttl_cron_name(job_name),
+        &ttl::ttl_cron_name(new_name),
+        &ttl::purge_command(new_name),
+    ));
+    if job_params.schedule == "realtime" {
+        let partitioning = init::get_partitioning(&job_params.schema, &job_params.table)?;
+        queries.extend(realtime_trigger_queries(
+            new_name,
+            &chunking::renamed_job_params(new_name, &job_params),
+            partitioning.as_ref(),
+        ));
+    }
+    if let Some(chunking) = &job_params.chunking {
+        let partitioning = init::get_partitioning(&chunking.schema, &chunking.table)?;
+        queries.extend(chunking::rechunk_trigger_queries(
+            new_name,
+            chunking,
+            partitioning.as_ref(),
+        ));
+    }
+    compat::connect(|mut c| {
+        for q in &queries {
+            compat::update(&mut c, q, vec![])?;
+        }
+        Ok::<_, spi::Error>(())
+    })?;
+    Ok(format!("Renamed job {job_name} to {new_name}"))
+}
+
+/// pauses or resumes a job without removing any of its state
+/// realtime triggers keep capturing changes while paused, the worker holds back their messages
+pub fn set_job_paused(job_name: &str, paused: bool) -> Result<String> {
+    let project_meta: VectorizeMeta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: types::JobParams = serde_json::from_value(project_meta.params)?;
+
+    compat::connect(|mut c| {
+        compat::update(
+            &mut c,
+            "UPDATE vectorize.job SET paused = $2 WHERE name = $1",
+            vec![arg(job_name), arg(paused)],
+        )?;
+        if job_params.schedule != "realtime" {
+            compat::update(
+                &mut c,
+                "SELECT cron.alter_job(jobid, active := $2) FROM cron.job WHERE jobname = $1",
+                vec![arg(job_name), arg(!paused)],
+            )?;
+        }
+        Ok::<_, spi::Error>(())
+    })?;
+    match paused {
+        true => Ok(format!("Paused job: {job_name}")),
+        false => Ok(format!("Resumed job: {job_name}")),
+    }
+}
+
+/// queues a job's rows for embedding, in batches sized by vectorize.batch_size
+/// with force, every row is re-embedded, e.g. after changing models, otherwise only new or changed rows are
+pub fn refresh_job(job_name: &str, force: bool) -> Result<String> {
+    let project_meta: VectorizeMeta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: types::JobParams = serde_json::from_value(project_meta.params.clone())?;
+
+    let rows_query = match (force, &job_params.table_method) {
+        (true, _) => all_rows_query(&job_params),
+        (false, TableMethod::append) => new_rows_query(job_name, &job_params),
+        (false, TableMethod::join) => new_rows_query_join(job_name, &job_params),
+    };
+    if force {
+        // progress is measured against embeddings written since the refresh started
+        compat::run(
+            "UPDATE vectorize.job SET refreshed_at = now() WHERE name = $1",
+            vec![arg(job_name)],
+        )?;
+    }
+    let num_rows = enqueue_rows(&project_meta, &job_params, &rows_query)?;
+    Ok(format!("Queued {num_rows} rows for job: {job_name}"))
+}
+
+/// rows in the job's source table, and how many of them have embeddings
+/// after a forced refresh, only embeddings written since the refresh started are counted
+pub fn refresh_progress(job_name: &str) -> Result<(i64, i64)> {
+    let project_meta: VectorizeMeta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: types::JobParams = serde_json::from_value(project_meta.params)?;
+
+    let total_rows: i64 = compat::get_one(
+        &format!(
+            "SELECT count(*) FROM {schema}.{table}",
+            schema = job_params.schema,
+            table = job_params.table,
+        ),
+        vec![],
+    )?
+    .context("error counting rows")?;
+    let completed_rows: i64 = compat::get_one(
+        &init::embedded_rows_query(job_name, &job_params),
+        vec![arg(job_name)],
+    )?
+    .context("error counting embedded rows")?;
+    Ok((total_rows, completed_rows))
+}
+
+/// creates a job with the configuration of an existing job's meta, and a different transformer
+pub fn init_job_like(
+    job_name: &str,
+    project_meta: VectorizeMeta,
+    transformer: &Model,
+) -> Result<String> {
+    let job_params: types::JobParams = serde_json::from_value(project_meta.params)?;
+    let ttl = job_params.ttl.clone();
+    let batch_size = job_params.batch_size;
+    // a chunked job is created over its source table, and chunks it again into a table of its own
+    let (schema, table, columns, primary_key, update_col, chunk_params, input_template) =
+        match job_params.chunking {
+            Some(mut chunking) => {
+                // the new job's filter function is called as the current user, not the existing job's owner
+                chunking.params.filter = chunking
+                    .params
+                    .filter
+                    .map(|filter| chunking::chunk_filter(serde_json::to_value(filter)?))
+                    .transpose()?;
+                (
+                    chunking.schema,
+                    chunking.table,
+                    chunking.columns,
+                    chunking.primary_key,
+                    None,
+                    Some(chunking.params),
+                    chunking.input_template,
+                )
+            }
+            None => (
+                job_params.schema,
+                job_params.table,
+                job_params.columns,
+                job_params.primary_key,
+                job_params.update_time_col,
+                None,
+                job_params.input_template,
+            ),
+        };
+    let message = init_table(
+        job_name,
+        &schema,
+        &table,
+        columns,
+        &primary_key,
+        update_col,
+        project_meta.index_dist_type,
+        job_params.index_params,
+        job_params.decryption,
+        job_params.column_weights,
+        job_params.partition_embeddings,
+        job_params.dest_schema,
+        job_params.truncated_dimensions(),
+        job_params.provenance,
+        job_params.collection,
+        input_template,
+        job_params.vector_storage,
+        job_params.storage_params,
+        chunk_params,
+        job_params.preprocess,
+        job_params.credential,
+        job_params.normalize,
+        job_params.content_type,
+        transformer,
+        job_params.table_method,
+        &job_params.schedule,
+    )?;
+    if batch_size.is_some() {
+        let meta = util::get_vectorize_meta_spi(job_name)?;
+        let mut job_params: types::JobParams = serde_json::from_value(meta.params)?;
+        job_params.batch_size = batch_size;
+        util::update_job_params(job_name, &job_params)?;
+    }
+    if let Some(ttl) = ttl {
+        ttl::set_ttl(job_name, Some(&ttl.interval), ttl.delete_source)?;
+    }
+    Ok(message)
+}
+
+/// creates a job with the configuration of an existing job, over another table
+/// new_table may be schema qualified, otherwise it is in the schema of the existing job's table
+/// a chunked job's clone chunks the rows of the new table
+pub fn clone_job(source_job: &str, new_job: &str, new_table: &str) -> Result<String> {
+    let mut project_meta = util::get_vectorize_meta_spi(source_job)?;
+    let mut job_params: types::JobParams = serde_json::from_value(project_meta.params)?;
+    if job_params.collection {
+        bail!(
+            "job {source_job} is a collection, create a new collection with vectorize.create_collection"
+        );
+    }
+    init::validate_job_name(new_job)?;
+    // the job would otherwise replace the existing job's row in vectorize.job
+    if job_exists(new_job)? {
+        bail!("a job named {new_job} already exists");
+    }
+    let (schema, table) = match new_table.split_once('.') {
+        Some((schema, table)) => (schema.to_string(), table.to_string()),
+        None => match &job_params.chunking {
+            Some(chunking) => (chunking.schema.clone(), new_table.to_string()),
+            None => (job_params.schema.clone(), new_table.to_string()),
+        },
+    };
+    check_input(&schema)?;
+    check_input(&table)?;
+    match &mut job_params.chunking {
+        Some(chunking) => {
+            chunking.schema = schema;
+            chunking.table = table;
+        }
+        None => {
+            job_params.schema = schema;
+            job_params.table = table;
+        }
+    }
+    project_meta.params = serde_json::to_value(&job_params)?;
+    let transformer = project_meta.transformer.clone();
+    init_job_like(new_job, project_meta, &transformer)
+}
+
+fn job_exists(job_name: &str) -> Result<bool> {
+    Ok(compat::get_one(
+        "SELECT EXISTS (SELECT 1 FROM vectorize.job WHERE name = $1)",
+        vec![arg(job_name)],
+    )?
+    .unwrap_or(false))
+}
+
+/// jobs whose transformer has been deprecated, along with the recommended replacement
+pub fn deprecated_jobs() -> Result<Vec<(String, Model, Model)>> {
+    compat::connect(|client| {
+        let mut jobs = Vec::new();
+        let tup_table = compat::select(
+            &client,
+            "SELECT name, transformer FROM vectorize.job ORDER BY name",
+            vec![],
+        )?;
+        for row in tup_table {
+            let name: String = row["name"].value()?.context("job name was null")?;
+            let transformer: String = row["transformer"]
+                .value()?
+                .context("job transformer was null")?;
+            let model = registry::resolve(&transformer)?;
+            if let Some(replacement) = model.deprecated_replacement() {
+                jobs.push((name, model, replacement));
+            }
+        }
+        Ok::<_, anyhow::Error>(jobs)
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn search(
     job_name: &str,
     query: &str,
@@ -187,6 +798,11 @@ pub fn search(
     return_columns: Vec<String>,
     num_results: i32,
     where_clause: Option<String>,
+    lexical_fallback: bool,
+    include_unembedded: bool,
+    metric: Option<DistanceMetric>,
+    retrieval: ChunkRetrieval,
+    rerank_model: Option<&Model>,
 ) -> Result<Vec<pgrx::JsonB>> {
     let project_meta: VectorizeMeta = util::get_vectorize_meta_spi(job_name)?;
     let proj_params: types::JobParams = serde_json::from_value(
@@ -199,66 +815,460 @@ pub fn search(
     let proj_api_key = match api_key {
         // if api passed in the function call, use that
         Some(k) => Some(k),
+        // a job's credential is read when the query is embedded
+        None if proj_params.credential.is_some() => None,
         // if not, use the one from the project metadata
         None => proj_params.api_key.clone(),
     };
-    let embeddings = transform(query, &project_meta.transformer, proj_api_key);
-
-    match project_meta.index_dist_type {
-        types::IndexDist::pgv_hnsw_l2 => error!("Not implemented."),
-        types::IndexDist::pgv_hnsw_ip => error!("Not implemented."),
-        types::IndexDist::pgv_hnsw_cosine | types::IndexDist::vsc_diskann_cosine => {
-            cosine_similarity_search(
+    if retrieval != ChunkRetrieval::Chunks && proj_params.chunking.is_none() {
+        bail!("parent_document and chunk_window are only used by jobs that chunk their rows, job {job_name} does not");
+    }
+    // the chunks the results are assembled from
+    let (mut chunk_columns, mut num_chunks) = retrieval.chunk_search(&return_columns, num_results);
+    // a reranked search picks its chunks from a wider set of candidates
+    let rerank = rerank_model
+        .map(|model| Rerank::new(model, &proj_params, &mut chunk_columns, &mut num_chunks))
+        .transpose()?;
+    let finish = |results: Vec<pgrx::JsonB>| -> Result<Vec<pgrx::JsonB>> {
+        let results = match &rerank {
+            Some(rerank) => rerank.apply(query, results)?,
+            None => results,
+        };
+        retrieval.assemble(&proj_params, &return_columns, num_results, results)
+    };
+    budget::check_budget(job_name, &project_meta.transformer.source)?;
+    let embeddings = match transform(
+        query,
+        &project_meta.transformer,
+        proj_api_key,
+        proj_params.credential.as_deref(),
+        InputType::Query,
+        proj_params.truncated_dimensions(),
+    ) {
+        Ok(e) => {
+            budget::record_token_usage(job_name, &project_meta.transformer, &[query], &[])?;
+            e
+        }
+        Err(e) if lexical_fallback => {
+            // provider is unavailable, serve a degraded full-text search instead of failing
+            warning!(
+                "pg-vectorize: failed to embed query for job {}, falling back to lexical search: {}",
                 job_name,
+                e
+            );
+            let results = lexical_search(
+                query,
                 &proj_params,
-                &return_columns,
-                num_results,
-                &embeddings[0],
+                &chunk_columns,
+                num_chunks,
                 where_clause,
-            )
+                None,
+            )?;
+            return finish(results);
         }
+        Err(e) => return Err(e),
+    };
+    let embeddings = fit_dimensions(embeddings, &proj_params)?;
+
+    configure_index_scan(&project_meta.index_dist_type, num_results)?;
+    let results = vector_search(
+        job_name,
+        &proj_params,
+        &VectorScoring::new(
+            &project_meta.index_dist_type,
+            metric,
+            proj_params.vector_storage,
+        ),
+        &chunk_columns,
+        num_chunks,
+        &embeddings[0],
+        where_clause.clone(),
+    )?;
+    if !include_unembedded {
+        return finish(results);
     }
+    // rows that are still waiting for embeddings, e.g. during a backfill, are only found by a full-text search
+    let unembedded = lexical_search(
+        query,
+        &proj_params,
+        &chunk_columns,
+        num_chunks,
+        where_clause,
+        Some(job_name),
+    )?;
+    let results = fuse_ranked(results, unembedded, num_chunks.max(0) as usize);
+    finish(results)
+}
+
+// the chunks searched for each result of a parent document search, as the rows of the best chunks
+// are found more than once
+const PARENT_CANDIDATES: i32 = 4;
+
+/// what the search of a chunked job returns for the chunks it finds
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChunkRetrieval {
+    // the chunks themselves
+    #[default]
+    Chunks,
+    // the rows the chunks were taken from, each once, with the return_columns of the rows
+    Parent,
+    // each chunk along with the text of this many chunks on either side of it, from the same column
+    Window(i32),
 }
 
-pub fn cosine_similarity_search(
+impl ChunkRetrieval {
+    pub fn new(parent_document: bool, chunk_window: Option<i32>) -> Result<Self> {
+        match (parent_document, chunk_window) {
+            (true, Some(_)) => bail!("parent_document and chunk_window can not be used together"),
+            (true, None) => Ok(ChunkRetrieval::Parent),
+            (false, Some(window)) if window < 0 => {
+                bail!("chunk_window must be at least 0, got {window}")
+            }
+            (false, Some(window)) => Ok(ChunkRetrieval::Window(window)),
+            (false, None) => Ok(ChunkRetrieval::Chunks),
+        }
+    }
+
+    // the columns and number of the chunks that are searched for num_results results
+    fn chunk_search(&self, return_columns: &[String], num_results: i32) -> (Vec<String>, i32) {
+        match self {
+            ChunkRetrieval::Chunks => (return_columns.to_vec(), num_results),
+            ChunkRetrieval::Parent => (
+                vec!["original_id".to_string(), "chunk".to_string()],
+                num_results.saturating_mul(PARENT_CANDIDATES),
+            ),
+            ChunkRetrieval::Window(_) => {
+                let mut columns = return_columns.to_vec();
+                if !columns.iter().any(|c| c == "*" || c == "chunk_id") {
+                    columns.push("chunk_id".to_string());
+                }
+                (columns, num_results)
+            }
+        }
+    }
+
+    // the results of a search, from the chunks found, in the order of their best chunks
+    fn assemble(
+        &self,
+        job_params: &types::JobParams,
+        return_columns: &[String],
+        num_results: i32,
+        chunks: Vec<pgrx::JsonB>,
+    ) -> Result<Vec<pgrx::JsonB>> {
+        match self {
+            ChunkRetrieval::Chunks => Ok(chunks),
+            ChunkRetrieval::Parent => {
+                parent_documents(job_params, return_columns, num_results, chunks)
+            }
+            ChunkRetrieval::Window(window) => {
+                let keep_chunk_id = return_columns.iter().any(|c| c == "*" || c == "chunk_id");
+                chunk_windows(job_params, *window, keep_chunk_id, chunks)
+            }
+        }
+    }
+}
+
+// the rows of the chunks found, each with its similarity_score, that of its best chunk,
+// and the text of its chunks that were found, best first
+fn parent_documents(
+    job_params: &types::JobParams,
+    return_columns: &[String],
+    num_results: i32,
+    chunks: Vec<pgrx::JsonB>,
+) -> Result<Vec<pgrx::JsonB>> {
+    let chunking = job_params
+        .chunking
+        .as_ref()
+        .context("job does not chunk its rows")?;
+    let mut parents: Vec<(String, serde_json::Value, Vec<serde_json::Value>)> = Vec::new();
+    for chunk in chunks {
+        let id = match &chunk.0["original_id"] {
+            serde_json::Value::String(id) => id.clone(),
+            id => id.to_string(),
+        };
+        let text = chunk.0["chunk"].clone();
+        match parents.iter_mut().find(|(parent, _, _)| *parent == id) {
+            Some((_, _, matched)) => matched.push(text),
+            None => parents.push((id, chunk.0["similarity_score"].clone(), vec![text])),
+        }
+    }
+    parents.truncate(num_results.max(0) as usize);
+    if parents.is_empty() {
+        return Ok(Vec::new());
+    }
+    let ids: Vec<String> = parents.iter().map(|(id, _, _)| id.clone()).collect();
+    let query = parent_documents_query(chunking, return_columns);
+    let mut rows: BTreeMap<String, serde_json::Value> = compat::connect(|client| {
+        let mut rows = BTreeMap::new();
+        for row in compat::select(&client, &query, vec![arg(ids)])? {
+            let id: String = row["id"].value()?.context("primary key was null")?;
+            let parent: Option<pgrx::JsonB> = row["parent"].value()?;
+            rows.insert(id, parent.map_or(serde_json::Value::Null, |p| p.0));
+        }
+        Ok::<_, anyhow::Error>(rows)
+    })?;
+    // rows that were deleted since their chunks were embedded are left out
+    Ok(parents
+        .into_iter()
+        .filter_map(|(id, score, matched)| {
+            let mut parent = rows.remove(&id)?;
+            let fields = parent.as_object_mut()?;
+            fields.insert("similarity_score".to_string(), score);
+            fields.insert("matched_chunks".to_string(), matched.into());
+            Some(pgrx::JsonB(parent))
+        })
+        .collect())
+}
+
+// the return_columns of the source rows whose primary keys are in $1
+fn parent_documents_query(chunking: &types::Chunking, return_columns: &[String]) -> String {
+    let cols = return_columns
+        .iter()
+        .map(|c| format!("t0.{c}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "SELECT t0.{pkey}::text AS id, (SELECT to_jsonb(r) FROM (SELECT {cols}) r) AS parent
+        FROM {schema}.{table} t0
+        WHERE t0.{pkey}::text = ANY($1)",
+        pkey = chunking.primary_key,
+        schema = chunking.schema,
+        table = chunking.table,
+    )
+}
+
+// the chunks found, each with the text of the chunks around it in its column as its context
+fn chunk_windows(
+    job_params: &types::JobParams,
+    window: i32,
+    keep_chunk_id: bool,
+    chunks: Vec<pgrx::JsonB>,
+) -> Result<Vec<pgrx::JsonB>> {
+    let chunk_ids: Vec<i64> = chunks
+        .iter()
+        .filter_map(|c| c.0["chunk_id"].as_i64())
+        .collect();
+    if chunk_ids.is_empty() {
+        return Ok(chunks);
+    }
+    let query = format!(
+        "SELECT c.chunk_id, w.chunk, w.char_start, w.char_end
+        FROM {schema}.{table} c
+        INNER JOIN {schema}.{table} w ON w.original_id = c.original_id
+            AND w.source_column = c.source_column
+            AND w.chunk_index BETWEEN c.chunk_index - $2 AND c.chunk_index + $2
+        WHERE c.chunk_id = ANY($1)
+        ORDER BY c.chunk_id, w.chunk_index",
+        schema = job_params.schema,
+        table = job_params.table,
+    );
+    let windows: BTreeMap<i64, Vec<(String, i32, i32)>> = compat::connect(|client| {
+        let mut windows: BTreeMap<i64, Vec<(String, i32, i32)>> = BTreeMap::new();
+        for row in compat::select(&client, &query, vec![arg(chunk_ids), arg(window)])? {
+            let chunk_id: i64 = row["chunk_id"].value()?.context("chunk_id was null")?;
+            let chunk: String = row["chunk"].value()?.unwrap_or_default();
+            let start: i32 = row["char_start"].value()?.unwrap_or_default();
+            let end: i32 = row["char_end"].value()?.unwrap_or_default();
+            windows
+                .entry(chunk_id)
+                .or_default()
+                .push((chunk, start, end));
+        }
+        Ok::<_, anyhow::Error>(windows)
+    })?;
+    Ok(chunks
+        .into_iter()
+        .map(|mut chunk| {
+            let chunk_id = chunk.0["chunk_id"].as_i64();
+            if let Some(fields) = chunk.0.as_object_mut() {
+                if let Some(window) = chunk_id.and_then(|id| windows.get(&id)) {
+                    fields.insert("context".to_string(), join_chunks(window).into());
+                }
+                if !keep_chunk_id {
+                    fields.remove("chunk_id");
+                }
+            }
+            chunk
+        })
+        .collect())
+}
+
+// the text of consecutive chunks of a column, given with their character offsets
+// the text that overlapping chunks share is only kept once, and chunks that are apart are joined by a line break
+fn join_chunks(chunks: &[(String, i32, i32)]) -> String {
+    let mut text = String::new();
+    let mut end: Option<i32> = None;
+    for (chunk, chunk_start, chunk_end) in chunks {
+        match end {
+            Some(end) if *chunk_start < end => {
+                text.extend(chunk.chars().skip((end - chunk_start) as usize));
+            }
+            Some(end) if *chunk_start > end => {
+                text.push('\n');
+                text.push_str(chunk);
+            }
+            _ => text.push_str(chunk),
+        }
+        end = Some(end.map_or(*chunk_end, |e| e.max(*chunk_end)));
+    }
+    text
+}
+
+// merges two ranked result lists with reciprocal rank fusion
+// a result's score is 1 / (k + rank) in its own list, so neither list's scores need to be comparable with the other's
+// ties go to the first list
+fn fuse_ranked<T>(first: Vec<T>, second: Vec<T>, num_results: usize) -> Vec<T> {
+    const K: f64 = 60.0;
+    let score = |rank: usize| 1.0 / (K + rank as f64 + 1.0);
+    let mut ranked: Vec<(f64, usize, T)> = first
+        .into_iter()
+        .enumerate()
+        .map(|(rank, r)| (score(rank), 0, r))
+        .chain(
+            second
+                .into_iter()
+                .enumerate()
+                .map(|(rank, r)| (score(rank), 1, r)),
+        )
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    ranked
+        .into_iter()
+        .take(num_results)
+        .map(|(_, _, r)| r)
+        .collect()
+}
+
+// pgvector 0.8 can keep scanning the index until enough rows pass the search filters
+fn pgvector_supports_iterative_scan() -> Result<bool> {
+    let version: Option<String> = compat::get_one(
+        "SELECT (SELECT extversion::text FROM pg_extension WHERE extname = 'vector')",
+        vec![],
+    )?;
+    let version = match version {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+    let mut parts = version.split('.').map(|p| p.parse::<u32>().unwrap_or(0));
+    let major = parts.next().unwrap_or(0);
+    let minor = parts.next().unwrap_or(0);
+    Ok(major > 0 || minor >= 8)
+}
+
+// configures the vector index scan for the current transaction so that selective filters
+// and large result sets are not silently truncated by the index
+fn configure_index_scan(index_dist_type: &types::IndexDist, num_results: i32) -> Result<()> {
+    if index_dist_type.is_hnsw() {
+        if pgvector_supports_iterative_scan()? {
+            compat::run(
+                "SELECT set_config('hnsw.iterative_scan', 'relaxed_order', true)",
+                vec![],
+            )?;
+        }
+        // without iterative scans, an hnsw scan returns at most ef_search rows
+        compat::run(
+            "SELECT set_config(
+                'hnsw.ef_search',
+                least(greatest(current_setting('hnsw.ef_search', true)::int, $1), 1000)::text,
+                true
+            )",
+            vec![arg(num_results)],
+        )?;
+    } else if index_dist_type.is_ivfflat() && pgvector_supports_iterative_scan()? {
+        compat::run(
+            "SELECT set_config('ivfflat.iterative_scan', 'relaxed_order', true)",
+            vec![],
+        )?;
+    } else if index_dist_type.is_diskann() {
+        // diskann scans stream their results, but only rescore the first query_rescore of them
+        // with the full vectors, the rest are ordered by their compressed vectors
+        compat::run(
+            "SELECT set_config(
+                'diskann.query_rescore',
+                greatest(current_setting('diskann.query_rescore', true)::int, $1)::text,
+                true
+            )",
+            vec![arg(num_results)],
+        )?;
+    }
+    Ok(())
+}
+
+/// how the candidates of a vector search are found and scored
+/// candidates are the nearest rows by the index's operator, so that the index can be used,
+/// their distance and similarity are then computed with the requested metric, and the results ordered by it
+/// the query in $1 is cast to the type the job's embeddings are searched as
+pub struct VectorScoring {
+    index_operator: &'static str,
+    metric: DistanceMetric,
+    vector_type: &'static str,
+}
+
+impl VectorScoring {
+    pub fn new(
+        index_dist_type: &types::IndexDist,
+        metric: Option<DistanceMetric>,
+        vector_storage: VectorStorage,
+    ) -> Self {
+        VectorScoring {
+            index_operator: index_dist_type.distance_operator(),
+            metric: metric.unwrap_or(index_dist_type.metric()),
+            vector_type: vector_storage.vector_type(),
+        }
+    }
+
+    // select list entries for the distance and similarity of an embeddings column to the query in $1
+    fn score_columns(&self, embeddings_col: &str) -> String {
+        let distance = format!(
+            "{embeddings_col} {} $1::{}",
+            self.metric.operator(),
+            self.vector_type
+        );
+        format!(
+            "{distance} AS distance, {similarity} AS similarity_score",
+            similarity = self.metric.similarity(&distance),
+        )
+    }
+
+    fn order_by(&self, embeddings_col: &str) -> String {
+        format!(
+            "{embeddings_col} {} $1::{}",
+            self.index_operator, self.vector_type
+        )
+    }
+}
+
+pub fn vector_search(
     project: &str,
     job_params: &types::JobParams,
+    scoring: &VectorScoring,
     return_columns: &[String],
     num_results: i32,
     embeddings: &[f64],
     where_clause: Option<String>,
 ) -> Result<Vec<pgrx::JsonB>> {
-    let schema = job_params.schema.clone();
-    let table = job_params.table.clone();
-
     // switch on table method
     let query = match job_params.table_method {
-        TableMethod::append => single_table_cosine_similarity(
+        TableMethod::append => single_table_vector_search(
             project,
-            &schema,
-            &table,
+            job_params,
+            scoring,
             return_columns,
             num_results,
             where_clause,
         ),
-        TableMethod::join => join_table_cosine_similarity(
+        TableMethod::join => join_table_vector_search(
             project,
             job_params,
+            scoring,
             return_columns,
             num_results,
             where_clause,
         ),
     };
-    Spi::connect(|client| {
+    compat::connect(|client| {
         let mut results: Vec<pgrx::JsonB> = Vec::new();
-        let tup_table = client.select(
-            &query,
-            None,
-            Some(vec![(
-                PgBuiltInOids::FLOAT8ARRAYOID.oid(),
-                embeddings.into_datum(),
-            )]),
-        )?;
+        let tup_table = compat::select(&client, &query, vec![arg(embeddings)])?;
         for row in tup_table {
             match row["results"].value()? {
                 Some(r) => results.push(r),
@@ -269,16 +1279,17 @@ pub fn cosine_similarity_search(
     })
 }
 
-fn join_table_cosine_similarity(
+fn join_table_vector_search(
     project: &str,
     job_params: &types::JobParams,
+    scoring: &VectorScoring,
     return_columns: &[String],
     num_results: i32,
     where_clause: Option<String>,
 ) -> String {
     let schema = job_params.schema.clone();
     let table = job_params.table.clone();
-    let join_key = &job_params.primary_key;
+    let pkey = job_params.pkey();
     let cols = &return_columns
         .iter()
         .map(|s| format!("t0.{}", s))
@@ -286,41 +1297,37 @@ fn join_table_cosine_similarity(
         .join(",");
 
     let where_str = if let Some(w) = where_clause {
-        prepare_filter(&w, join_key)
+        format!("WHERE {}", prepare_filter(&w, &pkey.column_names()))
     } else {
         "".to_string()
     };
-    let inner_query = format!(
-        "
-    SELECT
-        {join_key},
-        1 - (embeddings <=> $1::vector) AS similarity_score
-    FROM vectorize._embeddings_{project}
-    ORDER BY similarity_score DESC
-    "
-    );
+    let embeddings = job_params.searchable_embeddings("t1.embeddings");
+    // filter and limit in the same scan as the distance ordering so that the planner can
+    // push the filter into the vector index scan, rather than filtering an already truncated top-k
     format!(
         "
     SELECT to_jsonb(t) as results
     FROM (
-        SELECT {cols}, t1.similarity_score
-        FROM
-            (
-                {inner_query}
-            ) t1
-        INNER JOIN {schema}.{table} t0 on t0.{join_key} = t1.{join_key}
+        SELECT {cols}, {score_columns}
+        FROM {embeddings_schema}._embeddings_{project} t1
+        INNER JOIN {schema}.{table} t0 on {join_on}
         {where_str}
+        ORDER BY {order_by}
+        LIMIT {num_results}
     ) t
-    ORDER BY t.similarity_score DESC
-    LIMIT {num_results};
-    "
+    ORDER BY t.similarity_score DESC;
+    ",
+        join_on = pkey.join_on("t0", "t1"),
+        embeddings_schema = job_params.embeddings_schema(),
+        score_columns = scoring.score_columns(&embeddings),
+        order_by = scoring.order_by(&embeddings),
     )
 }
 
-fn single_table_cosine_similarity(
+fn single_table_vector_search(
     project: &str,
-    schema: &str,
-    table: &str,
+    job_params: &types::JobParams,
+    scoring: &VectorScoring,
     return_columns: &[String],
     num_results: i32,
     where_clause: Option<String>,
@@ -330,26 +1337,314 @@ fn single_table_cosine_similarity(
     } else {
         "".to_string()
     };
+    let embeddings = job_params.searchable_embeddings(&format!("{project}_embeddings"));
     format!(
         "
     SELECT to_jsonb(t) as results
     FROM (
         SELECT 
-        1 - ({project}_embeddings <=> $1::vector) AS similarity_score,
+        {score_columns},
         {cols}
     FROM {schema}.{table}
     WHERE {project}_updated_at is NOT NULL
     {where_str}
-    ORDER BY similarity_score DESC
+    ORDER BY {order_by}
     LIMIT {num_results}
     ) t
+    ORDER BY t.similarity_score DESC
     ",
         cols = return_columns.join(", "),
+        schema = job_params.schema,
+        table = job_params.table,
+        score_columns = scoring.score_columns(&embeddings),
+        order_by = scoring.order_by(&embeddings),
+    )
+}
+
+// full-text search over the job's source columns
+// used when the query embedding cannot be generated. every row is flagged as degraded
+// with unembedded_by set to the job's name, only the rows the job has not embedded yet are searched
+pub fn lexical_search(
+    query: &str,
+    job_params: &types::JobParams,
+    return_columns: &[String],
+    num_results: i32,
+    where_clause: Option<String>,
+    unembedded_by: Option<&str>,
+) -> Result<Vec<pgrx::JsonB>> {
+    let lexical_query = lexical_search_query(
+        job_params,
+        return_columns,
+        num_results,
+        where_clause,
+        unembedded_by,
+    );
+    compat::connect(|client| {
+        let mut results: Vec<pgrx::JsonB> = Vec::new();
+        let tup_table = compat::select(&client, &lexical_query, vec![arg(query)])?;
+        for row in tup_table {
+            match row["results"].value()? {
+                Some(r) => results.push(r),
+                None => error!("failed to get results"),
+            }
+        }
+        Ok(results)
+    })
+}
+
+fn lexical_search_query(
+    job_params: &types::JobParams,
+    return_columns: &[String],
+    num_results: i32,
+    where_clause: Option<String>,
+    unembedded_by: Option<&str>,
+) -> String {
+    let schema = &job_params.schema;
+    let table = &job_params.table;
+    let cols = return_columns
+        .iter()
+        .map(|s| format!("t0.{}", s))
+        .collect::<Vec<_>>()
+        .join(",");
+    let document = match job_params.templated_input_text("t0") {
+        Some(text) => format!("COALESCE({text}, '')"),
+        None => job_params
+            .columns
+            .iter()
+            .map(|c| format!("COALESCE(t0.{c}::text, '')"))
+            .collect::<Vec<_>>()
+            .join(" || ' ' || "),
+    };
+    let where_str = if let Some(w) = where_clause {
+        format!("AND {}", w)
+    } else {
+        "".to_string()
+    };
+    let unembedded_str = match unembedded_by {
+        None => "".to_string(),
+        Some(job_name) => match job_params.table_method {
+            TableMethod::append => format!("AND t0.{job_name}_updated_at IS NULL"),
+            TableMethod::join => format!(
+                "AND NOT EXISTS (SELECT 1 FROM {embeddings_schema}._embeddings_{job_name} t1 WHERE {join_on})",
+                embeddings_schema = job_params.embeddings_schema(),
+                join_on = job_params.pkey().join_on("t0", "t1"),
+            ),
+        },
+    };
+    format!(
+        "
+    SELECT to_jsonb(t) as results
+    FROM (
+        SELECT {cols},
+            ts_rank(to_tsvector({document}), plainto_tsquery($1)) AS similarity_score,
+            true AS degraded
+        FROM {schema}.{table} t0
+        WHERE to_tsvector({document}) @@ plainto_tsquery($1)
+        {unembedded_str}
+        {where_str}
+    ) t
+    ORDER BY t.similarity_score DESC
+    LIMIT {num_results};
+    "
     )
 }
 
 // transform user's where_sql into the format search query expects
-fn prepare_filter(filter: &str, pkey: &str) -> String {
-    let wc = filter.replace(pkey, &format!("t0.{}", pkey));
-    format!("AND {wc}")
+// the filter is split into tokens, and only identifiers naming a primary key column are qualified,
+// leaving string literals, quoted identifiers of other columns and already qualified names as they are
+fn prepare_filter(filter: &str, pkey_columns: &[String]) -> String {
+    let chars: Vec<char> = filter.chars().collect();
+    let mut out = String::with_capacity(filter.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c == '\'' || c == '"' {
+            // a doubled quote is an escaped quote, not the end of the literal
+            i += 1;
+            while i < chars.len() {
+                if chars[i] == c {
+                    if chars.get(i + 1) == Some(&c) {
+                        i += 2;
+                        continue;
+                    }
+                    i += 1;
+                    break;
+                }
+                i += 1;
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+            {
+                i += 1;
+            }
+        } else {
+            out.push(c);
+            i += 1;
+            continue;
+        }
+        let token: String = chars[start..i].iter().collect();
+        let name = match c {
+            // quoted identifiers are case sensitive, string literals never name a column
+            '"' => token
+                .strip_prefix('"')
+                .and_then(|t| t.strip_suffix('"'))
+                .map(|t| t.replace("\"\"", "\"")),
+            '\'' => None,
+            _ => Some(token.to_lowercase()),
+        };
+        // a name preceded or followed by a dot is part of a qualified name
+        let qualified = out.trim_end().ends_with('.')
+            || chars[i..].iter().find(|c| !c.is_whitespace()) == Some(&'.');
+        match name {
+            Some(name) if !qualified && pkey_columns.contains(&name) => {
+                out.push_str("t0.");
+                out.push_str(&token);
+            }
+            _ => out.push_str(&token),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_filter() {
+        let pkey = vec!["id".to_string()];
+        assert_eq!(prepare_filter("id > 5", &pkey), "t0.id > 5");
+        // other identifiers and literals containing the key's name are left alone
+        assert_eq!(
+            prepare_filter("product_id = 1 AND note = 'id 5' AND ID < 10", &pkey),
+            "product_id = 1 AND note = 'id 5' AND t0.ID < 10"
+        );
+        assert_eq!(
+            prepare_filter(
+                "\"id\" IN (1, 2) AND t0.id > 0 AND note = 'it''s id'",
+                &pkey
+            ),
+            "t0.\"id\" IN (1, 2) AND t0.id > 0 AND note = 'it''s id'"
+        );
+        assert_eq!(prepare_filter("\"Id\" = 1", &pkey), "\"Id\" = 1");
+    }
+
+    #[test]
+    fn test_vector_scoring() {
+        // candidates are found with the index's operator, and scored with the requested metric
+        let scoring = VectorScoring::new(
+            &types::IndexDist::pgv_hnsw_cosine,
+            Some(DistanceMetric::l2),
+            VectorStorage::vector,
+        );
+        assert_eq!(
+            scoring.order_by("t1.embeddings"),
+            "t1.embeddings <=> $1::vector"
+        );
+        assert_eq!(
+            scoring.score_columns("t1.embeddings"),
+            "t1.embeddings <-> $1::vector AS distance, 1 / (1 + (t1.embeddings <-> $1::vector)) AS similarity_score"
+        );
+        let scoring = VectorScoring::new(
+            &types::IndexDist::pgv_ivfflat_ip,
+            None,
+            VectorStorage::vector,
+        );
+        assert_eq!(scoring.order_by("e"), "e <#> $1::vector");
+        assert_eq!(
+            scoring.score_columns("e"),
+            "e <#> $1::vector AS distance, -(e <#> $1::vector) AS similarity_score"
+        );
+        // half precision and quantized embeddings are searched as halfvec
+        let scoring = VectorScoring::new(
+            &types::IndexDist::pgv_hnsw_cosine,
+            None,
+            VectorStorage::int8,
+        );
+        assert_eq!(scoring.order_by("e"), "e <=> $1::halfvec");
+    }
+
+    #[test]
+    fn test_chunk_retrieval() {
+        assert_eq!(
+            ChunkRetrieval::new(false, None).unwrap(),
+            ChunkRetrieval::Chunks
+        );
+        assert_eq!(
+            ChunkRetrieval::new(false, Some(1)).unwrap(),
+            ChunkRetrieval::Window(1)
+        );
+        assert!(ChunkRetrieval::new(true, Some(1)).is_err());
+        assert!(ChunkRetrieval::new(false, Some(-1)).is_err());
+        // a parent document search finds more chunks than results, as rows have several chunks
+        let columns = vec!["product_name".to_string()];
+        let (chunk_columns, num_chunks) = ChunkRetrieval::Parent.chunk_search(&columns, 5);
+        assert_eq!(chunk_columns, vec!["original_id", "chunk"]);
+        assert_eq!(num_chunks, 20);
+        let (chunk_columns, _) = ChunkRetrieval::Window(1).chunk_search(&columns, 5);
+        assert_eq!(chunk_columns, vec!["product_name", "chunk_id"]);
+
+        let chunking = types::Chunking {
+            schema: "public".to_string(),
+            table: "articles".to_string(),
+            primary_key: "article_id".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            parent_documents_query(&chunking, &["title".to_string(), "body".to_string()]),
+            "SELECT t0.article_id::text AS id, (SELECT to_jsonb(r) FROM (SELECT t0.title, t0.body) r) AS parent
+        FROM public.articles t0
+        WHERE t0.article_id::text = ANY($1)"
+        );
+    }
+
+    #[test]
+    fn test_join_chunks() {
+        // overlapping chunks share their text, adjacent chunks are joined as they are
+        let chunks = vec![
+            ("one two".to_string(), 0, 7),
+            ("two three".to_string(), 4, 13),
+            ("four".to_string(), 13, 17),
+            ("five".to_string(), 19, 23),
+        ];
+        assert_eq!(join_chunks(&chunks), "one two threefour\nfive");
+        assert_eq!(join_chunks(&[]), "");
+    }
+
+    #[test]
+    fn test_fuse_ranked() {
+        // ranks alternate between the lists, and the first list wins ties
+        assert_eq!(
+            fuse_ranked(vec![1, 2, 3], vec![10, 20], 4),
+            vec![1, 10, 2, 20]
+        );
+        assert_eq!(fuse_ranked(vec![1, 2], vec![], 3), vec![1, 2]);
+        assert_eq!(fuse_ranked(vec![], vec![10, 20], 1), vec![10]);
+    }
+
+    #[test]
+    fn test_lexical_search_query_unembedded() {
+        let job_params = types::JobParams {
+            schema: "public".to_string(),
+            table: "products".to_string(),
+            columns: vec!["product_name".to_string()],
+            primary_key: "product_id".to_string(),
+            pkey_type: "integer".to_string(),
+            ..Default::default()
+        };
+        let columns = vec!["product_id".to_string()];
+        let query = lexical_search_query(&job_params, &columns, 5, None, Some("my_job"));
+        assert!(query.contains(
+            "AND NOT EXISTS (SELECT 1 FROM vectorize._embeddings_my_job t1 WHERE t0.product_id = t1.product_id)"
+        ));
+        let job_params = types::JobParams {
+            table_method: TableMethod::append,
+            ..job_params
+        };
+        let query = lexical_search_query(&job_params, &columns, 5, None, Some("my_job"));
+        assert!(query.contains("AND t0.my_job_updated_at IS NULL"));
+        assert!(!lexical_search_query(&job_params, &columns, 5, None, None).contains("my_job"));
+    }
 }
@@ -1,18 +1,20 @@
 use crate::guc;
+use crate::registry;
 use anyhow::{Context, Result};
 
 use pgrx::prelude::*;
 
-use vectorize_core::transformers::providers::get_provider;
+use crate::transformers::get_provider;
+use vectorize_core::transformers::http_handler::with_headers;
 use vectorize_core::transformers::types::TransformerMetadata;
 use vectorize_core::types::Model;
 
 #[pg_extern]
 pub fn mod_info(model_name: &str, api_key: default!(Option<String>, "NULL")) -> pgrx::JsonB {
-    let transformer_model = Model::new(model_name)
+    let transformer_model = registry::resolve(model_name)
         .context("Invalid model name")
         .unwrap();
-    let mut guc_configs = guc::get_guc_configs(&transformer_model.source);
+    let mut guc_configs = guc::get_model_guc_configs(&transformer_model).unwrap();
     if let Some(key) = api_key {
         guc_configs.api_key = Some(key);
     }
@@ -29,7 +31,9 @@ pub fn sync_get_model_info(
         .enable_time()
         .build()
         .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
-    let meta = match runtime.block_on(async { get_model_info(model, guc_configs).await }) {
+    let meta = match runtime.block_on(with_headers(guc::headers(model)?, async {
+        get_model_info(model, guc_configs).await
+    })) {
         Ok(e) => e,
         Err(e) => {
             error!("error getting model info: {}", e);
@@ -43,12 +47,16 @@ pub async fn get_model_info(
     guc_configs: &guc::ModelGucConfig,
 ) -> Result<TransformerMetadata> {
     let provider = get_provider(
-        &model.source,
+        model,
         guc_configs.api_key.clone(),
         guc_configs.service_url.clone(),
         guc_configs.virtual_key.clone(),
+        None,
     )?;
-    let dim = provider.model_dim(&model.api_name()).await?;
+    let dim = match model.registered_dimensions() {
+        Some(d) => d,
+        None => provider.model_dim(&model.api_name()).await?,
+    };
     Ok(TransformerMetadata {
         model: model.api_name(),
         max_seq_len: 0,
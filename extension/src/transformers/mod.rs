@@ -1,45 +1,122 @@
 pub mod generic;
 pub mod http_handler;
+#[cfg(feature = "onnx")]
+pub mod onnx;
 pub mod openai;
 
 use crate::guc;
+use anyhow::Result;
 use pgrx::prelude::*;
 
-use vectorize_core::transformers::providers::{self, prepare_generic_embedding_request};
+use vectorize_core::transformers::http_handler;
+use vectorize_core::transformers::providers::{
+    self, batch, prepare_generic_embedding_request, EmbeddingProvider, InputType,
+};
+use vectorize_core::transformers::retry::RetryPolicy;
 use vectorize_core::transformers::types::Inputs;
 use vectorize_core::types::Model;
 
-pub fn transform(input: &str, transformer: &Model, api_key: Option<String>) -> Vec<Vec<f64>> {
+/// the provider that embeds with a model, in batches of vectorize.embedding_batch_sizes
+/// of which up to vectorize.max_concurrent_requests are sent at once
+/// failed requests are sent up to max_attempts times, or vectorize.max_attempts when it is not given
+/// with the onnx feature, sentence-transformers exported to vectorize.onnx_model_dir are run in-process
+pub fn get_provider(
+    model: &Model,
+    api_key: Option<String>,
+    url: Option<String>,
+    virtual_key: Option<String>,
+    max_attempts: Option<u32>,
+) -> Result<Box<dyn EmbeddingProvider>> {
+    #[cfg(feature = "onnx")]
+    if let Some(provider) = onnx::OnnxProvider::find(model)? {
+        return Ok(Box::new(provider));
+    }
+    let provider = providers::get_provider(&model.source, api_key, url, virtual_key)?;
+    let batch_size = batch::batch_size(
+        &model.source,
+        guc::get_guc(guc::VectorizeGuc::EmbeddingBatchSizes).as_deref(),
+    )?;
+    Ok(Box::new(batch::BatchedProvider::new(
+        provider,
+        &model.source,
+        batch_size,
+        guc::MAX_CONCURRENT_REQUESTS.get() as usize,
+        RetryPolicy::new(max_attempts.unwrap_or(guc::MAX_ATTEMPTS.get() as u32)),
+    )))
+}
+
+/// embeds a text with a model, with the api_key when it is given,
+/// and otherwise with the keys of the credential or the GUCs of the model's provider
+/// models that accept dimensions are asked for embeddings of them, others return their own
+pub fn transform(
+    input: &str,
+    transformer: &Model,
+    api_key: Option<String>,
+    credential: Option<&str>,
+    input_type: InputType,
+    dimensions: Option<u32>,
+) -> Result<Vec<Vec<f64>>> {
+    transform_batch(
+        &[input],
+        transformer,
+        api_key,
+        credential,
+        input_type,
+        dimensions,
+    )
+}
+
+/// embeds texts with a model in one request, or in batches of vectorize.embedding_batch_sizes when there are more
+/// the embeddings are in the order of the texts
+pub fn transform_batch(
+    inputs: &[&str],
+    transformer: &Model,
+    api_key: Option<String>,
+    credential: Option<&str>,
+    input_type: InputType,
+    dimensions: Option<u32>,
+) -> Result<Vec<Vec<f64>>> {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_io()
         .enable_time()
         .build()
         .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
 
-    let guc_configs: guc::ModelGucConfig = guc::get_guc_configs(&transformer.source);
+    let guc_configs: guc::ModelGucConfig = guc::get_job_guc_configs(transformer, credential)?;
     let api_key = if let Some(k) = api_key {
         Some(k)
     } else {
         guc_configs.api_key
     };
 
-    let provider = providers::get_provider(
-        &transformer.source,
+    let provider = get_provider(
+        transformer,
         api_key,
         guc_configs.service_url,
         guc_configs.virtual_key,
-    )
-    .expect("failed to get provider");
-    let input = Inputs {
-        record_id: "".to_string(),
-        inputs: input.to_string(),
-        token_estimate: 0,
-    };
-    let embedding_request = prepare_generic_embedding_request(transformer, &[input]);
-    match runtime.block_on(async { provider.generate_embedding(&embedding_request).await }) {
-        Ok(e) => e.embeddings,
-        Err(e) => {
-            error!("error getting embeddings: {}", e);
-        }
-    }
+        None,
+    )?;
+    let inputs: Vec<Inputs> = inputs
+        .iter()
+        .map(|input| Inputs {
+            record_id: "".to_string(),
+            inputs: input.to_string(),
+            token_estimate: 0,
+        })
+        .collect();
+    let mut embedding_request = prepare_generic_embedding_request(transformer, &inputs)
+        .with_dimensions(transformer, dimensions);
+    embedding_request.input_type = input_type;
+    let embeddings = runtime
+        .block_on(http_handler::with_headers(
+            guc::headers(transformer)?,
+            http_handler::with_proxies(
+                guc::proxies()?,
+                http_handler::with_timeouts(guc::timeouts(), async {
+                    provider.generate_embedding(&embedding_request).await
+                }),
+            ),
+        ))
+        .map_err(|e| anyhow::anyhow!("error getting embeddings: {}", e))?;
+    Ok(embeddings.embeddings)
 }
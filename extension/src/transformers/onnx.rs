@@ -0,0 +1,259 @@
+use crate::guc;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use ndarray::{Array2, ArrayView3, Ix3};
+use ort::session::Session;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+use vectorize_core::chunking::TokenCounter;
+use vectorize_core::errors::VectorizeError;
+use vectorize_core::transformers::providers::{
+    probe_model_dim, EmbeddingProvider, GenericEmbeddingRequest, GenericEmbeddingResponse,
+};
+use vectorize_core::types::{Model, ModelSource};
+
+const ONNX_BATCH_SIZE: usize = 32;
+// the longest input of sentence-transformers' BERT models, longer texts are truncated to it
+const MAX_SEQ_LEN: usize = 512;
+
+// models are loaded once per backend, and shared by the jobs and queries that use them
+static MODELS: OnceLock<Mutex<HashMap<PathBuf, Arc<OnnxModel>>>> = OnceLock::new();
+
+/// embeds texts with a sentence-transformer model exported to ONNX, in the Postgres backend
+/// a model is read from the directory of its name in vectorize.onnx_model_dir,
+/// e.g. <onnx_model_dir>/sentence-transformers/all-MiniLM-L6-v2/{model.onnx,tokenizer.json}
+pub struct OnnxProvider {
+    model: Arc<OnnxModel>,
+    path: PathBuf,
+}
+
+impl OnnxProvider {
+    /// the in-process provider of a model, when it is exported to vectorize.onnx_model_dir
+    pub fn find(model: &Model) -> Result<Option<Self>> {
+        if model.source != ModelSource::SentenceTransformers {
+            return Ok(None);
+        }
+        let Some(dir) = guc::get_guc(guc::VectorizeGuc::OnnxModelDir) else {
+            return Ok(None);
+        };
+        let path = Path::new(&dir).join(&model.fullname);
+        if !path.join("model.onnx").is_file() || !path.join("tokenizer.json").is_file() {
+            return Ok(None);
+        }
+        let mut models = MODELS
+            .get_or_init(Default::default)
+            .lock()
+            .map_err(|_| anyhow!("ONNX model cache is poisoned"))?;
+        let model = match models.get(&path) {
+            Some(model) => model.clone(),
+            None => {
+                let model = Arc::new(OnnxModel::load(&path)?);
+                models.insert(path.clone(), model.clone());
+                model
+            }
+        };
+        Ok(Some(OnnxProvider { model, path }))
+    }
+}
+
+// tokenizers that chunks are measured with, loaded once per backend
+static TOKENIZERS: OnceLock<Mutex<HashMap<PathBuf, &'static HfTokenizer>>> = OnceLock::new();
+
+/// measures chunks in the tokens of a model's own tokenizer
+/// read from tokenizer.json in the directory of the model's name in vectorize.onnx_model_dir
+pub struct HfTokenizer(Tokenizer);
+
+impl HfTokenizer {
+    /// the tokenizer of a model, when its tokenizer.json is in vectorize.onnx_model_dir
+    /// the model itself does not need to be exported to ONNX
+    pub fn find(model: &Model) -> Result<Option<&'static HfTokenizer>> {
+        let Some(dir) = guc::get_guc(guc::VectorizeGuc::OnnxModelDir) else {
+            return Ok(None);
+        };
+        let path = Path::new(&dir).join(&model.fullname).join("tokenizer.json");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let mut tokenizers = TOKENIZERS
+            .get_or_init(Default::default)
+            .lock()
+            .map_err(|_| anyhow!("tokenizer cache is poisoned"))?;
+        if let Some(tokenizer) = tokenizers.get(&path) {
+            return Ok(Some(tokenizer));
+        }
+        let tokenizer = Tokenizer::from_file(&path)
+            .map_err(|e| anyhow!("failed to load tokenizer {}: {e}", path.display()))?;
+        let tokenizer: &'static HfTokenizer = Box::leak(Box::new(HfTokenizer(tokenizer)));
+        tokenizers.insert(path, tokenizer);
+        Ok(Some(tokenizer))
+    }
+}
+
+impl TokenCounter for HfTokenizer {
+    fn count(&self, text: &str) -> usize {
+        self.token_offsets(text).len()
+    }
+
+    // the special tokens a model adds around its inputs are not part of the text
+    fn token_offsets(&self, text: &str) -> Vec<usize> {
+        match self.0.encode(text, false) {
+            Ok(encoding) => encoding
+                .get_offsets()
+                .iter()
+                .map(|(start, _)| *start)
+                .collect(),
+            // a text the tokenizer can not encode is measured as the other models' texts are
+            Err(_) => vectorize_core::chunking::tokenizer(None)
+                .map(|bpe| bpe.token_offsets(text))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+struct OnnxModel {
+    session: Session,
+    tokenizer: Tokenizer,
+    // not every export of a BERT model takes token type ids
+    token_type_ids: bool,
+}
+
+impl OnnxModel {
+    fn load(path: &Path) -> Result<Self> {
+        let mut tokenizer = Tokenizer::from_file(path.join("tokenizer.json"))
+            .map_err(|e| anyhow!("failed to load tokenizer of {}: {e}", path.display()))?;
+        // texts of a batch are padded to the longest one
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_SEQ_LEN,
+                ..Default::default()
+            }))
+            .map_err(|e| anyhow!("failed to configure tokenizer: {e}"))?;
+        // backends each run their own model, so one thread keeps them from competing for cores
+        let session = Session::builder()?
+            .with_intra_threads(1)?
+            .commit_from_file(path.join("model.onnx"))
+            .with_context(|| format!("failed to load ONNX model {}", path.display()))?;
+        let token_type_ids = session.inputs.iter().any(|i| i.name == "token_type_ids");
+        Ok(OnnxModel {
+            session,
+            tokenizer,
+            token_type_ids,
+        })
+    }
+
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f64>>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts, true)
+            .map_err(|e| anyhow!("failed to tokenize inputs: {e}"))?;
+        let shape = (
+            encodings.len(),
+            encodings.first().map(|e| e.len()).unwrap_or(0),
+        );
+        let tensor = |values: Vec<i64>| Array2::from_shape_vec(shape, values);
+        let input_ids = tensor(
+            encodings
+                .iter()
+                .flat_map(|e| e.get_ids().iter().map(|v| *v as i64))
+                .collect(),
+        )?;
+        let attention_mask = tensor(
+            encodings
+                .iter()
+                .flat_map(|e| e.get_attention_mask().iter().map(|v| *v as i64))
+                .collect(),
+        )?;
+        let outputs = if self.token_type_ids {
+            let token_type_ids = tensor(
+                encodings
+                    .iter()
+                    .flat_map(|e| e.get_type_ids().iter().map(|v| *v as i64))
+                    .collect(),
+            )?;
+            self.session.run(ort::inputs![
+                "input_ids" => input_ids.view(),
+                "attention_mask" => attention_mask.view(),
+                "token_type_ids" => token_type_ids.view(),
+            ]?)?
+        } else {
+            self.session.run(ort::inputs![
+                "input_ids" => input_ids.view(),
+                "attention_mask" => attention_mask.view(),
+            ]?)?
+        };
+        // the embedding of every token, of shape (batch, tokens, dimensions)
+        let hidden = outputs[0].try_extract_tensor::<f32>()?;
+        let hidden = hidden.into_dimensionality::<Ix3>()?;
+        Ok(mean_pool(hidden, &attention_mask))
+    }
+}
+
+/// averages the embeddings of each text's tokens, leaving out padding, and scales them to unit length
+/// as sentence-transformers' pooling and normalize modules do
+fn mean_pool(hidden: ArrayView3<f32>, attention_mask: &Array2<i64>) -> Vec<Vec<f64>> {
+    let (batch, tokens, dim) = hidden.dim();
+    (0..batch)
+        .map(|b| {
+            let mut pooled = vec![0.0; dim];
+            let mut count = 0.0;
+            for t in (0..tokens).filter(|t| attention_mask[[b, *t]] == 1) {
+                for (d, p) in pooled.iter_mut().enumerate() {
+                    *p += hidden[[b, t, d]] as f64;
+                }
+                count += 1.0;
+            }
+            if count > 0.0 {
+                pooled.iter_mut().for_each(|p| *p /= count);
+            }
+            let norm = pooled.iter().map(|p| p * p).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                pooled.iter_mut().for_each(|p| *p /= norm);
+            }
+            pooled
+        })
+        .collect()
+}
+
+#[async_trait]
+impl EmbeddingProvider for OnnxProvider {
+    async fn generate_embedding<'a>(
+        &self,
+        request: &'a GenericEmbeddingRequest,
+    ) -> Result<GenericEmbeddingResponse, VectorizeError> {
+        let request = request.clone().with_instructions();
+        let mut embeddings = Vec::with_capacity(request.input.len());
+        for inputs in request.input.chunks(ONNX_BATCH_SIZE) {
+            embeddings.extend(self.model.embed(inputs.to_vec())?);
+        }
+        Ok(GenericEmbeddingResponse { embeddings })
+    }
+
+    fn endpoint(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    async fn model_dim(&self, model_name: &str) -> Result<u32, VectorizeError> {
+        probe_model_dim(self, model_name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array3;
+
+    #[test]
+    fn test_mean_pool() {
+        // two texts of two tokens, the second padded after its first token
+        let hidden =
+            Array3::from_shape_vec((2, 2, 2), vec![2.0, 4.0, 4.0, 4.0, 0.0, 5.0, 100.0, 100.0])
+                .unwrap();
+        let mask = Array2::from_shape_vec((2, 2), vec![1, 1, 1, 0]).unwrap();
+        let pooled = mean_pool(hidden.view(), &mask);
+        assert_eq!(pooled, vec![vec![0.6, 0.8], vec![0.0, 1.0]]);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::compat::{self, arg};
+use crate::util;
+
+use anyhow::{anyhow, bail, Result};
+use pgrx::prelude::*;
+use vectorize_core::types::{JobParams, TableMethod, Ttl};
+
+// how often pg_cron purges the expired embeddings of a job with a ttl
+const PURGE_SCHEDULE: &str = "*/5 * * * *";
+
+/// name of the pg_cron job purging a job's expired embeddings
+pub fn ttl_cron_name(job_name: &str) -> String {
+    format!("vectorize_ttl_{job_name}")
+}
+
+/// the command pg_cron runs to purge a job's expired embeddings
+pub fn purge_command(job_name: &str) -> String {
+    format!("SELECT vectorize._purge_expired('{job_name}')")
+}
+
+/// sets how long a job keeps its embeddings, or keeps them indefinitely when ttl is None
+/// with delete_source, a collection's expired documents are deleted along with their embeddings
+pub fn set_ttl(job_name: &str, ttl: Option<&str>, delete_source: bool) -> Result<String> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let mut job_params: JobParams = serde_json::from_value(meta.params)?;
+    if delete_source && !job_params.collection {
+        bail!(
+            "delete_source is only supported for collections, job {job_name} is not a collection"
+        );
+    }
+    job_params.ttl = match ttl {
+        None => None,
+        Some(ttl) => {
+            // the interval is stored in its canonical form, as it is interpolated into queries
+            let interval: Option<String> = compat::get_one(
+                "SELECT CASE WHEN $1::interval > '0'::interval THEN $1::interval::text END",
+                vec![arg(ttl)],
+            )?;
+            Some(Ttl {
+                interval: interval
+                    .ok_or_else(|| anyhow!("ttl must be a positive interval: {ttl}"))?,
+                delete_source,
+            })
+        }
+    };
+
+    let params = pgrx::JsonB(serde_json::to_value(&job_params)?);
+    let cron_name = ttl_cron_name(job_name);
+    compat::connect(|mut c| {
+        compat::update(
+            &mut c,
+            "UPDATE vectorize.job SET params = $2 WHERE name = $1",
+            vec![arg(job_name), arg(params)],
+        )?;
+        match &job_params.ttl {
+            Some(_) => compat::update(
+                &mut c,
+                "SELECT cron.schedule($1, $2, $3)",
+                vec![
+                    arg(cron_name.as_str()),
+                    arg(PURGE_SCHEDULE),
+                    arg(purge_command(job_name)),
+                ],
+            )?,
+            None => compat::update(
+                &mut c,
+                "SELECT cron.unschedule(jobid) FROM cron.job WHERE jobname = $1",
+                vec![arg(cron_name.as_str())],
+            )?,
+        };
+        Ok::<_, spi::Error>(())
+    })?;
+    Ok(match job_params.ttl {
+        Some(ttl) => format!("Embeddings of job {job_name} expire after {}", ttl.interval),
+        None => format!("Embeddings of job {job_name} no longer expire"),
+    })
+}
+
+// returns the number of rows purged, the ttl interval is bound to $1
+fn purge_query(job_name: &str, job_params: &JobParams, ttl: &Ttl) -> Result<String> {
+    let schema = &job_params.schema;
+    let table = &job_params.table;
+    let purge = if ttl.delete_source {
+        // the embeddings of deleted documents are removed by ON DELETE CASCADE
+        let updated_at = job_params
+            .update_time_col
+            .as_ref()
+            .ok_or_else(|| anyhow!("collection {job_name} has no update time column"))?;
+        format!(
+            "DELETE FROM {schema}.{table} WHERE {updated_at} < NOW() - $1::interval RETURNING 1"
+        )
+    } else {
+        match job_params.table_method {
+            TableMethod::join => format!(
+                "DELETE FROM {embeddings_schema}._embeddings_{job_name}
+                WHERE updated_at < NOW() - $1::interval RETURNING 1",
+                embeddings_schema = job_params.embeddings_schema(),
+            ),
+            // the update trigger only fires for changes to the job's columns, so this does not re-embed the rows
+            TableMethod::append => format!(
+                "UPDATE {schema}.{table} SET {job_name}_embeddings = NULL, {job_name}_updated_at = NULL
+                WHERE {job_name}_updated_at < NOW() - $1::interval RETURNING 1"
+            ),
+        }
+    };
+    Ok(format!(
+        "WITH purged AS ({purge}) SELECT count(*) FROM purged"
+    ))
+}
+
+/// called by pg_cron for jobs with a ttl
+/// purges the embeddings that were written longer than the ttl ago
+#[pg_extern]
+fn _purge_expired(job_name: &str) -> Result<i64> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: JobParams = serde_json::from_value(meta.params)?;
+    let ttl = match &job_params.ttl {
+        Some(ttl) => ttl,
+        None => return Ok(0),
+    };
+    let purged: i64 = compat::get_one(
+        &purge_query(job_name, &job_params, ttl)?,
+        vec![arg(ttl.interval.as_str())],
+    )?
+    .unwrap_or(0);
+    compat::run(
+        "DELETE FROM vectorize.embedding_provenance
+        WHERE job_name = $1 AND embedded_at < NOW() - $2::interval",
+        vec![arg(job_name), arg(ttl.interval.as_str())],
+    )?;
+    if purged > 0 {
+        log!("pg-vectorize: purged {purged} expired rows of job {job_name}");
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_purge_query() {
+        let mut job_params = JobParams {
+            schema: "public".to_string(),
+            table: "transcripts".to_string(),
+            update_time_col: Some("last_updated_at".to_string()),
+            ..Default::default()
+        };
+        let mut ttl = Ttl {
+            interval: "30 days".to_string(),
+            delete_source: false,
+        };
+        let query = purge_query("chats", &job_params, &ttl).unwrap();
+        assert!(query.starts_with("WITH purged AS (DELETE FROM vectorize._embeddings_chats"));
+        assert!(query.contains("WHERE updated_at < NOW() - $1::interval RETURNING 1"));
+
+        job_params.table_method = TableMethod::append;
+        let query = purge_query("chats", &job_params, &ttl).unwrap();
+        assert!(query.contains(
+            "UPDATE public.transcripts SET chats_embeddings = NULL, chats_updated_at = NULL"
+        ));
+
+        ttl.delete_source = true;
+        let query = purge_query("chats", &job_params, &ttl).unwrap();
+        assert!(query.contains(
+            "DELETE FROM public.transcripts WHERE last_updated_at < NOW() - $1::interval"
+        ));
+        job_params.update_time_col = None;
+        assert!(purge_query("chats", &job_params, &ttl).is_err());
+    }
+
+    #[test]
+    fn test_expired_rows_not_embedded() {
+        let mut job_params = JobParams {
+            schema: "public".to_string(),
+            table: "transcripts".to_string(),
+            columns: vec!["body".to_string()],
+            primary_key: "id".to_string(),
+            pkey_type: "text".to_string(),
+            update_time_col: Some("last_updated_at".to_string()),
+            ..Default::default()
+        };
+        let query = crate::executor::new_rows_query_join("chats", &job_params);
+        assert!(!query.contains("interval"));
+
+        job_params.ttl = Some(Ttl {
+            interval: "30 days".to_string(),
+            delete_source: false,
+        });
+        let query = crate::executor::new_rows_query_join("chats", &job_params);
+        assert!(query.contains("WHERE (t1.id IS NULL"));
+        assert!(query.contains("AND t0.last_updated_at >= NOW() - '30 days'::interval"));
+        let query = crate::executor::new_rows_query("chats", &job_params);
+        assert!(query.contains("AND last_updated_at >= NOW() - '30 days'::interval"));
+    }
+}
@@ -48,7 +48,12 @@ pub enum IndexDist {
     pgv_hnsw_l2,
     pgv_hnsw_ip,
     pgv_hnsw_cosine,
+    pgv_ivfflat_l2,
+    pgv_ivfflat_ip,
+    pgv_ivfflat_cosine,
     vsc_diskann_cosine,
+    vsc_diskann_l2,
+    vsc_diskann_ip,
 }
 
 impl From<IndexDist> for CoreIndexDist {
@@ -57,7 +62,12 @@ impl From<IndexDist> for CoreIndexDist {
             IndexDist::pgv_hnsw_l2 => CoreIndexDist::pgv_hnsw_l2,
             IndexDist::pgv_hnsw_ip => CoreIndexDist::pgv_hnsw_ip,
             IndexDist::pgv_hnsw_cosine => CoreIndexDist::pgv_hnsw_cosine,
+            IndexDist::pgv_ivfflat_l2 => CoreIndexDist::pgv_ivfflat_l2,
+            IndexDist::pgv_ivfflat_ip => CoreIndexDist::pgv_ivfflat_ip,
+            IndexDist::pgv_ivfflat_cosine => CoreIndexDist::pgv_ivfflat_cosine,
             IndexDist::vsc_diskann_cosine => CoreIndexDist::vsc_diskann_cosine,
+            IndexDist::vsc_diskann_l2 => CoreIndexDist::vsc_diskann_l2,
+            IndexDist::vsc_diskann_ip => CoreIndexDist::vsc_diskann_ip,
         }
     }
 }
@@ -0,0 +1,69 @@
+use crate::compat::{self, arg};
+
+use anyhow::{bail, Result};
+use pgrx::prelude::*;
+
+/// the calls a job made to a model in a day, their tokens and their estimated cost
+pub struct Usage {
+    pub day: Date,
+    pub job_name: String,
+    pub provider: String,
+    pub model: String,
+    pub calls: i64,
+    pub tokens_in: i64,
+    pub tokens_out: i64,
+    // None when the model has no price in vectorize.model_prices
+    pub cost: Option<f64>,
+}
+
+/// the usage of the last days, of every job or of one, most recent first
+pub fn usage_report(job_name: Option<&str>, days: i32) -> Result<Vec<Usage>> {
+    if days < 1 {
+        bail!("days must be greater than 0");
+    }
+    Ok(compat::connect(|client| {
+        let tup_table = compat::select(
+            &client,
+            "SELECT day, job_name, provider, model, calls, tokens_in, tokens_out, cost
+            FROM vectorize.usage
+            WHERE day > current_date - $1 AND ($2::text IS NULL OR job_name = $2)
+            ORDER BY day DESC, job_name, model",
+            vec![arg(days), arg(job_name)],
+        )?;
+        let mut usage = Vec::new();
+        for row in tup_table {
+            let Some(day) = row["day"].value()? else {
+                continue;
+            };
+            usage.push(Usage {
+                day,
+                job_name: row["job_name"].value()?.unwrap_or_default(),
+                provider: row["provider"].value()?.unwrap_or_default(),
+                model: row["model"].value()?.unwrap_or_default(),
+                calls: row["calls"].value()?.unwrap_or_default(),
+                tokens_in: row["tokens_in"].value()?.unwrap_or_default(),
+                tokens_out: row["tokens_out"].value()?.unwrap_or_default(),
+                cost: row["cost"].value()?,
+            });
+        }
+        Ok::<_, spi::Error>(usage)
+    })?)
+}
+
+/// sets the price of a model, in USD per million tokens sent to it and received from it
+/// usage recorded from then on is costed at the new price
+pub fn set_model_price(model: &str, input_price: f64, output_price: f64) -> Result<String> {
+    if input_price < 0.0 || output_price < 0.0 {
+        bail!("prices must not be negative");
+    }
+    compat::run(
+        "INSERT INTO vectorize.model_prices (model, input_price, output_price)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (model)
+        DO UPDATE SET input_price = EXCLUDED.input_price, output_price = EXCLUDED.output_price",
+        vec![arg(model), arg(input_price), arg(output_price)],
+    )?;
+    Ok(format!(
+        "Set price of {model}: {input_price} in, {output_price} out per million tokens"
+    ))
+}
@@ -6,7 +6,9 @@ use sqlx::{Pool, Postgres};
 use std::env;
 use url::{ParseError, Url};
 
+use crate::compat::{self, arg};
 use crate::guc;
+use crate::registry;
 use vectorize_core::types::{self, Model};
 
 #[derive(Clone, Debug)]
@@ -72,12 +74,8 @@ pub fn get_vectorize_meta_spi(job_name: &str) -> Result<types::VectorizeMeta> {
         FROM vectorize.job
         WHERE name = $1
     ";
-    let result: Result<types::VectorizeMeta> = Spi::connect(|client| {
-        let tup_table: SpiTupleTable = client.select(
-            query,
-            Some(1),
-            Some(vec![(PgBuiltInOids::TEXTOID.oid(), job_name.into_datum())]),
-        )?;
+    let result: Result<types::VectorizeMeta> = compat::connect(|client| {
+        let tup_table: SpiTupleTable = compat::select(&client, query, vec![arg(job_name)])?;
         if tup_table.is_empty() {
             return Err(anyhow::anyhow!(
                 "project '{}' not yet initialized. Please initialize the project.",
@@ -107,7 +105,7 @@ pub fn get_vectorize_meta_spi(job_name: &str) -> Result<types::VectorizeMeta> {
             .expect("params column does not exist.")
             .expect("params column was null.");
 
-        let transformer_model = Model::new(&transformer)?;
+        let transformer_model = registry::resolve(&transformer)?;
         Ok(types::VectorizeMeta {
             job_id,
             name,
@@ -120,7 +118,24 @@ pub fn get_vectorize_meta_spi(job_name: &str) -> Result<types::VectorizeMeta> {
     result
 }
 
+/// replaces a job's params in vectorize.job
+pub fn update_job_params(job_name: &str, job_params: &types::JobParams) -> Result<()> {
+    compat::run(
+        "UPDATE vectorize.job SET params = $2 WHERE name = $1",
+        vec![
+            arg(job_name),
+            arg(pgrx::JsonB(serde_json::to_value(job_params)?)),
+        ],
+    )?;
+    Ok(())
+}
+
 pub async fn get_pg_conn() -> Result<Pool<Postgres>> {
+    get_pg_pool(4).await
+}
+
+/// a pool of connections to the database set by vectorize.database_name, or the GUC defaults
+pub async fn get_pg_pool(max_connections: u32) -> Result<Pool<Postgres>> {
     let mut cfg = Config::default();
 
     if let Some(host) = guc::get_guc(guc::VectorizeGuc::Host) {
@@ -138,7 +153,7 @@ pub async fn get_pg_conn() -> Result<Pool<Postgres>> {
 
     let pgp = PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(4))
-        .max_connections(4)
+        .max_connections(max_connections)
         .connect_with(opts)
         .await?;
     Ok(pgp)
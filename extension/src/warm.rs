@@ -0,0 +1,165 @@
+use crate::compat::{self, arg};
+use crate::guc;
+use crate::init;
+use crate::transformers::get_provider;
+use crate::util;
+
+use anyhow::Result;
+use pgrx::prelude::*;
+use sqlx::{Pool, Postgres};
+use std::time::{Duration, Instant};
+use vectorize_core::transformers::http_handler::{with_headers, with_proxies, with_timeouts};
+use vectorize_core::transformers::providers::{probe_model_dim, warmup_models};
+use vectorize_core::types::{JobParams, Model};
+
+// number of neighbours fetched by each warm-up search
+const WARM_SEARCH_LIMIT: i32 = 10;
+
+/// loads a job's index and embeddings into memory, so that the first searches after a restart or failover
+/// are not slowed down by reading them from disk
+/// with pg_prewarm installed, every page is loaded into shared buffers
+/// either way, searches are then run for num_queries of the stored embeddings
+/// returns each relation that was warmed, with the number of blocks loaded, NULL without pg_prewarm,
+/// and the number of searches that were run
+pub fn prewarm_index(job_name: &str, num_queries: i32) -> Result<Vec<(String, Option<i64>, i64)>> {
+    let meta = util::get_vectorize_meta_spi(job_name)?;
+    let job_params: JobParams = serde_json::from_value(meta.params)?;
+    let (schema, table, embeddings_col) = init::embeddings_location(job_name, &job_params);
+    let index = format!(
+        "{schema}.{}",
+        init::index_name(job_name, &meta.index_dist_type)
+    );
+
+    let has_prewarm: bool = compat::get_one(
+        "SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'pg_prewarm')",
+        vec![],
+    )?
+    .unwrap_or(false);
+
+    let mut warmed = Vec::new();
+    for relation in [index, format!("{schema}.{table}")] {
+        for leaf in leaf_relations(&relation)? {
+            let blocks: Option<i64> = if has_prewarm {
+                compat::get_one("SELECT pg_prewarm($1::regclass)", vec![arg(leaf.clone())])?
+            } else {
+                None
+            };
+            warmed.push((leaf, blocks));
+        }
+    }
+
+    // searches for stored embeddings walk the same parts of the index as searches for similar queries
+    let searched: i64 = compat::get_one(
+        &format!(
+            "SELECT count(*) FROM (
+                SELECT {searchable} AS q FROM {schema}.{table}
+                WHERE {embeddings_col} IS NOT NULL
+                ORDER BY random()
+                LIMIT $1
+            ) queries
+            CROSS JOIN LATERAL (
+                SELECT count(*) FROM (
+                    SELECT 1 FROM {schema}.{table}
+                    ORDER BY {searchable} {op} queries.q
+                    LIMIT {WARM_SEARCH_LIMIT}
+                ) n
+            ) neighbours",
+            op = meta.index_dist_type.distance_operator(),
+            searchable = job_params.searchable_embeddings(&embeddings_col),
+        ),
+        vec![arg(num_queries)],
+    )?
+    .unwrap_or(0);
+    Ok(warmed
+        .into_iter()
+        .map(|(relation, blocks)| (relation, blocks, searched))
+        .collect())
+}
+
+// the partitions of a partitioned table or index, or the relation itself
+fn leaf_relations(relation: &str) -> Result<Vec<String>> {
+    compat::connect(|client| {
+        let mut leaves = Vec::new();
+        let tup_table = compat::select(
+            &client,
+            "SELECT relid::regclass::text AS leaf
+            FROM pg_partition_tree(to_regclass($1))
+            WHERE isleaf",
+            vec![arg(relation)],
+        )?;
+        for row in tup_table {
+            leaves.extend(row["leaf"].value::<String>()?);
+        }
+        Ok(leaves)
+    })
+}
+
+/// warms every job, when vectorize.warm_on_startup is on
+/// run by the background worker once the extension is ready
+pub async fn warm_all_jobs(conn: &Pool<Postgres>) -> Result<()> {
+    let jobs: Vec<String> = sqlx::query_scalar("SELECT name FROM vectorize.job ORDER BY name")
+        .fetch_all(conn)
+        .await?;
+    for job_name in jobs {
+        match sqlx::query("SELECT * FROM vectorize.prewarm_index($1)")
+            .bind(&job_name)
+            .execute(conn)
+            .await
+        {
+            Ok(_) => log!("pg-vectorize: warmed job {job_name}"),
+            Err(e) => warning!("pg-vectorize: failed to warm job {job_name}: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// sends an embedding request to a model, so that a locally served model is loaded before the first texts
+/// of its jobs are sent to it
+/// models run in-process with the onnx feature are loaded into the calling backend, others by the service serving them
+/// returns the dimensions of the model's embeddings and how long the request took
+pub fn warm_up_model(model: &Model) -> Result<(u32, Duration)> {
+    let guc_configs = guc::get_model_guc_configs(model)?;
+    let provider = get_provider(
+        model,
+        guc_configs.api_key,
+        guc_configs.service_url,
+        guc_configs.virtual_key,
+        Some(1),
+    )?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap_or_else(|e| error!("failed to initialize tokio runtime: {}", e));
+    let proxies = guc::proxies()?;
+    let headers = guc::headers(model)?;
+    let started = Instant::now();
+    let dim = runtime.block_on(with_headers(
+        headers,
+        with_proxies(
+            proxies,
+            with_timeouts(
+                guc::timeouts(),
+                probe_model_dim(provider.as_ref(), &model.api_name()),
+            ),
+        ),
+    ))?;
+    Ok((dim, started.elapsed()))
+}
+
+/// warms up every model of vectorize.warmup_models
+/// run by each background worker once the extension is ready, as each loads its own in-process models
+pub fn warm_up_models() {
+    for name in warmup_models(guc::get_guc(guc::VectorizeGuc::WarmupModels).as_deref()) {
+        match Model::new(name)
+            .map_err(anyhow::Error::from)
+            .and_then(|model| warm_up_model(&model))
+        {
+            Ok((dim, took)) => log!(
+                "pg-vectorize: warmed up {name} in {} ms, {dim} dimensions",
+                took.as_millis()
+            ),
+            Err(e) => warning!("pg-vectorize: failed to warm up {name}: {e}"),
+        }
+    }
+}
@@ -1,16 +1,19 @@
 pub mod pg_bgw;
 
-use crate::guc::{get_guc_configs, ModelGucConfig};
+use crate::guc::{self, get_model_guc_configs, ModelGucConfig};
+use crate::transformers;
 
 use anyhow::Result;
 use pgmq::{Message, PGMQueueExt};
 use pgrx::*;
 use sqlx::{Pool, Postgres};
-use vectorize_core::transformers::http_handler;
-use vectorize_core::transformers::providers;
+use vectorize_core::preprocess;
+use vectorize_core::transformers::http_handler::{with_headers, with_proxies, with_timeouts};
+use vectorize_core::transformers::providers::{self, batch};
 use vectorize_core::transformers::types::PairedEmbeddings;
 use vectorize_core::types;
 use vectorize_core::worker::ops;
+use vectorize_core::worker::rate_limit::{self, RateLimits};
 
 pub async fn run_worker(
     queue: PGMQueueExt,
@@ -36,7 +39,57 @@ pub async fn run_worker(
         "pg-vectorize: received message for job: {:?}",
         msg.message.job_name
     );
-    let job_success = execute_job(conn.clone(), msg).await;
+    let job_name = &msg.message.job_name;
+    let source = &msg.message.job_meta.transformer.source;
+    if ops::is_job_paused(conn, job_name).await?
+        || ops::is_over_budget(conn, job_name, source).await?
+        || ops::is_provider_down(conn, source).await?
+    {
+        // hold the message back, re-sending it so that time paused does not count against retries
+        // over budget jobs are held back until the budget window resets or the cap is raised
+        // and jobs of a provider that failed its health check until it is checked again, or a minute has passed
+        info!(
+            "pg-vectorize: job {} is paused, over budget or its provider is down, deferring message: {}",
+            job_name, msg_id
+        );
+        queue
+            .send_delay(queue_name, &msg.message, ops::PAUSED_JOB_DELAY)
+            .await?;
+        queue.delete(queue_name, msg_id).await?;
+        return Ok(Some(()));
+    }
+    let limits = RateLimits::of(
+        source,
+        guc::get_guc(guc::VectorizeGuc::RequestsPerMinute).as_deref(),
+        guc::get_guc(guc::VectorizeGuc::TokensPerMinute).as_deref(),
+    )?;
+    let batch_size = batch::batch_size(
+        source,
+        guc::get_guc(guc::VectorizeGuc::EmbeddingBatchSizes).as_deref(),
+    )?;
+    if let Some(delay) =
+        rate_limit::claim(conn, source, &limits, &msg.message.inputs, batch_size).await?
+    {
+        // held back until the provider's minute is over, without counting against the retries
+        info!(
+            "pg-vectorize: {} is over its rate limit, deferring message {} for {}s",
+            source, msg_id, delay
+        );
+        queue.send_delay(queue_name, &msg.message, delay).await?;
+        queue.delete(queue_name, msg_id).await?;
+        return Ok(Some(()));
+    }
+    let job_params: types::JobParams = serde_json::from_value(msg.message.job_meta.params.clone())?;
+    let timeouts = guc::timeouts().for_job(&job_params);
+    let headers = guc::headers(&msg.message.job_meta.transformer)?;
+    let job_success = with_headers(
+        headers,
+        with_proxies(
+            guc::proxies()?,
+            with_timeouts(timeouts, execute_job(conn.clone(), msg, queue_name)),
+        ),
+    )
+    .await;
     let delete_it = match job_success {
         Ok(_) => {
             info!("pg-vectorize: job success");
@@ -64,50 +117,118 @@ pub async fn run_worker(
     Ok(Some(()))
 }
 
-async fn execute_job(dbclient: Pool<Postgres>, msg: Message<types::JobMessage>) -> Result<()> {
+async fn execute_job(
+    dbclient: Pool<Postgres>,
+    msg: Message<types::JobMessage>,
+    queue_name: &str,
+) -> Result<()> {
+    let trace_id = ops::trace_id(queue_name, msg.msg_id);
     let job_meta = msg.message.job_meta;
     let mut job_params: types::JobParams = serde_json::from_value(job_meta.params.clone())?;
 
-    let embedding_request =
-        providers::prepare_generic_embedding_request(&job_meta.transformer, &msg.message.inputs);
+    let inputs = if msg.message.semantic_chunking {
+        msg.message.inputs
+    } else {
+        // jobs with encrypted columns are queued without their input text
+        let inputs = ops::decrypt_inputs(&dbclient, &job_params, msg.message.inputs).await?;
+        preprocess::preprocess_inputs(&job_params, inputs)
+    };
+    if inputs.is_empty() {
+        return Ok(());
+    }
 
-    let guc_configs: ModelGucConfig = get_guc_configs(&job_meta.transformer.source);
+    let mut guc_configs: ModelGucConfig = get_model_guc_configs(&job_meta.transformer)?;
+    // a job's own credential takes the place of its provider's GUCs
+    if let Some(name) = &job_params.credential {
+        let credential = ops::get_credential(&dbclient, name).await?;
+        guc_configs = guc_configs.with_credential(&job_meta.transformer, credential);
+    }
 
     // if api_key found in GUC, then use that and re-assign
     if let Some(k) = guc_configs.api_key {
         job_params.api_key = Some(k);
     }
 
-    let provider = providers::get_provider(
-        &job_meta.transformer.source,
+    let provider = transformers::get_provider(
+        &job_meta.transformer,
         job_params.api_key.clone(),
         guc_configs.service_url,
         guc_configs.virtual_key,
+        job_params.max_attempts,
     )?;
 
-    let embedding_response = provider.generate_embedding(&embedding_request).await?;
-    let paired_embeddings: Vec<PairedEmbeddings> =
-        http_handler::merge_input_output(msg.message.inputs, embedding_response.embeddings);
+    if msg.message.semantic_chunking {
+        return ops::write_semantic_chunks(
+            &dbclient,
+            provider.as_ref(),
+            &job_meta.transformer,
+            &job_meta.name,
+            &job_params,
+            inputs,
+        )
+        .await;
+    }
 
-    log!("pg-vectorize: embeddings size: {}", paired_embeddings.len());
-    // write embeddings to result table
-    match job_params.clone().table_method {
-        types::TableMethod::append => {
-            ops::update_embeddings(
-                &dbclient,
-                &job_params.schema,
-                &job_params.table,
-                &job_meta.clone().name,
-                &job_params.primary_key,
-                &job_params.pkey_type,
-                paired_embeddings,
-            )
-            .await?;
-        }
-        types::TableMethod::join => {
-            ops::upsert_embedding_table(&dbclient, &job_meta.name, &job_params, paired_embeddings)
-                .await?
-        }
+    // the chunks of a chunked job whose text is already embedded are not embedded again,
+    // nor with vectorize.embedding_cache are texts the model embedded for any job
+    let cache = guc::EMBEDDING_CACHE.get();
+    let deduped = ops::dedup_chunks(
+        &dbclient,
+        &job_meta.name,
+        &job_meta.transformer,
+        &job_params,
+        inputs,
+        cache,
+    )
+    .await?;
+    let (inputs, embeddings) = if job_params.is_weighted() {
+        providers::generate_weighted_embeddings(
+            provider.as_ref(),
+            &job_meta.transformer,
+            &job_params,
+            deduped.to_embed.clone(),
+        )
+        .await?
+    } else if deduped.to_embed.is_empty() {
+        (vec![], vec![])
+    } else {
+        let mut embedding_request =
+            providers::prepare_generic_embedding_request(&job_meta.transformer, &deduped.to_embed)
+                .with_dimensions(&job_meta.transformer, job_params.truncated_dimensions());
+        embedding_request.content_type = job_params.content_type;
+        let embedding_response = provider.generate_embedding(&embedding_request).await?;
+        (deduped.to_embed.clone(), embedding_response.embeddings)
     };
+    if cache && !job_params.is_weighted() {
+        ops::cache_embeddings(&dbclient, &job_meta.transformer, &inputs, &embeddings).await?;
+    }
+    let embeddings = providers::fit_dimensions(embeddings, &job_params)?;
+    ops::record_token_usage(&dbclient, &job_meta.name, &job_meta.transformer, &inputs).await?;
+    ops::record_chunk_stats(
+        &dbclient,
+        &job_meta.name,
+        &job_params,
+        &inputs,
+        deduped.savings(),
+    )
+    .await?;
+    let (inputs, paired_embeddings): (_, Vec<PairedEmbeddings>) = deduped.merge(inputs, embeddings);
+
+    log!(
+        "pg-vectorize: embeddings size: {}, trace id: {trace_id}",
+        paired_embeddings.len()
+    );
+    // write embeddings to result table, along with their provenance
+    ops::write_embeddings(
+        &dbclient,
+        &job_meta.name,
+        &job_params,
+        &job_meta.transformer,
+        &provider.endpoint(),
+        &trace_id,
+        &inputs,
+        paired_embeddings,
+    )
+    .await?;
     Ok(())
 }
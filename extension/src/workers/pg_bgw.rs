@@ -1,6 +1,8 @@
-use crate::guc::{init_guc, NUM_BGW_PROC};
-use crate::init::VECTORIZE_QUEUE;
+use crate::export::run_export_worker;
+use crate::guc::{init_guc, NUM_BGW_PROC, WARM_ON_STARTUP};
+use crate::init::{VECTORIZE_EXPORT_QUEUE, VECTORIZE_QUEUE};
 use crate::util::{get_pg_conn, ready};
+use crate::warm::{warm_all_jobs, warm_up_models};
 use pgrx::bgworkers::*;
 use pgrx::*;
 use std::time::Duration;
@@ -49,21 +51,40 @@ pub extern "C" fn background_worker_main(_arg: pg_sys::Datum) {
             debug5!("pg-vectorize-bgw: waiting for first pg-vectorize job to be created");
             runtime.block_on(async {
                 ext_ready = ready(&conn).await;
+                if ext_ready {
+                    // search exports are delivered from their own queue
+                    if let Err(e) = queue.create(VECTORIZE_EXPORT_QUEUE).await {
+                        warning!("pg-vectorize: failed to create export queue: {e}");
+                    }
+                    // one worker is enough to warm the jobs
+                    if WARM_ON_STARTUP.get() && BackgroundWorker::get_name() == "pg-vectorize-bgw-0"
+                    {
+                        if let Err(e) = warm_all_jobs(&conn).await {
+                            warning!("pg-vectorize: failed to warm jobs: {e}");
+                        }
+                    }
+                }
             });
+            // the requests are sent from a runtime of their own, so outside of the worker's
+            if ext_ready {
+                warm_up_models();
+            }
             // return to wait_latch if extension is not ready
             continue;
         }
 
         wait_duration = runtime.block_on(async {
-            let wait_dur = match run_worker(queue.clone(), &conn, VECTORIZE_QUEUE).await {
-                // no messages in queue, so wait 2 seconds
-                Ok(None) => 2000,
-                // wait 10 seconds between polls when there is a failure
-                Err(_) => 10000,
-                // when there was a successfully processed message from queue,
+            let jobs = run_worker(queue.clone(), &conn, VECTORIZE_QUEUE).await;
+            let exports = run_export_worker(queue.clone()).await;
+            let wait_dur = match (jobs, exports) {
+                // when there was a successfully processed message from either queue,
                 // only wait 10ms before checking for more messages
                 // this allows postgres to kill or restart the bgw in between messages
-                Ok(Some(_)) => 10,
+                (Ok(Some(_)), _) | (_, Ok(Some(_))) => 10,
+                // wait 10 seconds between polls when there is a failure
+                (Err(_), _) | (_, Err(_)) => 10000,
+                // no messages in queue, so wait 2 seconds
+                (Ok(None), Ok(None)) => 2000,
             };
             Duration::from_millis(wait_dur)
         });
@@ -104,7 +104,7 @@ async fn test_scheduled_single_table() {
 
 #[ignore]
 #[tokio::test]
-async fn test_realtime_append_fail() {
+async fn test_realtime_append() {
     let conn = common::init_database().await;
     let mut rng = rand::thread_rng();
     let test_num = rng.gen_range(1..100000);
@@ -114,7 +114,7 @@ async fn test_realtime_append_fail() {
 
     common::init_embedding_svc_url(&conn).await;
     // initialize a job
-    let result = sqlx::query(&format!(
+    sqlx::query(&format!(
         "SELECT vectorize.table(
         job_name => '{job_name}',
         \"table\" => '{test_table_name}',
@@ -126,9 +126,70 @@ async fn test_realtime_append_fail() {
     );"
     ))
     .execute(&conn)
-    .await;
-    // realtime + append is not supported
-    assert!(result.is_err());
+    .await
+    .expect("failed to init job");
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    // a new row is embedded into its own embeddings column by the trigger
+    let random_product_id = rng.gen_range(0..100000);
+    sqlx::query(&format!(
+        "INSERT INTO \"{test_table_name}\"(product_id, product_name, description, product_category, price)
+        VALUES ({random_product_id}, 'car tester', $$a product for testing car's components$$, 'electronics', 10.99);"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert into test_table");
+    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+    let embedded: bool = sqlx::query_scalar(&format!(
+        "SELECT {job_name}_embeddings IS NOT NULL FROM \"{test_table_name}\" WHERE product_id = {random_product_id}"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to select from test_table");
+    assert!(embedded);
+
+    // changing the row's input re-embeds it, writing its embeddings does not trigger again
+    let embedded_at = format!(
+        "SELECT {job_name}_updated_at::text FROM \"{test_table_name}\" WHERE product_id = {random_product_id}"
+    );
+    let first: String = sqlx::query_scalar(&embedded_at)
+        .fetch_one(&conn)
+        .await
+        .expect("failed to select from test_table");
+    sqlx::query(&format!(
+        "UPDATE \"{test_table_name}\" SET product_name = 'car parts tester' WHERE product_id = {random_product_id}"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to update test_table");
+    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+    let second: String = sqlx::query_scalar(&embedded_at)
+        .fetch_one(&conn)
+        .await
+        .expect("failed to select from test_table");
+    assert_ne!(first, second);
+    let queued: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM pgmq.q_vectorize_jobs WHERE message->>'job_name' = '{job_name}'"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count queued messages");
+    assert_eq!(queued, 0);
+
+    let search_results =
+        common::search_with_retry(&conn, "car testing devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    let found_it = search_results.into_iter().any(|row| {
+        let row: common::SearchResult = serde_json::from_value(row.search_results).unwrap();
+        row.product_id == random_product_id && row.product_name == "car parts tester"
+    });
+    assert!(found_it);
 }
 
 #[ignore]
@@ -923,3 +984,3021 @@ async fn test_event_trigger_on_table_drop() {
     let final_job_count = common::row_count("vectorize.job", &conn).await;
     assert_eq!(final_job_count, 0, "vectorize.job should remain unaffected by unrelated table drops");
 }
+
+#[ignore]
+#[tokio::test]
+async fn test_search_lexical_fallback() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    let job_name = format!("job_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name', 'description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let search_query = format!(
+        "SELECT * FROM vectorize.search(
+            job_name => '{job_name}',
+            query => 'phone charger',
+            return_columns => ARRAY['product_id', 'product_name'],
+            num_results => 3
+        );"
+    );
+    // point the transaction at an embedding service that does not exist
+    let unreachable_svc = "SET LOCAL vectorize.embedding_service_url TO 'http://0.0.0.0:1/v1';";
+    let mut tx = conn.begin().await.unwrap();
+    sqlx::query(unreachable_svc)
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    let result = sqlx::query(&search_query).fetch_all(&mut *tx).await;
+    assert!(
+        result.is_err(),
+        "search should fail without lexical_fallback"
+    );
+    tx.rollback().await.unwrap();
+
+    let fallback_query = format!(
+        "SELECT * FROM vectorize.search(
+            job_name => '{job_name}',
+            query => 'phone charger',
+            return_columns => ARRAY['product_id', 'product_name'],
+            num_results => 3,
+            lexical_fallback => true
+        );"
+    );
+    let mut tx = conn.begin().await.unwrap();
+    sqlx::query(unreachable_svc)
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    let results = sqlx::query_as::<_, common::SearchJSON>(&fallback_query)
+        .fetch_all(&mut *tx)
+        .await
+        .expect("failed lexical fallback search");
+    assert!(!results.is_empty());
+    for r in results {
+        assert_eq!(r.search_results["degraded"], serde_json::json!(true));
+    }
+    tx.rollback().await.unwrap();
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_drop_job() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let _ = sqlx::query(&format!("SELECT vectorize.drop('{job_name}');"))
+        .execute(&conn)
+        .await
+        .expect("failed to drop job");
+
+    let job_exists: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM vectorize.job WHERE name = $1)")
+            .bind(&job_name)
+            .fetch_one(&conn)
+            .await
+            .unwrap();
+    assert!(!job_exists);
+
+    let embeddings_table: Option<String> = sqlx::query_scalar("SELECT to_regclass($1)::text")
+        .bind(format!("vectorize._embeddings_{job_name}"))
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+    assert!(embeddings_table.is_none());
+
+    let trigger_count: i64 =
+        sqlx::query_scalar("SELECT count(*) FROM pg_trigger WHERE tgname LIKE '%' || $1")
+            .bind(format!("_trigger_{job_name}"))
+            .fetch_one(&conn)
+            .await
+            .unwrap();
+    assert_eq!(trigger_count, 0);
+
+    // queued messages for the job are removed
+    let queued: i64 = sqlx::query_scalar(
+        "SELECT count(*) FROM pgmq.q_vectorize_jobs WHERE message->>'job_name' = $1",
+    )
+    .bind(&job_name)
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(queued, 0);
+
+    // the source table is left in place
+    let rows = common::row_count(&test_table_name, &conn).await;
+    assert!(rows > 0);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_refresh() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let result: String = sqlx::query_scalar(&format!(
+        "SELECT vectorize.refresh('{job_name}', force => true);"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to refresh job");
+    let rows = common::row_count(&test_table_name, &conn).await;
+    assert_eq!(result, format!("Queued {rows} rows for job: {job_name}"));
+
+    let (total_rows, completed_rows): (i64, i64) = sqlx::query_as(&format!(
+        "SELECT total_rows, completed_rows FROM vectorize.refresh_progress('{job_name}');"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get refresh progress");
+    assert_eq!(total_rows, rows);
+    assert!(completed_rows <= total_rows);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_composite_uuid_primary_key() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("docs_test_{}", test_num);
+    let job_name = format!("job_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    sqlx::query(&format!(
+        "CREATE TABLE {test_table_name} (
+            tenant_id TEXT NOT NULL,
+            doc_id UUID NOT NULL DEFAULT gen_random_uuid(),
+            content TEXT NOT NULL,
+            PRIMARY KEY (tenant_id, doc_id)
+        );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create table");
+    sqlx::query(&format!(
+        "INSERT INTO {test_table_name} (tenant_id, content) VALUES
+        ('acme', 'wireless mouse'), ('acme', 'mechanical keyboard'), ('globex', 'wireless mouse');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert rows");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'tenant_id, doc_id',
+        columns => ARRAY['content'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    // realtime triggers handle composite keys
+    sqlx::query(&format!(
+        "INSERT INTO {test_table_name} (tenant_id, content) VALUES ('globex', 'usb hub');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert row");
+
+    let search_results = common::search_with_retry(&conn, "mouse", &job_name, 10, 2, 4, None)
+        .await
+        .expect("failed to exec search");
+    assert_eq!(search_results.len(), 4);
+    for result in search_results {
+        assert!(result.search_results.get("tenant_id").is_some());
+        assert!(result.search_results.get("doc_id").is_some());
+    }
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_multiple_jobs_same_table() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_names = [format!("job_a_{}", test_num), format!("job_b_{}", test_num)];
+
+    common::init_embedding_svc_url(&conn).await;
+
+    for (job_name, transformer) in job_names.iter().zip([
+        "sentence-transformers/all-MiniLM-L6-v2",
+        "sentence-transformers/multi-qa-MiniLM-L6-dot-v1",
+    ]) {
+        let _ = sqlx::query(&format!(
+            "SELECT vectorize.table(
+            job_name => '{job_name}',
+            \"table\" => '{test_table_name}',
+            primary_key => 'product_id',
+            columns => ARRAY['product_name'],
+            transformer => '{transformer}',
+            schedule => 'realtime'
+        );"
+        ))
+        .execute(&conn)
+        .await
+        .expect("failed to init job");
+    }
+
+    // a new row is picked up by both jobs
+    let random_product_id = rng.gen_range(0..100000);
+    sqlx::query(&format!(
+        "INSERT INTO \"{test_table_name}\"(product_id, product_name, description, product_category, price)
+        VALUES ({random_product_id}, 'car tester', $$a product for testing car's components$$, 'electronics', 10.99);"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert row");
+
+    for job_name in &job_names {
+        let search_results =
+            common::search_with_retry(&conn, "car testing devices", job_name, 10, 2, 3, None)
+                .await
+                .expect("failed to exec search");
+        let found_it = search_results.into_iter().any(|row| {
+            let row: common::SearchResult = serde_json::from_value(row.search_results).unwrap();
+            row.product_id == random_product_id
+        });
+        assert!(found_it, "job {job_name} did not embed the new row");
+    }
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_partitioned_table() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("events_test_{}", test_num);
+    let job_name = format!("job_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    for stmt in [
+        format!(
+            "CREATE TABLE {test_table_name} (
+                event_id INT NOT NULL,
+                created_at DATE NOT NULL,
+                description TEXT NOT NULL,
+                PRIMARY KEY (event_id, created_at)
+            ) PARTITION BY RANGE (created_at);"
+        ),
+        format!(
+            "CREATE TABLE {test_table_name}_2024 PARTITION OF {test_table_name}
+            FOR VALUES FROM ('2024-01-01') TO ('2025-01-01');"
+        ),
+        format!(
+            "CREATE TABLE {test_table_name}_2025 PARTITION OF {test_table_name}
+            FOR VALUES FROM ('2025-01-01') TO ('2026-01-01');"
+        ),
+        format!(
+            "INSERT INTO {test_table_name} VALUES
+            (1, '2024-03-01', 'wireless mouse'), (2, '2025-03-01', 'mechanical keyboard');"
+        ),
+    ] {
+        sqlx::query(&stmt)
+            .execute(&conn)
+            .await
+            .expect("failed to create partitioned table");
+    }
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'event_id, created_at',
+        columns => ARRAY['description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        partition_embeddings => true
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    // written directly to a partition, picked up by the partition's trigger
+    sqlx::query(&format!(
+        "INSERT INTO {test_table_name}_2025 VALUES (3, '2025-06-01', 'usb hub');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert row");
+
+    let search_results = common::search_with_retry(&conn, "mouse", &job_name, 10, 2, 3, None)
+        .await
+        .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    let partitions: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM pg_inherits WHERE inhparent = 'vectorize._embeddings_{job_name}'::regclass;"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    // one for each source partition, and the default partition
+    assert_eq!(partitions, 3);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_dest_schema() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+    let dest_schema = format!("vectorize_data_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        dest_schema => '{dest_schema}'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    let in_dest_schema: bool = sqlx::query_scalar(&format!(
+        "SELECT to_regclass('{dest_schema}._embeddings_{job_name}') IS NOT NULL
+        AND to_regclass('vectorize._embeddings_{job_name}') IS NULL;"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert!(in_dest_schema);
+
+    // dest_schema only applies to jobs with an embeddings table
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}_append',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        table_method => 'append',
+        dest_schema => '{dest_schema}'
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_prewarm_index() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let _ = common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+        .await
+        .expect("failed to exec search");
+
+    let _ = sqlx::query("CREATE EXTENSION IF NOT EXISTS pg_prewarm;")
+        .execute(&conn)
+        .await
+        .expect("failed to create pg_prewarm");
+    let warmed: Vec<(String, Option<i64>, i64)> = sqlx::query_as(&format!(
+        "SELECT * FROM vectorize.prewarm_index('{job_name}');"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to warm job");
+    // the index and the embeddings table
+    assert_eq!(warmed.len(), 2);
+    assert!(warmed.iter().all(|(_, blocks, _)| blocks.unwrap_or(0) > 0));
+    assert!(warmed.iter().all(|(_, _, searches)| *searches == 10));
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_rag_provenance() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let agent_name = format!("agent_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.init_rag(
+            agent_name => '{agent_name}',
+            table_name => '{test_table_name}',
+            unique_record_id => 'product_id',
+            \"column\" => 'description',
+            transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+            provenance => '{{\"document_id\": \"product_name\", \"chunk_index\": \"product_id\"}}'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let provenance: serde_json::Value = sqlx::query_scalar(&format!(
+        "SELECT params -> 'provenance' FROM vectorize.job WHERE name = '{agent_name}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(
+        provenance,
+        serde_json::json!({"document_id": "product_name", "chunk_index": "product_id"})
+    );
+
+    // provenance columns must exist in the table
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.init_rag(
+            agent_name => '{agent_name}_missing',
+            table_name => '{test_table_name}',
+            unique_record_id => 'product_id',
+            \"column\" => 'description',
+            transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+            provenance => '{{\"document_id\": \"no_such_column\"}}'
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_truncated_dimensions() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        dimensions => 128
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    let col_type: String = sqlx::query_scalar(&format!(
+        "SELECT format_type(atttypid, atttypmod) FROM pg_attribute
+        WHERE attrelid = 'vectorize._embeddings_{job_name}'::regclass AND attname = 'embeddings';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(col_type, "vector(128)");
+
+    let embedding_len: i32 = sqlx::query_scalar(
+        "SELECT array_length(vectorize.transform_embeddings(
+            input => 'the quick brown fox',
+            model_name => 'sentence-transformers/all-MiniLM-L6-v2',
+            dimensions => 64
+        ), 1);",
+    )
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(embedding_len, 64);
+
+    // embeddings can not be made longer than the model's
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}_long',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        dimensions => 1024
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_create_collection() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let collection = format!("docs_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.create_collection(
+        name => '{collection}',
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create collection");
+
+    let _ = sqlx::query(&format!(
+        "INSERT INTO {collection} (content, metadata) VALUES
+        ('phones and tablets', '{{\"category\": \"electronics\"}}'),
+        ('mobile chargers', '{{\"category\": \"electronics\"}}'),
+        ('garden hose', '{{\"category\": \"outdoors\"}}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert documents");
+
+    // documents are embedded by the background worker
+    let search = format!(
+        "SELECT * FROM vectorize.search(
+        job_name => '{collection}',
+        query => 'mobile devices',
+        return_columns => ARRAY['id', 'content', 'metadata'],
+        num_results => 3,
+        where_sql => $$metadata->>'category' = 'electronics'$$
+    );"
+    );
+    let mut search_results: Vec<common::SearchJSON> = vec![];
+    for _ in 0..10 {
+        search_results = sqlx::query_as(&search).fetch_all(&conn).await.unwrap();
+        if search_results.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert_eq!(search_results.len(), 2);
+
+    // the collection's table already exists
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.create_collection(name => '{collection}');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_input_template() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name', 'description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        input_template => $$format('Product: %s. %s', product_name, description)$$
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    // realtime updates are embedded from the template as well
+    let _ = sqlx::query(&format!(
+        "INSERT INTO \"{test_table_name}\"(product_id, product_name, description, product_category, price)
+        VALUES (5000, 'Chromebook', 'a light laptop for school', 'electronics', 299.99);"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert product");
+    let mut embedded = false;
+    for _ in 0..10 {
+        embedded = sqlx::query_scalar(&format!(
+            "SELECT EXISTS (SELECT 1 FROM vectorize._embeddings_{job_name} WHERE product_id = 5000);"
+        ))
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        if embedded {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert!(embedded);
+
+    // the template must be a valid expression over the table's columns
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}_invalid',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        input_template => $$format('%s', no_such_column)$$
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+
+    // templates can not call other functions
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}_function',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        input_template => $$format('%s', pg_read_file('/etc/passwd'))$$
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_benchmark() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let _ = common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+        .await
+        .expect("failed to exec search");
+
+    for recorded_queries in ["NULL", "ARRAY['mobile devices', 'kitchen appliances']"] {
+        let (p50_ms, p99_ms, qps, errors): (f64, f64, f64, i32) = sqlx::query_as(&format!(
+            "SELECT p50_ms, p99_ms, qps, errors FROM vectorize.benchmark(
+                '{job_name}', queries => 20, concurrency => 2, recorded_queries => {recorded_queries}
+            );"
+        ))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to run benchmark");
+        assert_eq!(errors, 0);
+        assert!(p50_ms > 0.0 && p50_ms <= p99_ms);
+        assert!(qps > 0.0);
+    }
+
+    let runs: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM vectorize.benchmark WHERE job_name = '{job_name}' AND queries = 20;"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count benchmark runs");
+    assert_eq!(runs, 2);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_collection_upsert() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let collection = format!("docs_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.create_collection(name => '{collection}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create collection");
+
+    let id: String = sqlx::query_scalar(&format!(
+        "SELECT vectorize.upsert('{collection}', 'doc-1', 'garden hose', '{{\"category\": \"outdoors\"}}');"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to upsert document");
+    assert_eq!(id, "doc-1");
+
+    // doc-1 is replaced, and the second document is given an id
+    let ids: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT id FROM vectorize.upsert_batch('{collection}', '[
+            {{\"id\": \"doc-1\", \"content\": \"phones and tablets\", \"metadata\": {{\"category\": \"electronics\"}}}},
+            {{\"content\": \"mobile chargers\"}}
+        ]');"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to upsert documents");
+    assert_eq!(ids.len(), 2);
+    assert_eq!(ids[0], "doc-1");
+
+    let (content, category): (String, String) = sqlx::query_as(&format!(
+        "SELECT content, metadata->>'category' FROM {collection} WHERE id = 'doc-1';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(content, "phones and tablets");
+    assert_eq!(category, "electronics");
+
+    // both documents are embedded by the background worker
+    let search = format!(
+        "SELECT * FROM vectorize.search(
+        job_name => '{collection}',
+        query => 'mobile devices',
+        return_columns => ARRAY['id', 'content'],
+        num_results => 3
+    );"
+    );
+    let mut search_results: Vec<common::SearchJSON> = vec![];
+    for _ in 0..10 {
+        search_results = sqlx::query_as(&search).fetch_all(&conn).await.unwrap();
+        if search_results.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert_eq!(search_results.len(), 2);
+
+    // documents must have content
+    let result = sqlx::query(&format!(
+        "SELECT * FROM vectorize.upsert_batch('{collection}', '[{{\"id\": \"doc-2\"}}]');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_collection_delete() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let collection = format!("docs_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.create_collection(name => '{collection}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create collection");
+
+    let _ = sqlx::query(&format!(
+        "SELECT * FROM vectorize.upsert_batch('{collection}', '[
+            {{\"id\": \"doc-1\", \"content\": \"phones and tablets\"}},
+            {{\"id\": \"doc-2\", \"content\": \"mobile chargers\"}}
+        ]');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to upsert documents");
+
+    let count_embeddings = format!("SELECT count(*) FROM vectorize._embeddings_{collection};");
+    let mut num_embeddings: i64 = 0;
+    for _ in 0..10 {
+        num_embeddings = sqlx::query_scalar(&count_embeddings)
+            .fetch_one(&conn)
+            .await
+            .unwrap();
+        if num_embeddings == 2 {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert_eq!(num_embeddings, 2);
+
+    let deleted: i64 = sqlx::query_scalar(&format!(
+        "SELECT vectorize.delete('{collection}', ARRAY['doc-1', 'doc-3']);"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to delete documents");
+    assert_eq!(deleted, 1);
+
+    let remaining: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT id FROM vectorize._embeddings_{collection};"
+    ))
+    .fetch_all(&conn)
+    .await
+    .unwrap();
+    assert_eq!(remaining, vec!["doc-2".to_string()]);
+
+    // a document deleted before it is embedded never gets embeddings
+    for statement in [
+        format!("SELECT vectorize.pause('{collection}');"),
+        format!("SELECT vectorize.upsert('{collection}', 'doc-4', 'garden hose');"),
+        format!("SELECT vectorize.delete('{collection}', ARRAY['doc-4']);"),
+        format!("SELECT vectorize.resume('{collection}');"),
+    ] {
+        let _ = sqlx::query(&statement)
+            .execute(&conn)
+            .await
+            .expect("failed to delete queued document");
+    }
+    let queued: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM pgmq.q_vectorize_jobs WHERE message->>'job_name' = '{collection}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(queued, 0);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_search_include_unembedded() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    let job_name = format!("job_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name', 'description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let _ = common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+        .await
+        .expect("failed to exec search");
+
+    // the chargers lose their embeddings, as if they had not been embedded yet
+    let _ = sqlx::query(&format!(
+        "DELETE FROM vectorize._embeddings_{job_name} e
+        USING {test_table_name} p
+        WHERE e.product_id = p.product_id AND p.product_name ILIKE '%charger%';"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to delete embeddings");
+
+    let search = |include_unembedded: bool| {
+        format!(
+            "SELECT * FROM vectorize.search(
+                job_name => '{job_name}',
+                query => 'charger',
+                return_columns => ARRAY['product_id', 'product_name'],
+                num_results => 20,
+                include_unembedded => {include_unembedded}
+            );"
+        )
+    };
+    let is_degraded =
+        |r: &common::SearchJSON| r.search_results["degraded"] == serde_json::json!(true);
+
+    let results: Vec<common::SearchJSON> = sqlx::query_as(&search(false))
+        .fetch_all(&conn)
+        .await
+        .expect("failed to search");
+    assert!(!results.is_empty());
+    assert!(!results.iter().any(is_degraded));
+
+    let merged: Vec<common::SearchJSON> = sqlx::query_as(&search(true))
+        .fetch_all(&conn)
+        .await
+        .expect("failed to search unembedded rows");
+    assert!(merged.iter().any(is_degraded));
+    assert!(merged.iter().any(|r| !is_degraded(r)));
+    assert!(merged.len() <= 20);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_search_metric() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        index_dist_type => 'pgv_hnsw_l2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    // l2 jobs are scored with l2 distances by default
+    let results = common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+        .await
+        .expect("failed to exec search");
+    assert_eq!(results.len(), 3);
+    for r in &results {
+        let distance = r.search_results["distance"].as_f64().unwrap();
+        let similarity = r.search_results["similarity_score"].as_f64().unwrap();
+        assert!((similarity - 1.0 / (1.0 + distance)).abs() < 1e-9);
+    }
+
+    let results: Vec<common::SearchJSON> = sqlx::query_as(&format!(
+        "SELECT * FROM vectorize.search(
+            job_name => '{job_name}',
+            query => 'mobile devices',
+            return_columns => ARRAY['product_id'],
+            num_results => 3,
+            metric => 'cosine'
+        );"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to search with cosine metric");
+    assert_eq!(results.len(), 3);
+    let similarities: Vec<f64> = results
+        .iter()
+        .map(|r| r.search_results["similarity_score"].as_f64().unwrap())
+        .collect();
+    for (r, similarity) in results.iter().zip(&similarities) {
+        let distance = r.search_results["distance"].as_f64().unwrap();
+        assert!((similarity - (1.0 - distance)).abs() < 1e-9);
+    }
+    assert!(similarities.windows(2).all(|w| w[0] >= w[1]));
+
+    let result = sqlx::query(&format!(
+        "SELECT * FROM vectorize.search(job_name => '{job_name}', query => 'mobile', metric => 'manhattan');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_ttl() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let collection = format!("docs_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.create_collection(name => '{collection}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create collection");
+
+    let _ = sqlx::query(&format!(
+        "SELECT * FROM vectorize.upsert_batch('{collection}', '[
+            {{\"id\": \"old\", \"content\": \"phones and tablets\"}},
+            {{\"id\": \"new\", \"content\": \"mobile chargers\"}}
+        ]');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to upsert documents");
+
+    let count_embeddings = format!("SELECT count(*) FROM vectorize._embeddings_{collection};");
+    let mut num_embeddings: i64 = 0;
+    for _ in 0..10 {
+        num_embeddings = sqlx::query_scalar(&count_embeddings)
+            .fetch_one(&conn)
+            .await
+            .unwrap();
+        if num_embeddings == 2 {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert_eq!(num_embeddings, 2);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.set_ttl('{collection}', '1 day', delete_source => true);"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to set ttl");
+    let scheduled: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM cron.job WHERE jobname = 'vectorize_ttl_{collection}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(scheduled, 1);
+
+    let _ = sqlx::query(&format!(
+        "UPDATE {collection} SET last_updated_at = NOW() - '2 days'::interval WHERE id = 'old';"
+    ))
+    .execute(&conn)
+    .await
+    .unwrap();
+    let purged: i64 =
+        sqlx::query_scalar(&format!("SELECT vectorize._purge_expired('{collection}');"))
+            .fetch_one(&conn)
+            .await
+            .expect("failed to purge expired documents");
+    assert_eq!(purged, 1);
+
+    let remaining: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT id FROM vectorize._embeddings_{collection};"
+    ))
+    .fetch_all(&conn)
+    .await
+    .unwrap();
+    assert_eq!(remaining, vec!["new".to_string()]);
+
+    let _ = sqlx::query(&format!("SELECT vectorize.set_ttl('{collection}', NULL);"))
+        .execute(&conn)
+        .await
+        .expect("failed to remove ttl");
+    let scheduled: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM cron.job WHERE jobname = 'vectorize_ttl_{collection}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(scheduled, 0);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_provenance() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let collection = format!("docs_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.create_collection(name => '{collection}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create collection");
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.upsert('{collection}', 'doc1', 'phones and tablets');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to upsert document");
+
+    let query = format!(
+        "SELECT model, provider, content_hash, trace_id FROM vectorize.provenance('{collection}', 'doc1');"
+    );
+    let mut recorded: Option<(String, String, String, String)> = None;
+    for _ in 0..10 {
+        recorded = sqlx::query_as(&query).fetch_optional(&conn).await.unwrap();
+        if recorded.is_some() {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    let (model, provider, content_hash, trace_id) = recorded.expect("no provenance recorded");
+    assert_eq!(model, "sentence-transformers/all-MiniLM-L6-v2");
+    assert_eq!(provider, "sentence-transformers");
+    let expected_hash: String = sqlx::query_scalar(
+        "SELECT encode(sha256(convert_to('phones and tablets', 'UTF8')), 'hex');",
+    )
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(content_hash, expected_hash);
+    assert!(trace_id.starts_with("vectorize_jobs:"));
+
+    // records that were never embedded have no provenance
+    let none: Option<String> = sqlx::query_scalar(&format!(
+        "SELECT model FROM vectorize.provenance('{collection}', 'missing');"
+    ))
+    .fetch_optional(&conn)
+    .await
+    .unwrap();
+    assert!(none.is_none());
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.delete('{collection}', ARRAY['doc1']);"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to delete document");
+    let remaining: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM vectorize.embedding_provenance WHERE job_name = '{collection}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(remaining, 0);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_rename_job() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let collection = format!("docs_{}", test_num);
+    let new_name = format!("renamed_docs_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.create_collection(name => '{collection}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create collection");
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.upsert('{collection}', 'doc-1', 'phones and tablets');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to upsert document");
+
+    let wait_for_embeddings = |job: String, expected: i64| {
+        let conn = conn.clone();
+        async move {
+            let query = format!("SELECT count(*) FROM vectorize._embeddings_{job};");
+            let mut num_embeddings: i64 = 0;
+            for _ in 0..10 {
+                num_embeddings = sqlx::query_scalar(&query).fetch_one(&conn).await.unwrap();
+                if num_embeddings == expected {
+                    break;
+                }
+                tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+            }
+            num_embeddings
+        }
+    };
+    assert_eq!(wait_for_embeddings(collection.clone(), 1).await, 1);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.rename_job('{collection}', '{new_name}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to rename job");
+
+    // the embeddings were kept, and the renamed triggers embed new documents
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.upsert('{new_name}', 'doc-2', 'mobile chargers');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to upsert document");
+    assert_eq!(wait_for_embeddings(new_name.clone(), 2).await, 2);
+
+    let old_job: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM vectorize.job WHERE name = '{collection}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(old_job, 0);
+    let old_triggers: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM pg_trigger WHERE tgname LIKE 'vectorize_%_trigger_{collection}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(old_triggers, 0);
+
+    // the name of another job can not be taken
+    let other = format!("other_docs_{}", test_num);
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.create_collection(name => '{other}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create collection");
+    let taken = sqlx::query(&format!(
+        "SELECT vectorize.rename_job('{new_name}', '{other}');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(taken.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_alter_job() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.alter_job('{job_name}', '{{\"schedule\": \"*/5 * * * *\", \"update_time_col\": \"last_updated_at\", \"batch_size\": 500}}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to alter job");
+    let (update_time_col, batch_size): (String, i32) = sqlx::query_as(&format!(
+        "SELECT params->>'update_time_col', (params->>'batch_size')::int FROM vectorize.job WHERE name = '{job_name}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(update_time_col, "last_updated_at");
+    assert_eq!(batch_size, 500);
+    // the triggers were swapped for a pg_cron job
+    let scheduled: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM cron.job WHERE jobname = '{job_name}' AND schedule = '*/5 * * * *';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(scheduled, 1);
+    let triggers: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM pg_trigger WHERE tgname LIKE 'vectorize_%_trigger_{job_name}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(triggers, 0);
+
+    // settings that can not be altered are rejected
+    let invalid = sqlx::query(&format!(
+        "SELECT vectorize.alter_job('{job_name}', '{{\"columns\": [\"description\"]}}');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(invalid.is_err());
+
+    // a new transformer is migrated to in the background, the job keeps its model until then
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.alter_job('{job_name}', '{{\"transformer\": \"sentence-transformers/multi-qa-MiniLM-L6-dot-v1\"}}');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to alter transformer");
+    let transformer: String = sqlx::query_scalar(&format!(
+        "SELECT transformer FROM vectorize.job WHERE name = '{job_name}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(transformer, "sentence-transformers/all-MiniLM-L6-v2");
+    let (transformer, batch_size): (String, i32) = sqlx::query_as(&format!(
+        "SELECT transformer, (params->>'batch_size')::int FROM vectorize.job WHERE name = '{job_name}_next';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(
+        transformer,
+        "sentence-transformers/multi-qa-MiniLM-L6-dot-v1"
+    );
+    assert_eq!(batch_size, 500);
+
+    // chunk_size is only a setting of chunked jobs
+    let invalid = sqlx::query(&format!(
+        "SELECT vectorize.alter_job('{job_name}', '{{\"chunk_size\": 200}}');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(invalid.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_migrate_model() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+    let next = format!("{job_name}_next");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name', 'description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.migrate_model('{job_name}', 'sentence-transformers/multi-qa-MiniLM-L6-dot-v1');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to migrate model");
+    // a second migration can not be started while the first is in progress
+    let again = sqlx::query(&format!(
+        "SELECT vectorize.migrate_model('{job_name}', 'sentence-transformers/multi-qa-MiniLM-L6-dot-v1');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(again.is_err());
+
+    // both models can be searched while the new one is backfilled
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+    let search_results = common::search_with_retry(&conn, "mobile devices", &next, 10, 2, 3, None)
+        .await
+        .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    let backfilled =
+        format!("SELECT completed_rows = total_rows FROM vectorize.refresh_progress('{next}');");
+    for _ in 0..20 {
+        let done: bool = sqlx::query_scalar(&backfilled)
+            .fetch_one(&conn)
+            .await
+            .expect("failed to get progress");
+        if done {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    // the swap is normally run by pg_cron every minute
+    let finalize = format!("SELECT vectorize._migrate_model_finalize('{job_name}');");
+    let mut transformer = String::new();
+    for _ in 0..10 {
+        sqlx::query(&finalize)
+            .execute(&conn)
+            .await
+            .expect("failed to finalize migration");
+        transformer = sqlx::query_scalar(&format!(
+            "SELECT transformer FROM vectorize.job WHERE name = '{job_name}';"
+        ))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to get job");
+        if transformer.ends_with("multi-qa-MiniLM-L6-dot-v1") {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+    assert!(transformer.ends_with("multi-qa-MiniLM-L6-dot-v1"));
+
+    let remaining: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM vectorize.job WHERE name = '{next}';"
+    ))
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert_eq!(remaining, 0);
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_clone_job() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let tenant_schema = format!("tenant_{}", test_num);
+    let _ = sqlx::query(&format!("CREATE SCHEMA {tenant_schema};"))
+        .execute(&conn)
+        .await
+        .expect("failed to create schema");
+    common::init_test_table(&format!("{tenant_schema}.products"), &conn).await;
+    let job_name = format!("job_{}", test_num);
+    let clone_name = format!("job_clone_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name', 'description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        storage => 'halfvec'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.clone_job('{job_name}', '{clone_name}', '{tenant_schema}.products');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to clone job");
+    // a job's name can not be taken by a clone
+    let taken = sqlx::query(&format!(
+        "SELECT vectorize.clone_job('{clone_name}', '{job_name}', '{tenant_schema}.products');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(taken.is_err());
+
+    let params: serde_json::Value =
+        sqlx::query_scalar("SELECT params FROM vectorize.job WHERE name = $1")
+            .bind(&clone_name)
+            .fetch_one(&conn)
+            .await
+            .expect("failed to get params");
+    assert_eq!(params["schema"], tenant_schema.as_str());
+    assert_eq!(params["table"], "products");
+    assert_eq!(params["schedule"], "realtime");
+    assert_eq!(params["vector_storage"], "halfvec");
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &clone_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_diskann_l2() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_diskann_l2_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        index_dist_type => 'vsc_diskann_l2',
+        num_neighbors => 32,
+        storage_layout => 'plain',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let indexdef: String = sqlx::query_scalar(&format!(
+        "SELECT indexdef FROM pg_indexes WHERE indexname = '{job_name}_diskann_l2_idx'"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get index");
+    assert!(indexdef.contains("USING diskann (embeddings vector_l2_ops)"));
+    assert!(indexdef.contains("num_neighbors='32'"));
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+
+    // diskann build parameters are rejected for other index types
+    let result = sqlx::query(&format!(
+        "SELECT vectorize.reindex('{job_name}', 'pgv_hnsw_l2', num_neighbors => 32);"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_text() {
+    let conn = common::init_database().await;
+    let text = "向量搜索让数据库能够按含义查找相似的文本。".repeat(50);
+    let chunks: Vec<String> = sqlx::query_scalar("SELECT vectorize.chunk_text($1, 64)")
+        .bind(&text)
+        .fetch_one(&conn)
+        .await
+        .expect("failed to chunk text");
+    assert!(chunks.len() > 1);
+    // without overlap, the chunks put back together are the text
+    assert_eq!(chunks.concat(), text);
+
+    let overlapping: Vec<String> =
+        sqlx::query_scalar("SELECT vectorize.chunk_text($1, 64, chunk_overlap => 16)")
+            .bind(&text)
+            .fetch_one(&conn)
+            .await
+            .expect("failed to chunk text");
+    assert!(overlapping.len() > chunks.len());
+
+    let result = sqlx::query("SELECT vectorize.chunk_text('text', 16, chunk_overlap => 16)")
+        .execute(&conn)
+        .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunked_job() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        chunk_size => 6,
+        chunk_strategy => 'recursive',
+        chunk_separators => ARRAY[', ', ' ']
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    // rows longer than chunk_size are split into several chunks
+    let (rows, chunks): (i64, i64) = sqlx::query_as(&format!(
+        "SELECT (SELECT count(*) FROM {test_table_name}), (SELECT count(*) FROM vectorize._chunks_{job_name})"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count chunks");
+    assert!(chunks > rows);
+
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+    let result_val = search_results[0].search_results.clone();
+    assert!(result_val["original_id"].is_number());
+    assert!(result_val["chunk"].is_string());
+    // each chunk records where it was taken from
+    assert_eq!(result_val["source_column"], "description");
+    assert!(result_val["char_end"].as_i64() > result_val["char_start"].as_i64());
+    assert!(result_val["token_count"].as_i64().unwrap() > 0);
+
+    let misplaced: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM vectorize._chunks_{job_name} c JOIN {test_table_name} t ON t.product_id = c.original_id
+        WHERE substr(t.description, c.char_start + 1, c.char_end - c.char_start) <> c.chunk"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to check chunk offsets");
+    assert_eq!(misplaced, 0);
+
+    // the chunks are dropped along with the job
+    let _ = sqlx::query(&format!("SELECT vectorize.drop_job('{job_name}');"))
+        .execute(&conn)
+        .await
+        .expect("failed to drop job");
+    let exists: bool = sqlx::query_scalar(&format!(
+        "SELECT to_regclass('vectorize._chunks_{job_name}') IS NOT NULL"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to check chunks table");
+    assert!(!exists);
+
+    // composite keys can not be chunked
+    let composite = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id, product_name',
+        columns => ARRAY['description'],
+        chunk_size => 6
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(composite.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_table() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table(
+        input_table => '{test_table_name}',
+        column_name => 'description',
+        primary_key => 'product_id',
+        chunk_size => 4,
+        chunk_strategy => 'recursive'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk table");
+
+    // every row is chunked, and its chunks are numbered from 0
+    let mismatched: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM {test_table_name} t
+        LEFT JOIN (
+            SELECT original_id, count(*) AS num_chunks, max(chunk_index) AS last_index
+            FROM {test_table_name}_chunks GROUP BY original_id
+        ) c ON c.original_id = t.product_id
+        WHERE c.num_chunks IS NULL OR c.last_index <> c.num_chunks - 1"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count chunks");
+    assert_eq!(mismatched, 0);
+
+    let invalid = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table('{test_table_name}', 'description', 'product_id', 4, 4);"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(invalid.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_sentences() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("docs_test_{}", test_num);
+    let _ = sqlx::query(&format!(
+        "CREATE TABLE {test_table_name} (id INT PRIMARY KEY, body TEXT);
+        INSERT INTO {test_table_name} VALUES
+            (1, 'Vector search finds similar texts. It compares their embeddings. Indexes make it fast.');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create table");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table(
+        input_table => '{test_table_name}',
+        column_name => 'body',
+        primary_key => 'id',
+        chunk_size => 3,
+        chunk_strategy => 'sentence'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk table");
+
+    // sentences longer than chunk_size are kept whole
+    let chunks: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT chunk FROM {test_table_name}_chunks ORDER BY chunk_index"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to get chunks");
+    assert_eq!(
+        chunks,
+        vec![
+            "Vector search finds similar texts.",
+            "It compares their embeddings.",
+            "Indexes make it fast.",
+        ]
+    );
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_markdown() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("docs_test_{}", test_num);
+    let _ = sqlx::query(&format!(
+        "CREATE TABLE {test_table_name} (id INT PRIMARY KEY, body TEXT);
+        INSERT INTO {test_table_name} VALUES
+            (1, E'# Guide\\n## Install\\nRun the installer.\\n### Linux\\nUse the package.\\n## Usage\\nCall it.');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create table");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table(
+        input_table => '{test_table_name}',
+        column_name => 'body',
+        primary_key => 'id',
+        chunk_size => 100,
+        chunk_strategy => 'markdown'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk table");
+
+    let headings: Vec<Vec<String>> = sqlx::query_scalar(&format!(
+        "SELECT headings FROM {test_table_name}_chunks ORDER BY chunk_index"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to get chunks");
+    assert_eq!(
+        headings,
+        vec![
+            vec!["Guide", "Install"],
+            vec!["Guide", "Install", "Linux"],
+            vec!["Guide", "Usage"],
+        ]
+    );
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_html() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("pages_test_{}", test_num);
+    let _ = sqlx::query(&format!(
+        "CREATE TABLE {test_table_name} (url TEXT PRIMARY KEY, html TEXT);
+        INSERT INTO {test_table_name} VALUES
+            ('/docs', '<html><head><script>track();</script></head><body>
+                <nav><a href=\"/\">Home</a></nav>
+                <h1>Docs</h1><p>Vector search &amp; chunking.</p>
+            </body></html>');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create table");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table(
+        input_table => '{test_table_name}',
+        column_name => 'html',
+        primary_key => 'url',
+        chunk_size => 100,
+        preprocess => 'html'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk table");
+
+    let chunk: String = sqlx::query_scalar(&format!(
+        "SELECT chunk FROM {test_table_name}_chunks WHERE original_id = '/docs'"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get chunk");
+    assert_eq!(chunk, "Docs\n\nVector search & chunking.");
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_code() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("code_test_{}", test_num);
+    let _ = sqlx::query(&format!(
+        "CREATE TABLE {test_table_name} (path TEXT PRIMARY KEY, source TEXT);
+        INSERT INTO {test_table_name} VALUES
+            ('math.py', E'def add(a, b):\\n    return a + b\\n\\n\\ndef sub(a, b):\\n    return a - b\\n');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create table");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table(
+        input_table => '{test_table_name}',
+        column_name => 'source',
+        primary_key => 'path',
+        chunk_size => 15,
+        chunk_strategy => 'code'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk table");
+
+    // each function is a chunk of its own
+    let chunks: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT chunk FROM {test_table_name}_chunks ORDER BY chunk_index"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to get chunks");
+    assert_eq!(
+        chunks,
+        vec![
+            "def add(a, b):\n    return a + b",
+            "def sub(a, b):\n    return a - b"
+        ]
+    );
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_semantic_chunked_job() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        chunk_size => 50,
+        chunk_strategy => 'semantic',
+        chunk_similarity_threshold => 0.4
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    // the worker writes the chunks, which are then embedded and searchable
+    let search_results =
+        common::search_with_retry(&conn, "mobile devices", &job_name, 10, 2, 3, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+    assert!(search_results[0].search_results["chunk"].is_string());
+
+    // the semantic strategy has no overlap, and needs a job's model
+    let overlap = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}_overlap',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['description'],
+        chunk_size => 50,
+        chunk_overlap => 10,
+        chunk_strategy => 'semantic'
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(overlap.is_err());
+    let chunk_table = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table('{test_table_name}', 'description', 'product_id', 50, chunk_strategy => 'semantic');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(chunk_table.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_sync() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        chunk_size => 6
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    // a row is chunked again once its text changes
+    let _ = sqlx::query(&format!(
+        "UPDATE {test_table_name} SET description = 'a brand new description' WHERE product_id = 1"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to update row");
+    let chunks: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT chunk FROM vectorize._chunks_{job_name} WHERE original_id = 1 ORDER BY chunk_index"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to get chunks");
+    assert_eq!(chunks, vec!["a brand new description"]);
+
+    // and its chunks are deleted along with it
+    let _ = sqlx::query(&format!(
+        "DELETE FROM {test_table_name} WHERE product_id = 1"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to delete row");
+    let remaining: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM vectorize._chunks_{job_name} WHERE original_id = 1"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count chunks");
+    assert_eq!(remaining, 0);
+
+    // new rows are chunked as they are inserted, and their chunks embedded
+    let _ = sqlx::query(&format!(
+        "INSERT INTO {test_table_name} (product_id, product_name, description, product_category, price)
+        VALUES (1001, 'Telescope', 'a reflecting telescope for stargazing', 'optics', 300.0)"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert row");
+    let search_results =
+        common::search_with_retry(&conn, "stargazing telescope", &job_name, 10, 2, 1, None)
+            .await
+            .expect("failed to exec search");
+    assert_eq!(search_results[0].search_results["original_id"], 1001);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_rechunk() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        chunk_size => 6
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    // the same params give the same chunks, which keep their embeddings
+    let message: String = sqlx::query_scalar(&format!(
+        "SELECT vectorize.rechunk(job_name => '{job_name}', chunk_size => 6)"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to rechunk");
+    assert!(message.contains(": 0 new chunks to embed"), "{message}");
+    assert!(message.contains(", 0 chunks deleted"), "{message}");
+
+    let message: String = sqlx::query_scalar(&format!(
+        "SELECT vectorize.rechunk(job_name => '{job_name}', chunk_size => 200)"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to rechunk");
+    assert!(!message.contains(": 0 new chunks to embed"), "{message}");
+    // each description now fits in a chunk of its own
+    let (chunks, rows): (i64, i64) = sqlx::query_as(&format!(
+        "SELECT (SELECT count(*) FROM vectorize._chunks_{job_name}),
+            (SELECT count(*) FROM {test_table_name})"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count chunks");
+    assert_eq!(chunks, rows);
+    let chunk: String = sqlx::query_scalar(&format!(
+        "SELECT c.chunk FROM vectorize._chunks_{job_name} c
+        INNER JOIN {test_table_name} t ON t.product_id = c.original_id
+        WHERE t.product_id = 1"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get chunk");
+    let description: String = sqlx::query_scalar(&format!(
+        "SELECT description FROM {test_table_name} WHERE product_id = 1"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get description");
+    assert_eq!(chunk, description);
+
+    let params: serde_json::Value = sqlx::query_scalar(&format!(
+        "SELECT params FROM vectorize.job WHERE name = '{job_name}'"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get job params");
+    assert_eq!(params["chunking"]["params"]["chunk_size"], 200);
+
+    // the new chunks are embedded
+    let search_results = common::search_with_retry(
+        &conn,
+        "mobile electronic devices",
+        &job_name,
+        10,
+        2,
+        3,
+        None,
+    )
+    .await
+    .expect("failed to exec search");
+    assert_eq!(search_results.len(), 3);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_uuid_primary_key() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("docs_test_{}", test_num);
+    let job_name = format!("job_{}", test_num);
+
+    common::init_embedding_svc_url(&conn).await;
+
+    sqlx::query(&format!(
+        "CREATE TABLE {test_table_name} (
+            doc_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            slug TEXT UNIQUE NOT NULL,
+            content TEXT NOT NULL
+        );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create table");
+    sqlx::query(&format!(
+        "INSERT INTO {test_table_name} (slug, content) VALUES
+        ('mouse', 'a wireless mouse with a rechargeable battery'),
+        ('keyboard', 'a mechanical keyboard with backlit keys');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert rows");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'doc_id',
+        columns => ARRAY['content'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        chunk_size => 4
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table(
+        input_table => '{test_table_name}',
+        column_name => 'content',
+        primary_key => 'slug',
+        chunk_size => 4
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk table");
+
+    // original_id has the type of the primary key it references
+    let types: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT format_type(atttypid, atttypmod) FROM pg_attribute
+        WHERE attname = 'original_id'
+            AND attrelid IN ('vectorize._chunks_{job_name}'::regclass, '{test_table_name}_chunks'::regclass)
+        ORDER BY attrelid::regclass::text DESC"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to get column types");
+    assert_eq!(types, vec!["uuid", "text"]);
+
+    // new rows are chunked and embedded under their uuid
+    sqlx::query(&format!(
+        "INSERT INTO {test_table_name} (slug, content) VALUES ('hub', 'a usb hub with four ports');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to insert row");
+    let search_results = common::search_with_retry(&conn, "usb hub", &job_name, 10, 2, 1, None)
+        .await
+        .expect("failed to exec search");
+    let doc_id: String = sqlx::query_scalar(&format!(
+        "SELECT doc_id::text FROM {test_table_name} WHERE slug = 'hub'"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get doc_id");
+    assert_eq!(
+        search_results[0].search_results["original_id"],
+        doc_id.as_str()
+    );
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_dedup() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    // rows sharing the same text
+    sqlx::query(&format!(
+        "UPDATE {test_table_name} SET description = 'ships free within three days'
+        WHERE product_id <= 5"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to update rows");
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        chunk_size => 50
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+    // every chunk is embedded once the job is backfilled
+    for _ in 0..10 {
+        let percent_complete: f64 = sqlx::query_scalar(&format!(
+            "SELECT percent_complete FROM vectorize.refresh_progress('{job_name}')"
+        ))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to get progress");
+        if percent_complete >= 100.0 {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+
+    // the shared text was embedded once, and its chunks all have its embeddings
+    let distinct: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(DISTINCT e.embeddings::text) FROM vectorize._chunks_{job_name} c
+        INNER JOIN vectorize._embeddings_{job_name} e ON e.chunk_id = c.chunk_id
+        WHERE c.original_id <= 5"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count embeddings");
+    assert_eq!(distinct, 1);
+    let (deduplicated, tokens_saved): (i64, i64) = sqlx::query_as(&format!(
+        "SELECT deduplicated_chunks, tokens_saved FROM vectorize.job_stats WHERE job_name = '{job_name}'"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to get job stats");
+    assert_eq!(deduplicated, 4);
+    assert!(tokens_saved > 0);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_separator() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("logs_test_{}", test_num);
+    let _ = sqlx::query(&format!(
+        "CREATE TABLE {test_table_name} (id INT PRIMARY KEY, body TEXT);
+        INSERT INTO {test_table_name} VALUES
+            (1, E'server log\n2024-01-01 started\nlistening\n2024-01-02 stopped');"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create table");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table(
+        input_table => '{test_table_name}',
+        column_name => 'body',
+        primary_key => 'id',
+        chunk_size => 50,
+        chunk_strategy => 'separator',
+        separator => '(?m)^\\d{{4}}-'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk table");
+
+    // each entry of the log is a chunk of its own, starting with its timestamp
+    let chunks: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT chunk FROM {test_table_name}_chunks ORDER BY chunk_index"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to get chunks");
+    assert_eq!(
+        chunks,
+        vec![
+            "server log",
+            "2024-01-01 started\nlistening",
+            "2024-01-02 stopped"
+        ]
+    );
+
+    // a separator that matches an empty string would split between every character
+    let invalid = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table('{test_table_name}', 'body', 'id', 50,
+        chunk_strategy => 'separator', separator => '\\s*');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(invalid.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_column_params() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    // product names are not chunked, descriptions are cut into windows of 4 tokens
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name', 'description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        chunk_size => 100,
+        chunk_column_params => '{{\"product_name\": null, \"description\": {{\"chunk_size\": 4}}}}'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let (name_chunks, rows, long_descriptions): (i64, i64, i64) = sqlx::query_as(&format!(
+        "SELECT
+            (SELECT count(*) FROM vectorize._chunks_{job_name} WHERE source_column = 'product_name'),
+            (SELECT count(*) FROM {test_table_name}),
+            (SELECT count(*) FROM vectorize._chunks_{job_name}
+                WHERE source_column = 'description' AND token_count > 4)"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count chunks");
+    assert_eq!(name_chunks, rows);
+    assert_eq!(long_descriptions, 0);
+
+    // params of a column that is not one of the job's columns are rejected
+    let invalid = sqlx::query(&format!(
+        "SELECT vectorize.rechunk('{job_name}', 100, chunk_column_params => '{{\"price\": null}}');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(invalid.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_input_template() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['product_name', 'description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        chunk_size => 100,
+        input_template => $$format('Product: %s. %s', product_name, description)$$
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    // each row is chunked as its templated text
+    let (templated, rows, labelled): (i64, i64, i64) = sqlx::query_as(&format!(
+        "SELECT
+            (SELECT count(*) FROM vectorize._chunks_{job_name} WHERE source_column = 'input_template'),
+            (SELECT count(*) FROM {test_table_name}),
+            (SELECT count(*) FROM vectorize._chunks_{job_name}
+                WHERE chunk_index = 0 AND chunk LIKE 'Product: %')"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count chunks");
+    assert_eq!(templated, rows);
+    assert_eq!(labelled, rows);
+
+    // the templated text is chunked as a whole
+    let invalid = sqlx::query(&format!(
+        "SELECT vectorize.rechunk('{job_name}', 100, chunk_column_params => '{{\"product_name\": null}}');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(invalid.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_search_parent_document() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime',
+        chunk_size => 4
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+    for _ in 0..10 {
+        let percent_complete: f64 = sqlx::query_scalar(&format!(
+            "SELECT percent_complete FROM vectorize.refresh_progress('{job_name}')"
+        ))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to get progress");
+        if percent_complete >= 100.0 {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+
+    // each row is returned once, with its own columns and the chunks that were found
+    let results: Vec<serde_json::Value> = sqlx::query_scalar(&format!(
+        "SELECT search_results FROM vectorize.search(
+        job_name => '{job_name}',
+        query => 'mobile devices',
+        return_columns => ARRAY['product_id', 'product_name', 'description'],
+        num_results => 3,
+        parent_document => true
+    );"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to search");
+    assert!(!results.is_empty() && results.len() <= 3);
+    let mut ids: Vec<i64> = results
+        .iter()
+        .map(|r| r["product_id"].as_i64().expect("missing product_id"))
+        .collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), results.len());
+    for result in &results {
+        let description = result["description"].as_str().unwrap();
+        let matched = result["matched_chunks"].as_array().unwrap();
+        assert!(!matched.is_empty());
+        assert!(matched
+            .iter()
+            .all(|c| description.contains(c.as_str().unwrap())));
+    }
+
+    // the context of a chunk covers it and the chunks around it
+    let results: Vec<serde_json::Value> = sqlx::query_scalar(&format!(
+        "SELECT search_results FROM vectorize.search(
+        job_name => '{job_name}',
+        query => 'mobile devices',
+        return_columns => ARRAY['chunk'],
+        num_results => 3,
+        chunk_window => 1
+    );"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to search");
+    for result in &results {
+        let chunk = result["chunk"].as_str().unwrap();
+        assert!(result["context"].as_str().unwrap().contains(chunk));
+        assert!(result.get("chunk_id").is_none());
+    }
+
+    let invalid = sqlx::query(&format!(
+        "SELECT * FROM vectorize.search('{job_name}', 'mobile devices',
+        parent_document => true, chunk_window => 1);"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(invalid.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_windows() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("contracts_test_{}", test_num);
+    let _ = sqlx::query(&format!(
+        "CREATE TABLE {test_table_name} (id INT PRIMARY KEY, body TEXT);
+        INSERT INTO {test_table_name} VALUES
+            (1, repeat('The tenant shall pay the rent on the first day of each month. ', 10));"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create table");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table(
+        input_table => '{test_table_name}',
+        column_name => 'body',
+        primary_key => 'id',
+        chunk_size => 20,
+        chunk_strategy => 'window',
+        chunk_stride => 5
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk table");
+
+    // the last window is not cut short, and consecutive windows overlap
+    let (num_chunks, short_chunks, gaps): (i64, i64, i64) = sqlx::query_as(&format!(
+        "SELECT count(*),
+            count(*) FILTER (WHERE token_count < 15),
+            count(*) FILTER (WHERE next_start >= char_end)
+        FROM (
+            SELECT token_count, char_end,
+                lead(char_start) OVER (ORDER BY chunk_index) AS next_start
+            FROM {test_table_name}_chunks
+        ) c"
+    ))
+    .fetch_one(&conn)
+    .await
+    .expect("failed to count chunks");
+    assert!(num_chunks > 4);
+    assert_eq!(short_chunks, 0);
+    assert_eq!(gaps, 0);
+
+    // the stride can not be longer than the windows
+    let invalid = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table('{test_table_name}', 'body', 'id', 20,
+        chunk_strategy => 'window', chunk_stride => 21, output_table => 'invalid_chunks');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(invalid.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_chunk_filter() {
+    let conn = common::init_database().await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("pages_test_{}", test_num);
+    let _ = sqlx::query(&format!(
+        "CREATE TABLE {test_table_name} (id INT PRIMARY KEY, body TEXT);
+        INSERT INTO {test_table_name} VALUES
+            (1, E'Home\n\nVector search finds similar texts by comparing their embeddings.\n\nAll rights reserved by the authors of this page.\n\nIndexes make searches over millions of rows fast.');
+        CREATE FUNCTION no_indexes_{test_num}(chunk TEXT) RETURNS BOOLEAN AS $$
+            SELECT chunk NOT LIKE 'Indexes%'
+        $$ LANGUAGE sql IMMUTABLE;"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to create table");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table(
+        input_table => '{test_table_name}',
+        column_name => 'body',
+        primary_key => 'id',
+        chunk_size => 20,
+        chunk_strategy => 'separator',
+        separator => '\\n\\n',
+        chunk_filter => '{{\"min_tokens\": 3, \"exclude_patterns\": [\"^All rights reserved\"], \"function\": \"no_indexes_{test_num}\"}}'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to chunk table");
+
+    // the short, excluded and rejected chunks are discarded, the rest are numbered without gaps
+    let chunks: Vec<(i32, String)> = sqlx::query_as(&format!(
+        "SELECT chunk_index, chunk FROM {test_table_name}_chunks ORDER BY chunk_index"
+    ))
+    .fetch_all(&conn)
+    .await
+    .expect("failed to get chunks");
+    assert_eq!(
+        chunks,
+        vec![(
+            0,
+            "Vector search finds similar texts by comparing their embeddings.".to_string()
+        )]
+    );
+
+    // the function must exist and return a boolean
+    let invalid = sqlx::query(&format!(
+        "SELECT vectorize.chunk_table('{test_table_name}', 'body', 'id', 20,
+        chunk_filter => '{{\"function\": \"missing_filter_{test_num}\"}}', output_table => 'invalid_chunks');"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(invalid.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_search_rerank() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let job_name = format!("job_{}", test_num);
+    let co_api_key = std::env::var("CO_API_KEY").expect("CO_API_KEY must be set");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.table(
+        job_name => '{job_name}',
+        \"table\" => '{test_table_name}',
+        primary_key => 'product_id',
+        columns => ARRAY['description'],
+        transformer => 'sentence-transformers/all-MiniLM-L6-v2',
+        schedule => 'realtime'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+    for _ in 0..10 {
+        let percent_complete: f64 = sqlx::query_scalar(&format!(
+            "SELECT percent_complete FROM vectorize.refresh_progress('{job_name}')"
+        ))
+        .fetch_one(&conn)
+        .await
+        .expect("failed to get progress");
+        if percent_complete >= 100.0 {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+
+    let mut tx = conn.begin().await.unwrap();
+    sqlx::query(&format!(
+        "SET LOCAL vectorize.cohere_api_key TO '{co_api_key}'"
+    ))
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+    // the results are those the reranker finds most relevant, best first, without the text they were reranked by
+    let results: Vec<serde_json::Value> = sqlx::query_scalar(&format!(
+        "SELECT search_results FROM vectorize.search(
+        job_name => '{job_name}',
+        query => 'mobile devices',
+        return_columns => ARRAY['product_id', 'product_name'],
+        num_results => 3,
+        rerank_model => 'cohere/rerank-english-v3.0'
+    );"
+    ))
+    .fetch_all(&mut *tx)
+    .await
+    .expect("failed to search");
+    tx.commit().await.unwrap();
+    assert_eq!(results.len(), 3);
+    let scores: Vec<f64> = results
+        .iter()
+        .map(|r| r["rerank_score"].as_f64().expect("missing rerank_score"))
+        .collect();
+    assert!(scores.windows(2).all(|w| w[0] >= w[1]));
+    assert!(results.iter().all(|r| r.get("description").is_none()));
+
+    // a model that can not rerank is rejected
+    let result = sqlx::query(&format!(
+        "SELECT * FROM vectorize.search(
+        job_name => '{job_name}',
+        query => 'mobile devices',
+        rerank_model => 'openai/text-embedding-3-small'
+    );"
+    ))
+    .execute(&conn)
+    .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_rag_batch_failures() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let agent_name = format!("agent_{}", test_num);
+    let batch_name = format!("public.answers_{test_num}");
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.init_rag(
+            agent_name => '{agent_name}',
+            table_name => '{test_table_name}',
+            unique_record_id => 'product_id',
+            \"column\" => 'description',
+            transformer => 'sentence-transformers/all-MiniLM-L6-v2'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let mut tx = conn.begin().await.unwrap();
+    let _ = sqlx::query(&format!(
+        "CREATE TABLE questions_{test_num} (id INT PRIMARY KEY, question TEXT);
+        INSERT INTO questions_{test_num} VALUES (1, 'what is a mobile device?'), (2, 'what is a laptop?');"
+    ))
+    .execute(&mut *tx)
+    .await
+    .expect("failed to create questions");
+    // nothing listens here, so every question fails
+    let _ = sqlx::query("SET LOCAL vectorize.ollama_service_url = 'http://localhost:1'")
+        .execute(&mut *tx)
+        .await
+        .expect("failed to set ollama url");
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.rag_batch(
+            agent_name => '{agent_name}',
+            questions_table => 'questions_{test_num}',
+            output_table => 'answers_{test_num}',
+            chat_model => 'ollama/wizardlm2:7b'
+    );"
+    ))
+    .execute(&mut *tx)
+    .await
+    .expect("failed to init rag batch");
+
+    // a failing question is rolled back on its own, and given up on after its last attempt
+    for _ in 0..3 {
+        let _ = sqlx::query(&format!(
+            "SELECT vectorize._rag_batch_execute('{batch_name}')"
+        ))
+        .execute(&mut *tx)
+        .await
+        .expect("failed to execute rag batch");
+    }
+    let attempts: Vec<i32> = sqlx::query_scalar(&format!(
+        "SELECT attempts FROM vectorize.rag_batch_failures WHERE batch_name = '{batch_name}' ORDER BY question_id"
+    ))
+    .fetch_all(&mut *tx)
+    .await
+    .expect("failed to get failures");
+    assert_eq!(attempts, vec![3, 3]);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize._rag_batch_execute('{batch_name}')"
+    ))
+    .execute(&mut *tx)
+    .await
+    .expect("failed to execute rag batch");
+    let status: String = sqlx::query_scalar(&format!(
+        "SELECT status FROM vectorize.rag_batch WHERE name = '{batch_name}'"
+    ))
+    .fetch_one(&mut *tx)
+    .await
+    .expect("failed to get batch status");
+    assert_eq!(status, "completed");
+    tx.rollback().await.unwrap();
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_rag_session() {
+    let conn = common::init_database().await;
+    common::init_embedding_svc_url(&conn).await;
+    let mut rng = rand::thread_rng();
+    let test_num = rng.gen_range(1..100000);
+    let test_table_name = format!("products_test_{}", test_num);
+    common::init_test_table(&test_table_name, &conn).await;
+    let agent_name = format!("agent_{}", test_num);
+    let session_id = format!("session_{}", test_num);
+
+    let _ = sqlx::query(&format!(
+        "SELECT vectorize.init_rag(
+            agent_name => '{agent_name}',
+            table_name => '{test_table_name}',
+            unique_record_id => 'product_id',
+            \"column\" => 'description',
+            transformer => 'sentence-transformers/all-MiniLM-L6-v2'
+    );"
+    ))
+    .execute(&conn)
+    .await
+    .expect("failed to init job");
+
+    let openai_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+    let mut tx = conn.begin().await.unwrap();
+    let _ = sqlx::query(&format!("SET LOCAL vectorize.openai_key TO '{openai_key}'"))
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    for query in ["what is a mobile device?", "which of them is the cheapest?"] {
+        let _ = sqlx::query(&format!(
+            "SELECT vectorize.rag(
+                agent_name => '{agent_name}',
+                query => '{query}',
+                chat_model => 'openai/gpt-3.5-turbo',
+                session_id => '{session_id}'
+        );"
+        ))
+        .execute(&mut *tx)
+        .await
+        .expect("failed to rag");
+    }
+
+    // both turns are kept for the role that asked them
+    let turns: Vec<(String, String, bool)> = sqlx::query_as(&format!(
+        "SELECT role, content, owner = current_user FROM vectorize.chat_history
+        WHERE session_id = '{session_id}' ORDER BY id"
+    ))
+    .fetch_all(&mut *tx)
+    .await
+    .unwrap();
+    assert_eq!(turns.len(), 4);
+    assert_eq!(turns[0].0, "user");
+    assert_eq!(turns[0].1, "what is a mobile device?");
+    assert_eq!(turns[1].0, "assistant");
+    assert_eq!(turns[2].1, "which of them is the cheapest?");
+    assert!(turns.iter().all(|(_, _, owned)| *owned));
+
+    let _ = sqlx::query(&format!("SELECT vectorize.delete_session('{session_id}');"))
+        .execute(&mut *tx)
+        .await
+        .expect("failed to delete session");
+    let remaining: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM vectorize.chat_history WHERE session_id = '{session_id}'"
+    ))
+    .fetch_one(&mut *tx)
+    .await
+    .unwrap();
+    assert_eq!(remaining, 0);
+    tx.commit().await.unwrap();
+
+    // a deleted session no longer exists
+    let result = sqlx::query(&format!("SELECT vectorize.delete_session('{session_id}');"))
+        .execute(&conn)
+        .await;
+    assert!(result.is_err());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_pg_monitor_privileges() {
+    let conn = common::init_database().await;
+    // pg_monitor reads the jobs, but not the tables holding keys or what was embedded
+    let job: bool =
+        sqlx::query_scalar("SELECT has_table_privilege('pg_monitor', 'vectorize.job', 'SELECT');")
+            .fetch_one(&conn)
+            .await
+            .unwrap();
+    assert!(job);
+    for table in [
+        "vectorize.credentials",
+        "vectorize.embedding_provenance",
+        "vectorize.embedding_cache",
+        "vectorize.chat_history",
+    ] {
+        let readable: bool = sqlx::query_scalar(&format!(
+            "SELECT has_table_privilege('pg_monitor', '{table}', 'SELECT');"
+        ))
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        assert!(!readable, "pg_monitor can read {table}");
+    }
+    // nor the options of registered models, which may hold tokens in their headers
+    let (name, options): (bool, bool) = sqlx::query_as(
+        "SELECT has_column_privilege('pg_monitor', 'vectorize.models', 'name', 'SELECT'),
+        has_column_privilege('pg_monitor', 'vectorize.models', 'options', 'SELECT');",
+    )
+    .fetch_one(&conn)
+    .await
+    .unwrap();
+    assert!(name);
+    assert!(!options);
+}